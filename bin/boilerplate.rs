@@ -1,14 +1,17 @@
 #![allow(clippy::single_match)]
 use vangers::{
-    config::Settings,
-    render::{GraphicsContext, ScreenTargets, DEPTH_FORMAT},
+    config::{settings, Settings},
+    console::Console,
+    render::{capture, frame_graph, DeviceLoss, GraphicsContext, ScreenTargets, DEPTH_FORMAT},
 };
 
 use futures::executor::LocalPool;
+use std::sync::Arc;
 use winit::{
     event,
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    monitor::MonitorHandle,
+    window::{Fullscreen, Icon, Window, WindowBuilder},
 };
 
 pub trait Application {
@@ -18,6 +21,12 @@ pub trait Application {
     fn on_mouse_button(&mut self, _state: event::ElementState, _button: event::MouseButton) {}
     fn resize(&mut self, _device: &wgpu::Device, _extent: wgpu::Extent3d) {}
     fn reload(&mut self, device: &wgpu::Device);
+    /// Re-create the GPU resources on the new device, after the old one is lost,
+    /// keeping the rest of the state.
+    fn recover(&mut self, gfx: &GraphicsContext);
+    fn on_command(&mut self, command: &str) -> Result<(), String> {
+        Err(format!("Unknown command '{}'", command))
+    }
     fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, delta: f32);
     fn draw_ui(&mut self, context: &egui::Context);
     fn draw(&mut self, device: &wgpu::Device, targets: ScreenTargets) -> wgpu::CommandBuffer;
@@ -26,11 +35,49 @@ pub trait Application {
 struct WindowContext {
     window: Window,
     task_pool: LocalPool,
+    instance: wgpu::Instance,
     surface: wgpu::Surface,
     present_mode: wgpu::PresentMode,
     reload_on_focus: bool,
     egui_platform: egui_winit_platform::Platform,
+    console: Console,
     depth_target: wgpu::TextureView,
+    /// What is needed to re-create the device after a loss.
+    adapter_info: wgpu::AdapterInfo,
+    render_config: settings::Render,
+    slices: u32,
+    /// Mode to go back to with Alt+Enter, or to leave for the window.
+    fullscreen: settings::Fullscreen,
+    modifiers: event::ModifiersState,
+    device_loss: DeviceLoss,
+    /// Where to dump the frame graph, see `--dump-framegraph`.
+    dump_framegraph: Option<FrameGraphDump>,
+    /// Frame to capture, see `--renderdoc`.
+    capture_frame: Option<u32>,
+}
+
+/// Frame to record with `--dump-framegraph`, late enough for the initial uploads to settle.
+/// It's also the frame captured with `--renderdoc` by default.
+const DUMP_FRAME_INDEX: u32 = 10;
+/// Icon of the window, an RGBA PNG.
+const ICON_PATH: &str = "res/icon.png";
+
+enum FrameGraphDump {
+    Log,
+    File(std::path::PathBuf),
+}
+
+impl FrameGraphDump {
+    fn write(&self, events: &[frame_graph::Event]) {
+        let json = frame_graph::to_json(events);
+        match *self {
+            Self::Log => log::info!("Frame graph: {}", json),
+            Self::File(ref path) => match std::fs::write(path, json) {
+                Ok(()) => log::info!("Frame graph is saved into {}", path.display()),
+                Err(e) => log::error!("Unable to save the frame graph {}: {}", path.display(), e),
+            },
+        }
+    }
 }
 
 pub struct Harness {
@@ -41,15 +88,304 @@ pub struct Harness {
 
 pub struct HarnessOptions {
     pub title: &'static str,
+    /// Settings overrides in the form of "key=value".
+    pub settings_overrides: Vec<String>,
+    /// Dump the frame graph into the file, or into the log if the path is empty.
+    pub dump_framegraph: Option<String>,
+    /// Capture the frame with this index, counting from 1.
+    pub renderdoc: Option<u32>,
+}
+
+/// Register the command line options shared by all the windowed binaries.
+pub fn add_options(options: &mut getopts::Options) {
+    options
+        .optmulti("", "set", "override a setting", "KEY=VALUE")
+        .optopt(
+            "",
+            "backend",
+            "graphics backend: Auto, Vulkan, Metal, DX12, DX11, GL",
+            "NAME",
+        )
+        .optopt(
+            "",
+            "adapter",
+            "pick the adapter by a part of its name",
+            "NAME",
+        )
+        .optflag("", "list-adapters", "print the available adapters and exit")
+        .optflagopt(
+            "",
+            "dump-framegraph",
+            "record the passes, dispatches, and uploads of a frame as JSON, into the file or the log",
+            "FILE",
+        )
+        .optflagopt(
+            "",
+            "renderdoc",
+            "capture the frame with RenderDoc, 10th by default, when running under it",
+            "FRAME",
+        );
+}
+
+impl HarnessOptions {
+    pub fn new(title: &'static str, matches: &getopts::Matches) -> Self {
+        let mut settings_overrides = matches.opt_strs("set");
+        if let Some(backend) = matches.opt_str("backend") {
+            settings_overrides.push(format!("backend={}", backend));
+        }
+        if let Some(adapter) = matches.opt_str("adapter") {
+            settings_overrides.push(format!("adapter={}", adapter));
+        }
+        let renderdoc = matches
+            .opt_default("renderdoc", "")
+            .map(|frame| match frame.as_str() {
+                "" => DUMP_FRAME_INDEX,
+                _ => frame.parse().unwrap_or_else(|_| {
+                    log::error!("Invalid frame '{}' to capture, using the default", frame);
+                    DUMP_FRAME_INDEX
+                }),
+            });
+        HarnessOptions {
+            title,
+            settings_overrides,
+            dump_framegraph: matches.opt_default("dump-framegraph", ""),
+            renderdoc,
+        }
+    }
+}
+
+/// Print the adapters of all the backends, if the user asked for it.
+/// Returns true if the application should exit afterwards.
+pub fn list_adapters(matches: &getopts::Matches) -> bool {
+    if !matches.opt_present("list-adapters") {
+        return false;
+    }
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+    let adapters = instance.enumerate_adapters(wgpu::Backends::all());
+    println!("Available adapters:");
+    for adapter in adapters {
+        let info = adapter.get_info();
+        println!(
+            "\t{:?}: \"{}\" ({:?}), driver: {} {}",
+            info.backend, info.name, info.device_type, info.driver, info.driver_info
+        );
+    }
+    true
+}
+
+pub fn request_device(
+    adapter: &wgpu::Adapter,
+    render_config: &settings::Render,
+    slices: u32,
+    task_pool: &mut LocalPool,
+) -> (wgpu::Device, wgpu::Queue) {
+    let info = adapter.get_info();
+    vangers::crash::record_adapter(&info);
+    log::info!(
+        "Using adapter \"{}\" ({:?}) on {:?}, driver: {} {}",
+        info.name,
+        info.device_type,
+        info.backend,
+        info.driver,
+        info.driver_info
+    );
+    log::info!(
+        "Downlevel capabilities: {:?}",
+        adapter.get_downlevel_capabilities()
+    );
+    let limits = render_config.get_device_limits(&adapter.limits(), slices);
+    log::info!("Requested limits: {:?}", limits);
+
+    task_pool
+        .run_until(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits,
+            },
+            if render_config.wgpu_trace_path.is_empty() {
+                None
+            } else {
+                Some(std::path::Path::new(&render_config.wgpu_trace_path))
+            },
+        ))
+        .unwrap()
+}
+
+fn create_egui_platform(window: &Window, extent: wgpu::Extent3d) -> egui_winit_platform::Platform {
+    egui_winit_platform::Platform::new(egui_winit_platform::PlatformDescriptor {
+        physical_width: extent.width,
+        physical_height: extent.height,
+        scale_factor: window.scale_factor(),
+        font_definitions: egui::FontDefinitions::default(),
+        style: Default::default(),
+    })
+}
+
+fn load_icon(path: &str) -> Result<Icon, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut data = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+    if info.color_type != png::ColorType::Rgba {
+        return Err(format!("expected RGBA, got {:?}", info.color_type));
+    }
+    data.truncate(info.buffer_size());
+    Icon::from_rgba(data, info.width, info.height).map_err(|e| e.to_string())
+}
+
+/// Find the winit mode for the full screen on the monitor of the window.
+/// The exclusive mode takes the largest resolution, falling back to the borderless.
+fn make_fullscreen(
+    mode: settings::Fullscreen,
+    monitor: Option<MonitorHandle>,
+) -> Option<Fullscreen> {
+    match mode {
+        settings::Fullscreen::Off => None,
+        settings::Fullscreen::Borderless => Some(Fullscreen::Borderless(monitor)),
+        settings::Fullscreen::Exclusive => {
+            let video_mode = monitor.as_ref().and_then(|monitor| {
+                monitor.video_modes().max_by_key(|vm| {
+                    let size = vm.size();
+                    (size.width * size.height, vm.refresh_rate_millihertz())
+                })
+            });
+            match video_mode {
+                Some(vm) => Some(Fullscreen::Exclusive(vm)),
+                None => {
+                    log::warn!("No video modes for the exclusive full screen");
+                    Some(Fullscreen::Borderless(monitor))
+                }
+            }
+        }
+    }
+}
+
+pub fn create_depth_target(device: &wgpu::Device, extent: wgpu::Extent3d) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+impl WindowContext {
+    fn configure_surface(&mut self, gfx: &GraphicsContext) {
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: gfx.color_format,
+            width: gfx.screen_size.width,
+            height: gfx.screen_size.height,
+            present_mode: self.present_mode,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: Vec::new(),
+        };
+        self.surface.configure(&gfx.device, &config);
+        self.depth_target = create_depth_target(&gfx.device, gfx.screen_size);
+    }
+
+    /// Re-create the device after a loss, preferring the same adapter.
+    fn recreate_device(&mut self, gfx: &mut GraphicsContext) {
+        let adapter = self
+            .instance
+            .enumerate_adapters(self.adapter_info.backend.into())
+            .find(|adapter| {
+                adapter.get_info().name == self.adapter_info.name
+                    && adapter.is_surface_supported(&self.surface)
+            })
+            .or_else(|| {
+                self.task_pool.run_until(self.instance.request_adapter(
+                    &wgpu::RequestAdapterOptions {
+                        power_preference: wgpu::PowerPreference::HighPerformance,
+                        compatible_surface: Some(&self.surface),
+                        force_fallback_adapter: false,
+                    },
+                ))
+            })
+            .expect("Unable to find an adapter to recover on");
+
+        let (device, queue) = request_device(
+            &adapter,
+            &self.render_config,
+            self.slices,
+            &mut self.task_pool,
+        );
+        self.device_loss = DeviceLoss::watch(&device);
+        self.adapter_info = adapter.get_info();
+        *gfx = GraphicsContext {
+            device: Arc::new(device),
+            downlevel_caps: adapter.get_downlevel_capabilities(),
+            queue: Arc::new(queue),
+            color_format: gfx.color_format,
+            screen_size: gfx.screen_size,
+        };
+        self.configure_surface(gfx);
+        // the UI textures are gone with the old device, so start over
+        self.egui_platform = create_egui_platform(&self.window, gfx.screen_size);
+    }
+
+    /// Switch between the window and the full screen, re-configuring the surface
+    /// right away, since not all the platforms send a resize event for it.
+    fn toggle_fullscreen(&mut self, gfx: &mut GraphicsContext) {
+        let mode = match self.window.fullscreen() {
+            Some(_) => settings::Fullscreen::Off,
+            None => match self.fullscreen {
+                settings::Fullscreen::Off => settings::Fullscreen::Borderless,
+                other => other,
+            },
+        };
+        log::info!("Switching the full screen to {:?}", mode);
+        self.window
+            .set_fullscreen(make_fullscreen(mode, self.window.current_monitor()));
+        let size = self.window.inner_size();
+        if size.width != 0 && size.height != 0 {
+            gfx.screen_size = wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            };
+            self.configure_surface(gfx);
+        }
+    }
+}
+
+/// Set up the logging and the crash reports, then load the settings.
+pub fn load_settings(options: &HarnessOptions) -> Settings {
+    vangers::console::init();
+    vangers::crash::install(options.title);
+
+    log::info!("Loading the settings");
+    vangers::crash::record_settings(
+        "config/settings.ron",
+        options.title,
+        &options.settings_overrides,
+    );
+    let settings = Settings::load(
+        "config/settings.ron",
+        options.title,
+        &options.settings_overrides,
+    );
+    vangers::locale::init(&settings.language);
+    vangers::jobs::init(settings.threads);
+    settings
 }
 
 impl Harness {
     pub fn init(options: HarnessOptions) -> (Self, Settings) {
-        env_logger::init();
+        let mut settings = load_settings(&options);
         let mut task_pool = LocalPool::new();
-
-        log::info!("Loading the settings");
-        let settings = Settings::load("config/settings.ron");
         let extent = wgpu::Extent3d {
             width: settings.window.size[0],
             height: settings.window.size[1],
@@ -62,43 +398,74 @@ impl Harness {
             ..Default::default()
         });
         let event_loop = EventLoop::new();
-        let window = WindowBuilder::new()
-            .with_title(options.title)
+        let title = if settings.window.title.is_empty() {
+            options.title
+        } else {
+            &settings.window.title
+        };
+        let icon = load_icon(ICON_PATH)
+            .map_err(|e| log::warn!("Unable to load the icon {}: {}", ICON_PATH, e))
+            .ok();
+        let mut builder = WindowBuilder::new()
+            .with_title(title)
             .with_inner_size(winit::dpi::PhysicalSize::new(extent.width, extent.height))
             .with_resizable(true)
-            .build(&event_loop)
-            .unwrap();
+            .with_decorations(!settings.window.borderless)
+            .with_window_icon(icon)
+            .with_fullscreen(make_fullscreen(
+                settings.window.fullscreen,
+                event_loop.primary_monitor(),
+            ));
+        if let Some((x, y)) = settings.window.position {
+            builder = builder.with_position(winit::dpi::PhysicalPosition::new(x, y));
+        }
+        let window = builder.build(&event_loop).unwrap();
+        // the full screen may not have the size we asked for
+        let extent = match window.inner_size() {
+            size if size.width != 0 && size.height != 0 => wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            _ => extent,
+        };
         let surface =
             unsafe { instance.create_surface(&window) }.expect("Unable to create surface.");
 
         log::info!("Initializing the device");
-        let adapter = task_pool
-            .run_until(instance.request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            }))
-            .expect("Unable to initialize GPU via the selected backend.");
-
-        let downlevel_caps = adapter.get_downlevel_capabilities();
-        let limits = settings
+        let adapter = if settings.adapter.is_empty() {
+            task_pool
+                .run_until(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: Some(&surface),
+                    force_fallback_adapter: false,
+                }))
+                .expect("Unable to initialize GPU via the selected backend.")
+        } else {
+            instance
+                .enumerate_adapters(settings.backend.to_wgpu())
+                .find(|adapter| {
+                    settings.matches_adapter(&adapter.get_info())
+                        && adapter.is_surface_supported(&surface)
+                })
+                .unwrap_or_else(|| {
+                    panic!(
+                        "No adapter matching '{}' on {:?} can present, see `--list-adapters`",
+                        settings.adapter, settings.backend,
+                    )
+                })
+        };
+
+        settings
             .render
-            .get_device_limits(&adapter.limits(), settings.game.geometry.height);
-
-        let (device, queue) = task_pool
-            .run_until(adapter.request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    limits,
-                },
-                if settings.render.wgpu_trace_path.is_empty() {
-                    None
-                } else {
-                    Some(std::path::Path::new(&settings.render.wgpu_trace_path))
-                },
-            ))
-            .unwrap();
+            .fit_downlevel(&adapter.get_downlevel_capabilities());
+        let (device, queue) = request_device(
+            &adapter,
+            &settings.render,
+            settings.game.geometry.height,
+            &mut task_pool,
+        );
+        let device_loss = DeviceLoss::watch(&device);
 
         let surface_caps = surface.get_capabilities(&adapter);
         log::info!("Supported surface formats: {:?}", surface_caps.formats);
@@ -126,43 +493,37 @@ impl Harness {
         };
         surface.configure(&device, &config);
 
-        let egui_platform =
-            egui_winit_platform::Platform::new(egui_winit_platform::PlatformDescriptor {
-                physical_width: extent.width,
-                physical_height: extent.height,
-                scale_factor: window.scale_factor(),
-                font_definitions: egui::FontDefinitions::default(),
-                style: Default::default(),
-            });
-
-        let depth_target = device
-            .create_texture(&wgpu::TextureDescriptor {
-                label: Some("Depth"),
-                size: extent,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: DEPTH_FORMAT,
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                view_formats: &[],
-            })
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let egui_platform = create_egui_platform(&window, extent);
+        let depth_target = create_depth_target(&device, extent);
 
         let harness = Harness {
             event_loop,
             window_ctx: WindowContext {
                 window,
                 task_pool,
+                instance,
                 surface,
                 present_mode,
                 reload_on_focus: settings.window.reload_on_focus,
                 egui_platform,
+                console: Console::default(),
                 depth_target,
+                adapter_info: adapter.get_info(),
+                render_config: settings.render.clone(),
+                slices: settings.game.geometry.height,
+                fullscreen: settings.window.fullscreen,
+                modifiers: event::ModifiersState::empty(),
+                device_loss,
+                dump_framegraph: options.dump_framegraph.map(|path| match path.as_str() {
+                    "" => FrameGraphDump::Log,
+                    _ => FrameGraphDump::File(path.into()),
+                }),
+                capture_frame: options.renderdoc,
             },
             graphics_ctx: GraphicsContext {
-                device,
-                downlevel_caps,
-                queue,
+                device: Arc::new(device),
+                downlevel_caps: adapter.get_downlevel_capabilities(),
+                queue: Arc::new(queue),
                 color_format: config.format,
                 screen_size: extent,
             },
@@ -171,18 +532,26 @@ impl Harness {
         (harness, settings)
     }
 
-    pub fn main_loop<A: 'static + Application>(self, mut app: A) {
+    /// Run the application created by `make_app`.
+    /// If the device is lost, it's re-created, and the application recovers on it.
+    pub fn main_loop<A, F>(self, make_app: F)
+    where
+        A: 'static + Application,
+        F: 'static + FnOnce(&GraphicsContext) -> A,
+    {
         use std::time;
 
         let start_time = time::Instant::now();
         let mut last_time = time::Instant::now();
         let mut needs_reload = false;
+        let mut frame_index = 0u32;
         let Harness {
             event_loop,
             window_ctx: mut win,
             graphics_ctx: mut gfx,
         } = self;
 
+        let mut app = make_app(&gfx);
         let mut egui_pass = egui_wgpu_backend::RenderPass::new(&gfx.device, gfx.color_format, 1);
 
         event_loop.run(move |event, _, control_flow| {
@@ -207,29 +576,7 @@ impl Harness {
                         height: size.height,
                         depth_or_array_layers: 1,
                     };
-                    let config = wgpu::SurfaceConfiguration {
-                        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                        format: gfx.color_format,
-                        width: size.width,
-                        height: size.height,
-                        present_mode: win.present_mode,
-                        alpha_mode: wgpu::CompositeAlphaMode::Auto,
-                        view_formats: Vec::new(),
-                    };
-                    win.surface.configure(&gfx.device, &config);
-                    win.depth_target = gfx
-                        .device
-                        .create_texture(&wgpu::TextureDescriptor {
-                            label: Some("Depth"),
-                            size: gfx.screen_size,
-                            mip_level_count: 1,
-                            sample_count: 1,
-                            dimension: wgpu::TextureDimension::D2,
-                            format: DEPTH_FORMAT,
-                            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                            view_formats: &[],
-                        })
-                        .create_view(&wgpu::TextureViewDescriptor::default());
+                    win.configure_surface(&gfx);
                     app.resize(&gfx.device, gfx.screen_size);
                 }
                 event::Event::WindowEvent {
@@ -250,10 +597,34 @@ impl Harness {
                     event::WindowEvent::CloseRequested => {
                         *control_flow = ControlFlow::Exit;
                     }
-                    event::WindowEvent::KeyboardInput { input, .. } => {
-                        if !app.on_key(input) {
-                            *control_flow = ControlFlow::Exit;
-                        }
+                    event::WindowEvent::ModifiersChanged(modifiers) => {
+                        win.modifiers = modifiers;
+                    }
+                    event::WindowEvent::KeyboardInput {
+                        input:
+                            event::KeyboardInput {
+                                state: event::ElementState::Pressed,
+                                virtual_keycode: Some(event::VirtualKeyCode::Return),
+                                ..
+                            },
+                        ..
+                    } if win.modifiers.alt() => {
+                        win.toggle_fullscreen(&mut gfx);
+                        app.resize(&gfx.device, gfx.screen_size);
+                    }
+                    event::WindowEvent::KeyboardInput {
+                        input:
+                            event::KeyboardInput {
+                                state: event::ElementState::Pressed,
+                                virtual_keycode: Some(event::VirtualKeyCode::Grave),
+                                ..
+                            },
+                        ..
+                    } => {
+                        win.console.toggle();
+                    }
+                    event::WindowEvent::KeyboardInput { input, .. } if !app.on_key(input) => {
+                        *control_flow = ControlFlow::Exit;
                     }
                     event::WindowEvent::MouseWheel { delta, .. } => app.on_mouse_wheel(delta),
                     event::WindowEvent::CursorMoved { position, .. } => {
@@ -264,6 +635,13 @@ impl Harness {
                     }
                     _ => {}
                 },
+                event::Event::MainEventsCleared if win.device_loss.is_lost() => {
+                    log::warn!("Re-creating the device and the GPU resources");
+                    win.recreate_device(&mut gfx);
+                    egui_pass =
+                        egui_wgpu_backend::RenderPass::new(&gfx.device, gfx.color_format, 1);
+                    app.recover(&gfx);
+                }
                 event::Event::MainEventsCleared => {
                     let duration = time::Instant::now() - last_time;
                     last_time += duration;
@@ -279,6 +657,12 @@ impl Harness {
                         .update_time(start_time.elapsed().as_secs_f64());
                     win.egui_platform.begin_frame();
                     app.draw_ui(&win.egui_platform.context());
+                    if let Some(command) = win.console.draw(&win.egui_platform.context()) {
+                        log::info!("Console: {}", command);
+                        if let Err(e) = app.on_command(&command) {
+                            log::warn!("{}", e);
+                        }
+                    }
                     let egui_output = win.egui_platform.end_frame(Some(&win.window));
 
                     let egui_primitives =
@@ -306,7 +690,22 @@ impl Harness {
                         color: &view,
                         depth: &win.depth_target,
                     };
+                    frame_index += 1;
+                    let dump = match win.dump_framegraph {
+                        Some(ref dump) if frame_index == DUMP_FRAME_INDEX => Some(dump),
+                        _ => None,
+                    };
+                    if dump.is_some() {
+                        frame_graph::start();
+                    }
+                    if win.capture_frame == Some(frame_index) {
+                        capture::request();
+                    }
+                    let capture = capture::start(&gfx.device);
                     let command_buffer = app.draw(&gfx.device, targets);
+                    if let Some(dump) = dump {
+                        dump.write(&frame_graph::finish().unwrap_or_default());
+                    }
 
                     let mut egui_encoder =
                         gfx.device
@@ -327,6 +726,7 @@ impl Harness {
 
                     gfx.queue
                         .submit(vec![command_buffer, egui_encoder.finish()]);
+                    drop(capture);
                     egui_pass
                         .remove_textures(egui_output.textures_delta)
                         .unwrap();
@@ -53,7 +53,7 @@ impl LevelLayers {
     }
 
     fn import(&mut self, data: &LevelData) {
-        let terrain_bits = TerrainBits::new(self.num_terrains);
+        let terrain_bits = TerrainBits::new(self.num_terrains).unwrap();
         let row_size = data.size.0 as usize;
         for (hrow, mrow) in data.height.chunks(row_size).zip(data.meta.chunks(row_size)) {
             for ((&h0, &h1), (&m0, &m1)) in hrow
@@ -91,7 +91,7 @@ impl LevelLayers {
     }
 
     pub fn export(self) -> LevelData {
-        let terrain_bits = TerrainBits::new(self.num_terrains);
+        let terrain_bits = TerrainBits::new(self.num_terrains).unwrap();
         let total = self.size.0 as usize * self.size.1 as usize;
         let mut height = Vec::with_capacity(total);
         let mut meta = Vec::with_capacity(total);
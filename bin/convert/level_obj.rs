@@ -6,7 +6,7 @@ use std::{
 };
 use vangers::level::{Level, Texel};
 
-const EXTREME_HEIGHT: i32 = i32::max_value();
+const EXTREME_HEIGHT: i32 = i32::MAX;
 
 #[derive(Debug)]
 pub struct Config<'a> {
@@ -271,6 +271,7 @@ pub fn save(path: &Path, level: &Level, config: &Config) {
                 x_offset: ops::Range<i32>,
                 y_offset: ops::Range<i32>,
             }
+            #[allow(clippy::reversed_empty_ranges)] // ranges are coordinate pairs here
             let sides = [
                 Side {
                     corners: 0x8..0x1,
@@ -333,7 +334,7 @@ pub fn save(path: &Path, level: &Level, config: &Config) {
                 {
                     let diag_column = &c.face_columns[(dy - config.yr.start) as usize]
                         [(dx - config.xr.start) as usize];
-                    column.add_faces(&diag_column, diagonal.corner);
+                    column.add_faces(diag_column, diagonal.corner);
                 }
             }
 
@@ -441,7 +442,7 @@ pub fn save(path: &Path, level: &Level, config: &Config) {
         unit(num_tris),
     );
 
-    let mut dest = BufWriter::new(File::create(&path).unwrap());
+    let mut dest = BufWriter::new(File::create(path).unwrap());
     bar.set_job_title("Vertices:");
     for v in c.final_vertices {
         writeln!(dest, "v {} {} {}", v[0], v[1], v[2]).unwrap();
@@ -1,7 +1,11 @@
 mod layers;
 mod level_obj;
 mod level_png;
+mod model_gltf;
 mod model_obj;
+mod model_physics;
+mod physics_json;
+mod snapshot;
 
 use std::{
     fs::{read as fs_read, File},
@@ -12,36 +16,36 @@ use std::{
 pub fn save_tiff(path: &Path, layers: layers::LevelLayers) {
     let images = [
         tiff::Image {
-            width: layers.size.0 as u32,
-            height: layers.size.1 as u32,
+            width: layers.size.0,
+            height: layers.size.1,
             bpp: 8,
             name: "h0",
             data: &layers.het0,
         },
         tiff::Image {
-            width: layers.size.0 as u32,
-            height: layers.size.1 as u32,
+            width: layers.size.0,
+            height: layers.size.1,
             bpp: 8,
             name: "h1",
             data: &layers.het1,
         },
         tiff::Image {
-            width: layers.size.0 as u32,
-            height: layers.size.1 as u32,
+            width: layers.size.0,
+            height: layers.size.1,
             bpp: 8,
             name: "del",
             data: &layers.delta,
         },
         tiff::Image {
-            width: layers.size.0 as u32,
-            height: layers.size.1 as u32,
+            width: layers.size.0,
+            height: layers.size.1,
             bpp: 4,
             name: "m0",
             data: &layers.mat0,
         },
         tiff::Image {
-            width: layers.size.0 as u32,
-            height: layers.size.1 as u32,
+            width: layers.size.0,
+            height: layers.size.1,
             bpp: 4,
             name: "m1",
             data: &layers.mat1,
@@ -52,6 +56,29 @@ pub fn save_tiff(path: &Path, layers: layers::LevelLayers) {
     tiff::save(file, &images).unwrap();
 }
 
+/// Write the base world with the terrain of the changed map as VMP,
+/// or only the changes of the terrain as a RON patch.
+fn apply_map(map_path: &Path, ini_path: &Path, dst_path: &Path) {
+    let geometry = vangers::config::settings::Geometry::default();
+    println!("\tLoading the level...");
+    let config = vangers::level::LevelConfig::load(ini_path);
+    let mut level = vangers::level::load(&config, &geometry);
+    println!("\tReading the changed map...");
+    let patch = vangers::level::load_map_diff(map_path, &level).unwrap_or_else(|e| panic!("{}", e));
+    println!("\tFound {} changed rows", patch.spans.len());
+    if dst_path.extension().is_some_and(|ext| ext == "ron") {
+        println!("\tSaving the patch...");
+        let string = ron::ser::to_string_pretty(&patch, ron::ser::PrettyConfig::default()).unwrap();
+        std::fs::write(dst_path, string).expect("Unable to save the patch");
+    } else {
+        patch.apply(&mut level);
+        println!("\tSaving VMP...");
+        vangers::level::LevelData::from(level)
+            .save_vmp(dst_path)
+            .expect("Unable to save VMP");
+    }
+}
+
 fn main() {
     use std::env;
     use std::io::Write;
@@ -66,12 +93,72 @@ fn main() {
             "chunks",
             "number of chunks to split into",
             "big levels can be split into 8",
+        )
+        .optflag(
+            "",
+            "fix-physics",
+            "recompute the physics of the given m3d files or directories in place",
+        )
+        .optopt(
+            "",
+            "palette",
+            "objects palette for the materials of the glTF export",
+            "objects.pal",
+        )
+        .optflag("", "dry-run", "only report the physics changes")
+        .optopt(
+            "",
+            "apply-map",
+            "apply the terrain of a changed map of the world onto the base world",
+            "MAP",
+        )
+        .optflag(
+            "",
+            "render-snapshot",
+            "render a scene snapshot of `road` or the FFI into a PNG",
+        )
+        .optopt(
+            "",
+            "export-physics",
+            "export the physics constants and the cars of the game in `config/settings.ron` as JSON",
+            "OUTPUT",
         );
 
     let matches = options.parse(&args[1..]).unwrap();
+    if matches.opt_present("fix-physics") && !matches.free.is_empty() {
+        println!("\tRecomputing the model physics...");
+        model_physics::fix(&matches.free, matches.opt_present("dry-run"));
+        return;
+    }
+    if matches.opt_present("render-snapshot") && matches.free.len() == 2 {
+        snapshot::convert(Path::new(&matches.free[0]), Path::new(&matches.free[1]));
+        return;
+    }
+    if let Some(output) = matches.opt_str("export-physics") {
+        let settings = vangers::config::Settings::load("config/settings.ron", "convert", &[]);
+        println!("\tLoading the physics...");
+        let (common, cars) = physics_json::load(&settings);
+        println!("\tExporting {} cars...", cars.len());
+        std::fs::write(&output, physics_json::to_json(&common, &cars))
+            .expect("Unable to save the JSON");
+        return;
+    }
+    if let Some(map_path) = matches.opt_str("apply-map") {
+        if matches.free.len() == 2 {
+            apply_map(
+                Path::new(&map_path),
+                Path::new(&matches.free[0]),
+                Path::new(&matches.free[1]),
+            );
+            return;
+        }
+    }
     if matches.opt_present("h") || matches.free.len() != 2 {
         println!("Vangers resource converter");
-        let brief = format!("Usage: {} [options] <input> <output>", args[0]);
+        let brief = format!(
+            "Usage: {0} [options] <input> <output>\n       {0} --fix-physics <paths>...\n       {0} --render-snapshot <scene.ron> <image.png>\n       {0} --apply-map <changed.vmp|changed.vmc> <world.ini> <output.vmp|output.ron>\n       {0} --export-physics <output.json>",
+            args[0]
+        );
         println!("{}", options.usage(&brief));
         return;
     }
@@ -93,26 +180,54 @@ fn main() {
         ("m3d", "ron") => {
             let file = File::open(&src_path).unwrap();
             println!("\tLoading M3D...");
-            let raw = m3d::FullModel::load(file);
+            let raw = m3d::FullModel::load(file).unwrap();
             println!("\tExporting OBJ data...");
             model_obj::export_m3d(raw, &dst_path);
         }
+        ("m3d", "gltf") => {
+            let file = File::open(&src_path).unwrap();
+            println!("\tLoading M3D...");
+            let raw = m3d::FullModel::load(file).unwrap();
+            let palette = match matches.opt_str("palette") {
+                Some(path) => vangers::level::read_palette(File::open(path).unwrap(), None),
+                None => vangers::config::fallback::palette(),
+            };
+            println!("\tExporting glTF...");
+            model_gltf::export_m3d(&raw, &palette, &dst_path).unwrap();
+        }
         ("ron", "md3") => {
             println!("\tImporting OBJ data...");
-            let model = model_obj::import_m3d(&src_path);
+            let mut model = model_obj::import_m3d(&src_path);
+            println!("\tWelded {} vertices", model.weld());
+            println!("\tSaving M3D...");
+            model.save(File::create(&dst_path).unwrap());
+        }
+        ("m3d", "m3d") => {
+            println!("\tLoading M3D...");
+            let model = m3d::FullModel::load(File::open(&src_path).unwrap()).unwrap();
             println!("\tSaving M3D...");
             model.save(File::create(&dst_path).unwrap());
         }
+        ("a3d", "a3d") => {
+            println!("\tLoading A3D...");
+            let amesh = m3d::AnimatedMesh::<m3d::Geometry<m3d::DrawTriangle>>::load(
+                File::open(&src_path).unwrap(),
+            )
+            .unwrap();
+            println!("\tSaving A3D...");
+            amesh.save(File::create(&dst_path).unwrap());
+        }
         ("a3d", "ron") => {
             let file = File::open(&src_path).unwrap();
             println!("\tLoading A3D...");
-            let raw = m3d::AnimatedMesh::load(file);
+            let raw = m3d::AnimatedMesh::load(file).unwrap();
             println!("\tExporting OBJ data...");
             model_obj::export_a3d(raw, &dst_path);
         }
         ("ron", "a3d") => {
             println!("\tImporting OBJ data...");
-            let amesh = model_obj::import_a3d(&src_path);
+            let mut amesh = model_obj::import_a3d(&src_path);
+            println!("\tWelded {} vertices", amesh.weld());
             println!("\tSaving A3D...");
             amesh.save(File::create(&dst_path).unwrap());
         }
@@ -144,7 +259,9 @@ fn main() {
             let config = vangers::level::LevelConfig::load(&src_path);
             let level = vangers::level::load(&config, &geometry);
             println!("\tSaving VMP...");
-            vangers::level::LevelData::from(level).save_vmp(&dst_path);
+            vangers::level::LevelData::from(level)
+                .save_vmp(&dst_path)
+                .expect("Unable to save VMP");
         }
         ("ini", "obj") => {
             println!("\tLoading the level...");
@@ -183,7 +300,7 @@ fn main() {
             let layers = level_png::load(&src_path);
             println!("\tSaving VMP...");
             let level_data = layers.export();
-            level_data.save_vmp(&dst_path);
+            level_data.save_vmp(&dst_path).expect("Unable to save VMP");
         }
         ("pal", "mtl") => {
             println!("\tConverting object palette to MTL...");
@@ -0,0 +1,358 @@
+//! Export of M3D models into glTF 2.0, for Blender and other DCC tools.
+//!
+//! The body, the wheels, the debris, and the slots become the separate nodes
+//! under the root, with the physics and the wheel parameters in their "extras".
+//! Each color id is a material, colored by the objects palette. The geometry goes
+//! into a binary buffer next to the `.gltf` file, and the root turns the Z-up
+//! coordinates of the game into the Y-up of glTF.
+
+use m3d::{DrawTriangle, FullModel, Geometry, Mesh, NORMALIZER, NUM_COLOR_IDS};
+use vangers::render::object::COLOR_TABLE;
+
+use serde_json::{json, Value};
+use std::{fs, io::Result as IoResult, path::Path};
+
+const ARRAY_BUFFER: u32 = 34962;
+const FLOAT: u32 = 5126;
+/// Rotation by -90 degrees around X, from Z-up to Y-up.
+const ROOT_ROTATION: [f32; 4] = [
+    -std::f32::consts::FRAC_1_SQRT_2,
+    0.0,
+    0.0,
+    std::f32::consts::FRAC_1_SQRT_2,
+];
+
+#[derive(Default)]
+struct Builder {
+    data: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+}
+
+/// Numbers that are not finite become zeros, since JSON has no way to express them.
+fn finite(v: f32) -> f32 {
+    if v.is_finite() {
+        v
+    } else {
+        0.0
+    }
+}
+
+fn vec_value(v: &[f32]) -> Value {
+    v.iter().map(|&f| finite(f)).collect()
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Builder {
+    /// Add the vectors as an accessor, returning its index.
+    fn add_vec3(&mut self, values: &[[f32; 3]], with_bounds: bool) -> usize {
+        let offset = self.data.len();
+        for v in values {
+            for &c in v {
+                self.data.extend_from_slice(&finite(c).to_le_bytes());
+            }
+        }
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.data.len() - offset,
+            "target": ARRAY_BUFFER,
+        }));
+        let mut accessor = json!({
+            "bufferView": self.views.len() - 1,
+            "componentType": FLOAT,
+            "count": values.len(),
+            "type": "VEC3",
+        });
+        if with_bounds {
+            let mut min = [f32::INFINITY; 3];
+            let mut max = [f32::NEG_INFINITY; 3];
+            for v in values {
+                for i in 0..3 {
+                    min[i] = min[i].min(finite(v[i]));
+                    max[i] = max[i].max(finite(v[i]));
+                }
+            }
+            accessor["min"] = vec_value(&min);
+            accessor["max"] = vec_value(&max);
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    /// Add the geometry as a mesh with a primitive per color id, returning its index.
+    fn add_mesh(&mut self, name: &str, geom: &Geometry<DrawTriangle>) -> Option<usize> {
+        let mut primitives = Vec::new();
+        for color_id in 0..NUM_COLOR_IDS {
+            let mut positions = Vec::new();
+            let mut normals = Vec::new();
+            for poly in geom.polygons.iter().filter(|p| p.material[0] == color_id) {
+                for v in &poly.vertices {
+                    let p = geom.positions[v.pos as usize];
+                    positions.push([p[0] as f32, p[1] as f32, p[2] as f32]);
+                    let n = geom.normals[v.normal as usize];
+                    let n = [
+                        n[0] as f32 / NORMALIZER,
+                        n[1] as f32 / NORMALIZER,
+                        n[2] as f32 / NORMALIZER,
+                    ];
+                    let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                    normals.push(if length > 0.0 {
+                        [n[0] / length, n[1] / length, n[2] / length]
+                    } else {
+                        [0.0, 0.0, 1.0]
+                    });
+                }
+            }
+            if positions.is_empty() {
+                continue;
+            }
+            let position = self.add_vec3(&positions, true);
+            let normal = self.add_vec3(&normals, false);
+            primitives.push(json!({
+                "attributes": { "POSITION": position, "NORMAL": normal },
+                "material": color_id,
+            }));
+        }
+        if primitives.is_empty() {
+            return None;
+        }
+        self.meshes.push(json!({
+            "name": name,
+            "primitives": primitives,
+        }));
+        Some(self.meshes.len() - 1)
+    }
+
+    fn add_node(
+        &mut self,
+        name: &str,
+        mesh: Option<usize>,
+        translation: [f32; 3],
+        rotation: Option<[f32; 4]>,
+        extras: Value,
+    ) -> usize {
+        let mut node = json!({
+            "name": name,
+            "translation": vec_value(&translation),
+        });
+        if let Some(mesh) = mesh {
+            node["mesh"] = json!(mesh);
+        }
+        if let Some(rotation) = rotation {
+            node["rotation"] = vec_value(&rotation);
+        }
+        if !extras.is_null() {
+            node["extras"] = extras;
+        }
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// Add the mesh as a node with its physics in the extras, next to `extras`.
+    fn add_part(
+        &mut self,
+        name: &str,
+        mesh: &Mesh<Geometry<DrawTriangle>>,
+        mut extras: Value,
+    ) -> usize {
+        let index = self.add_mesh(name, &mesh.geometry);
+        let offset = mesh.parent_off.map(|c| c as f32);
+        let physics = &mesh.physics;
+        extras["volume"] = json!(finite(physics.volume));
+        extras["rcm"] = vec_value(&physics.rcm);
+        extras["jacobi"] = physics.jacobi.iter().map(|row| vec_value(row)).collect();
+        self.add_node(name, index, offset, None, extras)
+    }
+}
+
+/// Write the model as `.gltf` with the geometry in a `.bin` file next to it.
+/// The materials take their colors from the objects palette.
+pub fn export_m3d(full: &FullModel, palette: &[[u8; 4]; 0x100], model_path: &Path) -> IoResult<()> {
+    let bin_path = model_path.with_extension("bin");
+    let bin_name = bin_path.file_name().unwrap().to_string_lossy().into_owned();
+    let mut builder = Builder::default();
+    let mut children = Vec::new();
+
+    let body_extras = json!({
+        "bound": {
+            "dimensions": full.bound.dimensions,
+            "radius": full.bound.radius,
+        },
+        "color": { "offset": full.color.offset, "shift": full.color.shift },
+    });
+    children.push(builder.add_part("body", &full.body, body_extras));
+
+    for (i, wheel) in full.wheels.iter().enumerate() {
+        let name = format!("wheel{}", i);
+        let extras = json!({
+            "steer": wheel.steer,
+            "width": wheel.width,
+            "radius": wheel.radius,
+            "bound_index": wheel.bound_index,
+        });
+        children.push(match wheel.mesh {
+            Some(ref mesh) => builder.add_part(&name, mesh, extras),
+            None => builder.add_node(&name, None, wheel.pos, None, extras),
+        });
+    }
+
+    for (i, debrie) in full.debris.iter().enumerate() {
+        children.push(builder.add_part(&format!("debrie{}", i), &debrie.mesh, Value::Null));
+    }
+
+    for (i, slot) in full.slots.iter().enumerate() {
+        if full.slot_mask & (1 << i) == 0 {
+            continue;
+        }
+        let name = format!("slot{}", i);
+        let mesh = slot
+            .mesh
+            .as_ref()
+            .and_then(|mesh| builder.add_mesh(&name, &mesh.geometry));
+        let (sin, cos) = (0.5 * (slot.angle as f32).to_radians()).sin_cos();
+        let extras = json!({ "scale": slot.scale, "angle": slot.angle });
+        children.push(builder.add_node(
+            &name,
+            mesh,
+            slot.pos.map(|c| c as f32),
+            Some([0.0, sin, 0.0, cos]),
+            extras,
+        ));
+    }
+
+    let root_name = model_path
+        .file_stem()
+        .map_or("model".into(), |stem| stem.to_string_lossy());
+    let root = builder.nodes.len();
+    builder.nodes.push(json!({
+        "name": root_name,
+        "rotation": vec_value(&ROOT_ROTATION),
+        "children": children,
+    }));
+
+    let materials = (0..NUM_COLOR_IDS as usize)
+        .map(|color_id| {
+            // the brightest color of the range, as in the MTL export
+            let range = COLOR_TABLE[color_id];
+            let texel = range[0] as usize + (128 >> range[1]) as usize - 1;
+            let color = palette[texel];
+            json!({
+                "name": format!("{:?}", super::model_obj::map_color_id(color_id as u32)),
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [
+                        srgb_to_linear(color[0]),
+                        srgb_to_linear(color[1]),
+                        srgb_to_linear(color[2]),
+                        1.0,
+                    ],
+                    "metallicFactor": 0,
+                    "roughnessFactor": 0.8,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let document = json!({
+        "asset": { "version": "2.0", "generator": "vangers convert" },
+        "scene": 0,
+        "scenes": [{ "nodes": [root] }],
+        "nodes": builder.nodes,
+        "meshes": builder.meshes,
+        "materials": materials,
+        "accessors": builder.accessors,
+        "bufferViews": builder.views,
+        "buffers": [{ "uri": bin_name, "byteLength": builder.data.len() }],
+    });
+    fs::write(&bin_path, &builder.data)?;
+    fs::write(model_path, document.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    #[test]
+    fn fallback_car() {
+        let model = vangers::config::fallback::car_model();
+        let palette = vangers::config::fallback::palette();
+        let dir = std::env::temp_dir();
+        // the quote and the backslash have to be escaped in the root name
+        let path = dir.join(format!("vangers-\"car\\{}.gltf", std::process::id()));
+        super::export_m3d(&model, &palette, &path).unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        let data = std::fs::read(path.with_extension("bin")).unwrap();
+        std::fs::remove_file(path.with_extension("bin")).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let doc: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(doc["buffers"][0]["byteLength"], data.len());
+        let nodes = doc["nodes"].as_array().unwrap();
+        let root = &nodes[doc["scenes"][0]["nodes"][0].as_u64().unwrap() as usize];
+        assert_eq!(root["name"], path.file_stem().unwrap().to_str().unwrap());
+        let body = nodes.iter().find(|n| n["name"] == "body").unwrap();
+        assert!(body["extras"]["volume"].is_number());
+        for i in 0..model.wheels.len() {
+            let name = format!("wheel{}", i);
+            assert!(nodes.iter().any(|n| n["name"] == name.as_str()));
+        }
+
+        // one position and one normal per corner of the triangles
+        let mut parts = vec![("body".to_string(), &model.body.geometry)];
+        for (i, wheel) in model.wheels.iter().enumerate() {
+            if let Some(ref mesh) = wheel.mesh {
+                parts.push((format!("wheel{}", i), &mesh.geometry));
+            }
+        }
+        for (i, debrie) in model.debris.iter().enumerate() {
+            parts.push((format!("debrie{}", i), &debrie.mesh.geometry));
+        }
+        let accessors = doc["accessors"].as_array().unwrap();
+        let mut corners = 0;
+        for mesh in doc["meshes"].as_array().unwrap() {
+            let (_, geom) = parts
+                .iter()
+                .find(|(name, _)| mesh["name"] == name.as_str())
+                .unwrap();
+            for primitive in mesh["primitives"].as_array().unwrap() {
+                let accessor = |attribute: &str| {
+                    &accessors[primitive["attributes"][attribute].as_u64().unwrap() as usize]
+                };
+                let (position, normal) = (accessor("POSITION"), accessor("NORMAL"));
+                let color_id = primitive["material"].as_u64().unwrap() as u32;
+                let mut count = 0;
+                let mut min = [f64::INFINITY; 3];
+                let mut max = [f64::NEG_INFINITY; 3];
+                for poly in geom.polygons.iter().filter(|p| p.material[0] == color_id) {
+                    for v in &poly.vertices {
+                        let p = geom.positions[v.pos as usize];
+                        for i in 0..3 {
+                            min[i] = min[i].min(p[i] as f64);
+                            max[i] = max[i].max(p[i] as f64);
+                        }
+                        count += 1;
+                    }
+                }
+                assert_eq!(position["count"], count);
+                assert_eq!(normal["count"], count);
+                assert_eq!(position["min"], serde_json::json!(min));
+                assert_eq!(position["max"], serde_json::json!(max));
+                assert!(normal.get("min").is_none());
+                corners += count;
+            }
+        }
+        let triangles = parts.iter().map(|(_, g)| g.polygons.len()).sum::<usize>();
+        assert_eq!(corners, triangles * 3);
+        assert_eq!(data.len(), triangles * 3 * 2 * 12);
+    }
+}
@@ -16,7 +16,7 @@ type RefModel = Model<Mesh<String>, Mesh<String>>;
 type RefAnimatedMesh = AnimatedMesh<String>;
 type DrawAnimatedMesh = AnimatedMesh<Geometry<DrawTriangle>>;
 
-const MAT_NAME: &'static str = "object.mtl";
+const MAT_NAME: &str = "object.mtl";
 
 pub fn export_m3d(full: FullModel, model_path: &Path) {
     const BODY_PATH: &str = "body.obj";
@@ -73,6 +73,7 @@ pub fn export_m3d(full: FullModel, model_path: &Path) {
                 name
             })
         }),
+        slot_mask: full.slot_mask,
     };
 
     let string = ron::ser::to_string_pretty(&model, ron::ser::PrettyConfig::default()).unwrap();
@@ -87,11 +88,11 @@ pub fn import_m3d(model_path: &Path) -> FullModel {
     let resolve_geom_draw = |name| -> Geometry<DrawTriangle> { load_geometry(dir_path.join(name)) };
     let resolve_geom_coll =
         |name| -> Geometry<CollisionQuad> { load_geometry(dir_path.join(name)) };
-    let resolve_mesh = |mesh: Mesh<String>| mesh.map(&resolve_geom_draw);
+    let resolve_mesh = |mesh: Mesh<String>| mesh.map(resolve_geom_draw);
 
     FullModel {
-        body: model.body.map(&resolve_geom_draw),
-        shape: model.shape.map(&resolve_geom_coll),
+        body: model.body.map(resolve_geom_draw),
+        shape: model.shape.map(resolve_geom_coll),
         bound: model.bound,
         color: model.color,
         wheels: model
@@ -103,11 +104,12 @@ pub fn import_m3d(model_path: &Path) -> FullModel {
             .debris
             .into_iter()
             .map(|debrie| Debrie {
-                mesh: debrie.mesh.map(&resolve_geom_draw),
-                shape: debrie.shape.map(&resolve_geom_coll),
+                mesh: debrie.mesh.map(resolve_geom_draw),
+                shape: debrie.shape.map(resolve_geom_coll),
             })
             .collect(),
         slots: Slot::map_all(model.slots, |mesh, _| resolve_mesh(mesh)),
+        slot_mask: model.slot_mask,
     }
 }
 
@@ -150,10 +152,10 @@ pub fn import_a3d(mesh_path: &Path) -> DrawAnimatedMesh {
     }
 }
 
-fn map_color_id(id: u32) -> ColorId {
+pub fn map_color_id(id: u32) -> ColorId {
     use std::mem;
     if id < NUM_COLOR_IDS {
-        unsafe { mem::transmute(id) }
+        unsafe { mem::transmute::<u32, ColorId>(id) }
     } else {
         println!("Unknown ColorId {:?}", id);
         ColorId::Reserved
@@ -292,9 +294,9 @@ pub fn load_geometry<P: Polygon>(path: PathBuf) -> Geometry<P> {
         .iter()
         .map(|p| {
             [
-                p[0].min(NORMALIZER).max(-NORMALIZER) as i8,
-                p[1].min(NORMALIZER).max(-NORMALIZER) as i8,
-                p[2].min(NORMALIZER).max(-NORMALIZER) as i8,
+                p[0].clamp(-NORMALIZER, NORMALIZER) as i8,
+                p[1].clamp(-NORMALIZER, NORMALIZER) as i8,
+                p[2].clamp(-NORMALIZER, NORMALIZER) as i8,
             ]
         })
         .collect();
@@ -0,0 +1,56 @@
+//! Recompute the physics of existing models from their collision shapes,
+//! patching the files in place.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+fn collect(path: &Path, paths: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)
+            .unwrap_or_else(|e| panic!("Unable to read {}: {}", path.display(), e))
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        entries.sort();
+        for entry in entries {
+            collect(&entry, paths);
+        }
+    } else if path.extension().is_some_and(|ext| ext == "m3d") {
+        paths.push(path.to_path_buf());
+    }
+}
+
+/// Patch all the models at the given paths, searching the directories recursively.
+pub fn fix(inputs: &[String], dry_run: bool) {
+    let mut paths = Vec::new();
+    for input in inputs {
+        collect(Path::new(input), &mut paths);
+    }
+
+    for path in paths {
+        let mut model = match m3d::FullModel::load(File::open(&path).unwrap()) {
+            Ok(model) => model,
+            Err(e) => {
+                println!("\t{}: skipped, {}", path.display(), e);
+                continue;
+            }
+        };
+        let old_volume = model.body.physics.volume;
+        if model.recompute_physics() == 0 {
+            println!("\t{}: skipped, the shape is not closed", path.display());
+            continue;
+        }
+        println!(
+            "\t{}: volume {} -> {}, center {:?}",
+            path.display(),
+            old_volume,
+            model.body.physics.volume,
+            model.body.physics.rcm,
+        );
+        if !dry_run {
+            let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+            model.patch_physics(&mut file).unwrap();
+        }
+    }
+}
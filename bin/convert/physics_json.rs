@@ -0,0 +1,132 @@
+//! Export of the physics constants and the cars as JSON, see `--export-physics`.
+//!
+//! The output has the parsed `common.prm` under "common", and a list of "cars"
+//! in the order of `car.prm`, each with its stats, parameters, and the parts
+//! of the model the physics uses: the wheels, the bounds, and the mass properties.
+//! Positions are in the model units, before the car's "scale" is applied.
+//! The "mass" is derived the same way the physics step does it, and the
+//! "inertia" is the `jacobi` scaled by `scale² / volume`, as the step hands it
+//! to the rigid body, without a mass factor.
+
+use serde_derive::Serialize;
+use vangers::config::{self, car::CarEntry, common::Common};
+
+/// Wheel of the model, without the mesh.
+#[derive(Serialize)]
+struct Wheel {
+    pos: [f32; 3],
+    steer: u32,
+    width: u32,
+    radius: u32,
+    bound_index: u32,
+}
+
+/// Everything exported about a car.
+#[derive(Serialize)]
+struct CarExport<'a> {
+    name: &'a str,
+    kind: config::car::Kind,
+    scale: f32,
+    mass: f32,
+    inertia: [[f32; 3]; 3],
+    stats: &'a config::car::CarStats,
+    physics: &'a config::car::CarPhysics,
+    body: &'a m3d::Physics,
+    bounds: &'a m3d::Bounds,
+    bound: &'a m3d::UpperBound,
+    wheels: Vec<Wheel>,
+}
+
+#[derive(Serialize)]
+struct Export<'a> {
+    common: &'a Common,
+    cars: Vec<CarExport<'a>>,
+}
+
+fn export_car<'a>(entry: &'a CarEntry, model: &'a m3d::FullModel, density: f32) -> CarExport<'a> {
+    let physics = &model.body.physics;
+    // see the jump and the rigid body set up in `physics::step`
+    let scale2 = entry.scale * entry.scale;
+    let mass = density * physics.volume * scale2;
+    let inertia = physics
+        .jacobi
+        .map(|column| column.map(|v| v * scale2 / physics.volume));
+    CarExport {
+        name: &entry.name,
+        kind: entry.kind,
+        scale: entry.scale,
+        mass,
+        inertia,
+        stats: &entry.stats,
+        physics: &entry.physics,
+        body: physics,
+        bounds: &model.body.bounds,
+        bound: &model.bound,
+        wheels: model
+            .wheels
+            .iter()
+            .map(|w| Wheel {
+                pos: w.pos,
+                steer: w.steer,
+                width: w.width,
+                radius: w.radius,
+                bound_index: w.bound_index,
+            })
+            .collect(),
+    }
+}
+
+/// Compose the JSON of the constants and the cars with their models.
+pub fn to_json(common: &Common, cars: &[(CarEntry, m3d::FullModel)]) -> String {
+    let export = Export {
+        common,
+        cars: cars
+            .iter()
+            .map(|(entry, model)| export_car(entry, model, common.nature.density))
+            .collect(),
+    };
+    serde_json::to_string(&export).expect("Unable to serialize the physics")
+}
+
+/// Load the constants and the cars of the game, or the built-in ones without it.
+pub fn load(settings: &config::Settings) -> (Common, Vec<(CarEntry, m3d::FullModel)>) {
+    if !settings.has_game_data() {
+        let cars = vec![(config::fallback::car_entry(), config::fallback::car_model())];
+        return (config::fallback::common(), cars);
+    }
+    let common = config::common::load(settings.open_relative("common.prm"));
+    let registry = config::game::Registry::load(settings);
+    let cars = config::car::load_entries(settings, &registry)
+        .into_iter()
+        .map(|entry| {
+            let model = m3d::FullModel::load(settings.open_relative(&entry.model_path))
+                .expect("Unable to parse the car model");
+            (entry, model)
+        })
+        .collect();
+    (common, cars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_export() {
+        let common = config::fallback::common();
+        let cars = vec![(config::fallback::car_entry(), config::fallback::car_model())];
+        let json: serde_json::Value = serde_json::from_str(&to_json(&common, &cars)).unwrap();
+
+        assert!(json["common"]["nature"]["gravity"].is_number());
+        let car = &json["cars"][0];
+        assert_eq!(car["name"], config::fallback::CAR_NAME);
+        assert_eq!(car["kind"], "Main");
+        assert_eq!(car["wheels"].as_array().unwrap().len(), 4);
+
+        let physics = &cars[0].1.body.physics;
+        let mass = common.nature.density * physics.volume * 0.25;
+        assert_eq!(car["mass"].as_f64().unwrap() as f32, mass);
+        let inertia = physics.jacobi[0][0] * 0.25 / physics.volume;
+        assert_eq!(car["inertia"][0][0].as_f64().unwrap() as f32, inertia);
+    }
+}
@@ -0,0 +1,249 @@
+//! Offline rendering of the scene snapshots, see `vangers::render::snapshot`.
+//!
+//! This is a small software rasterizer, good enough to see where things are:
+//! the terrain is shaded by the height and slope, and the objects are drawn
+//! as their bounding boxes, colored by the body color.
+
+use cgmath::prelude::*;
+use vangers::render::snapshot::{Instance, MeshBounds, Snapshot};
+
+use std::{fs::File, path::Path};
+
+/// Width of the produced image, with the height following the camera aspect.
+pub const IMAGE_WIDTH: u32 = 800;
+/// Largest number of terrain quads along a side, the rest is skipped over.
+const MAX_TERRAIN_QUADS: i32 = 512;
+const BACKGROUND: [u8; 3] = [0x20, 0x20, 0x30];
+
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    pub color: Vec<[u8; 3]>,
+    depth: Vec<f32>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        let count = (width * height) as usize;
+        Canvas {
+            width,
+            height,
+            color: vec![BACKGROUND; count],
+            depth: vec![1.0; count],
+        }
+    }
+
+    /// Rasterize a triangle given in the clip space, skipping it if crossing the near plane.
+    fn triangle(&mut self, clip: [cgmath::Vector4<f32>; 3], color: [u8; 3]) {
+        if clip.iter().any(|v| v.w <= 0.0) {
+            return;
+        }
+        let screen = clip.map(|v| {
+            cgmath::vec3(
+                (0.5 + 0.5 * v.x / v.w) * self.width as f32,
+                (0.5 - 0.5 * v.y / v.w) * self.height as f32,
+                v.z / v.w,
+            )
+        });
+        let [a, b, c] = screen;
+        let area = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+        if area.abs() < 1e-6 {
+            return;
+        }
+        let x0 = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+        let y0 = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+        let x1 = (a.x.max(b.x).max(c.x).ceil() as u32).min(self.width);
+        let y1 = (a.y.max(b.y).max(c.y).ceil() as u32).min(self.height);
+        let edge = |p: cgmath::Vector3<f32>, q: cgmath::Vector3<f32>, x: f32, y: f32| {
+            ((q.x - p.x) * (y - p.y) - (q.y - p.y) * (x - p.x)) / area
+        };
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let (wa, wb, wc) = (edge(b, c, px, py), edge(c, a, px, py), edge(a, b, px, py));
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let depth = wa * a.z + wb * b.z + wc * c.z;
+                let index = (y * self.width + x) as usize;
+                if (0.0..self.depth[index]).contains(&depth) {
+                    self.depth[index] = depth;
+                    self.color[index] = color;
+                }
+            }
+        }
+    }
+}
+
+fn shade(base: [u8; 3], normal: cgmath::Vector3<f32>) -> [u8; 3] {
+    let light = cgmath::vec3(0.3, 0.5, 1.0).normalize();
+    let lit = 0.4 + 0.6 * normal.normalize().dot(light).abs();
+    base.map(|c| (c as f32 * lit) as u8)
+}
+
+fn body_color(color_id: u32) -> [u8; 3] {
+    const COLORS: [[u8; 3]; 6] = [
+        [0x40, 0xC0, 0x40],
+        [0xD0, 0x40, 0x30],
+        [0x40, 0x60, 0xE0],
+        [0xE0, 0xD0, 0x30],
+        [0xA0, 0xA0, 0xA0],
+        [0xC0, 0x60, 0xC0],
+    ];
+    COLORS[color_id as usize % COLORS.len()]
+}
+
+fn draw_terrain(canvas: &mut Canvas, snapshot: &Snapshot, view_proj: &cgmath::Matrix4<f32>) {
+    let terrain = &snapshot.terrain;
+    let step = (terrain.size.0.max(terrain.size.1) / MAX_TERRAIN_QUADS).max(1);
+    let point = |x: i32, y: i32| {
+        let height = terrain.height_at(x, y)? as f32;
+        Some(cgmath::vec3(x as f32, y as f32, height))
+    };
+    for y in (terrain.origin.1..terrain.origin.1 + terrain.size.1 - step).step_by(step as usize) {
+        for x in (terrain.origin.0..terrain.origin.0 + terrain.size.0 - step).step_by(step as usize)
+        {
+            let corners = match (
+                point(x, y),
+                point(x + step, y),
+                point(x + step, y + step),
+                point(x, y + step),
+            ) {
+                (Some(a), Some(b), Some(c), Some(d)) => [a, b, c, d],
+                _ => continue,
+            };
+            let gray = (0x40 + corners[0].z as u32 * 0xA0 / 0x100) as u8;
+            let normal = (corners[1] - corners[0]).cross(corners[3] - corners[0]);
+            let color = shade([gray, gray, (gray as u32 * 3 / 4) as u8], normal);
+            let clip = corners.map(|p| view_proj * p.extend(1.0));
+            canvas.triangle([clip[0], clip[1], clip[2]], color);
+            canvas.triangle([clip[0], clip[2], clip[3]], color);
+        }
+    }
+}
+
+fn draw_instance(
+    canvas: &mut Canvas,
+    instance: &Instance,
+    bounds: &MeshBounds,
+    view_proj: &cgmath::Matrix4<f32>,
+) {
+    if instance.fade <= 0.0 || instance.alpha <= 0.0 {
+        return;
+    }
+    let [qx, qy, qz, qw] = instance.orientation;
+    let rot = cgmath::Quaternion::new(qw, qx, qy, qz);
+    let position = cgmath::Vector3::from(instance.position);
+    let corner = |i: usize| {
+        let local: [f32; 3] = std::array::from_fn(|axis| match i & (1 << axis) {
+            0 => bounds.min[axis],
+            _ => bounds.max[axis],
+        });
+        position + rot * (cgmath::Vector3::from(local) * instance.scale)
+    };
+    let base = body_color(instance.color_id);
+    // faces as corner indices, same as the box of the fallback car
+    let faces: [[usize; 4]; 6] = [
+        [0, 2, 3, 1],
+        [4, 5, 7, 6],
+        [0, 1, 5, 4],
+        [2, 6, 7, 3],
+        [0, 4, 6, 2],
+        [1, 3, 7, 5],
+    ];
+    for face in faces.iter() {
+        let points = face.map(corner);
+        let color = shade(base, (points[1] - points[0]).cross(points[3] - points[0]));
+        let clip = points.map(|p| view_proj * p.extend(1.0));
+        canvas.triangle([clip[0], clip[1], clip[2]], color);
+        canvas.triangle([clip[0], clip[2], clip[3]], color);
+    }
+}
+
+pub fn render(snapshot: &Snapshot, width: u32) -> Canvas {
+    let height = ((width as f32 / snapshot.camera.aspect).round() as u32).max(1);
+    let mut canvas = Canvas::new(width, height);
+    let view_proj = cgmath::Matrix4::from(snapshot.camera.view_proj);
+    draw_terrain(&mut canvas, snapshot, &view_proj);
+    for instance in snapshot.instances.iter() {
+        match snapshot.meshes.get(instance.mesh) {
+            Some(bounds) => draw_instance(&mut canvas, instance, bounds, &view_proj),
+            None => println!("\tUnknown mesh {} of an instance", instance.mesh),
+        }
+    }
+    canvas
+}
+
+pub fn convert(src_path: &Path, dst_path: &Path) {
+    println!("\tLoading the snapshot...");
+    let snapshot = Snapshot::load(src_path).unwrap();
+    println!(
+        "\tRendering {} instances over {}x{} texels...",
+        snapshot.instances.len(),
+        snapshot.terrain.size.0,
+        snapshot.terrain.size.1
+    );
+    let canvas = render(&snapshot, IMAGE_WIDTH);
+    println!("\tSaving PNG...");
+    let file = File::create(dst_path).unwrap();
+    let mut encoder = png::Encoder::new(file, canvas.width, canvas.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(bytemuck::cast_slice(&canvas.color))
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vangers::render::snapshot::{CameraState, TerrainRegion};
+
+    #[test]
+    fn top_down() {
+        let size = (64, 64);
+        let camera = vangers::space::Camera {
+            loc: cgmath::vec3(32.0, 32.0, 500.0),
+            rot: cgmath::Quaternion::one(),
+            handedness: vangers::config::settings::Handedness::Right,
+            proj: vangers::space::Projection::ortho(64, 64, 1.0..1000.0),
+        };
+        let snapshot = Snapshot {
+            camera: CameraState {
+                position: camera.loc.into(),
+                orientation: [0.0, 0.0, 0.0, 1.0],
+                aspect: 1.0,
+                view_proj: camera.get_view_proj().into(),
+            },
+            meshes: vec![MeshBounds {
+                id: 0,
+                min: [-4.0; 3],
+                max: [4.0; 3],
+                num_vertices: 36,
+            }],
+            instances: vec![Instance {
+                mesh: 0,
+                position: [32.0, 32.0, 20.0],
+                scale: 1.0,
+                orientation: [0.0, 0.0, 0.0, 1.0],
+                color_id: 2,
+                fade: 1.0,
+                alpha: 1.0,
+            }],
+            terrain: TerrainRegion {
+                origin: (0, 0),
+                size,
+                height: vec![10; (size.0 * size.1) as usize],
+                meta: vec![0; (size.0 * size.1) as usize],
+            },
+        };
+        let canvas = render(&snapshot, 64);
+        assert_eq!((canvas.width, canvas.height), (64, 64));
+        let center = canvas.color[(32 * canvas.width + 32) as usize];
+        let corner = canvas.color[(4 * canvas.width + 4) as usize];
+        assert_eq!(center, shade(body_color(2), cgmath::Vector3::unit_z()));
+        assert_ne!(corner, BACKGROUND);
+        assert_ne!(corner, center);
+    }
+}
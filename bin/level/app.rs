@@ -1,13 +1,18 @@
-use crate::boilerplate::Application;
+use crate::{boilerplate::Application, diff, probe};
 use vangers::{
     config, level,
-    render::{Batcher, GraphicsContext, Render, ScreenTargets},
-    space,
+    locale::{tr, tr_fmt},
+    render::{brush::Brush, Batcher, GraphicsContext, Render, ScreenTargets},
+    space::{self, coords::ScreenPos},
 };
 
 use log::info;
+use std::ops::Range;
 use winit::event;
 
+/// Color of the brush preview, in linear RGBA.
+const BRUSH_COLOR: [f32; 4] = [1.0, 0.8, 0.1, 0.9];
+
 #[derive(Debug)]
 enum Input {
     Hor { dir: f32, alt: bool, shift: bool },
@@ -22,9 +27,28 @@ enum Input {
 pub struct LevelView {
     render: Render,
     level: level::Level,
+    /// Description of the level files, used to save the edited water levels.
+    level_config: level::LevelConfig,
+    /// Key of the level in the geometry overrides.
+    world_name: String,
+    geometry_overrides: config::worlds::GeometryOverrides,
+    /// What is needed to re-create the renders after a device loss.
+    objects_palette: [[u8; 4]; 0x100],
+    render_config: config::settings::Render,
+    diff: Option<diff::Diff>,
     cam: space::Camera,
+    /// Depth range of the camera at the ground level.
+    depth_base: Range<f32>,
+    auto_depth: bool,
     input: Input,
     ui: config::settings::Ui,
+    /// Last position of the cursor over the window, in pixels.
+    cursor: Option<cgmath::Vector2<f32>>,
+    screen_size: wgpu::Extent3d,
+    /// Radius of the brush previewed under the cursor, if shown.
+    brush_radius: f32,
+    show_brush: bool,
+    probe: probe::Probe,
 
     last_mouse_pos: cgmath::Vector2<f32>,
     alt_button_pressed: bool,
@@ -34,6 +58,7 @@ pub struct LevelView {
 impl LevelView {
     pub fn new(
         override_path: Option<&String>,
+        diff_path: Option<&String>,
         settings: &config::settings::Settings,
         gfx: &GraphicsContext,
     ) -> Self {
@@ -41,6 +66,12 @@ impl LevelView {
         let level_config = if settings.game.level.is_empty() {
             info!("Using test level");
             level::LevelConfig::new_test()
+        } else if let Some(preset) = level::TestPreset::from_level_name(&settings.game.level) {
+            info!("Using test level {:?}", preset);
+            level::LevelConfig::new_test_preset(preset)
+        } else if let Some(generator) = level::Generator::from_level_name(&settings.game.level) {
+            info!("Using generated level with seed {}", generator.seed);
+            level::LevelConfig::new_generated(generator)
         } else if let Some(ini_path) = override_path {
             info!("Using level at {}", ini_path);
             let full_path = settings.data_path.join(ini_path);
@@ -103,7 +134,7 @@ impl LevelView {
         let cam = space::Camera {
             loc: cgmath::vec3(0.0, 0.0, 400.0),
             rot: cgmath::One::one(),
-            scale: cgmath::vec3(1.0, -1.0, 1.0),
+            handedness: settings.render.handedness,
             proj: match settings.game.camera.projection {
                 config::settings::Projection::Perspective => {
                     let pf = cgmath::PerspectiveFov {
@@ -122,27 +153,69 @@ impl LevelView {
             },
         };
 
-        let objects_palette = level::read_palette(settings.open_palette(), None);
+        let is_test = settings.game.level.is_empty()
+            || level::TestPreset::from_level_name(&settings.game.level).is_some()
+            || level::Generator::from_level_name(&settings.game.level).is_some();
+        let world_name = match override_path {
+            Some(path) if !is_test => path.clone(),
+            _ => settings.game.level.clone(),
+        };
+        let geometry_overrides =
+            config::worlds::GeometryOverrides::load(config::worlds::GEOMETRY_PATH);
+        let geometry = geometry_overrides.get(&world_name, &settings.game.geometry);
+
+        let objects_palette = settings.load_palette(None);
         let render = Render::new(
             gfx,
             &level_config,
             &objects_palette,
             &settings.render,
-            &settings.game.geometry,
-            cam.front_face(),
+            &geometry,
         );
 
-        let mut level = level::load(&level_config, &settings.game.geometry);
+        let mut level = level::load(&level_config, &geometry);
         if let Some(pal_file) = override_palette {
             level.palette = level::read_palette(pal_file, Some(&level_config.terrains));
         }
 
+        let diff = diff_path.and_then(|path| {
+            info!("Comparing against level at {}", path);
+            let other_config = level::LevelConfig::load(&settings.data_path.join(path));
+            match diff::Diff::new(
+                &level_config,
+                &level,
+                &other_config,
+                &objects_palette,
+                &settings.render,
+                gfx,
+            ) {
+                Ok(diff) => Some(diff),
+                Err(e) => {
+                    log::error!("{}", e);
+                    None
+                }
+            }
+        });
+
         LevelView {
             render,
             level,
+            level_config,
+            world_name,
+            geometry_overrides,
+            objects_palette,
+            render_config: settings.render.clone(),
+            diff,
             cam,
+            depth_base: depth.0..depth.1,
+            auto_depth: settings.game.camera.auto_depth,
             input: Input::Empty,
-            ui: settings.ui.clone(),
+            ui: settings.ui,
+            cursor: None,
+            screen_size: gfx.screen_size,
+            brush_radius: 16.0,
+            show_brush: false,
+            probe: probe::Probe::default(),
             last_mouse_pos: cgmath::vec2(-1.0, -1.0),
             alt_button_pressed: false,
             mouse_button_pressed: false,
@@ -150,21 +223,67 @@ impl LevelView {
     }
 }
 
+impl LevelView {
+    /// Point of the terrain under the cursor.
+    fn pick_cursor(&self) -> Option<cgmath::Vector3<f32>> {
+        let cursor = self.cursor?;
+        let ndc = ScreenPos(cursor).to_ndc((self.screen_size.width, self.screen_size.height));
+        self.cam.pick(&self.level, ndc)
+    }
+
+    /// Describe the terrain under the cursor.
+    fn draw_cursor_ui(&self, ui: &mut egui::Ui) {
+        let point = match self.pick_cursor() {
+            Some(point) => point,
+            None => {
+                ui.label(tr("Cursor: no terrain"));
+                return;
+            }
+        };
+        let x = (point.x.floor() as i32).rem_euclid(self.level.size.0);
+        let y = (point.y.floor() as i32).rem_euclid(self.level.size.1);
+        ui.horizontal(|ui| {
+            ui.label(tr_fmt("Cursor: {}, {}", &[&x, &y]));
+            ui.separator();
+            match self.level.get((x, y)) {
+                level::Texel::Single(level::Point(height, terrain)) => {
+                    let height = format!("{:.1}", height);
+                    ui.label(tr_fmt("Height: {}, terrain {}", &[&height, &terrain]));
+                }
+                level::Texel::Dual { low, mid, high } => {
+                    let heights = format!("{:.1} / {:.1} / {:.1}", low.0, mid, high.0);
+                    ui.label(tr_fmt(
+                        "Heights: {}, terrains {} / {}",
+                        &[&heights, &low.1, &high.1],
+                    ));
+                }
+            }
+            ui.separator();
+            let flood = format!("{:.1}", self.level.flood_level(y));
+            ui.label(tr_fmt("Flood level: {}", &[&flood]));
+        });
+    }
+}
+
 impl Application for LevelView {
     fn on_cursor_move(&mut self, position: (f64, f64)) {
+        let position_vec = cgmath::vec2(position.0 as f32, position.1 as f32);
+        self.cursor = Some(position_vec);
+        if self.probe.is_dragging() {
+            if let Some(point) = self.pick_cursor() {
+                self.probe.drag(point);
+            }
+        }
         if !self.mouse_button_pressed {
             return;
         }
-        let position_vec = cgmath::vec2(position.0 as f32, position.1 as f32);
 
         if self.last_mouse_pos.x < 0.0 {
             self.last_mouse_pos = position_vec;
             return;
         }
 
-        let mut shift = position_vec - self.last_mouse_pos;
-        shift.x *= self.cam.scale.x;
-        shift.y *= self.cam.scale.y;
+        let shift = position_vec - self.last_mouse_pos;
 
         self.input = if self.alt_button_pressed {
             Input::RotQuant(shift)
@@ -189,6 +308,16 @@ impl Application for LevelView {
             self.mouse_button_pressed = state == event::ElementState::Pressed;
             self.last_mouse_pos = cgmath::vec2(-1.0, -1.0);
         }
+        if button == event::MouseButton::Right {
+            match state {
+                event::ElementState::Pressed => {
+                    if let Some(point) = self.pick_cursor() {
+                        self.probe.begin(point);
+                    }
+                }
+                event::ElementState::Released => self.probe.end(&self.level),
+            }
+        }
     }
 
     fn on_key(&mut self, input: event::KeyboardInput) -> bool {
@@ -206,41 +335,41 @@ impl Application for LevelView {
                 Key::Escape => return false,
                 Key::W => {
                     *i = Input::Ver {
-                        dir: self.cam.scale.y,
+                        dir: self.cam.handedness.y_sign(),
                         alt: modifiers.alt(),
                         shift: modifiers.shift(),
                     }
                 }
                 Key::S => {
                     *i = Input::Ver {
-                        dir: -self.cam.scale.y,
+                        dir: -self.cam.handedness.y_sign(),
                         alt: modifiers.alt(),
                         shift: modifiers.shift(),
                     }
                 }
                 Key::A => {
                     *i = Input::Hor {
-                        dir: -self.cam.scale.x,
+                        dir: -1.0,
                         alt: modifiers.alt(),
                         shift: modifiers.shift(),
                     }
                 }
                 Key::D => {
                     *i = Input::Hor {
-                        dir: self.cam.scale.x,
+                        dir: 1.0,
                         alt: modifiers.alt(),
                         shift: modifiers.shift(),
                     }
                 }
                 Key::Z => {
                     *i = Input::Dep {
-                        dir: -self.cam.scale.z,
+                        dir: -1.0,
                         alt: modifiers.alt(),
                     }
                 }
                 Key::X => {
                     *i = Input::Dep {
-                        dir: self.cam.scale.z,
+                        dir: 1.0,
                         alt: modifiers.alt(),
                     }
                 }
@@ -313,65 +442,125 @@ impl Application for LevelView {
                 self.cam.rot = self.cam.rot * rot;
             }
             Input::DepQuant(dir) => {
-                let vec = cgmath::Vector3::unit_z();
-                self.cam.loc += 1000.0 * delta * dir * vec.normalize();
+                self.cam.zoom(dir);
                 self.input = Input::Empty;
             }
-            Input::PlaneQuant(dir) => {
-                let vec_x = self.cam.rot * cgmath::vec3(-dir.x, 0.0, 0.0);
-                let vec_y = self.cam.rot * cgmath::vec3(0.0, dir.y, 0.0);
-
-                let mut vec = vec_x + vec_y;
-
-                let norm1 = vec.magnitude();
-                if norm1 > 0.0 {
-                    vec.z = 0.0;
-                    let norm = vec.magnitude();
-                    vec *= norm1 / norm;
-                    self.cam.loc += self.cam.loc.z * 0.2 * delta * vec;
-                }
+            Input::PlaneQuant(shift) => {
+                self.cam.pan(shift);
                 self.input = Input::Empty;
             }
-            Input::RotQuant(dir) => {
-                let speed = 0.3;
-                let rot_x = cgmath::Quaternion::from_angle_z(cgmath::Rad(speed * delta * dir.x));
-                let rot_y = cgmath::Quaternion::from_angle_x(cgmath::Rad(-speed * delta * dir.y));
-                self.cam.rot = rot_x * self.cam.rot * rot_y;
+            Input::RotQuant(shift) => {
+                self.cam.orbit(shift);
                 self.input = Input::Empty;
             }
             _ => {}
         }
+
+        if self.auto_depth {
+            self.cam
+                .fit_depth_range(self.depth_base.clone(), self.level.geometry.height as f32);
+        }
     }
 
     fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
+        self.screen_size = extent;
         self.cam
             .proj
             .update(extent.width as u16, extent.height as u16);
         self.render.resize(extent, device);
+        if let Some(ref mut diff) = self.diff {
+            for render in diff.renders() {
+                render.resize(extent, device);
+            }
+        }
     }
 
     fn reload(&mut self, device: &wgpu::Device) {
         self.render.reload(device);
+        if let Some(ref mut diff) = self.diff {
+            for render in diff.renders() {
+                render.reload(device);
+            }
+        }
+    }
+
+    fn recover(&mut self, gfx: &GraphicsContext) {
+        self.render.recover(
+            gfx,
+            &self.level_config,
+            &self.objects_palette,
+            &self.render_config,
+            &self.level.geometry,
+        );
+        if let Some(ref mut diff) = self.diff {
+            diff.recover(&self.objects_palette, &self.render_config, gfx);
+        }
     }
 
     fn draw_ui(&mut self, context: &egui::Context) {
         if !self.ui.enabled {
             return;
         }
+        self.probe.draw_line(context, &self.cam, self.screen_size);
+        self.probe.draw_ui(context);
         egui::SidePanel::right("Tweaks").show(context, |ui| {
             ui.group(|ui| {
-                ui.label("Camera:");
+                ui.label(tr("Camera:"));
+                ui.checkbox(&mut self.auto_depth, tr("Auto depth range"));
                 self.cam.draw_ui(ui);
             });
             ui.group(|ui| {
-                ui.label("Level:");
-                self.level.draw_ui(ui);
+                ui.label(tr("Level:"));
+                if self.level.draw_ui(ui) && !self.world_name.is_empty() {
+                    self.geometry_overrides
+                        .set(&self.world_name, &self.level.geometry);
+                }
+                // don't write the file on every step of a drag
+                if !ui.ctx().is_using_pointer() {
+                    self.geometry_overrides.save();
+                }
             });
             ui.group(|ui| {
-                ui.label("Renderer:");
+                ui.label(tr("Water:"));
+                egui::CollapsingHeader::new(tr("Sections")).show(ui, |ui| {
+                    if self.level.draw_flood_ui(ui) {
+                        self.render.terrain.dirty_flood = true;
+                    }
+                });
+                let has_file = !self.level_config.path_data.as_os_str().is_empty();
+                if ui
+                    .add_enabled(has_file, egui::Button::new(tr("Save")))
+                    .clicked()
+                {
+                    match level::save_flood(&self.level_config, &self.level.flood_map) {
+                        Ok(()) => info!("Saved the flood map"),
+                        Err(e) => log::error!("Unable to save the flood map: {}", e),
+                    }
+                }
+            });
+            ui.group(|ui| {
+                ui.label(tr("Brush:"));
+                ui.checkbox(&mut self.show_brush, tr("Show under the cursor"));
+                ui.add(
+                    egui::Slider::new(&mut self.brush_radius, 1.0..=128.0)
+                        .logarithmic(true)
+                        .text(tr("Radius")),
+                );
+            });
+            if let Some(ref mut diff) = self.diff {
+                ui.group(|ui| {
+                    ui.label(tr("Diff:"));
+                    diff.draw_ui(ui);
+                });
+            }
+            ui.group(|ui| {
+                ui.label(tr("Renderer:"));
                 self.render.draw_ui(ui);
             });
         });
+        egui::TopBottomPanel::bottom("Status").show(context, |ui| {
+            self.draw_cursor_ui(ui);
+        });
     }
 
     fn draw(&mut self, device: &wgpu::Device, targets: ScreenTargets) -> wgpu::CommandBuffer {
@@ -379,10 +568,23 @@ impl Application for LevelView {
             label: Some("World"),
         });
 
-        self.render.draw_world(
+        let brush = match self.pick_cursor() {
+            Some(point) if self.show_brush => Some(Brush {
+                center: point.truncate(),
+                radius: self.brush_radius,
+                color: BRUSH_COLOR,
+            }),
+            _ => None,
+        };
+        let (render, level) = match self.diff {
+            Some(ref mut diff) => diff.select(&self.level, &mut self.render),
+            None => (&mut self.render, &self.level),
+        };
+        render.brush = brush;
+        render.draw_world(
             &mut encoder,
             &mut Batcher::new(),
-            &self.level,
+            level,
             &self.cam,
             targets,
             None,
@@ -0,0 +1,304 @@
+//! Comparison of two versions of a level, e.g. the original and the converted one.
+//!
+//! The differences are shown as fake levels that keep the shape of the base level,
+//! but have every texel colored by how it changed.
+
+use vangers::{
+    config,
+    level::{self, DELTA_MASK, DOUBLE_LEVEL},
+    locale::tr,
+    render::{DirtyRect, GraphicsContext, Rect, Render},
+};
+
+/// What is shown in place of the level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum View {
+    Base,
+    Other,
+    /// Heat map of the height delta.
+    Height,
+    /// Mask of the changed terrain types and layer layouts.
+    Material,
+}
+
+impl View {
+    const ALL: [Self; 4] = [Self::Base, Self::Other, Self::Height, Self::Material];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Base => "base",
+            Self::Other => "other",
+            Self::Height => "height delta",
+            Self::Material => "material mask",
+        }
+    }
+}
+
+// Terrain types of the difference maps, each painted with a single color.
+const SAME: u8 = 0;
+const LOWER: [u8; 2] = [1, 2];
+const HIGHER: [u8; 2] = [3, 4];
+const CHANGED_TERRAIN: u8 = 5;
+const CHANGED_LAYOUT: u8 = 6;
+/// Height delta from which the texel is considered to be changed a lot.
+const LARGE_DELTA: i32 = 8;
+
+const COLORS: [[u8; 4]; 7] = [
+    [0x30, 0x30, 0x30, 0xFF],
+    [0x60, 0x90, 0xFF, 0xFF],
+    [0x10, 0x20, 0xC0, 0xFF],
+    [0xFF, 0xC0, 0x40, 0xFF],
+    [0xE0, 0x20, 0x10, 0xFF],
+    [0xFF, 0x30, 0xFF, 0xFF],
+    [0x30, 0xFF, 0x60, 0xFF],
+];
+
+fn height_bucket(delta: i32) -> u8 {
+    let index = (delta.abs() >= LARGE_DELTA) as usize;
+    match delta {
+        0 => SAME,
+        d if d < 0 => LOWER[index],
+        _ => HIGHER[index],
+    }
+}
+
+/// Terrain types per texel.
+type TypeMap = Box<[u8]>;
+
+/// Compute the height and the material maps.
+/// Fails if the levels have different sizes.
+pub fn compare(base: &level::Level, other: &level::Level) -> Result<(TypeMap, TypeMap), String> {
+    if base.size != other.size {
+        return Err(format!(
+            "Unable to compare levels of different sizes: {:?} and {:?}",
+            base.size, other.size
+        ));
+    }
+    let (base_bits, other_bits) = (base.terrain_bits(), other.terrain_bits());
+    let layout_mask = DOUBLE_LEVEL | DELTA_MASK;
+    let (height, material): (Vec<_>, Vec<_>) = base
+        .height
+        .iter()
+        .zip(other.height.iter())
+        .zip(base.meta.iter().zip(other.meta.iter()))
+        .map(|((&h0, &h1), (&m0, &m1))| {
+            let height = height_bucket(h1 as i32 - h0 as i32);
+            let material = if m0 & layout_mask != m1 & layout_mask {
+                CHANGED_LAYOUT
+            } else if base_bits.read(m0) != other_bits.read(m1) {
+                CHANGED_TERRAIN
+            } else {
+                SAME
+            };
+            (height, material)
+        })
+        .unzip();
+    Ok((height.into(), material.into()))
+}
+
+/// Make a level of the base shape, with the given terrain types.
+fn make_map(base: &level::Level, types: &[u8]) -> level::Level {
+    let bits = base.terrain_bits();
+    let type_mask = bits.write(bits.mask);
+    let mut palette = [[0; 4]; 0x100];
+    palette[..COLORS.len()].copy_from_slice(&COLORS);
+    level::Level {
+        size: base.size,
+        flood_map: base.flood_map.clone(),
+        height: base.height.clone(),
+        pyramid: base.pyramid.clone(),
+        meta: base
+            .meta
+            .iter()
+            .zip(types)
+            .map(|(&meta, &ty)| (meta & !type_mask) | bits.write(ty))
+            .collect(),
+        palette,
+        terrains: make_terrains(base.terrains.len()),
+        geometry: base.geometry.clone(),
+    }
+}
+
+fn make_terrains(count: usize) -> Box<[level::TerrainConfig]> {
+    (0..count)
+        .map(|i| {
+            let color = i.min(COLORS.len() - 1) as u8;
+            level::TerrainConfig {
+                shadow_offset: 0,
+                height_shift: 0,
+                colors: color..color,
+            }
+        })
+        .collect()
+}
+
+pub struct Diff {
+    pub view: View,
+    other: level::Level,
+    other_config: level::LevelConfig,
+    other_render: Render,
+    height_map: level::Level,
+    material_map: level::Level,
+    map_config: level::LevelConfig,
+    /// Shared by the maps, since they only differ in the terrain types.
+    map_render: Render,
+    /// Map currently uploaded into `map_render`.
+    active_map: View,
+}
+
+impl Diff {
+    pub fn new(
+        base_config: &level::LevelConfig,
+        base: &level::Level,
+        other_config: &level::LevelConfig,
+        objects_palette: &[[u8; 4]],
+        settings: &config::settings::Render,
+        gfx: &GraphicsContext,
+    ) -> Result<Self, String> {
+        let other = level::load(other_config, &base.geometry);
+        let (height_types, material_types) = compare(base, &other)?;
+        let other_render =
+            Render::new(gfx, other_config, objects_palette, settings, &base.geometry);
+        let changed = height_types.iter().filter(|&&t| t != SAME).count();
+        log::info!(
+            "{} of {} texels have a different height",
+            changed,
+            height_types.len()
+        );
+
+        let map_config = level::LevelConfig {
+            terrains: make_terrains(base.terrains.len()),
+            ..base_config.clone()
+        };
+        let map_render = Render::new(gfx, &map_config, objects_palette, settings, &base.geometry);
+
+        Ok(Diff {
+            view: View::Base,
+            height_map: make_map(base, &height_types),
+            material_map: make_map(base, &material_types),
+            other,
+            other_config: other_config.clone(),
+            other_render,
+            map_config,
+            map_render,
+            active_map: View::Height,
+        })
+    }
+
+    /// Pick the render and the level to draw, according to the view.
+    pub fn select<'a>(
+        &'a mut self,
+        base: &'a level::Level,
+        base_render: &'a mut Render,
+    ) -> (&'a mut Render, &'a level::Level) {
+        match self.view {
+            View::Base => (base_render, base),
+            View::Other => (&mut self.other_render, &self.other),
+            View::Height | View::Material => {
+                let map = if self.view == View::Height {
+                    &self.height_map
+                } else {
+                    &self.material_map
+                };
+                if self.active_map != self.view {
+                    self.active_map = self.view;
+                    self.map_render.terrain.dirty_rects.push(DirtyRect {
+                        rect: Rect {
+                            x: 0,
+                            y: 0,
+                            w: map.size.0 as _,
+                            h: map.size.1 as _,
+                        },
+                        z_range: 0..map.geometry.height as _,
+                        need_upload: true,
+                    });
+                }
+                (&mut self.map_render, map)
+            }
+        }
+    }
+
+    /// Re-create the renders on the new device, after the old one is lost.
+    pub fn recover(
+        &mut self,
+        objects_palette: &[[u8; 4]],
+        settings: &config::settings::Render,
+        gfx: &GraphicsContext,
+    ) {
+        let geometry = &self.other.geometry;
+        self.other_render
+            .recover(gfx, &self.other_config, objects_palette, settings, geometry);
+        self.map_render
+            .recover(gfx, &self.map_config, objects_palette, settings, geometry);
+    }
+
+    pub fn renders(&mut self) -> [&mut Render; 2] {
+        [&mut self.other_render, &mut self.map_render]
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        let mut index = View::ALL.iter().position(|&v| v == self.view).unwrap();
+        ui.add(
+            egui::Slider::new(&mut index, 0..=View::ALL.len() - 1)
+                .text(tr("View"))
+                .custom_formatter(|value, _| tr(View::ALL[value as usize].name()).to_string()),
+        );
+        self.view = View::ALL[index];
+        if let View::Height | View::Material = self.view {
+            let labels: &[(u8, &'static str)] = match self.view {
+                View::Height => &[
+                    (SAME, "same"),
+                    (LOWER[0], "lower"),
+                    (LOWER[1], "much lower"),
+                    (HIGHER[0], "higher"),
+                    (HIGHER[1], "much higher"),
+                ],
+                _ => &[
+                    (SAME, "same"),
+                    (CHANGED_TERRAIN, "terrain type"),
+                    (CHANGED_LAYOUT, "layer layout"),
+                ],
+            };
+            for &(ty, label) in labels {
+                let [r, g, b, _] = COLORS[ty as usize];
+                ui.colored_label(egui::Color32::from_rgb(r, g, b), tr(label));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(height: &[u8], meta: &[u8]) -> level::Level {
+        let config = level::LevelConfig::new_test();
+        level::Level {
+            size: (height.len() as i32, 1),
+            flood_map: vec![0].into_boxed_slice(),
+            pyramid: level::Pyramid::new((height.len() as i32, 1), height),
+            height: height.into(),
+            meta: meta.into(),
+            palette: [[0; 4]; 0x100],
+            terrains: config.terrains,
+            geometry: Default::default(),
+        }
+    }
+
+    #[test]
+    fn compare_texels() {
+        let bits = level::TerrainBits::new(8).unwrap();
+        let base = level(&[10, 10, 10, 10], &[0, 0, bits.write(1), 0]);
+        let other = level(&[10, 4, 30, 10], &[0, 0, bits.write(2), DOUBLE_LEVEL]);
+        let (height, material) = compare(&base, &other).unwrap();
+        assert_eq!(&*height, &[SAME, LOWER[0], HIGHER[1], SAME]);
+        assert_eq!(&*material, &[SAME, SAME, CHANGED_TERRAIN, CHANGED_LAYOUT]);
+
+        let map = make_map(&base, &material);
+        assert_eq!(bits.read(map.meta[2]), CHANGED_TERRAIN);
+        assert_eq!(map.height, base.height);
+
+        let smaller = level(&[10, 10], &[0, 0]);
+        assert!(compare(&base, &smaller).is_err());
+    }
+}
@@ -1,18 +1,25 @@
 mod app;
 #[path = "../boilerplate.rs"]
 mod boilerplate;
+mod diff;
+mod probe;
 
 fn main() {
     use std::env;
 
-    let (harness, settings) =
-        boilerplate::Harness::init(boilerplate::HarnessOptions { title: "level" });
-
     let args: Vec<_> = env::args().collect();
     let mut options = getopts::Options::new();
     options
         .parsing_style(getopts::ParsingStyle::StopAtFirstFree)
-        .optflag("h", "help", "print this help menu");
+        .optflag("h", "help", "print this help menu")
+        .optopt(
+            "",
+            "diff",
+            "compare against another version of the level",
+            "<path_to_level_ini>",
+        );
+
+    boilerplate::add_options(&mut options);
 
     let matches = options.parse(&args[1..]).unwrap();
     if matches.opt_present("h") || matches.free.len() > 1 {
@@ -22,8 +29,16 @@ fn main() {
         return;
     }
 
-    let path = matches.free.first();
-    let app = app::LevelView::new(path.as_deref(), &settings, &harness.graphics_ctx);
+    if boilerplate::list_adapters(&matches) {
+        return;
+    }
+
+    let (harness, settings) =
+        boilerplate::Harness::init(boilerplate::HarnessOptions::new("level", &matches));
 
-    harness.main_loop(app);
+    let path = matches.free.first().cloned();
+    let diff_path = matches.opt_str("diff");
+    harness.main_loop(move |gfx| {
+        app::LevelView::new(path.as_ref(), diff_path.as_ref(), &settings, gfx)
+    });
 }
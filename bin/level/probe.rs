@@ -0,0 +1,203 @@
+//! Cross-section of the terrain along a line drawn over the map.
+//!
+//! The line is dragged with the right mouse button, and every texel under it
+//! is plotted by the distance from the start: the top surface, the floor and
+//! the ceiling of the caves under the upper layer, and the water. Bumps in the
+//! plot are the bumps the cars would hit on the way.
+
+use vangers::{
+    level,
+    locale::{tr, tr_fmt},
+    space::{self, coords::ScreenPos, coords::WorldPos},
+};
+
+/// Color of the probe line over the map.
+const LINE_COLOR: egui::Color32 = egui::Color32::from_rgb(0xFF, 0x40, 0xFF);
+
+/// Heights of the level at a point of the cross-section.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// Distance from the start of the line, in texels.
+    pub distance: f32,
+    /// Top of the terrain, seen from above.
+    pub top: f32,
+    /// Floor of the lower layer, equal to the top where there is only one.
+    pub floor: f32,
+    /// Bottom of the upper layer, equal to the top where there is only one.
+    pub ceiling: f32,
+    pub water: f32,
+}
+
+/// Sample the level along the line, once per texel of the length.
+pub fn cross_section(
+    level: &level::Level,
+    start: cgmath::Vector2<f32>,
+    end: cgmath::Vector2<f32>,
+) -> Vec<Sample> {
+    use cgmath::InnerSpace as _;
+
+    let length = (end - start).magnitude();
+    let count = length.ceil() as usize + 1;
+    (0..count)
+        .map(|i| {
+            let t = if count > 1 {
+                i as f32 / (count - 1) as f32
+            } else {
+                0.0
+            };
+            let point = start + (end - start) * t;
+            let texel = WorldPos(point.extend(0.0)).texel();
+            let (top, floor, ceiling) = match level.get(texel) {
+                level::Texel::Single(level::Point(height, _)) => (height, height, height),
+                level::Texel::Dual { low, mid, high } => (high.0, low.0, mid),
+            };
+            Sample {
+                distance: length * t,
+                top,
+                floor,
+                ceiling,
+                water: level.flood_level(texel.y),
+            }
+        })
+        .collect()
+}
+
+#[derive(Default)]
+pub struct Probe {
+    /// Start and end of the line on the map, if drawn.
+    line: Option<(cgmath::Vector3<f32>, cgmath::Vector3<f32>)>,
+    samples: Vec<Sample>,
+    is_dragging: bool,
+}
+
+impl Probe {
+    /// Start a new line at the point of the terrain.
+    pub fn begin(&mut self, point: cgmath::Vector3<f32>) {
+        self.line = Some((point, point));
+        self.samples.clear();
+        self.is_dragging = true;
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.is_dragging
+    }
+
+    /// Move the end of the line being drawn.
+    pub fn drag(&mut self, point: cgmath::Vector3<f32>) {
+        if let Some((_, ref mut end)) = self.line {
+            *end = point;
+        }
+    }
+
+    /// Finish the line and sample the level under it.
+    pub fn end(&mut self, level: &level::Level) {
+        if !self.is_dragging {
+            return;
+        }
+        self.is_dragging = false;
+        if let Some((start, end)) = self.line {
+            self.samples = cross_section(level, start.truncate(), end.truncate());
+        }
+    }
+
+    /// Draw the line over the map.
+    pub fn draw_line(&self, context: &egui::Context, cam: &space::Camera, extent: wgpu::Extent3d) {
+        let (start, end) = match self.line {
+            Some(line) => line,
+            None => return,
+        };
+        let view_proj = cam.get_view_proj();
+        let scale = context.pixels_per_point();
+        let to_screen = |point: cgmath::Vector3<f32>| {
+            let clip = view_proj * point.extend(1.0);
+            if clip.w <= 0.0 {
+                return None;
+            }
+            let ndc = cgmath::vec2(clip.x / clip.w, clip.y / clip.w);
+            let pos = ScreenPos::from_ndc(ndc, (extent.width, extent.height)).0;
+            Some(egui::pos2(pos.x / scale, pos.y / scale))
+        };
+        if let (Some(a), Some(b)) = (to_screen(start), to_screen(end)) {
+            context
+                .layer_painter(egui::LayerId::background())
+                .line_segment([a, b], egui::Stroke::new(2.0, LINE_COLOR));
+        }
+    }
+
+    pub fn draw_ui(&mut self, context: &egui::Context) {
+        if self.samples.is_empty() {
+            return;
+        }
+        let mut is_open = true;
+        egui::Window::new(tr("Cross-section"))
+            .open(&mut is_open)
+            .default_size([400.0, 200.0])
+            .show(context, |ui| {
+                let (start, end) = self.line.unwrap();
+                let length = format!("{:.0}", self.samples.last().unwrap().distance);
+                ui.label(tr_fmt(
+                    "From {}, {} to {}, {}, length {}",
+                    &[
+                        &(start.x as i32),
+                        &(start.y as i32),
+                        &(end.x as i32),
+                        &(end.y as i32),
+                        &length,
+                    ],
+                ));
+                let line = |name: &str, get: fn(&Sample) -> f32| {
+                    let points = self
+                        .samples
+                        .iter()
+                        .map(|s| [s.distance as f64, get(s) as f64])
+                        .collect::<egui::plot::PlotPoints>();
+                    egui::plot::Line::new(points).name(name)
+                };
+                egui::plot::Plot::new("Cross-section")
+                    .legend(egui::plot::Legend::default())
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(line(tr("top"), |s| s.top));
+                        plot_ui.line(line(tr("floor"), |s| s.floor));
+                        plot_ui.line(line(tr("ceiling"), |s| s.ceiling));
+                        plot_ui.line(line(tr("water"), |s| s.water));
+                    });
+            });
+        if !is_open {
+            self.line = None;
+            self.samples.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vangers::config::settings::Geometry;
+
+    #[test]
+    fn cross_section_of_cave() {
+        let cave = level::load(
+            &level::LevelConfig::new_test_preset(level::TestPreset::Cave),
+            &Geometry::default(),
+        );
+        let y = 0.5;
+        let samples = cross_section(
+            &cave,
+            cgmath::vec2(0.5, y),
+            cgmath::vec2(cave.size.0 as f32 - 0.5, y),
+        );
+        assert_eq!(samples.len(), cave.size.0 as usize);
+        assert_eq!(samples[0].distance, 0.0);
+        assert_eq!(samples.last().unwrap().distance, cave.size.0 as f32 - 1.0);
+        assert!(samples
+            .iter()
+            .all(|s| s.floor <= s.ceiling && s.ceiling <= s.top));
+        assert!(
+            samples.iter().any(|s| s.floor < s.ceiling),
+            "The cave is missing"
+        );
+
+        let point = cross_section(&cave, cgmath::vec2(1.5, y), cgmath::vec2(1.5, y));
+        assert_eq!(point.len(), 1);
+    }
+}
@@ -1,23 +1,80 @@
-use crate::boilerplate::Application;
+use crate::{boilerplate::Application, session};
 use cgmath::Transform as _;
-use vangers::{config, level, model, render, space};
+use vangers::{config, model, render, space};
 
 use log::info;
+use std::path::PathBuf;
 
 struct CarContext {
     color: render::object::BodyColor,
     physics: config::car::CarPhysics,
 }
 
+/// Load the model to view, and the car it belongs to, if any.
+/// Returns them with the source path of the model.
+fn load_model(
+    path: Option<&String>,
+    settings: &config::settings::Settings,
+    gfx: &render::GraphicsContext,
+    object: &render::object::Context,
+) -> (model::VisualModel, Option<CarContext>, String) {
+    if let Some(path_str) = path {
+        info!("Loading model {}", path_str);
+        let file = settings.open_relative(path_str);
+        let model = model::load_m3d(
+            file,
+            &gfx.device,
+            object,
+            settings.game.physics.shape_sampling,
+        );
+        (model, None, path_str.clone())
+    } else {
+        info!("Loading car registry");
+        let game_reg = config::game::Registry::load(settings);
+        let car_reg = config::car::load_registry(settings, &game_reg, &gfx.device, object);
+        let cinfo = match car_reg.get(&settings.car.id) {
+            Some(ci) => ci,
+            None => {
+                let names = car_reg.keys().collect::<Vec<_>>();
+                panic!("Unable to find `{}` in {:?}", settings.car.id, names);
+            }
+        };
+
+        let source = game_reg
+            .model_infos
+            .get(&settings.car.id)
+            .map_or(String::new(), |info| info.path.clone());
+        let mut model = cinfo.model.clone();
+        for (ms, sid) in model.slots.iter_mut().zip(settings.car.slots.iter()) {
+            let info = &game_reg.model_infos[sid];
+            let raw = m3d::Mesh::load(&mut settings.open_relative(&info.path))
+                .expect("Unable to parse the slot mesh");
+            ms.mesh = Some(model::load_c3d(raw, None, &gfx.device));
+            ms.scale = info.scale;
+        }
+
+        let cc = CarContext {
+            color: settings.car.color,
+            physics: cinfo.physics.clone(),
+        };
+        (model, Some(cc), source)
+    }
+}
+
 pub struct ResourceView {
     model: model::VisualModel,
     car: Option<CarContext>,
+    editor: session::Editor,
+    data_path: PathBuf,
     global: render::global::Context,
     object: render::object::Context,
     transform: space::Transform,
     camera: space::Camera,
     rotation: (cgmath::Rad<f32>, cgmath::Rad<f32>),
     light_config: config::settings::Light,
+    /// What is needed to re-create the GPU resources after a device loss.
+    path: Option<String>,
+    settings: config::settings::Settings,
 }
 
 impl ResourceView {
@@ -29,7 +86,7 @@ impl ResourceView {
         let camera = space::Camera {
             loc: cgmath::vec3(0.0, -200.0, 100.0),
             rot: cgmath::Rotation3::from_angle_x::<cgmath::Rad<_>>(cgmath::Angle::turn_div_6()),
-            scale: cgmath::vec3(1.0, 1.0, 1.0),
+            handedness: config::settings::Handedness::Right,
             proj: space::Projection::Perspective(cgmath::PerspectiveFov {
                 fovy: cgmath::Deg(45.0).into(),
                 aspect: settings.window.size[0] as f32 / settings.window.size[1] as f32,
@@ -39,50 +96,20 @@ impl ResourceView {
         };
 
         info!("Initializing the render");
-        let pal_data = level::read_palette(settings.open_palette(), None);
+        let pal_data = settings.load_palette(None);
         let global = render::global::Context::new(gfx, None);
         let object = render::object::Context::new(gfx, camera.front_face(), &pal_data, &global);
 
-        let (model, car) = if let Some(path_str) = path {
-            info!("Loading model {}", path_str);
-            let file = settings.open_relative(path_str);
-            let model = model::load_m3d(
-                file,
-                &gfx.device,
-                &object,
-                settings.game.physics.shape_sampling,
-            );
-            (model, None)
-        } else {
-            info!("Loading car registry");
-            let game_reg = config::game::Registry::load(settings);
-            let car_reg = config::car::load_registry(settings, &game_reg, &gfx.device, &object);
-            let cinfo = match car_reg.get(&settings.car.id) {
-                Some(ci) => ci,
-                None => {
-                    let names = car_reg.keys().collect::<Vec<_>>();
-                    panic!("Unable to find `{}` in {:?}", settings.car.id, names);
-                }
-            };
-
-            let mut model = cinfo.model.clone();
-            for (ms, sid) in model.slots.iter_mut().zip(settings.car.slots.iter()) {
-                let info = &game_reg.model_infos[sid];
-                let raw = m3d::Mesh::load(&mut settings.open_relative(&info.path));
-                ms.mesh = Some(model::load_c3d(raw, &gfx.device));
-                ms.scale = info.scale;
-            }
+        let (model, car, source) = load_model(path, settings, gfx, &object);
 
-            let cc = CarContext {
-                color: settings.car.color,
-                physics: cinfo.physics.clone(),
-            };
-            (model, Some(cc))
-        };
+        let parts = session::Parts::new(&model, model.body.physics);
+        let editor = session::Editor::new(source, parts, model.slot_mask);
 
         ResourceView {
             model,
             car,
+            editor,
+            data_path: settings.data_path.clone(),
             global,
             object,
             transform: cgmath::Decomposed {
@@ -93,6 +120,8 @@ impl ResourceView {
             camera,
             rotation: (cgmath::Rad(0.), cgmath::Rad(0.)),
             light_config: settings.render.light,
+            path: path.cloned(),
+            settings: settings.clone(),
         }
     }
 
@@ -158,7 +187,8 @@ impl Application for ResourceView {
             self.rotate_x(rot);
         }
 
-        let global_data = render::global::Constants::new(&self.camera, &self.light_config, None);
+        let global_data =
+            render::global::Constants::new(&self.camera, self.camera.loc, &self.light_config, None);
         queue.write_buffer(
             &self.global.uniform_buf,
             0,
@@ -176,7 +206,22 @@ impl Application for ResourceView {
         self.object.reload(device);
     }
 
-    fn draw_ui(&mut self, _context: &egui::Context) {}
+    fn recover(&mut self, gfx: &render::GraphicsContext) {
+        let pal_data = self.settings.load_palette(None);
+        self.global = render::global::Context::new(gfx, None);
+        self.object =
+            render::object::Context::new(gfx, self.camera.front_face(), &pal_data, &self.global);
+        let (model, _, _) = load_model(self.path.as_ref(), &self.settings, gfx, &self.object);
+        self.model = model;
+        // the edits are kept by the editor, only the model is loaded again
+        self.editor.parts.write(&mut self.model);
+    }
+
+    fn draw_ui(&mut self, context: &egui::Context) {
+        if self.editor.draw_ui(context, &self.data_path) {
+            self.editor.parts.write(&mut self.model);
+        }
+    }
 
     fn draw(
         &mut self,
@@ -190,7 +235,7 @@ impl Application for ResourceView {
         };
         let mut batcher = render::Batcher::new();
         batcher.add_model(&self.model, &self.transform, bound, color);
-        batcher.prepare(device);
+        batcher.prepare(device, &mut render::Stats::default(), self.camera.loc);
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Draw"),
@@ -1,19 +1,19 @@
 mod app;
 #[path = "../boilerplate.rs"]
 mod boilerplate;
+mod session;
 
 fn main() {
     use std::env;
 
-    let (harness, settings) =
-        boilerplate::Harness::init(boilerplate::HarnessOptions { title: "model" });
-
     let args: Vec<_> = env::args().collect();
     let mut options = getopts::Options::new();
     options
         .parsing_style(getopts::ParsingStyle::StopAtFirstFree)
         .optflag("h", "help", "print this help menu");
 
+    boilerplate::add_options(&mut options);
+
     let matches = options.parse(&args[1..]).unwrap();
     if matches.opt_present("h") || matches.free.len() > 1 {
         println!("Vangers model viewer");
@@ -22,8 +22,13 @@ fn main() {
         return;
     }
 
-    let path = matches.free.first();
-    let app = app::ResourceView::new(path, &settings, &harness.graphics_ctx);
+    if boilerplate::list_adapters(&matches) {
+        return;
+    }
+
+    let (harness, settings) =
+        boilerplate::Harness::init(boilerplate::HarnessOptions::new("model", &matches));
 
-    harness.main_loop(app);
+    let path = matches.free.first().cloned();
+    harness.main_loop(move |gfx| app::ResourceView::new(path.as_ref(), &settings, gfx));
 }
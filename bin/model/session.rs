@@ -0,0 +1,431 @@
+//! Editing of the wheels, the slots, and the physics of a model.
+//!
+//! The edits are kept in a session file, holding only what differs from the
+//! source M3D, so the original game files are never touched while iterating.
+//! Writing a new M3D is a separate explicit action, reading the source again,
+//! applying the session on top, and refusing to write into the game data.
+
+use serde_derive::{Deserialize, Serialize};
+use vangers::locale::{tr, tr_fmt};
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+/// Editable parameters of a wheel.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Wheel {
+    pub pos: [f32; 3],
+    pub steer: u32,
+    pub width: u32,
+    pub radius: u32,
+}
+
+/// Editable parameters of a slot. The scale is not a part of M3D.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Slot {
+    pub pos: [i32; 3],
+    pub angle: i32,
+}
+
+/// All the editable parameters of a model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parts {
+    pub wheels: Vec<Wheel>,
+    pub slots: Vec<Slot>,
+    pub physics: m3d::Physics,
+}
+
+impl Parts {
+    pub fn new<M, S>(model: &m3d::Model<M, S>, physics: m3d::Physics) -> Self {
+        Parts {
+            wheels: model
+                .wheels
+                .iter()
+                .map(|w| Wheel {
+                    pos: w.pos,
+                    steer: w.steer,
+                    width: w.width,
+                    radius: w.radius,
+                })
+                .collect(),
+            slots: model
+                .slots
+                .iter()
+                .map(|s| Slot {
+                    pos: s.pos,
+                    angle: s.angle,
+                })
+                .collect(),
+            physics,
+        }
+    }
+
+    /// Write the wheels and the slots into the model. The physics is
+    /// a part of the body mesh, which differs between the model kinds.
+    pub fn write<M, S>(&self, model: &mut m3d::Model<M, S>) {
+        for (w, wheel) in model.wheels.iter_mut().zip(&self.wheels) {
+            w.pos = wheel.pos;
+            w.steer = wheel.steer;
+            w.width = wheel.width;
+            w.radius = wheel.radius;
+        }
+        for (s, slot) in model.slots.iter_mut().zip(&self.slots) {
+            s.pos = slot.pos;
+            s.angle = slot.angle;
+        }
+    }
+}
+
+/// Edits of a model, stored as the difference against the source.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// Path to the source M3D, relative to the game data.
+    pub source: String,
+    #[serde(default)]
+    pub wheels: BTreeMap<usize, Wheel>,
+    #[serde(default)]
+    pub slots: BTreeMap<usize, Slot>,
+    #[serde(default)]
+    pub physics: Option<m3d::Physics>,
+}
+
+impl Session {
+    /// Collect everything that changed between the original and the edited parts.
+    pub fn diff(source: &str, original: &Parts, edited: &Parts) -> Self {
+        fn changed<T: Copy + PartialEq>(original: &[T], edited: &[T]) -> BTreeMap<usize, T> {
+            original
+                .iter()
+                .zip(edited)
+                .enumerate()
+                .filter(|&(_, (a, b))| a != b)
+                .map(|(i, (_, &b))| (i, b))
+                .collect()
+        }
+        Session {
+            source: source.to_string(),
+            wheels: changed(&original.wheels, &edited.wheels),
+            slots: changed(&original.slots, &edited.slots),
+            physics: Some(edited.physics).filter(|p| *p != original.physics),
+        }
+    }
+
+    pub fn num_changes(&self) -> usize {
+        self.wheels.len() + self.slots.len() + self.physics.is_some() as usize
+    }
+
+    /// Apply the edits on top of the parts of the source model.
+    pub fn apply(&self, parts: &mut Parts) -> Result<(), String> {
+        if let Some(&index) = self.wheels.keys().find(|&&i| i >= parts.wheels.len()) {
+            return Err(format!("Model has no wheel {}", index));
+        }
+        if let Some(&index) = self.slots.keys().find(|&&i| i >= parts.slots.len()) {
+            return Err(format!("Model has no slot {}", index));
+        }
+        for (&i, &wheel) in self.wheels.iter() {
+            parts.wheels[i] = wheel;
+        }
+        for (&i, &slot) in self.slots.iter() {
+            parts.slots[i] = slot;
+        }
+        if let Some(physics) = self.physics {
+            parts.physics = physics;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| format!("Unable to write {:?}: {}", path, e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Unable to open {:?}: {}", path, e))?;
+        ron::de::from_reader(file).map_err(|e| format!("Unable to parse {:?}: {}", path, e))
+    }
+}
+
+/// Check if the path is within the directory, resolving the links
+/// and the relative parts of both, even if the path doesn't exist yet.
+fn is_inside(path: &Path, dir: &Path) -> bool {
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(_) => return false,
+    };
+    let path = match std::env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => path.to_path_buf(),
+    };
+    // the file itself may be missing, but its folder has to be there for writing
+    let resolved = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => match parent.canonicalize() {
+            Ok(parent) => parent.join(name),
+            Err(_) => path.clone(),
+        },
+        _ => path.clone(),
+    };
+    resolved.starts_with(dir)
+}
+
+/// Write a new M3D with the session applied to its source.
+pub fn export(session: &Session, data_path: &Path, target: &Path) -> Result<(), String> {
+    if session.source.is_empty() {
+        return Err("The model has no source file".to_string());
+    }
+    if is_inside(target, data_path) {
+        return Err(format!(
+            "Refusing to write {:?} into the game data, pick another path",
+            target
+        ));
+    }
+    let source = data_path.join(&session.source);
+    let file = File::open(&source).map_err(|e| format!("Unable to open {:?}: {}", source, e))?;
+    let mut model =
+        m3d::FullModel::load(file).map_err(|e| format!("Unable to parse {:?}: {}", source, e))?;
+    let mut parts = Parts::new(&model, model.body.physics);
+    session.apply(&mut parts)?;
+    parts.write(&mut model);
+    model.body.physics = parts.physics;
+
+    let file = File::create(target).map_err(|e| format!("Unable to create {:?}: {}", target, e))?;
+    model.save(file);
+    Ok(())
+}
+
+/// Editor of the model parameters, with the session and the export.
+pub struct Editor {
+    /// Path to the source M3D, empty for the built-in models.
+    source: String,
+    original: Parts,
+    pub parts: Parts,
+    slot_mask: u32,
+    session_path: String,
+    export_path: String,
+    status: String,
+}
+
+impl Editor {
+    pub fn new(source: String, original: Parts, slot_mask: u32) -> Self {
+        let stem = Path::new(&source)
+            .file_stem()
+            .map_or("model".into(), |stem| stem.to_string_lossy());
+        Editor {
+            session_path: format!("{}.session.ron", stem),
+            export_path: format!("{}.m3d", stem),
+            source,
+            parts: original.clone(),
+            original,
+            slot_mask,
+            status: String::new(),
+        }
+    }
+
+    pub fn session(&self) -> Session {
+        Session::diff(&self.source, &self.original, &self.parts)
+    }
+
+    fn load_session(&mut self) -> Result<(), String> {
+        let session = Session::load(Path::new(&self.session_path))?;
+        if session.source != self.source {
+            return Err(format!(
+                "Session is made for {:?}, not {:?}",
+                session.source, self.source
+            ));
+        }
+        let mut parts = self.original.clone();
+        session.apply(&mut parts)?;
+        self.parts = parts;
+        Ok(())
+    }
+
+    fn set_status(&mut self, result: Result<String, String>) {
+        self.status = match result {
+            Ok(message) => {
+                log::info!("{}", message);
+                message
+            }
+            Err(message) => {
+                log::error!("{}", message);
+                message
+            }
+        };
+    }
+
+    /// Draw the editor, returning true if the parts changed.
+    pub fn draw_ui(&mut self, context: &egui::Context, data_path: &Path) -> bool {
+        let before = self.parts.clone();
+        egui::Window::new(tr("Model")).show(context, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(400.0)
+                .show(ui, |ui| self.draw_parts(ui));
+            ui.separator();
+            let session = self.session();
+            ui.label(tr_fmt("{} changes", &[&session.num_changes()]));
+
+            ui.horizontal(|ui| {
+                ui.label(tr("Session"));
+                ui.text_edit_singleline(&mut self.session_path);
+            });
+            ui.horizontal(|ui| {
+                if ui.button(tr("Save")).clicked() {
+                    let path = PathBuf::from(&self.session_path);
+                    let result = session
+                        .save(&path)
+                        .map(|()| format!("Session saved to {:?}", path));
+                    self.set_status(result);
+                }
+                if ui.button(tr("Load")).clicked() {
+                    let result = self
+                        .load_session()
+                        .map(|()| format!("Session loaded from {:?}", self.session_path));
+                    self.set_status(result);
+                }
+                if ui.button(tr("Revert")).clicked() {
+                    self.parts = self.original.clone();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label(tr("Export"));
+                ui.text_edit_singleline(&mut self.export_path);
+            });
+            let can_export = !self.source.is_empty();
+            if ui
+                .add_enabled(can_export, egui::Button::new(tr("Export m3d")))
+                .clicked()
+            {
+                let path = PathBuf::from(&self.export_path);
+                let result =
+                    export(&session, data_path, &path).map(|()| format!("Exported to {:?}", path));
+                self.set_status(result);
+            }
+            if !self.status.is_empty() {
+                ui.label(&self.status);
+            }
+        });
+        self.parts != before
+    }
+
+    fn draw_parts(&mut self, ui: &mut egui::Ui) {
+        for (i, wheel) in self.parts.wheels.iter_mut().enumerate() {
+            ui.collapsing(tr_fmt("Wheel {}", &[&i]), |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(tr("Position"));
+                    for v in wheel.pos.iter_mut() {
+                        ui.add(egui::DragValue::new(v).speed(0.1));
+                    }
+                });
+                let mut steer = wheel.steer != 0;
+                ui.checkbox(&mut steer, tr("Steering"));
+                wheel.steer = steer as u32;
+                ui.horizontal(|ui| {
+                    ui.label(tr("Width"));
+                    ui.add(egui::DragValue::new(&mut wheel.width));
+                    ui.label(tr("Radius"));
+                    ui.add(egui::DragValue::new(&mut wheel.radius));
+                });
+            });
+        }
+        for (i, slot) in self.parts.slots.iter_mut().enumerate() {
+            if self.slot_mask & (1 << i) == 0 {
+                continue;
+            }
+            ui.collapsing(tr_fmt("Slot {}", &[&i]), |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(tr("Position"));
+                    for v in slot.pos.iter_mut() {
+                        ui.add(egui::DragValue::new(v));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(tr("Angle"));
+                    ui.add(egui::DragValue::new(&mut slot.angle));
+                });
+            });
+        }
+        let physics = &mut self.parts.physics;
+        ui.collapsing(tr("Physics"), |ui| {
+            ui.horizontal(|ui| {
+                ui.label(tr("Volume"));
+                ui.add(egui::DragValue::new(&mut physics.volume).speed(0.1));
+            });
+            ui.horizontal(|ui| {
+                ui.label(tr("Center of mass"));
+                for v in physics.rcm.iter_mut() {
+                    ui.add(egui::DragValue::new(v).speed(0.01));
+                }
+            });
+            ui.label(tr("Inertia"));
+            egui::Grid::new("jacobi").show(ui, |ui| {
+                // the matrix is column-major, showing it by rows
+                for row in 0..3 {
+                    for column in physics.jacobi.iter_mut() {
+                        ui.add(egui::DragValue::new(&mut column[row]).speed(0.1));
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vangers::config;
+
+    fn fallback_parts() -> Parts {
+        let model = config::fallback::car_model();
+        Parts::new(&model, model.body.physics)
+    }
+
+    #[test]
+    fn session_round_trip() {
+        let original = fallback_parts();
+        let mut edited = original.clone();
+        assert_eq!(
+            Session::diff("car.m3d", &original, &edited).num_changes(),
+            0
+        );
+
+        edited.wheels[1].pos[2] += 1.5;
+        edited.wheels[3].steer = 1 - edited.wheels[3].steer;
+        edited.physics.volume *= 2.0;
+        let session = Session::diff("car.m3d", &original, &edited);
+        assert_eq!(session.wheels.keys().collect::<Vec<_>>(), [&1, &3]);
+        assert!(session.slots.is_empty());
+        assert_eq!(session.num_changes(), 3);
+
+        let text = ron::ser::to_string(&session).unwrap();
+        let loaded: Session = ron::de::from_str(&text).unwrap();
+        assert_eq!(loaded, session);
+
+        let mut parts = original.clone();
+        loaded.apply(&mut parts).unwrap();
+        assert_eq!(parts, edited);
+
+        let mut wrong = Session::default();
+        wrong.slots.insert(original.slots.len(), original.slots[0]);
+        assert!(wrong.apply(&mut parts).is_err());
+    }
+
+    #[test]
+    fn export_avoids_game_data() {
+        let dir = std::env::temp_dir().join("vangers-model-session");
+        std::fs::create_dir_all(dir.join("resource")).unwrap();
+        assert!(is_inside(&dir.join("resource").join("car.m3d"), &dir));
+        assert!(is_inside(&dir.join("resource/../car.m3d"), &dir));
+        assert!(!is_inside(&dir.join("../car.m3d"), &dir));
+
+        let session = Session {
+            source: "resource/car.m3d".to_string(),
+            ..Default::default()
+        };
+        let result = export(&session, &dir, &dir.join(&session.source));
+        assert!(result.unwrap_err().starts_with("Refusing"));
+        assert!(export(&Session::default(), &dir, Path::new("car.m3d")).is_err());
+    }
+}
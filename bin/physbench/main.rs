@@ -0,0 +1,345 @@
+//! Headless physics benchmark: drives a number of cars over a level
+//! as fast as possible, without rendering anything.
+
+// shared with the road, which uses more of it
+#[allow(dead_code, unused_imports)]
+#[path = "../road/physics/mod.rs"]
+mod physics;
+
+use vangers::{config, level, render, space};
+
+use futures::executor::LocalPool;
+use rayon::prelude::*;
+
+use std::{
+    hash::Hasher as _,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Distance between the spawned cars.
+const SPAWN_SPACING: i32 = 64;
+/// Spawn heights over the ground, cycled through by the cars,
+/// so that some of them fly for a while, and the broadphase has something to skip.
+const SPAWN_HEIGHTS: [f32; 4] = [5.0, 20.0, 60.0, 150.0];
+/// Motor inputs, cycled through by the cars.
+const MOTORS: [f32; 3] = [1.0, 0.5, -1.0];
+
+struct Car {
+    /// Index into the available car types.
+    info: usize,
+    transform: space::Transform,
+    dynamo: physics::Dynamo,
+    rudder: f32,
+    motor: f32,
+}
+
+impl Car {
+    /// Hash of the physical state, to compare the runs bit by bit.
+    fn state_hash(&self) -> u64 {
+        let mut hasher = fnv::FnvHasher::default();
+        let (t, d) = (&self.transform, &self.dynamo);
+        let values = [
+            t.disp.x,
+            t.disp.y,
+            t.disp.z,
+            t.rot.s,
+            t.rot.v.x,
+            t.rot.v.y,
+            t.rot.v.z,
+            d.linear_velocity.x,
+            d.linear_velocity.y,
+            d.linear_velocity.z,
+            d.angular_velocity.x,
+            d.angular_velocity.y,
+            d.angular_velocity.z,
+        ];
+        for value in values {
+            hasher.write_u32(value.to_bits());
+        }
+        hasher.finish()
+    }
+}
+
+fn create_context(settings: &config::Settings) -> render::GraphicsContext {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: settings.backend.to_wgpu(),
+        ..Default::default()
+    });
+    let mut task_pool = LocalPool::new();
+    let adapter = task_pool
+        .run_until(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        }))
+        .expect("Unable to find an adapter");
+    log::info!("Using adapter {:?}", adapter.get_info());
+    let limits = settings
+        .render
+        .get_device_limits(&adapter.limits(), settings.game.geometry.height);
+    let (device, queue) = task_pool
+        .run_until(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits,
+            },
+            None,
+        ))
+        .expect("Unable to create the device");
+    render::GraphicsContext {
+        device: Arc::new(device),
+        queue: Arc::new(queue),
+        downlevel_caps: adapter.get_downlevel_capabilities(),
+        color_format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        screen_size: wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+    }
+}
+
+fn load_level(settings: &config::Settings) -> level::Level {
+    let name = &settings.game.level;
+    if let Some(path) = level::Fixture::path_from_level_name(name) {
+        return level::Fixture::load(path)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .into_level();
+    }
+    let config = if name.is_empty() {
+        level::LevelConfig::new_test()
+    } else if let Some(preset) = level::TestPreset::from_level_name(name) {
+        level::LevelConfig::new_test_preset(preset)
+    } else if let Some(generator) = level::Generator::from_level_name(name) {
+        level::LevelConfig::new_generated(generator)
+    } else {
+        let worlds = config::worlds::load(settings.open_relative("wrlds.dat"));
+        let ini_name = worlds
+            .get(name)
+            .unwrap_or_else(|| panic!("Unknown level '{}'", name));
+        level::LevelConfig::load(&settings.data_path.join(ini_name))
+    };
+    let geometry = config::worlds::GeometryOverrides::load(config::worlds::GEOMETRY_PATH)
+        .get(name, &settings.game.geometry);
+    level::load(&config, &geometry)
+}
+
+fn main() {
+    use std::env;
+
+    let args: Vec<_> = env::args().collect();
+    let mut options = getopts::Options::new();
+    options
+        .optflag("h", "help", "print this help menu")
+        .optopt("", "cars", "number of cars to simulate (16)", "N")
+        .optopt("", "seconds", "simulated time (60)", "SECONDS")
+        .optflag(
+            "",
+            "no-broadphase",
+            "test every collision polygon against the level",
+        )
+        .optopt(
+            "",
+            "expect",
+            "fail unless the total state hash matches",
+            "HASH",
+        )
+        .optmulti("", "set", "override a setting", "KEY=VALUE");
+
+    let matches = options.parse(&args[1..]).unwrap();
+    if matches.opt_present("h") || !matches.free.is_empty() {
+        println!("Vangers physics benchmark");
+        let brief = format!("Usage: {} [options]", args[0]);
+        println!("{}", options.usage(&brief));
+        return;
+    }
+    let num_cars: usize = matches.opt_get_default("cars", 16).unwrap();
+    let seconds: f32 = matches.opt_get_default("seconds", 60.0).unwrap();
+
+    vangers::console::init();
+    let settings =
+        config::Settings::load("config/settings.ron", "physbench", &matches.opt_strs("set"));
+    vangers::jobs::init(settings.threads);
+    let gfx = create_context(&settings);
+
+    log::info!("Loading the cars");
+    let mut common = if settings.has_game_data() {
+        config::common::load(settings.open_relative("common.prm"))
+    } else {
+        config::fallback::common()
+    };
+    common.terrain.set_surfaces(&settings.game.surfaces);
+    common.car.gearbox = settings.game.gearbox;
+    // the configured car goes first, the rest by name
+    let car_infos = {
+        let global = render::global::Context::new(&gfx, None);
+        let object = render::object::Context::new(
+            &gfx,
+            settings.render.handedness.front_face(),
+            &settings.load_palette(None),
+            &global,
+        );
+        let registry = config::game::Registry::load(&settings);
+        let mut cars = config::car::load_registry(&settings, &registry, &gfx.device, &object)
+            .into_iter()
+            .collect::<Vec<_>>();
+        cars.sort_by(|a, b| (a.0 != settings.car.id, &a.0).cmp(&(b.0 != settings.car.id, &b.0)));
+        assert!(!cars.is_empty(), "No cars are available");
+        log::info!(
+            "Car types: {:?}",
+            cars.iter().map(|(id, _)| id).collect::<Vec<_>>()
+        );
+        cars.into_iter().map(|(_, info)| info).collect::<Vec<_>>()
+    };
+
+    log::info!("Loading the level");
+    let level = load_level(&settings);
+    let use_broadphase = !matches.opt_present("no-broadphase");
+    let origin = settings
+        .car
+        .pos
+        .unwrap_or((level.size.0 / 2, level.size.1 / 2));
+    let row = (num_cars as f32).sqrt().ceil() as i32;
+    let mut cars = (0..num_cars as i32)
+        .map(|i| {
+            let coords = (
+                (origin.0 + (i % row) * SPAWN_SPACING).rem_euclid(level.size.0),
+                (origin.1 + (i / row) * SPAWN_SPACING).rem_euclid(level.size.1),
+            );
+            let info = i as usize % car_infos.len();
+            let height = SPAWN_HEIGHTS[i as usize % SPAWN_HEIGHTS.len()];
+            // spread the headings evenly, by the golden angle
+            let heading = cgmath::Rad(i as f32 * 2.399_963);
+            Car {
+                info,
+                transform: cgmath::Decomposed {
+                    scale: car_infos[info].scale,
+                    disp: cgmath::vec3(
+                        coords.0 as f32,
+                        coords.1 as f32,
+                        level.get(coords).high() + height,
+                    ),
+                    rot: physics::math::rotation(cgmath::Vector3::unit_z(), heading),
+                },
+                dynamo: physics::Dynamo {
+                    linear_velocity: cgmath::vec3(0.0, (i % 3) as f32 * 10.0, 0.0),
+                    ..physics::Dynamo::default()
+                },
+                // steer and drive the cars differently, to cover more situations
+                rudder: ((i % 5) - 2) as f32 * 0.25,
+                motor: MOTORS[i as usize % MOTORS.len()],
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // same time scale as in the road
+    let fps = common.speed.standard_frame_rate as f32;
+    let delta = 1.0 / fps;
+    let input_factor = delta / config::common::MAIN_LOOP_TIME;
+    let physics_dt =
+        delta * fps * common.nature.time_delta0 * common.nature.num_calls_analysis as f32;
+    let max_quant = settings.game.physics.max_quant;
+    let substep_config = &settings.game.physics.substeps;
+    let num_frames = (seconds * fps) as usize;
+    let engine_params = physics::EngineParams::new(&common);
+
+    log::info!("Simulating {} cars for {} frames", num_cars, num_frames);
+    let mut num_steps = 0;
+    let mut counters = physics::BroadphaseCounters::default();
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..num_frames {
+        let start = Instant::now();
+        let (steps, frame_counters) = cars
+            .par_iter_mut()
+            .map(|car| {
+                let car_info = &car_infos[car.info];
+                let max_rudder = common.car.rudder_max;
+                car.dynamo.rudder.0 = car.rudder * max_rudder;
+                let input = physics::EngineInput {
+                    motor: car.motor,
+                    brake: false,
+                    turbo: false,
+                };
+                car.dynamo
+                    .engine
+                    .update(&input, input_factor, &engine_params);
+
+                let radius = car_info.model.body.bbox.radius * car.transform.scale;
+                let split = physics::split_frame(
+                    &car.dynamo,
+                    &car.transform,
+                    radius,
+                    &level,
+                    physics_dt,
+                    max_quant,
+                    substep_config,
+                );
+                let mut car_counters = physics::BroadphaseCounters::default();
+                for i in 0..split.steps() {
+                    let dt = if i < split.count {
+                        split.quant
+                    } else {
+                        split.last
+                    };
+                    let outcome = physics::step(
+                        &mut car.dynamo,
+                        &mut car.transform,
+                        dt,
+                        car_info,
+                        &level,
+                        use_broadphase,
+                        &common,
+                        0.0,
+                        None,
+                        0.0,
+                        physics::StepDebug::default(),
+                    );
+                    car_counters += outcome.broadphase;
+                }
+
+                let disp = &mut car.transform.disp;
+                disp.x = disp.x.rem_euclid(level.size.0 as f32);
+                disp.y = disp.y.rem_euclid(level.size.1 as f32);
+                (split.steps(), car_counters)
+            })
+            .reduce(Default::default, |mut a, b| {
+                a.0 += b.0;
+                a.1 += b.1;
+                a
+            });
+        elapsed += start.elapsed();
+        num_steps += steps;
+        counters += frame_counters;
+    }
+
+    let secs = elapsed.as_secs_f64();
+    println!(
+        "{} steps in {:.3}s: {:.0} steps/s, {:.1}x real time",
+        num_steps,
+        secs,
+        num_steps as f64 / secs,
+        seconds as f64 / secs
+    );
+    println!(
+        "terrain polygons: {} tested, {} skipped",
+        counters.tested, counters.skipped
+    );
+    let mut total = fnv::FnvHasher::default();
+    for (i, car) in cars.iter().enumerate() {
+        let hash = car.state_hash();
+        println!("car[{}]: {:016x}", i, hash);
+        total.write_u64(hash);
+    }
+    let total = total.finish();
+    println!("total: {:016x}", total);
+    if let Some(expected) = matches.opt_str("expect") {
+        let expected = u64::from_str_radix(expected.trim_start_matches("0x"), 16)
+            .unwrap_or_else(|e| panic!("Invalid hash '{}': {}", expected, e));
+        if total != expected {
+            log::error!("State hash {:016x} doesn't match {:016x}", total, expected);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,661 @@
+//! World browser, the start screen of `--browse`.
+//!
+//! Lists the worlds of `wrlds.dat` and the test levels, each with a minimap and
+//! a summary of its level. The minimaps are rendered from above with an orthographic
+//! camera, one world at a time on a background thread, and cached on disk together
+//! with the summaries, so the levels don't need to be loaded again on the next start.
+//! Clicking the minimap picks the spawn point, and "Play" starts the game there.
+
+use crate::{
+    boilerplate::{self, Application},
+    game::Game,
+    matrix, smoke, trace, world,
+};
+use serde_derive::{Deserialize, Serialize};
+use vangers::{
+    config, level,
+    locale::{tr, tr_fmt},
+    render::{Batcher, GraphicsContext, Render, ScreenTargets},
+    space,
+};
+
+use std::{fs::File, path::PathBuf};
+use winit::event;
+
+/// Directory of the cached minimaps and summaries.
+const CACHE_DIR: &str = "cache/worlds";
+/// Width of the minimaps, in pixels. The height follows the aspect of the level.
+const THUMBNAIL_WIDTH: u32 = 128;
+const MAX_THUMBNAIL_HEIGHT: u32 = 1024;
+/// Height of the minimaps in the list, in points.
+const LIST_THUMBNAIL_HEIGHT: f32 = 48.0;
+const SPAWN_COLOR: egui::Color32 = egui::Color32::from_rgb(0xFF, 0x40, 0xFF);
+
+/// What the browser shows about a level.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub size: (i32, i32),
+    pub height: u32,
+    pub terrains: usize,
+    /// Share of the texels with two layers.
+    pub dual_share: f32,
+}
+
+impl Summary {
+    pub fn new(level: &level::Level) -> Self {
+        let dual = level
+            .meta
+            .iter()
+            .filter(|&&meta| meta & level::DOUBLE_LEVEL != 0)
+            .count();
+        Summary {
+            size: level.size,
+            height: level.geometry.height,
+            terrains: level.terrains.len(),
+            dual_share: dual as f32 / level.meta.len().max(1) as f32,
+        }
+    }
+}
+
+/// Minimap with the summary, as cached.
+pub struct Preview {
+    pub summary: Summary,
+    pub image: egui::ColorImage,
+}
+
+pub struct Cache {
+    path: PathBuf,
+}
+
+impl Cache {
+    pub fn new(path: PathBuf) -> Self {
+        Cache { path }
+    }
+
+    fn file_path(&self, world: &str, extension: &str) -> PathBuf {
+        // test levels have a colon in the name, which some file systems don't allow
+        self.path
+            .join(world.replace(':', "_"))
+            .with_extension(extension)
+    }
+
+    pub fn load(&self, world: &str) -> Option<Preview> {
+        let summary = ron::de::from_reader(File::open(self.file_path(world, "ron")).ok()?)
+            .map_err(|e| log::warn!("Unable to parse the summary of {}: {}", world, e))
+            .ok()?;
+        let decoder = png::Decoder::new(File::open(self.file_path(world, "png")).ok()?);
+        let mut reader = decoder.read_info().ok()?;
+        let mut pixels = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).ok()?;
+        if info.color_type != png::ColorType::Rgba {
+            return None;
+        }
+        let size = [info.width as usize, info.height as usize];
+        Some(Preview {
+            summary,
+            image: egui::ColorImage::from_rgba_unmultiplied(size, &pixels[..info.buffer_size()]),
+        })
+    }
+
+    pub fn save(&self, world: &str, preview: &Preview) {
+        let string =
+            ron::ser::to_string_pretty(&preview.summary, ron::ser::PrettyConfig::default())
+                .unwrap();
+        let pixels = preview
+            .image
+            .pixels
+            .iter()
+            .flat_map(|color| color.to_array())
+            .collect::<Vec<_>>();
+        let png_path = self.file_path(world, "png");
+        let result = std::fs::create_dir_all(&self.path)
+            .and_then(|()| std::fs::write(self.file_path(world, "ron"), string))
+            .map_err(|e| e.to_string())
+            .and_then(|()| {
+                let [width, height] = preview.image.size;
+                matrix::save_png(&png_path, width as u32, height as u32, &pixels)
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            log::error!("Unable to cache the minimap of {}: {}", world, e);
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            log::warn!("Unable to clear {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Camera looking down at the whole level.
+fn overview_camera(summary: &Summary, handedness: config::settings::Handedness) -> space::Camera {
+    let height = summary.height as f32;
+    space::Camera {
+        loc: cgmath::vec3(
+            0.5 * summary.size.0 as f32,
+            0.5 * summary.size.1 as f32,
+            height + 10.0,
+        ),
+        rot: cgmath::One::one(),
+        handedness,
+        proj: space::Projection::ortho(
+            summary.size.0 as u16,
+            summary.size.1 as u16,
+            1.0..height + 20.0,
+        ),
+    }
+}
+
+/// Render the minimap of the level, without the objects and the shadows.
+/// Loads the whole level, so it's meant to run in the background.
+fn render_preview(
+    level_config: &level::LevelConfig,
+    geometry: &config::settings::Geometry,
+    settings: &config::Settings,
+    gfx: &GraphicsContext,
+) -> Preview {
+    let level = level::load(level_config, geometry);
+    let summary = Summary::new(&level);
+    let aspect = level.size.1 as f32 / level.size.0 as f32;
+    let extent = wgpu::Extent3d {
+        width: THUMBNAIL_WIDTH,
+        height: ((THUMBNAIL_WIDTH as f32 * aspect) as u32).clamp(1, MAX_THUMBNAIL_HEIGHT),
+        depth_or_array_layers: 1,
+    };
+    let gfx = GraphicsContext {
+        color_format: smoke::COLOR_FORMAT,
+        screen_size: extent,
+        ..gfx.clone()
+    };
+    let mut render_config = settings.render.clone();
+    render_config.terrain = config::settings::Terrain::RayTraced;
+    render_config.light.shadow.size = 0;
+    let palette = settings.load_palette(Some(&level_config.terrains));
+    let mut render = Render::new(&gfx, level_config, &palette, &render_config, geometry);
+    world::upload(&mut render, &level, &gfx);
+
+    let color_texture = gfx.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Minimap"),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: smoke::COLOR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let color = color_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth = boilerplate::create_depth_target(&gfx.device, extent);
+    let mut encoder = gfx
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Minimap"),
+        });
+    render.draw_world(
+        &mut encoder,
+        &mut Batcher::new(),
+        &level,
+        &overview_camera(&summary, settings.render.handedness),
+        ScreenTargets {
+            extent,
+            color: &color,
+            depth: &depth,
+        },
+        None,
+        &gfx.device,
+    );
+    gfx.queue.submit(Some(encoder.finish()));
+
+    let pixels = smoke::read_back(&gfx, &color_texture);
+    Preview {
+        summary,
+        image: egui::ColorImage::from_rgba_unmultiplied(
+            [extent.width as usize, extent.height as usize],
+            &pixels,
+        ),
+    }
+}
+
+enum Thumbnail {
+    Missing,
+    /// Minimap waiting to be uploaded into the UI.
+    Ready(egui::ColorImage),
+    Uploaded(egui::TextureHandle),
+    Failed,
+}
+
+struct Entry {
+    /// Name for `game.level`.
+    name: String,
+    summary: Option<Summary>,
+    thumbnail: Thumbnail,
+}
+
+pub struct Browser {
+    settings: config::Settings,
+    gfx: GraphicsContext,
+    trace: Option<trace::Command>,
+    catalog: world::Catalog,
+    cache: Cache,
+    entries: Vec<Entry>,
+    selected: usize,
+    /// Spawn point in the level, if picked.
+    spawn: Option<(i32, i32)>,
+    is_chosen: bool,
+    /// Minimap being rendered, with the index of its entry.
+    rendering: Option<(usize, world::Background<Preview>)>,
+}
+
+impl Browser {
+    pub fn new(
+        settings: &config::Settings,
+        gfx: &GraphicsContext,
+        trace: Option<trace::Command>,
+    ) -> Self {
+        let catalog = world::Catalog::new(settings);
+        let mut names = catalog.worlds.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names.extend(level::TestPreset::ALL.iter().map(|p| p.level_name()));
+        let entries = names
+            .into_iter()
+            .map(|name| Entry {
+                name,
+                summary: None,
+                thumbnail: Thumbnail::Missing,
+            })
+            .collect::<Vec<_>>();
+        let selected = entries
+            .iter()
+            .position(|e| e.name == settings.game.level)
+            .unwrap_or(0);
+        Browser {
+            settings: settings.clone(),
+            gfx: gfx.clone(),
+            trace,
+            catalog,
+            cache: Cache::new(PathBuf::from(CACHE_DIR)),
+            entries,
+            selected,
+            spawn: None,
+            is_chosen: false,
+            rendering: None,
+        }
+    }
+
+    /// Find the preview of the next world that doesn't have it, in the cache,
+    /// or by rendering it in the background.
+    fn make_next_preview(&mut self) {
+        if let Some((index, ref job)) = self.rendering {
+            let entry = &mut self.entries[index];
+            match job.poll() {
+                world::Poll::Pending => return,
+                world::Poll::Ready(preview) => {
+                    self.cache.save(&entry.name, &preview);
+                    entry.summary = Some(preview.summary);
+                    entry.thumbnail = Thumbnail::Ready(preview.image);
+                }
+                world::Poll::Failed => {
+                    log::error!("Unable to render the minimap of {}", entry.name);
+                    entry.thumbnail = Thumbnail::Failed;
+                }
+            }
+            self.rendering = None;
+        }
+
+        let index = match self
+            .entries
+            .iter()
+            .position(|e| matches!(e.thumbnail, Thumbnail::Missing))
+        {
+            Some(index) => index,
+            None => return,
+        };
+        let entry = &mut self.entries[index];
+        if let Some(preview) = self.cache.load(&entry.name) {
+            entry.summary = Some(preview.summary);
+            entry.thumbnail = Thumbnail::Ready(preview.image);
+            return;
+        }
+        let level_config = match level::TestPreset::from_level_name(&entry.name) {
+            Some(preset) => Some(level::LevelConfig::new_test_preset(preset)),
+            None => self.catalog.level_config(&entry.name),
+        };
+        let level_config = match level_config {
+            Some(config) => config,
+            None => {
+                entry.thumbnail = Thumbnail::Failed;
+                return;
+            }
+        };
+        log::info!("Rendering the minimap of {}", entry.name);
+        let geometry = self.catalog.geometry(&entry.name);
+        let settings = self.settings.clone();
+        let gfx = self.gfx.clone();
+        let job = world::Background::spawn(move || {
+            render_preview(&level_config, &geometry, &settings, &gfx)
+        });
+        self.rendering = Some((index, job));
+    }
+
+    fn remaining(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|e| matches!(e.thumbnail, Thumbnail::Missing))
+            .count()
+    }
+
+    /// Start the game in the chosen world, if it's time.
+    fn start_game(&mut self) -> Option<Game> {
+        if !self.is_chosen {
+            return None;
+        }
+        let mut settings = self.settings.clone();
+        settings.game.level = self.entries[self.selected].name.clone();
+        if self.spawn.is_some() {
+            settings.car.pos = self.spawn;
+        }
+        log::info!(
+            "Starting in {} at {:?}",
+            settings.game.level,
+            settings.car.pos
+        );
+        Some(Game::new(&settings, &self.gfx, self.trace.take()))
+    }
+
+    fn draw_list(&mut self, ui: &mut egui::Ui) {
+        let context = ui.ctx().clone();
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            if let Thumbnail::Ready(_) = entry.thumbnail {
+                if let Thumbnail::Ready(image) =
+                    std::mem::replace(&mut entry.thumbnail, Thumbnail::Failed)
+                {
+                    let texture =
+                        context.load_texture(&entry.name, image, egui::TextureOptions::LINEAR);
+                    entry.thumbnail = Thumbnail::Uploaded(texture);
+                }
+            }
+            ui.horizontal(|ui| {
+                if let Thumbnail::Uploaded(ref texture) = entry.thumbnail {
+                    let size = texture.size_vec2();
+                    let scale = LIST_THUMBNAIL_HEIGHT / size.y;
+                    ui.image(texture.id(), size * scale);
+                }
+                ui.vertical(|ui| {
+                    if ui
+                        .selectable_label(index == self.selected, &entry.name)
+                        .clicked()
+                        && index != self.selected
+                    {
+                        self.selected = index;
+                        self.spawn = None;
+                    }
+                    if let Some(ref summary) = entry.summary {
+                        ui.label(tr_fmt(
+                            "{} x {}, {} terrains, {}% two-layered",
+                            &[
+                                &summary.size.0,
+                                &summary.size.1,
+                                &summary.terrains,
+                                &format!("{:.0}", summary.dual_share * 100.0),
+                            ],
+                        ));
+                    }
+                });
+            });
+        }
+    }
+
+    fn draw_selected(&mut self, ui: &mut egui::Ui) {
+        let entry = match self.entries.get(self.selected) {
+            Some(entry) => entry,
+            None => {
+                ui.label(tr("No worlds found"));
+                return;
+            }
+        };
+        ui.heading(&entry.name);
+        ui.horizontal(|ui| {
+            if ui.button(tr("Play")).clicked() {
+                self.is_chosen = true;
+            }
+            match self.spawn {
+                Some((x, y)) => ui.label(tr_fmt("Spawn: {}, {}", &[&x, &y])),
+                None => ui.label(tr("Click the map to pick the spawn point")),
+            };
+        });
+        let (texture, summary) = match (&entry.thumbnail, &entry.summary) {
+            (Thumbnail::Uploaded(texture), Some(summary)) => (texture, summary),
+            _ => {
+                ui.spinner();
+                return;
+            }
+        };
+        let size = texture.size_vec2();
+        let scale = (ui.available_height() / size.y).min(ui.available_width() / size.x);
+        let response =
+            ui.add(egui::Image::new(texture.id(), size * scale).sense(egui::Sense::click()));
+        let rect = response.rect;
+        if let Some(pos) = response
+            .interact_pointer_pos()
+            .filter(|_| response.clicked())
+        {
+            let uv = (pos - rect.min) / rect.size();
+            let ndc = cgmath::vec2(2.0 * uv.x - 1.0, 1.0 - 2.0 * uv.y);
+            let cam = overview_camera(summary, self.settings.render.handedness);
+            let point = cam.unproject(ndc, 0.0);
+            self.spawn = Some((point.x as i32, point.y as i32));
+        }
+        // the marker is placed by the same camera, so it works with either handedness
+        if let Some((x, y)) = self.spawn {
+            let cam = overview_camera(summary, self.settings.render.handedness);
+            let clip = cam.get_view_proj() * cgmath::vec4(x as f32, y as f32, 0.0, 1.0);
+            let uv = egui::vec2(0.5 + 0.5 * clip.x / clip.w, 0.5 - 0.5 * clip.y / clip.w);
+            let center = rect.min + uv * rect.size();
+            ui.painter()
+                .circle_stroke(center, 5.0, egui::Stroke::new(2.0, SPAWN_COLOR));
+        }
+    }
+}
+
+/// Either the browser, or the game started from it.
+pub enum Screen {
+    Browser(Box<Browser>),
+    Game(Box<Game>),
+}
+
+impl Screen {
+    pub fn new(
+        settings: &config::Settings,
+        gfx: &GraphicsContext,
+        trace: Option<trace::Command>,
+    ) -> Self {
+        Screen::Browser(Box::new(Browser::new(settings, gfx, trace)))
+    }
+}
+
+impl Application for Screen {
+    fn on_key(&mut self, input: event::KeyboardInput) -> bool {
+        match *self {
+            Screen::Browser(_) => input.virtual_keycode != Some(event::VirtualKeyCode::Escape),
+            Screen::Game(ref mut game) => game.on_key(input),
+        }
+    }
+
+    fn on_mouse_wheel(&mut self, delta: event::MouseScrollDelta) {
+        if let Screen::Game(ref mut game) = *self {
+            game.on_mouse_wheel(delta);
+        }
+    }
+
+    fn on_cursor_move(&mut self, position: (f64, f64)) {
+        if let Screen::Game(ref mut game) = *self {
+            game.on_cursor_move(position);
+        }
+    }
+
+    fn on_mouse_button(&mut self, state: event::ElementState, button: event::MouseButton) {
+        if let Screen::Game(ref mut game) = *self {
+            game.on_mouse_button(state, button);
+        }
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
+        match *self {
+            Screen::Browser(ref mut browser) => browser.gfx.screen_size = extent,
+            Screen::Game(ref mut game) => game.resize(device, extent),
+        }
+    }
+
+    fn reload(&mut self, device: &wgpu::Device) {
+        if let Screen::Game(ref mut game) = *self {
+            game.reload(device);
+        }
+    }
+
+    fn recover(&mut self, gfx: &GraphicsContext) {
+        match *self {
+            Screen::Browser(ref mut browser) => {
+                browser.gfx = gfx.clone();
+                // the minimap in progress is on the lost device, its entry is still missing
+                browser.rendering = None;
+                // the UI textures are gone, the minimaps come back from the cache
+                for entry in browser.entries.iter_mut() {
+                    if let Thumbnail::Uploaded(_) = entry.thumbnail {
+                        entry.thumbnail = Thumbnail::Missing;
+                    }
+                }
+            }
+            Screen::Game(ref mut game) => game.recover(gfx),
+        }
+    }
+
+    fn on_command(&mut self, command: &str) -> Result<(), String> {
+        match *self {
+            Screen::Browser(_) => Err(format!("Unknown command '{}'", command)),
+            Screen::Game(ref mut game) => game.on_command(command),
+        }
+    }
+
+    fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, delta: f32) {
+        let game = match *self {
+            Screen::Browser(ref mut browser) => {
+                browser.make_next_preview();
+                browser.start_game()
+            }
+            Screen::Game(ref mut game) => {
+                game.update(device, queue, delta);
+                None
+            }
+        };
+        if let Some(game) = game {
+            *self = Screen::Game(Box::new(game));
+        }
+    }
+
+    fn draw_ui(&mut self, context: &egui::Context) {
+        let browser = match *self {
+            Screen::Browser(ref mut browser) => browser,
+            Screen::Game(ref mut game) => return game.draw_ui(context),
+        };
+        egui::SidePanel::left("Worlds").show(context, |ui| {
+            ui.heading(tr("Worlds"));
+            let remaining = browser.remaining();
+            if remaining != 0 {
+                ui.label(tr_fmt("Rendering the minimaps: {} left", &[&remaining]));
+            } else if ui.button(tr("Rebuild the minimaps")).clicked() {
+                browser.cache.clear();
+                for entry in browser.entries.iter_mut() {
+                    entry.thumbnail = Thumbnail::Missing;
+                }
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| browser.draw_list(ui));
+        });
+        egui::CentralPanel::default().show(context, |ui| browser.draw_selected(ui));
+    }
+
+    fn draw(&mut self, device: &wgpu::Device, targets: ScreenTargets) -> wgpu::CommandBuffer {
+        if let Screen::Game(ref mut game) = *self {
+            return game.draw(device, targets);
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Browser"),
+        });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Clear"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: targets.color,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        encoder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_round_trip() {
+        let path = std::env::temp_dir().join("vangers-browser-test");
+        let _ = std::fs::remove_dir_all(&path);
+        let cache = Cache::new(path.clone());
+        let name = level::TestPreset::Cave.level_name();
+        assert!(cache.load(&name).is_none());
+
+        let level = level::load(
+            &level::LevelConfig::new_test_preset(level::TestPreset::Cave),
+            &config::settings::Geometry::default(),
+        );
+        let summary = Summary::new(&level);
+        assert_eq!(summary.size, level.size);
+        assert!(summary.dual_share > 0.0 && summary.dual_share < 1.0);
+
+        let preview = Preview {
+            summary,
+            image: egui::ColorImage::new([2, 3], egui::Color32::from_rgb(1, 2, 3)),
+        };
+        cache.save(&name, &preview);
+        let loaded = cache.load(&name).unwrap();
+        assert_eq!(loaded.summary, preview.summary);
+        assert_eq!(loaded.image.size, preview.image.size);
+        assert_eq!(loaded.image.pixels, preview.image.pixels);
+
+        cache.clear();
+        assert!(cache.load(&name).is_none());
+    }
+
+    #[test]
+    fn overview_covers_level() {
+        let summary = Summary {
+            size: (256, 1024),
+            height: 256,
+            terrains: 8,
+            dual_share: 0.0,
+        };
+        for handedness in [
+            config::settings::Handedness::Left,
+            config::settings::Handedness::Right,
+        ] {
+            let cam = overview_camera(&summary, handedness);
+            let corners = [cgmath::vec2(-1.0, -1.0), cgmath::vec2(1.0, 1.0)]
+                .map(|ndc| cam.unproject(ndc, 0.0));
+            let (min_x, max_x) = (
+                corners[0].x.min(corners[1].x),
+                corners[0].x.max(corners[1].x),
+            );
+            let (min_y, max_y) = (
+                corners[0].y.min(corners[1].y),
+                corners[0].y.max(corners[1].y),
+            );
+            assert!(min_x.abs() < 1e-3 && (max_x - 256.0).abs() < 1e-2);
+            assert!(min_y.abs() < 1e-3 && (max_y - 1024.0).abs() < 1e-2);
+        }
+    }
+}
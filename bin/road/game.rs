@@ -1,21 +1,52 @@
-use crate::{boilerplate::Application, physics};
+use crate::{boilerplate::Application, physics, profile, props, trace, world};
 use m3d::Mesh;
 use vangers::{
-    config, level, model,
+    config, jobs, level,
+    locale::{tr, tr_fmt},
+    model,
     render::{
-        debug::LineBuffer, object::BodyColor, Batcher, GraphicsContext, Render, ScreenTargets,
+        debug::LineBuffer,
+        object::BodyColor,
+        snapshot::Snapshot,
+        terrain::{Cutaway, SliceTransition},
+        Batcher, DirtyRect, DistanceCull, GraphicsContext, Rect, Render, ScreenTargets, WorldWrap,
     },
-    space,
+    space::{self, coords::WorldPos},
 };
 
 use cgmath::prelude::*;
-
-use std::collections::HashMap;
+use rand::{Rng as _, SeedableRng as _};
+
+use std::{collections::HashMap, ops::Range, path::PathBuf};
+
+mod antenna;
+pub mod events;
+mod flip;
+mod shaping;
+mod suspension;
+mod trailer;
+
+/// Particles of a splash when touching the water at rest.
+const SPLASH_PARTICLES_BASE: usize = 16;
+/// Additional particles per unit of the vertical speed.
+const SPLASH_PARTICLES_PER_SPEED: f32 = 4.0;
+const SPLASH_SPEED: f32 = 4.0;
+/// Dust particles kicked off by the wheels, per unit of the distance.
+const WHEEL_DUST_PER_DISTANCE: f32 = 0.3;
+const WHEEL_DUST_SPEED: f32 = 3.0;
+/// Distance to an escave at which it's considered reached.
+const CHECKPOINT_RADIUS: f32 = 64.0;
+/// Texels around the visible terrain where the AI keeps making decisions.
+const AI_ACTIVITY_MARGIN: i32 = 256;
+/// Number of agents worth a separate job when batching.
+const AGENTS_PER_JOB: usize = 4;
+/// Slowest and fastest time scale of the simulation.
+const TIME_SCALE_MIN: f32 = 0.1;
+const TIME_SCALE_MAX: f32 = 4.0;
 
 #[derive(Debug, PartialEq)]
 struct Ai {
     last_transform: space::Transform,
-    roll_time: f32,
 }
 
 #[derive(Debug, PartialEq)]
@@ -57,6 +88,30 @@ pub struct Agent {
     control: Control,
     jump: Option<f32>,
     physics: Physics,
+    immersion: physics::Immersion,
+    /// Whether the car was in the water at the end of the last frame.
+    in_water: bool,
+    /// Fastest wall hit since the last frame.
+    hit_speed: f32,
+    /// Fastest landing since the last frame.
+    landing_speed: f32,
+    /// Collision polygons tested and skipped since the last frame.
+    broadphase: physics::BroadphaseCounters,
+    /// Physics steps taken in the last frame.
+    substeps: usize,
+    /// Terrain type under the wheels after the last step, if they touch the ground.
+    surface: Option<level::TerrainType>,
+    /// Fraction of the next wheel dust particle.
+    dust: f32,
+    /// Index of the escave the car is currently at.
+    checkpoint: Option<usize>,
+    flip: flip::FlipAssist,
+    shaper: shaping::Shaper,
+    trailer: Option<trailer::Trailer>,
+    /// Chains hanging from the empty slots of the model.
+    antennas: Vec<antenna::Chain>,
+    /// State of the wheels after the last frame, if the suspension is debugged.
+    wheels: Option<Vec<physics::WheelState>>,
 }
 
 impl Agent {
@@ -73,14 +128,13 @@ impl Agent {
         let transform = cgmath::Decomposed {
             scale: car.scale,
             disp: cgmath::vec3(coords.0 as f32, coords.1 as f32, height),
-            rot: cgmath::Quaternion::from_angle_z(orientation),
+            rot: physics::math::rotation(cgmath::Vector3::unit_z(), orientation),
         };
 
         Agent {
             _name: name,
             spirit: Spirit::Other(Ai {
                 last_transform: transform,
-                roll_time: 0.0,
             }),
             car: car.clone(),
             car_name,
@@ -91,6 +145,20 @@ impl Agent {
                 transform,
                 dynamo: physics::Dynamo::default(),
             },
+            immersion: physics::Immersion::default(),
+            in_water: false,
+            hit_speed: 0.0,
+            landing_speed: 0.0,
+            broadphase: physics::BroadphaseCounters::default(),
+            substeps: 0,
+            surface: None,
+            dust: 0.0,
+            checkpoint: None,
+            flip: flip::FlipAssist::default(),
+            shaper: shaping::Shaper::default(),
+            trailer: None,
+            antennas: Vec::new(),
+            wheels: None,
         }
     }
 
@@ -103,24 +171,174 @@ impl Agent {
                 dynamo: _,
             } => {
                 transform.scale = car.scale;
+                if let Some(ref mut trailer) = self.trailer {
+                    trailer.attach(car, transform);
+                }
             }
         }
     }
 
-    fn cpu_apply_control(&mut self, dt: f32, common: &config::common::Common) {
+    /// Roots and up directions of the antennas, one per empty slot of the model.
+    fn antenna_roots(&self) -> Vec<(cgmath::Vector3<f32>, cgmath::Vector3<f32>)> {
+        let Physics::Cpu { ref transform, .. } = self.physics;
+        let model = &self.car.model;
+        model
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|&(i, slot)| model.slot_mask & (1 << i) != 0 && slot.mesh.is_none())
+            .map(|(_, slot)| {
+                let pos = cgmath::Point3::from(slot.pos).cast::<f32>().unwrap();
+                (
+                    transform.transform_point(pos).to_vec(),
+                    transform.rot * cgmath::Vector3::unit_z(),
+                )
+            })
+            .collect()
+    }
+
+    /// Swing the antennas after the car moved, starting them over if the slots changed.
+    fn update_antennas(&mut self, dt: f32, config: &config::settings::Antenna) {
+        if config.segments == 0 {
+            self.antennas.clear();
+            return;
+        }
+        let roots = self.antenna_roots();
+        if roots.len() != self.antennas.len() {
+            self.antennas = roots
+                .iter()
+                .map(|&(root, up)| antenna::Chain::new(root, up, config))
+                .collect();
+        }
+        for (chain, (root, up)) in self.antennas.iter_mut().zip(roots) {
+            chain.update(root, up, dt, config);
+        }
+    }
+
+    /// Pick the physics steps of the frame by the motion of the car.
+    fn split_frame(
+        &self,
+        dt: f32,
+        level: &level::Level,
+        max_quant: f32,
+        config: &config::settings::Substeps,
+    ) -> physics::Split {
+        let Physics::Cpu {
+            ref transform,
+            ref dynamo,
+        } = self.physics;
+        if dt < 0.0 {
+            // stepping back goes through the same steps as forward
+            let split = self.split_frame(-dt, level, max_quant, config);
+            return physics::Split {
+                quant: -split.quant,
+                last: -split.last,
+                ..split
+            };
+        }
+        let radius = self.car.model.body.bbox.radius * transform.scale;
+        physics::split_frame(dynamo, transform, radius, level, dt, max_quant, config)
+    }
+
+    fn place_trailer(&mut self) {
+        let Physics::Cpu { ref transform, .. } = self.physics;
+        if let Some(ref mut trailer) = self.trailer {
+            trailer.place(transform);
+        }
+    }
+
+    /// Move the car to the given coordinates, dropping all the accumulated motion.
+    /// The height is taken from the level if not provided.
+    /// Only the heading of the car is preserved.
+    fn teleport(&mut self, coords: (i32, i32), height: Option<f32>, level: &level::Level) {
+        let Physics::Cpu {
+            ref mut transform,
+            ref mut dynamo,
+        } = self.physics;
+        transform.disp = cgmath::vec3(
+            coords.0 as f32,
+            coords.1 as f32,
+            height.unwrap_or_else(|| level.get(coords).high() + 5.0),
+        );
+        transform.rot = flip::upright(transform.rot);
+        *dynamo = physics::Dynamo::default();
+        self.control = Control::default();
+        self.jump = None;
+        self.flip = flip::FlipAssist::default();
+        self.shaper = shaping::Shaper::default();
+        if let Spirit::Other(ref mut ai) = self.spirit {
+            ai.last_transform = *transform;
+        }
+        self.place_trailer();
+    }
+
+    /// Height of the car center when standing on the ground under it.
+    fn ground_height(&self, level: &level::Level) -> f32 {
+        let pos = self.position();
+        level.get_at(pos).high() + 5.0
+    }
+
+    /// Start turning the car back onto its wheels.
+    fn start_flip(&mut self, level: &level::Level) {
+        let height = self.ground_height(level);
+        let Physics::Cpu { ref transform, .. } = self.physics;
+        self.flip.start(transform, height);
+    }
+
+    /// Advance the flip assist. Returns true if it controls the car instead of the physics.
+    fn update_flip(
+        &mut self,
+        dt: f32,
+        level: &level::Level,
+        config: &config::settings::FlipAssist,
+    ) -> bool {
+        let height = self.ground_height(level);
+        let Physics::Cpu {
+            ref mut transform,
+            ref mut dynamo,
+        } = self.physics;
+        let speed = dynamo.linear_velocity.magnitude();
+        if !self.flip.update(dt, transform, speed, height, config) {
+            return false;
+        }
+        dynamo.linear_velocity = cgmath::Vector3::zero();
+        dynamo.angular_velocity = cgmath::Vector3::zero();
+        self.place_trailer();
+        true
+    }
+
+    /// Apply the shaped controls, with `delta` being the frame time in seconds,
+    /// and `input_factor` being it in terms of the original game frames.
+    fn cpu_apply_control(
+        &mut self,
+        delta: f32,
+        input_factor: f32,
+        common: &config::common::Common,
+        shaping: &config::settings::InputShaping,
+    ) {
         let dynamo = match self.physics {
             Physics::Cpu { ref mut dynamo, .. } => dynamo,
         };
-        if self.control.rudder != 0.0 {
-            let angle = dynamo.rudder.0 + common.car.rudder_step * 2.0 * dt * self.control.rudder;
+        let rudder = self
+            .shaper
+            .rudder
+            .update(self.control.rudder, delta, &shaping.rudder);
+        let motor = self
+            .shaper
+            .motor
+            .update(self.control.motor, delta, &shaping.motor);
+        if rudder != 0.0 {
+            let angle = dynamo.rudder.0 + common.car.rudder_step * 2.0 * input_factor * rudder;
             dynamo.rudder.0 = angle.min(common.car.rudder_max).max(-common.car.rudder_max);
         }
-        if self.control.motor != 0.0 {
-            dynamo.change_traction(self.control.motor * dt * common.car.traction_incr);
-        }
-        if self.control.brake && dynamo.traction != 0.0 {
-            dynamo.traction *= (-dt).exp2();
-        }
+        let input = physics::EngineInput {
+            motor,
+            brake: self.control.brake,
+            turbo: self.control.turbo,
+        };
+        dynamo
+            .engine
+            .update(&input, input_factor, &physics::EngineParams::new(common));
     }
 
     fn cpu_step(
@@ -136,8 +354,8 @@ impl Agent {
                 ref mut dynamo,
             } => (dynamo, transform),
         };
-        let (jump, roll, focus_point, line_buffer) = match sim_step {
-            SimulationStep::Intermediate => (None, 0.0, None, None),
+        let (jump, roll, focus_point, debug) = match sim_step {
+            SimulationStep::Intermediate => (None, 0.0, None, physics::StepDebug::default()),
             SimulationStep::Final {
                 focus_point,
                 line_buffer,
@@ -145,21 +363,20 @@ impl Agent {
                 self.jump.take(),
                 self.control.roll,
                 Some(*focus_point),
-                line_buffer,
+                physics::StepDebug {
+                    lines: line_buffer,
+                    wheels: self.wheels.as_mut(),
+                },
             ),
         };
-        physics::step(
+        let outcome = physics::step(
             dynamo,
             transform,
             dt,
             &self.car,
             level,
+            true,
             common,
-            if self.control.turbo {
-                common.global.k_traction_turbo
-            } else {
-                1.0
-            },
             if self.control.brake {
                 common.global.f_brake_max
             } else {
@@ -167,22 +384,35 @@ impl Agent {
             },
             jump,
             roll,
-            line_buffer,
+            debug,
         );
+        self.immersion = outcome.immersion;
+        self.hit_speed = self.hit_speed.max(outcome.hit_speed);
+        self.landing_speed = self.landing_speed.max(outcome.landing_speed);
+        self.broadphase += outcome.broadphase;
+        self.surface = outcome.surface;
+        if let Some(ref mut trailer) = self.trailer {
+            let outcome = trailer.step(dt, level, common, &self.car, transform, dynamo);
+            self.broadphase += outcome.broadphase;
+        }
 
         if let Some(focus) = focus_point {
             let wrap = cgmath::vec2(level.size.0 as f32, (level.size.1 >> 1) as f32);
             let offset = cgmath::Point3::from_vec(transform.disp) - focus;
+            let old_disp = transform.disp;
             transform.disp = focus.to_vec()
                 + cgmath::vec3(
                     (offset.x + 0.5 * wrap.x).rem_euclid(wrap.x) - 0.5 * wrap.x,
                     (offset.y + 0.5 * wrap.y).rem_euclid(wrap.y) - 0.5 * wrap.y,
                     offset.z,
                 );
+            if let Some(ref mut trailer) = self.trailer {
+                trailer.transform.disp += transform.disp - old_disp;
+            }
         }
     }
 
-    fn ai_behavior(&mut self, delta: f32) {
+    fn ai_behavior(&mut self, level: &level::Level) {
         let ai = match self.spirit {
             Spirit::Player => return,
             Spirit::Other(ref mut ai) => ai,
@@ -190,21 +420,64 @@ impl Agent {
         self.control.motor = 1.0; //full on
 
         let transform = match self.physics {
-            Physics::Cpu { ref transform, .. } => transform,
+            Physics::Cpu { ref transform, .. } => *transform,
         };
+        // stuck on the back or the side, no need to wait
+        let stuck = ai.last_transform.disp == transform.disp;
+        ai.last_transform = transform;
+        if stuck && !self.flip.is_active() && flip::is_overturned(transform.rot) {
+            self.start_flip(level);
+        }
+    }
 
-        if ai.roll_time > 0.0 {
-            ai.roll_time -= delta;
-            if ai.roll_time <= 0.0 {
-                self.control.roll = 0.0;
-            }
-        } else if ai.last_transform.disp == transform.disp {
-            ai.roll_time = 0.5;
-            let x_axis = transform.rot * cgmath::Vector3::unit_x();
-            self.control.roll = x_axis.z.signum();
+    fn trace_state(&self) -> trace::State {
+        let Physics::Cpu {
+            ref transform,
+            ref dynamo,
+        } = self.physics;
+        let rot = transform.rot;
+        trace::State {
+            position: transform.disp.into(),
+            orientation: [rot.s, rot.v.x, rot.v.y, rot.v.z],
+            linear_velocity: dynamo.linear_velocity.into(),
+            angular_velocity: dynamo.angular_velocity.into(),
         }
+    }
 
-        ai.last_transform = *transform;
+    fn set_trace_state(&mut self, state: &trace::State) {
+        let Physics::Cpu {
+            ref mut transform,
+            ref mut dynamo,
+        } = self.physics;
+        let [s, x, y, z] = state.orientation;
+        transform.disp = state.position.into();
+        transform.rot = cgmath::Quaternion::new(s, x, y, z);
+        dynamo.linear_velocity = state.linear_velocity.into();
+        dynamo.angular_velocity = state.angular_velocity.into();
+        self.place_trailer();
+    }
+
+    /// Detect the car getting into or out of the water since the last check.
+    fn water_transition(&mut self, index: usize) -> Option<events::Event> {
+        let in_water = self.immersion.in_water();
+        if in_water == self.in_water {
+            return None;
+        }
+        self.in_water = in_water;
+        let position = self.position();
+        Some(if in_water {
+            let Physics::Cpu { ref dynamo, .. } = self.physics;
+            events::Event::Splash {
+                agent: index,
+                position,
+                vertical_speed: dynamo.linear_velocity.z,
+            }
+        } else {
+            events::Event::Emerge {
+                agent: index,
+                position,
+            }
+        })
     }
 
     fn position(&self) -> cgmath::Vector3<f32> {
@@ -212,18 +485,74 @@ impl Agent {
             Physics::Cpu { ref transform, .. } => transform.disp,
         }
     }
+
+    /// Kick the dust off the surface under the wheels, the more the faster it goes.
+    fn kick_dust(
+        &mut self,
+        delta: f32,
+        surfaces: &[config::common::Surface],
+        particle: &mut vangers::render::particle::Context,
+    ) {
+        let Physics::Cpu {
+            ref transform,
+            ref dynamo,
+        } = self.physics;
+        let surface = match self.surface {
+            Some(terrain_type) => &surfaces[terrain_type as usize],
+            None => {
+                self.dust = 0.0;
+                return;
+            }
+        };
+        self.dust += dynamo.linear_velocity.magnitude() * delta * WHEEL_DUST_PER_DISTANCE;
+        let count = self.dust as usize;
+        if count != 0 {
+            self.dust -= count as f32;
+            let bbox = &self.car.model.body.bbox;
+            let rear = cgmath::Point3::new(0.0, bbox.min[1], bbox.min[2]);
+            let origin = transform.transform_point(rear).to_vec();
+            particle.spawn_colored(origin, count, WHEEL_DUST_SPEED, surface.dust);
+        }
+    }
 }
 
 #[derive(Default)]
 struct Stats {
     frame_deltas: Vec<f32>,
+    /// Collision polygons of all the agents during the last frame.
+    broadphase: physics::BroadphaseCounters,
+    substeps: SubstepStats,
+}
+
+/// Physics steps of the agents during the last frame.
+#[derive(Clone, Copy, Default)]
+struct SubstepStats {
+    player: usize,
+    average: f32,
+    max: usize,
+}
+
+/// Fill the slots of the player car with the models of the settings.
+fn load_slots(
+    model: &mut model::VisualModel,
+    settings: &config::Settings,
+    game: &config::game::Registry,
+    device: &wgpu::Device,
+) {
+    for (ms, sid) in model.slots.iter_mut().zip(settings.car.slots.iter()) {
+        let info = &game.model_infos[sid];
+        let raw = Mesh::load(&mut settings.open_relative(&info.path))
+            .expect("Unable to parse the slot mesh");
+        ms.mesh = Some(model::load_c3d(raw, None, device));
+        ms.scale = info.scale;
+    }
 }
 
 struct DataBase {
     _bunches: Vec<config::bunches::Bunch>,
     cars: HashMap<String, config::car::CarInfo>,
     common: config::common::Common,
-    _escaves: Vec<config::escaves::Escave>,
+    escaves: Vec<config::escaves::Escave>,
     game: config::game::Registry,
 }
 
@@ -246,6 +575,7 @@ impl CameraStyle {
                     angle_x: angle,
                     offset: cgmath::vec3(0.0, config.offset, config.height),
                     speed: config.speed,
+                    collision: config.collision,
                 },
                 ground_anchor: angle > cgmath::Deg(15.0),
             }
@@ -292,6 +622,9 @@ struct Input {
     turbo: bool,
     jump: Option<f32>,
     roll: Option<Roll>,
+    /// The simulation is stopped, but the camera keeps following the player.
+    is_frozen: bool,
+    /// Direction of the single frame step requested while paused or frozen.
     tick: Option<f32>,
 }
 
@@ -299,28 +632,89 @@ pub struct Game {
     db: DataBase,
     render: Render,
     batcher: Batcher,
+    /// Where to save the scene of the next frame.
+    snapshot_path: Option<PathBuf>,
     line_buffer: LineBuffer,
     level: level::Level,
+    /// Rows of the level from before the changes, to save them into the profile.
+    baseline: level::Baseline,
     agents: Vec<Agent>,
     stats: Stats,
     ui: config::settings::Ui,
     cam: space::Camera,
     cam_style: CameraStyle,
+    cam_effects: space::CameraEffects,
+    flip_config: config::settings::FlipAssist,
+    input_config: config::settings::InputShaping,
+    antenna_config: config::settings::Antenna,
+    strand_mesh: std::sync::Arc<model::Mesh>,
+    /// Depth range of the camera at the ground level.
+    depth_base: Range<f32>,
+    auto_depth: bool,
     max_quant: f32,
+    substep_config: config::settings::Substeps,
+    ai_culling: bool,
     input: Input,
+    /// Present if the suspension of the wheels is debugged.
+    suspension: Option<suspension::Tuning>,
+    /// Speed of the simulation relative to the real time.
+    time_scale: f32,
+    slice_transition: SliceTransition,
+    gfx: GraphicsContext,
+    /// What is needed to re-create the GPU resources after a device loss.
+    settings: config::Settings,
+    level_config: level::LevelConfig,
+    palette: [[u8; 4]; 0x100],
+    catalog: world::Catalog,
+    world_name: String,
+    selected_world: String,
+    switcher: world::Switcher,
+    trace: Option<trace::Session>,
+    draw_distance: config::settings::DrawDistance,
+    props: props::Props,
+    profile: Option<profile::Profile>,
+    /// Source of all the randomness affecting the game state.
+    rng: rand::rngs::StdRng,
+    pub events: events::Bus,
 }
 
 impl Game {
-    pub fn new(settings: &config::Settings, gfx: &GraphicsContext) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn new(
+        settings: &config::Settings,
+        gfx: &GraphicsContext,
+        trace_command: Option<trace::Command>,
+    ) -> Self {
+        let seed = match settings.game.seed {
+            0 => rand::random(),
+            seed => seed,
+        };
+        log::info!("Using random seed {}", seed);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
         log::info!("Loading world parameters");
-        let mut escaves = config::escaves::load(settings.open_relative("escaves.prm"));
-        let mut escaves_secondary = config::escaves::load(settings.open_relative("spots.prm"));
-        escaves.append(&mut escaves_secondary);
+        let mut escaves = Vec::new();
+        if settings.has_game_data() {
+            escaves = config::escaves::load(settings.open_relative("escaves.prm"));
+            let mut escaves_secondary = config::escaves::load(settings.open_relative("spots.prm"));
+            escaves.append(&mut escaves_secondary);
+        }
+        let catalog = world::Catalog::new(settings);
 
+        let mut fixture = None;
         let (level_config, default_coords) = if settings.game.level.is_empty() {
             log::info!("Using test level");
             (level::LevelConfig::new_test(), (0, 0))
+        } else if let Some(preset) = level::TestPreset::from_level_name(&settings.game.level) {
+            log::info!("Using test level {:?}", preset);
+            (level::LevelConfig::new_test_preset(preset), (0, 0))
+        } else if let Some(generator) = level::Generator::from_level_name(&settings.game.level) {
+            log::info!("Using generated level with seed {}", generator.seed);
+            (level::LevelConfig::new_generated(generator), (0, 0))
+        } else if let Some(path) = level::Fixture::path_from_level_name(&settings.game.level) {
+            log::info!("Using level fixture {}", path.display());
+            let loaded = level::Fixture::load(path).unwrap_or_else(|e| panic!("{}", e));
+            let config = loaded.config();
+            fixture = Some(loaded);
+            (config, (0, 0))
         } else {
             use rand::seq::SliceRandom as _;
 
@@ -334,13 +728,12 @@ impl Game {
                 None => (0, 0),
             };
 
-            let worlds = config::worlds::load(settings.open_relative("wrlds.dat"));
-            let ini_name = match worlds.get(&settings.game.level) {
+            let ini_name = match catalog.worlds.get(&settings.game.level) {
                 Some(name) => name,
                 None => panic!(
                     "Unknown level '{}', valid names are: {:?}",
                     settings.game.level,
-                    worlds.keys().collect::<Vec<_>>()
+                    catalog.worlds.keys().collect::<Vec<_>>()
                 ),
             };
             let ini_path = settings.data_path.join(ini_name);
@@ -354,7 +747,7 @@ impl Game {
         let cam = space::Camera {
             loc: cgmath::vec3(coords.0 as f32, coords.1 as f32, 200.0),
             rot: cgmath::One::one(),
-            scale: cgmath::vec3(1.0, -1.0, 1.0),
+            handedness: settings.render.handedness,
             proj: match settings.game.camera.projection {
                 config::settings::Projection::Perspective => {
                     let pf = cgmath::PerspectiveFov {
@@ -374,33 +767,48 @@ impl Game {
         };
 
         log::info!("Initializing the render");
-        let pal_data = level::read_palette(settings.open_palette(), Some(&level_config.terrains));
-        let render = Render::new(
-            gfx,
-            &level_config,
-            &pal_data,
-            &settings.render,
-            &settings.game.geometry,
-            cam.front_face(),
-        );
+        let (geometry, pal_data) = match fixture {
+            Some(ref fixture) => (fixture.geometry.clone(), fixture.palette()),
+            None => (
+                catalog.geometry(&settings.game.level),
+                settings.load_palette(Some(&level_config.terrains)),
+            ),
+        };
+        let render = Render::new(gfx, &level_config, &pal_data, &settings.render, &geometry);
 
         log::info!("Loading world database");
         let db = {
             let game = config::game::Registry::load(settings);
+            let mut common = if settings.has_game_data() {
+                config::common::load(settings.open_relative("common.prm"))
+            } else {
+                config::fallback::common()
+            };
+            common.terrain.set_surfaces(&settings.game.surfaces);
+            common.car.gearbox = settings.game.gearbox;
             DataBase {
-                _bunches: config::bunches::load(settings.open_relative("bunches.prm")),
+                _bunches: if settings.has_game_data() {
+                    config::bunches::load(settings.open_relative("bunches.prm"))
+                } else {
+                    Vec::new()
+                },
                 cars: config::car::load_registry(settings, &game, &gfx.device, &render.object),
-                common: config::common::load(settings.open_relative("common.prm")),
-                _escaves: escaves,
+                common,
+                escaves,
                 game,
             }
         };
 
         log::info!("Loading the level");
-        let level = level::load(&level_config, &settings.game.geometry);
+        let level = match fixture {
+            Some(fixture) => fixture.into_level(),
+            None => level::load(&level_config, &geometry),
+        };
 
         log::info!("Spawning agents");
-        let car_names = db.cars.keys().cloned().collect::<Vec<_>>();
+        let mut car_names = db.cars.keys().cloned().collect::<Vec<_>>();
+        // the map order changes between runs, which would defeat the seed
+        car_names.sort();
         let mut player_agent = Agent::spawn(
             "Player".to_string(),
             match db.cars.get(&settings.car.id) {
@@ -417,23 +825,56 @@ impl Game {
             &level,
         );
         player_agent.spirit = Spirit::Player;
-        for (ms, sid) in player_agent
-            .car
-            .model
-            .slots
-            .iter_mut()
-            .zip(settings.car.slots.iter())
-        {
-            let info = &db.game.model_infos[sid];
-            let raw = Mesh::load(&mut settings.open_relative(&info.path));
-            ms.mesh = Some(model::load_c3d(raw, &gfx.device));
-            ms.scale = info.scale;
+        if let Some(ref config) = settings.car.trailer {
+            match db.cars.get(&config.id) {
+                Some(info) => {
+                    let Physics::Cpu { ref transform, .. } = player_agent.physics;
+                    let trailer =
+                        trailer::Trailer::new(info.clone(), config, &player_agent.car, transform);
+                    player_agent.trailer = Some(trailer);
+                }
+                None => log::warn!("Unknown trailer car '{}'", config.id),
+            }
         }
+        load_slots(&mut player_agent.car.model, settings, &db.game, &gfx.device);
+
+        let trace = match trace_command {
+            Some(trace::Command::Record(path)) => Some(trace::Session::record(
+                path,
+                settings.game.level.clone(),
+                settings.car.id.clone(),
+                player_agent.trace_state(),
+            )),
+            Some(trace::Command::Compare(path)) => match trace::Session::compare(path) {
+                Ok(session) => {
+                    if let trace::Session::Compare { ref trace, .. } = session {
+                        if trace.world != settings.game.level {
+                            log::warn!(
+                                "Trace was recorded in world '{}', but '{}' is loaded",
+                                trace.world,
+                                settings.game.level
+                            );
+                        }
+                        match db.cars.get(&trace.car) {
+                            Some(info) => player_agent.change_car(info, trace.car.clone()),
+                            None => log::warn!("Unknown trace car '{}'", trace.car),
+                        }
+                        player_agent.set_trace_state(&trace.start);
+                    }
+                    Some(session)
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    None
+                }
+            },
+            None => None,
+        };
 
         let mut agents = vec![player_agent];
         // populate with random agents
         for i in 0..settings.game.other.count {
-            use rand::{prelude::SliceRandom, Rng};
+            use rand::prelude::SliceRandom;
             let color = match rng.gen_range(0..3) {
                 0 => BodyColor::Green,
                 1 => BodyColor::Red,
@@ -460,20 +901,225 @@ impl Game {
             agents.push(agent);
         }
 
-        Game {
+        let mut props = props::Props::load(settings, &gfx.device);
+        props.populate(&level, &settings.game.level, rng.gen());
+        let suspension = if settings.render.debug.suspension {
+            Some(suspension::Tuning::new(&db.common))
+        } else {
+            None
+        };
+
+        let mut game = Game {
             db,
             render,
             batcher: Batcher::new(),
+            snapshot_path: None,
             line_buffer: LineBuffer::new(),
             level,
+            baseline: level::Baseline::default(),
             agents,
             stats: Stats::default(),
-            ui: settings.ui.clone(),
+            ui: settings.ui,
             cam,
             cam_style: CameraStyle::new(&settings.game.camera),
+            cam_effects: space::CameraEffects::new(settings.game.camera.effects),
+            flip_config: settings.game.flip,
+            input_config: settings.game.input,
+            antenna_config: settings.game.antenna,
+            strand_mesh: model::strand_mesh(&gfx.device),
+            depth_base: depth.0..depth.1,
+            auto_depth: settings.game.camera.auto_depth,
             max_quant: settings.game.physics.max_quant,
+            substep_config: settings.game.physics.substeps,
+            ai_culling: settings.game.other.ai_culling,
             input: Input::default(),
+            suspension,
+            time_scale: 1.0,
+            slice_transition: SliceTransition::default(),
+            gfx: gfx.clone(),
+            settings: settings.clone(),
+            level_config,
+            palette: pal_data,
+            catalog,
+            world_name: settings.game.level.clone(),
+            selected_world: settings.game.level.clone(),
+            switcher: world::Switcher::new(),
+            trace,
+            draw_distance: settings.render.draw_distance,
+            props,
+            profile: profile::Profile::new(settings.profile_path.clone()),
+            rng,
+            events: {
+                let mut bus = events::Bus::default();
+                // stand-in for the audio, until we can play the sounds,
+                // the world switches are already logged on their own
+                bus.subscribe_positional(
+                    &[
+                        events::Kind::Collision,
+                        events::Kind::Splash,
+                        events::Kind::Emerge,
+                    ],
+                    events::log,
+                );
+                bus.subscribe(&[events::Kind::Checkpoint], events::log);
+                bus
+            },
+        };
+        game.restore_world();
+        game
+    }
+
+    fn player(&self) -> Option<&Agent> {
+        self.agents.iter().find(|a| a.spirit == Spirit::Player)
+    }
+
+    pub fn player_position(&self) -> Option<cgmath::Vector3<f32>> {
+        self.player().map(|agent| agent.position())
+    }
+
+    /// Save the state of the current world into the profile.
+    fn save_world(&self) {
+        let profile = match self.profile {
+            Some(ref profile) if self.trace.is_none() => profile,
+            _ => return,
+        };
+        let player = match self.player() {
+            Some(player) if self.catalog.worlds.contains_key(&self.world_name) => player,
+            _ => return,
+        };
+        let state = profile::WorldState {
+            car: player.car_name.clone(),
+            vehicle: player.trace_state(),
+            terrain: self.baseline.diff(&self.level),
+        };
+        profile.save(&self.world_name, &state);
+    }
+
+    /// Bring the current world into the state saved in the profile, if any.
+    fn restore_world(&mut self) {
+        let state = match self.profile {
+            Some(ref profile)
+                if self.trace.is_none() && self.catalog.worlds.contains_key(&self.world_name) =>
+            {
+                match profile.load(&self.world_name) {
+                    Some(state) => state,
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+        self.baseline.keep_patch(&self.level, &state.terrain);
+        if let Some((min, max)) = state.terrain.apply(&mut self.level) {
+            self.mark_dirty(min, max);
         }
+        let player = match self.agents.iter_mut().find(|a| a.spirit == Spirit::Player) {
+            Some(player) => player,
+            None => return,
+        };
+        match self.db.cars.get(&state.car) {
+            Some(info) => player.change_car(info, state.car.clone()),
+            None => log::warn!("Unknown saved car '{}'", state.car),
+        }
+        let old_pos = player.position();
+        player.set_trace_state(&state.vehicle);
+        self.cam.loc += player.position() - old_pos;
+    }
+
+    /// Re-upload the level texels in the given range of coordinates.
+    fn mark_dirty(&mut self, min: (i32, i32), max: (i32, i32)) {
+        self.render.terrain.dirty_rects.push(DirtyRect {
+            rect: Rect {
+                x: min.0 as u16,
+                y: min.1 as u16,
+                w: (max.0 - min.0) as u16,
+                h: (max.1 - min.1) as u16,
+            },
+            z_range: 0..self.level.geometry.height as _,
+            need_upload: true,
+        });
+    }
+
+    /// Lower the ground around the point into a bowl.
+    fn dig(&mut self, center: cgmath::Vector3<f32>, radius: f32, depth: f32) {
+        let altitude_scale = self.level.geometry.height as f32 / 256.0;
+        let r = radius.ceil() as i32;
+        let (cx, cy) = WorldPos(center).texel().into();
+        // stay within the level, without wrapping around
+        let min = ((cx - r).max(0), (cy - r).max(0));
+        let max = (
+            (cx + r + 1).min(self.level.size.0),
+            (cy + r + 1).min(self.level.size.1),
+        );
+        self.baseline.keep(&self.level, min.1..max.1);
+        for y in min.1..max.1 {
+            for x in min.0..max.0 {
+                let i = (y * self.level.size.0 + x) as usize;
+                // the layers of the double level texels would need a consistent update
+                if self.level.meta[i] & level::DOUBLE_LEVEL != 0 {
+                    continue;
+                }
+                let d2 = ((x - cx).pow(2) + (y - cy).pow(2)) as f32 / (radius * radius);
+                if d2 < 1.0 {
+                    let lower = depth * (1.0 - d2) / altitude_scale;
+                    self.level.height[i] = (self.level.height[i] as f32 - lower).max(0.0) as u8;
+                }
+            }
+        }
+        self.level.update_pyramid(min, max);
+        self.mark_dirty(min, max);
+    }
+
+    fn swap_world(&mut self, world: world::LoadedWorld) {
+        use rand::seq::SliceRandom as _;
+        log::info!("Switching to world {}", world.name);
+        self.save_world();
+
+        let coords = self
+            .db
+            .escaves
+            .iter()
+            .filter(|e| e.world == world.name)
+            .map(|e| e.coordinates)
+            .collect::<Vec<_>>()
+            .choose(&mut self.rng)
+            .cloned()
+            .unwrap_or((0, 0));
+        self.level = world.level;
+        self.baseline = level::Baseline::default();
+        self.render = world.render;
+        self.level_config = world.config;
+        self.palette = world.palette;
+        // the window could have been resized while the world was loading
+        self.render.resize(self.gfx.screen_size, &self.gfx.device);
+        self.world_name = world.name;
+        self.props
+            .populate(&self.level, &self.world_name, self.rng.gen());
+        self.events.emit(events::Event::WorldSwitch {
+            name: self.world_name.clone(),
+        });
+
+        // keep the cars as they are, only relocate them into the new world
+        let size = self.level.size;
+        for agent in self.agents.iter_mut() {
+            let Physics::Cpu {
+                ref mut transform, ..
+            } = agent.physics;
+            let (x, y) = match agent.spirit {
+                Spirit::Player => coords,
+                Spirit::Other(_) => (
+                    (transform.disp.x as i32).rem_euclid(size.0),
+                    (transform.disp.y as i32).rem_euclid(size.1),
+                ),
+            };
+            let old_disp = transform.disp;
+            transform.disp = cgmath::vec3(x as f32, y as f32, self.level.get((x, y)).high() + 5.0);
+            match agent.spirit {
+                Spirit::Player => self.cam.loc += transform.disp - old_disp,
+                Spirit::Other(ref mut ai) => ai.last_transform = *transform,
+            }
+            agent.place_trailer();
+        }
+        self.restore_world();
     }
 
     fn _move_cam(&mut self, step: f32) {
@@ -483,6 +1129,12 @@ impl Game {
     }
 }
 
+impl Drop for Game {
+    fn drop(&mut self) {
+        self.save_world();
+    }
+}
+
 impl Application for Game {
     fn on_key(&mut self, input: winit::event::KeyboardInput) -> bool {
         use winit::event::{ElementState, KeyboardInput, VirtualKeyCode as Key};
@@ -513,34 +1165,39 @@ impl Application for Game {
                         self.cam.focus_on(&center);
                     }
                 }
+                Key::Space => {
+                    self.input.tick = None;
+                    self.input.is_frozen = !self.input.is_frozen;
+                }
                 Key::Comma => self.input.tick = Some(-1.0),
                 Key::Period => self.input.tick = Some(1.0),
+                Key::LBracket => {
+                    self.time_scale = (self.time_scale * 0.5).max(TIME_SCALE_MIN);
+                }
+                Key::RBracket => {
+                    self.time_scale = (self.time_scale * 2.0).min(TIME_SCALE_MAX);
+                }
+                Key::Backslash => self.time_scale = 1.0,
                 Key::LShift => self.input.turbo = true,
                 Key::LAlt => self.input.jump = Some(0.0),
-                Key::W => self.input.spin_ver = self.cam.scale.x,
-                Key::S => self.input.spin_ver = -self.cam.scale.x,
+                Key::W => self.input.spin_ver = 1.0,
+                Key::S => self.input.spin_ver = -1.0,
                 Key::R => {
-                    if let Physics::Cpu {
-                        ref mut transform,
-                        ref mut dynamo,
-                    } = player.physics
-                    {
-                        transform.rot = cgmath::One::one();
-                        dynamo.linear_velocity = cgmath::Vector3::zero();
-                        dynamo.angular_velocity = cgmath::Vector3::zero();
-                    }
+                    player.start_flip(&self.level);
+                    self.input.jump = None;
+                    self.input.roll = None;
                 }
-                Key::A => self.input.spin_hor = -self.cam.scale.y,
-                Key::D => self.input.spin_hor = self.cam.scale.y,
+                Key::A => self.input.spin_hor = -self.cam.handedness.y_sign(),
+                Key::D => self.input.spin_hor = self.cam.handedness.y_sign(),
                 Key::Q => {
                     self.input.roll = Some(Roll {
-                        dir: -self.cam.scale.y,
+                        dir: -self.cam.handedness.y_sign(),
                         time: 0.0,
                     })
                 }
                 Key::E => {
                     self.input.roll = Some(Roll {
-                        dir: self.cam.scale.y,
+                        dir: self.cam.handedness.y_sign(),
                         time: 0.0,
                     })
                 }
@@ -555,7 +1212,21 @@ impl Application for Game {
                 Key::A | Key::D => self.input.spin_hor = 0.0,
                 Key::Q | Key::E => self.input.roll = None,
                 Key::LShift => self.input.turbo = false,
-                Key::LAlt => player.jump = self.input.jump.take(),
+                Key::LAlt => {
+                    player.jump = self.input.jump.take();
+                    if let (Some(power), Some(ref mut particle)) =
+                        (player.jump, self.render.particle.as_mut())
+                    {
+                        // kick some dust off the ground
+                        let color = match player.surface {
+                            Some(terrain_type) => {
+                                self.db.common.terrain.surfaces[terrain_type as usize].dust
+                            }
+                            None => vangers::render::particle::DUST_COLOR,
+                        };
+                        particle.spawn_colored(player.position(), 64, 2.0 * power, color);
+                    }
+                }
                 _ => (),
             },
             /*
@@ -581,11 +1252,22 @@ impl Application for Game {
     fn update(&mut self, _device: &wgpu::Device, _queue: &wgpu::Queue, delta: f32) {
         profiling::scope!("Update");
 
+        // a replay advances by the recorded frame times, regardless of the real ones
+        let replay_input = self.trace.as_ref().and_then(|t| t.replay_input());
+        let delta = match replay_input {
+            Some(ref input) => input.delta,
+            None => delta,
+        };
+
         self.stats.frame_deltas.push(delta * 1000.0);
         if self.stats.frame_deltas.len() > self.ui.frame_history {
             self.stats.frame_deltas.remove(0);
         }
 
+        if let Some(world) = self.switcher.update(delta) {
+            self.swap_world(world);
+        }
+
         let focus_point = self
             .cam
             .intersect_height(self.level.geometry.height as f32 * 0.3);
@@ -595,6 +1277,24 @@ impl Application for Game {
             *jump = (*jump + power).min(self.db.common.force.max_jump_power);
         }
 
+        // Everything simulated goes by this time, which is scaled, or a single
+        // frame of the original game while paused or frozen. A step back only
+        // reverses the physics, the animations keep going forward.
+        let is_stepping = self.input.is_paused || self.input.is_frozen;
+        let sim_delta = if is_stepping {
+            match self.input.tick.take() {
+                Some(tick) => tick / self.db.common.speed.standard_frame_rate as f32,
+                None => 0.0,
+            }
+        } else if replay_input.is_some() {
+            // the recorded frame times are scaled already
+            delta
+        } else {
+            delta * self.time_scale
+        };
+        let anim_delta = sim_delta.abs();
+
+        let trace_input;
         {
             let player = self
                 .agents
@@ -606,114 +1306,289 @@ impl Application for Game {
             };
 
             if self.input.is_paused {
-                if let Some(tick) = self.input.tick.take() {
-                    self.line_buffer.clear();
-                    player.control.roll = 0.0;
-
-                    player.cpu_step(
-                        tick * self.max_quant,
-                        &self.level,
-                        &self.db.common,
-                        SimulationStep::Final {
-                            focus_point: &focus_point,
-                            line_buffer: Some(&mut self.line_buffer),
-                        },
-                    );
-                }
-
+                // the controls are left as they were, since the keys move the camera
                 self.cam.rotate_focus(
                     &target,
                     cgmath::Rad(2.0 * delta * self.input.spin_hor),
                     cgmath::Rad(delta * self.input.spin_ver),
                 );
-
-                return;
-            }
-
-            player.control.rudder = self.input.spin_hor;
-            player.control.motor = 1.0 * self.input.spin_ver;
-            player.control.turbo = self.input.turbo;
-            player.control.roll = match self.input.roll {
-                Some(ref mut roll) => {
-                    let roll_count = (roll.time * self.db.common.speed.standard_frame_rate as f32)
-                        .min(100.0) as u8;
-                    roll.time += delta;
-                    if roll_count > self.db.common.force.side_impulse_delay {
-                        roll.time = 0.0;
-                    }
-                    if roll_count < self.db.common.force.side_impulse_duration {
-                        roll.dir
-                    } else {
-                        0.0
+            } else {
+                player.control.rudder = self.input.spin_hor;
+                player.control.motor = 1.0 * self.input.spin_ver;
+                player.control.turbo = self.input.turbo;
+                player.control.roll = match self.input.roll {
+                    Some(ref mut roll) => {
+                        let roll_count = (roll.time
+                            * self.db.common.speed.standard_frame_rate as f32)
+                            .min(100.0) as u8;
+                        roll.time += anim_delta;
+                        if roll_count > self.db.common.force.side_impulse_delay {
+                            roll.time = 0.0;
+                        }
+                        if roll_count < self.db.common.force.side_impulse_duration {
+                            roll.dir
+                        } else {
+                            0.0
+                        }
                     }
-                }
-                None => 0.0,
+                    None => 0.0,
+                };
+            }
+            if let Some(ref input) = replay_input {
+                player.control.motor = input.motor;
+                player.control.rudder = input.rudder;
+                player.control.roll = input.roll;
+                player.control.brake = input.brake;
+                player.control.turbo = input.turbo;
+                player.jump = input.jump;
+            }
+            trace_input = trace::Input {
+                delta: sim_delta,
+                motor: player.control.motor,
+                rudder: player.control.rudder,
+                roll: player.control.roll,
+                brake: player.control.brake,
+                turbo: player.control.turbo,
+                jump: player.jump,
             };
 
-            match self.cam_style {
-                CameraStyle::Simple(ref dir) => {
-                    self.cam.look_by(&target, dir);
-                }
-                CameraStyle::Follow {
-                    ref follow,
-                    ground_anchor,
-                } => {
-                    if ground_anchor {
-                        target.disp.z = self
-                            .level
-                            .get((target.disp.x as i32, target.disp.y as i32))
-                            .high();
+            if !self.input.is_paused {
+                match self.cam_style {
+                    CameraStyle::Simple(ref dir) => {
+                        self.cam.look_by(&target, dir);
+                    }
+                    CameraStyle::Follow {
+                        ref follow,
+                        ground_anchor,
+                    } => {
+                        if ground_anchor {
+                            let texel = self.level.get_at(target.disp);
+                            // stick to the lower layer while driving underground
+                            target.disp.z = if texel.depth_underground(target.disp.z).is_some() {
+                                texel.low()
+                            } else {
+                                texel.high()
+                            };
+                        }
+                        self.cam.follow(&target, delta, follow, &self.level);
                     }
-                    self.cam.follow(&target, delta, follow);
                 }
             }
         }
+        if let Some(player) = self.agents.iter().find(|a| a.spirit == Spirit::Player) {
+            let Physics::Cpu { ref dynamo, .. } = player.physics;
+            self.cam_effects
+                .update(anim_delta, dynamo.linear_velocity.magnitude());
+        }
+        if self.auto_depth {
+            self.cam
+                .fit_depth_range(self.depth_base.clone(), self.level.geometry.height as f32);
+        }
+        if sim_delta == 0.0 {
+            return;
+        }
 
         const TIME_HACK: f32 = 1.0;
         // Note: the equations below make the game absolutely match the original
         // in terms of time scale for both input and physics.
         // However! the game feels much faster, presumably because of the lack
         // of collision/drag forces that slow you down.
-        let input_factor = TIME_HACK * delta / config::common::MAIN_LOOP_TIME;
-        let physics_dt = TIME_HACK * delta * {
+        let input_factor = TIME_HACK * anim_delta / config::common::MAIN_LOOP_TIME;
+        let physics_dt = TIME_HACK * sim_delta * {
             let n = &self.db.common.nature;
             let fps = self.db.common.speed.standard_frame_rate as f32;
             fps * n.time_delta0 * n.num_calls_analysis as f32
         };
 
         {
-            use rayon::prelude::*;
-
             let clipper = Clipper::new(&self.cam);
+            let wrap = WorldWrap::new(&self.level, &self.cam);
+            let ai_rect = if self.ai_culling {
+                let rect = self.cam.visible_terrain_rect(&self.level);
+                Some(rect.grow_wrapped(AI_ACTIVITY_MARGIN, self.level.size))
+            } else {
+                None
+            };
             let max_quant = self.max_quant;
+            let substep_config = &self.substep_config;
             let common = &self.db.common;
             let level = &self.level;
+            let flip_config = &self.flip_config;
+            let input_config = &self.input_config;
+            let antenna_config = &self.antenna_config;
+            let debug_wheels = self.suspension.is_some();
+            // the pause shows the forces of the player's steps
+            let line_buffer = std::sync::Mutex::new(if self.input.is_paused {
+                self.line_buffer.clear();
+                Some(&mut self.line_buffer)
+            } else {
+                None
+            });
 
-            self.agents.par_iter_mut().for_each(|a| {
-                let mut dt = physics_dt;
-                a.cpu_apply_control(input_factor, common);
+            // the physics of a single agent is heavy enough for a job
+            jobs::for_each_mut(&mut self.agents, 1, |a| {
+                if a.update_flip(anim_delta, level, flip_config) {
+                    a.update_antennas(anim_delta, antenna_config);
+                    return;
+                }
+                a.cpu_apply_control(anim_delta, input_factor, common, input_config);
 
-                // only go through the full iteration on visible objects
-                if !clipper.clip(&a.position()) {
-                    while dt > max_quant {
-                        a.cpu_step(max_quant, level, common, SimulationStep::Intermediate);
-                        dt -= max_quant;
-                    }
+                // only go through the full iteration on visible objects,
+                // and always on the player, to keep the traces deterministic
+                let split =
+                    if a.spirit == Spirit::Player || !clipper.clip(&wrap.nearest(a.position())) {
+                        a.split_frame(physics_dt, level, max_quant, substep_config)
+                    } else {
+                        physics::Split::single(physics_dt)
+                    };
+                for _ in 0..split.count {
+                    a.cpu_step(split.quant, level, common, SimulationStep::Intermediate);
+                }
+                a.substeps = split.steps();
+                if debug_wheels {
+                    a.wheels.get_or_insert_with(Vec::new);
                 }
 
+                let mut lines = match a.spirit {
+                    Spirit::Player => Some(line_buffer.lock().unwrap()),
+                    _ => None,
+                };
                 a.cpu_step(
-                    dt,
+                    split.last,
                     level,
                     common,
                     SimulationStep::Final {
                         focus_point: &focus_point,
-                        line_buffer: None,
+                        line_buffer: lines.as_mut().and_then(|l| l.as_deref_mut()),
                     },
                 );
+                a.update_antennas(anim_delta, antenna_config);
 
-                a.ai_behavior(delta);
+                // with the culling, the ones far from the view keep going as they were
+                let texel = WorldPos(a.position()).texel();
+                if ai_rect.is_none_or(|r| r.contains_wrapped(texel.into(), level.size)) {
+                    a.ai_behavior(level);
+                }
             });
         }
+
+        if self.suspension.is_some() {
+            // the pause keeps the lines of the last step
+            if !self.input.is_paused {
+                self.line_buffer.clear();
+            }
+            for agent in self.agents.iter() {
+                if let Some(ref wheels) = agent.wheels {
+                    suspension::add_gizmos(wheels, &mut self.line_buffer);
+                }
+            }
+        }
+
+        let hit_threshold = self.db.common.contact.strong_ground_collision_threshold;
+        self.stats.broadphase = physics::BroadphaseCounters::default();
+        self.stats.substeps = SubstepStats::default();
+        let num_agents = self.agents.len();
+        for (index, agent) in self.agents.iter_mut().enumerate() {
+            self.stats.broadphase += std::mem::take(&mut agent.broadphase);
+            let substeps = std::mem::take(&mut agent.substeps);
+            self.stats.substeps.average += substeps as f32 / num_agents as f32;
+            self.stats.substeps.max = self.stats.substeps.max.max(substeps);
+            if agent.spirit == Spirit::Player {
+                self.stats.substeps.player = substeps;
+            }
+            if let Some(event) = agent.water_transition(index) {
+                self.events.emit(event);
+            }
+            if let Some(ref mut particle) = self.render.particle {
+                agent.kick_dust(anim_delta, &self.db.common.terrain.surfaces, particle);
+            }
+            let hit_speed = std::mem::take(&mut agent.hit_speed);
+            let landing_speed = std::mem::take(&mut agent.landing_speed);
+            if agent.spirit == Spirit::Player {
+                for speed in [hit_speed, landing_speed] {
+                    if speed > hit_threshold {
+                        self.cam_effects.impact(speed / hit_threshold);
+                    }
+                }
+            }
+            if hit_speed > hit_threshold {
+                self.events.emit(events::Event::Collision {
+                    agent: index,
+                    position: agent.position(),
+                    speed: hit_speed,
+                });
+            }
+            let pos = agent.position();
+            let checkpoint = self.db.escaves.iter().position(|e| {
+                let dx = e.coordinates.0 as f32 - pos.x;
+                let dy = e.coordinates.1 as f32 - pos.y;
+                e.world == self.world_name && dx * dx + dy * dy < CHECKPOINT_RADIUS.powi(2)
+            });
+            if checkpoint != agent.checkpoint {
+                agent.checkpoint = checkpoint;
+                if let Some(escave) = checkpoint {
+                    self.events.emit(events::Event::Checkpoint {
+                        agent: index,
+                        name: self.db.escaves[escave].name.clone(),
+                    });
+                }
+            }
+        }
+        self.events
+            .set_audible(self.cam.visible_terrain_rect(&self.level), self.level.size);
+        for event in self.events.dispatch() {
+            let particle = match self.render.particle {
+                Some(ref mut particle) => particle,
+                None => continue,
+            };
+            match event {
+                events::Event::Splash {
+                    position,
+                    vertical_speed,
+                    ..
+                } => {
+                    let speed = vertical_speed.abs();
+                    let count =
+                        SPLASH_PARTICLES_BASE + (SPLASH_PARTICLES_PER_SPEED * speed) as usize;
+                    particle.spawn_burst(position, count, SPLASH_SPEED + 0.5 * speed);
+                }
+                events::Event::Emerge { position, .. } => {
+                    // drips off the body
+                    particle.spawn_burst(position, SPLASH_PARTICLES_BASE / 2, 1.0);
+                }
+                events::Event::Collision {
+                    position, speed, ..
+                } => {
+                    // debris of the wall
+                    particle.spawn_burst(position, SPLASH_PARTICLES_BASE, speed);
+                }
+                _ => {}
+            }
+        }
+
+        // the steps back are left out of the trace
+        let player = self.agents.iter().find(|a| a.spirit == Spirit::Player);
+        if let (Some(trace), Some(player)) = (self.trace.as_mut(), player) {
+            if sim_delta > 0.0 {
+                let wrap = [self.level.size.0 as f32, self.level.size.1 as f32];
+                trace.advance(trace_input, player.trace_state(), wrap);
+            }
+        }
+
+        if let Some(ref mut particle) = self.render.particle {
+            particle.update(anim_delta);
+        }
+
+        if let Some(player) = self.agents.iter().find(|a| a.spirit == Spirit::Player) {
+            self.render.terrain.cutaway =
+                Cutaway::new(&self.level, player.position(), &self.render.cutaway_config);
+            self.render.terrain.slice_view = self.slice_transition.update(
+                &self.level,
+                player.position(),
+                delta,
+                &self.render.underground_config,
+            );
+        }
     }
 
     fn resize(&mut self, device: &wgpu::Device, extent: wgpu::Extent3d) {
@@ -721,13 +1596,146 @@ impl Application for Game {
             .proj
             .update(extent.width as u16, extent.height as u16);
         self.render.resize(extent, device);
+        // worlds are preloaded with the current screen size
+        self.gfx.screen_size = extent;
     }
 
     fn reload(&mut self, device: &wgpu::Device) {
         self.render.reload(device);
     }
 
+    fn recover(&mut self, gfx: &GraphicsContext) {
+        self.gfx = gfx.clone();
+        self.render.recover(
+            gfx,
+            &self.level_config,
+            &self.palette,
+            &self.settings.render,
+            &self.level.geometry,
+        );
+        self.db.cars = config::car::load_registry(
+            &self.settings,
+            &self.db.game,
+            &gfx.device,
+            &self.render.object,
+        );
+        // the cars keep their state, only the models are replaced
+        for agent in self.agents.iter_mut() {
+            if let Some(info) = self.db.cars.get(&agent.car_name) {
+                agent.car.model = info.model.clone();
+            }
+            if agent.spirit != Spirit::Player {
+                continue;
+            }
+            if agent.car_name == self.settings.car.id {
+                load_slots(
+                    &mut agent.car.model,
+                    &self.settings,
+                    &self.db.game,
+                    &gfx.device,
+                );
+            }
+            if let (Some(trailer), Some(config)) = (&mut agent.trailer, &self.settings.car.trailer)
+            {
+                if let Some(info) = self.db.cars.get(&config.id) {
+                    trailer.car.model = info.model.clone();
+                }
+            }
+        }
+        self.strand_mesh = model::strand_mesh(&gfx.device);
+        self.props.recover(&self.settings, &gfx.device);
+        self.batcher = Batcher::new();
+        // a world loaded on the old device is of no use, so it's loaded again
+        if let Some(name) = self.switcher.loading_name().map(str::to_string) {
+            self.switcher = world::Switcher::new();
+            if let Some(request) = self.catalog.request(&name) {
+                self.switcher.start(request, gfx);
+            }
+        }
+    }
+
+    fn on_command(&mut self, command: &str) -> Result<(), String> {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        if name == "snapshot" {
+            let path = words.next().unwrap_or("snapshot.ron");
+            self.snapshot_path = Some(PathBuf::from(path));
+            return Ok(());
+        }
+        if name == "fixture" {
+            let usage = "Usage: fixture <size> [path]";
+            let size = match words.next().map(str::parse::<i32>) {
+                Some(Ok(size)) if size > 1 && size & (size - 1) == 0 => size,
+                _ => return Err(usage.to_string()),
+            };
+            let path = PathBuf::from(words.next().unwrap_or("fixture.ron"));
+            let player = self
+                .agents
+                .iter()
+                .find(|a| a.spirit == Spirit::Player)
+                .ok_or("No player")?;
+            let pos = player.position();
+            let fixture = level::Fixture::capture(&self.level, WorldPos(pos).texel().into(), size);
+            fixture.save(&path)?;
+            log::info!(
+                "Saved {}x{} texels at {:?} into {}",
+                fixture.size.0,
+                fixture.size.1,
+                fixture.origin,
+                path.display()
+            );
+            return Ok(());
+        }
+        let args = words
+            .map(|w| {
+                w.parse::<f32>()
+                    .map_err(|_| format!("Invalid number '{}'", w))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let player = self
+            .agents
+            .iter_mut()
+            .find(|a| a.spirit == Spirit::Player)
+            .ok_or("No player")?;
+        let old_pos = player.position();
+        match (name, args.as_slice()) {
+            ("reset", []) => {
+                let pos = player.position();
+                player.teleport(WorldPos(pos).texel().into(), None, &self.level);
+            }
+            ("teleport", &[x, y]) => player.teleport((x as i32, y as i32), None, &self.level),
+            ("teleport", &[x, y, z]) => player.teleport((x as i32, y as i32), Some(z), &self.level),
+            ("teleport", _) => return Err("Usage: teleport <x> <y> [z]".to_string()),
+            ("dig", &[radius, depth]) if radius > 0.0 => {
+                let center = player.position();
+                self.dig(center, radius, depth);
+                return Ok(());
+            }
+            ("dig", _) => return Err("Usage: dig <radius> <depth>".to_string()),
+            _ => return Err(format!("Unknown command '{}'", command)),
+        }
+        // keep the camera around the player
+        self.cam.loc += player.position() - old_pos;
+        self.input.jump = None;
+        self.input.roll = None;
+        Ok(())
+    }
+
     fn draw_ui(&mut self, context: &egui::Context) {
+        let fade = self.switcher.fade();
+        if fade > 0.0 {
+            context
+                .layer_painter(egui::LayerId::new(
+                    egui::Order::Foreground,
+                    egui::Id::new("world-fade"),
+                ))
+                .rect_filled(
+                    context.screen_rect(),
+                    0.0,
+                    egui::Color32::from_black_alpha((fade * 255.0) as u8),
+                );
+        }
+
         if !self.ui.enabled {
             return;
         }
@@ -741,18 +1749,19 @@ impl Application for Game {
             .find(|agent| agent.spirit == Spirit::Player)
             .unwrap();
         let mut selected_car = &player.car_name;
+        let mut switch_to = None;
 
         egui::SidePanel::right("Tweaks").show(context, |ui| {
             ui.group(|ui| {
-                ui.label("Player:");
-                egui::ComboBox::from_label("Mechous")
+                ui.label(tr("Player:"));
+                egui::ComboBox::from_label(tr("Mechous"))
                     .selected_text(&player.car_name)
                     .show_ui(ui, |ui| {
                         for car_name in self.db.cars.keys() {
                             ui.selectable_value(&mut selected_car, car_name, car_name);
                         }
                     });
-                egui::ComboBox::from_label("Color")
+                egui::ComboBox::from_label(tr("Color"))
                     .selected_text(player.color.name())
                     .show_ui(ui, |ui| {
                         for &color in &[
@@ -765,13 +1774,20 @@ impl Application for Game {
                             ui.selectable_value(&mut player.color, color, color.name());
                         }
                     });
+                ui.label(tr_fmt(
+                    "Input: {} rudder, {} motor",
+                    &[
+                        &format!("{:.2}", player.shaper.rudder.value()),
+                        &format!("{:.2}", player.shaper.motor.value()),
+                    ],
+                ));
                 if let Physics::Cpu {
                     ref mut transform,
                     dynamo: _,
                 } = player.physics
                 {
                     ui.horizontal(|ui| {
-                        ui.label("Position");
+                        ui.label(tr("Position"));
                         ui.add(
                             egui::DragValue::new(&mut transform.disp.x)
                                 .speed(1.0)
@@ -784,16 +1800,53 @@ impl Application for Game {
                         );
                     });
                 }
+                let immersion = player.immersion;
+                ui.label(tr_fmt(
+                    "Immersion: {} water, {} terrain",
+                    &[
+                        &format!("{:.1}", immersion.water),
+                        &format!("{:.1}", immersion.terrain),
+                    ],
+                ));
             });
+            if let Some(ref tuning) = self.suspension {
+                ui.group(|ui| {
+                    ui.label(tr("Suspension:"));
+                    let wheels = player.wheels.as_deref().unwrap_or_default();
+                    tuning.draw_ui(ui, wheels, &mut self.db.common);
+                });
+            }
             ui.group(|ui| {
-                ui.label("Camera:");
+                ui.label(tr("Time:"));
+                ui.add(
+                    egui::Slider::new(&mut self.time_scale, TIME_SCALE_MIN..=TIME_SCALE_MAX)
+                        .logarithmic(true)
+                        .text(tr("Time scale")),
+                );
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.input.is_frozen, tr("Frozen"));
+                    let can_step = self.input.is_frozen || self.input.is_paused;
+                    if ui
+                        .add_enabled(can_step, egui::Button::new(tr("Step")))
+                        .clicked()
+                    {
+                        self.input.tick = Some(1.0);
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.label(tr("Camera:"));
+                ui.checkbox(&mut self.auto_depth, tr("Auto depth range"));
                 self.cam.draw_ui(ui);
+                self.cam_effects.draw_ui(ui);
                 if let CameraStyle::Follow {
                     ref mut follow,
                     ref mut ground_anchor,
                 } = self.cam_style
                 {
-                    ui.add(egui::Slider::new(&mut follow.angle_x.0, -105.0..=0.0).text("Angle"));
+                    ui.add(
+                        egui::Slider::new(&mut follow.angle_x.0, -105.0..=0.0).text(tr("Angle")),
+                    );
                     ui.horizontal(|ui| {
                         ui.add(
                             egui::DragValue::new(&mut follow.offset.x)
@@ -811,18 +1864,82 @@ impl Application for Game {
                                 .prefix("z:"),
                         );
                     });
-                    ui.add(egui::Slider::new(&mut follow.speed, 0.1..=10.0).text("Speed"));
-                    ui.checkbox(ground_anchor, "Ground anchor");
+                    ui.add(egui::Slider::new(&mut follow.speed, 0.1..=10.0).text(tr("Speed")));
+                    ui.checkbox(ground_anchor, tr("Ground anchor"));
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut follow.collision.enabled, tr("Collision"));
+                        ui.add(
+                            egui::DragValue::new(&mut follow.collision.margin)
+                                .speed(1.0)
+                                .prefix(tr("margin:")),
+                        );
+                        ui.add(
+                            egui::DragValue::new(&mut follow.collision.speed)
+                                .speed(0.1)
+                                .prefix(tr("speed:")),
+                        );
+                    });
                 }
             });
             ui.group(|ui| {
-                ui.label("Level:");
-                self.level.draw_ui(ui);
+                ui.label(tr("Level:"));
+                if self.level.draw_ui(ui) && !self.world_name.is_empty() {
+                    self.catalog
+                        .geometry_overrides
+                        .set(&self.world_name, &self.level.geometry);
+                }
+                // don't write the file on every step of a drag
+                if !ui.ctx().is_using_pointer() {
+                    self.catalog.geometry_overrides.save();
+                }
+                ui.label(tr_fmt("Props: {}", &[&self.props.count()]));
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label(tr("World"))
+                        .selected_text(&self.selected_world)
+                        .show_ui(ui, |ui| {
+                            let mut names = self.catalog.worlds.keys().collect::<Vec<_>>();
+                            names.sort();
+                            for name in names {
+                                ui.selectable_value(&mut self.selected_world, name.clone(), name);
+                            }
+                        });
+                    let can_switch =
+                        !self.switcher.is_busy() && self.selected_world != self.world_name;
+                    if ui
+                        .add_enabled(can_switch, egui::Button::new(tr("Switch")))
+                        .clicked()
+                    {
+                        switch_to = Some(self.selected_world.clone());
+                    }
+                });
+                if let Some(name) = self.switcher.loading_name() {
+                    ui.label(tr_fmt("Loading {}...", &[&name]));
+                }
             });
+            if let Some(ref trace) = self.trace {
+                ui.group(|ui| {
+                    ui.label(tr("Trace:"));
+                    trace.draw_ui(ui);
+                });
+            }
             ui.group(|ui| {
-                ui.label("Renderer:");
+                ui.label(tr("Renderer:"));
                 self.render.draw_ui(ui);
             });
+            let counters = self.stats.broadphase;
+            ui.label(tr_fmt(
+                "Terrain polygons: {} tested, {} skipped",
+                &[&counters.tested, &counters.skipped],
+            ));
+            let substeps = self.stats.substeps;
+            ui.label(tr_fmt(
+                "Physics steps: {} for the player, {} on average, {} at most",
+                &[
+                    &substeps.player,
+                    &format!("{:.1}", substeps.average),
+                    &substeps.max,
+                ],
+            ));
             egui::plot::Plot::new("Frame time")
                 .allow_zoom(false)
                 .allow_scroll(false)
@@ -840,27 +1957,81 @@ impl Application for Game {
             let name = selected_car.clone();
             player.change_car(&self.db.cars[&name], name);
         }
+        if let Some(request) = switch_to.and_then(|name| self.catalog.request(&name)) {
+            self.switcher.start(request, &self.gfx);
+        }
     }
 
     fn draw(&mut self, device: &wgpu::Device, targets: ScreenTargets) -> wgpu::CommandBuffer {
-        let clipper = Clipper::new(&self.cam);
+        let cam = self.cam_effects.apply(&self.cam);
+        let clipper = Clipper::new(&cam);
+        let wrap = WorldWrap::new(&self.level, &cam);
         self.batcher.clear();
+        self.batcher.wrap = Some(wrap);
+        self.batcher.cull = Some(DistanceCull {
+            origin: cam.loc,
+            distance: self.draw_distance,
+        });
 
-        for agent in self.agents.iter() {
-            let transform = match agent.physics {
-                Physics::Cpu { ref transform, .. } => {
-                    if clipper.clip(&transform.disp) {
-                        continue;
+        let base = &self.batcher;
+        let strand_mesh = &self.strand_mesh;
+        let parts = jobs::map_chunks(&self.agents, AGENTS_PER_JOB, |agents| {
+            let mut part = base.fork();
+            for agent in agents {
+                let transform = match agent.physics {
+                    Physics::Cpu { ref transform, .. } => {
+                        let radius = agent.car.model.body.bbox.radius * transform.scale;
+                        if wrap
+                            .copies(transform.disp, radius)
+                            .iter()
+                            .all(|pos| clipper.clip(pos))
+                        {
+                            continue;
+                        }
+                        transform
+                    }
+                };
+                let debug_shape_scale = match agent.spirit {
+                    Spirit::Player => Some(agent.car.physics.scale_bound),
+                    Spirit::Other { .. } => None,
+                };
+                // the player's car is see-through when the camera gets close
+                part.alpha = match (&agent.spirit, part.cull) {
+                    (Spirit::Player, Some(ref cull)) => {
+                        let radius = agent.car.model.body.bbox.radius * transform.scale;
+                        cull.near_alpha(transform.disp, radius)
                     }
-                    transform
+                    _ => 1.0,
+                };
+                part.add_model(&agent.car.model, transform, debug_shape_scale, agent.color);
+                part.alpha = 1.0;
+                if let Some(ref trailer) = agent.trailer {
+                    part.add_model(&trailer.car.model, &trailer.transform, None, agent.color);
                 }
-            };
-            let debug_shape_scale = match agent.spirit {
-                Spirit::Player => Some(agent.car.physics.scale_bound),
-                Spirit::Other { .. } => None,
-            };
-            self.batcher
-                .add_model(&agent.car.model, transform, debug_shape_scale, agent.color);
+                for chain in agent.antennas.iter() {
+                    for (from, to) in chain.segments() {
+                        part.add_strand(strand_mesh, from, to, agent.color);
+                    }
+                }
+            }
+            part
+        });
+        for part in parts {
+            self.batcher.merge(part);
+        }
+
+        self.props.draw(&mut self.batcher, &cam, &wrap);
+
+        if let Some(path) = self.snapshot_path.take() {
+            let snapshot = Snapshot::capture(&self.batcher, &self.level, &cam);
+            match snapshot.save(&path) {
+                Ok(()) => log::info!(
+                    "Saved {} instances into {}",
+                    snapshot.instances.len(),
+                    path.display()
+                ),
+                Err(e) => log::error!("{}", e),
+            }
         }
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -871,18 +2042,14 @@ impl Application for Game {
             &mut encoder,
             &mut self.batcher,
             &self.level,
-            &self.cam,
+            &cam,
             targets,
             None,
             device,
         );
 
-        /*
-        self.render.debug.draw_lines(
-            &self.line_buffer,
-            self.cam.get_view_proj().into(),
-            encoder,
-        );*/
+        self.render
+            .draw_debug_lines(&mut encoder, targets, &self.line_buffer, device);
 
         encoder.finish()
     }
@@ -0,0 +1,132 @@
+//! Verlet chains of the antennas, swinging with the motion of the car.
+//!
+//! The chain root follows the slot of the car, and the rest of the points
+//! are pulled towards the straight pose along the car's up axis, so they
+//! lag behind when the car accelerates and sag under the gravity.
+
+use vangers::config::settings::Antenna as Config;
+
+use cgmath::prelude::*;
+
+/// Passes of the length constraints per update.
+const ITERATIONS: usize = 4;
+/// Root jumps longer than this many antenna lengths move the whole chain,
+/// e.g. on the teleports and the world wrapping.
+const JUMP_LENGTHS: f32 = 4.0;
+
+#[derive(Clone)]
+pub struct Chain {
+    points: Vec<cgmath::Vector3<f32>>,
+    previous: Vec<cgmath::Vector3<f32>>,
+}
+
+impl Chain {
+    pub fn new(root: cgmath::Vector3<f32>, up: cgmath::Vector3<f32>, config: &Config) -> Self {
+        let step = config.length / config.segments as f32;
+        let points = (0..=config.segments)
+            .map(|i| root + up * (step * i as f32))
+            .collect::<Vec<_>>();
+        Chain {
+            previous: points.clone(),
+            points,
+        }
+    }
+
+    /// Advance the chain by `dt` seconds, with the root moved to the new location.
+    pub fn update(
+        &mut self,
+        root: cgmath::Vector3<f32>,
+        up: cgmath::Vector3<f32>,
+        dt: f32,
+        config: &Config,
+    ) {
+        let jump = root - self.points[0];
+        if jump.magnitude() > config.length * JUMP_LENGTHS {
+            for p in self.points.iter_mut().chain(self.previous.iter_mut()) {
+                *p += jump;
+            }
+        }
+
+        let step = config.length / self.points.len().saturating_sub(1).max(1) as f32;
+        let keep = (1.0 - config.damping).powf(dt);
+        for i in 1..self.points.len() {
+            let rest = root + up * (step * i as f32);
+            let acc = (rest - self.points[i]) * config.stiffness
+                - cgmath::Vector3::unit_z() * config.gravity;
+            let velocity = (self.points[i] - self.previous[i]) * keep;
+            self.previous[i] = self.points[i];
+            self.points[i] += velocity + acc * (dt * dt);
+        }
+        self.points[0] = root;
+        self.previous[0] = root;
+
+        for _ in 0..ITERATIONS {
+            for i in 1..self.points.len() {
+                let delta = self.points[i] - self.points[i - 1];
+                let distance = delta.magnitude();
+                if distance < f32::EPSILON {
+                    continue;
+                }
+                let correction = delta * ((distance - step) / distance);
+                if i == 1 {
+                    self.points[i] -= correction;
+                } else {
+                    self.points[i - 1] += correction * 0.5;
+                    self.points[i] -= correction * 0.5;
+                }
+            }
+        }
+    }
+
+    pub fn segments(
+        &self,
+    ) -> impl Iterator<Item = (cgmath::Vector3<f32>, cgmath::Vector3<f32>)> + '_ {
+        self.points.windows(2).map(|pair| (pair[0], pair[1]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: Config = Config {
+        segments: 4,
+        length: 24.0,
+        stiffness: 60.0,
+        damping: 0.9,
+        gravity: 40.0,
+    };
+    const DT: f32 = 1.0 / 60.0;
+
+    #[test]
+    fn lags_behind() {
+        let up = cgmath::Vector3::unit_z();
+        let mut chain = Chain::new(cgmath::Vector3::zero(), up, &CONFIG);
+        for _ in 0..60 {
+            chain.update(cgmath::Vector3::zero(), up, DT, &CONFIG);
+        }
+        // standing up, only sagging a bit
+        let tip = chain.points[4];
+        assert!(tip.z > 0.8 * CONFIG.length && tip.x.abs() < 1e-3);
+
+        let mut root = cgmath::Vector3::zero();
+        for frame in 0..20 {
+            root.x += 0.5 * frame as f32;
+            chain.update(root, up, DT, &CONFIG);
+        }
+        assert!(chain.points[4].x < root.x);
+        for (a, b) in chain.segments() {
+            let length = (b - a).magnitude();
+            assert!((length - CONFIG.length / 4.0).abs() < 0.5, "{}", length);
+        }
+
+        // teleporting keeps the shape
+        let offset = cgmath::vec3(1000.0, 0.0, 0.0);
+        let mut moved = chain.clone();
+        chain.update(root, up, DT, &CONFIG);
+        moved.update(root + offset, up, DT, &CONFIG);
+        for (a, b) in chain.points.iter().zip(moved.points.iter()) {
+            assert!((b - a - offset).magnitude() < 1e-2);
+        }
+    }
+}
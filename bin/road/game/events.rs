@@ -0,0 +1,215 @@
+//! Gameplay events, for the systems reacting to what happens in the world,
+//! such as effects, sounds, scoring, or scripting.
+//!
+//! The game emits the events during `Game::update`, and delivers them
+//! at the end of it, so the subscribers don't need to be hard-wired there.
+
+use vangers::{render::Rect, space::coords::WorldPos};
+
+/// Something that happened in the game.
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// The vehicle hit a terrain wall.
+    Collision {
+        agent: usize,
+        position: cgmath::Vector3<f32>,
+        /// Approach speed against the wall.
+        speed: f32,
+    },
+    /// The vehicle got into the water after being dry.
+    Splash {
+        agent: usize,
+        position: cgmath::Vector3<f32>,
+        /// Speed along the vertical axis at the moment of entry.
+        vertical_speed: f32,
+    },
+    /// The vehicle got out of the water.
+    Emerge {
+        agent: usize,
+        position: cgmath::Vector3<f32>,
+    },
+    /// The vehicle reached an escave or a spot.
+    Checkpoint { agent: usize, name: String },
+    /// The game switched to another world.
+    WorldSwitch { name: String },
+}
+
+impl Event {
+    /// Where it happened, for the events that have a place in the world.
+    pub fn position(&self) -> Option<cgmath::Vector3<f32>> {
+        match *self {
+            Self::Collision { position, .. }
+            | Self::Splash { position, .. }
+            | Self::Emerge { position, .. } => Some(position),
+            Self::Checkpoint { .. } | Self::WorldSwitch { .. } => None,
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        match *self {
+            Self::Collision { .. } => Kind::Collision,
+            Self::Splash { .. } => Kind::Splash,
+            Self::Emerge { .. } => Kind::Emerge,
+            Self::Checkpoint { .. } => Kind::Checkpoint,
+            Self::WorldSwitch { .. } => Kind::WorldSwitch,
+        }
+    }
+}
+
+/// Type of an event, for the subscribers to filter on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Collision,
+    Splash,
+    Emerge,
+    Checkpoint,
+    WorldSwitch,
+}
+
+pub type Subscriber = Box<dyn FnMut(&Event)>;
+
+/// Queue of the events of a frame, delivered to the subscribers at once.
+#[derive(Default)]
+pub struct Bus {
+    pending: Vec<Event>,
+    /// Subscribers with the kinds of events they want,
+    /// and whether they only want the ones in the audible area.
+    subscribers: Vec<(Vec<Kind>, bool, Subscriber)>,
+    /// Texels where the positional events are heard, and the level size they wrap around.
+    audible: Option<(Rect, (i32, i32))>,
+}
+
+impl Bus {
+    /// Receive the events of the given kinds, or all of them if none are given.
+    pub fn subscribe(&mut self, kinds: &[Kind], subscriber: impl FnMut(&Event) + 'static) {
+        self.subscribers
+            .push((kinds.to_vec(), false, Box::new(subscriber)));
+    }
+
+    /// Like `subscribe`, but skipping the events outside of the audible area,
+    /// as the positional sounds do.
+    pub fn subscribe_positional(
+        &mut self,
+        kinds: &[Kind],
+        subscriber: impl FnMut(&Event) + 'static,
+    ) {
+        self.subscribers
+            .push((kinds.to_vec(), true, Box::new(subscriber)));
+    }
+
+    /// Set the area of the level where the positional events are heard,
+    /// typically the visible terrain.
+    pub fn set_audible(&mut self, rect: Rect, level_size: (i32, i32)) {
+        self.audible = Some((rect, level_size));
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        self.pending.push(event);
+    }
+
+    /// Deliver the pending events to the subscribers,
+    /// and hand them over to the caller.
+    pub fn dispatch(&mut self) -> std::vec::Drain<'_, Event> {
+        for event in self.pending.iter() {
+            let kind = event.kind();
+            let audible = match (event.position(), self.audible) {
+                (Some(pos), Some((rect, level_size))) => {
+                    rect.contains_wrapped(WorldPos(pos).texel().into(), level_size)
+                }
+                _ => true,
+            };
+            for &mut (ref kinds, positional, ref mut subscriber) in self.subscribers.iter_mut() {
+                if (kinds.is_empty() || kinds.contains(&kind)) && (audible || !positional) {
+                    subscriber(event);
+                }
+            }
+        }
+        self.pending.drain(..)
+    }
+}
+
+/// Subscriber that reports the events into the log.
+pub fn log(event: &Event) {
+    match *event {
+        Event::Collision { agent, speed, .. } => {
+            log::debug!("Agent {} hit a wall at speed {}", agent, speed)
+        }
+        Event::Splash {
+            agent,
+            vertical_speed,
+            ..
+        } => log::debug!("Agent {} splashed at speed {}", agent, vertical_speed),
+        Event::Emerge { agent, .. } => log::debug!("Agent {} emerged", agent),
+        Event::Checkpoint { agent, ref name } => log::info!("Agent {} reached {}", agent, name),
+        Event::WorldSwitch { ref name } => log::info!("Entered world {}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn dispatch() {
+        let mut bus = Bus::default();
+        let splashes = Rc::new(RefCell::new(0));
+        let counter = Rc::clone(&splashes);
+        bus.subscribe(&[Kind::Splash], move |event| {
+            assert_eq!(event.kind(), Kind::Splash);
+            *counter.borrow_mut() += 1;
+        });
+        let total = Rc::new(RefCell::new(0));
+        let counter = Rc::clone(&total);
+        bus.subscribe(&[], move |_| *counter.borrow_mut() += 1);
+
+        let position = cgmath::vec3(0.0, 0.0, 0.0);
+        bus.emit(Event::Splash {
+            agent: 0,
+            position,
+            vertical_speed: -5.0,
+        });
+        bus.emit(Event::Emerge { agent: 0, position });
+        bus.emit(Event::WorldSwitch {
+            name: "Fostral".to_string(),
+        });
+        assert_eq!(bus.dispatch().count(), 3);
+        assert_eq!(*splashes.borrow(), 1);
+        assert_eq!(*total.borrow(), 3);
+        assert_eq!(bus.dispatch().count(), 0);
+    }
+
+    #[test]
+    fn audible() {
+        let mut bus = Bus::default();
+        let heard = Rc::new(RefCell::new(0));
+        let counter = Rc::clone(&heard);
+        bus.subscribe_positional(&[], move |_| *counter.borrow_mut() += 1);
+        bus.set_audible(
+            Rect {
+                x: 1000,
+                y: 0,
+                w: 100,
+                h: 100,
+            },
+            (1024, 1024),
+        );
+
+        // across the seam of the level
+        bus.emit(Event::Emerge {
+            agent: 0,
+            position: cgmath::vec3(10.0, 10.0, 0.0),
+        });
+        bus.emit(Event::Emerge {
+            agent: 1,
+            position: cgmath::vec3(500.0, 10.0, 0.0),
+        });
+        // no position, so always heard
+        bus.emit(Event::Checkpoint {
+            agent: 0,
+            name: "Podish".to_string(),
+        });
+        assert_eq!(bus.dispatch().count(), 3);
+        assert_eq!(*heard.borrow(), 2);
+    }
+}
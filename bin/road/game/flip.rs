@@ -0,0 +1,142 @@
+//! Turning an overturned vehicle back onto its wheels.
+//!
+//! Instead of resetting the transform at once, the body is lifted and rotated
+//! upright over a short time, with the physics being out of control meanwhile.
+//! It happens on request, or automatically after the vehicle stays upside down.
+
+use crate::physics::math;
+use cgmath::prelude::*;
+use vangers::{config::settings, space};
+
+/// Vertical part of the body Z axis below which the vehicle is considered overturned.
+const UPRIGHT_THRESHOLD: f32 = 0.3;
+/// Speed below which the vehicle is considered to be still.
+const STILL_SPEED: f32 = 1.0;
+/// Peak of the lift in the middle of the rotation.
+const LIFT_HEIGHT: f32 = 10.0;
+
+pub fn is_overturned(rot: cgmath::Quaternion<f32>) -> bool {
+    (rot * cgmath::Vector3::unit_z()).z < UPRIGHT_THRESHOLD
+}
+
+/// Rotation standing on the wheels, keeping the heading of the given one.
+pub fn upright(rot: cgmath::Quaternion<f32>) -> cgmath::Quaternion<f32> {
+    let forward = rot * cgmath::Vector3::unit_y();
+    let heading = cgmath::Rad(math::atan2(forward.y, forward.x)) - cgmath::Rad::turn_div_4();
+    math::rotation(cgmath::Vector3::unit_z(), heading)
+}
+
+#[derive(Debug, PartialEq)]
+struct Motion {
+    from: space::Transform,
+    to: space::Transform,
+    /// From 0 to 1.
+    progress: f32,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct FlipAssist {
+    /// Time spent overturned and still.
+    still_time: f32,
+    motion: Option<Motion>,
+}
+
+impl FlipAssist {
+    pub fn is_active(&self) -> bool {
+        self.motion.is_some()
+    }
+
+    /// Start turning the vehicle over, to end up standing at the given height.
+    pub fn start(&mut self, transform: &space::Transform, height: f32) {
+        let mut to = *transform;
+        to.disp.z = height;
+        to.rot = upright(transform.rot);
+        // go the short way around
+        if transform.rot.dot(to.rot) < 0.0 {
+            to.rot = -to.rot;
+        }
+        self.motion = Some(Motion {
+            from: *transform,
+            to,
+            progress: 0.0,
+        });
+    }
+
+    /// Advance the assist, starting it if the vehicle stays overturned for long.
+    /// Returns true while the transform is controlled by the assist.
+    pub fn update(
+        &mut self,
+        dt: f32,
+        transform: &mut space::Transform,
+        speed: f32,
+        height: f32,
+        config: &settings::FlipAssist,
+    ) -> bool {
+        if self.motion.is_none() {
+            if config.delay > 0.0 && speed < STILL_SPEED && is_overturned(transform.rot) {
+                self.still_time += dt;
+                if self.still_time >= config.delay {
+                    self.start(transform, height);
+                    return true;
+                }
+            } else {
+                self.still_time = 0.0;
+            }
+        }
+
+        let motion = match self.motion {
+            Some(ref mut motion) => motion,
+            None => return false,
+        };
+        motion.progress = if config.duration > 0.0 {
+            (motion.progress + dt / config.duration).min(1.0)
+        } else {
+            1.0
+        };
+        let p = motion.progress;
+        let t = p * p * (3.0 - 2.0 * p);
+        transform.rot = motion.from.rot.nlerp(motion.to.rot, t);
+        transform.disp = motion.from.disp.lerp(motion.to.disp, t);
+        transform.disp.z += LIFT_HEIGHT * math::sin_cos(p * std::f32::consts::PI).0;
+
+        if p >= 1.0 {
+            *transform = motion.to;
+            self.motion = None;
+            self.still_time = 0.0;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flip_over() {
+        let config = settings::FlipAssist {
+            delay: 1.0,
+            duration: 0.5,
+        };
+        let mut transform = space::Transform {
+            disp: cgmath::vec3(10.0, 20.0, 5.0),
+            rot: cgmath::Quaternion::from_angle_y(cgmath::Deg(180.0)),
+            scale: 1.0,
+        };
+        assert!(is_overturned(transform.rot));
+
+        let mut assist = FlipAssist::default();
+        // moving cars are left alone
+        assert!(!assist.update(2.0, &mut transform, 10.0, 30.0, &config));
+        assert!(!assist.update(0.6, &mut transform, 0.0, 30.0, &config));
+        assert!(assist.update(0.6, &mut transform, 0.0, 30.0, &config));
+        assert!(assist.is_active());
+        assert!(assist.update(0.1, &mut transform, 0.0, 30.0, &config));
+        assert!(transform.disp.z > 5.0);
+
+        while assist.update(0.1, &mut transform, 0.0, 30.0, &config) {}
+        assert!(!assist.is_active());
+        assert!(!is_overturned(transform.rot));
+        assert_eq!(transform.disp, cgmath::vec3(10.0, 20.0, 30.0));
+    }
+}
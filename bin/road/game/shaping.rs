@@ -0,0 +1,113 @@
+//! Shaping of the raw control inputs, before they reach the physics.
+//!
+//! Keyboard keys only give the extreme values, so the shaped value can move towards
+//! the raw one at constant rates per second, independent of the frame rate.
+//! By default, it follows the raw value right away.
+//! Analog inputs, like the gamepad sticks, go through the dead zone and the
+//! response curve in addition.
+
+use crate::physics::math;
+use vangers::config::settings;
+
+fn curve(raw: f32, config: &settings::InputAxis) -> f32 {
+    let magnitude = raw.abs().min(1.0);
+    if magnitude <= config.dead_zone {
+        return 0.0;
+    }
+    let normalized = (magnitude - config.dead_zone) / (1.0 - config.dead_zone);
+    raw.signum() * math::powf(normalized, config.exponent) * config.sensitivity
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Axis {
+    value: f32,
+}
+
+impl Axis {
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Advance the shaped value towards the raw input over `dt` seconds.
+    pub fn update(&mut self, raw: f32, dt: f32, config: &settings::InputAxis) -> f32 {
+        let target = curve(raw, config);
+        // moving away from zero attacks, everything else releases
+        let rate = if target * self.value >= 0.0 && target.abs() > self.value.abs() {
+            config.attack
+        } else {
+            config.release
+        };
+        let step = if rate > 0.0 { rate * dt } else { f32::INFINITY };
+        self.value = if (target - self.value).abs() <= step {
+            target
+        } else {
+            self.value + step * (target - self.value).signum()
+        };
+        self.value
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Shaper {
+    pub rudder: Axis,
+    pub motor: Axis,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_through() {
+        let config = settings::InputShaping::default().rudder;
+        let mut axis = Axis::default();
+        assert_eq!(axis.update(1.0, 0.01, &config), 1.0);
+        assert_eq!(axis.update(0.05, 0.01, &config), 0.05);
+        assert_eq!(axis.update(-0.5, 0.01, &config), -0.5);
+    }
+
+    #[test]
+    fn frame_rate_independent() {
+        let config = settings::InputAxis {
+            attack: 6.0,
+            release: 10.0,
+            dead_zone: 0.1,
+            ..Default::default()
+        };
+        let run = |fps: u32| {
+            let mut axis = Axis::default();
+            let dt = 1.0 / fps as f32;
+            let mut history = Vec::new();
+            for _ in 0..fps / 10 {
+                axis.update(1.0, dt, &config);
+            }
+            history.push(axis.value());
+            for _ in 0..fps / 20 {
+                axis.update(0.0, dt, &config);
+            }
+            history.push(axis.value());
+            history
+        };
+        let (slow, fast) = (run(20), run(240));
+        for (a, b) in slow.iter().zip(fast.iter()) {
+            assert!((a - b).abs() < 1e-4, "{:?} vs {:?}", slow, fast);
+        }
+        assert!((slow[0] - config.attack * 0.1).abs() < 1e-4);
+        assert!((slow[1] - (config.attack * 0.1 - config.release * 0.05)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn analog_curve() {
+        let config = settings::InputAxis {
+            attack: 100.0,
+            release: 100.0,
+            dead_zone: 0.2,
+            exponent: 2.0,
+            sensitivity: 0.5,
+        };
+        let mut axis = Axis::default();
+        assert_eq!(axis.update(0.1, 1.0, &config), 0.0);
+        assert_eq!(axis.update(-1.0, 1.0, &config), -0.5);
+        assert!((axis.update(0.6, 1.0, &config) - 0.125).abs() < 1e-6);
+    }
+}
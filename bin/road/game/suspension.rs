@@ -0,0 +1,126 @@
+//! Gizmos of the wheel suspension, and the live tuning of its constants.
+//!
+//! Each wheel gets a line from the ground contact to its center, going from
+//! green to red as the ground gets deeper into the wheel, a cross at the contact,
+//! and a line of its sideways slip. The panel changes the spring and elasticity
+//! constants of `common.prm` in place, for all the cars, until the game is restarted.
+
+use crate::physics::WheelState;
+use vangers::{
+    config::common::Common,
+    locale::{tr, tr_fmt},
+    render::debug::LineBuffer,
+};
+
+/// Length of the slip line per unit of the sideways speed.
+const SLIP_SCALE: f32 = 2.0;
+/// Half size of the cross at the contact point.
+const CROSS_SIZE: f32 = 1.0;
+const SLIP_COLOR: u32 = 0x00FFFFFF;
+const CONTACT_COLOR: u32 = 0xFFFFFFFF;
+
+/// Original constants, which the tuning can go back to.
+pub struct Tuning {
+    original: Common,
+}
+
+/// Color of the suspension line, from green at rest to red when fully compressed.
+fn compression_color(compression: f32) -> u32 {
+    let red = (compression.clamp(0.0, 1.0) * 255.0) as u32;
+    (red << 24) | ((255 - red) << 16) | 0xFF
+}
+
+pub fn add_gizmos(wheels: &[WheelState], lines: &mut LineBuffer) {
+    for wheel in wheels {
+        let center = wheel.position;
+        if let Some(contact) = wheel.contact {
+            let color = compression_color(wheel.compression);
+            lines.add_overlay(contact.into(), center.into(), color);
+            for offset in [
+                cgmath::vec3(CROSS_SIZE, 0.0, 0.0),
+                cgmath::vec3(0.0, CROSS_SIZE, 0.0),
+            ] {
+                lines.add_overlay(
+                    (contact - offset).into(),
+                    (contact + offset).into(),
+                    CONTACT_COLOR,
+                );
+            }
+        }
+        let slip_end = center + wheel.slip * SLIP_SCALE;
+        lines.add_overlay(center.into(), slip_end.into(), SLIP_COLOR);
+    }
+}
+
+fn tweak(ui: &mut egui::Ui, value: &mut f32, name: &str) {
+    ui.horizontal(|ui| {
+        let speed = 0.01 * value.abs().max(0.01);
+        ui.add(egui::DragValue::new(value).speed(speed));
+        ui.label(name);
+    });
+}
+
+impl Tuning {
+    pub fn new(common: &Common) -> Self {
+        Tuning { original: *common }
+    }
+
+    /// Show the state of the wheels, and the constants to change.
+    pub fn draw_ui(&self, ui: &mut egui::Ui, wheels: &[WheelState], common: &mut Common) {
+        for (i, wheel) in wheels.iter().enumerate() {
+            ui.label(tr_fmt(
+                "Wheel {}: {}% compressed, {} slip",
+                &[
+                    &i,
+                    &format!("{:.0}", wheel.compression * 100.0),
+                    &format!("{:.1}", wheel.slip.x.hypot(wheel.slip.y)),
+                ],
+            ));
+        }
+        tweak(ui, &mut common.contact.k_elastic_spring, "k_elastic_spring");
+        tweak(
+            ui,
+            &mut common.impulse.elastic_restriction,
+            "elastic_restriction",
+        );
+        tweak(ui, &mut common.impulse.factors[0], "impulse_factor (wall)");
+        tweak(
+            ui,
+            &mut common.impulse.factors[1],
+            "impulse_factor (ground)",
+        );
+        tweak(ui, &mut common.impulse.k_friction, "k_friction_impulse");
+        tweak(ui, &mut common.impulse.k_wheel, "k_wheel");
+        tweak(ui, &mut common.drag.spring.v, "drag_spring (v)");
+        tweak(ui, &mut common.drag.spring.w, "drag_spring (w)");
+        if ui.button(tr("Reset")).clicked() {
+            common.contact.k_elastic_spring = self.original.contact.k_elastic_spring;
+            common.impulse = self.original.impulse;
+            common.drag.spring = self.original.drag.spring;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gizmo_colors() {
+        assert_eq!(compression_color(0.0), 0x00FF00FF);
+        assert_eq!(compression_color(1.0), 0xFF0000FF);
+        assert_eq!(compression_color(2.0), 0xFF0000FF);
+
+        let mut lines = LineBuffer::new();
+        add_gizmos(&[], &mut lines);
+        assert!(lines.is_empty());
+        let wheel = WheelState {
+            position: cgmath::Point3::new(0.0, 0.0, 5.0),
+            contact: None,
+            compression: 0.0,
+            slip: cgmath::vec3(1.0, 0.0, 0.0),
+        };
+        add_gizmos(&[wheel], &mut lines);
+        assert!(!lines.is_empty());
+    }
+}
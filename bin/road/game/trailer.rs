@@ -0,0 +1,103 @@
+//! Second body towed behind a car, held by a joint at the hitch.
+//!
+//! The trailer goes through the same physics step as the cars, only without
+//! the engine and the controls, and the joint then corrects the velocities of both.
+
+use crate::physics;
+use vangers::{config, level, space};
+
+use cgmath::prelude::*;
+
+pub struct Trailer {
+    pub car: config::car::CarInfo,
+    pub transform: space::Transform,
+    dynamo: physics::Dynamo,
+    joint: config::settings::Joint,
+    gap: f32,
+    /// Hitch point in the model space of the towing car.
+    hitch: cgmath::Point3<f32>,
+    /// Hitch point in the model space of the trailer.
+    anchor: cgmath::Point3<f32>,
+}
+
+impl Trailer {
+    pub fn new(
+        car: config::car::CarInfo,
+        config: &config::settings::Trailer,
+        tractor: &config::car::CarInfo,
+        tractor_transform: &space::Transform,
+    ) -> Self {
+        let mut trailer = Trailer {
+            transform: space::Transform {
+                disp: tractor_transform.disp,
+                rot: tractor_transform.rot,
+                scale: car.scale,
+            },
+            anchor: cgmath::Point3::new(0.0, car.model.body.bbox.max[1], 0.0),
+            car,
+            dynamo: physics::Dynamo::default(),
+            joint: config.joint,
+            gap: config.gap,
+            hitch: cgmath::Point3::origin(),
+        };
+        trailer.attach(tractor, tractor_transform);
+        trailer
+    }
+
+    /// Hook onto the rear of the car, placing the trailer behind it.
+    pub fn attach(&mut self, tractor: &config::car::CarInfo, tractor_transform: &space::Transform) {
+        let bbox = &tractor.model.body.bbox;
+        self.hitch = cgmath::Point3::new(0.0, bbox.min[1] - self.gap / tractor.scale, 0.0);
+        self.place(tractor_transform);
+    }
+
+    /// Put the trailer straight behind the car, at rest.
+    pub fn place(&mut self, tractor_transform: &space::Transform) {
+        let hitch = tractor_transform.transform_point(self.hitch);
+        self.transform.rot = tractor_transform.rot;
+        self.transform.disp =
+            hitch.to_vec() - self.transform.rot * (self.anchor.to_vec() * self.transform.scale);
+        self.dynamo = physics::Dynamo::default();
+    }
+
+    /// Simulate the trailer on its own, then hold it together with the car.
+    pub fn step(
+        &mut self,
+        dt: f32,
+        level: &level::Level,
+        common: &config::common::Common,
+        tractor: &config::car::CarInfo,
+        tractor_transform: &space::Transform,
+        tractor_dynamo: &mut physics::Dynamo,
+    ) -> physics::Outcome {
+        let outcome = physics::step(
+            &mut self.dynamo,
+            &mut self.transform,
+            dt,
+            &self.car,
+            level,
+            true,
+            common,
+            0.0,
+            None,
+            0.0,
+            physics::StepDebug::default(),
+        );
+
+        let density = common.nature.density;
+        let mut a = physics::JointBody {
+            transform: tractor_transform,
+            dynamo: tractor_dynamo,
+            mass: physics::Mass::new(&tractor.model.body.physics, density, tractor.scale),
+            anchor: self.hitch,
+        };
+        let mut b = physics::JointBody {
+            transform: &self.transform,
+            dynamo: &mut self.dynamo,
+            mass: physics::Mass::new(&self.car.model.body.physics, density, self.car.scale),
+            anchor: self.anchor,
+        };
+        physics::solve_joint(self.joint, &mut a, &mut b, dt);
+        outcome
+    }
+}
@@ -1,24 +1,57 @@
 #![allow(irrefutable_let_patterns)]
 
-use log::info;
-
 #[path = "../boilerplate.rs"]
 mod boilerplate;
+mod browser;
 mod game;
+mod matrix;
 mod physics;
+mod profile;
+mod props;
+mod smoke;
+mod trace;
+mod world;
 
 fn main() {
     use std::env;
 
-    let (harness, settings) =
-        boilerplate::Harness::init(boilerplate::HarnessOptions { title: "road" });
-
-    info!("Parsing command line");
     let args: Vec<_> = env::args().collect();
     let mut options = getopts::Options::new();
     options
         .parsing_style(getopts::ParsingStyle::StopAtFirstFree)
-        .optflag("h", "help", "print this help menu");
+        .optflag("h", "help", "print this help menu")
+        .optopt("", "record", "record a physics trace of the player", "FILE")
+        .optopt(
+            "",
+            "generate",
+            "play on a procedurally generated level with the seed",
+            "SEED",
+        )
+        .optopt(
+            "",
+            "compare",
+            "replay a physics trace and report the divergence",
+            "FILE",
+        )
+        .optopt(
+            "",
+            "smoke",
+            "drive over the test level for the number of frames without a window, and exit",
+            "FRAMES",
+        )
+        .optflag(
+            "",
+            "browse",
+            "pick the world and the spawn point on a start screen",
+        )
+        .optopt(
+            "",
+            "render-matrix",
+            "render the test level with each terrain method, with and without shadows, into PNGs",
+            "DIR",
+        );
+
+    boilerplate::add_options(&mut options);
 
     let matches = options.parse(&args[1..]).unwrap();
     if matches.opt_present("h") || !matches.free.is_empty() {
@@ -28,7 +61,47 @@ fn main() {
         return;
     }
 
-    let game = game::Game::new(&settings, &harness.graphics_ctx);
+    if boilerplate::list_adapters(&matches) {
+        return;
+    }
 
-    harness.main_loop(game);
+    let mut options = boilerplate::HarnessOptions::new("road", &matches);
+    if let Some(seed) = matches.opt_str("generate") {
+        let seed = seed
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid level seed '{}': {}", seed, e));
+        options.settings_overrides.push(format!(
+            "game.level={:?}",
+            vangers::level::Generator::level_name(seed)
+        ));
+    }
+    if let Some(frames) = matches.opt_str("smoke") {
+        let frames = frames
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid number of frames '{}': {}", frames, e));
+        let passed = smoke::run(options, frames);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    if let Some(dir) = matches.opt_str("render-matrix") {
+        let saved = matrix::run(options, std::path::Path::new(&dir));
+        std::process::exit(if saved != 0 { 0 } else { 1 });
+    }
+
+    let (harness, settings) = boilerplate::Harness::init(options);
+
+    let mut trace = if let Some(path) = matches.opt_str("record") {
+        Some(trace::Command::Record(path.into()))
+    } else {
+        matches
+            .opt_str("compare")
+            .map(|path| trace::Command::Compare(path.into()))
+    };
+
+    // a trace is only followed by the first game, not the one re-created on device loss
+    if matches.opt_present("browse") {
+        harness.main_loop(move |gfx| browser::Screen::new(&settings, gfx, trace.take()));
+    } else {
+        harness.main_loop(move |gfx| game::Game::new(&settings, gfx, trace.take()));
+    }
 }
@@ -0,0 +1,164 @@
+//! Screenshots of the same scene under the different render settings, see `--render-matrix`.
+//!
+//! Each terrain method is rendered with and without the shadows, on a fresh
+//! device without a window, after the same number of frames from the start,
+//! so the camera and the cars end up in the same place. Every image is saved
+//! as a PNG named after its settings, and all of them are put together into
+//! a sheet, with a row per terrain method and the shadowed image first.
+//! The methods the adapter can't run are skipped, leaving a gap in the sheet.
+
+use crate::{
+    boilerplate::{self, Application as _, HarnessOptions},
+    game::Game,
+    smoke,
+};
+use vangers::{config, render::ScreenTargets};
+
+use std::{fs::File, path::Path};
+
+/// Frames to run before taking the screenshot, letting the terrain settle.
+const WARMUP_FRAMES: u32 = 10;
+/// Terrain methods to compare, with the parameters of the settings template.
+const TERRAINS: [(&str, &str); 6] = [
+    ("raytraced", "RayTraced"),
+    (
+        "voxel",
+        "RayVoxelTraced(voxel_size: (2, 4, 1), max_outer_steps: 40, max_inner_steps: 40, max_update_texels: 1000000)",
+    ),
+    ("sliced", "Sliced"),
+    ("painted", "Painted"),
+    ("scattered", "Scattered(density: (2, 2, 2))"),
+    (
+        "streamed",
+        "Streamed(tile_size: 256, margin: 256, max_tiles: 64)",
+    ),
+];
+const SHADOWS: [(&str, bool); 2] = [("shadow", true), ("noshadow", false)];
+
+/// Render the image with the settings, returning the RGBA pixels,
+/// or `None` if the adapter doesn't support them.
+fn render(settings: config::Settings) -> Option<Vec<u8>> {
+    let adapter = smoke::find_adapter(&settings);
+    let required = settings.render.terrain.required_downlevel_flags();
+    let limits = settings
+        .render
+        .get_device_limits(&adapter.limits(), settings.game.geometry.height);
+    if !adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(required)
+        || !limits.check_limits(&adapter.limits())
+    {
+        return None;
+    }
+    let (gfx, settings) = smoke::init_headless(&adapter, settings);
+    let size = gfx.screen_size;
+    let color_texture = gfx.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Matrix color"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: smoke::COLOR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let color = color_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth = boilerplate::create_depth_target(&gfx.device, size);
+
+    let mut game = Game::new(&settings, &gfx, None);
+    for _ in 0..WARMUP_FRAMES {
+        game.update(&gfx.device, &gfx.queue, smoke::FRAME_TIME);
+        let targets = ScreenTargets {
+            extent: size,
+            color: &color,
+            depth: &depth,
+        };
+        let command_buffer = game.draw(&gfx.device, targets);
+        gfx.queue.submit(Some(command_buffer));
+        gfx.device.poll(wgpu::Maintain::Wait);
+    }
+
+    Some(smoke::read_back(&gfx, &color_texture))
+}
+
+pub fn save_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(File::create(path)?, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.write_header()?.write_image_data(pixels)
+}
+
+/// Render all the combinations of the settings into the directory.
+/// Returns the number of the images saved.
+pub fn run(options: HarnessOptions, dir: &Path) -> usize {
+    // the user overrides come last, so they can pick another level
+    let defaults = [
+        "game.level=\"test:hills\"",
+        "game.cycle=\"\"",
+        "game.seed=1",
+        "profile_path=\"\"",
+    ];
+    let base = defaults
+        .iter()
+        .map(|s| s.to_string())
+        .chain(options.settings_overrides.iter().cloned())
+        .collect::<Vec<_>>();
+    let settings = boilerplate::load_settings(&HarnessOptions {
+        settings_overrides: base.clone(),
+        ..options
+    });
+    let (width, height) = (settings.window.size[0], settings.window.size[1]);
+    std::fs::create_dir_all(dir).expect("Unable to create the output directory");
+
+    let row_bytes = (width * 4) as usize;
+    let mut sheet = vec![0u8; row_bytes * SHADOWS.len() * height as usize * TERRAINS.len()];
+    let mut saved = 0;
+    for (row, &(terrain_name, terrain)) in TERRAINS.iter().enumerate() {
+        for (column, &(shadow_name, shadow)) in SHADOWS.iter().enumerate() {
+            let label = format!("{}-{}", terrain_name, shadow_name);
+            let mut overrides = base.clone();
+            overrides.push(format!("render.terrain={}", terrain));
+            if !shadow {
+                overrides.push("render.light.shadow.size=0".to_string());
+            }
+            let settings = config::Settings::load("config/settings.ron", options.title, &overrides);
+            let pixels = match render(settings) {
+                Some(pixels) => pixels,
+                None => {
+                    log::warn!("Skipping {}, the adapter doesn't support it", label);
+                    continue;
+                }
+            };
+            let path = dir.join(label).with_extension("png");
+            match save_png(&path, width, height, &pixels) {
+                Ok(()) => {
+                    log::info!("Saved {}", path.display());
+                    saved += 1;
+                }
+                Err(e) => log::error!("Unable to save {}: {}", path.display(), e),
+            }
+            for (y, line) in pixels.chunks(row_bytes).enumerate() {
+                let sheet_row = row * height as usize + y;
+                let start = (sheet_row * SHADOWS.len() + column) * row_bytes;
+                sheet[start..start + row_bytes].copy_from_slice(line);
+            }
+        }
+    }
+
+    let path = dir.join("sheet.png");
+    let sheet_size = (width * SHADOWS.len() as u32, height * TERRAINS.len() as u32);
+    match save_png(&path, sheet_size.0, sheet_size.1, &sheet) {
+        Ok(()) => log::info!(
+            "Saved the sheet of {} images into {}",
+            saved,
+            path.display()
+        ),
+        Err(e) => log::error!("Unable to save {}: {}", path.display(), e),
+    }
+    saved
+}
@@ -0,0 +1,39 @@
+//! Shared early-out of the terrain collisions.
+//!
+//! A collision polygon that is entirely above the highest texel under it
+//! can't touch the ground, so its samples don't need to be checked against
+//! the level at all. The bound comes from `Level::query_minmax`.
+
+use vangers::level;
+
+/// Margin added to the heights, to stay conservative with the float rounding.
+const HEIGHT_MARGIN: f32 = 1.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Counters {
+    /// Collision polygons checked against the level.
+    pub tested: u64,
+    /// Collision polygons skipped by the broadphase.
+    pub skipped: u64,
+}
+
+impl std::ops::AddAssign for Counters {
+    fn add_assign(&mut self, other: Self) {
+        self.tested += other.tested;
+        self.skipped += other.skipped;
+    }
+}
+
+/// Check if all the points within `reach` around the center are above the terrain.
+pub fn is_above(level: &level::Level, center: cgmath::Vector3<f32>, reach: f32) -> bool {
+    // the samples are truncated to the texels, so cover both the floor and the ceiling
+    let min = (
+        (center.x - reach).floor() as i32,
+        (center.y - reach).floor() as i32,
+    );
+    let max = (
+        (center.x + reach).ceil() as i32 + 1,
+        (center.y + reach).ceil() as i32 + 1,
+    );
+    center.z - reach > level.query_minmax(min, max).end + HEIGHT_MARGIN
+}
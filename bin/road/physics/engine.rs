@@ -0,0 +1,302 @@
+//! Engine of a mechos: turns the motor controls into wheel traction.
+
+use vangers::config::common::{Common, Gearbox, Traction};
+
+pub const MAX_TRACTION: Traction = 4.0;
+
+/// Engine parameters, extracted from "common.prm",
+/// and the gearbox on top of them, see `Gearbox`.
+#[derive(Clone, Copy, Debug)]
+pub struct Params {
+    pub traction_incr: Traction,
+    pub traction_decr: Traction,
+    pub k_turbo: f32,
+    pub gearbox: Gearbox,
+}
+
+impl Params {
+    pub fn new(common: &Common) -> Self {
+        Params {
+            traction_incr: common.car.traction_incr,
+            traction_decr: common.car.traction_decr,
+            k_turbo: common.global.k_traction_turbo,
+            gearbox: common.car.gearbox,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Input {
+    /// Motor control, positive is forward.
+    pub motor: f32,
+    pub brake: bool,
+    pub turbo: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gear {
+    Neutral,
+    Forward(u8),
+    Reverse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Turbo {
+    Ready,
+    /// Remaining time is infinite if the duration is not limited.
+    Active {
+        time_left: f32,
+    },
+    Cooldown {
+        time_left: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Engine {
+    pub traction: Traction,
+    pub turbo: Turbo,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine {
+            traction: 0.0,
+            turbo: Turbo::Ready,
+        }
+    }
+}
+
+impl Engine {
+    pub fn gear(&self, gearbox: &Gearbox) -> Gear {
+        if self.traction > 0.0 {
+            let index = gearbox
+                .gears
+                .iter()
+                .position(|&(share, _)| self.traction < share * MAX_TRACTION)
+                .unwrap_or(gearbox.gears.len() - 1);
+            Gear::Forward(index as u8 + 1)
+        } else if self.traction < 0.0 {
+            Gear::Reverse
+        } else {
+            Gear::Neutral
+        }
+    }
+
+    /// Multiplier of the traction force.
+    pub fn turbo_factor(&self, params: &Params) -> f32 {
+        match self.turbo {
+            Turbo::Active { .. } => params.k_turbo,
+            _ => 1.0,
+        }
+    }
+
+    /// Apply the controls over a period of time.
+    pub fn update(&mut self, input: &Input, dt: f32, params: &Params) {
+        let gearbox = &params.gearbox;
+        let after_turbo = if gearbox.turbo_cooldown > 0.0 {
+            Turbo::Cooldown {
+                time_left: gearbox.turbo_cooldown,
+            }
+        } else {
+            Turbo::Ready
+        };
+        self.turbo = match self.turbo {
+            Turbo::Ready if input.turbo => Turbo::Active {
+                time_left: if gearbox.turbo_duration > 0.0 {
+                    gearbox.turbo_duration
+                } else {
+                    f32::INFINITY
+                },
+            },
+            Turbo::Active { time_left } if input.turbo && time_left > dt => Turbo::Active {
+                time_left: time_left - dt,
+            },
+            Turbo::Active { .. } => after_turbo,
+            Turbo::Cooldown { time_left } if time_left > dt => Turbo::Cooldown {
+                time_left: time_left - dt,
+            },
+            Turbo::Cooldown { .. } => Turbo::Ready,
+            Turbo::Ready => Turbo::Ready,
+        };
+
+        if input.motor != 0.0 {
+            let delta = input.motor * dt * params.traction_incr;
+            if self.traction * input.motor < 0.0 && gearbox.reversal_brake > 0.0 {
+                // reversing: brake down to neutral first
+                self.slow_down(gearbox.reversal_brake * delta.abs());
+            } else {
+                let factor = match self.gear(gearbox) {
+                    Gear::Forward(index) if input.motor > 0.0 => {
+                        gearbox.gears[index as usize - 1].1
+                    }
+                    Gear::Neutral if input.motor > 0.0 => gearbox.gears[0].1,
+                    _ => 1.0,
+                };
+                self.traction = (self.traction + factor * delta)
+                    .clamp(-gearbox.reverse_share * MAX_TRACTION, MAX_TRACTION);
+            }
+        }
+        if input.brake && self.traction != 0.0 {
+            self.traction *= super::math::exp2(-dt);
+        }
+    }
+
+    /// Natural traction loss, applied after every physics step.
+    pub fn idle(&mut self, dt: f32, params: &Params) {
+        self.slow_down(dt * params.traction_decr);
+    }
+
+    fn slow_down(&mut self, delta: Traction) {
+        let old = self.traction;
+        self.traction -= delta * old.signum();
+        if old * self.traction < 0.0 {
+            self.traction = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Representative parameters, with time measured in frames.
+    const PARAMS: Params = Params {
+        traction_incr: 12.0 / 64.0,
+        traction_decr: 4.0 / 64.0,
+        k_turbo: 2.0,
+        gearbox: Gearbox {
+            gears: [(0.3, 1.0), (0.7, 0.6), (1.0, 0.35)],
+            reverse_share: 0.5,
+            reversal_brake: 4.0,
+            turbo_duration: 2.0,
+            turbo_cooldown: 3.0,
+        },
+    };
+    const FRAME: f32 = 1.0;
+
+    fn run(engine: &mut Engine, input: Input, frames: usize) {
+        for _ in 0..frames {
+            engine.update(&input, FRAME, &PARAMS);
+            engine.idle(FRAME, &PARAMS);
+        }
+    }
+
+    #[test]
+    fn gears_shift_up() {
+        let gearbox = &PARAMS.gearbox;
+        let mut engine = Engine::default();
+        let input = Input {
+            motor: 1.0,
+            ..Input::default()
+        };
+        let mut gears = vec![engine.gear(gearbox)];
+        let mut last_traction = 0.0;
+        let mut last_growth = f32::INFINITY;
+        for _ in 0..200 {
+            run(&mut engine, input, 1);
+            // acceleration never grows with the gear
+            let growth = engine.traction - last_traction;
+            assert!(growth >= 0.0 && growth <= last_growth + 1e-6);
+            last_traction = engine.traction;
+            last_growth = growth;
+            if gears.last() != Some(&engine.gear(gearbox)) {
+                gears.push(engine.gear(gearbox));
+            }
+        }
+        assert_eq!(
+            gears,
+            [
+                Gear::Neutral,
+                Gear::Forward(1),
+                Gear::Forward(2),
+                Gear::Forward(3)
+            ]
+        );
+        assert!(engine.traction <= MAX_TRACTION);
+    }
+
+    #[test]
+    fn reverse_brakes_first() {
+        let gearbox = &PARAMS.gearbox;
+        let mut engine = Engine {
+            traction: MAX_TRACTION,
+            turbo: Turbo::Ready,
+        };
+        let input = Input {
+            motor: -1.0,
+            ..Input::default()
+        };
+        run(&mut engine, input, 1);
+        assert_eq!(engine.gear(gearbox), Gear::Forward(3));
+        run(&mut engine, input, 200);
+        assert_eq!(engine.gear(gearbox), Gear::Reverse);
+        assert!(engine.traction >= -gearbox.reverse_share * MAX_TRACTION);
+    }
+
+    #[test]
+    fn turbo_cooldown() {
+        let gearbox = &PARAMS.gearbox;
+        let mut engine = Engine::default();
+        let input = Input {
+            turbo: true,
+            ..Input::default()
+        };
+        run(&mut engine, input, 1);
+        assert_eq!(engine.turbo_factor(&PARAMS), PARAMS.k_turbo);
+        run(&mut engine, input, gearbox.turbo_duration as usize);
+        assert!(matches!(engine.turbo, Turbo::Cooldown { .. }));
+        assert_eq!(engine.turbo_factor(&PARAMS), 1.0);
+        run(
+            &mut engine,
+            Input::default(),
+            gearbox.turbo_cooldown as usize,
+        );
+        assert_eq!(engine.turbo, Turbo::Ready);
+    }
+
+    /// With the default gearbox, the engine follows the ramp of "common.prm" exactly,
+    /// over the controls and the time steps of the physics benchmark.
+    #[test]
+    fn default_follows_common() {
+        use vangers::config::{common::MAIN_LOOP_TIME, fallback};
+
+        let common = fallback::common();
+        let params = Params::new(&common);
+        let fps = common.speed.standard_frame_rate as f32;
+        let input_factor = 1.0 / fps / MAIN_LOOP_TIME;
+        let physics_dt = common.nature.time_delta0 * common.nature.num_calls_analysis as f32;
+
+        let mut engine = Engine::default();
+        let mut traction: Traction = 0.0;
+        for frame in 0..600 {
+            let input = Input {
+                motor: [1.0, 0.5, -1.0, 0.0][frame / 60 % 4],
+                brake: frame % 150 > 140,
+                turbo: frame % 100 > 70,
+            };
+            engine.update(&input, input_factor, &params);
+            engine.idle(physics_dt, &params);
+
+            // the ramp of the original, as it was before the gearbox
+            traction = (traction + input.motor * input_factor * common.car.traction_incr)
+                .clamp(-MAX_TRACTION, MAX_TRACTION);
+            if input.brake && traction != 0.0 {
+                traction *= super::super::math::exp2(-input_factor);
+            }
+            let old = traction;
+            traction -= physics_dt * common.car.traction_decr * old.signum();
+            if old * traction < 0.0 {
+                traction = 0.0;
+            }
+
+            assert_eq!(engine.traction, traction, "frame {}", frame);
+            let turbo = if input.turbo {
+                common.global.k_traction_turbo
+            } else {
+                1.0
+            };
+            assert_eq!(engine.turbo_factor(&params), turbo, "frame {}", frame);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Constraint between two bodies, solved with sequential impulses
+//! applied to the velocities after each of the bodies was stepped on its own.
+
+use super::Dynamo;
+use vangers::{config::settings::Joint as Kind, space};
+
+use cgmath::prelude::*;
+
+/// Number of passes over the constraint per step.
+const ITERATIONS: usize = 4;
+/// Fraction of the position error corrected per step.
+const BIAS_FACTOR: f32 = 0.2;
+
+/// Mass properties of a body, in its local space.
+#[derive(Clone, Copy, Debug)]
+pub struct Mass {
+    pub inv_mass: f32,
+    pub inv_inertia: cgmath::Matrix3<f32>,
+}
+
+impl Mass {
+    /// Derive the mass properties the same way the step does,
+    /// with the mass following the density and the inertia following the shape.
+    pub fn new(physics: &m3d::Physics, density: f32, scale: f32) -> Self {
+        let mass = density * physics.volume * scale * scale;
+        let jacobian = cgmath::Matrix3::from(physics.jacobi) * (scale * scale / physics.volume);
+        Mass {
+            inv_mass: 1.0 / mass,
+            inv_inertia: jacobian.invert().unwrap() / mass,
+        }
+    }
+}
+
+pub struct Body<'a> {
+    pub transform: &'a space::Transform,
+    pub dynamo: &'a mut Dynamo,
+    pub mass: Mass,
+    /// Attachment point in the model space of the body.
+    pub anchor: cgmath::Point3<f32>,
+}
+
+impl Body<'_> {
+    /// Offset from the center to the anchor, in the local space.
+    fn arm(&self) -> cgmath::Vector3<f32> {
+        self.anchor.to_vec() * self.transform.scale
+    }
+
+    fn world_anchor(&self) -> cgmath::Vector3<f32> {
+        self.transform.transform_point(self.anchor).to_vec()
+    }
+
+    fn world_velocity(&self) -> cgmath::Vector3<f32> {
+        let d = &self.dynamo;
+        self.transform.rot * (d.linear_velocity + d.angular_velocity.cross(self.arm()))
+    }
+
+    fn world_angular_velocity(&self) -> cgmath::Vector3<f32> {
+        self.transform.rot * self.dynamo.angular_velocity
+    }
+
+    fn world_inv_inertia(&self) -> cgmath::Matrix3<f32> {
+        let rot = cgmath::Matrix3::from(self.transform.rot);
+        rot * self.mass.inv_inertia * rot.transpose()
+    }
+
+    /// Apply a linear impulse at the anchor, given in the world space.
+    fn apply_impulse(&mut self, impulse: cgmath::Vector3<f32>) {
+        let local = self.transform.rot.invert() * impulse;
+        let arm = self.arm();
+        self.dynamo.linear_velocity += local * self.mass.inv_mass;
+        self.dynamo.angular_velocity += self.mass.inv_inertia * arm.cross(local);
+    }
+
+    /// Apply an angular impulse, given in the world space.
+    fn apply_angular_impulse(&mut self, impulse: cgmath::Vector3<f32>) {
+        let local = self.transform.rot.invert() * impulse;
+        self.dynamo.angular_velocity += self.mass.inv_inertia * local;
+    }
+}
+
+fn skew(v: cgmath::Vector3<f32>) -> cgmath::Matrix3<f32> {
+    // columns of the cross product matrix
+    cgmath::Matrix3::new(0.0, v.z, -v.y, -v.z, 0.0, v.x, v.y, -v.x, 0.0)
+}
+
+/// Push the velocities of the bodies towards keeping the anchors together,
+/// and, for a hinge, the vertical axes aligned.
+pub fn solve(kind: Kind, a: &mut Body, b: &mut Body, dt: f32) {
+    let (ra, rb) = (
+        a.world_anchor() - a.transform.disp,
+        b.world_anchor() - b.transform.disp,
+    );
+    let (ia, ib) = (a.world_inv_inertia(), b.world_inv_inertia());
+    // effective mass of the point constraint
+    let (sa, sb) = (skew(ra), skew(rb));
+    let k = cgmath::Matrix3::identity() * (a.mass.inv_mass + b.mass.inv_mass)
+        - sa * ia * sa
+        - sb * ib * sb;
+    let k_inv = match k.invert() {
+        Some(k_inv) => k_inv,
+        None => return,
+    };
+    let bias = (b.world_anchor() - a.world_anchor()) * (BIAS_FACTOR / dt);
+
+    let hinge = match kind {
+        Kind::Ball => None,
+        Kind::Hinge => {
+            let axis_a = a.transform.rot * cgmath::Vector3::unit_z();
+            let axis_b = b.transform.rot * cgmath::Vector3::unit_z();
+            let t1 = axis_a.cross(cgmath::Vector3::unit_x());
+            let t1 = if t1.magnitude2() > 1e-6 {
+                t1.normalize()
+            } else {
+                axis_a.cross(cgmath::Vector3::unit_y()).normalize()
+            };
+            let t2 = axis_a.cross(t1);
+            let error = axis_a.cross(axis_b) * (BIAS_FACTOR / dt);
+            Some(([t1, t2], error))
+        }
+    };
+
+    for _ in 0..ITERATIONS {
+        let dv = b.world_velocity() - a.world_velocity();
+        let impulse = -(k_inv * (dv + bias));
+        a.apply_impulse(-impulse);
+        b.apply_impulse(impulse);
+
+        if let Some((ref tangents, error)) = hinge {
+            for &t in tangents.iter() {
+                let dw = b.world_angular_velocity() - a.world_angular_velocity();
+                let k = t.dot((ia + ib) * t);
+                let lambda = -(dw.dot(t) + error.dot(t)) / k;
+                a.apply_angular_impulse(-t * lambda);
+                b.apply_angular_impulse(t * lambda);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(y: f32) -> space::Transform {
+        space::Transform {
+            disp: cgmath::vec3(0.0, y, 0.0),
+            rot: cgmath::Quaternion::one(),
+            scale: 1.0,
+        }
+    }
+
+    fn solve_pair(kind: Kind) -> (Dynamo, Dynamo) {
+        let physics = vangers::config::fallback::car_model().body.physics;
+        let mass = Mass::new(&physics, 1.0, 1.0);
+        let (ta, tb) = (transform(0.0), transform(-10.0));
+        let mut da = Dynamo::default();
+        let mut db = Dynamo {
+            linear_velocity: cgmath::vec3(0.0, -3.0, 1.0),
+            angular_velocity: cgmath::vec3(0.2, 0.0, 0.5),
+            ..Dynamo::default()
+        };
+        let mut a = Body {
+            transform: &ta,
+            dynamo: &mut da,
+            mass,
+            anchor: cgmath::Point3::new(0.0, -5.0, 0.0),
+        };
+        let mut b = Body {
+            transform: &tb,
+            dynamo: &mut db,
+            mass,
+            anchor: cgmath::Point3::new(0.0, 5.0, 0.0),
+        };
+        solve(kind, &mut a, &mut b, 1.0);
+        let relative = b.world_velocity() - a.world_velocity();
+        assert!(relative.magnitude() < 1e-3, "{:?}", relative);
+        (da, db)
+    }
+
+    #[test]
+    fn ball_keeps_together() {
+        let (da, db) = solve_pair(Kind::Ball);
+        // the same masses, so the momentum is shared equally
+        let momentum = da.linear_velocity + db.linear_velocity;
+        assert!((momentum - cgmath::vec3(0.0, -3.0, 1.0)).magnitude() < 1e-3);
+        assert!(da.linear_velocity.y < 0.0);
+    }
+
+    #[test]
+    fn hinge_only_turns_around_z() {
+        let (da, db) = solve_pair(Kind::Hinge);
+        let relative = db.angular_velocity - da.angular_velocity;
+        assert!(relative.x.abs() < 1e-2 && relative.y.abs() < 1e-2);
+        assert!(relative.z.abs() > 0.1);
+    }
+}
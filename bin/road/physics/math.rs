@@ -0,0 +1,307 @@
+//! Math functions of the physics that IEEE 754 doesn't pin down.
+//!
+//! The basic arithmetic and `sqrt` are rounded exactly everywhere, but `powf`,
+//! `ln`, or `sin_cos` come from the platform library, and differ in the last
+//! bits between the platforms. With the "deterministic" feature, they are
+//! computed in software out of the basic operations instead, so the simulation
+//! goes bit by bit the same everywhere, as the lockstep networking and the
+//! replays need. See `physbench --expect` for checking a platform.
+
+#[cfg(not(feature = "deterministic"))]
+mod native {
+    pub fn powf(x: f32, y: f32) -> f32 {
+        x.powf(y)
+    }
+
+    pub fn ln(x: f32) -> f32 {
+        x.ln()
+    }
+
+    pub fn exp2(x: f32) -> f32 {
+        x.exp2()
+    }
+
+    pub fn sin_cos(x: f32) -> (f32, f32) {
+        x.sin_cos()
+    }
+
+    pub fn atan2(y: f32, x: f32) -> f32 {
+        y.atan2(x)
+    }
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub use native::*;
+#[cfg(feature = "deterministic")]
+pub use soft::*;
+
+/// Rotation around the unit axis, matching `Quaternion::from_axis_angle`.
+pub fn rotation(axis: cgmath::Vector3<f32>, angle: cgmath::Rad<f32>) -> cgmath::Quaternion<f32> {
+    let (s, c) = sin_cos(0.5 * angle.0);
+    cgmath::Quaternion::from_sv(c, axis * s)
+}
+
+/// Software versions, evaluated in `f64` with the series that converge
+/// well past the precision of `f32` on the reduced ranges.
+#[cfg_attr(not(feature = "deterministic"), allow(dead_code))]
+pub mod soft {
+    use std::f64::consts::{FRAC_PI_2, LN_2, PI, SQRT_2};
+
+    /// Low part of pi/2, for the exact argument reduction.
+    const FRAC_PI_2_LOW: f64 = 6.123_233_995_736_766e-17;
+    const SERIES_TERMS: u32 = 14;
+
+    fn exp_f64(x: f64) -> f64 {
+        if x.is_nan() {
+            return x;
+        }
+        // past the range of `f32`, including the subnormals
+        if x > 90.0 {
+            return f64::INFINITY;
+        }
+        if x < -110.0 {
+            return 0.0;
+        }
+        // x = k * ln(2) + r, with |r| <= ln(2) / 2
+        let k = (x / LN_2).round();
+        let r = x - k * LN_2;
+        let mut term = 1.0;
+        let mut sum = 1.0;
+        for n in 1..=SERIES_TERMS {
+            term *= r / n as f64;
+            sum += term;
+        }
+        sum * f64::from_bits(((k as i64 + 1023) as u64) << 52)
+    }
+
+    /// Natural logarithm of a positive normal number.
+    fn ln_f64(x: f64) -> f64 {
+        // x = m * 2^e, with m in [sqrt(2)/2, sqrt(2)]
+        let bits = x.to_bits();
+        let mut e = ((bits >> 52) & 0x7FF) as i64 - 1023;
+        let mut m = f64::from_bits((bits & ((1 << 52) - 1)) | (1023 << 52));
+        if m > SQRT_2 {
+            m *= 0.5;
+            e += 1;
+        }
+        // ln(m) = 2 * atanh(s)
+        let s = (m - 1.0) / (m + 1.0);
+        let s2 = s * s;
+        let mut term = s;
+        let mut sum = 0.0;
+        for k in 0..SERIES_TERMS {
+            sum += term / (2 * k + 1) as f64;
+            term *= s2;
+        }
+        2.0 * sum + e as f64 * LN_2
+    }
+
+    pub fn ln(x: f32) -> f32 {
+        if x.is_nan() || x < 0.0 {
+            f32::NAN
+        } else if x == 0.0 {
+            f32::NEG_INFINITY
+        } else if x.is_infinite() {
+            x
+        } else {
+            ln_f64(x as f64) as f32
+        }
+    }
+
+    pub fn exp2(x: f32) -> f32 {
+        exp_f64(x as f64 * LN_2) as f32
+    }
+
+    pub fn powf(x: f32, y: f32) -> f32 {
+        if y == 0.0 || x == 1.0 {
+            return 1.0;
+        }
+        if x.is_nan() || y.is_nan() {
+            return f32::NAN;
+        }
+        let (base, sign) = if x < 0.0 {
+            if y.fract() != 0.0 {
+                return f32::NAN;
+            }
+            (-x, if y % 2.0 != 0.0 { -1.0 } else { 1.0 })
+        } else {
+            (x, 1.0)
+        };
+        let value = if base == 0.0 {
+            if y > 0.0 {
+                0.0
+            } else {
+                f32::INFINITY
+            }
+        } else if base.is_infinite() {
+            if y > 0.0 {
+                f32::INFINITY
+            } else {
+                0.0
+            }
+        } else {
+            exp_f64(y as f64 * ln_f64(base as f64)) as f32
+        };
+        sign * value
+    }
+
+    pub fn sin_cos(x: f32) -> (f32, f32) {
+        if !x.is_finite() {
+            return (f32::NAN, f32::NAN);
+        }
+        // x = k * pi/2 + r, with |r| <= pi/4
+        let x = x as f64;
+        let k = (x / FRAC_PI_2).round();
+        let r = (x - k * FRAC_PI_2) - k * FRAC_PI_2_LOW;
+        let r2 = r * r;
+        let (mut sin_term, mut cos_term) = (r, 1.0);
+        let (mut sin, mut cos) = (r, 1.0);
+        for n in 1..SERIES_TERMS {
+            let n = n as f64;
+            sin_term *= -r2 / ((2.0 * n) * (2.0 * n + 1.0));
+            cos_term *= -r2 / ((2.0 * n - 1.0) * (2.0 * n));
+            sin += sin_term;
+            cos += cos_term;
+        }
+        let (sin, cos) = match (k as i64).rem_euclid(4) {
+            0 => (sin, cos),
+            1 => (cos, -sin),
+            2 => (-sin, -cos),
+            _ => (-cos, sin),
+        };
+        (sin as f32, cos as f32)
+    }
+
+    fn atan_f64(t: f64) -> f64 {
+        if t.abs() > 1.0 {
+            return FRAC_PI_2.copysign(t) - atan_f64(1.0 / t);
+        }
+        // halve the angle twice, atan(t) = 2 * atan(t / (1 + sqrt(1 + t^2)))
+        let mut r = t;
+        for _ in 0..2 {
+            r /= 1.0 + (1.0 + r * r).sqrt();
+        }
+        let r2 = r * r;
+        let mut term = r;
+        let mut sum = 0.0;
+        for k in 0..SERIES_TERMS {
+            sum += term / (2 * k + 1) as f64;
+            term *= -r2;
+        }
+        4.0 * sum
+    }
+
+    pub fn atan2(y: f32, x: f32) -> f32 {
+        if x.is_nan() || y.is_nan() {
+            return f32::NAN;
+        }
+        let (y, x) = (y as f64, x as f64);
+        let angle = if x == 0.0 {
+            if y != 0.0 {
+                FRAC_PI_2.copysign(y)
+            } else if x.is_sign_negative() {
+                PI.copysign(y)
+            } else {
+                y
+            }
+        } else if x.is_infinite() && y.is_infinite() {
+            let quarter = if x > 0.0 { 0.25 } else { 0.75 };
+            (quarter * PI).copysign(y)
+        } else if x > 0.0 {
+            atan_f64(y / x)
+        } else {
+            atan_f64(y / x) + PI.copysign(y)
+        };
+        angle as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::soft;
+    use cgmath::prelude::*;
+    use std::hash::Hasher as _;
+
+    fn assert_close(actual: f32, expected: f32, what: &str) {
+        let tolerance = 4.0 * f32::EPSILON * expected.abs().max(f32::MIN_POSITIVE);
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{}: {} instead of {}",
+            what,
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn soft_matches_std() {
+        for i in -200..200 {
+            let x = i as f32 * 0.37;
+            let (s, c) = soft::sin_cos(x);
+            // near the zeros, the closeness is absolute
+            assert!((s - x.sin()).abs() < 1e-6, "sin {}", x);
+            assert!((c - x.cos()).abs() < 1e-6, "cos {}", x);
+            assert_close(soft::exp2(x * 0.1), (x * 0.1).exp2(), "exp2");
+            let y = (i as f32 * 0.11).abs() + 1e-3;
+            assert_close(soft::ln(y), y.ln(), "ln");
+            assert_close(soft::powf(y, x * 0.05), y.powf(x * 0.05), "powf");
+            let (a, b) = (x.sin() * 3.0, (x * 0.7).cos() * 2.0 - 1.0);
+            assert!(
+                (soft::atan2(a, b) - a.atan2(b)).abs() < 1e-6,
+                "atan2 {} {}",
+                a,
+                b
+            );
+        }
+        for &(y, x) in &[
+            (0.0, -1.0),
+            (-0.0, -1.0),
+            (1.0, 0.0),
+            (0.0, 0.0),
+            (-0.0, -0.0),
+        ] {
+            assert_eq!(soft::atan2(y, x), f32::atan2(y, x), "atan2 {} {}", y, x);
+        }
+        assert_eq!(
+            soft::atan2(f32::INFINITY, f32::NEG_INFINITY),
+            f32::atan2(f32::INFINITY, f32::NEG_INFINITY)
+        );
+        assert_eq!(soft::powf(-2.0, 3.0), -8.0);
+        assert!(soft::powf(-2.0, 0.5).is_nan());
+        assert_eq!(soft::powf(0.0, 2.0), 0.0);
+        assert_eq!(soft::ln(0.0), f32::NEG_INFINITY);
+
+        let axis = cgmath::vec3(0.0, 0.6, 0.8);
+        let expected = cgmath::Quaternion::from_axis_angle(axis, cgmath::Rad(1.3));
+        let actual = super::rotation(axis, cgmath::Rad(1.3));
+        assert!((actual - expected).magnitude() < 1e-6);
+    }
+
+    /// A toy simulation with the drag and the rotations of the physics,
+    /// hashed bit by bit. The hash is the same on every platform.
+    #[test]
+    fn soft_state_hash() {
+        let mut rot = cgmath::Quaternion::<f32>::one();
+        let mut vel = cgmath::vec3(30.0f32, -4.0, 2.5);
+        let mut spin = cgmath::vec3(0.5f32, 1.5, -0.25);
+        let mut hasher = fnv::FnvHasher::default();
+        for step in 0..1000 {
+            let dt = 0.02 + (step % 7) as f32 * 0.001;
+            let drag = 0.98 * soft::powf(0.995, vel.magnitude());
+            vel *= soft::powf(drag, dt / 0.05);
+            spin *= soft::exp2(-dt) * (1.0 + soft::ln(1.0 + dt));
+            let w = spin.magnitude();
+            let turn = {
+                let (s, c) = soft::sin_cos(-0.5 * dt * w);
+                cgmath::Quaternion::from_sv(c, spin / (w + f32::EPSILON) * s)
+            };
+            rot = (rot * turn).normalize();
+            vel = turn * vel + rot * cgmath::vec3(0.0, 20.0 * dt, 0.0);
+            for value in [rot.s, rot.v.x, rot.v.y, rot.v.z, vel.x, vel.y, vel.z] {
+                hasher.write_u32(value.to_bits());
+            }
+        }
+        assert!(vel.magnitude() > 1.0 && vel.magnitude().is_finite());
+        assert_eq!(hasher.finish(), 0x3739_abdd_940d_f35b);
+    }
+}
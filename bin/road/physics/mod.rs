@@ -5,12 +5,18 @@ use vangers::{config, level, render::debug::LineBuffer, space};
 
 use cgmath::prelude::*;
 
-use std::f32::EPSILON;
-
+mod broadphase;
+mod engine;
+mod joint;
+pub mod math;
 mod rigid;
+mod substeps;
 mod terrain;
 
-const MAX_TRACTION: config::common::Traction = 4.0;
+pub use broadphase::Counters as BroadphaseCounters;
+pub use engine::{Engine, Input as EngineInput, Params as EngineParams};
+pub use joint::{solve as solve_joint, Body as JointBody, Mass};
+pub use substeps::{split as split_frame, Split};
 
 #[derive(Debug)]
 struct AccelerationVectors {
@@ -19,7 +25,7 @@ struct AccelerationVectors {
 }
 
 pub struct Dynamo {
-    pub traction: config::common::Traction,
+    pub engine: Engine,
     pub rudder: cgmath::Rad<f32>,
     pub linear_velocity: cgmath::Vector3<f32>,
     pub angular_velocity: cgmath::Vector3<f32>,
@@ -28,7 +34,7 @@ pub struct Dynamo {
 impl Default for Dynamo {
     fn default() -> Self {
         Dynamo {
-            traction: 0.,
+            engine: Engine::default(),
             rudder: cgmath::Rad(0.),
             linear_velocity: cgmath::Vector3::zero(),
             angular_velocity: cgmath::Vector3::zero(),
@@ -36,20 +42,102 @@ impl Default for Dynamo {
     }
 }
 
-impl Dynamo {
-    pub fn change_traction(&mut self, delta: config::common::Traction) {
-        self.traction = (self.traction + delta).min(MAX_TRACTION).max(-MAX_TRACTION);
+/// How deep the body got into the water and the terrain during a step.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Immersion {
+    /// Number of the collision polygons under the water.
+    pub float_count: u32,
+    /// Total depth of these polygons under the water level.
+    pub water: f32,
+    pub terrain: f32,
+}
+
+impl Immersion {
+    pub fn in_water(&self) -> bool {
+        self.float_count != 0
+    }
+}
+
+/// What happened to the body during a step.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Outcome {
+    pub immersion: Immersion,
+    /// Fastest approach speed against a hard terrain wall, if any was hit.
+    pub hit_speed: f32,
+    /// Fastest approach speed against the ground, if touched.
+    pub landing_speed: f32,
+    pub broadphase: BroadphaseCounters,
+    /// Terrain type under the wheels, if they touched the ground.
+    pub surface: Option<level::TerrainType>,
+}
+
+/// State of a wheel after a step, for the debugging.
+#[derive(Clone, Copy, Debug)]
+pub struct WheelState {
+    /// Center of the wheel in the world.
+    pub position: cgmath::Point3<f32>,
+    /// Point of the ground under the wheel, if it's within the wheel radius.
+    pub contact: Option<cgmath::Point3<f32>>,
+    /// How deep the ground gets into the wheel, relative to its radius.
+    pub compression: f32,
+    /// Sideways velocity of the wheel in the world, resisted by the wheel impulse.
+    pub slip: cgmath::Vector3<f32>,
+}
+
+/// Optional outputs of a step, for the debugging.
+#[derive(Default)]
+pub struct StepDebug<'a> {
+    /// Receives the collision impulses and the forces.
+    pub lines: Option<&'a mut LineBuffer>,
+    /// Receives the state of each wheel, replacing the old contents.
+    pub wheels: Option<&'a mut Vec<WheelState>>,
+}
+
+/// Wheel of a car placed in the world.
+struct PlacedWheel {
+    center: cgmath::Point3<f32>,
+    radius: f32,
+    contact: Option<terrain::WheelContact>,
+}
+
+impl PlacedWheel {
+    /// Place the wheel by the transform of the car, and find the ground under it.
+    fn new<M>(level: &level::Level, transform: &space::Transform, wheel: &m3d::Wheel<M>) -> Self {
+        let center = transform.transform_point(cgmath::Point3::from(wheel.pos));
+        let radius = (wheel.radius as f32 * transform.scale).max(f32::EPSILON);
+        let half_axle =
+            transform.transform_vector(cgmath::Vector3::unit_x() * (0.5 * wheel.width as f32));
+        PlacedWheel {
+            center,
+            radius,
+            contact: terrain::cast_wheel(level, center, half_axle, radius),
+        }
     }
 
-    pub fn slow_down(&mut self, delta: config::common::Traction) {
-        let old = self.traction;
-        self.change_traction(delta * -old.signum());
-        if old * self.traction < 0.0 {
-            self.traction = 0.0;
+    fn state(&self, slip: cgmath::Vector3<f32>) -> WheelState {
+        WheelState {
+            position: self.center,
+            contact: self.contact.map(|c| c.point),
+            compression: self
+                .contact
+                .map_or(0.0, |c| (c.depth / self.radius).clamp(0.0, 1.0)),
+            slip,
         }
     }
 }
 
+/// Direction along the plane with the normal, to push the wheels along the ground.
+fn along_plane(dir: cgmath::Vector3<f32>, normal: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    let projected = dir - normal * normal.dot(dir);
+    let length = projected.magnitude();
+    // the direction is nearly perpendicular to the plane on the steep walls
+    if length > 0.1 {
+        projected / length
+    } else {
+        dir
+    }
+}
+
 pub fn jump_dir(power: f32) -> cgmath::Vector3<f32> {
     5.0 * power * cgmath::vec3(0.0, 3.0, 10.0).normalize()
 }
@@ -60,13 +148,19 @@ pub fn step(
     dt: f32,
     car: &config::car::CarInfo,
     level: &level::Level,
+    use_broadphase: bool,
     common: &config::common::Common,
-    f_turbo: f32,
     f_brake: f32,
     jump: Option<f32>,
     roll: f32,
-    mut line_buffer: Option<&mut LineBuffer>,
-) {
+    debug: StepDebug,
+) -> Outcome {
+    let mut line_buffer = debug.lines;
+    let mut wheel_states = debug.wheels;
+    if let Some(ref mut wheels) = wheel_states {
+        wheels.clear();
+    }
+    let engine_params = EngineParams::new(common);
     let speed_correction_factor = dt / common.nature.time_delta0;
     let acc_global = AccelerationVectors {
         f: cgmath::vec3(0.0, 0.0, -common.nature.gravity),
@@ -74,7 +168,6 @@ pub fn step(
     };
     let rot_inv = transform.rot.invert();
     log::debug!("dt {}, num {}", dt, common.nature.num_calls_analysis);
-    let flood_level = level.flood_map[0] as f32;
     // Z axis in the local coordinate space
     let z_axis = rot_inv * cgmath::Vector3::unit_z();
     let device_modulation = 1.0;
@@ -92,7 +185,8 @@ pub fn step(
             * car.model.body.physics.volume
             * transform.scale
             * transform.scale;
-        let f = device_modulation * common.force.k_distance_to_force * dt_impulse / mass.powf(0.3);
+        let f = device_modulation * common.force.k_distance_to_force * dt_impulse
+            / math::powf(mass, 0.3);
         log::info!("jump mass {:?}, f {:?}", mass, f);
         //DBV dV = A_g2l*DBV(-Sin(Pi/10)*Sin(psi),-Sin(Pi/10)*Cos(psi),Cos(Pi/10));
         rigid.vel += f * jump_dir(power);
@@ -104,6 +198,9 @@ pub fn step(
 
     let mut float_count = 0;
     let (mut terrain_immersion, mut water_immersion) = (0.0, 0.0);
+    let mut hit_speed = 0.0f32;
+    let mut landing_speed = 0.0f32;
+    let mut counters = BroadphaseCounters::default();
     let stand_on_wheels =
         z_axis.z > 0.0 && (transform.rot * cgmath::Vector3::unit_x()).z.abs() < 0.7;
     let modulation = 1.0;
@@ -134,13 +231,13 @@ pub fn step(
             poly.middle,
             r
         );
-        match level.get((rglob.x as i32, rglob.y as i32)) {
+        match level.get_at(rglob) {
             level::Texel::Single(level::Point(_, 0))
             | level::Texel::Dual {
                 low: level::Point(_, 0),
                 ..
             } => {
-                let dz = flood_level - rglob.z;
+                let dz = level.flood_level(rglob.y as i32) - rglob.z;
                 if dz > 0.0 {
                     float_count += 1;
                     water_immersion += dz;
@@ -150,6 +247,13 @@ pub fn step(
         };
         let poly_norm = cgmath::Vector3::from(poly.normal).normalize();
         if z_axis.dot(poly_norm) < 0.0 {
+            // none of the samples can be lower than this
+            let reach = poly.radius * car.physics.scale_bound * transform.scale;
+            if use_broadphase && broadphase::is_above(level, rglob, reach) {
+                counters.skipped += 1;
+                continue;
+            }
+            counters.tested += 1;
             let cdata = terrain::CollisionData::collide_low(
                 poly,
                 &car.model.shape.samples,
@@ -188,6 +292,7 @@ pub fn step(
                     };
                     let dot = pv.dot(normal);
                     if dot > 0.0 {
+                        hit_speed = hit_speed.max(dot);
                         rigid.push(r, normal * (dot * -common.impulse.factors[0] * modulation));
                     }
                 }
@@ -199,6 +304,7 @@ pub fn step(
                         rot_inv * cgmath::vec3(cp.pos.x - origin.x, cp.pos.y - origin.y, rg0.z);
                     let pv = rigid.velocity_at(r1);
                     if pv.dot(z_axis) < 0.0 {
+                        landing_speed = landing_speed.max(-pv.dot(z_axis));
                         let vec = if stand_on_wheels {
                             // ignore XY
                             cgmath::vec3(0.0, 0.0, pv.z)
@@ -238,13 +344,13 @@ pub fn step(
 
                 if let Some(ref mut lbuf) = line_buffer {
                     // Red: center -> collision point
-                    lbuf.add(transform.disp.into(), rglob.into(), 0xFF000000);
+                    lbuf.add(transform.disp.into(), rglob.into(), 0xFF0000FF);
                     // Yellow: collision point -> linear force
                     let up = rglob + cgmath::vec3(0.0, 0.0, df0);
-                    lbuf.add(rglob.into(), up.into(), 0xFFFF0000);
+                    lbuf.add(rglob.into(), up.into(), 0xFFFF00FF);
                     // Purple: collision point -> angular force
                     let end = rglob + df * cgmath::vec3(rg0.y, -rg0.x, 0.0);
-                    lbuf.add(rglob.into(), end.into(), 0xFF00FF00);
+                    lbuf.add(rglob.into(), end.into(), 0xFF00FFFF);
                 }
             }
         } else {
@@ -265,66 +371,87 @@ pub fn step(
     }
 
     // apply drag
-    let mut v_drag = common.drag.free.v * common.drag.speed.v.powf(rigid.vel.magnitude());
+    let mut v_drag = common.drag.free.v * math::powf(common.drag.speed.v, rigid.vel.magnitude());
     let mut w_drag = common.drag.free.w
-        * common
-            .drag
-            .speed
-            .w
-            .powf(rigid.angular_velocity().magnitude2()); //why mag2?
+        * math::powf(
+            common.drag.speed.w,
+            rigid.angular_velocity().magnitude2(), //why mag2?
+        );
     if wheels_touch > 0 {
         //TODO: why `ln()`?
-        let speed =
-            common.drag.wheel_speed.ln() * car.physics.mobility_factor * common.global.speed_factor
-                / car.physics.speed_factor;
-        rigid.vel.y *= (1.0 + speed).powf(speed_correction_factor);
+        let speed = math::ln(common.drag.wheel_speed)
+            * car.physics.mobility_factor
+            * common.global.speed_factor
+            / car.physics.speed_factor;
+        rigid.vel.y *= math::powf(1.0 + speed, speed_correction_factor);
     }
 
-    let _ = (float_count, water_immersion, terrain_immersion); //TODO
     let is_after_collision = false;
-    if wheels_touch != 0 && stand_on_wheels {
-        let f_traction_per_wheel =
-            car.physics.mobility_factor * common.global.mobility_factor * f_turbo * dynamo.traction
-                / (car.model.wheels.len() as f32);
+    let mut wheel_surface = None;
+    if stand_on_wheels {
+        let f_traction_per_wheel = car.physics.mobility_factor
+            * common.global.mobility_factor
+            * dynamo.engine.turbo_factor(&engine_params)
+            * dynamo.engine.traction
+            / (car.model.wheels.len() as f32);
         let rudder_vec = {
-            let (sin, cos) = dynamo.rudder.sin_cos();
+            let (sin, cos) = math::sin_cos(dynamo.rudder.0);
             cgmath::vec3(cos, -sin, 0.0)
         };
+        let num_wheels = car.model.wheels.len() as f32;
         for wheel in car.model.wheels.iter() {
-            let pw = transform.transform_point(cgmath::Point3::from(wheel.pos));
-            let detect_wheel_hits = false;
-            if detect_wheel_hits {
-                let dist = terrain::get_distance_to_terrain(level, pw);
-                if dist > 0.0 {
+            let placed = PlacedWheel::new(level, transform, wheel);
+            let contact = match placed.contact {
+                Some(contact) => contact,
+                None => {
+                    // the wheel is in the air
+                    if let Some(ref mut wheels) = wheel_states {
+                        wheels.push(placed.state(cgmath::Vector3::zero()));
+                    }
                     continue;
                 }
-            }
-
-            let rx_max = if wheel.pos[0] > 0.0 {
-                car.model.body.bbox.max[0]
-            } else {
-                car.model.body.bbox.min[0]
             };
-            let pos = cgmath::vec3(rx_max, wheel.pos[1], wheel.pos[2]) * transform.scale;
+            let terrain_type = terrain::surface_at(level, contact.point).1;
+            let surface = &common.terrain.surfaces[terrain_type as usize];
+            wheel_surface = Some(terrain_type);
+
+            // the wheel pushes at the contact point, along the ground
+            let pos = rot_inv * (contact.point.to_vec() - transform.disp);
+            let normal = rot_inv * contact.normal;
             let pv = rigid.velocity_at(pos);
 
-            acc_cur.f.y += f_traction_per_wheel;
-            acc_cur.f -= pv * f_brake;
+            acc_cur.f += along_plane(cgmath::Vector3::unit_y(), normal)
+                * (f_traction_per_wheel * surface.grip);
+            acc_cur.f -= pv * (f_brake + surface.rolling / num_wheels);
 
+            let mut slip = cgmath::Vector3::zero();
             if !is_after_collision {
                 let dir = if wheel.steer != 0 {
                     rudder_vec
                 } else {
                     cgmath::Vector3::unit_x()
                 };
+                let dir = along_plane(dir, normal);
 
                 let dot = dir.dot(pv);
-                let pulse = rigid.push(pos, dir * (dot * -common.impulse.k_wheel));
+                slip = transform.transform_vector(dir * dot);
+                let pulse = rigid.push(pos, dir * (dot * -common.impulse.k_wheel * surface.grip));
                 if let Some(ref mut lbuf) = line_buffer {
-                    let dest = pw + transform.transform_vector(pulse) * 10.0;
-                    lbuf.add(pw.into(), dest.into(), 0xFFFFFF00);
+                    let dest = contact.point + transform.transform_vector(pulse) * 10.0;
+                    lbuf.add(contact.point.into(), dest.into(), 0xFFFFFFFF);
                 }
             }
+            if let Some(ref mut wheels) = wheel_states {
+                wheels.push(placed.state(slip));
+            }
+        }
+    }
+    if let Some(ref mut wheels) = wheel_states {
+        // nothing to slip on in the air
+        if wheels.is_empty() {
+            wheels.extend(car.model.wheels.iter().map(|wheel| {
+                PlacedWheel::new(level, transform, wheel).state(cgmath::Vector3::zero())
+            }));
         }
     }
 
@@ -338,7 +465,7 @@ pub fn step(
         acc_cur.k -= common.nature.gravity * tmp.cross(z_axis);
         let vz = z_axis.dot(rigid.vel);
         if vz < -10.0 {
-            v_drag *= common.drag.z.powf(-vz);
+            v_drag *= math::powf(common.drag.z, -vz);
         }
     }
 
@@ -367,10 +494,10 @@ pub fn step(
     }
     let (v_mag, w_mag) = (v_vel.magnitude(), w_vel.magnitude());
     if stand_on_wheels && v_mag < common.drag.abs_min.v && w_mag < common.drag.abs_min.w {
-        let v_pow = common.drag.abs_min.v / (v_mag + EPSILON);
-        let w_pow = common.drag.abs_min.w / (w_mag + EPSILON);
-        v_drag *= common.drag.coll.v.powf(v_pow);
-        w_drag *= common.drag.coll.w.powf(w_pow);
+        let v_pow = common.drag.abs_min.v / (v_mag + f32::EPSILON);
+        let w_pow = common.drag.abs_min.w / (w_mag + f32::EPSILON);
+        v_drag *= math::powf(common.drag.coll.v, v_pow);
+        w_drag *= math::powf(common.drag.coll.w, w_pow);
     }
 
     if v_mag * v_drag > common.drag.abs_stop.v || w_mag * w_drag > common.drag.abs_stop.w {
@@ -380,7 +507,7 @@ pub fn step(
         let vs = v_vel - r_diff_sign * local_z_scaled.cross(w_vel);
 
         let angle = cgmath::Rad(-dt * w_mag);
-        let vel_rot_inv = cgmath::Quaternion::from_axis_angle(w_vel / (w_mag + EPSILON), angle);
+        let vel_rot_inv = math::rotation(w_vel / (w_mag + f32::EPSILON), angle);
         transform.disp += (transform.rot * vs) * dt;
         transform.rot = transform.rot * vel_rot_inv.invert();
         v_vel = vel_rot_inv * v_vel;
@@ -394,8 +521,8 @@ pub fn step(
         );
     }
     //log::debug!("\tdrag v={} w={}", v_drag, w_drag);
-    v_vel *= v_drag.powf(speed_correction_factor);
-    w_vel *= w_drag.powf(speed_correction_factor);
+    v_vel *= math::powf(v_drag, speed_correction_factor);
+    w_vel *= math::powf(w_drag, speed_correction_factor);
 
     if let Some(ref mut lbuf) = line_buffer {
         // Note: velocity and acceleration are in local space
@@ -403,15 +530,16 @@ pub fn step(
         let ba = transform.disp + cgmath::vec3(3.0, 0.0, 10.0);
         let xf = ba + rot * acc_cur.f;
         let xk = ba + rot * acc_cur.k;
-        lbuf.add(ba.into(), xf.into(), 0x0000FF00);
-        lbuf.add(ba.into(), xk.into(), 0xFF00FF00);
+        // drawn over the body, which would hide them otherwise
+        lbuf.add_overlay(ba.into(), xf.into(), 0x0000FFFF);
+        lbuf.add_overlay(ba.into(), xk.into(), 0xFF00FFFF);
         // Yellow: center -> angular springs total
-        lbuf.add(ba.into(), (ba + acc_springs.k).into(), 0xFFFF0000);
+        lbuf.add_overlay(ba.into(), (ba + acc_springs.k).into(), 0xFFFF00FF);
         let bv = transform.disp + cgmath::vec3(-3.0, 0.0, 10.0);
         let xv = bv + rot * v_vel;
         let xw = bv + rot * w_vel * 10.0; //TEMP
-        lbuf.add(bv.into(), xv.into(), 0x00FF0000);
-        lbuf.add(bv.into(), xw.into(), 0x00FFFF00);
+        lbuf.add_overlay(bv.into(), xv.into(), 0x00FF00FF);
+        lbuf.add_overlay(bv.into(), xw.into(), 0x00FFFFFF);
     }
 
     dynamo.linear_velocity = v_vel;
@@ -422,5 +550,136 @@ pub fn step(
         dynamo.rudder.0 -= dynamo.rudder.0.signum() * change.abs();
     }
     // slow down
-    dynamo.slow_down(dt * common.car.traction_decr);
+    dynamo.engine.idle(dt, &engine_params);
+
+    Outcome {
+        immersion: Immersion {
+            float_count,
+            water: water_immersion,
+            terrain: terrain_immersion,
+        },
+        hit_speed,
+        landing_speed,
+        broadphase: counters,
+        surface: wheel_surface,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use vangers::{config::settings::Geometry, render};
+
+    fn create_gfx() -> Option<render::GraphicsContext> {
+        use futures::executor::block_on;
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+        let (device, queue) = block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits:
+                    wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        ))
+        .ok()?;
+        Some(render::GraphicsContext {
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            downlevel_caps: adapter.get_downlevel_capabilities(),
+            color_format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            screen_size: wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        })
+    }
+
+    #[test]
+    fn settle_on_ramp() {
+        let gfx = match create_gfx() {
+            Some(gfx) => gfx,
+            None => {
+                println!("No adapter to create the car meshes, skipping");
+                return;
+            }
+        };
+        let global = render::global::Context::new(&gfx, None);
+        let object = render::object::Context::new(
+            &gfx,
+            wgpu::FrontFace::Ccw,
+            &config::fallback::palette(),
+            &global,
+        );
+        let car = config::fallback::car(&gfx.device, &object, 1);
+        let mut common = config::fallback::common();
+        // the fallback box stands on a single bottom polygon
+        common.impulse.elastic_restriction = 100.0;
+        let level = level::load(
+            &level::LevelConfig::new_test_preset(level::TestPreset::Ramps),
+            &Geometry::default(),
+        );
+
+        let (x, y) = (24.5, 10.5);
+        let ground = terrain::surface_at(&level, cgmath::Point3::new(x, y, 100.0)).0;
+        let mut transform = space::Transform {
+            disp: cgmath::vec3(x, y, ground + 20.0),
+            rot: cgmath::Quaternion::one(),
+            scale: car.scale,
+        };
+        // dropped with some speed, or the drag would keep it hanging
+        let mut dynamo = Dynamo {
+            linear_velocity: cgmath::vec3(0.0, 0.0, -10.0),
+            ..Dynamo::default()
+        };
+        let dt = common.nature.time_delta0 * common.nature.num_calls_analysis as f32;
+        let mut wheels = Vec::new();
+        let (mut touching, mut tilt) = (0, 0.0);
+        for i in 0..400 {
+            step(
+                &mut dynamo,
+                &mut transform,
+                dt,
+                &car,
+                &level,
+                true,
+                &common,
+                0.0,
+                None,
+                0.0,
+                StepDebug {
+                    lines: None,
+                    wheels: Some(&mut wheels),
+                },
+            );
+            if i >= 300 {
+                if wheels.iter().any(|w| w.contact.is_some()) {
+                    touching += 1;
+                }
+                tilt += (transform.rot * cgmath::Vector3::unit_z()).x;
+            }
+        }
+
+        let up = transform.rot * cgmath::Vector3::unit_z();
+        assert!(up.z > 0.9, "Fell over: {:?}", up);
+        assert!(touching > 90, "Wheels touched in {} steps", touching);
+        // leaning back against the slope, and rolling down it
+        assert!(tilt < 0.0, "Tilt {}", tilt);
+        assert!(transform.disp.x < x, "Position {:?}", transform.disp);
+        let top = transform.disp + cgmath::vec3(0.0, 0.0, 100.0);
+        let ground = terrain::surface_at(&level, cgmath::Point3::from_vec(top)).0;
+        assert!(
+            (transform.disp.z - ground).abs() < 5.0,
+            "Height {} over {}",
+            transform.disp.z,
+            ground
+        );
+    }
 }
@@ -0,0 +1,185 @@
+//! Splitting of a physics frame into the steps.
+//!
+//! With the fixed quantum, a car resting on the ground is stepped as often
+//! as one flying into a wall. The adaptive split instead limits how far the
+//! car travels and turns per step, so it takes more steps when moving fast,
+//! or when the ground is within the reach of the frame, and a single step
+//! when the car is at rest.
+
+use super::Dynamo;
+use vangers::{config::settings::Substeps as Config, level, space};
+
+use cgmath::prelude::*;
+
+/// Steps of a frame: `count` steps of `quant` seconds, then the `last` one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Split {
+    pub count: usize,
+    pub quant: f32,
+    pub last: f32,
+}
+
+impl Split {
+    /// The whole frame in a single step.
+    pub fn single(dt: f32) -> Self {
+        Split {
+            count: 0,
+            quant: 0.0,
+            last: dt,
+        }
+    }
+
+    /// Steps of `max_quant` seconds, with the rest of the frame left to the last one.
+    pub fn fixed(mut dt: f32, max_quant: f32) -> Self {
+        let mut count = 0;
+        while dt > max_quant {
+            dt -= max_quant;
+            count += 1;
+        }
+        Split {
+            count,
+            quant: max_quant,
+            last: dt,
+        }
+    }
+
+    /// Total number of the steps.
+    pub fn steps(&self) -> usize {
+        self.count + 1
+    }
+}
+
+/// Pick the steps for a frame of `dt` seconds, following the motion of the car.
+pub fn split(
+    dynamo: &Dynamo,
+    transform: &space::Transform,
+    radius: f32,
+    level: &level::Level,
+    dt: f32,
+    max_quant: f32,
+    config: &Config,
+) -> Split {
+    if !config.adaptive {
+        return Split::fixed(dt, max_quant);
+    }
+    let travel = dynamo.linear_velocity.magnitude() * dt;
+    let turn = dynamo.angular_velocity.magnitude() * dt;
+
+    let pos = transform.disp;
+    let ground = match level.get_at(pos) {
+        level::Texel::Dual { low, mid, .. } if pos.z < mid => low.0,
+        texel => texel.high(),
+    };
+    let gap = pos.z - radius - ground;
+    let max_travel = if gap < travel {
+        config.contact_travel
+    } else {
+        config.max_travel
+    };
+
+    let steps = (travel / max_travel).max(turn / config.max_turn).ceil() as usize;
+    let steps = steps.clamp(1, config.max_count.max(1));
+    let quant = dt / steps as f32;
+    Split {
+        count: steps - 1,
+        quant,
+        last: dt - quant * (steps - 1) as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: Config = Config {
+        adaptive: true,
+        max_travel: 8.0,
+        contact_travel: 2.0,
+        max_turn: 0.3,
+        max_count: 16,
+    };
+
+    fn split_at(
+        height: f32,
+        velocity: cgmath::Vector3<f32>,
+        max_quant: f32,
+        config: &Config,
+    ) -> Split {
+        let level = level::Level::from_height((16, 16), vec![0; 16 * 16].into_boxed_slice());
+        let transform = space::Transform {
+            disp: cgmath::vec3(0.0, 0.0, height),
+            rot: cgmath::Quaternion::one(),
+            scale: 1.0,
+        };
+        let dynamo = Dynamo {
+            linear_velocity: velocity,
+            ..Dynamo::default()
+        };
+        split(&dynamo, &transform, 4.0, &level, 1.0, max_quant, config)
+    }
+
+    #[test]
+    fn fixed_matches_quant() {
+        let split = Split::fixed(0.25, 0.1);
+        assert_eq!(split.count, 2);
+        assert!((split.last - 0.05).abs() < 1e-6);
+        let fixed = Config {
+            adaptive: false,
+            ..CONFIG
+        };
+        assert_eq!(
+            split_at(100.0, cgmath::Vector3::zero(), 0.1, &fixed).steps(),
+            10
+        );
+    }
+
+    #[test]
+    fn follows_motion() {
+        // at rest, a single step does it
+        assert_eq!(
+            split_at(100.0, cgmath::Vector3::zero(), 1.0, &CONFIG).steps(),
+            1
+        );
+        // fast in the air
+        let fast = split_at(1000.0, cgmath::vec3(40.0, 0.0, 0.0), 1.0, &CONFIG);
+        assert_eq!(fast.steps(), 5);
+        assert!((fast.quant * fast.count as f32 + fast.last - 1.0).abs() < 1e-6);
+        // slower, in the air and next to the ground
+        assert_eq!(
+            split_at(1000.0, cgmath::vec3(16.0, 0.0, 0.0), 1.0, &CONFIG).steps(),
+            2
+        );
+        assert_eq!(
+            split_at(10.0, cgmath::vec3(16.0, 0.0, 0.0), 1.0, &CONFIG).steps(),
+            8
+        );
+        // capped
+        assert_eq!(
+            split_at(10.0, cgmath::vec3(400.0, 0.0, 0.0), 1.0, &CONFIG).steps(),
+            16
+        );
+    }
+
+    #[test]
+    fn shrinks_at_rest() {
+        // the fixed quantum doesn't hold the car at rest to its step count
+        let fixed = Config {
+            adaptive: false,
+            ..CONFIG
+        };
+        let rest = split_at(100.0, cgmath::Vector3::zero(), 0.1, &CONFIG);
+        assert_eq!(rest.steps(), 1);
+        assert_eq!(rest.last, 1.0);
+        assert!(rest.steps() < split_at(100.0, cgmath::Vector3::zero(), 0.1, &fixed).steps());
+        // resting on the ground
+        assert_eq!(
+            split_at(4.0, cgmath::Vector3::zero(), 0.1, &CONFIG).steps(),
+            1
+        );
+        // the motion is split the same regardless of the quantum
+        assert_eq!(
+            split_at(10.0, cgmath::vec3(16.0, 0.0, 0.0), 0.05, &CONFIG),
+            split_at(10.0, cgmath::vec3(16.0, 0.0, 0.0), 1.0, &CONFIG)
+        );
+    }
+}
@@ -11,6 +11,7 @@ pub struct CollisionPoint {
 #[derive(Debug)]
 pub struct CollisionData {
     pub soft: Option<CollisionPoint>,
+    #[allow(dead_code)]
     pub hard_dominant: bool,
     pub hard: Option<CollisionPoint>,
 }
@@ -46,18 +47,67 @@ impl HitAccumulator {
     }
 }
 
-pub fn get_distance_to_terrain(level: &level::Level, point: cgmath::Point3<f32>) -> f32 {
-    point.z
-        - match level.get((point.x as i32, point.y as i32)) {
-            level::Texel::Single(p) => p.0,
-            level::Texel::Dual { high, low, mid } => {
-                if point.z > mid {
-                    high.0
-                } else {
-                    low.0
-                }
+/// The terrain surface right below or above the point.
+/// The texel is found by flooring the position, unlike the truncation of the
+/// earlier code, which put the points past the seam at zero on the wrong side.
+pub fn surface_at(level: &level::Level, point: cgmath::Point3<f32>) -> level::Point {
+    match level.get_at(point) {
+        level::Texel::Single(p) => p,
+        level::Texel::Dual { high, low, mid } => {
+            if point.z > mid {
+                high
+            } else {
+                low
             }
         }
+    }
+}
+
+pub fn get_distance_to_terrain(level: &level::Level, point: cgmath::Point3<f32>) -> f32 {
+    point.z - surface_at(level, point).0
+}
+
+/// Normal of the terrain surface around the point, from the heights of the neighbors.
+pub fn surface_normal(level: &level::Level, point: cgmath::Point3<f32>) -> cgmath::Vector3<f32> {
+    let height = |dx: f32, dy: f32| surface_at(level, point + cgmath::vec3(dx, dy, 0.0)).0;
+    let dh_dx = 0.5 * (height(1.0, 0.0) - height(-1.0, 0.0));
+    let dh_dy = 0.5 * (height(0.0, 1.0) - height(0.0, -1.0));
+    cgmath::vec3(-dh_dx, -dh_dy, 1.0).normalize()
+}
+
+/// Ground touching a wheel, found by the vertical rays from its rims.
+#[derive(Clone, Copy, Debug)]
+pub struct WheelContact {
+    /// Highest point of the ground under the wheel.
+    pub point: cgmath::Point3<f32>,
+    pub normal: cgmath::Vector3<f32>,
+    /// How deep the ground gets into the wheel.
+    pub depth: f32,
+}
+
+/// Cast the rays down from both rims of a wheel, given its center and radius
+/// in the world, and the half of its width along the axle.
+/// Returns the ground only if it's within the radius.
+pub fn cast_wheel(
+    level: &level::Level,
+    center: cgmath::Point3<f32>,
+    half_axle: cgmath::Vector3<f32>,
+    radius: f32,
+) -> Option<WheelContact> {
+    let (rim, distance) = [center - half_axle, center + half_axle]
+        .into_iter()
+        .map(|rim| (rim, get_distance_to_terrain(level, rim)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap();
+    if distance >= radius {
+        return None;
+    }
+    let point = rim - cgmath::vec3(0.0, 0.0, distance);
+    Some(WheelContact {
+        point,
+        normal: surface_normal(level, point),
+        depth: radius - distance,
+    })
 }
 
 impl CollisionData {
@@ -73,7 +123,7 @@ impl CollisionData {
         for s in samples[poly.samples.clone()].iter() {
             let sp = cgmath::Point3::from(*s).cast::<f32>().unwrap();
             let pos = transform.transform_point(sp * scale).to_vec();
-            let texel = level.get((pos.x as i32, pos.y as i32));
+            let texel = level.get_at(pos);
             let height = match texel {
                 level::Texel::Single(point) => point.0,
                 level::Texel::Dual { high, low, mid } => {
@@ -111,3 +161,69 @@ impl CollisionData {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vangers::config::settings::Geometry;
+
+    fn load(preset: level::TestPreset) -> level::Level {
+        level::load(
+            &level::LevelConfig::new_test_preset(preset),
+            &Geometry::default(),
+        )
+    }
+
+    #[test]
+    fn wheel_on_flat_ground() {
+        let flat = load(level::TestPreset::Flat);
+        let axle = cgmath::vec3(2.0, 0.0, 0.0);
+        let contact = cast_wheel(&flat, cgmath::Point3::new(10.5, 10.5, 5.0), axle, 8.0).unwrap();
+        assert_eq!(contact.point.z, 0.0);
+        assert_eq!(contact.depth, 3.0);
+        assert_eq!(contact.normal, cgmath::Vector3::unit_z());
+        assert!(cast_wheel(&flat, cgmath::Point3::new(10.5, 10.5, 20.0), axle, 8.0).is_none());
+    }
+
+    #[test]
+    fn wheel_on_ramp() {
+        let ramps = load(level::TestPreset::Ramps);
+        let center = cgmath::Point3::new(24.5, 10.5, 60.0);
+        let ground = surface_at(&ramps, center).0;
+        let place = |z, half_width| {
+            let axle = cgmath::vec3(half_width, 0.0, 0.0);
+            cast_wheel(
+                &ramps,
+                cgmath::Point3::new(center.x, center.y, z),
+                axle,
+                8.0,
+            )
+        };
+
+        let narrow = place(ground + 4.0, 0.5).unwrap();
+        assert!(narrow.normal.x < 0.0, "The ramp goes up along X");
+        assert_eq!(narrow.normal.y, 0.0);
+        // the wider wheel rests on the higher side of the slope
+        let wide = place(ground + 4.0, 6.0).unwrap();
+        assert!(wide.point.x > narrow.point.x);
+        assert!(wide.depth > narrow.depth);
+        assert!(place(ground + 20.0, 6.0).is_none());
+    }
+
+    #[test]
+    fn surface_across_seam() {
+        // the texel is floored, so a point right before the seam at zero
+        // is on the last column, where the truncation would put it on the first
+        let mut ramps = load(level::TestPreset::Ramps);
+        let width = ramps.size.0;
+        ramps.height[(10 * width + width - 1) as usize] = 100;
+        let last = ramps.get((width - 1, 10)).high();
+        assert_ne!(last, ramps.get((0, 10)).high());
+        let point = cgmath::Point3::new(-0.5, 10.5, 200.0);
+        assert_eq!(surface_at(&ramps, point).0, last);
+        assert_eq!(
+            surface_at(&ramps, point + cgmath::vec3(width as f32, 0.0, 0.0)).0,
+            last
+        );
+    }
+}
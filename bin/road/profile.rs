@@ -0,0 +1,105 @@
+//! State of the worlds kept between the runs: the player vehicle,
+//! and the terrain changes as a level patch. Each world is saved into
+//! its own file in the profile directory, when leaving the world.
+
+use crate::trace;
+use serde_derive::{Deserialize, Serialize};
+use vangers::level;
+
+use std::{fs::File, path::PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorldState {
+    pub car: String,
+    pub vehicle: trace::State,
+    #[serde(default)]
+    pub terrain: level::Patch,
+}
+
+pub struct Profile {
+    path: PathBuf,
+}
+
+impl Profile {
+    /// Returns `None` if the path is empty, and nothing is to be persisted.
+    pub fn new(path: PathBuf) -> Option<Self> {
+        if path.as_os_str().is_empty() {
+            None
+        } else {
+            Some(Profile { path })
+        }
+    }
+
+    fn file_path(&self, world: &str) -> PathBuf {
+        self.path.join(world).with_extension("ron")
+    }
+
+    pub fn load(&self, world: &str) -> Option<WorldState> {
+        let path = self.file_path(world);
+        let file = File::open(&path).ok()?;
+        match ron::de::from_reader(file) {
+            Ok(state) => {
+                log::info!("Restoring world {} from {}", world, path.display());
+                Some(state)
+            }
+            Err(e) => {
+                log::error!("Unable to parse {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, world: &str, state: &WorldState) {
+        let path = self.file_path(world);
+        let string = ron::ser::to_string_pretty(state, ron::ser::PrettyConfig::default()).unwrap();
+        let result =
+            std::fs::create_dir_all(&self.path).and_then(|()| std::fs::write(&path, string));
+        match result {
+            Ok(()) => log::info!(
+                "Saved world {} with {} changed terrain rows into {}",
+                world,
+                state.terrain.spans.len(),
+                path.display()
+            ),
+            Err(e) => log::error!("Unable to save {}: {}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join("vangers-profile-test");
+        let _ = std::fs::remove_dir_all(&path);
+        assert!(Profile::new(PathBuf::new()).is_none());
+        let profile = Profile::new(path.clone()).unwrap();
+        assert!(profile.load("Fostral").is_none());
+
+        let state = WorldState {
+            car: "OxidizeMonk".to_string(),
+            vehicle: trace::State {
+                position: [1.0, 2.0, 3.0],
+                orientation: [1.0, 0.0, 0.0, 0.0],
+                linear_velocity: [0.0; 3],
+                angular_velocity: [0.0; 3],
+            },
+            terrain: level::Patch {
+                spans: vec![level::Span {
+                    x: 4,
+                    y: 5,
+                    height: vec![10, 20],
+                    meta: vec![0, 1],
+                }],
+            },
+        };
+        profile.save("Fostral", &state);
+        let loaded = profile.load("Fostral").unwrap();
+        assert_eq!(loaded.car, state.car);
+        assert_eq!(loaded.vehicle.position, state.vehicle.position);
+        assert_eq!(loaded.terrain, state.terrain);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,181 @@
+//! Static props scattered over the world, drawn in frustum-culled chunks.
+
+use vangers::{
+    config::{self, scatter},
+    jobs, level, model,
+    render::{Batcher, WorldWrap},
+    space,
+};
+
+use cgmath::Rotation3 as _;
+use std::{collections::HashMap, sync::Arc};
+
+/// Size of the square area covered by a chunk, in texels.
+const CHUNK_SIZE: f32 = 256.0;
+/// Number of chunks worth a separate job when batching.
+const CHUNKS_PER_JOB: usize = 4;
+
+struct Chunk {
+    min: cgmath::Vector3<f32>,
+    max: cgmath::Vector3<f32>,
+    /// Index of the mesh and the transform of each prop.
+    props: Vec<(usize, space::Transform)>,
+}
+
+impl Chunk {
+    /// Check if the box, shifted by the offset, may be seen through the view-projection.
+    fn is_visible(&self, mx_vp: &cgmath::Matrix4<f32>, offset: cgmath::Vector3<f32>) -> bool {
+        let (min, max) = (self.min + offset, self.max + offset);
+        // the box is hidden if all of its corners are outside of the same frustum plane
+        let mut outside = [true; 5];
+        for i in 0..8 {
+            let corner = cgmath::vec3(
+                if i & 1 != 0 { max.x } else { min.x },
+                if i & 2 != 0 { max.y } else { min.y },
+                if i & 4 != 0 { max.z } else { min.z },
+            );
+            let p = mx_vp * corner.extend(1.0);
+            for (out, is_out) in
+                outside
+                    .iter_mut()
+                    .zip([p.x < -p.w, p.x > p.w, p.y < -p.w, p.y > p.w, p.w <= 0.0])
+            {
+                *out &= is_out;
+            }
+        }
+        !outside.iter().any(|&out| out)
+    }
+}
+
+fn load_meshes(
+    rules: &[scatter::Rule],
+    settings: &config::Settings,
+    device: &wgpu::Device,
+) -> Vec<Option<Arc<model::Mesh>>> {
+    rules
+        .iter()
+        .map(|rule| {
+            if !settings.check_path(&rule.model) {
+                log::warn!("Unable to find the prop model {}", rule.model);
+                return None;
+            }
+            match m3d::FullModel::load(settings.open_relative(&rule.model)) {
+                Ok(raw) => Some(model::load_c3d(raw.body, Some(&raw.shape.geometry), device)),
+                Err(e) => {
+                    log::warn!("Skipping the prop model {}: {}", rule.model, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+pub struct Props {
+    rules: Vec<scatter::Rule>,
+    /// Mesh of each rule, if it was loaded.
+    meshes: Vec<Option<Arc<model::Mesh>>>,
+    chunks: Vec<Chunk>,
+    count: usize,
+}
+
+impl Props {
+    /// Load the scatter rules and their models.
+    pub fn load(settings: &config::Settings, device: &wgpu::Device) -> Self {
+        let rules = scatter::load(scatter::SCATTER_PATH.as_ref());
+        let meshes = load_meshes(&rules, settings, device);
+        Props {
+            rules,
+            meshes,
+            chunks: Vec::new(),
+            count: 0,
+        }
+    }
+
+    /// Load the models again on the new device, after the old one is lost.
+    /// The props stay where they are.
+    pub fn recover(&mut self, settings: &config::Settings, device: &wgpu::Device) {
+        self.meshes = load_meshes(&self.rules, settings, device);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Scatter the props over the level of the world, replacing the old ones.
+    pub fn populate(&mut self, level: &level::Level, world: &str, seed: u64) {
+        profiling::scope!("Scatter Props");
+        self.chunks.clear();
+        self.count = 0;
+        let mut chunk_indices = HashMap::new();
+        for prop in level::scatter::scatter(level, world, &self.rules, seed) {
+            let mesh = match self.meshes[prop.rule] {
+                Some(ref mesh) => mesh,
+                None => continue,
+            };
+            let transform = space::Transform {
+                disp: prop.position,
+                rot: cgmath::Quaternion::from_angle_z(cgmath::Rad(prop.angle)),
+                scale: prop.scale,
+            };
+            let radius = mesh.bbox.radius * prop.scale;
+            let key = (
+                (prop.position.x / CHUNK_SIZE) as i32,
+                (prop.position.y / CHUNK_SIZE) as i32,
+            );
+            let index = *chunk_indices.entry(key).or_insert_with(|| {
+                self.chunks.push(Chunk {
+                    min: prop.position,
+                    max: prop.position,
+                    props: Vec::new(),
+                });
+                self.chunks.len() - 1
+            });
+            let chunk = &mut self.chunks[index];
+            for i in 0..3 {
+                chunk.min[i] = chunk.min[i].min(prop.position[i] - radius);
+                chunk.max[i] = chunk.max[i].max(prop.position[i] + radius);
+            }
+            chunk.props.push((prop.rule, transform));
+            self.count += 1;
+        }
+        log::info!(
+            "Scattered {} props in {} chunks",
+            self.count,
+            self.chunks.len()
+        );
+    }
+
+    /// Add the props of the visible chunks.
+    pub fn draw(&self, batcher: &mut Batcher, cam: &space::Camera, wrap: &WorldWrap) {
+        use cgmath::InnerSpace as _;
+
+        let mx_vp = cam.get_view_proj();
+        let base = &*batcher;
+        let parts = jobs::map_chunks(&self.chunks, CHUNKS_PER_JOB, |chunks| {
+            let mut part = base.fork();
+            for chunk in chunks {
+                let center = 0.5 * (chunk.min + chunk.max);
+                let radius = 0.5 * (chunk.max - chunk.min).magnitude();
+                for copy in wrap.copies(center, radius) {
+                    let offset = copy - center;
+                    if !chunk.is_visible(&mx_vp, offset) {
+                        continue;
+                    }
+                    for &(mesh_index, ref transform) in chunk.props.iter() {
+                        if let Some(ref mesh) = self.meshes[mesh_index] {
+                            let shifted = space::Transform {
+                                disp: transform.disp + offset,
+                                ..*transform
+                            };
+                            part.add_prop(mesh, &shifted);
+                        }
+                    }
+                }
+            }
+            part
+        });
+        for part in parts {
+            batcher.merge(part);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Smoke test of the whole stack, see `--smoke`.
+//!
+//! The game runs on a test level without a window, rendering into an offscreen
+//! target, while a script drives the player car around. It passes if nothing
+//! panics on the way, and the car gets somewhere.
+
+use crate::{
+    boilerplate::{self, Application as _, HarnessOptions},
+    game::Game,
+};
+use vangers::{
+    config,
+    render::{capture, GraphicsContext, ScreenTargets},
+};
+
+use cgmath::InnerSpace as _;
+use futures::executor::LocalPool;
+use std::sync::Arc;
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode as Key};
+
+/// Simulated duration of a frame, in seconds.
+pub const FRAME_TIME: f32 = 1.0 / 30.0;
+/// Distance the player has to travel for the test to pass.
+const MIN_TRAVEL: f32 = 1.0;
+pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Keys to press and release at the given frame out of the total.
+/// The car goes forward all the way, steering left and then right on the way.
+fn script(frame: u32, total: u32) -> Vec<(ElementState, Key)> {
+    let mut keys = Vec::new();
+    if frame == 0 {
+        keys.push((ElementState::Pressed, Key::W));
+    }
+    if frame == total / 3 {
+        keys.push((ElementState::Pressed, Key::A));
+    }
+    if frame == total / 2 {
+        keys.push((ElementState::Released, Key::A));
+        keys.push((ElementState::Pressed, Key::D));
+    }
+    if frame == 2 * total / 3 {
+        keys.push((ElementState::Released, Key::D));
+    }
+    keys
+}
+
+/// Find the adapter matching the settings, with no window to present to.
+pub fn find_adapter(settings: &config::Settings) -> wgpu::Adapter {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: settings.backend.to_wgpu(),
+        ..Default::default()
+    });
+    if settings.adapter.is_empty() {
+        LocalPool::new()
+            .run_until(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            }))
+            .expect("Unable to initialize GPU via the selected backend.")
+    } else {
+        instance
+            .enumerate_adapters(settings.backend.to_wgpu())
+            .find(|adapter| settings.matches_adapter(&adapter.get_info()))
+            .unwrap_or_else(|| {
+                panic!(
+                    "No adapter matching '{}' on {:?}, see `--list-adapters`",
+                    settings.adapter, settings.backend,
+                )
+            })
+    }
+}
+
+/// Create the device on the adapter, fitting the settings into its capabilities.
+pub fn init_headless(
+    adapter: &wgpu::Adapter,
+    mut settings: config::Settings,
+) -> (GraphicsContext, config::Settings) {
+    let mut task_pool = LocalPool::new();
+    settings
+        .render
+        .fit_downlevel(&adapter.get_downlevel_capabilities());
+    let (device, queue) = boilerplate::request_device(
+        adapter,
+        &settings.render,
+        settings.game.geometry.height,
+        &mut task_pool,
+    );
+    let gfx = GraphicsContext {
+        device: Arc::new(device),
+        downlevel_caps: adapter.get_downlevel_capabilities(),
+        queue: Arc::new(queue),
+        color_format: COLOR_FORMAT,
+        screen_size: wgpu::Extent3d {
+            width: settings.window.size[0],
+            height: settings.window.size[1],
+            depth_or_array_layers: 1,
+        },
+    };
+    (gfx, settings)
+}
+
+/// Read the pixels of the texture in `COLOR_FORMAT` back, tightly packed.
+pub fn read_back(gfx: &GraphicsContext, texture: &wgpu::Texture) -> Vec<u8> {
+    let extent = texture.size();
+    let bytes_per_row = wgpu::util::align_to(extent.width * 4, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let readback = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback"),
+        size: (bytes_per_row * extent.height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = gfx
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &readback,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        extent,
+    );
+    gfx.queue.submit(Some(encoder.finish()));
+
+    let slice = readback.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    gfx.device.poll(wgpu::Maintain::Wait);
+    receiver
+        .recv()
+        .unwrap()
+        .expect("Unable to read the pixels back");
+    let mapped = slice.get_mapped_range();
+    let pixels = mapped
+        .chunks(bytes_per_row as usize)
+        .flat_map(|row| &row[..extent.width as usize * 4])
+        .cloned()
+        .collect();
+    drop(mapped);
+    readback.unmap();
+    pixels
+}
+
+/// Run the game for the given number of frames. Returns true if the test passed.
+pub fn run(mut options: HarnessOptions, frames: u32) -> bool {
+    options.settings_overrides.extend(
+        [
+            "game.level=\"test:hills\"",
+            "game.cycle=\"\"",
+            "game.seed=1",
+            "profile_path=\"\"",
+        ]
+        .iter()
+        .map(|s| s.to_string()),
+    );
+    let settings = boilerplate::load_settings(&options);
+    let (gfx, settings) = init_headless(&find_adapter(&settings), settings);
+
+    let color = gfx
+        .device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("Smoke color"),
+            size: gfx.screen_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: COLOR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default());
+    let depth = boilerplate::create_depth_target(&gfx.device, gfx.screen_size);
+    let egui_context = egui::Context::default();
+
+    let mut game = Game::new(&settings, &gfx, None);
+    let start = match game.player_position() {
+        Some(pos) => pos,
+        None => {
+            log::error!("Smoke test: no player car");
+            return false;
+        }
+    };
+    for frame in 0..frames {
+        for (state, key) in script(frame, frames) {
+            #[allow(deprecated)]
+            game.on_key(KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: Default::default(),
+            });
+        }
+        game.update(&gfx.device, &gfx.queue, FRAME_TIME);
+        let _ = egui_context.run(egui::RawInput::default(), |context| game.draw_ui(context));
+        let targets = ScreenTargets {
+            extent: gfx.screen_size,
+            color: &color,
+            depth: &depth,
+        };
+        if options.renderdoc == Some(frame + 1) {
+            capture::request();
+        }
+        let capture = capture::start(&gfx.device);
+        let command_buffer = game.draw(&gfx.device, targets);
+        gfx.queue.submit(Some(command_buffer));
+        drop(capture);
+        gfx.device.poll(wgpu::Maintain::Wait);
+    }
+
+    let travel = match game.player_position() {
+        Some(pos) => (pos - start).magnitude(),
+        None => 0.0,
+    };
+    if travel < MIN_TRAVEL {
+        log::error!(
+            "Smoke test: the player only moved by {} in {} frames",
+            travel,
+            frames
+        );
+        false
+    } else {
+        log::info!(
+            "Smoke test: the player moved by {} in {} frames",
+            travel,
+            frames
+        );
+        true
+    }
+}
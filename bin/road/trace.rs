@@ -0,0 +1,329 @@
+//! Physics traces: the per-frame state of a mechos driven by an input sequence.
+//!
+//! A trace is recorded with `--record`, or captured from the original engine
+//! by other means. Replaying it with `--compare` feeds the recorded inputs
+//! back into the physics, and reports how far the result drifts from the
+//! recorded states over time.
+
+use serde_derive::{Deserialize, Serialize};
+use vangers::locale::tr_fmt;
+
+use std::{fs::File, path::PathBuf};
+
+/// Inputs applied to the car at a frame.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Input {
+    /// Frame duration, in seconds.
+    pub delta: f32,
+    pub motor: f32,
+    pub rudder: f32,
+    pub roll: f32,
+    #[serde(default)]
+    pub brake: bool,
+    #[serde(default)]
+    pub turbo: bool,
+    /// Jump power, if released at this frame.
+    #[serde(default)]
+    pub jump: Option<f32>,
+}
+
+/// Physical state of the car, in world space.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct State {
+    pub position: [f32; 3],
+    /// Quaternion, scalar part first.
+    pub orientation: [f32; 4],
+    pub linear_velocity: [f32; 3],
+    pub angular_velocity: [f32; 3],
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Frame {
+    pub input: Input,
+    /// State after the input is applied.
+    pub state: State,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trace {
+    pub world: String,
+    pub car: String,
+    pub start: State,
+    pub frames: Vec<Frame>,
+}
+
+/// Difference between the recorded and the simulated states.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Divergence {
+    pub position: f32,
+    /// Angle between the orientations, in radians.
+    pub orientation: f32,
+    pub linear_velocity: f32,
+    pub angular_velocity: f32,
+}
+
+impl Divergence {
+    /// Compare the states, with positions wrapped around the world of the given size.
+    pub fn new(expected: &State, actual: &State, wrap: [f32; 2]) -> Self {
+        let mut position = 0.0;
+        for i in 0..3 {
+            let mut d = actual.position[i] - expected.position[i];
+            if let Some(&size) = wrap.get(i) {
+                d = (d + 0.5 * size).rem_euclid(size) - 0.5 * size;
+            }
+            position += d * d;
+        }
+        let dot = expected
+            .orientation
+            .iter()
+            .zip(actual.orientation.iter())
+            .map(|(a, b)| a * b)
+            .sum::<f32>();
+        Divergence {
+            position: position.sqrt(),
+            orientation: 2.0 * dot.abs().min(1.0).acos(),
+            linear_velocity: distance(&expected.linear_velocity, &actual.linear_velocity),
+            angular_velocity: distance(&expected.angular_velocity, &actual.angular_velocity),
+        }
+    }
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// Divergence of a replay over time.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub frames: Vec<Divergence>,
+}
+
+impl Report {
+    /// First frame at which the position drifted further than the threshold.
+    pub fn first_divergent(&self, threshold: f32) -> Option<usize> {
+        self.frames.iter().position(|d| d.position > threshold)
+    }
+
+    pub fn max(&self) -> Divergence {
+        self.frames
+            .iter()
+            .fold(Divergence::default(), |m, d| Divergence {
+                position: m.position.max(d.position),
+                orientation: m.orientation.max(d.orientation),
+                linear_velocity: m.linear_velocity.max(d.linear_velocity),
+                angular_velocity: m.angular_velocity.max(d.angular_velocity),
+            })
+    }
+
+    fn log(&self) {
+        let max = self.max();
+        log::info!(
+            "Trace comparison over {} frames: max position {}, orientation {}, \
+            linear velocity {}, angular velocity {}",
+            self.frames.len(),
+            max.position,
+            max.orientation,
+            max.linear_velocity,
+            max.angular_velocity,
+        );
+        match self.first_divergent(DIVERGENCE_THRESHOLD) {
+            Some(frame) => log::warn!(
+                "Position diverged by more than {} at frame {}",
+                DIVERGENCE_THRESHOLD,
+                frame
+            ),
+            None => log::info!("Position stayed within {}", DIVERGENCE_THRESHOLD),
+        }
+    }
+}
+
+/// Position divergence that is considered a mismatch, in world units.
+const DIVERGENCE_THRESHOLD: f32 = 1.0;
+
+pub enum Command {
+    Record(PathBuf),
+    Compare(PathBuf),
+}
+
+pub enum Session {
+    Record {
+        path: PathBuf,
+        trace: Trace,
+    },
+    Compare {
+        trace: Trace,
+        next: usize,
+        report: Report,
+    },
+}
+
+impl Session {
+    pub fn record(path: PathBuf, world: String, car: String, start: State) -> Self {
+        log::info!("Recording trace into {}", path.display());
+        Session::Record {
+            path,
+            trace: Trace {
+                world,
+                car,
+                start,
+                frames: Vec::new(),
+            },
+        }
+    }
+
+    pub fn compare(path: PathBuf) -> Result<Self, String> {
+        let file = File::open(&path)
+            .map_err(|e| format!("Unable to open trace {}: {}", path.display(), e))?;
+        let trace: Trace = ron::de::from_reader(file)
+            .map_err(|e| format!("Unable to parse trace {}: {}", path.display(), e))?;
+        log::info!(
+            "Comparing against trace {} with {} frames",
+            path.display(),
+            trace.frames.len()
+        );
+        Ok(Session::Compare {
+            trace,
+            next: 0,
+            report: Report::default(),
+        })
+    }
+
+    /// The input to replay at the current frame, if any.
+    pub fn replay_input(&self) -> Option<Input> {
+        match *self {
+            Session::Record { .. } => None,
+            Session::Compare {
+                ref trace, next, ..
+            } => trace.frames.get(next).map(|f| f.input),
+        }
+    }
+
+    /// Register the outcome of a frame.
+    pub fn advance(&mut self, input: Input, state: State, wrap: [f32; 2]) {
+        match *self {
+            Session::Record { ref mut trace, .. } => {
+                trace.frames.push(Frame { input, state });
+            }
+            Session::Compare {
+                ref trace,
+                ref mut next,
+                ref mut report,
+            } => {
+                if let Some(frame) = trace.frames.get(*next) {
+                    report
+                        .frames
+                        .push(Divergence::new(&frame.state, &state, wrap));
+                    *next += 1;
+                    if *next == trace.frames.len() {
+                        report.log();
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn draw_ui(&self, ui: &mut egui::Ui) {
+        match *self {
+            Session::Record { ref trace, .. } => {
+                ui.label(tr_fmt("Recording: {} frames", &[&trace.frames.len()]));
+            }
+            Session::Compare {
+                ref trace,
+                next,
+                ref report,
+            } => {
+                ui.label(tr_fmt(
+                    "Replay: {}/{} frames",
+                    &[&next, &trace.frames.len()],
+                ));
+                let max = report.max();
+                ui.label(tr_fmt(
+                    "Max divergence: {} pos, {} rad",
+                    &[
+                        &format!("{:.3}", max.position),
+                        &format!("{:.3}", max.orientation),
+                    ],
+                ));
+                let points = egui::plot::PlotPoints::from_ys_f32(
+                    &report.frames.iter().map(|d| d.position).collect::<Vec<_>>(),
+                );
+                egui::plot::Plot::new("Divergence")
+                    .height(100.0)
+                    .allow_zoom(false)
+                    .allow_scroll(false)
+                    .allow_drag(false)
+                    .include_y(0.0)
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(egui::plot::Line::new(points).name("position"));
+                        plot_ui
+                            .hline(egui::plot::HLine::new(DIVERGENCE_THRESHOLD).name("threshold"));
+                    });
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Session::Record {
+            ref path,
+            ref trace,
+        } = *self
+        {
+            let string =
+                ron::ser::to_string_pretty(trace, ron::ser::PrettyConfig::default()).unwrap();
+            match std::fs::write(path, string) {
+                Ok(()) => log::info!(
+                    "Saved {} trace frames into {}",
+                    trace.frames.len(),
+                    path.display()
+                ),
+                Err(e) => log::error!("Unable to save trace {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATE: State = State {
+        position: [10.0, 20.0, 30.0],
+        orientation: [1.0, 0.0, 0.0, 0.0],
+        linear_velocity: [1.0, 0.0, 0.0],
+        angular_velocity: [0.0, 0.0, 1.0],
+    };
+
+    #[test]
+    fn divergence() {
+        let wrap = [100.0, 100.0];
+        assert_eq!(Divergence::new(&STATE, &STATE, wrap), Divergence::default());
+
+        // crossing the world boundary is not a divergence
+        let wrapped = State {
+            position: [110.0, -80.0, 30.0],
+            ..STATE
+        };
+        assert!(Divergence::new(&STATE, &wrapped, wrap).position < 1e-4);
+
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let turned = State {
+            position: [13.0, 24.0, 30.0],
+            orientation: [half, 0.0, 0.0, half],
+            ..STATE
+        };
+        let d = Divergence::new(&STATE, &turned, wrap);
+        assert!((d.position - 5.0).abs() < 1e-4);
+        assert!((d.orientation - std::f32::consts::FRAC_PI_2).abs() < 1e-3);
+
+        let report = Report {
+            frames: vec![Divergence::default(), d],
+        };
+        assert_eq!(report.first_divergent(DIVERGENCE_THRESHOLD), Some(1));
+    }
+}
@@ -0,0 +1,270 @@
+use vangers::{
+    config, level,
+    render::{GraphicsContext, Render, Stats},
+};
+
+use std::{fs::File, path::PathBuf, sync::mpsc, thread};
+
+/// Duration of the fade out, and then the fade in, in seconds.
+const FADE_TIME: f32 = 0.5;
+
+pub struct LoadedWorld {
+    pub name: String,
+    pub level: level::Level,
+    pub render: Render,
+    /// What the render is created from, to re-create it after a device loss.
+    pub config: level::LevelConfig,
+    pub palette: [[u8; 4]; 0x100],
+}
+
+pub struct Request {
+    pub name: String,
+    pub ini_path: PathBuf,
+    pub palette: File,
+    pub render: config::settings::Render,
+    pub geometry: config::settings::Geometry,
+}
+
+fn load(request: Request, gfx: &GraphicsContext) -> LoadedWorld {
+    profiling::scope!("Load World");
+    let level_config = level::LevelConfig::load(&request.ini_path);
+    let pal_data = level::read_palette(request.palette, Some(&level_config.terrains));
+    let mut render = Render::new(
+        gfx,
+        &level_config,
+        &pal_data,
+        &request.render,
+        &request.geometry,
+    );
+    let level = level::load(&level_config, &request.geometry);
+    upload(&mut render, &level, gfx);
+
+    LoadedWorld {
+        name: request.name,
+        level,
+        render,
+        config: level_config,
+        palette: pal_data,
+    }
+}
+
+/// Upload the level and bake the acceleration structures up front,
+/// so that the first frames with it don't stall.
+pub fn upload(render: &mut Render, level: &level::Level, gfx: &GraphicsContext) {
+    loop {
+        let mut encoder = gfx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("World preload"),
+            });
+        render
+            .terrain
+            .update_dirty(&mut encoder, level, &gfx.device, &mut Stats::default());
+        gfx.queue.submit(Some(encoder.finish()));
+        if render.terrain.dirty_rects.is_empty() {
+            break;
+        }
+    }
+}
+
+/// Everything needed to load any of the known worlds.
+pub struct Catalog {
+    pub worlds: config::worlds::Worlds,
+    pub geometry_overrides: config::worlds::GeometryOverrides,
+    data_path: PathBuf,
+    palette_path: PathBuf,
+    render: config::settings::Render,
+    geometry: config::settings::Geometry,
+}
+
+impl Catalog {
+    pub fn new(settings: &config::Settings) -> Self {
+        Catalog {
+            worlds: if settings.has_game_data() {
+                config::worlds::load(settings.open_relative("wrlds.dat"))
+            } else {
+                config::worlds::Worlds::default()
+            },
+            geometry_overrides: config::worlds::GeometryOverrides::load(
+                config::worlds::GEOMETRY_PATH,
+            ),
+            data_path: settings.data_path.clone(),
+            palette_path: settings.palette_path(),
+            render: settings.render.clone(),
+            geometry: settings.game.geometry.clone(),
+        }
+    }
+
+    /// Level geometry of the world, with the overrides applied.
+    pub fn geometry(&self, name: &str) -> config::settings::Geometry {
+        self.geometry_overrides.get(name, &self.geometry)
+    }
+
+    pub fn level_config(&self, name: &str) -> Option<level::LevelConfig> {
+        let ini_name = self.worlds.get(name)?;
+        Some(level::LevelConfig::load(&self.data_path.join(ini_name)))
+    }
+
+    pub fn request(&self, name: &str) -> Option<Request> {
+        let ini_name = self.worlds.get(name)?;
+        let palette = match File::open(&self.palette_path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Unable to open palette: {}", e);
+                return None;
+            }
+        };
+        Some(Request {
+            name: name.to_string(),
+            ini_path: self.data_path.join(ini_name),
+            palette,
+            render: self.render.clone(),
+            geometry: self.geometry(name),
+        })
+    }
+}
+
+/// State of a job running on a background thread.
+pub enum Poll<T> {
+    Pending,
+    Ready(T),
+    /// The thread is gone without a result, e.g. it panicked.
+    Failed,
+}
+
+/// Job running on a background thread, polled from the main one.
+pub struct Background<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T: Send + 'static> Background<T> {
+    pub fn spawn(job: impl FnOnce() -> T + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(job());
+        });
+        Background { receiver }
+    }
+}
+
+impl<T> Background<T> {
+    pub fn poll(&self) -> Poll<T> {
+        match self.receiver.try_recv() {
+            Ok(result) => Poll::Ready(result),
+            Err(mpsc::TryRecvError::Empty) => Poll::Pending,
+            Err(mpsc::TryRecvError::Disconnected) => Poll::Failed,
+        }
+    }
+}
+
+enum State {
+    Idle,
+    Loading {
+        name: String,
+        job: Background<LoadedWorld>,
+    },
+    FadeOut(Box<LoadedWorld>),
+    FadeIn,
+}
+
+/// Loads worlds in the background, and cross-fades to them once ready.
+pub struct Switcher {
+    state: State,
+    fade: f32,
+}
+
+impl Switcher {
+    pub fn new() -> Self {
+        Switcher {
+            state: State::Idle,
+            fade: 0.0,
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        !matches!(self.state, State::Idle)
+    }
+
+    pub fn loading_name(&self) -> Option<&str> {
+        match self.state {
+            State::Loading { ref name, .. } => Some(name),
+            State::FadeOut(ref world) => Some(&world.name),
+            _ => None,
+        }
+    }
+
+    /// Opacity of the black overlay.
+    pub fn fade(&self) -> f32 {
+        self.fade
+    }
+
+    pub fn start(&mut self, request: Request, gfx: &GraphicsContext) {
+        log::info!("Preloading world {}", request.name);
+        let name = request.name.clone();
+        let gfx = gfx.clone();
+        let job = Background::spawn(move || load(request, &gfx));
+        self.state = State::Loading { name, job };
+    }
+
+    /// Advance the transition. Returns the new world when it's time to swap it in.
+    pub fn update(&mut self, delta: f32) -> Option<LoadedWorld> {
+        match self.state {
+            State::Idle => None,
+            State::Loading { ref name, ref job } => {
+                match job.poll() {
+                    Poll::Ready(world) => self.state = State::FadeOut(Box::new(world)),
+                    Poll::Pending => {}
+                    Poll::Failed => {
+                        log::error!("Unable to load world {}", name);
+                        self.state = State::Idle;
+                    }
+                }
+                None
+            }
+            State::FadeOut(_) => {
+                self.fade += delta / FADE_TIME;
+                if self.fade < 1.0 {
+                    return None;
+                }
+                self.fade = 1.0;
+                match std::mem::replace(&mut self.state, State::FadeIn) {
+                    State::FadeOut(world) => Some(*world),
+                    _ => unreachable!(),
+                }
+            }
+            State::FadeIn => {
+                self.fade -= delta / FADE_TIME;
+                if self.fade <= 0.0 {
+                    self.fade = 0.0;
+                    self.state = State::Idle;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait<T>(job: &Background<T>) -> Poll<T> {
+        loop {
+            match job.poll() {
+                Poll::Pending => thread::yield_now(),
+                other => return other,
+            }
+        }
+    }
+
+    #[test]
+    fn background_job() {
+        let job = Background::spawn(|| 2 + 2);
+        assert!(matches!(wait(&job), Poll::Ready(4)));
+        // the result is taken only once
+        assert!(matches!(job.poll(), Poll::Failed));
+
+        let failing = Background::spawn(|| -> i32 { panic!("Expected failure") });
+        assert!(matches!(wait(&failing), Poll::Failed));
+    }
+}
@@ -0,0 +1,63 @@
+//! Load a level and print its statistics.
+//!
+//! The argument is either a generated world, like "test:hills",
+//! or a path to the `.ini` of a game world.
+//!
+//! ```bash
+//! cargo run --example level_stats -- test:cave
+//! ```
+
+use vangers::{config::settings::Geometry, level};
+
+fn main() {
+    let name = std::env::args().nth(1).unwrap_or("test:hills".to_string());
+    let config = match level::TestPreset::from_level_name(&name) {
+        Some(preset) => level::LevelConfig::new_test_preset(preset),
+        None => level::LevelConfig::load(name.as_ref()),
+    };
+    let level = level::load(&config, &Geometry::default());
+
+    let mut terrain_counts = vec![0usize; level.terrains.len().max(1)];
+    let (mut min, mut max, mut sum) = (f32::MAX, f32::MIN, 0.0);
+    let mut dual = 0;
+    for y in 0..level.size.1 {
+        for x in 0..level.size.0 {
+            let texel = level.get((x, y));
+            let top = match texel {
+                level::Texel::Single(point) => point,
+                level::Texel::Dual { high, .. } => {
+                    dual += 1;
+                    high
+                }
+            };
+            min = min.min(top.0);
+            max = max.max(top.0);
+            sum += top.0;
+            if let Some(count) = terrain_counts.get_mut(top.1 as usize) {
+                *count += 1;
+            }
+        }
+    }
+
+    let total = (level.size.0 * level.size.1) as f32;
+    println!("Level {}: {}x{}", name, level.size.0, level.size.1);
+    println!(
+        "Height: {} to {}, {:.1} on average, out of {}",
+        min,
+        max,
+        sum / total,
+        level.geometry.height
+    );
+    println!("Two layers: {:.1}%", 100.0 * dual as f32 / total);
+    let flood = level.flood_map.iter().copied();
+    println!(
+        "Flood: {} to {}",
+        flood.clone().min().unwrap_or(0),
+        flood.max().unwrap_or(0)
+    );
+    for (terrain, &count) in terrain_counts.iter().enumerate() {
+        if count != 0 {
+            println!("Terrain {}: {:.1}%", terrain, 100.0 * count as f32 / total);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Load an M3D model and recompute its physics from the collision shapes.
+//!
+//! Without arguments, the built-in test car is used. Given the second path,
+//! the model with the new physics is saved there.
+//!
+//! ```bash
+//! cargo run --example m3d_physics -- path/to/car.m3d fixed.m3d
+//! ```
+
+use std::fs::File;
+
+fn print(title: &str, model: &m3d::FullModel) {
+    let physics = &model.body.physics;
+    println!("{}:", title);
+    println!("\tvolume {}", physics.volume);
+    println!("\tcenter of mass {:?}", physics.rcm);
+    println!("\tinertia {:?}", physics.jacobi);
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut model = match args.first() {
+        Some(path) => m3d::FullModel::load(File::open(path).unwrap()).unwrap(),
+        None => vangers::config::fallback::car_model(),
+    };
+    println!(
+        "Model with {} wheels, {} debris, {} shape polygons",
+        model.wheels.len(),
+        model.debris.len(),
+        model.shape.geometry.polygons.len()
+    );
+
+    print("Loaded", &model);
+    let count = model.recompute_physics();
+    print("Recomputed", &model);
+    println!("{} meshes updated", count);
+
+    if let Some(output) = args.get(1) {
+        model.save(File::create(output).unwrap());
+        println!("Saved into {}", output);
+    }
+}
@@ -0,0 +1,205 @@
+//! Render one frame of a generated world with the test car, without a window,
+//! and save it into a PNG.
+//!
+//! The render settings come from the template, and the backend can be picked
+//! with `WGPU_BACKEND`, e.g. "gl" on the machines without a Vulkan driver.
+//!
+//! ```bash
+//! cargo run --example offscreen -- frame.png
+//! ```
+
+use vangers::{config, level, render, space};
+
+use futures::executor::LocalPool;
+use std::{fs::File, sync::Arc};
+
+const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+const EXTENT: wgpu::Extent3d = wgpu::Extent3d {
+    width: 640,
+    height: 400,
+    depth_or_array_layers: 1,
+};
+
+fn create_context(settings: &config::Settings) -> render::GraphicsContext {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY),
+        ..Default::default()
+    });
+    let mut task_pool = LocalPool::new();
+    let adapter = task_pool
+        .run_until(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+        .expect("Unable to find an adapter");
+    println!("Using {:?}", adapter.get_info().name);
+    let limits = settings
+        .render
+        .get_device_limits(&adapter.limits(), settings.game.geometry.height);
+    let (device, queue) = task_pool
+        .run_until(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits,
+            },
+            None,
+        ))
+        .expect("Unable to create the device");
+    render::GraphicsContext {
+        device: Arc::new(device),
+        queue: Arc::new(queue),
+        downlevel_caps: adapter.get_downlevel_capabilities(),
+        color_format: COLOR_FORMAT,
+        screen_size: EXTENT,
+    }
+}
+
+fn create_target(device: &wgpu::Device, format: wgpu::TextureFormat) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: EXTENT,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn read_back(gfx: &render::GraphicsContext, texture: &wgpu::Texture) -> Vec<u8> {
+    let bytes_per_row = wgpu::util::align_to(EXTENT.width * 4, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (bytes_per_row * EXTENT.height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = gfx
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        EXTENT,
+    );
+    gfx.queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    gfx.device.poll(wgpu::Maintain::Wait);
+    let pixels = slice
+        .get_mapped_range()
+        .chunks(bytes_per_row as usize)
+        .flat_map(|row| row[..EXTENT.width as usize * 4].to_vec())
+        .collect();
+    buffer.unmap();
+    pixels
+}
+
+fn main() {
+    let output = std::env::args().nth(1).unwrap_or("frame.png".to_string());
+    let file = File::open("config/settings.template.ron").unwrap();
+    let settings: config::Settings = ron::de::from_reader(file).unwrap();
+    let gfx = create_context(&settings);
+
+    let level_config = level::LevelConfig::new_test_preset(level::TestPreset::Hills);
+    let level = level::load(&level_config, &settings.game.geometry);
+    let palette = settings.load_palette(Some(&level_config.terrains));
+    let mut render = render::Render::new(
+        &gfx,
+        &level_config,
+        &palette,
+        &settings.render,
+        &settings.game.geometry,
+    );
+    let car = config::fallback::car(
+        &gfx.device,
+        &render.object,
+        settings.game.physics.shape_sampling,
+    );
+
+    let center = (level.size.0 / 2, level.size.1 / 2);
+    let ground = level.get(center).high();
+    let transform = space::Transform {
+        disp: cgmath::vec3(center.0 as f32, center.1 as f32, ground + 16.0 * car.scale),
+        rot: cgmath::One::one(),
+        scale: car.scale,
+    };
+    let camera = space::Camera {
+        loc: transform.disp + cgmath::vec3(0.0, -200.0, 150.0),
+        rot: cgmath::Rotation3::from_angle_x(cgmath::Deg(55.0)),
+        handedness: settings.render.handedness,
+        proj: space::Projection::Perspective(cgmath::PerspectiveFov {
+            fovy: cgmath::Deg(45.0).into(),
+            aspect: EXTENT.width as f32 / EXTENT.height as f32,
+            near: 1.0,
+            far: 1000.0,
+        }),
+    };
+
+    let color = create_target(&gfx.device, COLOR_FORMAT);
+    let depth = create_target(&gfx.device, render::DEPTH_FORMAT);
+    let mut batcher = render::Batcher::new();
+    batcher.add_model(
+        &car.model,
+        &transform,
+        None,
+        render::object::BodyColor::Green,
+    );
+
+    // the level data goes to the GPU in pieces, one per update
+    while !render.terrain.dirty_rects.is_empty() {
+        let mut encoder = gfx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        render.terrain.update_dirty(
+            &mut encoder,
+            &level,
+            &gfx.device,
+            &mut render::Stats::default(),
+        );
+        gfx.queue.submit(Some(encoder.finish()));
+    }
+
+    let mut encoder = gfx
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    render.draw_world(
+        &mut encoder,
+        &mut batcher,
+        &level,
+        &camera,
+        render::ScreenTargets {
+            extent: EXTENT,
+            color: &color.create_view(&wgpu::TextureViewDescriptor::default()),
+            depth: &depth.create_view(&wgpu::TextureViewDescriptor::default()),
+        },
+        None,
+        &gfx.device,
+    );
+    gfx.queue.submit(Some(encoder.finish()));
+
+    let pixels = read_back(&gfx, &color);
+    let mut encoder =
+        png::Encoder::new(File::create(&output).unwrap(), EXTENT.width, EXTENT.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(&pixels)
+        .unwrap();
+    println!(
+        "{} terrain dispatches, {} object draws of {} instances",
+        render.stats.terrain_dispatches,
+        render.stats.object_draw_calls,
+        render.stats.object_instances
+    );
+    println!("Saved into {}", output);
+}
@@ -0,0 +1,148 @@
+//! Optional egui overlay with the tweak panels, drawn on top of the 3D layer.
+//! The input is forwarded by the host with `rv_ui_event()`.
+
+use std::time::Instant;
+use vangers::locale::tr;
+
+// Kinds of the input events, see `UiEvent`.
+/// The pointer moved to `x`, `y`.
+const POINTER_MOVED: u32 = 0;
+/// A `button` was pressed or released at `x`, `y`.
+const POINTER_BUTTON: u32 = 1;
+/// Scrolled by `x`, `y`.
+const SCROLL: u32 = 2;
+/// A character was typed, given as a Unicode scalar in `button`.
+const TEXT: u32 = 3;
+
+/// Input event coming from the host, in physical pixels.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct UiEvent {
+    kind: u32,
+    x: f32,
+    y: f32,
+    /// Mouse button: 0 = primary, 1 = secondary, 2 = middle.
+    button: u32,
+    pressed: bool,
+}
+
+impl UiEvent {
+    fn translate(&self, pointer: &mut egui::Pos2) -> Option<egui::Event> {
+        Some(match self.kind {
+            POINTER_MOVED => {
+                *pointer = egui::pos2(self.x, self.y);
+                egui::Event::PointerMoved(*pointer)
+            }
+            POINTER_BUTTON => {
+                *pointer = egui::pos2(self.x, self.y);
+                egui::Event::PointerButton {
+                    pos: *pointer,
+                    button: match self.button {
+                        0 => egui::PointerButton::Primary,
+                        1 => egui::PointerButton::Secondary,
+                        2 => egui::PointerButton::Middle,
+                        _ => return None,
+                    },
+                    pressed: self.pressed,
+                    modifiers: egui::Modifiers::NONE,
+                }
+            }
+            SCROLL => egui::Event::Scroll(egui::vec2(self.x, self.y)),
+            TEXT => egui::Event::Text(char::from_u32(self.button)?.to_string()),
+            other => {
+                log::warn!("Unknown UI event kind {}", other);
+                return None;
+            }
+        })
+    }
+}
+
+pub struct DebugUi {
+    pub visible: bool,
+    context: egui::Context,
+    /// Created on the first use, since most of the time the overlay is hidden.
+    pass: Option<egui_wgpu_backend::RenderPass>,
+    events: Vec<egui::Event>,
+    pointer: egui::Pos2,
+    start: Instant,
+}
+
+impl DebugUi {
+    pub fn new() -> Self {
+        DebugUi {
+            visible: false,
+            context: egui::Context::default(),
+            pass: None,
+            events: Vec::new(),
+            pointer: egui::Pos2::ZERO,
+            start: Instant::now(),
+        }
+    }
+
+    /// Queue an input event. Returns true if the UI wants to consume it,
+    /// so that the host doesn't process it further.
+    pub fn add_event(&mut self, event: &UiEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        if let Some(event) = event.translate(&mut self.pointer) {
+            self.events.push(event);
+        }
+        match event.kind {
+            TEXT => self.context.wants_keyboard_input(),
+            _ => self.context.wants_pointer_input() || self.context.is_pointer_over_area(),
+        }
+    }
+
+    /// Record the UI commands on top of the `target`.
+    pub fn draw(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        gfx: &vangers::render::GraphicsContext,
+        render: &mut vangers::render::Render,
+        level: &mut vangers::level::Level,
+    ) {
+        let raw_input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(gfx.screen_size.width as f32, gfx.screen_size.height as f32),
+            )),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            events: self.events.drain(..).collect(),
+            ..Default::default()
+        };
+        let output = self.context.run(raw_input, |context| {
+            egui::SidePanel::right("Tweaks").show(context, |ui| {
+                ui.group(|ui| {
+                    ui.label(tr("Level:"));
+                    level.draw_ui(ui);
+                });
+                ui.group(|ui| {
+                    ui.label(tr("Renderer:"));
+                    render.draw_ui(ui);
+                });
+                ui.group(|ui| {
+                    ui.label(tr("Statistics:"));
+                    render.stats.draw_ui(ui);
+                });
+            });
+        });
+
+        let pass = self.pass.get_or_insert_with(|| {
+            egui_wgpu_backend::RenderPass::new(&gfx.device, gfx.color_format, 1)
+        });
+        let primitives = self.context.tessellate(output.shapes);
+        let screen_descriptor = egui_wgpu_backend::ScreenDescriptor {
+            physical_width: gfx.screen_size.width,
+            physical_height: gfx.screen_size.height,
+            scale_factor: 1.0,
+        };
+        pass.update_buffers(&gfx.device, &gfx.queue, &primitives, &screen_descriptor);
+        pass.add_textures(&gfx.device, &gfx.queue, &output.textures_delta)
+            .unwrap();
+        pass.execute(encoder, target, &primitives, &screen_descriptor, None)
+            .unwrap();
+        pass.remove_textures(output.textures_delta).unwrap();
+    }
+}
@@ -1,9 +1,44 @@
 /*! Rusty Vangers FFI bindings.
 
-Matches "lib/renderer/src/renderer/scene/rust/vange_rs.h"
+The C header is generated into "vange_rs.h" with cbindgen, see "cbindgen.toml",
+and copied into "lib/renderer/src/renderer/scene/rust/vange_rs.h" of the game.
 See https://github.com/KranX/Vangers/pull/517
 
+The functions working on the active map log an error without one, and return
+early with zeros or false instead of unwinding into the host.
+
 Changelog:
+  3.23: The texel queries take a const `Context`, and the texel coordinates wrap around the map.
+  3.22: Draw with more palettes, uploaded by `rv_map_set_palette_layer()` and selected
+        with `rv_map_select_palette_layer()` and `rv_model_instance_set_palette_layer()`.
+  3.21: Shade the overlays of the host with the shadow map, found by `rv_get_shadow_info()`.
+  3.20: Query the terrain for the gameplay with `rv_map_trace_ray()` and `rv_map_get_height_fast()`.
+  3.19: Draw the top-down silhouettes of models for the icons with `rv_model_get_pictogram()`.
+  3.18: Query the visible texels of the map with `rv_camera_get_visible_rect()`.
+  3.17: Read and write single texels of the map with `rv_map_get_height()`,
+        `rv_map_set_height()`, `rv_map_get_meta()`, and `rv_map_set_meta()`.
+  3.16: Estimate the GPU memory usage with `rv_get_memory_report()`,
+        compared against `memory_budget_mb` of the render config.
+  3.15: Query the bounds of models with `rv_model_get_bounds()`,
+        and of the placed instances with `rv_model_instance_get_world_bounds()`.
+  3.14: Render into the palette indices with `rv_set_render_palette()`,
+        reading them back with `rv_read_palette_indices()`.
+  3.13: Save the scene for the offline rendering with `rv_scene_snapshot()`.
+  3.12: Apply many data and palette updates at once with `rv_map_update_batch()`,
+        of the kinds `RV_MAP_UPDATE_DATA` and `RV_MAP_UPDATE_PALETTE`.
+  3.11: Edit the water level of a map section with `rv_map_set_flood()`.
+  3.10: Query what the renderer supports with `rv_query_capabilities()`.
+  3.9: Free camera helpers `rv_camera_orbit()`, `rv_camera_pan()`, and `rv_camera_zoom()`,
+       matching the controls of the level viewer.
+  3.8: Debug overlay with `rv_debug_ui_toggle()` and `rv_ui_event()`,
+       available with the "debug-ui" feature.
+  3.7: Recover from the device loss with `rv_device_recover()`, reported by
+       `rv_set_device_lost_callback()` and `rv_is_device_lost()`.
+  3.6: Interpolate instance transforms with `rv_model_instance_set_transform_interpolated()`
+       and `rv_set_interpolation_alpha()`.
+  3.5: Teleport model instances with `rv_model_instance_teleport()`.
+  3.4: Multiple maps, selected with `rv_map_activate()`.
+  3.3: Render statistics.
   3.2: Control model visibility.
   3.1: Expose render config path.
   2.1: Transform scales, model APIs.
@@ -11,20 +46,24 @@ Changelog:
   0.1: Basic version.
 !*/
 
+#[cfg(feature = "debug-ui")]
+mod debug_ui;
+
 use futures::executor::LocalPool;
 use slotmap::{DefaultKey, Key as _, SlotMap};
 use std::{
+    collections::HashMap,
     ffi::{CStr, CString},
     fs::File,
-    mem,
+    ops,
     os::raw,
     ptr, slice,
     sync::Arc,
 };
 
-// Update this whenever C header changes
+// Update this whenever C header changes, together with the changelog above
 #[no_mangle]
-pub static rv_api_3: i32 = 2;
+pub static rv_api_3: i32 = 23;
 
 #[repr(C)]
 #[derive(Default)]
@@ -59,6 +98,81 @@ pub struct Rect {
     height: i32,
 }
 
+impl Transform {
+    fn to_native(&self) -> vangers::space::Transform {
+        vangers::space::Transform {
+            disp: cgmath::vec3(self.position.x, self.position.y, self.position.z),
+            scale: self.scale,
+            rot: self.rotation.to_native(),
+        }
+    }
+}
+
+impl Context {
+    /// Check if the device is lost, informing the host about it once.
+    fn is_device_lost(&mut self) -> bool {
+        if !self.device_loss.is_lost() {
+            return false;
+        }
+        if !self.device_loss_reported {
+            self.device_loss_reported = true;
+            if let (Some(callback), user_data) = self.device_lost_callback {
+                callback(user_data);
+            }
+        }
+        true
+    }
+
+    /// The active map, logging an error if there is none.
+    fn active_level(&mut self) -> Option<&mut LevelContext> {
+        match self.active_level {
+            Some(key) => Some(&mut self.levels[key]),
+            None => {
+                log::error!("No active map");
+                None
+            }
+        }
+    }
+
+    fn active_level_ref(&self) -> Option<&LevelContext> {
+        match self.active_level {
+            Some(key) => Some(&self.levels[key]),
+            None => {
+                log::error!("No active map");
+                None
+            }
+        }
+    }
+}
+
+impl Quaternion {
+    fn to_native(&self) -> cgmath::Quaternion<f32> {
+        vangers::space::coords::quat_from_xyzw([self.x, self.y, self.z, self.w])
+    }
+}
+
+impl Vector3 {
+    fn from_array(v: [f32; 3]) -> Self {
+        Vector3 {
+            x: v[0],
+            y: v[1],
+            z: v[2],
+        }
+    }
+}
+
+/// Write the bounds into the outputs of the bounds queries.
+fn write_bounds(
+    bbox: &vangers::model::BoundingBox,
+    out_min: &mut Vector3,
+    out_max: &mut Vector3,
+    out_radius: &mut f32,
+) {
+    *out_min = Vector3::from_array(bbox.min);
+    *out_max = Vector3::from_array(bbox.max);
+    *out_radius = bbox.radius;
+}
+
 impl Rect {
     fn to_native(&self) -> vangers::render::Rect {
         vangers::render::Rect {
@@ -133,34 +247,123 @@ pub struct Model {
     jacobian: [f64; 9],
 }
 
-struct MeshInstance {
+/// GPU mesh, together with its vertices, so that it can be re-created.
+#[derive(Clone)]
+struct MeshSource {
     mesh: Arc<vangers::model::Mesh>,
+    vertices: Arc<[vangers::render::object::Vertex]>,
+}
+
+impl MeshSource {
+    fn new(
+        label: Option<&str>,
+        vertices: Arc<[vangers::render::object::Vertex]>,
+        offset: [f32; 3],
+        bbox: vangers::model::BoundingBox,
+        physics: m3d::Physics,
+        device: &wgpu::Device,
+    ) -> Self {
+        use wgpu::util::DeviceExt as _;
+        let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label,
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let mut memory =
+            vangers::render::memory::Allocation::new(vangers::render::memory::Subsystem::Meshes);
+        memory.add_buffer(
+            device,
+            label.unwrap_or_default(),
+            std::mem::size_of_val(&vertices[..]) as _,
+        );
+        let mesh = vangers::model::Mesh {
+            id: vangers::model::vertex_id(bytemuck::cast_slice(&vertices)),
+            num_vertices: vertices.len(),
+            vertex_buf,
+            offset,
+            bbox,
+            physics,
+            memory,
+        };
+        MeshSource {
+            mesh: Arc::new(mesh),
+            vertices,
+        }
+    }
+
+    fn recreate(&self, device: &wgpu::Device) -> Self {
+        let mesh = &self.mesh;
+        Self::new(
+            None,
+            Arc::clone(&self.vertices),
+            mesh.offset,
+            mesh.bbox,
+            mesh.physics,
+            device,
+        )
+    }
+}
+
+struct MeshInstance {
+    mesh: MeshSource,
     transform: vangers::space::Transform,
+    /// Transform at the previous logic tick, if interpolated.
+    prev_transform: Option<vangers::space::Transform>,
     color_id: u8,
+    /// Layer of the palette array, see `rv_model_instance_set_palette_layer()`.
+    palette_layer: u32,
     visible: bool,
 }
 
+impl MeshInstance {
+    fn current_transform(&self, alpha: f32) -> vangers::space::Transform {
+        use cgmath::VectorSpace as _;
+        match self.prev_transform {
+            Some(ref prev) => vangers::space::Transform {
+                disp: prev.disp.lerp(self.transform.disp, alpha),
+                scale: prev.scale + (self.transform.scale - prev.scale) * alpha,
+                rot: prev.rot.slerp(self.transform.rot, alpha),
+            },
+            None => self.transform,
+        }
+    }
+}
+
 struct LevelContext {
     desc: MapDescription,
+    config: vangers::level::LevelConfig,
     render: vangers::render::Render,
     level: vangers::level::Level,
 }
 
 pub struct Context {
-    level: Option<LevelContext>,
+    levels: SlotMap<DefaultKey, LevelContext>,
+    /// The map targeted by updates and rendering.
+    active_level: Option<DefaultKey>,
     geometry_config: vangers::config::settings::Geometry,
     render_config: vangers::config::settings::Render,
     color_view: wgpu::TextureView,
     depth_view: wgpu::TextureView,
     gfx: vangers::render::GraphicsContext,
     _instance: wgpu::Instance,
+    device_loss: vangers::render::DeviceLoss,
+    device_lost_callback: (DeviceLostCallback, *mut raw::c_void),
+    /// Set once the host is informed about the current loss.
+    device_loss_reported: bool,
     camera: vangers::space::Camera,
     objects_palette: [[u8; 4]; 0x100],
-    meshes: SlotMap<DefaultKey, Arc<vangers::model::Mesh>>,
+    meshes: SlotMap<DefaultKey, MeshSource>,
     instances: SlotMap<DefaultKey, MeshInstance>,
+    /// Blend factor between the previous and the next transforms of the instances.
+    interpolation_alpha: f32,
+    palette_output: Option<PaletteOutput>,
+    #[cfg(feature = "debug-ui")]
+    debug_ui: debug_ui::DebugUi,
 }
 
 pub type GlFunctionDiscovery = unsafe extern "C" fn(*const raw::c_char) -> *const raw::c_void;
+/// Function informing the host about the loss of the device, null if not registered.
+pub type DeviceLostCallback = Option<extern "C" fn(user_data: *mut raw::c_void)>;
 
 #[repr(C)]
 pub struct InitDescriptor {
@@ -207,56 +410,130 @@ fn crate_main_views(
     (color_view, depth_view)
 }
 
-#[no_mangle]
-pub extern "C" fn rv_init(desc: InitDescriptor) -> Option<ptr::NonNull<Context>> {
-    #[cfg(feature = "env_logger")]
-    let _ = env_logger::try_init();
+/// Offscreen targets of the frame converted into the palette indices,
+/// see `rv_set_render_palette()`.
+struct PaletteOutput {
+    color_view: wgpu::TextureView,
+    depth_view: wgpu::TextureView,
+    index_texture: wgpu::Texture,
+    index_view: wgpu::TextureView,
+    /// Indices of the last rendered frame, with the rows padded for the copy.
+    readback: wgpu::Buffer,
+    bytes_per_row: u32,
+    palettizer: vangers::render::palettize::Palettizer,
+    _memory: vangers::render::memory::Allocation,
+}
 
-    #[cfg(feature = "android_logger")]
-    log_panics::init();
+impl PaletteOutput {
+    fn new(gfx: &vangers::render::GraphicsContext) -> Self {
+        let size = gfx.screen_size;
+        let mut texture_desc = wgpu::TextureDescriptor {
+            label: Some("Palette color"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: gfx.color_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        };
+        let mut memory =
+            vangers::render::memory::Allocation::new(vangers::render::memory::Subsystem::Other);
+        memory.add_texture(&gfx.device, &texture_desc);
+        let color_view = gfx
+            .device
+            .create_texture(&texture_desc)
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        texture_desc.label = Some("Palette depth");
+        texture_desc.format = vangers::render::DEPTH_FORMAT;
+        texture_desc.usage = wgpu::TextureUsages::RENDER_ATTACHMENT;
+        memory.add_texture(&gfx.device, &texture_desc);
+        let depth_view = gfx
+            .device
+            .create_texture(&texture_desc)
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        texture_desc.label = Some("Palette indices");
+        texture_desc.format = vangers::render::palettize::FORMAT;
+        texture_desc.usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC;
+        memory.add_texture(&gfx.device, &texture_desc);
+        let index_texture = gfx.device.create_texture(&texture_desc);
+        let index_view = index_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-    #[cfg(feature = "android_logger")]
-    let _ = android_logger::init_once(
-        android_logger::Config::default().with_min_level(log::Level::Warn),
-    );
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = size.width.div_ceil(alignment) * alignment;
+        let readback_size = (bytes_per_row * size.height) as wgpu::BufferAddress;
+        memory.add_buffer(&gfx.device, "Palette readback", readback_size);
+        let readback = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Palette readback"),
+            size: readback_size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
 
-    let geometry_config: vangers::config::settings::Geometry = {
-        let file = File::open("res/ffi/geometry.ron").unwrap();
-        ron::de::from_reader(file).unwrap()
-    };
-    let render_config: vangers::config::settings::Render = {
-        let config_path = if desc.render_config.is_null() {
-            log::info!("Null render config path");
-            "res/ffi/render-compat.ron".to_string()
-        } else {
-            unsafe { CStr::from_ptr(desc.render_config) }
-                .to_string_lossy()
-                .to_string()
-        };
-        log::info!("Using render: {}", config_path);
-        let file = File::open(&config_path).unwrap();
-        ron::de::from_reader(file).unwrap()
-    };
-    let objects_palette = {
-        let file = File::open("res/ffi/objects.pal").unwrap();
-        vangers::level::read_palette(file, None)
-    };
+        PaletteOutput {
+            color_view,
+            depth_view,
+            index_texture,
+            index_view,
+            readback,
+            bytes_per_row,
+            palettizer: vangers::render::palettize::Palettizer::new(&gfx.device),
+            _memory: memory,
+        }
+    }
 
+    /// Convert the rendered colors with the palette, and copy the indices for reading.
+    fn resolve(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        gfx: &vangers::render::GraphicsContext,
+        palette: &[[u8; 4]; 0x100],
+    ) {
+        self.palettizer.set_palette(&gfx.queue, palette);
+        self.palettizer
+            .convert(encoder, &gfx.device, &self.color_view, &self.index_view);
+        encoder.copy_texture_to_buffer(
+            self.index_texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            gfx.screen_size,
+        );
+    }
+}
+
+fn create_graphics(
+    gl_functor: GlFunctionDiscovery,
+    screen_size: wgpu::Extent3d,
+    render_config: &mut vangers::config::settings::Render,
+    geometry_config: &vangers::config::settings::Geometry,
+) -> Option<(wgpu::Instance, vangers::render::GraphicsContext)> {
     let mut task_pool = LocalPool::new();
 
-    let exposed = unsafe {
+    let exposed = match unsafe {
         <hal::api::Gles as hal::Api>::Adapter::new_external(|name| {
             let cstr = CString::new(name).unwrap();
-            (desc.gl_functor)(cstr.as_ptr())
+            gl_functor(cstr.as_ptr())
         })
-    }
-    .expect("GL adapter can't be initialized");
+    } {
+        Some(exposed) => exposed,
+        None => {
+            log::error!("GL adapter can't be initialized");
+            return None;
+        }
+    };
 
     let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
         backends: wgpu::Backends::empty(),
         ..Default::default()
     });
     let adapter = unsafe { instance.create_adapter_from_hal(exposed) };
+    render_config.fit_downlevel(&adapter.get_downlevel_capabilities());
     let limits = render_config.get_device_limits(&adapter.limits(), geometry_config.height);
 
     let (device, queue) = task_pool
@@ -271,30 +548,99 @@ pub extern "C" fn rv_init(desc: InitDescriptor) -> Option<ptr::NonNull<Context>>
         .ok()?;
 
     let gfx = vangers::render::GraphicsContext {
-        queue,
-        device,
+        queue: Arc::new(queue),
+        device: Arc::new(device),
         downlevel_caps: adapter.get_downlevel_capabilities(),
-        screen_size: wgpu::Extent3d {
-            width: desc.width,
-            height: desc.height,
-            depth_or_array_layers: 1,
-        },
+        screen_size,
         color_format: wgpu::TextureFormat::Rgba8UnormSrgb,
     };
+    Some((instance, gfx))
+}
+
+fn load_ron<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path, e))?;
+    ron::de::from_reader(file).map_err(|e| format!("Unable to parse {}: {}", path, e))
+}
+
+#[no_mangle]
+pub extern "C" fn rv_init(desc: InitDescriptor) -> Option<ptr::NonNull<Context>> {
+    #[cfg(feature = "env_logger")]
+    let _ = env_logger::try_init();
+
+    #[cfg(feature = "android_logger")]
+    log_panics::init();
+
+    #[cfg(feature = "android_logger")]
+    let _ = android_logger::init_once(
+        android_logger::Config::default().with_min_level(log::Level::Warn),
+    );
+
+    let geometry_config: vangers::config::settings::Geometry =
+        match load_ron("res/ffi/geometry.ron") {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Falling back to the default geometry. {}", e);
+                Default::default()
+            }
+        };
+    let mut render_config: vangers::config::settings::Render = {
+        let config_path = if desc.render_config.is_null() {
+            log::info!("Null render config path");
+            "res/ffi/render-compat.ron".to_string()
+        } else {
+            unsafe { CStr::from_ptr(desc.render_config) }
+                .to_string_lossy()
+                .to_string()
+        };
+        log::info!("Using render: {}", config_path);
+        match load_ron(&config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("{}", e);
+                return None;
+            }
+        }
+    };
+    let objects_palette = match File::open("res/ffi/objects.pal") {
+        Ok(file) => vangers::level::read_palette(file, None),
+        Err(e) => {
+            log::error!("Unable to open the object palette: {}", e);
+            return None;
+        }
+    };
+
+    let screen_size = wgpu::Extent3d {
+        width: desc.width,
+        height: desc.height,
+        depth_or_array_layers: 1,
+    };
+    let (instance, gfx) = create_graphics(
+        desc.gl_functor,
+        screen_size,
+        &mut render_config,
+        &geometry_config,
+    )?;
     let (color_view, depth_view) = crate_main_views(&gfx);
+    let handedness = render_config.handedness;
+
+    let device_loss = vangers::render::DeviceLoss::watch(&gfx.device);
 
     let ctx = Context {
-        level: None,
+        levels: SlotMap::new(),
+        active_level: None,
         geometry_config,
         render_config,
         gfx,
         color_view,
         depth_view,
+        device_loss,
+        device_lost_callback: (None, ptr::null_mut()),
+        device_loss_reported: false,
         _instance: instance,
         camera: vangers::space::Camera {
             loc: cgmath::Zero::zero(),
             rot: cgmath::Zero::zero(),
-            scale: cgmath::vec3(1.0, 1.0, 1.0),
+            handedness,
             proj: vangers::space::Projection::Perspective(cgmath::PerspectiveFov {
                 aspect: 1.0,
                 near: 1.0,
@@ -305,11 +651,17 @@ pub extern "C" fn rv_init(desc: InitDescriptor) -> Option<ptr::NonNull<Context>>
         objects_palette,
         meshes: SlotMap::new(),
         instances: SlotMap::new(),
+        interpolation_alpha: 1.0,
+        palette_output: None,
+        #[cfg(feature = "debug-ui")]
+        debug_ui: debug_ui::DebugUi::new(),
     };
     let ptr = Box::into_raw(Box::new(ctx));
     ptr::NonNull::new(ptr)
 }
 
+/// # Safety
+/// `ctx` has to be produced by `rv_init` and not used afterwards.
 #[no_mangle]
 pub unsafe extern "C" fn rv_exit(ctx: *mut Context) {
     let _ctx = Box::from_raw(ctx);
@@ -325,6 +677,12 @@ pub extern "C" fn rv_resize(ctx: &mut Context, width: u32, height: u32) {
     let (color_view, depth_view) = crate_main_views(&ctx.gfx);
     ctx.color_view = color_view;
     ctx.depth_view = depth_view;
+    if ctx.palette_output.is_some() {
+        ctx.palette_output = Some(PaletteOutput::new(&ctx.gfx));
+    }
+    for (_, lc) in ctx.levels.iter_mut() {
+        lc.render.resize(ctx.gfx.screen_size, &ctx.gfx.device);
+    }
 
     //if let vangers::space::Projection::Perspective(ref mut p) = ctx.camera.proj {
     //    p.aspect = width as f32 / height as f32;
@@ -345,12 +703,53 @@ pub extern "C" fn rv_camera_init(ctx: &mut Context, desc: CameraDescription) {
 pub extern "C" fn rv_camera_set_transform(ctx: &mut Context, t: Transform) {
     assert_eq!(t.scale, 1.0);
     ctx.camera.loc = cgmath::vec3(t.position.x, t.position.y, t.position.z);
-    ctx.camera.rot =
-        cgmath::Quaternion::new(t.rotation.w, t.rotation.x, t.rotation.y, t.rotation.z);
+    ctx.camera.rot = t.rotation.to_native();
 }
 
+/// Rotate the camera by a cursor movement, in pixels.
 #[no_mangle]
-pub extern "C" fn rv_map_init(ctx: &mut Context, desc: MapDescription) {
+pub extern "C" fn rv_camera_orbit(ctx: &mut Context, dx: f32, dy: f32) {
+    ctx.camera.orbit(cgmath::vec2(dx, dy));
+}
+
+/// Drag the camera along the ground by a cursor movement, in pixels.
+#[no_mangle]
+pub extern "C" fn rv_camera_pan(ctx: &mut Context, dx: f32, dy: f32) {
+    ctx.camera.pan(cgmath::vec2(dx, dy));
+}
+
+/// Move the camera vertically by lines of the mouse wheel.
+#[no_mangle]
+pub extern "C" fn rv_camera_zoom(ctx: &mut Context, delta: f32) {
+    ctx.camera.zoom(delta);
+}
+
+/// Texels of the active map that may be seen by the camera,
+/// with the origin wrapped into the map.
+#[no_mangle]
+pub extern "C" fn rv_camera_get_visible_rect(ctx: &mut Context) -> Rect {
+    let rect = match ctx.active_level_ref() {
+        Some(lc) => ctx.camera.visible_terrain_rect(&lc.level),
+        None => {
+            return Rect {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            }
+        }
+    };
+    Rect {
+        x: rect.x as i32,
+        y: rect.y as i32,
+        width: rect.w as i32,
+        height: rect.h as i32,
+    }
+}
+
+/// Create a new map without making it active. Returns the map handle.
+#[no_mangle]
+pub extern "C" fn rv_map_create(ctx: &mut Context, desc: MapDescription) -> u64 {
     use vangers::level::Power;
 
     let level_config = vangers::level::LevelConfig {
@@ -358,8 +757,8 @@ pub extern "C" fn rv_map_init(ctx: &mut Context, desc: MapDescription) {
         path_data: Default::default(),
         is_compressed: false,
         size: (
-            Power::from_value(desc.width as i32),
-            Power::from_value(desc.height as i32),
+            Power::from_value(desc.width),
+            Power::from_value(desc.height),
         ),
         geo: Power(0),
         section: Power(8),
@@ -372,6 +771,8 @@ pub extern "C" fn rv_map_init(ctx: &mut Context, desc: MapDescription) {
                 tc
             })
             .collect(),
+        test_preset: vangers::level::TestPreset::Flat,
+        generator: None,
     };
 
     let render = vangers::render::Render::new(
@@ -380,57 +781,139 @@ pub extern "C" fn rv_map_init(ctx: &mut Context, desc: MapDescription) {
         &ctx.objects_palette,
         &ctx.render_config,
         &ctx.geometry_config,
-        ctx.camera.front_face(),
     );
 
     let level = vangers::level::load(&level_config, &ctx.geometry_config);
-    ctx.level = Some(LevelContext {
+    let key = ctx.levels.insert(LevelContext {
         desc,
+        config: level_config,
         render,
         level,
     });
+    key.data().as_ffi()
 }
 
+/// Make the map current for the subsequent updates and rendering.
 #[no_mangle]
-pub extern "C" fn rv_map_exit(ctx: &mut Context) {
-    ctx.level = None;
+pub extern "C" fn rv_map_activate(ctx: &mut Context, handle: u64) {
+    let key = slotmap::KeyData::from_ffi(handle).into();
+    assert!(ctx.levels.contains_key(key), "Invalid map handle");
+    ctx.active_level = Some(key);
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn rv_map_update_data(ctx: &mut Context, region: Rect) {
-    let lc = ctx.level.as_mut().unwrap();
-    let line_width = lc.level.size.0 as usize;
-
-    for y in region.y..region.y + region.height {
-        // In the source data, each line contains N height values followed by N meta values.
-        // We copy them into separate height and meta data arrays.
-        let dst_offset = y as usize * line_width + region.x as usize;
-        let line = *lc.desc.lines.add(y as usize);
-        if line.is_null() {
-            continue;
+pub extern "C" fn rv_map_destroy(ctx: &mut Context, handle: u64) {
+    let key = slotmap::KeyData::from_ffi(handle).into();
+    if ctx.active_level == Some(key) {
+        ctx.active_level = None;
+    }
+    let _ = ctx.levels.remove(key);
+}
+
+/// Replace the active map with a new one.
+#[no_mangle]
+pub extern "C" fn rv_map_init(ctx: &mut Context, desc: MapDescription) {
+    rv_map_exit(ctx);
+    let handle = rv_map_create(ctx, desc);
+    rv_map_activate(ctx, handle);
+}
+
+/// Destroy the active map.
+#[no_mangle]
+pub extern "C" fn rv_map_exit(ctx: &mut Context) {
+    if let Some(key) = ctx.active_level.take() {
+        ctx.levels.remove(key);
+    }
+}
+
+impl LevelContext {
+    /// # Safety
+    /// The map lines given to `rv_map_create` have to cover the region.
+    unsafe fn copy_data(&mut self, region: &Rect) {
+        let line_width = self.level.size.0 as usize;
+
+        for y in region.y..region.y + region.height {
+            // In the source data, each line contains N height values followed by N meta values.
+            // We copy them into separate height and meta data arrays.
+            let dst_offset = y as usize * line_width + region.x as usize;
+            let line = *self.desc.lines.add(y as usize);
+            if line.is_null() {
+                continue;
+            }
+            ptr::copy_nonoverlapping(
+                line.add(region.x as usize),
+                self.level.height[dst_offset..].as_mut_ptr(),
+                region.width as usize,
+            );
+            ptr::copy_nonoverlapping(
+                line.add(region.x as usize + line_width),
+                self.level.meta[dst_offset..].as_mut_ptr(),
+                region.width as usize,
+            );
         }
-        ptr::copy_nonoverlapping(
-            line.add(region.x as usize),
-            lc.level.height[dst_offset..].as_mut_ptr(),
-            region.width as usize,
-        );
-        ptr::copy_nonoverlapping(
-            line.add(region.x as usize + line_width),
-            lc.level.meta[dst_offset..].as_mut_ptr(),
-            region.width as usize,
+        self.level.update_pyramid(
+            (region.x, region.y),
+            (region.x + region.width, region.y + region.height),
         );
     }
 
-    lc.render
-        .terrain
-        .dirty_rects
-        .push(vangers::render::DirtyRect {
-            rect: region.to_native(),
-            z_range: 0..0x100,
-            need_upload: true,
+    fn mark_data(&mut self, region: &Rect) {
+        self.render
+            .terrain
+            .dirty_rects
+            .push(vangers::render::DirtyRect {
+                rect: region.to_native(),
+                z_range: 0..0x100,
+                need_upload: true,
+            });
+    }
+
+    /// Upload the texel, together with its pair of the stacked texels.
+    fn mark_texel(&mut self, x: i32, y: i32) {
+        self.mark_data(&Rect {
+            x: x & !1,
+            y,
+            width: 2,
+            height: 1,
         });
+    }
+
+    /// # Safety
+    /// `palette` has to point to `entries_count` RGB entries.
+    unsafe fn copy_palette(&mut self, first_entry: u32, entries_count: u32, palette: *const u8) {
+        let end = first_entry + entries_count;
+        for (i, color) in self.level.palette[first_entry as usize..end as usize]
+            .iter_mut()
+            .enumerate()
+        {
+            ptr::copy_nonoverlapping(palette.add(i * 3), color.first_mut().unwrap(), 3);
+        }
+    }
+
+    fn mark_palette(&mut self, entries: ops::Range<u32>) {
+        let dp = self.render.terrain.dirty_palette.clone();
+        self.render.terrain.dirty_palette = if dp != (0..0) {
+            dp.start.min(entries.start)..dp.end.max(entries.end)
+        } else {
+            entries
+        };
+    }
 }
 
+/// # Safety
+/// The map lines given to `rv_map_create` have to cover the region.
+#[no_mangle]
+pub unsafe extern "C" fn rv_map_update_data(ctx: &mut Context, region: Rect) {
+    let lc = match ctx.active_level() {
+        Some(lc) => lc,
+        None => return,
+    };
+    lc.copy_data(&region);
+    lc.mark_data(&region);
+}
+
+/// # Safety
+/// `palette` has to point to `entries_count` RGB entries.
 #[no_mangle]
 pub unsafe extern "C" fn rv_map_update_palette(
     ctx: &mut Context,
@@ -438,44 +921,321 @@ pub unsafe extern "C" fn rv_map_update_palette(
     entries_count: u32,
     palette: *const u8,
 ) {
-    let lc = ctx.level.as_mut().unwrap();
-    let end = first_entry + entries_count;
+    let lc = match ctx.active_level() {
+        Some(lc) => lc,
+        None => return,
+    };
+    lc.copy_palette(first_entry, entries_count, palette);
+    lc.mark_palette(first_entry..first_entry + entries_count);
+}
 
-    for (i, color) in lc.level.palette[first_entry as usize..end as usize]
-        .iter_mut()
-        .enumerate()
-    {
-        ptr::copy_nonoverlapping(palette.add(i * 3), color.first_mut().unwrap(), 3);
+// Kinds of the map updates, see `MapUpdate`.
+/// Copy the `region` from the map lines, like `rv_map_update_data()`.
+pub const RV_MAP_UPDATE_DATA: u32 = 0;
+/// Copy `entries_count` palette entries starting at `first_entry`,
+/// like `rv_map_update_palette()`.
+pub const RV_MAP_UPDATE_PALETTE: u32 = 1;
+
+/// One of the updates of `rv_map_update_batch()`.
+/// Only the fields used by the `kind` are read.
+#[repr(C)]
+pub struct MapUpdate {
+    kind: u32,
+    region: Rect,
+    first_entry: u32,
+    entries_count: u32,
+    palette: *const u8,
+}
+
+/// Apply a number of data and palette updates to the active map at once.
+/// The changed regions are merged into a single dirty rectangle.
+///
+/// # Safety
+/// `updates` has to point to `count` entries, each following the safety
+/// requirements of the corresponding single update function.
+#[no_mangle]
+pub unsafe extern "C" fn rv_map_update_batch(
+    ctx: &mut Context,
+    updates: *const MapUpdate,
+    count: usize,
+) {
+    if count == 0 {
+        return;
     }
+    let lc = match ctx.active_level() {
+        Some(lc) => lc,
+        None => return,
+    };
+    let mut bounds: Option<Rect> = None;
+    let mut entries: Option<ops::Range<u32>> = None;
 
-    let dp = lc.render.terrain.dirty_palette.clone();
-    lc.render.terrain.dirty_palette = if dp != (0..0) {
-        dp.start.min(first_entry)..dp.end.max(end)
+    for update in slice::from_raw_parts(updates, count) {
+        match update.kind {
+            RV_MAP_UPDATE_DATA => {
+                let r = &update.region;
+                lc.copy_data(r);
+                bounds = Some(match bounds {
+                    Some(b) => {
+                        let (x, y) = (b.x.min(r.x), b.y.min(r.y));
+                        Rect {
+                            x,
+                            y,
+                            width: (b.x + b.width).max(r.x + r.width) - x,
+                            height: (b.y + b.height).max(r.y + r.height) - y,
+                        }
+                    }
+                    None => Rect {
+                        x: r.x,
+                        y: r.y,
+                        width: r.width,
+                        height: r.height,
+                    },
+                });
+            }
+            RV_MAP_UPDATE_PALETTE => {
+                lc.copy_palette(update.first_entry, update.entries_count, update.palette);
+                let end = update.first_entry + update.entries_count;
+                entries = Some(match entries {
+                    Some(e) => e.start.min(update.first_entry)..e.end.max(end),
+                    None => update.first_entry..end,
+                });
+            }
+            other => log::warn!("Unknown map update kind {}", other),
+        }
+    }
+
+    if let Some(region) = bounds {
+        lc.mark_data(&region);
+    }
+    if let Some(range) = entries {
+        lc.mark_palette(range);
+    }
+}
+
+/// Check that the layer is one of the extra palettes, not the one of the map.
+fn is_extra_palette_layer(layer: u32) -> bool {
+    if layer == 0 || layer >= vangers::render::MAX_PALETTES {
+        log::error!(
+            "Palette layer {} is out of 1..{}",
+            layer,
+            vangers::render::MAX_PALETTES
+        );
+        false
     } else {
-        first_entry..end
+        true
+    }
+}
+
+/// Upload 256 RGB entries into a palette layer of the active map, used by both
+/// the terrain and the objects. Layer 0 is the palette of the map itself,
+/// so the extra layers start at 1. Returns false if the layer is out of range.
+///
+/// # Safety
+/// `palette` has to point to 256 RGB entries.
+#[no_mangle]
+pub unsafe extern "C" fn rv_map_set_palette_layer(
+    ctx: &mut Context,
+    layer: u32,
+    palette: *const u8,
+) -> bool {
+    if !is_extra_palette_layer(layer) {
+        return false;
+    }
+    let key = match ctx.active_level {
+        Some(key) => key,
+        None => {
+            log::error!("No active map for the palette layer {}", layer);
+            return false;
+        }
     };
+    let mut data = [[0xFF; 4]; 0x100];
+    for (i, color) in data.iter_mut().enumerate() {
+        ptr::copy_nonoverlapping(palette.add(i * 3), color.as_mut_ptr(), 3);
+    }
+    let render = &mut ctx.levels[key].render;
+    render.terrain.set_palette(&ctx.gfx.queue, layer, &data);
+    render.object.set_palette(&ctx.gfx.queue, layer, &data);
+    true
 }
 
+/// Draw the terrain of the active map with a palette layer,
+/// 0 being the palette of the map. Returns false if the layer is out of range.
 #[no_mangle]
-pub extern "C" fn rv_render(ctx: &mut Context, viewport: Rect) {
-    let lc = ctx.level.as_mut().unwrap();
-    let targets = vangers::render::ScreenTargets {
-        extent: ctx.gfx.screen_size,
-        depth: &ctx.depth_view,
-        color: &ctx.color_view,
+pub extern "C" fn rv_map_select_palette_layer(ctx: &mut Context, layer: u32) -> bool {
+    if layer != 0 && !is_extra_palette_layer(layer) {
+        return false;
+    }
+    match ctx.active_level {
+        Some(key) => {
+            ctx.levels[key].render.terrain.palette_layer = layer;
+            true
+        }
+        None => {
+            log::error!("No active map for the palette layer {}", layer);
+            false
+        }
+    }
+}
+
+/// Set the water level of a section of the active map.
+#[no_mangle]
+pub extern "C" fn rv_map_set_flood(ctx: &mut Context, section: u32, level: u8) {
+    let lc = match ctx.active_level() {
+        Some(lc) => lc,
+        None => return,
+    };
+    match lc.level.flood_map.get_mut(section as usize) {
+        Some(flood) => {
+            *flood = level;
+            lc.render.terrain.dirty_flood = true;
+        }
+        None => log::error!(
+            "Flood section {} is out of {}",
+            section,
+            lc.level.flood_map.len()
+        ),
+    }
+}
+
+/// Height of a texel of the active map, with the coordinates wrapping around it.
+#[no_mangle]
+pub extern "C" fn rv_map_get_height(ctx: &Context, x: i32, y: i32) -> u8 {
+    let level = match ctx.active_level_ref() {
+        Some(lc) => &lc.level,
+        None => return 0,
+    };
+    level.height[level.wrap_texel(x, y).1]
+}
+
+/// Override the height of a texel of the active map, with the coordinates
+/// wrapping around it. Only the renderer's copy of the map is changed, not the
+/// map lines of the host, so the override lasts until the texel is copied
+/// from the lines again by `rv_map_update_data()`.
+#[no_mangle]
+pub extern "C" fn rv_map_set_height(ctx: &mut Context, x: i32, y: i32, height: u8) {
+    let lc = match ctx.active_level() {
+        Some(lc) => lc,
+        None => return,
+    };
+    let ((x, y), index) = lc.level.wrap_texel(x, y);
+    lc.level.height[index] = height;
+    lc.level.update_pyramid((x, y), (x + 1, y + 1));
+    lc.mark_texel(x, y);
+}
+
+/// Altitude of the upper surface of the active map at the world position,
+/// wrapping around the map. Unlike `rv_map_get_height()`, there are no checks
+/// to log about, so it's fine to call for every object every frame.
+#[no_mangle]
+pub extern "C" fn rv_map_get_height_fast(ctx: &Context, x: f32, y: f32) -> f32 {
+    let lc = match ctx.active_level_ref() {
+        Some(lc) => lc,
+        None => return 0.0,
+    };
+    lc.level.get_at(cgmath::vec3(x, y, 0.0)).high()
+}
+
+/// Find where the ray from `origin` along `dir` hits the terrain of the active map,
+/// within `max_dist`, writing the point into `out_hit`.
+/// The terrain around the origin is skipped if the origin is inside of it.
+/// Returns false if nothing is hit.
+#[no_mangle]
+pub extern "C" fn rv_map_trace_ray(
+    ctx: &Context,
+    origin: Vector3,
+    dir: Vector3,
+    max_dist: f32,
+    out_hit: &mut Vector3,
+) -> bool {
+    let lc = match ctx.active_level_ref() {
+        Some(lc) => lc,
+        None => return false,
+    };
+    let origin = cgmath::vec3(origin.x, origin.y, origin.z);
+    let dir = cgmath::vec3(dir.x, dir.y, dir.z);
+    match vangers::space::trace_ray(&lc.level, origin, dir, max_dist) {
+        Some(hit) => {
+            *out_hit = Vector3::from_array(hit.into());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Meta data of a texel of the active map, with the coordinates wrapping around it.
+#[no_mangle]
+pub extern "C" fn rv_map_get_meta(ctx: &Context, x: i32, y: i32) -> u8 {
+    let level = match ctx.active_level_ref() {
+        Some(lc) => &lc.level,
+        None => return 0,
     };
+    level.meta[level.wrap_texel(x, y).1]
+}
 
+/// Override the meta data of a texel of the active map, the same way
+/// as `rv_map_set_height()` does for the height.
+#[no_mangle]
+pub extern "C" fn rv_map_set_meta(ctx: &mut Context, x: i32, y: i32, meta: u8) {
+    let lc = match ctx.active_level() {
+        Some(lc) => lc,
+        None => return,
+    };
+    let ((x, y), index) = lc.level.wrap_texel(x, y);
+    lc.level.meta[index] = meta;
+    lc.mark_texel(x, y);
+}
+
+/// Collect the visible instances of the frame.
+fn make_batcher(ctx: &Context) -> vangers::render::Batcher {
     let mut batcher = vangers::render::Batcher::new();
+    batcher.cull = Some(vangers::render::DistanceCull {
+        origin: ctx.camera.loc,
+        distance: ctx.render_config.draw_distance,
+    });
     for (_, instance) in ctx.instances.iter() {
         if !instance.visible {
             continue;
         }
+        let transform = instance.current_transform(ctx.interpolation_alpha);
+        let radius = instance.mesh.mesh.bbox.radius * transform.scale;
+        let fade = match batcher.fade(vangers::render::Category::Prop, transform.disp, radius) {
+            Some(fade) => fade,
+            None => continue,
+        };
         batcher.add_mesh(
-            &instance.mesh,
-            vangers::render::object::Instance::new(&instance.transform, 1.0, instance.color_id),
+            &instance.mesh.mesh,
+            vangers::render::object::Instance::new(&transform, 1.0, instance.color_id)
+                .with_palette(instance.palette_layer)
+                .with_fade(fade),
         );
     }
+    batcher
+}
 
+#[no_mangle]
+pub extern "C" fn rv_render(ctx: &mut Context, viewport: Rect) {
+    if ctx.is_device_lost() {
+        return;
+    }
+    let mut batcher = make_batcher(ctx);
+    let lc = match ctx.active_level {
+        Some(key) => &mut ctx.levels[key],
+        None => {
+            log::error!("No active map to render");
+            return;
+        }
+    };
+    let (color, depth) = match ctx.palette_output {
+        Some(ref output) => (&output.color_view, &output.depth_view),
+        None => (&ctx.color_view, &ctx.depth_view),
+    };
+    let targets = vangers::render::ScreenTargets {
+        extent: ctx.gfx.screen_size,
+        depth,
+        color,
+    };
+
+    let capture = vangers::render::capture::start(&ctx.gfx.device);
     let mut encoder = ctx
         .gfx
         .device
@@ -493,7 +1253,311 @@ pub extern "C" fn rv_render(ctx: &mut Context, viewport: Rect) {
         &ctx.gfx.device,
     );
 
+    #[cfg(feature = "debug-ui")]
+    if ctx.debug_ui.visible {
+        ctx.debug_ui
+            .draw(&mut encoder, color, &ctx.gfx, &mut lc.render, &mut lc.level);
+    }
+
+    if let Some(ref output) = ctx.palette_output {
+        output.resolve(&mut encoder, &ctx.gfx, &lc.level.palette);
+    }
+
     ctx.gfx.queue.submit(Some(encoder.finish()));
+    drop(capture);
+    // report the loss as soon as possible
+    let _ = ctx.is_device_lost();
+}
+
+/// Save the scene as it would be rendered now into a RON file,
+/// to be rendered offline with `convert --render-snapshot`.
+/// Returns false if there is no active map, or the file can't be written.
+///
+/// # Safety
+///
+/// `path` has to be a valid null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rv_scene_snapshot(ctx: &mut Context, path: *const raw::c_char) -> bool {
+    let level_key = match ctx.active_level {
+        Some(key) => key,
+        None => {
+            log::error!("No active map to snapshot");
+            return false;
+        }
+    };
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    let batcher = make_batcher(ctx);
+    let snapshot = vangers::render::snapshot::Snapshot::capture(
+        &batcher,
+        &ctx.levels[level_key].level,
+        &ctx.camera,
+    );
+    match snapshot.save(path.as_ref()) {
+        Ok(()) => true,
+        Err(e) => {
+            log::error!("{}", e);
+            false
+        }
+    }
+}
+
+/// Register a function to be called once the device is lost.
+/// It's called from within `rv_render` or `rv_is_device_lost`.
+/// Passing null unregisters it.
+#[no_mangle]
+pub extern "C" fn rv_set_device_lost_callback(
+    ctx: &mut Context,
+    callback: DeviceLostCallback,
+    user_data: *mut raw::c_void,
+) {
+    ctx.device_lost_callback = (callback, user_data);
+}
+
+#[no_mangle]
+pub extern "C" fn rv_is_device_lost(ctx: &mut Context) -> bool {
+    ctx.is_device_lost()
+}
+
+/// Re-create the device and all the GPU resources after a loss,
+/// with the new GL context being current. Maps, models, and instances stay valid.
+/// Returns false if the device can't be created.
+#[no_mangle]
+pub extern "C" fn rv_device_recover(ctx: &mut Context, gl_functor: GlFunctionDiscovery) -> bool {
+    let (instance, gfx) = match create_graphics(
+        gl_functor,
+        ctx.gfx.screen_size,
+        &mut ctx.render_config,
+        &ctx.geometry_config,
+    ) {
+        Some(pair) => pair,
+        None => return false,
+    };
+    log::info!("Re-creating the GPU resources");
+    ctx.device_loss = vangers::render::DeviceLoss::watch(&gfx.device);
+    ctx.device_loss_reported = false;
+    ctx.gfx = gfx;
+    ctx._instance = instance;
+    let (color_view, depth_view) = crate_main_views(&ctx.gfx);
+    ctx.color_view = color_view;
+    ctx.depth_view = depth_view;
+    if ctx.palette_output.is_some() {
+        ctx.palette_output = Some(PaletteOutput::new(&ctx.gfx));
+    }
+    #[cfg(feature = "debug-ui")]
+    {
+        // the UI textures are gone with the old device
+        let visible = ctx.debug_ui.visible;
+        ctx.debug_ui = debug_ui::DebugUi::new();
+        ctx.debug_ui.visible = visible;
+    }
+
+    for (_, lc) in ctx.levels.iter_mut() {
+        // a new renderer uploads the whole level and its palette
+        lc.render.recover(
+            &ctx.gfx,
+            &lc.config,
+            &ctx.objects_palette,
+            &ctx.render_config,
+            &ctx.geometry_config,
+        );
+    }
+
+    // instances may outlive their models, so the meshes are matched by identity
+    let mut recreated = ctx
+        .meshes
+        .values()
+        .map(|source| (Arc::as_ptr(&source.mesh), source.recreate(&ctx.gfx.device)))
+        .collect::<HashMap<_, _>>();
+    for source in ctx.meshes.values_mut() {
+        *source = recreated[&Arc::as_ptr(&source.mesh)].clone();
+    }
+    for instance in ctx.instances.values_mut() {
+        let new = recreated
+            .entry(Arc::as_ptr(&instance.mesh.mesh))
+            .or_insert_with(|| instance.mesh.recreate(&ctx.gfx.device))
+            .clone();
+        instance.mesh = new;
+    }
+    true
+}
+
+/// Show or hide the debug overlay. Returns true if it's visible now.
+#[cfg(feature = "debug-ui")]
+#[no_mangle]
+pub extern "C" fn rv_debug_ui_toggle(ctx: &mut Context) -> bool {
+    ctx.debug_ui.visible ^= true;
+    ctx.debug_ui.visible
+}
+
+/// Forward an input event to the debug overlay.
+/// Returns true if the overlay consumes it, and the host should ignore it.
+#[cfg(feature = "debug-ui")]
+#[no_mangle]
+pub extern "C" fn rv_ui_event(ctx: &mut Context, event: debug_ui::UiEvent) -> bool {
+    ctx.debug_ui.add_event(&event)
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct Capabilities {
+    /// Shadows are enabled by the render config.
+    supports_shadow: bool,
+    /// The device can run the voxel ray-traced terrain.
+    supports_voxel_terrain: bool,
+    /// The frame is rendered in RGBA colors.
+    supports_render_rgba: bool,
+    /// The frame can be rendered into the palette indices, see `rv_set_render_palette()`.
+    supports_render_palette: bool,
+    /// The debug overlay is compiled in, see `rv_debug_ui_toggle()`.
+    supports_debug_ui: bool,
+    /// Largest width and height of a map, in texels.
+    max_texture_size: u32,
+}
+
+/// Report what the renderer supports with the current build, config, and device.
+#[no_mangle]
+pub extern "C" fn rv_query_capabilities(ctx: &Context, caps: &mut Capabilities) {
+    let flags = ctx.gfx.downlevel_caps.flags;
+    let limits = ctx.gfx.device.limits();
+    *caps = Capabilities {
+        supports_shadow: ctx.render_config.light.shadow.size != 0,
+        supports_voxel_terrain: flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            && limits.max_storage_buffers_per_shader_stage != 0,
+        supports_render_rgba: true,
+        supports_render_palette: true,
+        supports_debug_ui: cfg!(feature = "debug-ui"),
+        max_texture_size: limits.max_texture_dimension_2d,
+    };
+}
+
+/// Render into the palette indices of the active map instead of the RGBA colors,
+/// or switch back. The indices of each frame are read with `rv_read_palette_indices()`,
+/// and nothing is drawn into the default framebuffer in the meantime.
+#[no_mangle]
+pub extern "C" fn rv_set_render_palette(ctx: &mut Context, enabled: bool) {
+    ctx.palette_output = if enabled {
+        Some(PaletteOutput::new(&ctx.gfx))
+    } else {
+        None
+    };
+}
+
+/// Copy the palette indices of the last rendered frame, one byte per pixel,
+/// with rows of `pitch` bytes going from the top.
+/// Returns false if the palette output is not enabled, or the indices can't be read.
+///
+/// # Safety
+///
+/// `data` has to point to at least `pitch * height` bytes of the screen,
+/// and `pitch` has to be at least its width.
+#[no_mangle]
+pub unsafe extern "C" fn rv_read_palette_indices(
+    ctx: &mut Context,
+    data: *mut u8,
+    pitch: u32,
+) -> bool {
+    let output = match ctx.palette_output {
+        Some(ref output) => output,
+        None => {
+            log::error!("Palette output is not enabled");
+            return false;
+        }
+    };
+    let size = ctx.gfx.screen_size;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let slice = output.readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    ctx.gfx.device.poll(wgpu::Maintain::Wait);
+    match receiver.recv() {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            log::error!("Unable to read the palette indices: {}", e);
+            return false;
+        }
+        Err(_) => return false,
+    }
+    {
+        let mapped = slice.get_mapped_range();
+        let target = slice::from_raw_parts_mut(data, (pitch * size.height) as usize);
+        for (dst, src) in target
+            .chunks_mut(pitch as usize)
+            .zip(mapped.chunks(output.bytes_per_row as usize))
+        {
+            let width = size.width as usize;
+            dst[..width].copy_from_slice(&src[..width]);
+        }
+    }
+    output.readback.unmap();
+    true
+}
+
+/// Fill in the statistics of the last rendered frame.
+#[no_mangle]
+pub extern "C" fn rv_get_stats(ctx: &Context, stats: &mut vangers::render::Stats) {
+    *stats = match ctx.active_level {
+        Some(key) => ctx.levels[key].render.stats,
+        None => Default::default(),
+    };
+}
+
+/// Fill in the estimated GPU memory usage by subsystem, in bytes.
+/// The usage is logged as a warning once it goes over the budget.
+#[no_mangle]
+pub extern "C" fn rv_get_memory_report(
+    ctx: &Context,
+    report: &mut vangers::render::memory::Report,
+) {
+    *report = match ctx.active_level {
+        Some(key) => ctx.levels[key].render.memory_report(),
+        None => vangers::render::memory::Report::current(
+            (ctx.render_config.memory_budget_mb as u64) << 20,
+        ),
+    };
+}
+
+/// Fill in the light transform and the shadow map of the last rendered frame,
+/// for the host to shade its own sprites and overlays the same way.
+///
+/// The matrix takes the world positions into the clip space of the light,
+/// with X and Y in [-1, 1] going right and up on the map, and the depth in [0, 1].
+/// There is a single shadow map, no cascades. It's written as 16 floats, column-major.
+///
+/// The texture is the GL name of the depth texture on the GLES backend, 0 otherwise.
+/// It's owned by the renderer: the host may only sample it, with its own sampler,
+/// between the `rv_render()` calls. It belongs to the active map, and stays valid
+/// until the map is destroyed, the device is recovered, or `rv_exit()` is called.
+/// The contents and the matrix change with every `rv_render()` of the map.
+///
+/// Returns false, leaving the outputs untouched, if there is no active map
+/// or the shadows are disabled by the render config.
+///
+/// # Safety
+///
+/// `out_matrix` has to point to 16 floats.
+#[no_mangle]
+pub unsafe extern "C" fn rv_get_shadow_info(
+    ctx: &Context,
+    out_matrix: *mut f32,
+    out_texture: &mut u32,
+) -> bool {
+    let shadow = match ctx.active_level {
+        Some(key) => match ctx.levels[key].render.shadow {
+            Some(ref shadow) => shadow,
+            None => return false,
+        },
+        None => return false,
+    };
+    let matrix: [[f32; 4]; 4] = shadow.light_view_proj().into();
+    slice::from_raw_parts_mut(out_matrix, 16).copy_from_slice(bytemuck::cast_slice(&matrix));
+    *out_texture = 0;
+    shadow.texture().as_hal::<hal::api::Gles, _>(|texture| {
+        if let Some(&hal::gles::TextureInner::Texture { raw, .. }) = texture.map(|t| &t.inner) {
+            *out_texture = raw.0.get();
+        }
+    });
+    true
 }
 
 fn vec_i2f(v: [i32; 3]) -> [f32; 3] {
@@ -501,6 +1565,7 @@ fn vec_i2f(v: [i32; 3]) -> [f32; 3] {
 }
 
 #[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn rv_model_create(
     ctx: &mut Context,
     name: *const raw::c_char,
@@ -518,43 +1583,36 @@ pub extern "C" fn rv_model_create(
 
     let num_vertices = model.num_poly as usize * 3;
     log::debug!("\tGot {} GPU vertices...", num_vertices);
-    let vertex_size = mem::size_of::<ObjectVertex>();
-    let vertex_buf = ctx.gfx.device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some(label),
-        size: (num_vertices * vertex_size) as wgpu::BufferAddress,
-        usage: wgpu::BufferUsages::VERTEX,
-        mapped_at_creation: true,
-    });
-    {
-        let polygons = unsafe { slice::from_raw_parts(model.polygons, model.num_poly as usize) };
-        let mut mapping = vertex_buf.slice(..).get_mapped_range_mut();
-        for (chunk, tri) in mapping.chunks_mut(3 * vertex_size).zip(polygons) {
-            let out_vertices =
-                unsafe { slice::from_raw_parts_mut(chunk.as_mut_ptr() as *mut ObjectVertex, 3) };
-            for ((vo, v_ptr), n_ptr) in out_vertices.iter_mut().zip(tri.vertices).zip(tri.normals) {
-                let p = unsafe { &(*v_ptr).data };
-                let n = unsafe { &(*n_ptr).data };
-                *vo = ObjectVertex {
-                    pos: [p[0], p[1], p[2], 1],
-                    color: tri.color_id as u32,
-                    normal: [n[0], n[1], n[2], 0],
-                };
-            }
-        }
-    }
-    vertex_buf.unmap();
-
+    let polygons = unsafe { slice::from_raw_parts(model.polygons, model.num_poly as usize) };
+    // the game doesn't pass the collision shape, so there is nothing to bake the occlusion against
+    let vertices = polygons
+        .iter()
+        .flat_map(|tri| {
+            tri.vertices
+                .iter()
+                .zip(tri.normals)
+                .map(move |(&v_ptr, n_ptr)| {
+                    let p = unsafe { &(*v_ptr).data };
+                    let n = unsafe { &(*n_ptr).data };
+                    ObjectVertex {
+                        pos: [p[0], p[1], p[2], 1],
+                        color: tri.color_id as u32,
+                        normal: [n[0], n[1], n[2], 127],
+                    }
+                })
+        })
+        .collect::<Arc<[_]>>();
     let j = &model.jacobian;
-    let mesh = vangers::model::Mesh {
-        num_vertices,
-        vertex_buf,
-        offset: vec_i2f(model.off),
-        bbox: vangers::model::BoundingBox {
+    let mesh = MeshSource::new(
+        Some(label),
+        vertices,
+        vec_i2f(model.off),
+        vangers::model::BoundingBox {
             min: vec_i2f(model.min),
             max: vec_i2f(model.max),
             radius: model.rmax as f32,
         },
-        physics: m3d::Physics {
+        m3d::Physics {
             volume: model.volume as f32,
             rcm: [
                 model.rcm[0] as f32,
@@ -567,8 +1625,9 @@ pub extern "C" fn rv_model_create(
                 [j[6] as f32, j[7] as f32, j[8] as f32],
             ],
         },
-    };
-    let key = ctx.meshes.insert(Arc::new(mesh));
+        &ctx.gfx.device,
+    );
+    let key = ctx.meshes.insert(mesh);
     key.data().as_ffi()
 }
 
@@ -577,6 +1636,77 @@ pub extern "C" fn rv_model_destroy(ctx: &mut Context, handle: u64) {
     let _ = ctx.meshes.remove(slotmap::KeyData::from_ffi(handle).into());
 }
 
+/// Get the bounding box of a model in its local space, and the radius
+/// of the bounding sphere around the origin.
+/// Returns false if the handle is not valid.
+#[no_mangle]
+pub extern "C" fn rv_model_get_bounds(
+    ctx: &Context,
+    handle: u64,
+    out_min: &mut Vector3,
+    out_max: &mut Vector3,
+    out_radius: &mut f32,
+) -> bool {
+    match ctx.meshes.get(slotmap::KeyData::from_ffi(handle).into()) {
+        Some(source) => {
+            write_bounds(&source.mesh.bbox, out_min, out_max, out_radius);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Draw the silhouette of a model seen from above into a `width` by `height` bitmap,
+/// one byte per pixel with rows of `pitch` bytes going from the top, 0xFF where
+/// the model is, and 0 elsewhere. The front of the model is at the top.
+/// Returns false if the handle is not valid, if `pitch` is less than `width`,
+/// or if `pitch * height` is too large.
+///
+/// # Safety
+///
+/// `data` has to point to at least `pitch * height` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rv_model_get_pictogram(
+    ctx: &Context,
+    handle: u64,
+    width: u32,
+    height: u32,
+    data: *mut u8,
+    pitch: u32,
+) -> bool {
+    let source = match ctx.meshes.get(slotmap::KeyData::from_ffi(handle).into()) {
+        Some(source) => source,
+        None => return false,
+    };
+    let size = match vangers::model::Pictogram::bitmap_size(width, height, pitch) {
+        Some(size) => size,
+        None => {
+            log::error!(
+                "Pictogram of {}x{} doesn't fit into the rows of {} bytes",
+                width,
+                height,
+                pitch
+            );
+            return false;
+        }
+    };
+    if size == 0 {
+        return true;
+    }
+    if data.is_null() {
+        log::error!("Pictogram bitmap is null");
+        return false;
+    }
+    let triangles = source
+        .vertices
+        .chunks_exact(3)
+        .map(|tri| [0, 1, 2].map(|i| [0, 1, 2].map(|axis| tri[i].pos[axis] as f32)))
+        .collect::<Vec<_>>();
+    let picture = vangers::model::Pictogram::from_triangles(&triangles, width, height);
+    picture.copy_to(slice::from_raw_parts_mut(data, size), pitch as usize);
+    true
+}
+
 #[no_mangle]
 pub extern "C" fn rv_model_instance_create(
     ctx: &mut Context,
@@ -585,9 +1715,11 @@ pub extern "C" fn rv_model_instance_create(
 ) -> u64 {
     let mesh = &ctx.meshes[slotmap::KeyData::from_ffi(model_handle).into()];
     let key = ctx.instances.insert(MeshInstance {
-        mesh: Arc::clone(mesh),
+        mesh: mesh.clone(),
         transform: cgmath::One::one(),
+        prev_transform: None,
         color_id,
+        palette_layer: 0,
         visible: true,
     });
     key.data().as_ffi()
@@ -600,11 +1732,67 @@ pub extern "C" fn rv_model_instance_set_transform(
     t: Transform,
 ) {
     let inst = &mut ctx.instances[slotmap::KeyData::from_ffi(inst_handle).into()];
-    inst.transform = vangers::space::Transform {
-        disp: cgmath::vec3(t.position.x, t.position.y, t.position.z),
-        scale: t.scale,
-        rot: cgmath::Quaternion::new(t.rotation.w, t.rotation.x, t.rotation.y, t.rotation.z),
+    inst.transform = t.to_native();
+    inst.prev_transform = None;
+}
+
+/// Set the transforms of an instance at the last two logic ticks.
+/// The rendered transform is blended between them, see `rv_set_interpolation_alpha`.
+#[no_mangle]
+pub extern "C" fn rv_model_instance_set_transform_interpolated(
+    ctx: &mut Context,
+    inst_handle: u64,
+    prev: Transform,
+    next: Transform,
+) {
+    let inst = &mut ctx.instances[slotmap::KeyData::from_ffi(inst_handle).into()];
+    inst.transform = next.to_native();
+    inst.prev_transform = Some(prev.to_native());
+}
+
+/// Set the progress from the previous logic tick to the next one,
+/// in [0, 1] range, to be used by the following `rv_render` calls.
+#[no_mangle]
+pub extern "C" fn rv_set_interpolation_alpha(ctx: &mut Context, alpha: f32) {
+    ctx.interpolation_alpha = alpha.clamp(0.0, 1.0);
+}
+
+/// Move an instance to a new position, keeping the rest of the transform.
+/// If `on_ground` is set, the height is taken from the active map instead.
+#[no_mangle]
+pub extern "C" fn rv_model_instance_teleport(
+    ctx: &mut Context,
+    inst_handle: u64,
+    position: Vector3,
+    on_ground: bool,
+) {
+    let z = match ctx.active_level {
+        Some(key) if on_ground => {
+            let pos = cgmath::vec3(position.x, position.y, position.z);
+            ctx.levels[key].level.get_at(pos).high()
+        }
+        _ => position.z,
     };
+    let inst = &mut ctx.instances[slotmap::KeyData::from_ffi(inst_handle).into()];
+    inst.transform.disp = cgmath::vec3(position.x, position.y, z);
+    // don't blend from the old place
+    inst.prev_transform = None;
+}
+
+/// Draw an instance with a palette layer, 0 being the objects palette.
+/// Returns false if the layer is out of range.
+#[no_mangle]
+pub extern "C" fn rv_model_instance_set_palette_layer(
+    ctx: &mut Context,
+    inst_handle: u64,
+    layer: u32,
+) -> bool {
+    if layer != 0 && !is_extra_palette_layer(layer) {
+        return false;
+    }
+    let inst = &mut ctx.instances[slotmap::KeyData::from_ffi(inst_handle).into()];
+    inst.palette_layer = layer;
+    true
 }
 
 #[no_mangle]
@@ -617,6 +1805,31 @@ pub extern "C" fn rv_model_instance_set_visible(
     inst.visible = visible;
 }
 
+/// Get the axis-aligned box enclosing an instance in the world, as it's rendered
+/// with the current interpolation, and the radius of its bounding sphere
+/// around the instance position. Returns false if the handle is not valid.
+#[no_mangle]
+pub extern "C" fn rv_model_instance_get_world_bounds(
+    ctx: &Context,
+    inst_handle: u64,
+    out_min: &mut Vector3,
+    out_max: &mut Vector3,
+    out_radius: &mut f32,
+) -> bool {
+    match ctx
+        .instances
+        .get(slotmap::KeyData::from_ffi(inst_handle).into())
+    {
+        Some(inst) => {
+            let transform = inst.current_transform(ctx.interpolation_alpha);
+            let bbox = inst.mesh.mesh.bbox.transform(&transform);
+            write_bounds(&bbox, out_min, out_max, out_radius);
+            true
+        }
+        None => false,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn rv_model_instance_destroy(ctx: &mut Context, handle: u64) {
     let _ = ctx
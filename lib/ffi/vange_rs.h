@@ -0,0 +1,301 @@
+/* Rusty Vangers FFI bindings, generated by cbindgen from `lib/ffi`. Don't edit by hand. */
+
+#ifndef VANGE_RS_H
+#define VANGE_RS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define RV_MAP_UPDATE_DATA 0
+
+#define RV_MAP_UPDATE_PALETTE 1
+
+typedef struct Context Context;
+
+typedef const void *(*GlFunctionDiscovery)(const char*);
+
+typedef struct {
+  uint32_t width;
+  uint32_t height;
+  const char *render_config;
+  GlFunctionDiscovery gl_functor;
+} InitDescriptor;
+
+typedef struct {
+  float fov;
+  float aspect;
+  float near;
+  float far;
+} CameraDescription;
+
+typedef struct {
+  float x;
+  float y;
+  float z;
+} Vector3;
+
+typedef struct {
+  float x;
+  float y;
+  float z;
+  float w;
+} Quaternion;
+
+typedef struct {
+  Vector3 position;
+  float scale;
+  Quaternion rotation;
+} Transform;
+
+typedef struct {
+  int32_t x;
+  int32_t y;
+  int32_t width;
+  int32_t height;
+} Rect;
+
+typedef struct {
+  int32_t width;
+  int32_t height;
+  const uint8_t *const *lines;
+  const uint8_t *material_begin_offsets;
+  const uint8_t *material_end_offsets;
+  int32_t material_count;
+} MapDescription;
+
+typedef struct {
+  uint32_t kind;
+  Rect region;
+  uint32_t first_entry;
+  uint32_t entries_count;
+  const uint8_t *palette;
+} MapUpdate;
+
+typedef void (*DeviceLostCallback)(void *user_data);
+
+#if defined(RV_DEBUG_UI)
+typedef struct {
+  uint32_t kind;
+  float x;
+  float y;
+  uint32_t button;
+  bool pressed;
+} UiEvent;
+#endif
+
+typedef struct {
+  bool supports_shadow;
+  bool supports_voxel_terrain;
+  bool supports_render_rgba;
+  bool supports_render_palette;
+  bool supports_debug_ui;
+  uint32_t max_texture_size;
+} Capabilities;
+
+typedef struct {
+  uint32_t terrain_dispatches;
+  uint32_t object_draw_calls;
+  uint32_t object_instances;
+  uint32_t buffers_allocated;
+  uint64_t buffer_memory;
+  uint32_t dirty_rects;
+} Stats;
+
+typedef struct {
+  uint64_t terrain;
+  uint64_t voxels;
+  uint64_t meshes;
+  uint64_t instances;
+  uint64_t shadow;
+  uint64_t water;
+  uint64_t particles;
+  uint64_t other;
+  uint64_t total;
+  uint64_t budget;
+} MemoryReport;
+
+typedef struct {
+  int8_t data[3];
+  uint8_t scr[3];
+} Vertex;
+
+typedef struct {
+  int8_t data[3];
+  uint8_t i;
+  uint8_t n_power;
+} Normal;
+
+typedef struct {
+  const Vertex *vertices[3];
+  const Normal *normals[3];
+  uint8_t color_id;
+  int8_t middle[3];
+} Polygon;
+
+typedef struct {
+  int32_t num_vert;
+  const Vertex *vertices;
+  int32_t num_norm;
+  const Normal *normals;
+  int32_t num_poly;
+  const Polygon *polygons;
+  int32_t max[3];
+  int32_t min[3];
+  int32_t off[3];
+  int32_t rmax;
+  int32_t memory_allocation_method;
+  double volume;
+  double rcm[3];
+  double jacobian[9];
+} Model;
+
+
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+extern const int32_t rv_api_3;
+
+Context *rv_init(InitDescriptor desc);
+
+void rv_exit(Context *ctx);
+
+void rv_resize(Context *ctx, uint32_t width, uint32_t height);
+
+void rv_camera_init(Context *ctx, CameraDescription desc);
+
+void rv_camera_set_transform(Context *ctx, Transform t);
+
+void rv_camera_orbit(Context *ctx, float dx, float dy);
+
+void rv_camera_pan(Context *ctx, float dx, float dy);
+
+void rv_camera_zoom(Context *ctx, float delta);
+
+Rect rv_camera_get_visible_rect(Context *ctx);
+
+uint64_t rv_map_create(Context *ctx, MapDescription desc);
+
+void rv_map_activate(Context *ctx, uint64_t handle);
+
+void rv_map_destroy(Context *ctx, uint64_t handle);
+
+void rv_map_init(Context *ctx, MapDescription desc);
+
+void rv_map_exit(Context *ctx);
+
+void rv_map_update_data(Context *ctx, Rect region);
+
+void rv_map_update_palette(Context *ctx,
+                           uint32_t first_entry,
+                           uint32_t entries_count,
+                           const uint8_t *palette);
+
+void rv_map_update_batch(Context *ctx, const MapUpdate *updates, size_t count);
+
+bool rv_map_set_palette_layer(Context *ctx, uint32_t layer, const uint8_t *palette);
+
+bool rv_map_select_palette_layer(Context *ctx, uint32_t layer);
+
+void rv_map_set_flood(Context *ctx, uint32_t section, uint8_t level);
+
+uint8_t rv_map_get_height(const Context *ctx, int32_t x, int32_t y);
+
+void rv_map_set_height(Context *ctx, int32_t x, int32_t y, uint8_t height);
+
+float rv_map_get_height_fast(const Context *ctx, float x, float y);
+
+bool rv_map_trace_ray(const Context *ctx,
+                      Vector3 origin,
+                      Vector3 dir,
+                      float max_dist,
+                      Vector3 *out_hit);
+
+uint8_t rv_map_get_meta(const Context *ctx, int32_t x, int32_t y);
+
+void rv_map_set_meta(Context *ctx, int32_t x, int32_t y, uint8_t meta);
+
+void rv_render(Context *ctx, Rect viewport);
+
+bool rv_scene_snapshot(Context *ctx, const char *path);
+
+void rv_set_device_lost_callback(Context *ctx, DeviceLostCallback callback, void *user_data);
+
+bool rv_is_device_lost(Context *ctx);
+
+bool rv_device_recover(Context *ctx, GlFunctionDiscovery gl_functor);
+
+#if defined(RV_DEBUG_UI)
+bool rv_debug_ui_toggle(Context *ctx);
+#endif
+
+#if defined(RV_DEBUG_UI)
+bool rv_ui_event(Context *ctx, UiEvent event);
+#endif
+
+void rv_query_capabilities(const Context *ctx, Capabilities *caps);
+
+void rv_set_render_palette(Context *ctx, bool enabled);
+
+bool rv_read_palette_indices(Context *ctx, uint8_t *data, uint32_t pitch);
+
+void rv_get_stats(const Context *ctx, Stats *stats);
+
+void rv_get_memory_report(const Context *ctx, MemoryReport *report);
+
+bool rv_get_shadow_info(const Context *ctx, float *out_matrix, uint32_t *out_texture);
+
+uint64_t rv_model_create(Context *ctx, const char *name, const Model *model);
+
+void rv_model_destroy(Context *ctx, uint64_t handle);
+
+bool rv_model_get_bounds(const Context *ctx,
+                         uint64_t handle,
+                         Vector3 *out_min,
+                         Vector3 *out_max,
+                         float *out_radius);
+
+bool rv_model_get_pictogram(const Context *ctx,
+                            uint64_t handle,
+                            uint32_t width,
+                            uint32_t height,
+                            uint8_t *data,
+                            uint32_t pitch);
+
+uint64_t rv_model_instance_create(Context *ctx, uint64_t model_handle, uint8_t color_id);
+
+void rv_model_instance_set_transform(Context *ctx, uint64_t inst_handle, Transform t);
+
+void rv_model_instance_set_transform_interpolated(Context *ctx,
+                                                  uint64_t inst_handle,
+                                                  Transform prev,
+                                                  Transform next);
+
+void rv_set_interpolation_alpha(Context *ctx, float alpha);
+
+void rv_model_instance_teleport(Context *ctx,
+                                uint64_t inst_handle,
+                                Vector3 position,
+                                bool on_ground);
+
+bool rv_model_instance_set_palette_layer(Context *ctx, uint64_t inst_handle, uint32_t layer);
+
+void rv_model_instance_set_visible(Context *ctx, uint64_t inst_handle, bool visible);
+
+bool rv_model_instance_get_world_bounds(const Context *ctx,
+                                        uint64_t inst_handle,
+                                        Vector3 *out_min,
+                                        Vector3 *out_max,
+                                        float *out_radius);
+
+void rv_model_instance_destroy(Context *ctx, uint64_t handle);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* VANGE_RS_H */
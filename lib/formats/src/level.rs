@@ -0,0 +1,178 @@
+//! Height and meta maps of the levels, as stored in the VMC and VMP files.
+//!
+//! Every texel has a height byte and a meta byte. The meta byte holds the terrain type,
+//! the `DOUBLE_LEVEL` flag of the texels having a cave under the upper layer,
+//! and the bits of the layer delta.
+
+use byteorder::{LittleEndian as E, ReadBytesExt, WriteBytesExt};
+use log::info;
+use splay::Splay;
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+pub type TerrainType = u8;
+
+pub const DOUBLE_LEVEL: u8 = 1 << 6;
+pub const DELTA_BITS: u8 = 2;
+pub const DELTA_MASK: u8 = 0x3;
+
+/// Location of the terrain type bits in the meta byte.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TerrainBits {
+    pub shift: u8,
+    pub mask: TerrainType,
+}
+
+impl TerrainBits {
+    /// Bits for the number of terrain types of the level.
+    /// Only 8 and 16 types fit into the meta byte.
+    pub fn new(count: u8) -> io::Result<Self> {
+        match count {
+            8 => Ok(TerrainBits {
+                shift: 3,
+                mask: 0x7,
+            }),
+            16 => Ok(TerrainBits {
+                shift: 2,
+                mask: 0xF,
+            }),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported terrain count {}, expected 8 or 16", other),
+            )),
+        }
+    }
+
+    pub fn read(&self, meta: u8) -> TerrainType {
+        (meta >> self.shift) & self.mask
+    }
+
+    pub fn write(&self, tt: TerrainType) -> u8 {
+        tt << self.shift
+    }
+}
+
+pub struct LevelData {
+    pub height: Box<[u8]>,
+    pub meta: Box<[u8]>,
+    pub size: (i32, i32),
+}
+
+impl LevelData {
+    pub fn save_vmp(&self, path: &Path) -> io::Result<()> {
+        let mut vmp = BufWriter::new(File::create(path)?);
+        for (h_row, m_row) in self
+            .height
+            .chunks(self.size.0 as _)
+            .zip(self.meta.chunks(self.size.0 as _))
+        {
+            vmp.write_all(h_row)?;
+            vmp.write_all(m_row)?;
+        }
+        vmp.flush()
+    }
+
+    pub fn save_vmc(&self, path: &Path) -> io::Result<()> {
+        let mut vmc = BufWriter::new(File::create(path)?);
+
+        let base_offset = self.size.1 as u64 * (2 + 4) + Splay::tree_size();
+        for i in 0..self.size.1 {
+            vmc.write_i32::<E>(base_offset as i32 + i * self.size.0 * 2)?;
+            vmc.write_i16::<E>(self.size.0 as i16 * 2)?;
+        }
+
+        Splay::write_trivial(&mut vmc);
+        assert_eq!(vmc.stream_position()?, base_offset);
+
+        for (h_row, m_row) in self
+            .height
+            .chunks(self.size.0 as _)
+            .zip(self.meta.chunks(self.size.0 as _))
+        {
+            Splay::compress_trivial(h_row, m_row, &mut vmc);
+        }
+        vmc.flush()
+    }
+}
+
+/// Load the splay-compressed level data.
+pub fn load_vmc(path: &Path, size: (i32, i32)) -> io::Result<LevelData> {
+    use rayon::prelude::*;
+
+    info!("Loading height map...");
+    let total = (size.0 * size.1) as usize;
+    let mut level = LevelData {
+        height: vec![0u8; total].into_boxed_slice(),
+        meta: vec![0u8; total].into_boxed_slice(),
+        size,
+    };
+
+    let (splay, st_table, sz_table) = {
+        profiling::scope!("Prepare");
+        let mut vmc_base = BufReader::new(File::open(path)?);
+
+        info!("\tLoading compression tables...");
+        let mut st_table = Vec::<i32>::with_capacity(size.1 as usize);
+        let mut sz_table = Vec::<i16>::with_capacity(size.1 as usize);
+        for _ in 0..size.1 {
+            st_table.push(vmc_base.read_i32::<E>()?);
+            sz_table.push(vmc_base.read_i16::<E>()?);
+        }
+
+        info!("\tDecompressing level data...");
+        let splay = Splay::new(&mut vmc_base);
+        (splay, st_table, sz_table)
+    };
+
+    level
+        .height
+        .chunks_mut(size.0 as _)
+        .zip(level.meta.chunks_mut(size.0 as _))
+        .zip(st_table.iter().zip(&sz_table))
+        .collect::<Vec<_>>()
+        .par_chunks_mut(64)
+        .try_for_each(|source_group| -> io::Result<()> {
+            //Note: a separate file per group is required
+            let mut vmc = File::open(path)?;
+            let data_size: i16 = source_group
+                .iter()
+                .map(|(_, (_, &size))| size)
+                .max()
+                .unwrap();
+            let mut data = vec![0u8; data_size as usize];
+            for &mut ((ref mut h_row, ref mut m_row), (offset, &size)) in source_group {
+                vmc.seek(SeekFrom::Start(*offset as u64))?;
+                vmc.read_exact(&mut data[..size as usize])?;
+                splay.expand(&data[..size as usize], h_row, m_row);
+            }
+            Ok(())
+        })?;
+
+    Ok(level)
+}
+
+/// Load the uncompressed level data.
+pub fn load_vmp(path: &Path, size: (i32, i32)) -> io::Result<LevelData> {
+    let total = (size.0 * size.1) as usize;
+    let mut level = LevelData {
+        height: vec![0u8; total].into_boxed_slice(),
+        meta: vec![0u8; total].into_boxed_slice(),
+        size,
+    };
+
+    let mut vmp = BufReader::new(File::open(path)?);
+    for (h_row, m_row) in level
+        .height
+        .chunks_mut(size.0 as _)
+        .zip(level.meta.chunks_mut(size.0 as _))
+    {
+        vmp.read_exact(h_row)?;
+        vmp.read_exact(m_row)?;
+    }
+
+    Ok(level)
+}
@@ -0,0 +1,23 @@
+//! Parsers of the Vangers game data, without any graphics dependencies.
+//!
+//! This is the stable entry point for the external tools, like editors and converters.
+//! It re-exports the format crates, and adds the level data on top of them.
+
+#![deny(
+    trivial_casts,
+    trivial_numeric_casts,
+    unused,
+    unused_qualifications,
+    rust_2018_compatibility,
+    rust_2018_idioms,
+    future_incompatible,
+    nonstandard_style,
+    missing_copy_implementations
+)]
+#![allow(missing_debug_implementations, clippy::new_without_default)]
+
+pub mod level;
+
+pub use m3d;
+pub use splay;
+pub use tiff;
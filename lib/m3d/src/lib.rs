@@ -19,25 +19,29 @@ pub use self::geometry::{
 
 use byteorder::{LittleEndian as E, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize};
-use std::{fs::File, io::Write};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+};
 
 const MAX_SLOTS: usize = 3;
 const MAGIC_VERSION: u32 = 8;
 
-fn read_vec_i32<I: ReadBytesExt>(source: &mut I) -> [i32; 3] {
-    [
-        source.read_i32::<E>().unwrap(),
-        source.read_i32::<E>().unwrap(),
-        source.read_i32::<E>().unwrap(),
-    ]
+fn read_vec_i32<I: ReadBytesExt>(source: &mut I) -> io::Result<[i32; 3]> {
+    Ok([
+        source.read_i32::<E>()?,
+        source.read_i32::<E>()?,
+        source.read_i32::<E>()?,
+    ])
+}
+
+fn read_vec_i8<I: ReadBytesExt>(source: &mut I) -> io::Result<[i8; 3]> {
+    Ok([source.read_i8()?, source.read_i8()?, source.read_i8()?])
 }
 
-fn read_vec_i8<I: ReadBytesExt>(source: &mut I) -> [i8; 3] {
-    [
-        source.read_i8().unwrap(),
-        source.read_i8().unwrap(),
-        source.read_i8().unwrap(),
-    ]
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
 }
 
 fn write_vec_i32<W: WriteBytesExt>(dest: &mut W, v: [i32; 3]) {
@@ -52,7 +56,7 @@ fn write_vec_i8<W: WriteBytesExt>(dest: &mut W, v: [i8; 3]) {
     dest.write_i8(v[2]).unwrap();
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Physics {
     pub volume: f32,
     pub rcm: [f32; 3],
@@ -60,13 +64,73 @@ pub struct Physics {
 }
 
 impl Physics {
-    fn load<I: ReadBytesExt>(source: &mut I) -> Self {
+    /// Compute the mass properties of a closed collision shape, assuming unit density.
+    /// The inertia tensor is taken around the center of mass.
+    /// Returns `None` if the shape doesn't enclose any volume.
+    pub fn from_shape(shape: &Geometry<CollisionQuad>) -> Option<Self> {
+        let mut volume = 0.0f64;
+        let mut first = [0.0f64; 3];
+        let mut second = [[0.0f64; 3]; 3];
+        for quad in shape.polygons.iter() {
+            let corner = |i: usize| {
+                let p = shape.positions[quad.vertices[i] as usize];
+                [p[0] as f64, p[1] as f64, p[2] as f64]
+            };
+            let (a, b, c, d) = (corner(0), corner(1), corner(2), corner(3));
+            // sum up the tetrahedra between the origin and the fan of triangles
+            for &[p1, p2, p3] in &[[a, b, c], [a, c, d]] {
+                let v = (p1[0] * (p2[1] * p3[2] - p2[2] * p3[1])
+                    - p1[1] * (p2[0] * p3[2] - p2[2] * p3[0])
+                    + p1[2] * (p2[0] * p3[1] - p2[1] * p3[0]))
+                    / 6.0;
+                volume += v;
+                for i in 0..3 {
+                    let si = p1[i] + p2[i] + p3[i];
+                    first[i] += v * si / 4.0;
+                    for j in 0..3 {
+                        let sj = p1[j] + p2[j] + p3[j];
+                        second[i][j] +=
+                            v / 20.0 * (p1[i] * p1[j] + p2[i] * p2[j] + p3[i] * p3[j] + si * sj);
+                    }
+                }
+            }
+        }
+        if volume.abs() < 1.0 {
+            return None;
+        }
+
+        // the shapes may be wound either way
+        let sign = volume.signum();
+        let rcm = [first[0] / volume, first[1] / volume, first[2] / volume];
+        let mut covariance = [[0.0f64; 3]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[i][j] = sign * second[i][j] - volume.abs() * rcm[i] * rcm[j];
+            }
+        }
+        let trace = covariance[0][0] + covariance[1][1] + covariance[2][2];
+        let mut jacobi = [[0.0f32; 3]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                let diagonal = if i == j { trace } else { 0.0 };
+                jacobi[i][j] = (diagonal - covariance[i][j]) as f32;
+            }
+        }
+
+        Some(Physics {
+            volume: volume.abs() as f32,
+            rcm: [rcm[0] as f32, rcm[1] as f32, rcm[2] as f32],
+            jacobi,
+        })
+    }
+
+    fn load<I: ReadBytesExt>(source: &mut I) -> io::Result<Self> {
         let mut q = [0.0f32; 1 + 3 + 9];
         for qel in q.iter_mut() {
-            *qel = source.read_f64::<E>().unwrap() as f32;
+            *qel = source.read_f64::<E>()? as f32;
         }
 
-        Physics {
+        Ok(Physics {
             volume: q[0],
             rcm: [q[1], q[2], q[3]],
             jacobi: [
@@ -74,7 +138,7 @@ impl Physics {
                 [q[5], q[8], q[11]],
                 [q[6], q[9], q[12]],
             ],
-        }
+        })
     }
 
     fn write<W: WriteBytesExt>(&self, dest: &mut W) {
@@ -181,15 +245,15 @@ pub struct UpperBound {
 }
 
 impl UpperBound {
-    fn read<I: ReadBytesExt>(source: &mut I) -> Self {
-        UpperBound {
+    fn read<I: ReadBytesExt>(source: &mut I) -> io::Result<Self> {
+        Ok(UpperBound {
             dimensions: [
-                source.read_u32::<E>().unwrap(),
-                source.read_u32::<E>().unwrap(),
-                source.read_u32::<E>().unwrap(),
+                source.read_u32::<E>()?,
+                source.read_u32::<E>()?,
+                source.read_u32::<E>()?,
             ],
-            radius: source.read_u32::<E>().unwrap(),
-        }
+            radius: source.read_u32::<E>()?,
+        })
     }
 
     fn write<W: WriteBytesExt>(&self, dest: &mut W) {
@@ -207,11 +271,11 @@ pub struct BodyColor {
 }
 
 impl BodyColor {
-    fn read<I: ReadBytesExt>(source: &mut I) -> Self {
-        BodyColor {
-            offset: source.read_u32::<E>().unwrap(),
-            shift: source.read_u32::<E>().unwrap(),
-        }
+    fn read<I: ReadBytesExt>(source: &mut I) -> io::Result<Self> {
+        Ok(BodyColor {
+            offset: source.read_u32::<E>()?,
+            shift: source.read_u32::<E>()?,
+        })
     }
 
     fn write<W: WriteBytesExt>(&self, dest: &mut W) {
@@ -229,6 +293,8 @@ pub struct Model<M, S> {
     pub wheels: Vec<Wheel<M>>,
     pub debris: Vec<Debrie<M, S>>,
     pub slots: [Slot<M>; MAX_SLOTS],
+    #[serde(default)]
+    pub slot_mask: u32,
 }
 
 impl<M, S> Model<M, S> {
@@ -244,11 +310,11 @@ pub struct Bounds {
 }
 
 impl Bounds {
-    fn read<I: ReadBytesExt>(source: &mut I) -> Self {
-        Bounds {
-            coord_max: read_vec_i32(source),
-            coord_min: read_vec_i32(source),
-        }
+    fn read<I: ReadBytesExt>(source: &mut I) -> io::Result<Self> {
+        Ok(Bounds {
+            coord_max: read_vec_i32(source)?,
+            coord_min: read_vec_i32(source)?,
+        })
     }
 
     fn write<W: WriteBytesExt>(&self, dest: &mut W) {
@@ -261,6 +327,8 @@ pub trait Polygon: Sized {
     fn new(middle: [i8; 3], flat_normal: [i8; 3], material: [u32; 2], vertices: &[Vertex]) -> Self;
     fn dump(&self, vertices: &mut Vec<Vertex>) -> ([i8; 3], [i8; 3], [u32; 2]);
     fn num_vertices() -> u32;
+    /// Replace the position and normal indices with the given new ones.
+    fn remap(&mut self, positions: &[u16], normals: &[u16]);
 }
 impl Polygon for DrawTriangle {
     fn new(_middle: [i8; 3], flat_normal: [i8; 3], material: [u32; 2], v: &[Vertex]) -> Self {
@@ -278,6 +346,12 @@ impl Polygon for DrawTriangle {
     fn num_vertices() -> u32 {
         3
     }
+    fn remap(&mut self, positions: &[u16], normals: &[u16]) {
+        for v in self.vertices.iter_mut() {
+            v.pos = positions[v.pos as usize];
+            v.normal = normals[v.normal as usize];
+        }
+    }
 }
 impl Polygon for CollisionQuad {
     fn new(middle: [i8; 3], flat_normal: [i8; 3], _material: [u32; 2], v: &[Vertex]) -> Self {
@@ -295,6 +369,96 @@ impl Polygon for CollisionQuad {
     fn num_vertices() -> u32 {
         4
     }
+    fn remap(&mut self, positions: &[u16], _normals: &[u16]) {
+        for pos in self.vertices.iter_mut() {
+            *pos = positions[*pos as usize];
+        }
+    }
+}
+
+/// Merge the equal entries, returning the new index of every old one.
+fn dedup(values: &mut Vec<[i8; 3]>) -> Vec<u16> {
+    let mut unique = Vec::with_capacity(values.len());
+    let mut map = HashMap::new();
+    let remap = values
+        .iter()
+        .map(|&value| {
+            *map.entry(value).or_insert_with(|| {
+                unique.push(value);
+                unique.len() as u16 - 1
+            })
+        })
+        .collect();
+    *values = unique;
+    remap
+}
+
+impl<P: Polygon> Geometry<P> {
+    /// Weld the equal positions and normals together.
+    /// Returns the number of entries removed.
+    pub fn weld(&mut self) -> usize {
+        let count = self.positions.len() + self.normals.len();
+        let positions = dedup(&mut self.positions);
+        let normals = dedup(&mut self.normals);
+        for poly in self.polygons.iter_mut() {
+            poly.remap(&positions, &normals);
+        }
+        count - self.positions.len() - self.normals.len()
+    }
+
+    /// Middle points of the polygons, averaged from their corners.
+    pub fn middles(&self) -> Vec<[i8; 3]> {
+        let mut vertices = Vec::new();
+        self.polygons
+            .iter()
+            .map(|poly| {
+                vertices.clear();
+                poly.dump(&mut vertices);
+                let mut sum = [0i32; 3];
+                for v in vertices.iter() {
+                    let p = self.positions[v.pos as usize];
+                    for (s, c) in sum.iter_mut().zip(p) {
+                        *s += c as i32;
+                    }
+                }
+                let n = vertices.len().max(1) as i32;
+                [(sum[0] / n) as i8, (sum[1] / n) as i8, (sum[2] / n) as i8]
+            })
+            .collect()
+    }
+
+    /// Polygon indices sorted by the middle point along each axis,
+    /// as the original engine expects them to be.
+    pub fn sorted_polygons(&self) -> [Vec<u32>; 3] {
+        let middles = self.middles();
+        let sort = |axis: usize| {
+            let mut indices = (0..middles.len() as u32).collect::<Vec<_>>();
+            indices.sort_by_key(|&i| middles[i as usize][axis]);
+            indices
+        };
+        [sort(0), sort(1), sort(2)]
+    }
+}
+
+/// Data of a mesh file that is not understood, kept for writing it back as is.
+#[derive(Clone, Debug, Default)]
+pub struct RawMesh {
+    /// Per position: the full precision coordinates, and the sort info.
+    pub positions: Vec<([i32; 3], u32)>,
+    /// Per normal: the extra byte, and the sort info.
+    pub normals: Vec<(i8, u32)>,
+    /// Per polygon: the sort info, the extra byte, and the middle point.
+    /// Without the raw data, the sort info of a polygon is its index.
+    pub polygons: Vec<(u32, i8, [i8; 3])>,
+    pub sorted_polygons: [Vec<u32>; 3],
+}
+
+impl RawMesh {
+    fn fits<P>(&self, geometry: &Geometry<P>) -> bool {
+        self.positions.len() == geometry.positions.len()
+            && self.normals.len() == geometry.normals.len()
+            && self.polygons.len() == geometry.polygons.len()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -305,6 +469,11 @@ pub struct Mesh<G> {
     pub parent_rot: [i32; 3],
     pub max_radius: u32,
     pub physics: Physics,
+    /// Unknown data of the loaded file. It's written back on save
+    /// as long as the number of positions, normals, and polygons stays the same,
+    /// so reset it after editing the geometry in place.
+    #[serde(skip)]
+    pub raw: Option<Box<RawMesh>>,
 }
 
 impl<G> Mesh<G> {
@@ -316,19 +485,52 @@ impl<G> Mesh<G> {
             parent_rot: self.parent_rot,
             max_radius: self.max_radius,
             physics: self.physics,
+            raw: self.raw,
         }
     }
 }
 
+/// Counts the bytes written, to find the layout of serialized models.
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 impl<P: Polygon> Mesh<Geometry<P>> {
-    pub fn load<I: ReadBytesExt>(source: &mut I) -> Self {
+    /// Offset of the physics block within a serialized mesh:
+    /// version, 4 counts, bounds, offset, radius, and rotation.
+    const PHYSICS_OFFSET: u64 = 4 * (1 + 4 + 6 + 3 + 1 + 3);
+
+    fn serialized_size(&self) -> u64 {
+        let mut counter = ByteCounter(0);
+        self.save(&mut counter);
+        counter.0
+    }
+
+    pub fn load<I: ReadBytesExt>(source: &mut I) -> io::Result<Self> {
         profiling::scope!("Load Mesh");
-        let version = source.read_u32::<E>().unwrap();
-        assert_eq!(version, MAGIC_VERSION);
-        let num_positions = source.read_u32::<E>().unwrap();
-        let num_normals = source.read_u32::<E>().unwrap();
-        let num_polygons = source.read_u32::<E>().unwrap();
-        let _total_verts = source.read_u32::<E>().unwrap();
+        let version = source.read_u32::<E>()?;
+        if version != MAGIC_VERSION {
+            return Err(invalid_data(format!("Unknown mesh version {}", version)));
+        }
+        let num_positions = source.read_u32::<E>()?;
+        let num_normals = source.read_u32::<E>()?;
+        let num_polygons = source.read_u32::<E>()?;
+        let _total_verts = source.read_u32::<E>()?;
+        // the vertices index the positions and normals with 16 bits
+        if num_positions > 0x10000 || num_normals > 0x10000 || num_polygons > 0x10000 {
+            return Err(invalid_data(format!(
+                "Too many elements: {} positions, {} normals, {} polygons",
+                num_positions, num_normals, num_polygons
+            )));
+        }
 
         let mut result = Mesh {
             geometry: Geometry {
@@ -336,12 +538,14 @@ impl<P: Polygon> Mesh<Geometry<P>> {
                 normals: Vec::with_capacity(num_normals as usize),
                 polygons: Vec::with_capacity(num_polygons as usize),
             },
-            bounds: Bounds::read(source),
-            parent_off: read_vec_i32(source),
-            max_radius: source.read_u32::<E>().unwrap(),
-            parent_rot: read_vec_i32(source),
-            physics: Physics::load(source),
+            bounds: Bounds::read(source)?,
+            parent_off: read_vec_i32(source)?,
+            max_radius: source.read_u32::<E>()?,
+            parent_rot: read_vec_i32(source)?,
+            physics: Physics::load(source)?,
+            raw: None,
         };
+        let mut raw = RawMesh::default();
         log::debug!(
             "\tBounds {:?} with offset {:?}",
             result.bounds,
@@ -350,38 +554,45 @@ impl<P: Polygon> Mesh<Geometry<P>> {
 
         log::debug!("\tReading {} positions...", num_positions);
         for _ in 0..num_positions {
-            read_vec_i32(source); //unknown
-            let pos = read_vec_i8(source);
-            let _sort_info = source.read_u32::<E>().unwrap();
+            let full = read_vec_i32(source)?;
+            let pos = read_vec_i8(source)?;
+            let sort_info = source.read_u32::<E>()?;
             result.geometry.positions.push(pos);
+            raw.positions.push((full, sort_info));
         }
 
         log::debug!("\tReading {} normals...", num_normals);
         for _ in 0..num_normals {
-            let norm = read_vec_i8(source);
-            let _something = source.read_i8().unwrap();
-            let _sort_info = source.read_u32::<E>().unwrap();
+            let norm = read_vec_i8(source)?;
+            let something = source.read_i8()?;
+            let sort_info = source.read_u32::<E>()?;
             result.geometry.normals.push(norm);
+            raw.normals.push((something, sort_info));
         }
 
         log::debug!("\tReading {} polygons...", num_polygons);
         let mut vertices = Vec::with_capacity(4);
         for _ in 0..num_polygons {
-            let num_corners = source.read_u32::<E>().unwrap();
-            let _sort_info = source.read_u32::<E>().unwrap();
-            let material = [
-                source.read_u32::<E>().unwrap(),
-                source.read_u32::<E>().unwrap(),
-            ];
-            let flat_normal = read_vec_i8(source);
-            let _something = source.read_i8().unwrap();
-            let middle = read_vec_i8(source);
+            let num_corners = source.read_u32::<E>()?;
+            if num_corners != P::num_vertices() {
+                return Err(invalid_data(format!(
+                    "Polygon with {} corners, expected {}",
+                    num_corners,
+                    P::num_vertices()
+                )));
+            }
+            let sort_info = source.read_u32::<E>()?;
+            let material = [source.read_u32::<E>()?, source.read_u32::<E>()?];
+            let flat_normal = read_vec_i8(source)?;
+            let something = source.read_i8()?;
+            let middle = read_vec_i8(source)?;
+            raw.polygons.push((sort_info, something, middle));
 
             vertices.clear();
             for _ in 0..num_corners {
                 vertices.push(Vertex {
-                    pos: source.read_u32::<E>().unwrap() as u16,
-                    normal: source.read_u32::<E>().unwrap() as u16,
+                    pos: source.read_u32::<E>()? as u16,
+                    normal: source.read_u32::<E>()? as u16,
                 });
             }
 
@@ -392,13 +603,14 @@ impl<P: Polygon> Mesh<Geometry<P>> {
         }
 
         // sorted variable polygons
-        for _ in 0..3 {
+        for sorted in raw.sorted_polygons.iter_mut() {
             for _ in 0..num_polygons {
-                let _poly_ind = source.read_u32::<E>().unwrap();
+                sorted.push(source.read_u32::<E>()?);
             }
         }
 
-        result
+        result.raw = Some(Box::new(raw));
+        Ok(result)
     }
 
     pub fn save<W: Write>(&self, dest: &mut W) {
@@ -418,32 +630,60 @@ impl<P: Polygon> Mesh<Geometry<P>> {
         write_vec_i32(dest, self.parent_rot);
         self.physics.write(dest);
 
-        for p in &self.geometry.positions {
-            write_vec_i32(dest, [p[0] as i32, p[1] as i32, p[2] as i32]);
+        let raw = self.raw.as_deref().filter(|raw| raw.fits(&self.geometry));
+
+        for (i, p) in self.geometry.positions.iter().enumerate() {
+            let (full, sort_info) = match raw {
+                Some(raw) => raw.positions[i],
+                None => ([p[0] as i32, p[1] as i32, p[2] as i32], 0),
+            };
+            write_vec_i32(dest, full);
             write_vec_i8(dest, *p);
-            let sort_info = 0;
             dest.write_u32::<E>(sort_info).unwrap();
         }
 
-        for n in &self.geometry.normals {
+        for (i, n) in self.geometry.normals.iter().enumerate() {
+            let (something, sort_info) = match raw {
+                Some(raw) => raw.normals[i],
+                None => (0, 0),
+            };
             write_vec_i8(dest, *n);
-            dest.write_i8(0).unwrap();
-            let sort_info = 0;
+            dest.write_i8(something).unwrap();
             dest.write_u32::<E>(sort_info).unwrap();
         }
 
+        let middles = match raw {
+            Some(_) => Vec::new(),
+            None => self.geometry.middles(),
+        };
         let mut vertices = Vec::new();
-        for poly in &self.geometry.polygons {
-            let (middle, flat_normal, materials) = poly.dump(&mut vertices);
+        for (i, poly) in self.geometry.polygons.iter().enumerate() {
+            let (mut middle, flat_normal, materials) = poly.dump(&mut vertices);
+            let (sort_info, something) = match raw {
+                Some(raw) => {
+                    let (sort_info, something, raw_middle) = raw.polygons[i];
+                    // drawn polygons don't keep their middle points
+                    if P::num_vertices() == 3 {
+                        middle = raw_middle;
+                    }
+                    (sort_info, something)
+                }
+                None => {
+                    if P::num_vertices() == 3 {
+                        middle = middles[i];
+                    }
+                    // the sort info is read but not used, so the polygons written
+                    // from scratch are numbered, keeping it unique per polygon
+                    (i as u32, 0)
+                }
+            };
             dest.write_u32::<E>(vertices.len() as u32).unwrap();
-            let sort_info = 0;
             dest.write_u32::<E>(sort_info).unwrap();
 
             for m in &materials {
                 dest.write_u32::<E>(*m).unwrap();
             }
             write_vec_i8(dest, flat_normal);
-            let something = 0;
             dest.write_i8(something).unwrap();
             write_vec_i8(dest, middle);
 
@@ -453,9 +693,12 @@ impl<P: Polygon> Mesh<Geometry<P>> {
             }
         }
 
-        for _ in 0..3 {
-            for _ in 0..self.geometry.polygons.len() {
-                let poly_ind = 0; //TODO?
+        let sorted_polygons = match raw {
+            Some(raw) => raw.sorted_polygons.clone(),
+            None => self.geometry.sorted_polygons(),
+        };
+        for sorted in sorted_polygons.iter() {
+            for &poly_ind in sorted.iter() {
                 dest.write_u32::<E>(poly_ind).unwrap();
             }
         }
@@ -473,13 +716,20 @@ pub struct AnimatedMesh<G> {
 }
 
 impl<P: Polygon> AnimatedMesh<Geometry<P>> {
-    pub fn load(mut input: File) -> Self {
-        let count = input.read_u32::<E>().unwrap();
-        AnimatedMesh {
-            bound: UpperBound::read(&mut input),
-            color: BodyColor::read(&mut input),
-            meshes: (0..count).map(|_| Mesh::load(&mut input)).collect(),
-        }
+    pub fn load(mut input: File) -> io::Result<Self> {
+        let count = input.read_u32::<E>()?;
+        Ok(AnimatedMesh {
+            bound: UpperBound::read(&mut input)?,
+            color: BodyColor::read(&mut input)?,
+            meshes: (0..count)
+                .map(|_| Mesh::load(&mut input))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+
+    /// Weld the vertices of all the meshes, see `Geometry::weld`.
+    pub fn weld(&mut self) -> usize {
+        self.meshes.iter_mut().map(|m| m.geometry.weld()).sum()
     }
 
     pub fn save(&self, mut output: File) {
@@ -495,31 +745,31 @@ impl<P: Polygon> AnimatedMesh<Geometry<P>> {
 pub type FullModel = Model<DrawMesh, CollisionMesh>;
 
 impl FullModel {
-    pub fn load(mut input: File) -> Self {
+    pub fn load(mut input: File) -> io::Result<Self> {
         profiling::scope!("Load Model");
 
         log::debug!("\tReading the body...");
-        let body: DrawMesh = Mesh::load(&mut input);
+        let body: DrawMesh = Mesh::load(&mut input)?;
 
-        let bound = UpperBound::read(&mut input);
-        let num_wheels = input.read_u32::<E>().unwrap();
-        let num_debris = input.read_u32::<E>().unwrap();
-        let color = BodyColor::read(&mut input);
+        let bound = UpperBound::read(&mut input)?;
+        let num_wheels = input.read_u32::<E>()?;
+        let num_debris = input.read_u32::<E>()?;
+        let color = BodyColor::read(&mut input)?;
 
         let mut wheels = Vec::with_capacity(num_wheels as usize);
         log::debug!("\tReading {} wheels...", num_wheels);
         for _ in 0..num_wheels {
-            let steer = input.read_u32::<E>().unwrap();
+            let steer = input.read_u32::<E>()?;
             let pos = [
-                input.read_f64::<E>().unwrap() as f32,
-                input.read_f64::<E>().unwrap() as f32,
-                input.read_f64::<E>().unwrap() as f32,
+                input.read_f64::<E>()? as f32,
+                input.read_f64::<E>()? as f32,
+                input.read_f64::<E>()? as f32,
             ];
-            let width = input.read_u32::<E>().unwrap();
-            let radius = input.read_u32::<E>().unwrap();
-            let bound_index = input.read_u32::<E>().unwrap();
+            let width = input.read_u32::<E>()?;
+            let radius = input.read_u32::<E>()?;
+            let bound_index = input.read_u32::<E>()?;
             let mesh: Option<DrawMesh> = if steer != 0 {
-                Some(Mesh::load(&mut input))
+                Some(Mesh::load(&mut input)?)
             } else {
                 None
             };
@@ -538,26 +788,26 @@ impl FullModel {
         log::debug!("\tReading {} debris...", num_debris);
         for _ in 0..num_debris {
             debris.push(Debrie {
-                mesh: Mesh::load(&mut input),
-                shape: Mesh::load(&mut input),
+                mesh: Mesh::load(&mut input)?,
+                shape: Mesh::load(&mut input)?,
             });
         }
 
         log::debug!("\tReading the shape...");
-        let shape: CollisionMesh = Mesh::load(&mut input);
+        let shape: CollisionMesh = Mesh::load(&mut input)?;
 
         let mut slots = [Slot::EMPTY, Slot::EMPTY, Slot::EMPTY];
-        let slot_mask = input.read_u32::<E>().unwrap();
+        let slot_mask = input.read_u32::<E>()?;
         log::debug!("\tReading {} slot mask...", slot_mask);
         for slot in &mut slots {
             for p in &mut slot.pos {
-                *p = input.read_i32::<E>().unwrap();
+                *p = input.read_i32::<E>()?;
             }
-            slot.angle = input.read_i32::<E>().unwrap();
+            slot.angle = input.read_i32::<E>()?;
             slot.scale = 1.0;
         }
 
-        FullModel {
+        Ok(FullModel {
             body,
             shape,
             bound,
@@ -565,7 +815,77 @@ impl FullModel {
             wheels,
             debris,
             slots,
+            slot_mask,
+        })
+    }
+
+    /// Recompute the physics of the body and the debris from their collision shapes.
+    /// Returns the number of meshes updated. The wheels are left as is.
+    pub fn recompute_physics(&mut self) -> usize {
+        let mut count = 0;
+        if let Some(physics) = Physics::from_shape(&self.shape.geometry) {
+            self.body.physics = physics;
+            self.shape.physics = physics;
+            count += 2;
+        }
+        for debrie in self.debris.iter_mut() {
+            if let Some(physics) = Physics::from_shape(&debrie.shape.geometry) {
+                debrie.mesh.physics = physics;
+                debrie.shape.physics = physics;
+                count += 2;
+            }
+        }
+        count
+    }
+
+    /// Weld the vertices of all the meshes, see `Geometry::weld`.
+    /// Returns the total number of entries removed.
+    pub fn weld(&mut self) -> usize {
+        let mut count = self.body.geometry.weld() + self.shape.geometry.weld();
+        for wheel in self.wheels.iter_mut() {
+            if let Some(ref mut mesh) = wheel.mesh {
+                count += mesh.geometry.weld();
+            }
+        }
+        for debrie in self.debris.iter_mut() {
+            count += debrie.mesh.geometry.weld() + debrie.shape.geometry.weld();
+        }
+        count
+    }
+
+    /// Overwrite the physics blocks of this model, previously serialized into `dest`,
+    /// without touching the rest of the data.
+    pub fn patch_physics<W: Write + Seek>(&self, dest: &mut W) -> io::Result<()> {
+        let mut offset = 0;
+        let mut patch = |offset: u64, physics: &Physics| -> io::Result<()> {
+            dest.seek(SeekFrom::Start(offset))?;
+            let mut data = Vec::with_capacity(13 * 8);
+            physics.write(&mut data);
+            dest.write_all(&data)
+        };
+
+        patch(offset + DrawMesh::PHYSICS_OFFSET, &self.body.physics)?;
+        offset += self.body.serialized_size();
+        // bound, wheel and debris counts, and color
+        offset += 16 + 4 + 4 + 8;
+        for wheel in self.wheels.iter() {
+            // steering, position, width, radius, and bound index
+            offset += 4 + 3 * 8 + 4 + 4 + 4;
+            if let Some(ref mesh) = wheel.mesh {
+                offset += mesh.serialized_size();
+            }
+        }
+        for debrie in self.debris.iter() {
+            patch(offset + DrawMesh::PHYSICS_OFFSET, &debrie.mesh.physics)?;
+            offset += debrie.mesh.serialized_size();
+            patch(
+                offset + CollisionMesh::PHYSICS_OFFSET,
+                &debrie.shape.physics,
+            )?;
+            offset += debrie.shape.serialized_size();
         }
+        patch(offset + CollisionMesh::PHYSICS_OFFSET, &self.shape.physics)?;
+        dest.flush()
     }
 
     pub fn save(&self, mut output: File) {
@@ -595,8 +915,7 @@ impl FullModel {
 
         self.shape.save(&mut output);
 
-        let slot_mask = 0; //TODO?
-        output.write_u32::<E>(slot_mask).unwrap();
+        output.write_u32::<E>(self.slot_mask).unwrap();
         for slot in &self.slots {
             for p in &slot.pos {
                 output.write_i32::<E>(*p).unwrap();
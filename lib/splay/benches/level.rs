@@ -1,3 +1,4 @@
+#![cfg(nightly)]
 #![feature(test)]
 
 use byteorder::{LittleEndian as E, ReadBytesExt};
@@ -6,7 +7,7 @@ use std::{
     io::{Read, Seek},
 };
 
-const VMC_PATH: &'static str = "/hub/gog/Vangers/game/thechain/fostral/output.vmc";
+const VMC_PATH: &str = "/hub/gog/Vangers/game/thechain/fostral/output.vmc";
 const SIZE: [usize; 2] = [1 << 11, 1 << 14];
 
 #[bench]
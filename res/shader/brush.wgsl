@@ -0,0 +1,36 @@
+//!include globals.inc
+
+struct Locals {
+    center_radius: vec4<f32>, // XY = center, Z = radius
+    color: vec4<f32>,
+};
+@group(1) @binding(0) var<uniform> u_Locals: Locals;
+
+// width of the ring, in pixels
+const c_RingWidth: f32 = 2.0;
+// opacity of the area inside, relative to the ring
+const c_FillAlpha: f32 = 0.15;
+
+struct Varyings {
+    @builtin(position) clip_pos: vec4<f32>,
+    @location(0) world_pos: vec2<f32>,
+};
+
+@vertex
+fn main_vs(@location(0) pos: vec3<f32>) -> Varyings {
+    return Varyings(world_to_clip(vec4<f32>(pos, 1.0)), pos.xy);
+}
+
+@fragment
+fn main_fs(in: Varyings) -> @location(0) vec4<f32> {
+    let dist = length(in.world_pos - u_Locals.center_radius.xy);
+    let pixel = max(fwidth(dist), 1e-4);
+    let edge = abs(dist - u_Locals.center_radius.z) / pixel;
+    let ring = clamp(0.5 * c_RingWidth + 0.5 - edge, 0.0, 1.0);
+    let fill = select(0.0, c_FillAlpha, dist < u_Locals.center_radius.z);
+    let alpha = max(ring, fill);
+    if (alpha <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(u_Locals.color.rgb, u_Locals.color.a * alpha);
+}
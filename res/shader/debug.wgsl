@@ -2,27 +2,56 @@
 
 struct Debug {
     color: vec4<f32>,
+    // screen width and height, line width in pixels, and 1 for the anti-aliasing
+    line_params: vec4<f32>,
 };
 
 @group(1) @binding(0) var<uniform> c_Debug: Debug;
 
-layout(location = 0) in vec4 a_Pos;
-layout(location = 1) in vec4 a_Color;
-
 struct Varyings {
     @builtin(position) pos: vec4<f32>,
     @location(0) color: vec4<f32>,
+    // distance from the middle of the line, in pixels
+    @location(1) offset: f32,
 };
 
+// Lines are drawn as instanced quads, expanded across in the screen space.
 @vertex
-fn main_vs(@location(0) pos: vec4<f32>, @location(1) color: vec4<f32>) -> Varyings {
+fn main_vs(
+    @builtin(vertex_index) index: u32,
+    @location(0) line_start: vec3<f32>,
+    @location(1) line_end: vec3<f32>,
+    @location(2) color: vec4<f32>,
+) -> Varyings {
+    let a = world_to_clip(vec4<f32>(line_start, 1.0));
+    let b = world_to_clip(vec4<f32>(line_end, 1.0));
+    let half_screen = 0.5 * c_Debug.line_params.xy;
+    let delta = (b.xy / b.w - a.xy / a.w) * half_screen;
+    var dir = vec2<f32>(1.0, 0.0);
+    if (dot(delta, delta) > 0.0) {
+        dir = normalize(delta);
+    }
+    // leave a pixel to fade out with the anti-aliasing
+    let half_width = 0.5 * c_Debug.line_params.z + c_Debug.line_params.w;
+    let side = f32(index & 1u) * 2.0 - 1.0;
+    var pos = a;
+    if (index >= 2u) {
+        pos = b;
+    }
+    let shift = vec2<f32>(-dir.y, dir.x) * side * half_width / half_screen;
     return Varyings(
-        u_Globals.view_proj * pos,
-        color,
+        vec4<f32>(pos.xy + shift * pos.w, pos.zw),
+        // the bytes of 0xRRGGBBAA come in the reverse order
+        color.wzyx * c_Debug.color,
+        side * half_width,
     );
 }
 
 @fragment
 fn main_fs(in: Varyings) -> @location(0) vec4<f32> {
-    return in.color;
+    var coverage = 1.0;
+    if (c_Debug.line_params.w > 0.0) {
+        coverage = clamp(0.5 * c_Debug.line_params.z + 0.5 - abs(in.offset), 0.0, 1.0);
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
 }
@@ -1,10 +1,17 @@
 struct Globals {
     camera_pos: vec4<f32>,
+    // the matrices take positions relative to the origin
     view_proj: mat4x4<f32>,
     inv_view_proj: mat4x4<f32>,
     light_view_proj: mat4x4<f32>,
     light_pos: vec4<f32>,
     light_color: vec4<f32>, // not used
+    origin: vec4<f32>,
 };
 
 @group(0) @binding(0) var<uniform> u_Globals: Globals;
+
+// Project a homogeneous world position, moving it to the origin first.
+fn world_to_clip(pos: vec4<f32>) -> vec4<f32> {
+    return u_Globals.view_proj * vec4<f32>(pos.xyz - u_Globals.origin.xyz * pos.w, pos.w);
+}
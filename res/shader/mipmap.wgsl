@@ -0,0 +1,25 @@
+// Downsampling of a texture level into the next one.
+
+@group(0) @binding(0) var t_Source: texture_2d<f32>;
+@group(0) @binding(1) var s_Source: sampler;
+
+struct Varyings {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) tc: vec2<f32>,
+};
+
+@vertex
+fn main_vs(@builtin(vertex_index) index: u32) -> Varyings {
+    // a triangle covering the whole target
+    let tc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return Varyings(
+        vec4<f32>(tc.x * 2.0 - 1.0, 1.0 - tc.y * 2.0, 0.0, 1.0),
+        tc,
+    );
+}
+
+@fragment
+fn main_fs(in: Varyings) -> @location(0) vec4<f32> {
+    // linear filtering at the texel corners averages 2x2 source texels
+    return textureSampleLevel(t_Source, s_Source, in.tc, 0.0);
+}
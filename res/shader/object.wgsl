@@ -14,6 +14,9 @@ struct Geometry {
     @location(3) pos_scale: vec4<f32>,
     @location(4) orientation: vec4<f32>,
     @location(6) body_and_color_id: vec2<u32>,
+    @location(7) palette_layer: u32,
+    @location(8) fade: f32,
+    @location(9) alpha: f32,
 };
 
 struct BodyGeometry {
@@ -42,8 +45,9 @@ fn geometry_vs(
     let body = get_body(geo.body_and_color_id.x);
 
     let local = qrot(geo.orientation, vec3<f32>(vertex.xyz)) * geo.pos_scale.w + geo.pos_scale.xyz;
-    let world = qrot(body.orient, local) * body.scale + body.pos;
-    return u_Globals.view_proj * vec4<f32>(world, 1.0);
+    // the instances are relative to the origin already
+    let relative = qrot(body.orient, local) * body.scale + body.pos;
+    return u_Globals.view_proj * vec4<f32>(relative, 1.0);
 }
 
 struct Varyings {
@@ -51,6 +55,10 @@ struct Varyings {
     @location(0) palette_range: vec2<f32>,
     @location(1) position: vec3<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) occlusion: f32,
+    @location(4) @interpolate(flat) palette_layer: u32,
+    @location(5) @interpolate(flat) fade: f32,
+    @location(6) @interpolate(flat) alpha: f32,
 };
 
 @vertex
@@ -63,7 +71,7 @@ fn color_vs(
     let body = get_body(geo.body_and_color_id.x);
 
     let local = qrot(geo.orientation, vec3<f32>(vertex.xyz)) * geo.pos_scale.w + geo.pos_scale.xyz;
-    let world = qrot(body.orient, local) * body.scale + body.pos;
+    let relative = qrot(body.orient, local) * body.scale + body.pos;
 
     let color_id = select(color_index, geo.body_and_color_id.y, color_index == c_BodyColorId);
     let range = textureLoad(t_ColorTable, i32(color_id), 0).xy;
@@ -72,24 +80,48 @@ fn color_vs(
     let n = normalize(normal.xyz);
     let world_normal = qrot(body.orient, qrot(geo.orientation, n));
     return Varyings(
-        u_Globals.view_proj * vec4<f32>(world, 1.0),
+        u_Globals.view_proj * vec4<f32>(relative, 1.0),
         palette_range,
-        world,
+        relative + u_Globals.origin.xyz,
         world_normal,
+        normal.w,
+        geo.palette_layer,
+        geo.fade,
+        geo.alpha,
     );
 }
 
 
 @group(0) @binding(1) var s_PaletteSampler: sampler;
-@group(1) @binding(1) var t_Palette: texture_1d<f32>;
+@group(1) @binding(1) var t_Palette: texture_2d_array<f32>;
 
-@fragment
-fn color_fs(in: Varyings, @builtin(front_facing) is_front: bool) -> @location(0) vec4<f32> {
+fn shade(in: Varyings, is_front: bool) -> vec4<f32> {
     let lit_factor = fetch_shadow(in.position);
     let normal = normalize(in.normal) * select(-1.0, 1.0, is_front);
     let light = normalize(u_Globals.light_pos.xyz - in.position * u_Globals.light_pos.w);
-    let n_dot_l = lit_factor * max(0.0, dot(normal, light));
+    // the baked occlusion darkens the ambient share of the shadow only, not the direct light
+    let ambient = c_Ambient * in.occlusion;
+    let n_dot_l = (ambient + max(0.0, lit_factor - c_Ambient)) * max(0.0, dot(normal, light));
     let tc_raw = mix(in.palette_range.x, in.palette_range.y, n_dot_l);
     let tc = clamp(tc_raw, in.palette_range.x + 0.5, in.palette_range.y - 0.5) / 256.0;
-    return textureSample(t_Palette, s_PaletteSampler, tc);
+    let color = textureSample(t_Palette, s_PaletteSampler, vec2<f32>(tc, 0.5), i32(in.palette_layer));
+    return vec4<f32>(color.rgb, color.a * in.alpha);
+}
+
+@fragment
+fn color_fs(in: Varyings, @builtin(front_facing) is_front: bool) -> @location(0) vec4<f32> {
+    return shade(in, is_front);
+}
+
+// Only the objects fading out go through here, since the discard
+// disables the early depth test for the whole pipeline.
+@fragment
+fn faded_fs(in: Varyings, @builtin(front_facing) is_front: bool) -> @location(0) vec4<f32> {
+    let color = shade(in, is_front);
+    // screen-door transparency of the fading out objects
+    let noise = fract(52.9829189 * fract(dot(in.proj_pos.xy, vec2<f32>(0.06711056, 0.00583715))));
+    if (noise >= in.fade) {
+        discard;
+    }
+    return color;
 }
@@ -0,0 +1,32 @@
+// Conversion of the rendered colors into the nearest palette indices.
+
+struct Palette {
+    // linear colors, with the alpha ignored
+    colors: array<vec4<f32>, 256>,
+};
+
+@group(0) @binding(0) var t_Color: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> u_Palette: Palette;
+
+@vertex
+fn main_vs(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    // a triangle covering the whole target
+    let tc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(tc.x * 2.0 - 1.0, 1.0 - tc.y * 2.0, 0.0, 1.0);
+}
+
+@fragment
+fn main_fs(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<u32> {
+    let color = textureLoad(t_Color, vec2<i32>(pos.xy), 0).xyz;
+    var best = 0u;
+    var best_distance = 4.0;
+    for (var i = 0u; i < 256u; i += 1u) {
+        let diff = u_Palette.colors[i].xyz - color;
+        let distance = dot(diff, diff);
+        if (distance < best_distance) {
+            best = i;
+            best_distance = distance;
+        }
+    }
+    return vec4<u32>(best, 0u, 0u, 0u);
+}
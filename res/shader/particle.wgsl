@@ -0,0 +1,90 @@
+//!include globals.inc surface.inc terrain/collision.inc
+
+struct Particle {
+    pos_life: vec4<f32>, // XYZ = position, W = remaining life in seconds
+    vel: vec4<f32>, // XYZ = velocity, W = color bytes packed into an integer
+};
+
+struct Constants {
+    params: vec4<f32>, // X = time delta, Y = gravity, Z = restitution, W = friction
+};
+
+@group(2) @binding(0) var<storage, read_write> b_Particles: array<Particle>;
+@group(2) @binding(1) var<uniform> u_Constants: Constants;
+
+@compute @workgroup_size(64, 1, 1)
+fn update(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if (index >= arrayLength(&b_Particles)) {
+        return;
+    }
+    var particle = b_Particles[index];
+    if (particle.pos_life.w <= 0.0) {
+        return;
+    }
+
+    let dt = u_Constants.params.x;
+    var vel = particle.vel.xyz - vec3<f32>(0.0, 0.0, u_Constants.params.y * dt);
+    var pos = particle.pos_life.xyz + vel * dt;
+
+    let contact = collide_terrain(pos);
+    if (contact.depth > 0.0) {
+        pos += contact.depth * contact.normal;
+        let normal_speed = dot(vel, contact.normal);
+        if (normal_speed < 0.0) {
+            let tangent = vel - normal_speed * contact.normal;
+            vel = (1.0 - u_Constants.params.w) * tangent - u_Constants.params.z * normal_speed * contact.normal;
+        }
+    }
+
+    particle.pos_life = vec4<f32>(pos, particle.pos_life.w - dt);
+    particle.vel = vec4<f32>(vel, particle.vel.w);
+    b_Particles[index] = particle;
+}
+
+const c_ParticleSize: f32 = 1.5;
+
+struct Varyings {
+    @builtin(position) clip_pos: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) alpha: f32,
+    @location(2) color: vec3<f32>,
+};
+
+@vertex
+fn main_vs(
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) pos_life: vec4<f32>,
+    @location(1) vel: vec4<f32>,
+) -> Varyings {
+    let corner = vec2<f32>(
+        select(-1.0, 1.0, vertex_index == 1u || vertex_index >= 4u),
+        select(-1.0, 1.0, vertex_index == 2u || vertex_index == 3u || vertex_index == 5u),
+    );
+    // camera-facing quad, extended along the screen axes
+    let right = normalize((u_Globals.inv_view_proj * vec4<f32>(1.0, 0.0, 0.0, 0.0)).xyz);
+    let up = normalize((u_Globals.inv_view_proj * vec4<f32>(0.0, 1.0, 0.0, 0.0)).xyz);
+    let world_pos = pos_life.xyz + c_ParticleSize * (corner.x * right + corner.y * up);
+    // dead particles are collapsed outside of the view
+    let clip_pos = select(
+        vec4<f32>(0.0, 0.0, -2.0, 1.0),
+        world_to_clip(vec4<f32>(world_pos, 1.0)),
+        pos_life.w > 0.0,
+    );
+    let bytes = u32(vel.w);
+    let color = vec3<f32>(
+        f32(bytes & 0xFFu),
+        f32((bytes >> 8u) & 0xFFu),
+        f32((bytes >> 16u) & 0xFFu),
+    ) / 255.0;
+    return Varyings(clip_pos, corner, clamp(pos_life.w, 0.0, 1.0), color);
+}
+
+@fragment
+fn main_fs(in: Varyings) -> @location(0) vec4<f32> {
+    let fade = 1.0 - dot(in.uv, in.uv);
+    if (fade <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(in.color, fade * in.alpha);
+}
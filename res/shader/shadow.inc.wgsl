@@ -12,7 +12,7 @@ fn fetch_shadow(pos: vec3<f32>) -> f32 {
         // shadow is disabled
         return 1.0;
     }
-    let homogeneous_coords = u_Globals.light_view_proj * vec4<f32>(pos, 1.0);
+    let homogeneous_coords = u_Globals.light_view_proj * vec4<f32>(pos - u_Globals.origin.xyz, 1.0);
     if (homogeneous_coords.w <= 0.0) {
         // outside of shadow projection
         return 0.0;
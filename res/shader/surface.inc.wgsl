@@ -4,15 +4,19 @@ struct SurfaceConstants {
     texture_scale: vec4<f32>,    // XY = size, Z = height scale, w = number of layers
     terrain_bits: u32, // low 4 bits = shift, high 4 bits = mask
     delta_mode: u32, // low 8 bits = power, higher 16 bits = mask
+    tile_power: u32, // 0 = not streamed
+    tile_slots_per_row: u32,
 };
 
 @group(1) @binding(0) var<uniform> u_Surface: SurfaceConstants;
 @group(1) @binding(2) var t_Terrain: texture_2d<u32>;
+@group(1) @binding(3) var t_TerrainTiles: texture_2d<u32>;
 
 const c_DoubleLevelMask: u32 = 64u;
 const c_ShadowMask: u32 = 128u;
 const c_DeltaShift: u32 = 0u;
 const c_DeltaBits: u32 = 2u;
+const c_NotResident: u32 = 0xFFFFFFFFu;
 
 struct Surface {
     low_alt: f32,
@@ -44,10 +48,28 @@ fn get_map_coordinates(pos: vec2<f32>) -> vec2<i32> {
     return vec2<i32>(pos - floor(pos / u_Surface.texture_scale.xy) * u_Surface.texture_scale.xy);
 }
 
+// Load the pair of texels, finding them in the tiles of the streamed terrain.
+// The tiles that are not resident are the flat ground.
+fn load_terrain_data(tci: vec2<i32>) -> vec4<u32> {
+    let power = u_Surface.tile_power;
+    if (power == 0u) {
+        return textureLoad(t_Terrain, vec2<i32>(tci.x/2, tci.y), 0);
+    }
+    let slot = textureLoad(t_TerrainTiles, tci >> vec2<u32>(power), 0).x;
+    if (slot == c_NotResident) {
+        return vec4<u32>(0u);
+    }
+    let tile_size = 1 << power;
+    let slots_per_row = u_Surface.tile_slots_per_row;
+    let origin = vec2<i32>(vec2<u32>(slot % slots_per_row, slot / slots_per_row) << vec2<u32>(power));
+    let pos = origin + (tci & vec2<i32>(tile_size - 1));
+    return textureLoad(t_Terrain, vec2<i32>(pos.x/2, pos.y), 0);
+}
+
 fn get_surface_impl(tci: vec2<i32>) -> Surface {
     var suf: Surface;
 
-    let data = textureLoad(t_Terrain, vec2<i32>(tci.x/2, tci.y), 0);
+    let data = load_terrain_data(tci);
     suf.is_shadowed = (data.y & c_ShadowMask) != 0u;
     let scale = u_Surface.texture_scale.z / 256.0;
 
@@ -0,0 +1,28 @@
+// Terrain collision queries that only need the surface data,
+// so they can be used from compute shaders as well.
+// Depends on "surface.inc".
+
+struct TerrainContact {
+    // positive when the point is below the ground
+    depth: f32,
+    normal: vec3<f32>,
+};
+
+fn get_ground_height(pos: vec3<f32>) -> f32 {
+    let suf = get_surface(pos.xy);
+    // between the layers, the lower one is the ground
+    return select(suf.low_alt, suf.high_alt, pos.z >= suf.mid_alt);
+}
+
+fn get_ground_normal(pos: vec3<f32>) -> vec3<f32> {
+    let dx = get_ground_height(pos + vec3<f32>(1.0, 0.0, 0.0)) - get_ground_height(pos - vec3<f32>(1.0, 0.0, 0.0));
+    let dy = get_ground_height(pos + vec3<f32>(0.0, 1.0, 0.0)) - get_ground_height(pos - vec3<f32>(0.0, 1.0, 0.0));
+    return normalize(vec3<f32>(-dx, -dy, 2.0));
+}
+
+fn collide_terrain(pos: vec3<f32>) -> TerrainContact {
+    var contact: TerrainContact;
+    contact.depth = get_ground_height(pos) - pos.z;
+    contact.normal = get_ground_normal(pos);
+    return contact;
+}
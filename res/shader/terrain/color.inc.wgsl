@@ -2,8 +2,8 @@
 
 // Terrain parameters per type: shadow offset, height shift, palette start, palette end
 @group(1) @binding(5) var t_Table: texture_1d<u32>;
-// corresponds to SDL palette
-@group(1) @binding(6) var t_Palette: texture_1d<f32>;
+// corresponds to SDL palette, one per layer
+@group(1) @binding(6) var t_Palette: texture_2d_array<f32>;
 
 @group(0) @binding(1) var s_Palette: sampler;
 
@@ -59,7 +59,94 @@ fn evaluate_color_id(ty: u32, pos: vec3<f32>, lit_factor: f32) -> f32 {
     return evaluate_palette(ty, lit_factor * tmp);
 }
 
+// Debug visualization modes, see `DebugMode` in "terrain.rs".
+// Each mode encodes its data into an 8-bit value, which is then turned into a color.
+const c_DebugNone: u32 = 0u;
+const c_DebugHeight: u32 = 1u;
+const c_DebugTerrainType: u32 = 2u;
+const c_DebugDeltaMask: u32 = 3u;
+const c_DebugVoxelLod: u32 = 4u;
+const c_DebugStepCount: u32 = 5u;
+
+const c_DebugUpperLayer: u32 = 128u;
+
+fn debug_hue(index: u32) -> vec3<f32> {
+    // golden ratio spreads the neighbors apart
+    let h = fract(f32(index) * 0.618034);
+    return clamp(abs(fract(h + vec3<f32>(0.0, 2.0, 1.0) / 3.0) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn debug_heat(value: f32) -> vec3<f32> {
+    return clamp(vec3<f32>(value * 3.0, value * 3.0 - 1.0, value * 3.0 - 2.0), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn evaluate_debug_value(ty: u32, pos: vec3<f32>) -> u32 {
+    switch (u_Locals.debug_mode.x) {
+        case 1u: { // c_DebugHeight
+            return u32(clamp(pos.z / u_Surface.texture_scale.z, 0.0, 1.0) * 255.0);
+        }
+        case 2u: { // c_DebugTerrainType
+            return ty;
+        }
+        case 3u: { // c_DebugDeltaMask
+            let suf = get_surface(pos.xy);
+            if (suf.low_alt >= suf.high_alt) {
+                return 0u;
+            }
+            let delta = (suf.mid_alt - suf.low_alt) / u_Surface.texture_scale.z;
+            let layer = select(0u, c_DebugUpperLayer, pos.z >= suf.mid_alt);
+            return layer | (1u + u32(clamp(delta * 4.0, 0.0, 1.0) * 126.0));
+        }
+        default: {
+            return 0u;
+        }
+    }
+}
+
+fn debug_value_color(value: u32) -> vec4<f32> {
+    var color = vec3<f32>(0.0);
+    switch (u_Locals.debug_mode.x) {
+        case 1u: { // c_DebugHeight
+            color = vec3<f32>(f32(value) / 255.0);
+        }
+        case 2u, 4u: { // c_DebugTerrainType, c_DebugVoxelLod
+            color = debug_hue(value);
+        }
+        case 3u: { // c_DebugDeltaMask
+            if (value == 0u) {
+                color = vec3<f32>(0.15);
+            } else {
+                let tint = select(vec3<f32>(0.2, 0.4, 1.0), vec3<f32>(1.0, 0.3, 0.2), value >= c_DebugUpperLayer);
+                let delta = f32((value & (c_DebugUpperLayer - 1u)) - 1u) / 126.0;
+                color = tint * (0.4 + 0.6 * delta);
+            }
+        }
+        case 5u: { // c_DebugStepCount
+            color = debug_heat(f32(value) / 255.0);
+        }
+        default: {}
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+// Mip level of the palette, by the number of the terrain texels covered by a pixel.
+// The palette coordinate jumps between the texels, so its own derivatives are of no use.
+fn palette_level(pos: vec3<f32>) -> f32 {
+    let half_size = 0.5 * vec2<f32>(u_Locals.screen_rect.zw);
+    let center = world_to_clip(vec4<f32>(pos, 1.0));
+    let step_x = world_to_clip(vec4<f32>(pos + vec3<f32>(1.0, 0.0, 0.0), 1.0));
+    let step_y = world_to_clip(vec4<f32>(pos + vec3<f32>(0.0, 1.0, 0.0), 1.0));
+    let ndc = center.xy / center.w;
+    let pixels_x = length((step_x.xy / step_x.w - ndc) * half_size);
+    let pixels_y = length((step_y.xy / step_y.w - ndc) * half_size);
+    // at the grazing angles, one of the directions gets squeezed the most
+    return max(0.0, -log2(max(min(pixels_x, pixels_y), 1e-4)));
+}
+
 fn evaluate_color(ty: u32, pos: vec3<f32>, lit_factor: f32) -> vec4<f32> {
+    if (u_Locals.debug_mode.x != c_DebugNone) {
+        return debug_value_color(evaluate_debug_value(ty, pos));
+    }
     let color_id = evaluate_color_id(ty, pos, lit_factor);
-    return textureSample(t_Palette, s_Palette, color_id);
+    return textureSampleLevel(t_Palette, s_Palette, vec2<f32>(color_id, 0.5), i32(u_Locals.palette_layer.x), palette_level(pos));
 }
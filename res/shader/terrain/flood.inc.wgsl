@@ -0,0 +1,9 @@
+// Water level of the flood sections along Y.
+
+@group(1) @binding(4) var t_Flood: texture_1d<f32>;
+
+fn get_flood_level(y: f32) -> f32 {
+    let rows = i32(u_Surface.texture_scale.y);
+    let section = modulo(i32(floor(y)), rows) * i32(textureDimensions(t_Flood)) / rows;
+    return textureLoad(t_Flood, section, 0).x * u_Surface.texture_scale.z;
+}
@@ -6,6 +6,9 @@ struct Locals {
     sample_range: vec4<f32>,     // XY = X range, ZW = y range
     fog_color: vec4<f32>,
     fog_params: vec4<f32>,       // X=near, Y = far
+    cutaway: vec4<f32>,          // XY = center, Z = radius, W = strength
+    debug_mode: vec4<u32>,       // X = mode
+    palette_layer: vec4<u32>,    // X = layer
 };
 @group(1) @binding(1) var<uniform> u_Locals: Locals;
 
@@ -22,7 +25,7 @@ fn get_frag_ndc(frag_coord: vec2<f32>, z: f32) -> vec4<f32> {
 fn get_frag_world(frag_coord: vec2<f32>, z: f32) -> vec3<f32> {
     let ndc = get_frag_ndc(frag_coord, z);
     let homogeneous = u_Globals.inv_view_proj * ndc;
-    return homogeneous.xyz / homogeneous.w;
+    return homogeneous.xyz / homogeneous.w + u_Globals.origin.xyz;
 }
 
 fn apply_fog(terrain_color: vec4<f32>, world_pos: vec2<f32>) -> vec4<f32> {
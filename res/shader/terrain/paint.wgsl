@@ -37,7 +37,7 @@ fn vertex(
     let ty = select(suf.low_type, suf.high_type, is_high);
 
     return Varyings(
-        u_Globals.view_proj * vec4<f32>(pos, 1.0),
+        world_to_clip(vec4<f32>(pos, 1.0)),
         pos,
         ty,
         plane_pos,
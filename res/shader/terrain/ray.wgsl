@@ -1,10 +1,10 @@
-//!include globals.inc terrain/locals.inc surface.inc shadow.inc terrain/color.inc
+//!include globals.inc terrain/locals.inc surface.inc shadow.inc terrain/color.inc terrain/steps.inc
 
 @vertex
 fn main(@location(0) pos: vec4<i32>) -> @builtin(position) vec4<f32> {
     // orhto projections don't like infinite values
     return select(
-        u_Globals.view_proj * vec4<f32>(pos),
+        world_to_clip(vec4<f32>(pos)),
         // the expected geometry is 4 trianges meeting in the center
         vec4<f32>(vec2<f32>(pos.xy), 0.0, 0.5),
         u_Globals.view_proj[2][3] == 0.0
@@ -18,6 +18,11 @@ fn cast_ray_to_plane(level: f32, base: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
     return t * dir + base;
 }
 
+// Number of surface fetches by the ray cast, for the debug view.
+var<private> debug_steps: u32 = 0u;
+// Maximum number of steps done by `cast_ray_to_map`.
+const c_MaxSteps: u32 = 21u;
+
 struct CastResult {
     surface: Surface,
     a: vec3<f32>,
@@ -35,6 +40,7 @@ fn cast_ray_impl(
 
     for (var i = 0; i < num_forward; i = i + 1) {
         let c = a + step;
+        debug_steps += 1u;
         let suf = get_surface_alt(c.xy);
 
         if (c.z > suf.high) {
@@ -53,6 +59,7 @@ fn cast_ray_impl(
 
     for (var i = 0; i < num_binary; i += 1) {
         let c = mix(a, b, 0.5);
+        debug_steps += 1u;
         let suf = get_surface_alt(c.xy);
 
         let height = select(suf.low, suf.high, high);
@@ -78,6 +85,7 @@ fn cast_ray_impl_smooth(
 
     for (var i = 0; i < num_forward; i = i + 1) {
         let c = a + step;
+        debug_steps += 1u;
         let suf = get_surface_alt_smooth(c.xy);
 
         if (c.z > suf.high) {
@@ -96,6 +104,7 @@ fn cast_ray_impl_smooth(
 
     for (var i = 0; i < num_binary; i += 1) {
         let c = mix(a, b, 0.5);
+        debug_steps += 1u;
         let suf = get_surface_alt_smooth(c.xy);
 
         let height = select(suf.low, suf.high, high);
@@ -117,7 +126,18 @@ struct CastPoint {
     //is_shadowed: bool,
 };
 
-fn cast_ray_to_map(base: vec3<f32>, dir: vec3<f32>) -> CastPoint {
+// Returns the opacity of the upper layer of a stacked texel at this point.
+fn get_cutaway_fade(pos: vec3<f32>) -> f32 {
+    let suf = get_surface(pos.xy);
+    if (u_Locals.cutaway.w <= 0.0 || suf.low_alt >= suf.high_alt || pos.z < suf.mid_alt) {
+        return 0.0;
+    }
+    let radius = u_Locals.cutaway.z;
+    let distance = length(pos.xy - u_Locals.cutaway.xy);
+    return u_Locals.cutaway.w * (1.0 - smoothstep(0.5 * radius, radius, distance));
+}
+
+fn cast_ray_to_map(base: vec3<f32>, dir: vec3<f32>, cut_upper: bool) -> CastPoint {
     var pt: CastPoint;
 
     let a_in = select(
@@ -134,7 +154,7 @@ fn cast_ray_to_map(base: vec3<f32>, dir: vec3<f32>) -> CastPoint {
     pt.ty = suf.high_type;
     pt.is_underground = false;
 
-    if (suf.low_alt < suf.high_alt && b.z < suf.mid_alt) {
+    if (suf.low_alt < suf.high_alt && (b.z < suf.mid_alt || cut_upper)) {
         // continue the cast underground, but reserve
         // the right to re-appear above the surface.
         let cr = cast_ray_impl(b, c, false, 6, 3);
@@ -157,6 +177,9 @@ fn cast_ray_to_map(base: vec3<f32>, dir: vec3<f32>) -> CastPoint {
 }
 
 fn color_point(pt: CastPoint, lit_factor: f32) -> vec4<f32> {
+    if (u_Locals.debug_mode.x == c_DebugStepCount) {
+        return debug_value_color(min(debug_steps * 255u / c_MaxSteps, 255u));
+    }
     return evaluate_color(pt.ty, pt.pos, lit_factor);
 }
 
@@ -169,9 +192,9 @@ fn ray_depth(in: RayInput) -> @builtin(frag_depth) f32 {
     let sp_near_world = get_frag_world(in.frag_coord.xy, 0.0);
     let sp_far_world = get_frag_world(in.frag_coord.xy, 1.0);
     let view = normalize(sp_far_world - sp_near_world);
-    let pt = cast_ray_to_map(sp_near_world, view);
+    let pt = cast_ray_to_map(sp_near_world, view, false);
 
-    let target_ndc = u_Globals.view_proj * vec4<f32>(pt.pos, 1.0);
+    let target_ndc = world_to_clip(vec4<f32>(pt.pos, 1.0));
     return target_ndc.z / target_ndc.w + c_DepthBias;
 }
 
@@ -192,17 +215,40 @@ fn ray_color_debug(in: RayInput) -> FragOutput {
     return FragOutput(color, 1.0);
 }
 
-@fragment
-fn ray_color(in: RayInput) -> FragOutput {
+fn ray_color_impl(in: RayInput) -> FragOutput {
     let sp_near_world = get_frag_world(in.frag_coord.xy, 0.0);
     let sp_far_world = get_frag_world(in.frag_coord.xy, 1.0);
     let view = normalize(sp_far_world - sp_near_world);
-    let pt = cast_ray_to_map(sp_near_world, view);
+    var pt = cast_ray_to_map(sp_near_world, view, false);
 
     let lit_factor = fetch_shadow(pt.pos);
     var frag_color = color_point(pt, lit_factor);
 
-    let target_ndc = u_Globals.view_proj * vec4<f32>(pt.pos, 1.0);
+    let fade = get_cutaway_fade(pt.pos);
+    if (fade > 0.0) {
+        let pt_under = cast_ray_to_map(sp_near_world, view, true);
+        let color_under = color_point(pt_under, fetch_shadow(pt_under.pos));
+        frag_color = mix(frag_color, color_under, fade);
+        if (fade > 0.5) {
+            pt = pt_under;
+        }
+    }
+
+    let target_ndc = world_to_clip(vec4<f32>(pt.pos, 1.0));
     let depth = target_ndc.z / target_ndc.w;
     return FragOutput(frag_color, depth);
 }
+
+@fragment
+fn ray_color(in: RayInput) -> FragOutput {
+    return ray_color_impl(in);
+}
+
+// Same as `ray_color`, but also gathers the step counters.
+// Requires writable storage in fragment shaders.
+@fragment
+fn ray_color_stats(in: RayInput) -> FragOutput {
+    let out = ray_color_impl(in);
+    record_steps(debug_steps, c_MaxSteps);
+    return out;
+}
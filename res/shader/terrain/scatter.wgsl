@@ -33,7 +33,10 @@ struct CopyOutput {
 @fragment
 fn copy_fs(@builtin(position) pos: vec4<f32>) -> CopyOutput {
     let value = atomicLoad(&s_Storage.data[u32(pos.y) * u_Locals.screen_rect.z + u32(pos.x)]);
-    let color = textureLoad(t_Palette, i32(value & 255u), 0);
+    var color = textureLoad(t_Palette, vec2<i32>(i32(value & 255u), 0), i32(u_Locals.palette_layer.x), 0);
+    if (u_Locals.debug_mode.x != c_DebugNone) {
+        color = debug_value_color(value & 255u);
+    }
     let depth = f32(value >> 8u) / 16777215.0; //TODO: 0xFFFFFFu
     return CopyOutput(color, depth);
 }
@@ -46,15 +49,18 @@ fn is_visible(p: vec4<f32>) -> bool {
 }
 
 fn add_voxel(pos: vec2<f32>, altitude: f32, ty: u32, lit_factor: f32) {
-    let screen_pos = u_Globals.view_proj * vec4<f32>(pos, altitude, 1.0);
+    let screen_pos = world_to_clip(vec4<f32>(pos, altitude, 1.0));
     if (!is_visible(screen_pos)) {
         return;
     }
     var ndc = screen_pos.xyz / screen_pos.w;
     ndc.y *= -1.0; // flip Y
-    let color_id = evaluate_color_id(ty, vec3<f32>(pos, altitude), lit_factor);
+    var color_index = u32(evaluate_color_id(ty, vec3<f32>(pos, altitude), lit_factor) * 255.0);
+    if (u_Locals.debug_mode.x != c_DebugNone) {
+        color_index = evaluate_debug_value(ty, vec3<f32>(pos, altitude));
+    }
     let depth = clamp(ndc.z, 0.0, 1.0);
-    let value = (u32(depth * 16777215.0) << 8u) | u32(color_id * 255.0); //TODO: 0xFFFFFF, 0xFF
+    let value = (u32(depth * 16777215.0) << 8u) | color_index; //TODO: 0xFFFFFF, 0xFF
 
     let r = u_Locals.screen_rect;
     let tc = r.xy + clamp(
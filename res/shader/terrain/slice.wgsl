@@ -16,7 +16,7 @@ fn main_vs(
         select(r.z, r.w, (vert_index & 2u) != 0u),
         u_Surface.texture_scale.z - f32(inst_index + 1u),
         1.0);
-    return Varyings(vpos, u_Globals.view_proj * vpos);
+    return Varyings(vpos, world_to_clip(vpos));
 }
 
 
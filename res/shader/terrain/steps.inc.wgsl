@@ -0,0 +1,23 @@
+// Aggregate cost of the ray marching, see `StepCounters` in "terrain.rs".
+
+const c_StepHistogramSize: u32 = 16u;
+
+struct StepCounters {
+    pixels: atomic<u32>,
+    total: atomic<u32>,
+    max: atomic<u32>,
+    pad: u32,
+    histogram: array<atomic<u32>, 16>,
+};
+@group(2) @binding(2) var<storage, read_write> b_StepCounters: StepCounters;
+
+fn record_steps(steps: u32, max_steps: u32) {
+    if (u_Locals.debug_mode.y == 0u) {
+        return;
+    }
+    atomicAdd(&b_StepCounters.pixels, 1u);
+    atomicAdd(&b_StepCounters.total, steps);
+    atomicMax(&b_StepCounters.max, steps);
+    let bucket = min(steps * c_StepHistogramSize / max(max_steps, 1u), c_StepHistogramSize - 1u);
+    atomicAdd(&b_StepCounters.histogram[bucket], 1u);
+}
@@ -1,6 +1,7 @@
-//!include morton.inc surface.inc terrain/voxel.inc
+//!include morton.inc surface.inc terrain/flood.inc terrain/voxel.inc
 
 struct VoxelData {
+    // X = number of LODs, Y = offset of the water bits
     lod_count: vec4<u32>,
     lods: array<VoxelLod, 16>,
     occupancy: array<atomic<u32>>,
@@ -39,15 +40,18 @@ fn init(
     }
 
     let vlod = b_VoxelGrid.lods[0];
+    let water_offset = b_VoxelGrid.lod_count.y;
 
     if (all(flat_coords % u_Constants.voxel_size.xy == vec2<i32>(0))) {
         for(var z = u_Constants.update_start.z; z < u_Constants.update_end.z; z += u_Constants.voxel_size.z) {
             let voxel_coords = vec3<i32>(flat_coords, z) / u_Constants.voxel_size.xyz;
             unset_bit(linearize(vec3<u32>(voxel_coords), vlod));
+            unset_bit(linearize_water(vec3<u32>(voxel_coords), vlod, water_offset));
         }
     }
 
     let suf = get_surface_impl(flat_coords);
+    let water_alt = get_flood_level(f32(flat_coords.y));
     // All the voxel occupancy bits are unset now, wait until we can set them again.
     workgroupBarrier();
 
@@ -55,9 +59,14 @@ fn init(
         // Do a range intersection with the terrain
         let z0 = f32(z);
         let z1 = f32(z + u_Constants.voxel_size.z);
+        let voxel_coords = vec3<u32>(vec3<i32>(flat_coords, z) / u_Constants.voxel_size.xyz);
         if (z1 <= suf.low_alt || (z1 > suf.mid_alt && z0 < suf.high_alt)) {
-            let voxel_coords = vec3<i32>(flat_coords, z) / u_Constants.voxel_size.xyz;
-            set_bit(linearize(vec3<u32>(voxel_coords), vlod));
+            set_bit(linearize(voxel_coords, vlod));
+        }
+        // water is everywhere below the flood level that is not inside the terrain
+        let z_water = min(z1, water_alt);
+        if (z0 < z_water && z_water > suf.low_alt && (z0 < suf.mid_alt || z_water > suf.high_alt)) {
+            set_bit(linearize_water(voxel_coords, vlod, water_offset));
         }
     }
 }
@@ -77,7 +86,9 @@ fn mip(
     for (var i = 0u; i < 8u; i += 1u) {
         let src_coords = dst_coords * 2u + ((vec3<u32>(i) >> vec3<u32>(0u, 1u, 2u)) & vec3<u32>(1u));
         let src_index = linearize(src_coords, slod);
-        if (check_bit(src_index)) {
+        // the coarser LODs have the water as occupied
+        let has_water = u_Mip.src_lod == 0u && check_bit(linearize_water(src_coords, slod, b_VoxelGrid.lod_count.y));
+        if (check_bit(src_index) || has_water) {
             is_occupied = true;
             break;
         }
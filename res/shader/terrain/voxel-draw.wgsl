@@ -1,7 +1,9 @@
-//!include globals.inc morton.inc terrain/locals.inc surface.inc shadow.inc terrain/color.inc terrain/voxel.inc
+//!include globals.inc morton.inc terrain/locals.inc surface.inc terrain/flood.inc shadow.inc terrain/color.inc terrain/steps.inc terrain/voxel.inc
 
 struct VoxelConstants {
-    voxel_size: vec4<i32>,
+    voxel_size: vec3<i32>,
+    // LODs with the up to date mips, the coarser ones are skipped
+    ready_lod_count: u32,
     max_depth: f32,
     debug_alpha: f32,
     max_outer_steps: u32,
@@ -9,6 +11,7 @@ struct VoxelConstants {
 }
 
 struct VoxelData {
+    // X = number of LODs, Y = offset of the water bits
     lod_count: vec4<u32>,
     lods: array<VoxelLod, 16>,
     occupancy: array<u32>,
@@ -17,19 +20,30 @@ struct VoxelData {
 @group(2) @binding(0) var<storage, read> b_VoxelGrid: VoxelData;
 @group(2) @binding(1) var<uniform> u_Constants: VoxelConstants;
 
-fn check_occupancy(coordinates: vec3<i32>, lod: u32) -> bool {
+// The coarser LODs have the water as occupied, the finest one only if `with_water` is set.
+fn check_occupancy(coordinates: vec3<i32>, lod: u32, with_water: bool) -> bool {
     let lod_info = b_VoxelGrid.lods[lod];
     let ramainder = coordinates % lod_info.dim;
     // see https://github.com/gfx-rs/naga/issues/2122
     let sanitized = ramainder + select(lod_info.dim, vec3<i32>(0), ramainder >= vec3<i32>(0));
     let addr = linearize(vec3<u32>(sanitized), lod_info);
-    return (b_VoxelGrid.occupancy[addr.offset] & addr.mask) != 0u;
+    if ((b_VoxelGrid.occupancy[addr.offset] & addr.mask) != 0u) {
+        return true;
+    }
+    if (!with_water || lod != 0u) {
+        return false;
+    }
+    let water = linearize_water(vec3<u32>(sanitized), lod_info, b_VoxelGrid.lod_count.y);
+    return (b_VoxelGrid.occupancy[water.offset] & water.mask) != 0u;
 }
 
 const enable_unzoom = true;
 const step_scale = 1.0;
 
 var<private> debug_color: vec4<f32> = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+// Encoded value for the LOD and step count debug modes.
+var<private> debug_value: u32 = 0u;
+var<private> debug_steps: u32 = 0u;
 
 @vertex
 fn main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
@@ -42,6 +56,7 @@ fn main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
 }
 
 const TYPE_MISS: u32 = 0xFFu;
+const TYPE_WATER: u32 = 0xFEu;
 
 struct CastPoint {
     pos: vec3<f32>,
@@ -62,23 +77,26 @@ fn cast_miss() -> CastPoint {
     return CastPoint(vec3<f32>(0.0), TYPE_MISS);
 }
 
-fn check_hit(pos: vec3<f32>) -> u32 {
+fn check_hit(pos: vec3<f32>, with_water: bool) -> u32 {
     let suf = get_surface(pos.xy);
     if (pos.z < suf.low_alt) {
         return suf.low_type;
     } else
     if (pos.z >= suf.mid_alt && pos.z < suf.high_alt) {
         return suf.high_type;
+    } else
+    if (with_water && pos.z < get_flood_level(pos.y)) {
+        return TYPE_WATER;
     } else {
         return TYPE_MISS;
     }
 }
 
-fn cast_ray_linear(a: vec3<f32>, b: vec3<f32>, num_steps: u32) -> CastPoint {
+fn cast_ray_linear(a: vec3<f32>, b: vec3<f32>, num_steps: u32, with_water: bool) -> CastPoint {
     var pt: CastPoint;
     for (var i: u32 = 0u; i < num_steps; i += 1u) {
         let c = mix(a, b, (f32(i) + 0.5) / f32(num_steps));
-        pt.ty = check_hit(c);
+        pt.ty = check_hit(c, with_water);
         if (pt.ty != TYPE_MISS) {
             pt.pos = c;
             return pt;
@@ -98,7 +116,7 @@ fn cast_ray_binary(a_in: vec3<f32>, b_in: vec3<f32>, num_steps: u32) -> CastPoin
 
     for (var i: u32 = 0u; i < num_steps; i += 1u) {
         let c = 0.5 * (a + b);
-        let ty = check_hit(c);
+        let ty = check_hit(c, false);
         if (pt.ty == TYPE_MISS) {
             a = c;
         } else {
@@ -118,10 +136,18 @@ fn cast_ray_fallback(base: vec3<f32>, dir: vec3<f32>) -> CastPoint {
         return cast_miss();
     }
 
-    return cast_ray_linear(base + tr.x * dir, base + tr.y * dir, 10u);
+    return cast_ray_linear(base + tr.x * dir, base + tr.y * dir, 10u, false);
 }
 
-fn set_debug_color(num_outer_steps: u32, num_inner_steps: u32, extra_height: f32) {
+fn set_debug_color(num_outer_steps: u32, num_inner_steps: u32, extra_height: f32, lod: u32) {
+    if (u_Locals.debug_mode.x == c_DebugVoxelLod) {
+        debug_value = lod;
+    }
+    let max_steps = u_Constants.max_outer_steps + u_Constants.max_inner_steps;
+    debug_steps = max_steps - num_outer_steps - num_inner_steps;
+    if (u_Locals.debug_mode.x == c_DebugStepCount) {
+        debug_value = min(debug_steps * 255u / max(max_steps, 1u), 255u);
+    }
     if (u_Constants.debug_alpha > 0.0) {
         debug_color = vec4<f32>(
             extra_height * 0.1,
@@ -132,7 +158,8 @@ fn set_debug_color(num_outer_steps: u32, num_inner_steps: u32, extra_height: f32
     }
 }
 
-fn cast_ray_through_voxels(base: vec3<f32>, dir: vec3<f32>) -> CastPoint {
+// With `with_water`, the ray stops at the water surface instead of going through.
+fn cast_ray_through_voxels(base: vec3<f32>, dir: vec3<f32>, with_water: bool) -> CastPoint {
     var num_outer_steps: u32 = u_Constants.max_outer_steps;
     var num_inner_steps: u32 = u_Constants.max_inner_steps;
 
@@ -148,11 +175,12 @@ fn cast_ray_through_voxels(base: vec3<f32>, dir: vec3<f32>) -> CastPoint {
     let tpu = step_scale / abs(dir); // "t" step per unit of distance
     let t_step = min(tpu.x, min(tpu.y, tpu.y));
 
-    var lod = b_VoxelGrid.lod_count.x - 1u;
+    let lod_count = min(b_VoxelGrid.lod_count.x, u_Constants.ready_lod_count);
+    var lod = lod_count - 1u;
     let base_lod_voxel_size = vec3<f32>(u_Constants.voxel_size.xyz << vec3<u32>(lod));
     var lod_voxel_pos = vec3<i32>(floor(pos / base_lod_voxel_size));
     loop {
-        let is_occupied = check_occupancy(lod_voxel_pos, lod);
+        let is_occupied = check_occupancy(lod_voxel_pos, lod, with_water);
         if (is_occupied && lod != 0u) {
             lod -= 1u;
             // Now that we descended to a LOD level below,
@@ -181,9 +209,9 @@ fn cast_ray_through_voxels(base: vec3<f32>, dir: vec3<f32>) -> CastPoint {
         if (is_occupied && lod == 0u) {
             let num_linear_steps = min(u32(ceil(t / t_step)), num_inner_steps);
             num_inner_steps -= num_linear_steps;
-            let cp = cast_ray_linear(pos, new_pos, num_linear_steps);
+            let cp = cast_ray_linear(pos, new_pos, num_linear_steps, with_water);
             if (cp.ty != TYPE_MISS) {
-                set_debug_color(num_outer_steps, num_inner_steps, 0.0);
+                set_debug_color(num_outer_steps, num_inner_steps, 0.0, lod);
                 return cp;
             } else if (num_inner_steps == 0u) {
                 break;
@@ -196,7 +224,7 @@ fn cast_ray_through_voxels(base: vec3<f32>, dir: vec3<f32>) -> CastPoint {
         let can_raise = (lod_voxel_pos & vec3<i32>(1)) == vec3<i32>(step(vec3<f32>(0.0), dir));
         //TODO: this should just use "||", but currently GLSL backend doesn't handle it
         let will_raise = select(vec3<bool>(true), can_raise, vec3<f32>(t) == tc);
-        if (enable_unzoom && lod + 1u < b_VoxelGrid.lod_count.x && all(will_raise)) {
+        if (enable_unzoom && lod + 1u < lod_count && all(will_raise)) {
             lod += 1u;
             lod_voxel_pos = (lod_voxel_pos + select(vec3<i32>(0), vec3<i32>(-1), lod_voxel_pos < vec3<i32>(0))) / 2;
         }
@@ -209,10 +237,11 @@ fn cast_ray_through_voxels(base: vec3<f32>, dir: vec3<f32>) -> CastPoint {
     }
 
     if (dir.z >= 0.0) {
+        set_debug_color(num_outer_steps, num_inner_steps, 0.0, lod);
         return CastPoint(pos, TYPE_MISS);
     }
     let suf = get_surface(pos.xy);
-    set_debug_color(num_outer_steps, num_inner_steps, pos.z - suf.high_alt);
+    set_debug_color(num_outer_steps, num_inner_steps, pos.z - suf.high_alt, lod);
     let ty = select(suf.low_type, suf.high_type, pos.z > suf.low_alt);
     return CastPoint(pos, ty);
 }
@@ -222,13 +251,14 @@ fn draw_depth(@builtin(position) frag_coord: vec4<f32>) -> @builtin(frag_depth)
     let sp_near_world = get_frag_world(frag_coord.xy, 0.0);
     let sp_far_world = get_frag_world(frag_coord.xy, 1.0);
     let view = normalize(sp_far_world - sp_near_world);
-    let pt = cast_ray_through_voxels(sp_near_world, view);
+    // the shadows fall onto the water surface, not the bottom
+    let pt = cast_ray_through_voxels(sp_near_world, view, true);
     //let pt = cast_ray_fallback(sp_near_world, view);
     if (pt.ty == TYPE_MISS) {
         return 1.0;
     }
 
-    let target_ndc = u_Globals.view_proj * vec4<f32>(pt.pos, 1.0);
+    let target_ndc = world_to_clip(vec4<f32>(pt.pos, 1.0));
     return target_ndc.z / target_ndc.w + c_DepthBias;
 }
 
@@ -242,20 +272,29 @@ fn draw_color(@builtin(position) frag_coord: vec4<f32>) -> FragOutput {
     let sp_near_world = get_frag_world(frag_coord.xy, 0.0);
     let sp_far_world = get_frag_world(frag_coord.xy, 1.0);
     let view = normalize(sp_far_world - sp_near_world);
-    let pt = cast_ray_through_voxels(sp_near_world, view);
+    // the water is drawn on top by its own pass
+    let pt = cast_ray_through_voxels(sp_near_world, view, false);
     //let pt = cast_ray_fallback(sp_near_world, view);
+    record_steps(debug_steps, u_Constants.max_outer_steps + u_Constants.max_inner_steps);
     if (debug_color.a == 1.0) {
         return FragOutput(debug_color, 1.0);
     }
+    if (u_Locals.debug_mode.x == c_DebugStepCount && pt.ty == TYPE_MISS) {
+        // the misses are often the most expensive
+        return FragOutput(debug_value_color(debug_value), 1.0);
+    }
     if (pt.ty == TYPE_MISS) {
         return FragOutput(u_Locals.fog_color, 1.0);
     }
 
     let lit_factor = fetch_shadow(pt.pos);
-    let frag_color = evaluate_color(pt.ty, pt.pos, lit_factor);
+    var frag_color = evaluate_color(pt.ty, pt.pos, lit_factor);
+    if (u_Locals.debug_mode.x == c_DebugVoxelLod || u_Locals.debug_mode.x == c_DebugStepCount) {
+        frag_color = debug_value_color(debug_value);
+    }
     let actual_color = mix(frag_color, debug_color, debug_color.a);
 
-    let target_ndc = u_Globals.view_proj * vec4<f32>(pt.pos, 1.0);
+    let target_ndc = world_to_clip(vec4<f32>(pt.pos, 1.0));
     let depth = target_ndc.z / target_ndc.w;
     return FragOutput(actual_color, depth);
 }
@@ -284,7 +323,7 @@ fn vert_bound(
         lod += 1u;
     }
 
-    let is_occupied = check_occupancy(coord, lod);
+    let is_occupied = check_occupancy(coord, lod, false);
     if (lod == b_VoxelGrid.lod_count.x || !is_occupied) {
         return DebugOutput(vec4<f32>(0.0, 0.0, 0.0, 1.0), 0u);
     };
@@ -296,7 +335,7 @@ fn vert_bound(
     let world_pos = origin + vec3<f32>(shift) * vec3<f32>(lod_voxel_size);
 
     var out: DebugOutput;
-    out.pos = u_Globals.view_proj * vec4<f32>(world_pos, 1.0);
+    out.pos = world_to_clip(vec4<f32>(world_pos, 1.0));
     out.lod = lod;
     return out;
 }
@@ -19,3 +19,10 @@ fn linearize(coords: vec3<u32>, vlod: VoxelLod) -> BitAddress {
     addr.mask = 1u << (bit_index & 31u);
     return addr;
 }
+
+// The water bits are laid out as the finest LOD, starting at the given offset.
+fn linearize_water(coords: vec3<u32>, vlod: VoxelLod, water_offset: u32) -> BitAddress {
+    var addr = linearize(coords, vlod);
+    addr.offset += water_offset - vlod.offset;
+    return addr;
+}
@@ -4,8 +4,20 @@
 @group(1) @binding(4) var t_Flood: texture_1d<f32>;
 @group(1) @binding(8) var s_Flood: sampler;
 
+struct WaterConstants {
+    params: vec4<f32>, // X = time, Y = refraction strength, Z = foam enabled
+};
+
+@group(2) @binding(0) var<uniform> u_Water: WaterConstants;
+@group(2) @binding(1) var t_Scene: texture_2d<f32>;
+
 const c_TerrainWater = 0u;
 const c_WaterColor = vec3<f32>(0.0, 0.1, 0.4);
+const c_FoamColor = vec3<f32>(0.9, 0.9, 0.85);
+// water depth at which the foam disappears
+const c_FoamDepth = 4.0;
+// water depth at which the refraction reaches full strength
+const c_RefractionDepth = 16.0;
 
 struct Varyings {
     @builtin(position) clip_pos: vec4<f32>,
@@ -15,7 +27,7 @@ struct Varyings {
 @vertex
 fn main_vs(@location(0) pos: vec2<f32>, @location(1) flood_id: i32) -> Varyings {
     let z = textureLoad(t_Flood, flood_id, 0).x * u_Surface.texture_scale.z;
-    let clip_pos = u_Globals.view_proj * vec4<f32>(pos, z, 1.0);
+    let clip_pos = world_to_clip(vec4<f32>(pos, z, 1.0));
     return Varyings( clip_pos, vec3<f32>(pos, z) );
 }
 
@@ -29,7 +41,44 @@ fn main_fs(in: Varyings) -> @location(0) vec4<f32> {
     let shadow = fetch_shadow(in.world_pos);
 
     let view = normalize(in.world_pos - u_Globals.camera_pos.xyz);
+    let depth = in.world_pos.z - surface.low_alt;
+    let time = u_Water.params.x;
+    let ripple = vec2<f32>(
+        sin(0.3 * in.world_pos.x + 2.0 * time),
+        cos(0.25 * in.world_pos.y + 1.7 * time),
+    );
+
     //TODO: screen-space reflections
-    //TODO: read the depth texture to find out actual transparency
-    return vec4<f32>(shadow * c_WaterColor, 1.0 + 0.9*view.z);
+    var color = shadow * c_WaterColor;
+    var alpha = 1.0 + 0.9*view.z;
+
+    if (u_Water.params.y > 0.0) {
+        let offset = u_Water.params.y * clamp(depth / c_RefractionDepth, 0.0, 1.0) * ripple;
+        let max_coord = vec2<i32>(textureDimensions(t_Scene)) - vec2<i32>(1);
+        let coord = clamp(vec2<i32>(in.clip_pos.xy + offset), vec2<i32>(0), max_coord);
+        let behind = textureLoad(t_Scene, coord, 0).xyz;
+        color = mix(behind, color, alpha);
+        alpha = 1.0;
+    }
+
+    if (u_Water.params.z > 0.0) {
+        let shore = 1.0 - smoothstep(0.0, c_FoamDepth, depth);
+        let waves = 0.5 + 0.5 * sin(2.0 * depth - 3.0 * time + ripple.x);
+        let foam = shore * waves;
+        color = mix(color, shadow * c_FoamColor, foam);
+        alpha = max(alpha, foam);
+    }
+
+    return vec4<f32>(color, alpha);
+}
+
+@vertex
+fn blit_vs(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32(index & 1u), f32(index >> 1u));
+    return vec4<f32>(4.0 * uv - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn blit_fs(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(t_Scene, vec2<i32>(pos.xy), 0);
 }
@@ -11,14 +11,14 @@ pub type Price = u32;
 pub type Time = u16;
 pub type Shield = u16;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize)]
 pub enum Kind {
     Main,
     Ruffa,
     Constructor,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize)]
 pub struct CarStats {
     pub class: u8,
     pub price_buy: Price,
@@ -78,7 +78,7 @@ pub enum _Side {
 
 pub const NUM_SIDES: usize = 5;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct CarPhysics {
     pub name: String,
     // base
@@ -154,13 +154,21 @@ pub struct CarInfo {
     pub scale: f32,
 }
 
-pub fn load_registry(
-    settings: &Settings,
-    reg: &super::game::Registry,
-    device: &wgpu::Device,
-    object: &ObjectContext,
-) -> HashMap<String, CarInfo> {
-    let mut map = HashMap::new();
+/// Parameters of a car from `car.prm` and its own `.prm`, without the model.
+#[derive(Clone, Debug)]
+pub struct CarEntry {
+    pub name: String,
+    pub kind: Kind,
+    pub stats: CarStats,
+    pub physics: CarPhysics,
+    pub scale: f32,
+    /// Path to the M3D model, relative to the game data.
+    pub model_path: String,
+}
+
+/// Read the parameters of all the cars, in the order of `car.prm`.
+/// Requires the game data.
+pub fn load_entries(settings: &Settings, reg: &super::game::Registry) -> Vec<CarEntry> {
     let mut fi = Reader::new(settings.open_relative("car.prm"));
     fi.advance();
     assert_eq!(fi.cur(), "uniVang-ParametersFile_Ver_1");
@@ -173,6 +181,7 @@ pub fn load_registry(
         num_main, num_ruffa, num_const
     );
 
+    let mut entries = Vec::new();
     for i in 0..num_main + num_ruffa + num_const {
         let (name, data) = fi.next_entry();
         let mi = &reg.model_infos[name];
@@ -188,22 +197,50 @@ pub fn load_registry(
         } else {
             physics.scale_size
         };
-        let file = settings.open_relative(&mi.path);
+        entries.push(CarEntry {
+            name: name.to_owned(),
+            kind: if i < num_main {
+                Kind::Main
+            } else if i < num_main + num_ruffa {
+                Kind::Ruffa
+            } else {
+                Kind::Constructor
+            },
+            stats: CarStats::new(&data),
+            physics,
+            scale,
+            model_path: mi.path.clone(),
+        });
+    }
+
+    entries
+}
+
+pub fn load_registry(
+    settings: &Settings,
+    reg: &super::game::Registry,
+    device: &wgpu::Device,
+    object: &ObjectContext,
+) -> HashMap<String, CarInfo> {
+    let mut map = HashMap::new();
+    if !settings.has_game_data() {
+        let shape_sampling = settings.game.physics.shape_sampling;
+        let info = super::fallback::car(device, object, shape_sampling);
+        map.insert(info.physics.name.clone(), info);
+        return map;
+    }
+
+    for entry in load_entries(settings, reg) {
+        let file = settings.open_relative(&entry.model_path);
         let model = model::load_m3d(file, device, object, settings.game.physics.shape_sampling);
         map.insert(
-            name.to_owned(),
+            entry.name,
             CarInfo {
-                kind: if i < num_main {
-                    Kind::Main
-                } else if i < num_main + num_ruffa {
-                    Kind::Ruffa
-                } else {
-                    Kind::Constructor
-                },
-                stats: CarStats::new(&data),
-                physics,
+                kind: entry.kind,
+                stats: entry.stats,
+                physics: entry.physics,
                 model,
-                scale,
+                scale: entry.scale,
             },
         );
     }
@@ -8,7 +8,7 @@ pub const MAIN_LOOP_TIME: f32 = 0.05;
 pub type Traction = f32;
 pub type Angle = f32;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize)]
 pub struct VelocityPair {
     pub v: f32, //linear
     pub w: f32, //angular
@@ -20,7 +20,7 @@ impl VelocityPair {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Nature {
     pub gravity: f32,
     pub density: f32,
@@ -30,7 +30,7 @@ pub struct Nature {
     pub movement_detection_threshold: u8,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Impulse {
     pub elastic_restriction: f32,
     pub elastic_time_scale_factor: f32,
@@ -41,16 +41,52 @@ pub struct Impulse {
     pub k_friction: f32,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Car {
     pub rudder_step: Angle,
     pub rudder_max: Angle,
     pub rudder_k_decr: f32,
     pub traction_incr: Traction,
     pub traction_decr: Traction,
+    /// Not a part of the original data.
+    pub gearbox: Gearbox,
 }
 
-#[derive(Copy, Clone)]
+/// Number of the forward gears, see `Gearbox::gears`.
+pub const NUM_GEARS: usize = 3;
+
+/// How the traction follows the motor, on top of the original ramp.
+/// The defaults keep the ramp as it is: the gears don't change the acceleration,
+/// the reverse is as strong as the forward, and the turbo lasts while it's held.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Gearbox {
+    /// Forward gears: upper traction bound, as a share of the maximum,
+    /// and the factor of the acceleration within the gear.
+    pub gears: [(f32, f32); NUM_GEARS],
+    /// Limit of the backing up traction, as a share of the forward one.
+    pub reverse_share: f32,
+    /// Factor of the traction drop when switching the direction, 0 to not brake first.
+    pub reversal_brake: f32,
+    /// Longest turbo burst, in the frames of the original, 0 for unlimited.
+    pub turbo_duration: f32,
+    /// Time before the turbo can be used again, in the frames of the original.
+    pub turbo_cooldown: f32,
+}
+
+impl Default for Gearbox {
+    fn default() -> Self {
+        Gearbox {
+            gears: [(1.0 / 3.0, 1.0), (2.0 / 3.0, 1.0), (1.0, 1.0)],
+            reverse_share: 1.0,
+            reversal_brake: 0.0,
+            turbo_duration: 0.0,
+            turbo_cooldown: 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Serialize)]
 pub struct Global {
     pub speed_factor: f32,
     pub mobility_factor: f32,
@@ -61,7 +97,7 @@ pub struct Global {
     pub f_brake_max: f32,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Helicopter {
     pub max_height: u16,
     pub height_incr: u16,
@@ -80,7 +116,7 @@ pub struct Helicopter {
     pub circle_dphi: u16,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Drag {
     pub speed: VelocityPair,
     pub wheel_speed: f32,
@@ -99,13 +135,49 @@ pub struct Drag {
     pub abs_min: VelocityPair,
 }
 
-#[derive(Copy, Clone)]
+/// Most terrain types a level can have.
+pub const MAX_TERRAINS: usize = 16;
+
+/// How a terrain type feels under the wheels.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Surface {
+    /// Factor of the traction and the side grip of the wheels.
+    pub grip: f32,
+    /// Drag of the wheels rolling, per unit of the speed.
+    pub rolling: f32,
+    /// Color of the dust kicked off by the wheels.
+    pub dust: [f32; 3],
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Surface {
+            grip: 1.0,
+            rolling: 0.0,
+            dust: [0.6, 0.5, 0.4],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Serialize)]
 pub struct Terrain {
     pub dz_max: f32,
     pub min_wall_delta: f32,
+    /// Properties per terrain type, not a part of the original data.
+    pub surfaces: [Surface; MAX_TERRAINS],
+}
+
+impl Terrain {
+    /// Override the surfaces of the first terrain types.
+    pub fn set_surfaces(&mut self, surfaces: &[Surface]) {
+        for (dst, src) in self.surfaces.iter_mut().zip(surfaces) {
+            *dst = *src;
+        }
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Mole {
     pub k_elastic_mole: f32,
     pub k_mole: f32,
@@ -114,7 +186,7 @@ pub struct Mole {
     pub mole_submerging_fz: f32,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Contact {
     pub k_elastic_wheel: f32,
     pub k_elastic_spring: f32,
@@ -130,7 +202,7 @@ pub struct Contact {
     pub k_friction_spring: f32,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Force {
     pub f_spring_impulse: f32,
     pub k_spring_impulse: f32,
@@ -142,13 +214,13 @@ pub struct Force {
     pub side_impulse_duration: u8,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Speed {
     pub standard_frame_rate: u32,
     pub speed_correction_tau: f32,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize)]
 pub struct Common {
     pub nature: Nature,
     pub impulse: Impulse,
@@ -215,6 +287,7 @@ pub fn load(file: File) -> Common {
             rudder_k_decr: fi.next_key_value("rudder_k_decr:"),
             traction_incr: fi.next_key_value::<u16>("traction_increment:") as f32 * traction_scale,
             traction_decr: fi.next_key_value::<u16>("traction_decrement:") as f32 * traction_scale,
+            gearbox: Gearbox::default(),
         },
         global: Global {
             speed_factor: fi.next_key_value("global_speed_factor:"),
@@ -268,6 +341,7 @@ pub fn load(file: File) -> Common {
         terrain: Terrain {
             dz_max: fi.next_key_value("dZ_max:"),
             min_wall_delta: fi.next_key_value("MIN_WALL_DELTA:"),
+            surfaces: [Surface::default(); MAX_TERRAINS],
         },
         mole: Mole {
             k_elastic_mole: fi.next_key_value("k_elastic_mole:"),
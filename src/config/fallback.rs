@@ -0,0 +1,335 @@
+//! Built-in stand-ins for the resources of the original game.
+//!
+//! They are used with the "asset-free" feature when the game data is not found,
+//! so that the binaries can run without owning the game. The values are
+//! hand-picked approximations and don't match the original data.
+
+use super::{
+    car::{CarEntry, CarInfo, CarPhysics, CarStats, Kind, NUM_SIDES},
+    common::*,
+};
+use crate::{model, render::object::Context as ObjectContext};
+
+/// Name of the generated car.
+pub const CAR_NAME: &str = "TestCar";
+
+/// Palette with a ramp of hues along the rows, and brightness along the columns.
+pub fn palette() -> [[u8; 4]; 0x100] {
+    let mut data = [[0xFF; 4]; 0x100];
+    for (i, color) in data.iter_mut().enumerate() {
+        let (row, column) = (i >> 4, i & 0xF);
+        let value = (column * 0x11) as u32;
+        let hue = [
+            (row * 5 % 16) as u32,
+            (row * 7 % 16) as u32,
+            (row * 11 % 16) as u32,
+        ];
+        for (c, h) in color.iter_mut().zip(hue.iter()) {
+            *c = (value * (8 + h) / 23) as u8;
+        }
+    }
+    data[0] = [0, 0, 0, 0xFF];
+    data
+}
+
+fn pair(v: f32, w: f32) -> VelocityPair {
+    VelocityPair { v, w }
+}
+
+pub fn common() -> Common {
+    use std::f32::consts::PI;
+    Common {
+        nature: Nature {
+            gravity: 10.0,
+            density: 1.0,
+            time_delta0: 0.05,
+            scale_general: 1.0,
+            num_calls_analysis: 3,
+            movement_detection_threshold: 1,
+        },
+        impulse: Impulse {
+            elastic_restriction: 0.6,
+            elastic_time_scale_factor: 1.0,
+            rolling_scale: 0.5,
+            normal_threshold: 0.3,
+            k_wheel: 1.0,
+            factors: [1.0, 1.0],
+            k_friction: 0.4,
+        },
+        car: Car {
+            rudder_step: PI / 64.0,
+            rudder_max: PI / 6.0,
+            rudder_k_decr: 0.9,
+            traction_incr: 12.0 / 64.0,
+            traction_decr: 4.0 / 64.0,
+            gearbox: Gearbox::default(),
+        },
+        global: Global {
+            speed_factor: 1.0,
+            mobility_factor: 1.0,
+            water_speed_factor: 0.5,
+            air_speed_factor: 1.0,
+            underground_speed_factor: 0.7,
+            k_traction_turbo: 2.0,
+            f_brake_max: 4.0,
+        },
+        heli: Helicopter {
+            max_height: 200,
+            height_incr: 4,
+            height_decr: 2,
+            k_thrust: 1.0,
+            k_rotate: 1.0,
+            k_strife: 1.0,
+            max_time: 100,
+            convert: [1.0, 1.0],
+            rudder_decr: 0.9,
+            traction_decr: 0.9,
+            z_offset: 0.0,
+            ampl: 1.0,
+            dphi: 64,
+            circle_radius: [10.0, 10.0],
+            circle_dphi: 64,
+        },
+        drag: Drag {
+            speed: pair(0.9, 0.9),
+            wheel_speed: 0.95,
+            z: 0.9,
+            free: pair(0.99, 0.95),
+            wheel: pair(0.95, 0.9),
+            spring: pair(0.95, 0.9),
+            coll: pair(0.9, 0.9),
+            helicopter: pair(0.9, 0.9),
+            float: pair(0.9, 0.9),
+            friction: pair(0.9, 0.9),
+            abs_stop: pair(0.5, 0.05),
+            stuff: 0.9,
+            swamp: 0.9,
+            mole: 0.9,
+            abs_min: pair(0.5, 0.05),
+        },
+        terrain: Terrain {
+            dz_max: 50.0,
+            min_wall_delta: 6.0,
+            surfaces: [Surface::default(); MAX_TERRAINS],
+        },
+        mole: Mole {
+            k_elastic_mole: 1.0,
+            k_mole: 1.0,
+            k_mole_rudder: 1.0,
+            mole_emerging_fz: 1.0,
+            mole_submerging_fz: 1.0,
+        },
+        contact: Contact {
+            k_elastic_wheel: 1.0,
+            k_elastic_spring: 1.0,
+            k_elastic_xy: 1.0,
+            k_elastic_db_coll: 1.0,
+            k_destroy_level: 1.0,
+            strong_ground_collision_threshold: 10.0,
+            strong_double_collision_threshold: 10.0,
+            k_friction_wheel_x: 1.0,
+            k_friction_wheel_x_back: 1.0,
+            k_friction_wheel_y: 1.0,
+            k_friction_wheel_z: 1.0,
+            k_friction_spring: 1.0,
+        },
+        force: Force {
+            f_spring_impulse: 1.0,
+            k_spring_impulse: 1.0,
+            f_traction_impulse: 1.0,
+            k_distance_to_force: 0.01,
+            explosion: pair(1.0, 1.0),
+            max_jump_power: 30.0,
+            side_impulse_delay: 20,
+            side_impulse_duration: 3,
+        },
+        speed: Speed {
+            standard_frame_rate: 14,
+            speed_correction_tau: 1.6e-2,
+        },
+    }
+}
+
+/// Half-extents of the generated car body.
+const HALF_SIZE: [i8; 3] = [32, 48, 16];
+
+/// Generate an axis-aligned box with four wheels at the lower corners.
+pub fn car_model() -> m3d::FullModel {
+    let [a, b, c] = HALF_SIZE;
+    let positions = (0..8)
+        .map(|i| {
+            [
+                if i & 1 != 0 { a } else { -a },
+                if i & 2 != 0 { b } else { -b },
+                if i & 4 != 0 { c } else { -c },
+            ]
+        })
+        .collect::<Vec<_>>();
+    let n = m3d::NORMALIZER as i8;
+    // faces as corner indices, counter-clockwise when looking from the outside
+    let faces: [([u16; 4], [i8; 3]); 6] = [
+        ([0, 2, 3, 1], [0, 0, -n]),
+        ([4, 5, 7, 6], [0, 0, n]),
+        ([0, 1, 5, 4], [0, -n, 0]),
+        ([2, 6, 7, 3], [0, n, 0]),
+        ([0, 4, 6, 2], [-n, 0, 0]),
+        ([1, 3, 7, 5], [n, 0, 0]),
+    ];
+    let color = [m3d::ColorId::Body as u32, 0];
+
+    let mut draw = m3d::Geometry::<m3d::DrawTriangle> {
+        positions: positions.clone(),
+        normals: faces.iter().map(|&(_, normal)| normal).collect(),
+        polygons: Vec::new(),
+    };
+    let mut collision = m3d::Geometry::<m3d::CollisionQuad> {
+        positions,
+        normals: Vec::new(),
+        polygons: Vec::new(),
+    };
+    for (face_index, &(corners, normal)) in faces.iter().enumerate() {
+        let vertex = |i: usize| m3d::Vertex {
+            pos: corners[i],
+            normal: face_index as u16,
+        };
+        for &(v0, v1, v2) in &[(0, 1, 2), (0, 2, 3)] {
+            draw.polygons.push(m3d::DrawTriangle {
+                vertices: [vertex(v0), vertex(v1), vertex(v2)],
+                flat_normal: normal,
+                material: color,
+            });
+        }
+        let middle = {
+            let sum = corners.iter().fold([0i32; 3], |mut sum, &ci| {
+                for (s, &p) in sum.iter_mut().zip(collision.positions[ci as usize].iter()) {
+                    *s += p as i32;
+                }
+                sum
+            });
+            [(sum[0] / 4) as i8, (sum[1] / 4) as i8, (sum[2] / 4) as i8]
+        };
+        collision.polygons.push(m3d::CollisionQuad {
+            vertices: corners,
+            middle,
+            flat_normal: normal,
+        });
+    }
+
+    let (fa, fb, fc) = (a as f32, b as f32, c as f32);
+    let volume = 8.0 * fa * fb * fc;
+    let physics = m3d::Physics {
+        volume,
+        rcm: [0.0; 3],
+        jacobi: [
+            [volume * (fb * fb + fc * fc) / 3.0, 0.0, 0.0],
+            [0.0, volume * (fa * fa + fc * fc) / 3.0, 0.0],
+            [0.0, 0.0, volume * (fa * fa + fb * fb) / 3.0],
+        ],
+    };
+    let bounds = m3d::Bounds {
+        coord_min: [-a as i32, -b as i32, -c as i32],
+        coord_max: [a as i32, b as i32, c as i32],
+    };
+    let radius = (fa * fa + fb * fb + fc * fc).sqrt() as u32;
+
+    m3d::FullModel {
+        body: m3d::Mesh {
+            geometry: draw,
+            bounds,
+            parent_off: [0; 3],
+            parent_rot: [0; 3],
+            max_radius: radius,
+            physics,
+            raw: None,
+        },
+        shape: m3d::Mesh {
+            geometry: collision,
+            bounds,
+            parent_off: [0; 3],
+            parent_rot: [0; 3],
+            max_radius: radius,
+            physics,
+            raw: None,
+        },
+        bound: m3d::UpperBound {
+            dimensions: [a as u32, b as u32, c as u32],
+            radius,
+        },
+        color: m3d::BodyColor {
+            offset: 128,
+            shift: 3,
+        },
+        wheels: [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+            .iter()
+            .map(|&(x, y)| m3d::Wheel {
+                mesh: None,
+                steer: 0,
+                pos: [x * fa, y * 0.7 * fb, -fc],
+                width: 8,
+                radius: 12,
+                bound_index: 0,
+            })
+            .collect(),
+        debris: Vec::new(),
+        slots: [m3d::Slot::EMPTY, m3d::Slot::EMPTY, m3d::Slot::EMPTY],
+        slot_mask: 0,
+    }
+}
+
+/// Parameters of the generated car, without the model.
+pub fn car_entry() -> CarEntry {
+    let physics = CarPhysics {
+        name: CAR_NAME.to_string(),
+        scale_size: 0.5,
+        scale_bound: 1.0,
+        scale_box: 1.0,
+        z_offset_of_mass_center: 0.0,
+        speed_factor: 1.0,
+        mobility_factor: 1.0,
+        water_speed_factor: 1.0,
+        air_speed_factor: 1.0,
+        underground_speed_factor: 1.0,
+        k_archimedean: 1.0,
+        k_water_traction: 1.0,
+        k_water_rudder: 1.0,
+        terra_mover_sx: [0.0; 3],
+        defence: [0; NUM_SIDES],
+        ram_power: [0; NUM_SIDES],
+    };
+    CarEntry {
+        name: CAR_NAME.to_string(),
+        kind: Kind::Main,
+        stats: CarStats {
+            class: 1,
+            price_buy: 0,
+            price_sell: 0,
+            size: [1; 4],
+            max_speed: 100,
+            max_armor: 100,
+            shield_max: 0,
+            shield_regen: 0,
+            shield_drop: 0,
+            drop_time: 0,
+            max_fire: 0,
+            max_water: 0,
+            max_oxygen: 0,
+            max_fly: 0,
+            max_damage: 0,
+            max_teleport: 0,
+        },
+        scale: physics.scale_size,
+        physics,
+        model_path: String::new(),
+    }
+}
+
+pub fn car(device: &wgpu::Device, object: &ObjectContext, shape_sampling: u8) -> CarInfo {
+    let entry = car_entry();
+    CarInfo {
+        kind: entry.kind,
+        stats: entry.stats,
+        physics: entry.physics,
+        model: model::convert_m3d(car_model(), device, object, shape_sampling),
+        scale: entry.scale,
+    }
+}
@@ -16,6 +16,9 @@ impl Registry {
         let mut reg = Registry {
             model_infos: HashMap::new(),
         };
+        if !settings.has_game_data() {
+            return reg;
+        }
         let mut fi = Reader::new(settings.open_relative("game.lst"));
 
         while !fi.cur().starts_with("NumModel") {
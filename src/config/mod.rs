@@ -2,7 +2,9 @@ pub mod bunches;
 pub mod car;
 pub mod common;
 pub mod escaves;
+pub mod fallback;
 pub mod game;
+pub mod scatter;
 pub mod settings;
 mod text;
 pub mod worlds;
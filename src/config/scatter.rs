@@ -0,0 +1,55 @@
+//! Rules of scattering the static props over the terrain.
+
+use std::{fs::File, path::Path};
+
+/// Location of the scatter rules, next to the settings.
+pub const SCATTER_PATH: &str = "config/scatter.ron";
+
+/// Placement of a single kind of props, e.g.
+/// `(model: "resource/m3d/items/i11.m3d", terrain: 3, density: 2, max_slope: 20)`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    /// Path to the M3D model, relative to the game data.
+    pub model: String,
+    /// Worlds where the rule applies, all of them if empty.
+    #[serde(default)]
+    pub worlds: Vec<String>,
+    /// Terrain type of the ground under a prop.
+    pub terrain: u8,
+    /// Average number of props per 100x100 texels of the matching ground.
+    pub density: f32,
+    /// Steepest ground under a prop, in degrees.
+    #[serde(default = "default_max_slope")]
+    pub max_slope: f32,
+    /// Range of the random scale of the model.
+    #[serde(default = "default_scale")]
+    pub scale: (f32, f32),
+}
+
+fn default_max_slope() -> f32 {
+    90.0
+}
+
+fn default_scale() -> (f32, f32) {
+    (1.0, 1.0)
+}
+
+impl Rule {
+    pub fn applies_to(&self, world: &str) -> bool {
+        self.worlds.is_empty() || self.worlds.iter().any(|w| w == world)
+    }
+}
+
+/// Load the rules. A missing file means there are none.
+pub fn load(path: &Path) -> Vec<Rule> {
+    match File::open(path) {
+        Ok(file) => match ron::de::from_reader(file) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Unable to parse {}: {}", path.display(), e);
+                Vec::new()
+            }
+        },
+        Err(_) => Vec::new(),
+    }
+}
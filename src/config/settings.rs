@@ -1,14 +1,132 @@
-use crate::render::object::BodyColor;
+use crate::{
+    config::common::{Gearbox, Surface, NUM_GEARS},
+    level::TerrainConfig,
+    render::object::BodyColor,
+};
 
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Deserialize)]
+/// Assignment of a single setting by its path in the tree,
+/// e.g. `["render", "fog", "depth"]`. The value is parsed as RON.
+pub trait Override {
+    fn set_path(&mut self, path: &[&str], value: &str) -> Result<(), String>;
+}
+
+fn parse_value<T: serde::de::DeserializeOwned>(value: &str) -> Result<T, String> {
+    ron::de::from_str(value).map_err(|e| format!("Unable to parse '{}': {}", value, e))
+}
+
+macro_rules! impl_override_leaf {
+    ($($ty:ty),*) => {$(
+        impl Override for $ty {
+            fn set_path(&mut self, path: &[&str], value: &str) -> Result<(), String> {
+                match path.first() {
+                    Some(key) => Err(format!("Unexpected key '{}'", key)),
+                    None => {
+                        *self = parse_value(value)?;
+                        Ok(())
+                    }
+                }
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_override_struct {
+    ($ty:ty { $($field:ident),* }) => {
+        impl Override for $ty {
+            fn set_path(&mut self, path: &[&str], value: &str) -> Result<(), String> {
+                match path.split_first().map(|(&key, rest)| (key, rest)) {
+                    $(
+                        Some((stringify!($field), rest)) => self.$field.set_path(rest, value),
+                    )*
+                    Some((key, _)) => Err(format!(
+                        "Unknown key '{}', expected one of: {}",
+                        key,
+                        [$(stringify!($field)),*].join(", "),
+                    )),
+                    None => {
+                        *self = parse_value(value)?;
+                        Ok(())
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_override_leaf!(
+    bool,
+    u8,
+    u32,
+    u64,
+    usize,
+    f32,
+    (f32, f32),
+    [u32; 2],
+    [f32; 3],
+    [f32; 4],
+    [(f32, f32); NUM_GEARS]
+);
+impl_override_leaf!(
+    Vec<String>,
+    Vec<Surface>,
+    Option<(i32, i32)>,
+    Option<Trailer>,
+    BodyColor
+);
+
+// Strings can also be given without the quotes.
+impl Override for String {
+    fn set_path(&mut self, path: &[&str], value: &str) -> Result<(), String> {
+        match path.first() {
+            Some(key) => Err(format!("Unexpected key '{}'", key)),
+            None => {
+                *self = parse_value(value).unwrap_or_else(|_| value.to_string());
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Override for PathBuf {
+    fn set_path(&mut self, path: &[&str], value: &str) -> Result<(), String> {
+        let mut string = String::new();
+        string.set_path(path, value)?;
+        *self = string.into();
+        Ok(())
+    }
+}
+
+#[derive(Clone, Deserialize)]
 pub struct Car {
     pub id: String,
     pub color: BodyColor,
     pub slots: Vec<String>,
     pub pos: Option<(i32, i32)>,
+    #[serde(default)]
+    pub trailer: Option<Trailer>,
+}
+
+/// How the trailer is connected to the car.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
+pub enum Joint {
+    /// Free to turn around the hitch in any direction.
+    Ball,
+    /// Only turns around the vertical axis of the car.
+    Hinge,
+}
+
+/// Second body towed behind the car.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Trailer {
+    /// Car whose model is used for the trailer body.
+    pub id: String,
+    pub joint: Joint,
+    /// Distance between the rear of the car and the front of the trailer.
+    pub gap: f32,
 }
 
 #[derive(Copy, Clone, Deserialize)]
@@ -24,7 +142,140 @@ pub struct Camera {
     pub offset: f32,
     pub speed: f32,
     pub depth_range: (f32, f32),
+    /// Fit the depth range to the camera altitude, starting from `depth_range` at the ground.
+    #[serde(default)]
+    pub auto_depth: bool,
     pub projection: Projection,
+    #[serde(default)]
+    pub collision: CameraCollision,
+    #[serde(default)]
+    pub effects: CameraEffects,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CameraCollision {
+    /// Pull the follow camera in front of the terrain that blocks the view of the car.
+    pub enabled: bool,
+    /// Distance to keep between the camera and the terrain surfaces.
+    pub margin: f32,
+    /// Rate of pulling the camera in, which is faster than following.
+    pub speed: f32,
+}
+
+impl Default for CameraCollision {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            margin: 8.0,
+            speed: 10.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CameraEffects {
+    /// Strength of the shake on hard hits and landings, 0 to disable.
+    pub shake: f32,
+    /// Rate of the shake calming down, per second.
+    pub shake_decay: f32,
+    /// Widening of the view at full speed, in degrees, 0 to disable.
+    pub speed_fov: f32,
+    /// Speed of the car at which the view is widened the most.
+    pub full_speed: f32,
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        Self {
+            shake: 1.0,
+            shake_decay: 1.5,
+            speed_fov: 8.0,
+            full_speed: 60.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FlipAssist {
+    /// Time spent upside down and still before the car is turned back
+    /// onto its wheels, in seconds, 0 to only do it on request.
+    pub delay: f32,
+    /// Duration of turning the car over, in seconds.
+    pub duration: f32,
+}
+
+impl Default for FlipAssist {
+    fn default() -> Self {
+        Self {
+            delay: 2.0,
+            duration: 0.8,
+        }
+    }
+}
+
+/// Shaping of a single control axis, from the raw input in [-1, 1].
+/// The default passes the input through as it is.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct InputAxis {
+    /// Rate of moving away from zero, in full ranges per second, 0 to jump there.
+    pub attack: f32,
+    /// Rate of moving back to zero, in full ranges per second, 0 to jump there.
+    pub release: f32,
+    /// Raw inputs below this magnitude are ignored, for the analog sticks.
+    pub dead_zone: f32,
+    /// Power of the response curve, above 1 for finer control near the center.
+    pub exponent: f32,
+    pub sensitivity: f32,
+}
+
+impl Default for InputAxis {
+    fn default() -> Self {
+        Self {
+            attack: 0.0,
+            release: 0.0,
+            dead_zone: 0.0,
+            exponent: 1.0,
+            sensitivity: 1.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct InputShaping {
+    pub rudder: InputAxis,
+    pub motor: InputAxis,
+}
+
+/// Antennas swinging on the empty slots of the cars, purely visual.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Antenna {
+    /// Number of the chain segments, 0 to disable.
+    pub segments: usize,
+    /// Total length of the antenna.
+    pub length: f32,
+    /// Acceleration towards the rest pose, per unit of the displacement.
+    pub stiffness: f32,
+    /// Fraction of the velocity lost per second.
+    pub damping: f32,
+    /// Downward acceleration, in units per second squared.
+    pub gravity: f32,
+}
+
+impl Default for Antenna {
+    fn default() -> Self {
+        Self {
+            segments: 0,
+            length: 24.0,
+            stiffness: 60.0,
+            damping: 0.9,
+            gravity: 40.0,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Deserialize)]
@@ -37,15 +288,98 @@ pub enum SpawnAt {
 pub struct Other {
     pub count: usize,
     pub spawn_at: SpawnAt,
+    /// Let the AI make decisions only around the visible terrain.
+    /// Saves the CPU, but the behavior then depends on the camera.
+    #[serde(default)]
+    pub ai_culling: bool,
 }
 
 #[derive(Copy, Clone, Deserialize)]
 pub struct Physics {
     pub max_quant: f32,
     pub shape_sampling: u8,
+    #[serde(default)]
+    pub substeps: Substeps,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// Splitting of the physics frame of each car into the steps.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Substeps {
+    /// Split following the motion of the car instead of `max_quant`,
+    /// down to a single step at rest.
+    pub adaptive: bool,
+    /// Distance the car may travel per step.
+    pub max_travel: f32,
+    /// Distance per step with the ground in the reach of the car.
+    pub contact_travel: f32,
+    /// Angle, in radians, the car may turn per step.
+    pub max_turn: f32,
+    /// Most steps per frame.
+    pub max_count: usize,
+}
+
+impl Default for Substeps {
+    fn default() -> Self {
+        Self {
+            adaptive: false,
+            max_travel: 8.0,
+            contact_travel: 2.0,
+            max_turn: 0.3,
+            max_count: 16,
+        }
+    }
+}
+
+impl_override_leaf!(Projection, SpawnAt);
+impl_override_struct!(Car {
+    id,
+    color,
+    slots,
+    pos,
+    trailer
+});
+impl_override_struct!(Camera {
+    angle,
+    height,
+    offset,
+    speed,
+    depth_range,
+    auto_depth,
+    projection,
+    collision,
+    effects
+});
+impl_override_struct!(CameraCollision {
+    enabled,
+    margin,
+    speed
+});
+impl_override_struct!(CameraEffects {
+    shake,
+    shake_decay,
+    speed_fov,
+    full_speed
+});
+impl_override_struct!(Other {
+    count,
+    spawn_at,
+    ai_culling
+});
+impl_override_struct!(Physics {
+    max_quant,
+    shape_sampling,
+    substeps
+});
+impl_override_struct!(Substeps {
+    adaptive,
+    max_travel,
+    contact_travel,
+    max_turn,
+    max_count
+});
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Geometry {
     pub height: u32,
     pub delta_mask: u32,
@@ -65,7 +399,14 @@ impl Default for Geometry {
     }
 }
 
-#[derive(Deserialize)]
+impl_override_struct!(Geometry {
+    height,
+    delta_mask,
+    delta_power,
+    delta_const
+});
+
+#[derive(Clone, Deserialize)]
 pub struct Game {
     pub level: String,
     pub cycle: String,
@@ -73,16 +414,51 @@ pub struct Game {
     pub camera: Camera,
     pub other: Other,
     pub physics: Physics,
+    /// Seed of the game randomness, for reproducible runs. Zero picks a random one.
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub flip: FlipAssist,
+    #[serde(default)]
+    pub input: InputShaping,
+    #[serde(default)]
+    pub antenna: Antenna,
+    /// Driving properties per terrain type, starting from the first one.
+    #[serde(default)]
+    pub surfaces: Vec<Surface>,
+    /// Gears and turbo timings of the engine, pass-through by default.
+    #[serde(default)]
+    pub gearbox: Gearbox,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Window {
     pub title: String,
     pub size: [u32; 2],
     pub reload_on_focus: bool,
+    /// Start in the full screen, which is toggled with Alt+Enter.
+    #[serde(default)]
+    pub fullscreen: Fullscreen,
+    /// Hide the title bar and the borders of the window.
+    #[serde(default)]
+    pub borderless: bool,
+    /// Position of the top-left corner on the desktop, in pixels.
+    /// Left to the system if not set.
+    #[serde(default)]
+    pub position: Option<(i32, i32)>,
 }
 
-#[derive(Copy, Clone, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+pub enum Fullscreen {
+    #[default]
+    Off,
+    /// Window covering the whole monitor, at the desktop resolution.
+    Borderless,
+    /// Exclusive mode at the largest resolution of the monitor.
+    Exclusive,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
 pub enum Backend {
     Auto,
     Metal,
@@ -92,6 +468,53 @@ pub enum Backend {
     GL,
 }
 
+impl_override_struct!(Game {
+    level,
+    cycle,
+    geometry,
+    camera,
+    other,
+    physics,
+    seed,
+    flip,
+    input,
+    antenna,
+    surfaces,
+    gearbox
+});
+impl_override_struct!(Gearbox {
+    gears,
+    reverse_share,
+    reversal_brake,
+    turbo_duration,
+    turbo_cooldown
+});
+impl_override_struct!(FlipAssist { delay, duration });
+impl_override_struct!(InputShaping { rudder, motor });
+impl_override_struct!(Antenna {
+    segments,
+    length,
+    stiffness,
+    damping,
+    gravity
+});
+impl_override_struct!(InputAxis {
+    attack,
+    release,
+    dead_zone,
+    exponent,
+    sensitivity
+});
+impl_override_struct!(Window {
+    title,
+    size,
+    reload_on_focus,
+    fullscreen,
+    borderless,
+    position
+});
+impl_override_leaf!(Backend, Fullscreen);
+
 impl Backend {
     pub fn to_wgpu(&self) -> wgpu::Backends {
         match *self {
@@ -111,6 +534,63 @@ pub struct DebugRender {
     pub collision_shapes: bool,
     pub collision_map: bool,
     pub impulses: bool,
+    /// Show the compression, the contact and the slip of each wheel,
+    /// with a panel to tune the suspension constants.
+    #[serde(default)]
+    pub suspension: bool,
+    #[serde(default)]
+    pub lines: DebugLines,
+}
+
+/// Look of the debug lines.
+#[derive(Copy, Clone, Deserialize)]
+pub struct DebugLines {
+    /// Width in pixels.
+    pub width: f32,
+    /// Smooth the edges of the lines.
+    pub antialias: bool,
+}
+
+impl Default for DebugLines {
+    fn default() -> Self {
+        DebugLines {
+            width: 1.0,
+            antialias: false,
+        }
+    }
+}
+
+/// Experimental see-through of the upper layer of stacked terrain,
+/// used when the player gets underground. Zero radius disables it.
+#[derive(Copy, Clone, Default, Deserialize)]
+pub struct Cutaway {
+    pub radius: f32,
+    /// Depth under the upper layer at which it fully disappears.
+    pub depth: f32,
+}
+
+/// Switch of the terrain into the slices while the player is under a ceiling,
+/// cutting off everything above the cave, like the original does.
+/// Works with any terrain method. Zero depth disables it.
+#[derive(Copy, Clone, Default, Deserialize)]
+pub struct Underground {
+    /// Depth under the upper layer at which the view switches.
+    pub enter_depth: f32,
+    /// Seconds out from under the ceiling before switching back.
+    pub exit_delay: f32,
+    /// Seconds of lowering the cut down to the ceiling, or raising it back.
+    pub duration: f32,
+}
+
+/// Distance from the camera at which the objects stop being drawn, per category.
+/// Zero means unlimited.
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+pub struct DrawDistance {
+    pub vehicles: f32,
+    pub debris: f32,
+    pub props: f32,
+    /// Length of the dithered fade-out before the draw distance.
+    pub fade: f32,
 }
 
 #[derive(Copy, Clone, Deserialize)]
@@ -135,6 +615,31 @@ pub struct Light {
     pub shadow: Shadow,
 }
 
+impl_override_struct!(DebugRender {
+    max_vertices,
+    collision_shapes,
+    collision_map,
+    impulses,
+    suspension,
+    lines
+});
+impl_override_struct!(DebugLines { width, antialias });
+impl_override_struct!(Cutaway { radius, depth });
+impl_override_struct!(Underground {
+    enter_depth,
+    exit_delay,
+    duration
+});
+impl_override_struct!(DrawDistance {
+    vehicles,
+    debris,
+    props,
+    fade
+});
+impl_override_leaf!(ShadowTerrain);
+impl_override_struct!(Shadow { size, terrain });
+impl_override_struct!(Light { pos, color, shadow });
+
 #[derive(Copy, Clone, Deserialize)]
 pub enum Terrain {
     RayTraced,
@@ -143,16 +648,81 @@ pub enum Terrain {
         max_outer_steps: u32,
         max_inner_steps: u32,
         max_update_texels: usize,
+        /// Maximum size of the voxel grid in megabytes, 0 for unlimited.
+        /// The finest LODs are dropped until the grid fits.
+        #[serde(default)]
+        max_memory_mb: u32,
+        /// Mip dispatches per frame at most, spreading the rebuild of the LODs
+        /// after a large change over the next frames. 0 for no limit.
+        #[serde(default)]
+        max_mip_dispatches: u32,
     },
     Sliced,
     Painted,
     Scattered {
         density: [u32; 3],
     },
+    /// Ray traced like `RayTraced`, but only the tiles of the level around
+    /// the visible terrain are kept on the GPU, for the huge levels on the devices
+    /// with little memory. The new tiles are uploaded a few at a time, and look
+    /// flat until their data arrives.
+    Streamed {
+        /// Side of a tile in texels, a power of two.
+        tile_size: u32,
+        /// Texels around the visible terrain to keep resident.
+        margin: u32,
+        /// Tiles to keep resident at most. The least recently seen are evicted.
+        max_tiles: u32,
+    },
+}
+
+impl Terrain {
+    /// Adapter capabilities that the terrain method can't work without.
+    pub fn required_downlevel_flags(&self) -> wgpu::DownlevelFlags {
+        match *self {
+            // Both only sample the level textures.
+            Self::RayTraced | Self::Sliced | Self::Streamed { .. } => wgpu::DownlevelFlags::empty(),
+            Self::Painted => wgpu::DownlevelFlags::VERTEX_STORAGE,
+            Self::RayVoxelTraced { .. } => {
+                wgpu::DownlevelFlags::COMPUTE_SHADERS
+                    | wgpu::DownlevelFlags::FRAGMENT_WRITABLE_STORAGE
+            }
+            Self::Scattered { .. } => {
+                wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::VERTEX_STORAGE
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Self::RayTraced => "RayTraced",
+            Self::RayVoxelTraced { .. } => "RayVoxelTraced",
+            Self::Sliced => "Sliced",
+            Self::Painted => "Painted",
+            Self::Scattered { .. } => "Scattered",
+            Self::Streamed { .. } => "Streamed",
+        }
+    }
 }
 
 #[derive(Copy, Clone, Deserialize)]
-pub struct Water {}
+#[serde(default)]
+pub struct Water {
+    /// Distort the scene behind the water surface.
+    /// Requires an extra color target and a render pass, so it's off by default.
+    pub refraction: bool,
+    /// Animated foam on the shallow water near the shores.
+    pub foam: bool,
+}
+
+impl Default for Water {
+    fn default() -> Self {
+        Self {
+            refraction: false,
+            foam: true,
+        }
+    }
+}
 
 #[derive(Copy, Clone, Deserialize)]
 pub struct Fog {
@@ -160,24 +730,99 @@ pub struct Fog {
     pub depth: f32,
 }
 
+/// Handedness of the world coordinate system as it appears on screen.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum Handedness {
+    /// Convention of the original game, with Y axis pointing down the map.
+    #[default]
+    Left,
+    Right,
+}
+
+impl_override_leaf!(Terrain, Handedness);
+impl_override_struct!(Water { refraction, foam });
+impl_override_struct!(Fog { color, depth });
+
+impl Handedness {
+    /// Sign of the world Y axis in the view space.
+    pub fn y_sign(self) -> f32 {
+        match self {
+            Self::Left => -1.0,
+            Self::Right => 1.0,
+        }
+    }
+
+    pub fn front_face(self) -> wgpu::FrontFace {
+        match self {
+            Self::Left => wgpu::FrontFace::Ccw,
+            Self::Right => wgpu::FrontFace::Cw,
+        }
+    }
+}
+
 #[derive(Clone, Deserialize)]
 pub struct Render {
     #[serde(default)]
     pub wgpu_trace_path: String,
     pub allow_tearing: bool,
+    #[serde(default)]
+    pub handedness: Handedness,
     pub light: Light,
     pub terrain: Terrain,
     pub water: Water,
     pub fog: Fog,
     #[serde(default)]
+    pub cutaway: Cutaway,
+    #[serde(default)]
+    pub underground: Underground,
+    #[serde(default)]
+    pub draw_distance: DrawDistance,
+    #[serde(default)]
     pub debug: DebugRender,
+    /// Estimated GPU memory to stay within, warning when it's exceeded.
+    /// Zero means no budget.
+    #[serde(default)]
+    pub memory_budget_mb: u32,
 }
 
+impl_override_struct!(Render {
+    wgpu_trace_path,
+    allow_tearing,
+    handedness,
+    light,
+    terrain,
+    water,
+    fog,
+    cutaway,
+    underground,
+    draw_distance,
+    debug,
+    memory_budget_mb
+});
+
 impl Render {
+    /// Switch to the ray traced terrain if the adapter can't run the configured one,
+    /// e.g. on WebGL2 that has neither compute shaders nor storage buffers.
+    pub fn fit_downlevel(&mut self, caps: &wgpu::DownlevelCapabilities) {
+        let missing = self.terrain.required_downlevel_flags() - caps.flags;
+        if missing.is_empty() {
+            return;
+        }
+        warn!(
+            "Terrain {} is not supported by the adapter (missing {:?}), using RayTraced",
+            self.terrain.name(),
+            missing
+        );
+        self.terrain = Terrain::RayTraced;
+        if let ShadowTerrain::RayVoxelTraced { .. } = self.light.shadow.terrain {
+            self.light.shadow.terrain = ShadowTerrain::RayTraced;
+        }
+    }
+
     pub fn get_device_limits(&self, adapter_limits: &wgpu::Limits, slices: u32) -> wgpu::Limits {
         let (max_width, max_height) = (2048usize, 16384usize);
         match self.terrain {
-            Terrain::RayTraced { .. } | Terrain::Sliced { .. } | Terrain::Painted { .. } => {
+            Terrain::RayTraced | Terrain::Sliced | Terrain::Painted | Terrain::Streamed { .. } => {
                 wgpu::Limits {
                     max_texture_dimension_2d: adapter_limits.max_texture_dimension_2d,
                     ..wgpu::Limits::downlevel_webgl2_defaults()
@@ -186,10 +831,11 @@ impl Render {
             Terrain::RayVoxelTraced { voxel_size, .. } => {
                 let voxel_points = voxel_size[0] * voxel_size[1] * voxel_size[2];
                 let max_voxels = max_width * max_height * slices as usize / voxel_points as usize;
-                // Note: 1/7 is roughly the sum size of all the mips
+                // Note: 1/7 is roughly the sum size of all the mips,
+                // and the water bits take as much as the finest one.
                 // Division by 8 is because we have 8 bits per byte.
                 // The extra space is for rounding and such.
-                let voxel_storage_size = (max_voxels * 8 / 7) / 8 + 4096;
+                let voxel_storage_size = (max_voxels * 15 / 7) / 8 + 4096;
                 info!(
                     "Estimating {} MB for voxel storage",
                     voxel_storage_size >> 20
@@ -211,19 +857,56 @@ pub struct Ui {
     pub frame_history: usize,
 }
 
-#[derive(Deserialize)]
+impl_override_struct!(Ui {
+    enabled,
+    frame_history
+});
+
+#[derive(Clone, Deserialize)]
 pub struct Settings {
     pub data_path: PathBuf,
     pub car: Car,
     pub game: Game,
     pub window: Window,
     pub backend: Backend,
+    /// Part of the adapter name to pick, case-insensitive.
+    /// Empty for the default high-performance adapter.
+    #[serde(default)]
+    pub adapter: String,
     pub render: Render,
     pub ui: Ui,
+    /// Directory of the saved per-world state, empty to not persist it.
+    #[serde(default)]
+    pub profile_path: PathBuf,
+    /// Language of the UI strings, empty for English.
+    #[serde(default)]
+    pub language: String,
+    /// Number of threads for the CPU work of a frame, zero for one per core.
+    #[serde(default)]
+    pub threads: usize,
 }
 
+impl_override_struct!(Settings {
+    data_path,
+    car,
+    game,
+    window,
+    backend,
+    adapter,
+    render,
+    ui,
+    profile_path,
+    language,
+    threads
+});
+
 impl Settings {
-    pub fn load(path: &str) -> Self {
+    /// Load the settings in layers:
+    ///   1. the base file at `path`,
+    ///   2. the overrides for the given binary, found next to it as "settings.<binary>.ron",
+    ///      in the form of `{ "render.terrain": "Sliced" }`,
+    ///   3. the `key=value` overrides, typically coming from the command line.
+    pub fn load(path: &str, binary: &str, overrides: &[String]) -> Self {
         use std::io::Read;
 
         const TEMPLATE: &str = "config/settings.template.ron";
@@ -234,7 +917,7 @@ impl Settings {
                 e, TEMPLATE, PATH))
             .read_to_string(&mut string)
             .unwrap();
-        let set: Settings = match ron::de::from_str(&string) {
+        let mut set: Settings = match ron::de::from_str(&string) {
             Ok(set) => set,
             Err(e) => panic!(
                 "Unable to parse settings RON: {:?}.\nPlease check if `{}` has changed and your local config needs to be adjusted.",
@@ -243,16 +926,81 @@ impl Settings {
             ),
         };
 
-        if !set.check_path("options.dat") {
-            panic!(
+        let binary_path = Path::new(path).with_file_name(format!("settings.{}.ron", binary));
+        if let Ok(file) = File::open(&binary_path) {
+            info!("Applying overrides from {}", binary_path.display());
+            let map: BTreeMap<String, String> = ron::de::from_reader(file)
+                .unwrap_or_else(|e| panic!("Unable to parse {}: {}", binary_path.display(), e));
+            for (key, value) in map {
+                if let Err(e) = set.set_value(&key, &value) {
+                    panic!("{}: {}", binary_path.display(), e);
+                }
+            }
+        }
+        for assignment in overrides {
+            let (key, value) = match assignment.split_once('=') {
+                Some(pair) => pair,
+                None => panic!(
+                    "Invalid setting override '{}', expected 'key=value'",
+                    assignment
+                ),
+            };
+            if let Err(e) = set.set_value(key.trim(), value.trim()) {
+                panic!("{}", e);
+            }
+        }
+
+        if !set.has_game_data() {
+            if cfg!(feature = "asset-free") {
+                warn!(
+                    "No game resources found at {:?}, using the built-in assets",
+                    set.data_path
+                );
+                set.use_fallback_assets();
+            } else {
+                panic!(
                 "Can't find the resources of the original Vangers game at {:?}, please check your `{}`",
                 set.data_path, PATH,
             );
+            }
         }
 
         set
     }
 
+    /// Point the settings at the built-in assets,
+    /// replacing everything that refers to the original game data.
+    fn use_fallback_assets(&mut self) {
+        self.car.id = super::fallback::CAR_NAME.to_string();
+        self.car.slots.clear();
+        if crate::level::TestPreset::from_level_name(&self.game.level).is_none()
+            && crate::level::Generator::from_level_name(&self.game.level).is_none()
+            && crate::level::Fixture::path_from_level_name(&self.game.level).is_none()
+        {
+            self.game.level.clear();
+        }
+        self.game.cycle.clear();
+    }
+
+    /// Check if the resources of the original game are present.
+    pub fn has_game_data(&self) -> bool {
+        self.check_path("options.dat")
+    }
+
+    /// Check if the adapter is selected by the `adapter` setting.
+    pub fn matches_adapter(&self, info: &wgpu::AdapterInfo) -> bool {
+        info.name
+            .to_lowercase()
+            .contains(&self.adapter.to_lowercase())
+    }
+
+    /// Override a single setting by its dotted path, e.g. "render.fog.depth".
+    pub fn set_value(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let path = key.split('.').collect::<Vec<_>>();
+        self.set_path(&path, value)
+            .map_err(|e| format!("Unable to set '{}': {}", key, e))
+    }
+
     pub fn open_relative(&self, path: &str) -> File {
         File::open(self.data_path.join(path))
             .unwrap_or_else(|_| panic!("Unable to open game file: {}", path))
@@ -262,13 +1010,24 @@ impl Settings {
         self.data_path.join(path).exists()
     }
 
-    pub fn open_palette(&self) -> File {
-        let path = self
-            .data_path
+    pub fn palette_path(&self) -> PathBuf {
+        self.data_path
             .join("resource")
             .join("pal")
-            .join("objects.pal");
-        File::open(path).expect("Unable to open palette")
+            .join("objects.pal")
+    }
+
+    pub fn open_palette(&self) -> File {
+        File::open(self.palette_path()).expect("Unable to open palette")
+    }
+
+    /// Read the objects palette, or generate one if there is no game data.
+    pub fn load_palette(&self, terrains: Option<&[TerrainConfig]>) -> [[u8; 4]; 0x100] {
+        if self.has_game_data() {
+            crate::level::read_palette(self.open_palette(), terrains)
+        } else {
+            super::fallback::palette()
+        }
     }
 
     pub fn _open_vehicle_model(&self, name: &str) -> File {
@@ -1,7 +1,8 @@
-use crate::config::text::Reader;
+use crate::config::{settings::Geometry, text::Reader};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
+use std::path::PathBuf;
 
 pub type Worlds = HashMap<String, String>;
 
@@ -15,3 +16,69 @@ pub fn load(file: File) -> Worlds {
         })
         .collect()
 }
+
+/// Location of the per-world geometry overrides, next to the settings.
+pub const GEOMETRY_PATH: &str = "config/geometry.ron";
+
+/// Level geometry of the individual worlds, overriding `game.geometry`
+/// of the settings. Stored as a map from the world name, e.g.
+/// `{ "Necross": (height: 256, delta_mask: 65535, delta_power: 2, delta_const: 1) }`.
+pub struct GeometryOverrides {
+    path: PathBuf,
+    worlds: BTreeMap<String, Geometry>,
+    /// Whether there are changes not saved yet.
+    dirty: bool,
+}
+
+impl GeometryOverrides {
+    /// Load the overrides. A missing file means there are none.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let worlds = match File::open(&path) {
+            Ok(file) => match ron::de::from_reader(file) {
+                Ok(worlds) => worlds,
+                Err(e) => {
+                    error!("Unable to parse {}: {}", path.display(), e);
+                    BTreeMap::new()
+                }
+            },
+            Err(_) => BTreeMap::new(),
+        };
+        GeometryOverrides {
+            path,
+            worlds,
+            dirty: false,
+        }
+    }
+
+    /// Geometry to use for the world.
+    pub fn get(&self, world: &str, default: &Geometry) -> Geometry {
+        match self.worlds.get(world) {
+            Some(geometry) => {
+                info!("Using the geometry override for {}", world);
+                geometry.clone()
+            }
+            None => default.clone(),
+        }
+    }
+
+    /// Remember the geometry of the world, to be written by `save`.
+    pub fn set(&mut self, world: &str, geometry: &Geometry) {
+        self.worlds.insert(world.to_string(), geometry.clone());
+        self.dirty = true;
+    }
+
+    /// Write all the overrides, if any of them changed since the last time.
+    /// Meant to be called once an edit is finished, not on every step of it.
+    pub fn save(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        let string =
+            ron::ser::to_string_pretty(&self.worlds, ron::ser::PrettyConfig::default()).unwrap();
+        if let Err(e) = std::fs::write(&self.path, string) {
+            error!("Unable to save {}: {}", self.path.display(), e);
+        }
+    }
+}
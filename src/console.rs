@@ -0,0 +1,217 @@
+//! In-app log console.
+//!
+//! The logger forwards everything to `env_logger` as before, but also keeps
+//! the recent warnings and errors around, so that they can be inspected
+//! in the UI instead of getting lost in the terminal.
+//! A longer tail of the log, down to the info level, is kept for the crash reports.
+//! The console also accepts text commands, which are handed over to the application.
+
+use crate::locale::{tr, tr_fmt};
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    sync::{Mutex, OnceLock, TryLockError},
+    time::Instant,
+};
+
+const MAX_ENTRIES: usize = 500;
+/// Number of the recent log lines kept for the crash reports.
+const TAIL_LINES: usize = 200;
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// Seconds since the logger was installed.
+    pub time: f32,
+    pub level: log::Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{:.3}] {} {}: {}",
+            self.time, self.level, self.target, self.message
+        )
+    }
+}
+
+struct Logger {
+    fallback: env_logger::Logger,
+    start: Instant,
+    capture_level: log::Level,
+    tail_level: log::Level,
+    journal: Mutex<Journal>,
+}
+
+#[derive(Default)]
+struct Journal {
+    entries: VecDeque<Entry>,
+    /// Total number of entries ever captured.
+    counter: usize,
+    /// Formatted lines of the recent log, at the `tail_level`.
+    tail: VecDeque<String>,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= self.capture_level.max(self.tail_level)
+            || self.fallback.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.fallback.matches(record) {
+            self.fallback.log(record);
+        }
+        if record.level() > self.capture_level.max(self.tail_level) {
+            return;
+        }
+        let entry = Entry {
+            time: self.start.elapsed().as_secs_f32(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        let mut journal = self.journal.lock().unwrap();
+        if record.level() <= self.tail_level {
+            if journal.tail.len() == TAIL_LINES {
+                journal.tail.pop_front();
+            }
+            journal.tail.push_back(entry.to_string());
+        }
+        if record.level() > self.capture_level {
+            return;
+        }
+        if journal.entries.len() == MAX_ENTRIES {
+            journal.entries.pop_front();
+        }
+        journal.entries.push_back(entry);
+        journal.counter += 1;
+    }
+
+    fn flush(&self) {
+        self.fallback.flush();
+    }
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Install the capturing logger. Subsequent calls are ignored.
+pub fn init() {
+    let logger = LOGGER.get_or_init(|| Logger {
+        fallback: env_logger::Builder::from_default_env().build(),
+        start: Instant::now(),
+        capture_level: log::Level::Warn,
+        tail_level: log::Level::Info,
+        journal: Mutex::default(),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(
+            logger.fallback.filter().max(
+                logger
+                    .capture_level
+                    .max(logger.tail_level)
+                    .to_level_filter(),
+            ),
+        );
+    }
+}
+
+/// The recent log lines, oldest first.
+/// Doesn't wait for the journal, since the panic hook may run on the thread
+/// that holds it, in which case there are no lines.
+pub fn recent_lines() -> Option<Vec<String>> {
+    let logger = match LOGGER.get() {
+        Some(logger) => logger,
+        None => return Some(Vec::new()),
+    };
+    let journal = match logger.journal.try_lock() {
+        Ok(journal) => journal,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => return None,
+    };
+    Some(journal.tail.iter().cloned().collect())
+}
+
+/// The egui side of the log console.
+#[derive(Default)]
+pub struct Console {
+    pub visible: bool,
+    seen: usize,
+    command: String,
+}
+
+impl Console {
+    pub fn toggle(&mut self) {
+        self.visible ^= true;
+    }
+
+    /// Draw the console window. Returns a command if one was submitted.
+    pub fn draw(&mut self, context: &egui::Context) -> Option<String> {
+        let logger = LOGGER.get()?;
+        let mut submitted = None;
+        let journal = logger.journal.lock().unwrap();
+        let entries = &journal.entries;
+        // pop up whenever a new error comes in
+        let fresh = (journal.counter - self.seen).min(entries.len());
+        if entries
+            .iter()
+            .rev()
+            .take(fresh)
+            .any(|e| e.level == log::Level::Error)
+        {
+            self.visible = true;
+        }
+        self.seen = journal.counter;
+
+        let mut visible = self.visible;
+        egui::Window::new(tr("Log"))
+            .open(&mut visible)
+            .default_width(500.0)
+            .show(context, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button(tr("Copy")).clicked() {
+                        let mut text = String::new();
+                        for e in entries.iter() {
+                            let _ = writeln!(text, "{}", e);
+                        }
+                        ui.output_mut(|o| o.copied_text = text);
+                    }
+                    ui.label(tr_fmt("{} entries", &[&entries.len()]));
+                });
+                ui.separator();
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.command)
+                        .hint_text("command")
+                        .desired_width(f32::INFINITY),
+                );
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    let command = self.command.trim();
+                    if !command.is_empty() {
+                        submitted = Some(command.to_string());
+                    }
+                    self.command.clear();
+                    response.request_focus();
+                }
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for e in entries.iter() {
+                            let color = match e.level {
+                                log::Level::Error => egui::Color32::LIGHT_RED,
+                                log::Level::Warn => egui::Color32::YELLOW,
+                                _ => ui.visuals().text_color(),
+                            };
+                            ui.colored_label(
+                                color,
+                                format!("[{:.3}] {}: {}", e.time, e.target, e.message),
+                            );
+                        }
+                    });
+            });
+        self.visible = visible;
+        submitted
+    }
+}
@@ -0,0 +1,169 @@
+//! Crash reports.
+//!
+//! The panic hook writes down what is needed to triage a crash without
+//! asking back: the settings in effect, the adapter, the recent log lines,
+//! and the last shader handed to the device. The user is then pointed at
+//! the report with a message box, if the platform has a way to show one.
+
+use std::{
+    fmt::Write as _,
+    fs, panic,
+    path::{Path, PathBuf},
+    process,
+    sync::{Mutex, MutexGuard, TryLockError},
+    time,
+};
+
+struct State {
+    title: String,
+    settings: String,
+    adapter: Option<wgpu::AdapterInfo>,
+    last_shader: Option<(String, String)>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    title: String::new(),
+    settings: String::new(),
+    adapter: None,
+    last_shader: None,
+});
+
+fn state() -> MutexGuard<'static, State> {
+    // a panic elsewhere must not stop us from reporting
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The state for the panic hook, which may run on the thread recording it.
+fn try_state() -> Option<MutexGuard<'static, State>> {
+    match STATE.try_lock() {
+        Ok(state) => Some(state),
+        Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+/// Install the panic hook of the application with the given title.
+/// The default hook still runs first, printing the panic into the terminal.
+pub fn install(title: &str) {
+    state().title = title.to_string();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let path = report_path();
+        match fs::write(&path, report(&info.to_string())) {
+            Ok(()) => {
+                eprintln!("Crash report is saved into {}", path.display());
+                notify(&path);
+            }
+            Err(e) => eprintln!("Unable to save the crash report {}: {}", path.display(), e),
+        }
+    }));
+}
+
+/// Remember the settings as loaded by `Settings::load` with the same arguments.
+pub fn record_settings(path: &str, binary: &str, overrides: &[String]) {
+    let mut text = String::new();
+    let binary_path = Path::new(path).with_file_name(format!("settings.{}.ron", binary));
+    for file_path in [Path::new(path), binary_path.as_path()] {
+        if let Ok(contents) = fs::read_to_string(file_path) {
+            let _ = writeln!(text, "--- {}\n{}", file_path.display(), contents);
+        }
+    }
+    if !overrides.is_empty() {
+        let _ = writeln!(text, "--- overrides\n{}", overrides.join("\n"));
+    }
+    state().settings = text;
+}
+
+pub fn record_adapter(info: &wgpu::AdapterInfo) {
+    state().adapter = Some(info.clone());
+}
+
+pub(crate) fn record_shader(name: &str, code: &str) {
+    state().last_shader = Some((name.to_string(), code.to_string()));
+}
+
+/// Compose the report of a crash with the given message.
+/// Never blocks: the parts locked by the crashed thread are left out.
+pub fn report(message: &str) -> String {
+    let mut text = String::new();
+    let state = try_state();
+    let title = state
+        .as_ref()
+        .map_or("The application", |s| s.title.as_str());
+    let _ = writeln!(text, "{} crashed: {}", title, message);
+    let _ = writeln!(
+        text,
+        "Version {} on {} {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    match state {
+        Some(ref state) => {
+            match state.adapter {
+                Some(ref info) => {
+                    let _ = writeln!(text, "== Adapter\n{:#?}\n", info);
+                }
+                None => text.push_str("== Adapter\nnot initialized\n\n"),
+            }
+            let _ = writeln!(text, "== Settings\n{}", state.settings);
+        }
+        None => text.push_str("== State\nlocked by the crashed thread\n\n"),
+    }
+    text.push_str("== Log\n");
+    match crate::console::recent_lines() {
+        Some(lines) => {
+            for line in lines {
+                let _ = writeln!(text, "{}", line);
+            }
+        }
+        None => text.push_str("locked by the crashed thread\n"),
+    }
+    if let Some((ref name, ref code)) = state.as_ref().and_then(|s| s.last_shader.as_ref()) {
+        let _ = writeln!(text, "\n== Last shader '{}'\n{}", name, code);
+    }
+    text
+}
+
+fn report_path() -> PathBuf {
+    let seconds = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    PathBuf::from(format!("crash-{}.txt", seconds))
+}
+
+/// Show a message box with the platform tools, ignoring any failures.
+/// The tool is left running on its own, so the crashed process can exit.
+fn notify(path: &Path) {
+    let message = format!(
+        "The application has crashed. Please attach {} to the bug report.",
+        fs::canonicalize(path)
+            .unwrap_or_else(|_| path.to_path_buf())
+            .display()
+    );
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = process::Command::new("powershell");
+        command.arg("-Command").arg(format!(
+            "Add-Type -AssemblyName PresentationFramework; [System.Windows.MessageBox]::Show('{}')",
+            message.replace('\'', "''")
+        ));
+        command
+    } else if cfg!(target_os = "macos") {
+        let mut command = process::Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display alert \"Crash\" message \"{}\"",
+            message.replace('"', "\\\"")
+        ));
+        command
+    } else {
+        let mut command = process::Command::new("zenity");
+        command.arg("--error").arg("--text").arg(&message);
+        command
+    };
+    let _ = command
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn();
+}
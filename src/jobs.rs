@@ -0,0 +1,72 @@
+//! Jobs for the CPU work of a frame, running on the global rayon pool.
+//!
+//! Everything per frame that grows with the number of instances goes through
+//! here: the physics of the agents, the building of the batches, and the
+//! staging of the terrain updates. Each call takes a "grain", the number of
+//! items worth a separate job, and keeps smaller work on the calling thread,
+//! where sending it away would cost more than it saves.
+
+use rayon::prelude::*;
+
+/// Set the number of threads of the pool, zero for one per core.
+/// Has to be called before any job runs, or it's ignored.
+pub fn init(num_threads: usize) {
+    let result = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(|i| format!("job-{}", i))
+        .build_global();
+    match result {
+        Ok(()) => log::info!("Running jobs on {} threads", rayon::current_num_threads()),
+        Err(e) => log::warn!("Unable to set up the job threads: {}", e),
+    }
+}
+
+/// Number of items per job, for the given total.
+fn chunk_size(count: usize, grain: usize) -> usize {
+    let per_thread = count.div_ceil(rayon::current_num_threads());
+    per_thread.max(grain).max(1)
+}
+
+/// Run the function on every item, in parallel.
+pub fn for_each_mut<T: Send>(items: &mut [T], grain: usize, fun: impl Fn(&mut T) + Sync + Send) {
+    if items.len() <= grain || rayon::current_num_threads() == 1 {
+        items.iter_mut().for_each(fun);
+    } else {
+        items.par_iter_mut().with_min_len(grain).for_each(fun);
+    }
+}
+
+/// Map the chunks of items in parallel, producing the results in the order
+/// of the chunks, so that merging them is deterministic.
+pub fn map_chunks<T: Sync, R: Send>(
+    items: &[T],
+    grain: usize,
+    fun: impl Fn(&[T]) -> R + Sync + Send,
+) -> Vec<R> {
+    if items.len() <= grain || rayon::current_num_threads() == 1 {
+        vec![fun(items)]
+    } else {
+        let size = chunk_size(items.len(), grain);
+        items.par_chunks(size).map(fun).collect()
+    }
+}
+
+/// Fill the rows of a buffer in parallel. The function gets the index of the row.
+pub fn for_each_row(
+    data: &mut [u8],
+    row_size: usize,
+    grain: usize,
+    fun: impl Fn(usize, &mut [u8]) + Sync + Send,
+) {
+    let count = data.len() / row_size.max(1);
+    if count <= grain || rayon::current_num_threads() == 1 {
+        for (i, row) in data.chunks_mut(row_size).enumerate() {
+            fun(i, row);
+        }
+    } else {
+        data.par_chunks_mut(row_size)
+            .with_min_len(grain)
+            .enumerate()
+            .for_each(|(i, row)| fun(i, row));
+    }
+}
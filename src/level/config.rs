@@ -1,3 +1,4 @@
+use super::{generator::Generator, TerrainBits, TestPreset};
 use ini::Ini;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
@@ -17,13 +18,14 @@ impl Power {
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct TerrainConfig {
     pub shadow_offset: u8,
     pub height_shift: u8,
     pub colors: Range<u8>, // note: actually, this is inclusive range
 }
 
+#[derive(Clone)]
 pub struct LevelConfig {
     //pub name: String,
     pub path_palette: PathBuf,
@@ -34,24 +36,47 @@ pub struct LevelConfig {
     pub section: Power,
     pub min_square: Power,
     pub terrains: Box<[TerrainConfig]>,
+    /// Terrain to generate if there is no data file.
+    pub test_preset: TestPreset,
+    /// Procedural terrain to generate instead of the test preset.
+    pub generator: Option<Generator>,
 }
 
 impl LevelConfig {
     pub fn new_test() -> Self {
+        Self::new_test_preset(TestPreset::Flat)
+    }
+
+    pub fn new_test_preset(test_preset: TestPreset) -> Self {
         let tc = TerrainConfig {
             shadow_offset: 0,
             height_shift: 0,
             colors: 0..1,
         };
+        let (size, section) = match test_preset {
+            TestPreset::Flat => ((Power(1), Power(0)), Power(8)),
+            _ => ((Power(10), Power(10)), Power(7)),
+        };
         LevelConfig {
             path_palette: PathBuf::default(),
             path_data: PathBuf::default(),
             is_compressed: false,
-            size: (Power(1), Power(0)),
+            size,
             geo: Power(0),
-            section: Power(8),
+            section,
             min_square: Power(0),
             terrains: (0..8).map(|_| tc.clone()).collect(),
+            test_preset,
+            generator: None,
+        }
+    }
+
+    pub fn new_generated(generator: Generator) -> Self {
+        LevelConfig {
+            size: (Power(11), Power(11)),
+            section: Power(7),
+            generator: Some(generator),
+            ..Self::new_test_preset(TestPreset::Hills)
         }
     }
 
@@ -65,8 +90,11 @@ impl LevelConfig {
 
         let terra_count = render
             .get("Terrain Max")
-            .map_or(8, |value| value.parse::<usize>().unwrap());
-        let mut terrains = (0..terra_count)
+            .map_or(8, |value| value.parse::<u8>().unwrap());
+        if let Err(e) = TerrainBits::new(terra_count) {
+            panic!("Unable to use the level {:?}: {}", ini_path, e);
+        }
+        let mut terrains = (0..terra_count as usize)
             .map(|_| TerrainConfig::default())
             .collect::<Box<[_]>>();
 
@@ -109,6 +137,8 @@ impl LevelConfig {
             section: Power(global["Section Size Power"].parse().unwrap()),
             min_square: Power(global["Minimal Square Power"].parse().unwrap()),
             terrains,
+            test_preset: TestPreset::Flat,
+            generator: None,
         }
     }
 }
@@ -0,0 +1,159 @@
+//! Small self-contained pieces of a level, for the tests.
+//!
+//! A fixture is a square region of the level data around a point, together
+//! with the flood sections, the palette, the terrain configs, and the geometry
+//! it's interpreted with. It's saved as RON, and loaded back either in the tests,
+//! or as the level with `level: "fixture:<path>"`.
+
+use super::{Level, LevelConfig, Power, Pyramid, TerrainBits, TerrainConfig};
+use crate::config::settings;
+
+use std::{fs::File, path::Path};
+
+const FIXTURE_PREFIX: &str = "fixture:";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    /// Level coordinates of the first texel, for reference.
+    pub origin: (i32, i32),
+    pub size: (i32, i32),
+    pub height: Vec<u8>,
+    pub meta: Vec<u8>,
+    /// Flood level per section of the rows.
+    pub flood_map: Vec<u8>,
+    pub palette: Vec<[u8; 4]>,
+    pub terrains: Vec<TerrainConfig>,
+    pub geometry: settings::Geometry,
+}
+
+impl Fixture {
+    /// Parse a level name of the form "fixture:<path>".
+    pub fn path_from_level_name(level: &str) -> Option<&Path> {
+        level.strip_prefix(FIXTURE_PREFIX).map(Path::new)
+    }
+
+    /// Copy the square of the level around the center, wrapping around the level.
+    /// The size has to be a power of two, not larger than the level.
+    pub fn capture(level: &Level, center: (i32, i32), size: i32) -> Self {
+        assert!(
+            size > 1 && size & (size - 1) == 0,
+            "Fixture size {} is not a power of two",
+            size
+        );
+        let size = (size.min(level.size.0), size.min(level.size.1));
+        // keep the pairs of the stacked texels together
+        let origin = ((center.0 - size.0 / 2) & !1, center.1 - size.1 / 2);
+        let mut height = Vec::with_capacity((size.0 * size.1) as usize);
+        let mut meta = Vec::with_capacity((size.0 * size.1) as usize);
+        for y in origin.1..origin.1 + size.1 {
+            let row = y.rem_euclid(level.size.1) * level.size.0;
+            for x in origin.0..origin.0 + size.0 {
+                let index = (row + x.rem_euclid(level.size.0)) as usize;
+                height.push(level.height[index]);
+                meta.push(level.meta[index]);
+            }
+        }
+
+        let sections = level.flood_map.len();
+        // a fixture has at least one section, dry if the level has none
+        let flood_map = if sections == 0 {
+            vec![0]
+        } else {
+            let section_rows = (level.size.1 / sections as i32).max(1);
+            (0..(size.1 / section_rows).max(1))
+                .map(|section| {
+                    let row = (origin.1 + section * section_rows).rem_euclid(level.size.1);
+                    level.flood_map[row as usize * sections / level.size.1 as usize]
+                })
+                .collect()
+        };
+
+        Fixture {
+            origin,
+            size,
+            height,
+            meta,
+            flood_map,
+            palette: level.palette.to_vec(),
+            terrains: level.terrains.to_vec(),
+            geometry: level.geometry.clone(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file =
+            File::open(path).map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+        let fixture: Self = ron::de::from_reader(file)
+            .map_err(|e| format!("Unable to parse {}: {}", path.display(), e))?;
+        let (width, height) = fixture.size;
+        let total = width as usize * height as usize;
+        if width <= 0
+            || height <= 0
+            || width & (width - 1) != 0
+            || height & (height - 1) != 0
+            || fixture.height.len() != total
+            || fixture.meta.len() != total
+        {
+            return Err(format!(
+                "Fixture {} doesn't have {}x{} texels of a power of two size",
+                path.display(),
+                width,
+                height
+            ));
+        }
+        let sections = fixture.flood_map.len() as i32;
+        if sections == 0
+            || height % sections != 0
+            || (height / sections) & (height / sections - 1) != 0
+            || fixture.palette.len() != 0x100
+        {
+            return Err(format!(
+                "Fixture {} doesn't have a valid flood map or palette",
+                path.display()
+            ));
+        }
+        if let Err(e) = TerrainBits::new(fixture.terrains.len() as u8) {
+            return Err(format!("Fixture {}: {}", path.display(), e));
+        }
+        Ok(fixture)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Unable to create {}: {}", path.display(), e))?;
+        ron::ser::to_writer_pretty(file, self, ron::ser::PrettyConfig::default())
+            .map_err(|e| format!("Unable to save {}: {}", path.display(), e))
+    }
+
+    /// Level configuration matching the fixture, with no files behind it.
+    pub fn config(&self) -> LevelConfig {
+        let mut config = LevelConfig::new_test();
+        config.size = (
+            Power::from_value(self.size.0),
+            Power::from_value(self.size.1),
+        );
+        config.section = Power::from_value(self.size.1 / self.flood_map.len() as i32);
+        config.terrains = self.terrains.clone().into_boxed_slice();
+        config
+    }
+
+    pub fn palette(&self) -> [[u8; 4]; 0x100] {
+        let mut palette = [[0; 4]; 0x100];
+        palette.copy_from_slice(&self.palette);
+        palette
+    }
+
+    pub fn into_level(self) -> Level {
+        let palette = self.palette();
+        Level {
+            size: self.size,
+            flood_map: self.flood_map.into_boxed_slice(),
+            pyramid: Pyramid::new(self.size, &self.height),
+            height: self.height.into_boxed_slice(),
+            meta: self.meta.into_boxed_slice(),
+            palette,
+            terrains: self.terrains.into_boxed_slice(),
+            geometry: self.geometry,
+        }
+    }
+}
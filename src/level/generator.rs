@@ -0,0 +1,222 @@
+//! Procedural levels, for driving around without the original game data.
+//!
+//! The surface comes from layers of value noise that wrap around the level,
+//! so there is no seam. Caves run along a band of another noise, as stacked texels
+//! under the surface, and the terrain types are painted by the altitude and the slope.
+//! Each flood section gets its own water level around the configured one.
+//! The same parameters always produce the same level.
+
+use super::{LevelData, TerrainBits, DELTA_BITS, DELTA_MASK, DOUBLE_LEVEL};
+
+const GENERATE_PREFIX: &str = "generate:";
+/// Depth of the cave floor under the surface.
+const CAVE_DEPTH: u8 = 96;
+/// Height of the caves, between the floor and the ceiling.
+const CAVE_CLEARANCE: u32 = 64;
+/// Difference to the neighbors at which the surface is painted as a cliff.
+const CLIFF_STEP: u8 = 6;
+/// Variation of the water level between the flood sections.
+const FLOOD_VARIATION: f32 = 8.0;
+
+// terrain types, as the test presets use them
+const TERRAIN_SHORE: u8 = 1;
+const TERRAIN_GRASS: u8 = 2;
+const TERRAIN_MEADOW: u8 = 3;
+const TERRAIN_PEAK: u8 = 4;
+const TERRAIN_CLIFF: u8 = 5;
+const TERRAIN_CAVE: u8 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Generator {
+    pub seed: u64,
+    /// Size of the largest hills, in texels.
+    pub feature_size: u32,
+    /// Layers of the noise, each one adding the detail of half the size.
+    pub octaves: u32,
+    /// Altitudes of the lowest and the highest surface.
+    pub heights: (u8, u8),
+    /// Width of the cave tunnels, as a band of the noise values, 0 for no caves.
+    pub cave_width: f32,
+    /// Average water level of the flood sections.
+    pub water_level: u8,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator {
+            seed: 0,
+            feature_size: 256,
+            octaves: 5,
+            heights: (16, 224),
+            cave_width: 0.03,
+            water_level: 64,
+        }
+    }
+}
+
+/// Pseudo-random value in [0, 1) of the lattice point.
+fn hash(seed: u64, x: i32, y: i32) -> f32 {
+    // SplitMix64 finalizer over the combined coordinates
+    let mut z = seed
+        .wrapping_add((x as u32 as u64) << 32 | y as u32 as u64)
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Smoothly interpolated noise over a lattice that repeats after `period` cells.
+fn value_noise(seed: u64, x: f32, y: f32, period: (i32, i32)) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let corner = |dx: i32, dy: i32| {
+        hash(
+            seed,
+            (x0 as i32 + dx).rem_euclid(period.0),
+            (y0 as i32 + dy).rem_euclid(period.1),
+        )
+    };
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+    top + (bottom - top) * ty
+}
+
+impl Generator {
+    /// Parse a level name of the form "generate:<seed>".
+    /// Returns `None` if the name doesn't refer to a generated level,
+    /// including the names with an invalid seed.
+    pub fn from_level_name(level: &str) -> Option<Self> {
+        let seed = level.strip_prefix(GENERATE_PREFIX)?;
+        match seed.parse() {
+            Ok(seed) => Some(Generator {
+                seed,
+                ..Default::default()
+            }),
+            Err(_) => {
+                warn!("Invalid seed '{}' of the generated level", seed);
+                None
+            }
+        }
+    }
+
+    pub fn level_name(seed: u64) -> String {
+        format!("{}{}", GENERATE_PREFIX, seed)
+    }
+
+    /// Sum of the noise layers at the texel, in [0, 1].
+    fn noise(&self, seed: u64, size: (i32, i32), feature_size: u32, x: i32, y: i32) -> f32 {
+        let mut cells = (
+            (size.0 / feature_size as i32).max(1),
+            (size.1 / feature_size as i32).max(1),
+        );
+        let mut amplitude = 1.0;
+        let (mut sum, mut total) = (0.0, 0.0);
+        for octave in 0..self.octaves.max(1) {
+            let u = x as f32 * cells.0 as f32 / size.0 as f32;
+            let v = y as f32 * cells.1 as f32 / size.1 as f32;
+            sum += amplitude * value_noise(seed.wrapping_add(octave as u64), u, v, cells);
+            total += amplitude;
+            amplitude *= 0.5;
+            cells = (cells.0 * 2, cells.1 * 2);
+        }
+        sum / total
+    }
+
+    /// Generate the level data, with the flood level per section.
+    pub fn generate(
+        &self,
+        size: (i32, i32),
+        bits: TerrainBits,
+        delta_power: u8,
+        sections: usize,
+    ) -> (LevelData, Box<[u8]>) {
+        let total = size.0 as usize * size.1 as usize;
+        let (low, high) = self.heights;
+        let range = high.saturating_sub(low) as f32;
+        let surface = (0..total)
+            .map(|i| {
+                let (x, y) = (i as i32 % size.0, i as i32 / size.0);
+                // stretch the noise, which rarely goes to the extremes
+                let n = self.noise(self.seed, size, self.feature_size, x, y);
+                let t = (1.5 * (n - 0.5) + 0.5).clamp(0.0, 1.0);
+                low + (t * range) as u8
+            })
+            .collect::<Vec<_>>();
+
+        let flood = (0..sections)
+            .map(|section| {
+                let offset =
+                    (2.0 * hash(self.seed ^ 0xF100D, section as i32, 0) - 1.0) * FLOOD_VARIATION;
+                (self.water_level as f32 + offset).clamp(0.0, 255.0) as u8
+            })
+            .collect::<Box<[u8]>>();
+        let section_rows = (size.1 as usize / sections.max(1)).max(1);
+
+        let mut data = LevelData {
+            height: vec![0; total].into_boxed_slice(),
+            meta: vec![0; total].into_boxed_slice(),
+            size,
+        };
+        let cave_seed = self.seed ^ 0xCA7E;
+        let delta = (CAVE_CLEARANCE >> delta_power).min(0xF) as u8;
+        let get = |x: i32, y: i32| {
+            surface[(y.rem_euclid(size.1) * size.0 + x.rem_euclid(size.0)) as usize]
+        };
+
+        for y in 0..size.1 {
+            let water = flood[(y as usize / section_rows).min(sections - 1)];
+            for x in (0..size.0).step_by(2) {
+                let i = (y * size.0 + x) as usize;
+                let pair = [get(x, y), get(x + 1, y)];
+                let types = [0, 1].map(|k| {
+                    let (tx, h) = (x + k, pair[k as usize]);
+                    let step = [
+                        get(tx - 1, y),
+                        get(tx + 1, y),
+                        get(tx, y - 1),
+                        get(tx, y + 1),
+                    ]
+                    .iter()
+                    .map(|&n| n.abs_diff(h))
+                    .max()
+                    .unwrap_or(0);
+                    if step >= CLIFF_STEP {
+                        TERRAIN_CLIFF
+                    } else if h <= water.saturating_add(2) {
+                        TERRAIN_SHORE
+                    } else if h as f32 > low as f32 + 0.75 * range {
+                        TERRAIN_PEAK
+                    } else if hash(self.seed, tx >> 4, y >> 4) < 0.5 {
+                        TERRAIN_GRASS
+                    } else {
+                        TERRAIN_MEADOW
+                    }
+                });
+
+                let top = pair[0].max(pair[1]);
+                let cave = self.noise(cave_seed, size, self.feature_size / 2, x, y);
+                let is_cave = (cave - 0.5).abs() < self.cave_width
+                    && top >= low.saturating_add(CAVE_DEPTH)
+                    && top.saturating_sub(CAVE_DEPTH) > water;
+                if is_cave {
+                    // the floor is in the even texel, the surface above in the odd one
+                    data.height[i] = top - CAVE_DEPTH;
+                    data.height[i + 1] = top;
+                    data.meta[i] = DOUBLE_LEVEL
+                        | ((delta >> DELTA_BITS) & DELTA_MASK)
+                        | bits.write(TERRAIN_CAVE);
+                    data.meta[i + 1] = DOUBLE_LEVEL | (delta & DELTA_MASK) | bits.write(types[1]);
+                } else {
+                    for k in 0..2 {
+                        data.height[i + k] = pair[k];
+                        data.meta[i + k] = bits.write(types[k]);
+                    }
+                }
+            }
+        }
+
+        (data, flood)
+    }
+}
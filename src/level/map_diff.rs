@@ -0,0 +1,36 @@
+//! Changes of the terrain between two maps of the same world.
+//!
+//! The changed map is either uncompressed in the VMP layout or splay-compressed
+//! as VMC, of the same size as the base world, e.g. a map copied out of the
+//! data of a played game. The save files of the original game are not parsed,
+//! only the maps. The changed map is compared against the base world into
+//! a patch, which is applied or persisted like the changes made while driving.
+
+use super::{load_vmc, load_vmp, Level, Patch};
+
+use std::path::Path;
+
+/// Read the terrain of the changed map, returning its changes against `base`.
+pub fn load_map_diff(path: &Path, base: &Level) -> Result<Patch, String> {
+    let data = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("vmc") => load_vmc(path, base.size),
+        _ => {
+            let expected = 2 * base.size.0 as u64 * base.size.1 as u64;
+            match std::fs::metadata(path) {
+                Ok(meta) if meta.len() != expected => {
+                    return Err(format!(
+                        "Changed map {} has {} bytes, expected {} for a {}x{} world",
+                        path.display(),
+                        meta.len(),
+                        expected,
+                        base.size.0,
+                        base.size.1
+                    ))
+                }
+                _ => load_vmp(path, base.size),
+            }
+        }
+    }
+    .map_err(|e| format!("Unable to load the changed map {}: {}", path.display(), e))?;
+    Ok(Patch::diff_texels(base, &data.height, &data.meta))
+}
@@ -1,27 +1,44 @@
 use byteorder::{LittleEndian as E, ReadBytesExt, WriteBytesExt};
 
 use std::{
-    fs::File,
-    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
-    path::{Path, PathBuf},
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::Path,
 };
 
 mod config;
+mod fixture;
+pub mod generator;
+mod map_diff;
+mod patch;
+mod preset;
+mod pyramid;
+pub mod scatter;
 
 pub use self::config::{LevelConfig, Power, TerrainConfig};
-use crate::config::settings;
-
-pub type TerrainType = u8;
-
-pub const DOUBLE_LEVEL: u8 = 1 << 6;
-pub const DELTA_BITS: u8 = 2;
-pub const DELTA_MASK: u8 = 0x3;
+pub use self::fixture::Fixture;
+pub use self::generator::Generator;
+pub use self::map_diff::load_map_diff;
+pub use self::patch::{Baseline, Patch, Span};
+pub use self::preset::TestPreset;
+pub use self::pyramid::Pyramid;
+use crate::{
+    config::settings,
+    locale::{tr, tr_fmt},
+    space::coords::{TexelPos, WorldPos},
+};
+pub use formats::level::{
+    load_vmc, load_vmp, LevelData, TerrainBits, TerrainType, DELTA_BITS, DELTA_MASK, DOUBLE_LEVEL,
+};
 
 pub struct Level {
     pub size: (i32, i32),
     pub flood_map: Box<[u8]>,
     pub height: Box<[u8]>,
     pub meta: Box<[u8]>,
+    /// Bounds of the `height` per region, see `update_pyramid`.
+    pub pyramid: Pyramid,
     pub palette: [[u8; 4]; 0x100],
     pub terrains: Box<[TerrainConfig]>,
     pub geometry: settings::Geometry,
@@ -30,36 +47,6 @@ pub struct Level {
 #[derive(Copy, Clone)]
 pub struct Point(pub f32, pub TerrainType);
 
-#[derive(Copy, Clone)]
-pub struct TerrainBits {
-    pub shift: u8,
-    pub mask: TerrainType,
-}
-
-impl TerrainBits {
-    pub fn new(count: u8) -> Self {
-        match count {
-            8 => TerrainBits {
-                shift: 3,
-                mask: 0x7,
-            },
-            16 => TerrainBits {
-                shift: 2,
-                mask: 0xF,
-            },
-            other => panic!("Unexpected terrain count {}!", other),
-        }
-    }
-
-    pub fn read(&self, meta: u8) -> TerrainType {
-        (meta >> self.shift) & self.mask
-    }
-
-    pub fn write(&self, tt: TerrainType) -> u8 {
-        tt << self.shift
-    }
-}
-
 #[derive(Copy, Clone)]
 pub enum Texel {
     Single(Point),
@@ -80,11 +67,60 @@ impl Texel {
             Texel::Dual { ref high, .. } => high.0,
         }
     }
+
+    /// Returns how deep the given altitude is under the upper layer,
+    /// if it's between the layers of a stacked texel.
+    pub fn depth_underground(&self, altitude: f32) -> Option<f32> {
+        match *self {
+            Texel::Single(_) => None,
+            Texel::Dual { ref low, mid, .. } if altitude >= low.0 && altitude < mid => {
+                Some(mid - altitude)
+            }
+            Texel::Dual { .. } => None,
+        }
+    }
+
+    /// Check if the altitude is inside the terrain, or closer than `margin` to its surfaces.
+    pub fn obstructs(&self, altitude: f32, margin: f32) -> bool {
+        match *self {
+            Texel::Single(ref p) => altitude < p.0 + margin,
+            Texel::Dual {
+                ref low,
+                mid,
+                ref high,
+            } => {
+                altitude < low.0 + margin || (altitude > mid - margin && altitude < high.0 + margin)
+            }
+        }
+    }
 }
 
 impl Level {
+    /// A level of single texels with the given heights, a single flood section,
+    /// and the terrains of `LevelConfig::new_test`. Mostly useful for the tests.
+    pub fn from_height(size: (i32, i32), height: Box<[u8]>) -> Self {
+        let total = size.0 as usize * size.1 as usize;
+        assert_eq!(
+            height.len(),
+            total,
+            "Height doesn't match the size {:?}",
+            size
+        );
+        Level {
+            size,
+            flood_map: vec![0].into_boxed_slice(),
+            pyramid: Pyramid::new(size, &height),
+            height,
+            meta: vec![0; total].into_boxed_slice(),
+            palette: [[0; 4]; 0x100],
+            terrains: LevelConfig::new_test().terrains,
+            geometry: Default::default(),
+        }
+    }
+
+    /// The terrain count is checked by `LevelConfig::load` and `Fixture::load`.
     pub fn terrain_bits(&self) -> TerrainBits {
-        TerrainBits::new(self.terrains.len() as u8)
+        TerrainBits::new(self.terrains.len() as u8).expect("Unsupported level terrains")
     }
 
     fn get_mid_altitude(&self, low: u8, high: u8, delta: u8) -> u8 {
@@ -93,10 +129,9 @@ impl Level {
         low.saturating_add(delta << power).min(high)
     }
 
-    pub fn get(&self, coord: (i32, i32)) -> Texel {
+    pub fn get(&self, coord: impl Into<TexelPos>) -> Texel {
         let bits = self.terrain_bits();
-        let i = (coord.1.rem_euclid(self.size.1) * self.size.0 + coord.0.rem_euclid(self.size.0))
-            as usize;
+        let i = coord.into().index(self.size);
         let meta = self.meta[i];
         let altitude_scale = self.geometry.height as f32 / 256.0;
         if meta & DOUBLE_LEVEL != 0 {
@@ -123,6 +158,44 @@ impl Level {
         }
     }
 
+    /// Wrap the coordinates of a texel around the level,
+    /// returning them together with the index of the texel in the data.
+    pub fn wrap_texel(&self, x: i32, y: i32) -> ((i32, i32), usize) {
+        let (x, y) = (x.rem_euclid(self.size.0), y.rem_euclid(self.size.1));
+        ((x, y), (y * self.size.0 + x) as usize)
+    }
+
+    /// Texel under the world position.
+    pub fn get_at(&self, pos: impl Into<WorldPos>) -> Texel {
+        self.get(pos.into().texel())
+    }
+
+    /// Water altitude at the given row, as given by its flood section.
+    /// Without any sections, there is no water.
+    pub fn flood_level(&self, y: i32) -> f32 {
+        if self.flood_map.is_empty() {
+            return 0.0;
+        }
+        let row = y.rem_euclid(self.size.1) as usize;
+        let section = row * self.flood_map.len() / self.size.1 as usize;
+        let altitude_scale = self.geometry.height as f32 / 256.0;
+        self.flood_map[section] as f32 * altitude_scale
+    }
+
+    /// Range of the altitudes within the rectangle of texels, given as
+    /// the start and end coordinates, wrapping around the level.
+    /// It's conservative, i.e. may be wider than the actual range.
+    pub fn query_minmax(&self, min: (i32, i32), max: (i32, i32)) -> Range<f32> {
+        let [low, high] = self.pyramid.query(min, max);
+        let altitude_scale = self.geometry.height as f32 / 256.0;
+        low as f32 * altitude_scale..high as f32 * altitude_scale
+    }
+
+    /// Refresh the bounds of the query after changing the heights in the rectangle.
+    pub fn update_pyramid(&mut self, min: (i32, i32), max: (i32, i32)) {
+        self.pyramid.update(&self.height, min, max);
+    }
+
     /// A faster version of query that only returns the lowest level altitude.
     pub fn get_low_fast(&self, coord: (i32, i32)) -> f32 {
         assert!(coord.0 >= 0 && coord.1 >= 0);
@@ -164,21 +237,48 @@ impl Level {
         data
     }
 
-    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
-        ui.label(format!("Delta mask: {}", self.geometry.delta_mask));
+    /// Show the geometry controls. Returns true if the geometry has changed.
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        ui.label(tr_fmt("Delta mask: {}", &[&self.geometry.delta_mask]));
         ui.horizontal(|ui| {
             for terrain_id in 0..8 {
                 let mask = 1 << terrain_id;
                 let mut checked = self.geometry.delta_mask & mask != 0;
-                ui.add(egui::Checkbox::without_text(&mut checked));
+                changed |= ui.add(egui::Checkbox::without_text(&mut checked)).changed();
                 self.geometry.delta_mask &= !mask;
                 if checked {
                     self.geometry.delta_mask |= mask;
                 }
             }
         });
-        ui.add(egui::Slider::new(&mut self.geometry.delta_power, 0..=4).text("Delta power"));
-        ui.add(egui::Slider::new(&mut self.geometry.delta_const, 1..=15).text("Delta const"));
+        changed |= ui
+            .add(egui::Slider::new(&mut self.geometry.delta_power, 0..=4).text(tr("Delta power")))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut self.geometry.delta_const, 1..=15).text(tr("Delta const")))
+            .changed();
+        changed
+    }
+
+    /// Show the water level of each section. Returns true if any of them has changed.
+    pub fn draw_flood_ui(&mut self, ui: &mut egui::Ui) -> bool {
+        if self.flood_map.is_empty() {
+            ui.label(tr("No water sections"));
+            return false;
+        }
+        let mut changed = false;
+        let rows_per_section = self.size.1 as usize / self.flood_map.len();
+        for (i, flood) in self.flood_map.iter_mut().enumerate() {
+            let start = i * rows_per_section;
+            changed |= ui
+                .add(egui::Slider::new(flood, 0..=0xFF).text(tr_fmt(
+                    "Rows {}..{}",
+                    &[&start, &(start + rows_per_section)],
+                )))
+                .changed();
+        }
+        changed
     }
 }
 
@@ -187,8 +287,8 @@ fn print_palette(data: &[[u8; 4]], info: &str) {
     print!("Palette - {}:", info);
     for i in 0..3 {
         print!("\n\t");
-        for j in 0..0x100 {
-            print!("{:02X}", data[j][i]);
+        for color in data.iter().take(0x100) {
+            print!("{:02X}", color[i]);
         }
     }
     println!();
@@ -232,23 +332,29 @@ pub fn read_palette(input: File, config: Option<&[TerrainConfig]>) -> [[u8; 4];
     data
 }
 
-pub fn load_flood(config: &LevelConfig) -> Box<[u8]> {
-    profiling::scope!("Flood Map");
+/// Offset of the flood map in the VPR file, and the expected size of the file.
+fn flood_layout(config: &LevelConfig) -> (u64, u64) {
     let size = (config.size.0.as_value(), config.size.1.as_value());
     let flood_size = size.1 >> config.section.as_power();
+    let geo_pow = config.geo.as_power();
+    let net_size = (size.0 * size.1) >> (2 * geo_pow);
+    let flood_offset =
+        (2 * 4 + (1 + 4 + 4) * 4 + 2 * net_size + 2 * geo_pow * 4 + 2 * flood_size * geo_pow * 4)
+            as u64;
+    (flood_offset, flood_offset + (flood_size * 4) as u64)
+}
 
-    let vpr_file = match File::open(&config.path_data.with_extension("vpr")) {
+pub fn load_flood(config: &LevelConfig) -> Box<[u8]> {
+    profiling::scope!("Flood Map");
+    let flood_size = config.size.1.as_value() >> config.section.as_power();
+
+    let vpr_file = match File::open(config.path_data.with_extension("vpr")) {
         Ok(file) => file,
         Err(_) => return vec![0; flood_size as usize].into_boxed_slice(),
     };
 
     info!("Loading flood map...");
-    let geo_pow = config.geo.as_power();
-    let net_size = (size.0 * size.1) >> (2 * geo_pow);
-    let flood_offset =
-        (2 * 4 + (1 + 4 + 4) * 4 + 2 * net_size + 2 * geo_pow * 4 + 2 * flood_size * geo_pow * 4)
-            as u64;
-    let expected_file_size = flood_offset + (flood_size * 4) as u64;
+    let (flood_offset, expected_file_size) = flood_layout(config);
     assert_eq!(vpr_file.metadata().unwrap().len(), expected_file_size,);
     let mut vpr = BufReader::new(vpr_file);
     vpr.seek(SeekFrom::Start(flood_offset)).unwrap();
@@ -257,10 +363,43 @@ pub fn load_flood(config: &LevelConfig) -> Box<[u8]> {
         .collect()
 }
 
-pub struct LevelData {
-    pub height: Box<[u8]>,
-    pub meta: Box<[u8]>,
-    pub size: (i32, i32),
+/// Write the flood map into the existing VPR file of the level,
+/// leaving the rest of the file intact.
+pub fn save_flood(config: &LevelConfig, flood_map: &[u8]) -> io::Result<()> {
+    let path = config.path_data.with_extension("vpr");
+    let (flood_offset, expected_file_size) = flood_layout(config);
+    let flood_size = config.size.1.as_value() >> config.section.as_power();
+    if flood_map.len() != flood_size as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Expected {} flood sections, got {}",
+                flood_size,
+                flood_map.len()
+            ),
+        ));
+    }
+    let mut vpr_file = OpenOptions::new().write(true).open(&path)?;
+    let file_size = vpr_file.metadata()?.len();
+    if file_size != expected_file_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Unexpected size of {}: {} instead of {}",
+                path.display(),
+                file_size,
+                expected_file_size
+            ),
+        ));
+    }
+
+    info!("Saving flood map...");
+    vpr_file.seek(SeekFrom::Start(flood_offset))?;
+    let mut vpr = BufWriter::new(vpr_file);
+    for &level in flood_map {
+        vpr.write_u32::<E>(level as u32)?;
+    }
+    vpr.flush()
 }
 
 impl From<Level> for LevelData {
@@ -273,119 +412,8 @@ impl From<Level> for LevelData {
     }
 }
 
-impl LevelData {
-    pub fn save_vmp(&self, path: &Path) {
-        let mut vmp = BufWriter::new(File::create(path).unwrap());
-        self.height
-            .chunks(self.size.0 as _)
-            .zip(self.meta.chunks(self.size.0 as _))
-            .for_each(|(h_row, m_row)| {
-                vmp.write_all(h_row).unwrap();
-                vmp.write_all(m_row).unwrap();
-            });
-    }
-
-    pub fn save_vmc(&self, path: &Path) {
-        use splay::Splay;
-        let mut vmc = BufWriter::new(File::create(path).unwrap());
-
-        let base_offset = self.size.1 as u64 * (2 + 4) + Splay::tree_size();
-        for i in 0..self.size.1 {
-            vmc.write_i32::<E>(base_offset as i32 + i * self.size.0 * 2)
-                .unwrap();
-            vmc.write_i16::<E>(self.size.0 as i16 * 2).unwrap();
-        }
-
-        Splay::write_trivial(&mut vmc);
-        assert_eq!(vmc.seek(SeekFrom::Current(0)).unwrap(), base_offset);
-
-        self.height
-            .chunks(self.size.0 as _)
-            .zip(self.meta.chunks(self.size.0 as _))
-            .for_each(|(h_row, m_row)| {
-                Splay::compress_trivial(h_row, m_row, &mut vmc);
-            });
-    }
-}
-
-pub fn load_vmc(path: &Path, size: (i32, i32)) -> LevelData {
-    use rayon::prelude::*;
-    use splay::Splay;
-
-    info!("Loading height map...");
-    let total = (size.0 * size.1) as usize;
-    let mut level = LevelData {
-        height: vec![0u8; total].into_boxed_slice(),
-        meta: vec![0u8; total].into_boxed_slice(),
-        size,
-    };
-
-    let (splay, st_table, sz_table) = {
-        profiling::scope!("Prepare");
-        let mut vmc_base = BufReader::new(File::open(path).expect("Unable to open VMC"));
-
-        info!("\tLoading compression tables...");
-        let mut st_table = Vec::<i32>::with_capacity(size.1 as usize);
-        let mut sz_table = Vec::<i16>::with_capacity(size.1 as usize);
-        for _ in 0..size.1 {
-            st_table.push(vmc_base.read_i32::<E>().unwrap());
-            sz_table.push(vmc_base.read_i16::<E>().unwrap());
-        }
-
-        info!("\tDecompressing level data...");
-        let splay = Splay::new(&mut vmc_base);
-        (splay, st_table, sz_table)
-    };
-
-    level
-        .height
-        .chunks_mut(size.0 as _)
-        .zip(level.meta.chunks_mut(size.0 as _))
-        .zip(st_table.iter().zip(&sz_table))
-        .collect::<Vec<_>>()
-        .par_chunks_mut(64)
-        .for_each(|source_group| {
-            //Note: a separate file per group is required
-            let mut vmc = File::open(path).unwrap();
-            let data_size: i16 = source_group
-                .iter()
-                .map(|(_, (_, &size))| size)
-                .max()
-                .unwrap();
-            let mut data = vec![0u8; data_size as usize];
-            for &mut ((ref mut h_row, ref mut m_row), (offset, &size)) in source_group {
-                vmc.seek(SeekFrom::Start(*offset as u64)).unwrap();
-                vmc.read_exact(&mut data[..size as usize]).unwrap();
-                splay.expand(&data[..size as usize], h_row, m_row);
-            }
-        });
-
-    level
-}
-
-pub fn load_vmp(path: &Path, size: (i32, i32)) -> LevelData {
-    let total = (size.0 * size.1) as usize;
-    let mut level = LevelData {
-        height: vec![0u8; total].into_boxed_slice(),
-        meta: vec![0u8; total].into_boxed_slice(),
-        size,
-    };
-
-    let mut vmp = BufReader::new(File::open(path).expect("Unable to open VMP"));
-    level
-        .height
-        .chunks_mut(size.0 as _)
-        .zip(level.meta.chunks_mut(size.0 as _))
-        .for_each(|(h_row, m_row)| {
-            vmp.read_exact(h_row).unwrap();
-            vmp.read_exact(m_row).unwrap();
-        });
-
-    level
-}
-
-fn path_empty(path_buf: &PathBuf) -> bool {
-    path_buf.as_path().to_str() == Some("")
+fn path_empty(path: &Path) -> bool {
+    path.to_str() == Some("")
 }
 
 pub fn load(config: &LevelConfig, geometry: &settings::Geometry) -> Level {
@@ -393,25 +421,29 @@ pub fn load(config: &LevelConfig, geometry: &settings::Geometry) -> Level {
 
     info!("Loading data map...");
     let size = (config.size.0.as_value(), config.size.1.as_value());
-    let LevelData { height, meta, size } = if path_empty(&config.path_data) {
-        let total = size.0 as usize * size.1 as usize;
-        LevelData {
-            height: vec![0; total].into_boxed_slice(),
-            meta: vec![0; total].into_boxed_slice(),
-            size,
+    let (LevelData { height, meta, size }, flood_map) = if path_empty(&config.path_data) {
+        let sections = (size.1 as usize >> config.section.as_power()).max(1);
+        let bits =
+            TerrainBits::new(config.terrains.len() as u8).expect("Unsupported level terrains");
+        match config.generator {
+            Some(ref generator) => {
+                info!("Generating level {:?}", generator);
+                generator.generate(size, bits, geometry.delta_power, sections)
+            }
+            None => {
+                info!("Generating test level {:?}", config.test_preset);
+                config
+                    .test_preset
+                    .generate(size, bits, geometry.delta_power, sections)
+            }
         }
-    } else if config.is_compressed {
-        load_vmc(&config.path_data.with_extension("vmc"), size)
     } else {
-        load_vmp(&config.path_data.with_extension("vmp"), size)
-    };
-
-    info!("Loading flood map...");
-    let flood_map = if path_empty(&config.path_data) {
-        let sections = size.1 as usize >> config.section.as_power();
-        vec![0; sections].into_boxed_slice()
-    } else {
-        load_flood(config)
+        let data = if config.is_compressed {
+            load_vmc(&config.path_data.with_extension("vmc"), size).expect("Unable to load VMC")
+        } else {
+            load_vmp(&config.path_data.with_extension("vmp"), size).expect("Unable to load VMP")
+        };
+        (data, load_flood(config))
     };
 
     let palette = if path_empty(&config.path_palette) {
@@ -424,6 +456,7 @@ pub fn load(config: &LevelConfig, geometry: &settings::Geometry) -> Level {
     Level {
         size,
         flood_map,
+        pyramid: Pyramid::new(size, &height),
         height,
         meta,
         palette,
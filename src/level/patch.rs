@@ -0,0 +1,150 @@
+//! Changes of the terrain against the original level data.
+//!
+//! A patch is a list of row spans with the new height and meta values,
+//! so that only the modified texels need to be stored.
+
+use super::Level;
+
+use std::{collections::BTreeMap, ops::Range};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub x: i32,
+    pub y: i32,
+    pub height: Vec<u8>,
+    pub meta: Vec<u8>,
+}
+
+/// Span of the texels of the row that differ from the base row, if any.
+fn diff_row(y: i32, base: (&[u8], &[u8]), current: (&[u8], &[u8])) -> Option<Span> {
+    let changed = |x: &usize| base.0[*x] != current.0[*x] || base.1[*x] != current.1[*x];
+    let first = (0..current.0.len()).find(changed)?;
+    let last = (0..current.0.len()).rev().find(changed).unwrap();
+    Some(Span {
+        x: first as i32,
+        y,
+        height: current.0[first..=last].to_vec(),
+        meta: current.1[first..=last].to_vec(),
+    })
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Patch {
+    pub spans: Vec<Span>,
+}
+
+impl Patch {
+    /// Collect the texels of `current` that differ from `base`, per row.
+    pub fn diff(base: &Level, current: &Level) -> Self {
+        assert_eq!(
+            base.size, current.size,
+            "Unable to diff levels of different sizes"
+        );
+        Self::diff_texels(base, &current.height, &current.meta)
+    }
+
+    /// Collect the texels of the data that differ from `base`, per row.
+    pub(super) fn diff_texels(base: &Level, height: &[u8], meta: &[u8]) -> Self {
+        let width = base.size.0 as usize;
+        let spans = (0..base.size.1)
+            .filter_map(|y| {
+                let row = y as usize * width..(y as usize + 1) * width;
+                diff_row(
+                    y,
+                    (&base.height[row.clone()], &base.meta[row.clone()]),
+                    (&height[row.clone()], &meta[row]),
+                )
+            })
+            .collect();
+        Patch { spans }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Write the spans into the level.
+    /// Returns the bounding box of the changes, as the start and end coordinates.
+    pub fn apply(&self, level: &mut Level) -> Option<((i32, i32), (i32, i32))> {
+        let mut bounds: Option<((i32, i32), (i32, i32))> = None;
+        for span in self.spans.iter() {
+            let length = span.height.len().min(span.meta.len()) as i32;
+            if span.x < 0 || span.y < 0 || span.x + length > level.size.0 || span.y >= level.size.1
+            {
+                warn!(
+                    "Skipping a patch span out of the level at {}x{}",
+                    span.x, span.y
+                );
+                continue;
+            }
+            let start = (span.y * level.size.0 + span.x) as usize;
+            let texels = start..start + length as usize;
+            level.height[texels.clone()].copy_from_slice(&span.height[..length as usize]);
+            level.meta[texels].copy_from_slice(&span.meta[..length as usize]);
+
+            let (min, max) = ((span.x, span.y), (span.x + length, span.y + 1));
+            bounds = Some(match bounds {
+                Some((b_min, b_max)) => (
+                    (b_min.0.min(min.0), b_min.1.min(min.1)),
+                    (b_max.0.max(max.0), b_max.1.max(max.1)),
+                ),
+                None => (min, max),
+            });
+        }
+        if let Some((min, max)) = bounds {
+            level.update_pyramid(min, max);
+        }
+        bounds
+    }
+}
+
+/// Original rows of a level, kept from before they are changed, so that
+/// the changes can be collected without loading the level again.
+#[derive(Debug, Default)]
+pub struct Baseline {
+    /// Spans of the whole rows, by the row index.
+    rows: BTreeMap<i32, Span>,
+}
+
+impl Baseline {
+    /// Remember the rows of the level about to change, unless they are already kept.
+    pub fn keep(&mut self, level: &Level, rows: Range<i32>) {
+        let width = level.size.0 as usize;
+        for y in rows.start.max(0)..rows.end.min(level.size.1) {
+            self.rows.entry(y).or_insert_with(|| {
+                let row = y as usize * width..(y as usize + 1) * width;
+                Span {
+                    x: 0,
+                    y,
+                    height: level.height[row.clone()].to_vec(),
+                    meta: level.meta[row].to_vec(),
+                }
+            });
+        }
+    }
+
+    /// Remember the rows the patch is about to change.
+    pub fn keep_patch(&mut self, level: &Level, patch: &Patch) {
+        for span in patch.spans.iter() {
+            self.keep(level, span.y..span.y + 1);
+        }
+    }
+
+    /// Collect the texels of the kept rows that differ in the level now.
+    pub fn diff(&self, current: &Level) -> Patch {
+        let width = current.size.0 as usize;
+        let spans = self
+            .rows
+            .values()
+            .filter_map(|base| {
+                let row = base.y as usize * width..(base.y as usize + 1) * width;
+                diff_row(
+                    base.y,
+                    (&base.height, &base.meta),
+                    (&current.height[row.clone()], &current.meta[row]),
+                )
+            })
+            .collect();
+        Patch { spans }
+    }
+}
@@ -0,0 +1,150 @@
+use super::{LevelData, TerrainBits, DELTA_BITS, DELTA_MASK, DOUBLE_LEVEL};
+
+use std::f32::consts::TAU;
+
+/// Generated test worlds, selected with `level: "test:<name>"`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TestPreset {
+    /// Flat zero ground.
+    Flat,
+    /// Ramps of increasing steepness along Y.
+    Ramps,
+    /// Stairs with growing step height along Y.
+    Stairs,
+    /// Smooth sine hills.
+    Hills,
+    /// A tunnel band of stacked texels.
+    Cave,
+    /// A round basin filled with water.
+    Pool,
+}
+
+const TEST_PREFIX: &str = "test:";
+const BASE: u8 = 32;
+const POOL_FLOOD: u8 = 40;
+
+impl TestPreset {
+    pub const ALL: [Self; 6] = [
+        Self::Flat,
+        Self::Ramps,
+        Self::Stairs,
+        Self::Hills,
+        Self::Cave,
+        Self::Pool,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Flat => "flat",
+            Self::Ramps => "ramps",
+            Self::Stairs => "stairs",
+            Self::Hills => "hills",
+            Self::Cave => "cave",
+            Self::Pool => "pool",
+        }
+    }
+
+    /// Level name of the preset, as accepted by `game.level`.
+    pub fn level_name(self) -> String {
+        format!("{}{}", TEST_PREFIX, self.name())
+    }
+
+    /// Parse a level name of the form "test:<preset>".
+    /// Returns `None` if the name doesn't refer to a test level,
+    /// including the names of unknown presets.
+    pub fn from_level_name(level: &str) -> Option<Self> {
+        let name = level.strip_prefix(TEST_PREFIX)?;
+        let preset = Self::ALL.iter().find(|p| p.name() == name).cloned();
+        if preset.is_none() {
+            warn!(
+                "Unknown test level '{}', valid names are: {:?}",
+                name,
+                Self::ALL.iter().map(|p| p.name()).collect::<Vec<_>>()
+            );
+        }
+        preset
+    }
+
+    /// Generate the level data, with the flood level per section.
+    pub fn generate(
+        self,
+        size: (i32, i32),
+        bits: TerrainBits,
+        delta_power: u8,
+        sections: usize,
+    ) -> (LevelData, Box<[u8]>) {
+        let total = size.0 as usize * size.1 as usize;
+        let mut data = LevelData {
+            height: vec![0; total].into_boxed_slice(),
+            meta: vec![0; total].into_boxed_slice(),
+            size,
+        };
+        let mut flood = 0;
+
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                let i = (y * size.0 + x) as usize;
+                let (height, terrain) = match self {
+                    Self::Flat => (0, 0),
+                    Self::Ramps => {
+                        // up, plateau, and down within each cell
+                        let t = x % 128;
+                        let profile = if t < 48 {
+                            t as f32 / 48.0
+                        } else if t < 80 {
+                            1.0
+                        } else {
+                            (128 - t) as f32 / 48.0
+                        };
+                        let amplitude = 16.0 * (y / 128 + 1) as f32;
+                        (BASE + (profile * amplitude) as u8, 1)
+                    }
+                    Self::Stairs => {
+                        let step = 1 + 2 * (y / 128);
+                        let count = (x % 256) / 16;
+                        let height = (BASE as i32 + count * step).min(0xFF);
+                        (height as u8, 2 + (count & 1) as u8)
+                    }
+                    Self::Hills => {
+                        let (u, v) = (x as f32 / 256.0, y as f32 / 256.0);
+                        let wave = (u * TAU).sin() * (v * TAU).sin()
+                            + 0.25 * (u * 3.0 * TAU).cos() * (v * 2.0 * TAU).cos();
+                        let height = 80.0 + 48.0 * wave;
+                        (height as u8, if wave > 0.5 { 4 } else { 1 })
+                    }
+                    Self::Cave => {
+                        let in_band = (size.0 / 4..size.0 * 3 / 4).contains(&x);
+                        if in_band && y % 256 < 192 {
+                            // the cave has a floor and a ceiling, 64 units above
+                            let delta = (64u32 >> delta_power).min(0xF) as u8;
+                            let (height, meta_delta) = if x & 1 == 0 {
+                                (BASE, delta >> DELTA_BITS)
+                            } else {
+                                (BASE + 96, delta)
+                            };
+                            data.meta[i] = DOUBLE_LEVEL | (meta_delta & DELTA_MASK);
+                            (height, 5)
+                        } else {
+                            (BASE, 1)
+                        }
+                    }
+                    Self::Pool => {
+                        flood = POOL_FLOOD;
+                        let center = (size.0 as f32 * 0.5, size.1 as f32 * 0.5);
+                        let radius = 0.2 * size.0.min(size.1) as f32;
+                        let d = (x as f32 - center.0).hypot(y as f32 - center.1) / radius;
+                        if d < 1.0 {
+                            (64 - (48.0 * (1.0 - d * d)) as u8, 6)
+                        } else {
+                            (64, 1)
+                        }
+                    }
+                };
+                data.height[i] = height;
+                data.meta[i] |= bits.write(terrain);
+            }
+        }
+
+        (data, vec![flood; sections].into_boxed_slice())
+    }
+}
@@ -0,0 +1,154 @@
+//! Lowest and highest texels per region of the level.
+//!
+//! The level is split into chunks, with the extremes of each chunk stored
+//! in a pyramid of coarser and coarser layers, so that any rectangle can be
+//! bounded by looking at a few cells of a single layer.
+
+/// Side of the finest chunks, in texels, as a power of two.
+const CHUNK_SHIFT: u32 = 4;
+
+#[derive(Clone, Debug, Default)]
+struct Layer {
+    /// Number of cells along each axis.
+    size: (i32, i32),
+    /// Lowest and highest raw texel height in each cell.
+    cells: Vec<[u8; 2]>,
+}
+
+impl Layer {
+    fn index(&self, x: i32, y: i32) -> usize {
+        (y * self.size.0 + x) as usize
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Pyramid {
+    /// From the finest to the coarsest, ending with a single cell.
+    layers: Vec<Layer>,
+    level_size: (i32, i32),
+}
+
+impl Pyramid {
+    pub fn new(level_size: (i32, i32), height: &[u8]) -> Self {
+        let mut layers = Vec::new();
+        let mut size = (
+            (level_size.0 + (1 << CHUNK_SHIFT) - 1) >> CHUNK_SHIFT,
+            (level_size.1 + (1 << CHUNK_SHIFT) - 1) >> CHUNK_SHIFT,
+        );
+        loop {
+            layers.push(Layer {
+                size,
+                cells: vec![[0; 2]; (size.0 * size.1) as usize],
+            });
+            if size.0 <= 1 && size.1 <= 1 {
+                break;
+            }
+            size = ((size.0 + 1) >> 1, (size.1 + 1) >> 1);
+        }
+        let mut pyramid = Pyramid { layers, level_size };
+        pyramid.update(height, (0, 0), level_size);
+        pyramid
+    }
+
+    /// Recompute the cells covering the changed rectangle of texels,
+    /// given as the start and end coordinates.
+    pub fn update(&mut self, height: &[u8], min: (i32, i32), max: (i32, i32)) {
+        let size = self.level_size;
+        let min = (min.0.max(0), min.1.max(0));
+        let max = (max.0.min(size.0), max.1.min(size.1));
+        if min.0 >= max.0 || min.1 >= max.1 {
+            return;
+        }
+        let (mut lo, mut hi) = (
+            (min.0 >> CHUNK_SHIFT, min.1 >> CHUNK_SHIFT),
+            ((max.0 - 1) >> CHUNK_SHIFT, (max.1 - 1) >> CHUNK_SHIFT),
+        );
+        let chunk = 1 << CHUNK_SHIFT;
+        let base = &mut self.layers[0];
+        for cy in lo.1..=hi.1 {
+            for cx in lo.0..=hi.0 {
+                let mut extremes = [0xFF, 0];
+                for y in cy * chunk..((cy + 1) * chunk).min(size.1) {
+                    let row = (y * size.0) as usize;
+                    let xs = cx * chunk..((cx + 1) * chunk).min(size.0);
+                    for &h in height[row + xs.start as usize..row + xs.end as usize].iter() {
+                        extremes = [extremes[0].min(h), extremes[1].max(h)];
+                    }
+                }
+                let index = base.index(cx, cy);
+                base.cells[index] = extremes;
+            }
+        }
+
+        for i in 1..self.layers.len() {
+            lo = (lo.0 >> 1, lo.1 >> 1);
+            hi = (hi.0 >> 1, hi.1 >> 1);
+            let (finer, coarser) = self.layers.split_at_mut(i);
+            let (finer, layer) = (&finer[i - 1], &mut coarser[0]);
+            for cy in lo.1..=hi.1 {
+                for cx in lo.0..=hi.0 {
+                    let mut extremes = [0xFF, 0];
+                    for y in 2 * cy..(2 * cy + 2).min(finer.size.1) {
+                        for x in 2 * cx..(2 * cx + 2).min(finer.size.0) {
+                            let cell = finer.cells[finer.index(x, y)];
+                            extremes = [extremes[0].min(cell[0]), extremes[1].max(cell[1])];
+                        }
+                    }
+                    let index = layer.index(cx, cy);
+                    layer.cells[index] = extremes;
+                }
+            }
+        }
+    }
+
+    /// Bounds of the raw heights within the rectangle of texels,
+    /// given as the start and end coordinates, wrapping around the level.
+    /// The result may be wider than the exact one, but never tighter.
+    pub fn query(&self, min: (i32, i32), max: (i32, i32)) -> [u8; 2] {
+        if min.0 >= max.0 || min.1 >= max.1 {
+            return [0xFF, 0];
+        }
+        let last = (max.0 - 1, max.1 - 1);
+        // pick the finest layer where the area spans at most 2x2 cells
+        let mut shift = CHUNK_SHIFT;
+        let mut index = 0;
+        while index + 1 < self.layers.len()
+            && ((last.0 >> shift) - (min.0 >> shift) > 1
+                || (last.1 >> shift) - (min.1 >> shift) > 1)
+        {
+            shift += 1;
+            index += 1;
+        }
+        let layer = &self.layers[index];
+        let xs = wrap_cells(min.0, last.0, self.level_size.0, shift);
+        let ys = wrap_cells(min.1, last.1, self.level_size.1, shift);
+        let mut extremes = [0xFF, 0];
+        for &(y0, y1) in ys.iter() {
+            for cy in y0..=y1 {
+                for &(x0, x1) in xs.iter() {
+                    for cx in x0..=x1 {
+                        let cell = layer.cells[layer.index(cx, cy)];
+                        extremes = [extremes[0].min(cell[0]), extremes[1].max(cell[1])];
+                    }
+                }
+            }
+        }
+        extremes
+    }
+}
+
+/// Ranges of the cells covering the texels from `first` to `last` inclusive,
+/// split at the level edge, since the level size doesn't have to be aligned.
+fn wrap_cells(first: i32, last: i32, size: i32, shift: u32) -> [(i32, i32); 2] {
+    let last_cell = (size - 1) >> shift;
+    if last - first + 1 >= size {
+        return [(0, last_cell); 2];
+    }
+    let start = first.rem_euclid(size);
+    let end = start + last - first;
+    if end < size {
+        [(start >> shift, end >> shift); 2]
+    } else {
+        [(start >> shift, last_cell), (0, (end - size) >> shift)]
+    }
+}
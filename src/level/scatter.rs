@@ -0,0 +1,66 @@
+//! Deterministic placement of the props, according to the scatter rules.
+
+use super::{Level, Point, Texel};
+use crate::config::scatter::Rule;
+
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+/// Area that the density of a rule is given for, in texels.
+const DENSITY_AREA: f32 = 100.0 * 100.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prop {
+    /// Index of the rule that placed it.
+    pub rule: usize,
+    pub position: cgmath::Vector3<f32>,
+    /// Rotation around the vertical axis, in radians.
+    pub angle: f32,
+    pub scale: f32,
+}
+
+fn surface(texel: Texel) -> Point {
+    match texel {
+        Texel::Single(point) => point,
+        Texel::Dual { high, .. } => high,
+    }
+}
+
+/// Steepness of the upper surface at the texel, in degrees.
+fn slope(level: &Level, x: i32, y: i32) -> f32 {
+    let height = |dx: i32, dy: i32| level.get((x + dx, y + dy)).high();
+    let gradient_x = 0.5 * (height(1, 0) - height(-1, 0));
+    let gradient_y = 0.5 * (height(0, 1) - height(0, -1));
+    gradient_x.hypot(gradient_y).atan().to_degrees()
+}
+
+/// Place the props of the rules that apply to the world over its level.
+/// The same seed always produces the same props.
+pub fn scatter(level: &Level, world: &str, rules: &[Rule], seed: u64) -> Vec<Prop> {
+    let area = level.size.0 as f32 * level.size.1 as f32;
+    let mut props = Vec::new();
+    for (index, rule) in rules.iter().enumerate() {
+        if !rule.applies_to(world) {
+            continue;
+        }
+        // every rule has its own sequence, so that editing one doesn't move the others
+        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(index as u64));
+        let attempts = (rule.density * area / DENSITY_AREA).round() as usize;
+        for _ in 0..attempts {
+            let x = rng.gen_range(0..level.size.0);
+            let y = rng.gen_range(0..level.size.1);
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            let scale = rule.scale.0 + (rule.scale.1 - rule.scale.0) * rng.gen::<f32>();
+            let Point(height, terrain) = surface(level.get((x, y)));
+            if terrain != rule.terrain || slope(level, x, y) > rule.max_slope {
+                continue;
+            }
+            props.push(Prop {
+                rule: index,
+                position: cgmath::vec3(x as f32 + 0.5, y as f32 + 0.5, height),
+                angle,
+                scale,
+            });
+        }
+    }
+    props
+}
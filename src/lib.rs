@@ -22,7 +22,11 @@ extern crate log;
 extern crate serde_derive;
 
 pub mod config;
+pub mod console;
+pub mod crash;
+pub mod jobs;
 pub mod level;
+pub mod locale;
 pub mod model;
 pub mod render;
 pub mod space;
@@ -0,0 +1,63 @@
+//! Translations of the UI strings.
+//!
+//! The English text of a string is its key, looked up in the table of the
+//! selected language at `res/locale/<language>.ron`. Strings missing from
+//! the table are shown in English.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Write as _},
+    fs::File,
+    path::Path,
+    sync::OnceLock,
+};
+
+pub const LOCALE_PATH: &str = "res/locale";
+
+pub type Table = HashMap<String, String>;
+
+static TABLE: OnceLock<Table> = OnceLock::new();
+
+pub fn load(path: &Path) -> Result<Table, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+    ron::de::from_reader(file).map_err(|e| format!("Unable to parse {}: {}", path.display(), e))
+}
+
+/// Select the language of the UI, empty for English.
+/// Only the first call has an effect.
+pub fn init(language: &str) {
+    if language.is_empty() {
+        return;
+    }
+    let path = Path::new(LOCALE_PATH).join(language).with_extension("ron");
+    match load(&path) {
+        Ok(table) => {
+            info!("Using {} UI strings from {}", table.len(), path.display());
+            let _ = TABLE.set(table);
+        }
+        Err(e) => error!("{}", e),
+    }
+}
+
+/// Translate the string into the selected language.
+pub fn tr(text: &'static str) -> &'static str {
+    match TABLE.get().and_then(|table| table.get(text)) {
+        Some(translated) => translated,
+        None => text,
+    }
+}
+
+/// Translate the string, and fill its `{}` placeholders with the arguments in order.
+pub fn tr_fmt(text: &'static str, args: &[&dyn fmt::Display]) -> String {
+    let mut result = String::new();
+    let mut args = args.iter();
+    for (i, piece) in tr(text).split("{}").enumerate() {
+        if i != 0 {
+            if let Some(arg) = args.next() {
+                let _ = write!(result, "{}", arg);
+            }
+        }
+        result.push_str(piece);
+    }
+    result
+}
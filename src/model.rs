@@ -1,12 +1,14 @@
 use crate::render::{
-    debug::Position as DebugPos,
+    debug::Line as DebugLine,
+    memory::{Allocation, Subsystem},
     object::{Context as ObjectContext, Vertex as ObjectVertex},
     ShapePolygon, VertexStorageNotSupported,
 };
 use m3d;
+use rayon::prelude::*;
 use wgpu::util::DeviceExt as _;
 
-use std::{fs::File, mem, ops::Range, slice, sync::Arc};
+use std::{collections::HashMap, fs::File, mem, ops::Range, slice, sync::Arc};
 
 #[derive(Copy, Clone)]
 pub struct BoundingBox {
@@ -15,12 +17,45 @@ pub struct BoundingBox {
     pub radius: f32,
 }
 
+impl BoundingBox {
+    /// Bounds of the box placed with the transform,
+    /// enclosing all of its corners.
+    pub fn transform(&self, t: &crate::space::Transform) -> Self {
+        use cgmath::Transform as _;
+
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for i in 0..8 {
+            let corner = cgmath::Point3::new(
+                if i & 1 != 0 { self.max[0] } else { self.min[0] },
+                if i & 2 != 0 { self.max[1] } else { self.min[1] },
+                if i & 4 != 0 { self.max[2] } else { self.min[2] },
+            );
+            let p = t.transform_point(corner);
+            for (axis, value) in [p.x, p.y, p.z].into_iter().enumerate() {
+                min[axis] = min[axis].min(value);
+                max[axis] = max[axis].max(value);
+            }
+        }
+        BoundingBox {
+            min,
+            max,
+            radius: self.radius * t.scale,
+        }
+    }
+}
+
 pub struct Mesh {
+    /// Hash of the vertices, to recognize the mesh outside of the game,
+    /// e.g. in the scene snapshots, see `vertex_id`.
+    pub id: u64,
     pub num_vertices: usize,
     pub vertex_buf: wgpu::Buffer,
     pub offset: [f32; 3],
     pub bbox: BoundingBox,
     pub physics: m3d::Physics,
+    /// GPU memory taken by the vertices.
+    pub memory: Allocation,
 }
 
 #[derive(Clone, Debug)]
@@ -28,6 +63,8 @@ pub struct Polygon {
     pub middle: [f32; 3],
     pub normal: [f32; 3],
     pub samples: Range<usize>,
+    /// Distance from the middle to the farthest sample.
+    pub radius: f32,
 }
 
 pub struct Shape {
@@ -38,6 +75,7 @@ pub struct Shape {
     pub sample_buf: Option<(wgpu::Buffer, usize)>,
     pub bind_group: Result<wgpu::BindGroup, VertexStorageNotSupported>,
     pub bounds: m3d::Bounds,
+    pub memory: Allocation,
 }
 
 pub type RawVertex = [i8; 3];
@@ -121,41 +159,329 @@ fn vec_i2f(v: [i32; 3]) -> [f32; 3] {
     [v[0] as f32, v[1] as f32, v[2] as f32]
 }
 
+/// Color of the debug normals at the collision samples, as the line color bytes.
+const SAMPLE_COLOR: u32 = 0xFF000080;
+
+/// Number of rays distributed over the sphere for the occlusion bake.
+/// Only the ones in the hemisphere of the vertex normal are traced.
+const OCCLUSION_RAYS: usize = 48;
+/// Shift of the ray origins along the normal, to avoid self-intersection.
+const OCCLUSION_BIAS: f32 = 0.5;
+
+/// Triangle corner in model space, as it's fed into the vertex buffer.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Corner {
+    pub pos: RawVertex,
+    pub normal: RawVertex,
+}
+
+/// Check if the ray hits the front side of the counter-clockwise triangle.
+/// The back sides are ignored, since the ray can start inside of the shape.
+fn ray_hits_triangle(origin: [f32; 3], dir: [f32; 3], tri: &[[f32; 3]; 3], max_t: f32) -> bool {
+    use cgmath::{InnerSpace as _, Vector3};
+    // Möller–Trumbore
+    let o = Vector3::from(origin);
+    let d = Vector3::from(dir);
+    let v0 = Vector3::from(tri[0]);
+    let e1 = Vector3::from(tri[1]) - v0;
+    let e2 = Vector3::from(tri[2]) - v0;
+    let p = d.cross(e2);
+    let det = e1.dot(p);
+    if det < 1.0e-6 {
+        return false;
+    }
+    let inv_det = 1.0 / det;
+    let s = o - v0;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return false;
+    }
+    let q = s.cross(e1);
+    let v = d.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+    let t = e2.dot(q) * inv_det;
+    t > 0.0 && t < max_t
+}
+
+/// Triangles of the collision shape, counter-clockwise when looking from the outside.
+pub fn shape_triangles(geometry: &m3d::Geometry<m3d::CollisionQuad>) -> Vec<[[f32; 3]; 3]> {
+    use cgmath::{InnerSpace as _, Vector3};
+
+    let position = |i: u16| geometry.positions[i as usize].map(|c| c as f32);
+    geometry
+        .polygons
+        .iter()
+        .flat_map(|quad| {
+            let [a, b, c, d] = quad.vertices.map(position);
+            let winding = (Vector3::from(b) - Vector3::from(a))
+                .cross(Vector3::from(c) - Vector3::from(a))
+                .dot(Vector3::from(quad.flat_normal.map(|n| n as f32)));
+            if winding < 0.0 {
+                [[a, c, b], [a, d, c]]
+            } else {
+                [[a, b, c], [a, c, d]]
+            }
+        })
+        .collect()
+}
+
+/// Bake the ambient occlusion of a mesh against the triangles of its collision shape.
+///
+/// Returns the visibility of each triangle corner in `[0, 127]`,
+/// which is the Snorm8 range of the `w` component of the vertex normal.
+pub fn bake_occlusion(
+    triangles: &[[Corner; 3]],
+    occluders: &[[[f32; 3]; 3]],
+    max_distance: f32,
+) -> Vec<[i8; 3]> {
+    use cgmath::{InnerSpace as _, Vector3};
+    profiling::scope!("Bake Occlusion");
+
+    // Fibonacci sphere gives an even enough distribution for the purpose.
+    let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+    let directions = (0..OCCLUSION_RAYS)
+        .map(|i| {
+            let z = 1.0 - (2.0 * i as f32 + 1.0) / OCCLUSION_RAYS as f32;
+            let r = (1.0 - z * z).sqrt();
+            let phi = golden_angle * i as f32;
+            Vector3::new(r * phi.cos(), r * phi.sin(), z)
+        })
+        .collect::<Vec<_>>();
+
+    // Most of the corners are shared between triangles, only trace each once.
+    let mut unique = triangles.iter().flatten().cloned().collect::<Vec<_>>();
+    unique.sort_by_key(|c| (c.pos, c.normal));
+    unique.dedup();
+
+    let visibility = unique
+        .par_iter()
+        .map(|corner| {
+            let n = Vector3::new(
+                corner.normal[0] as f32,
+                corner.normal[1] as f32,
+                corner.normal[2] as f32,
+            );
+            if n.magnitude2() == 0.0 {
+                return (*corner, 127);
+            }
+            let n = n.normalize();
+            let p = Vector3::new(
+                corner.pos[0] as f32,
+                corner.pos[1] as f32,
+                corner.pos[2] as f32,
+            );
+            let origin = p + n * OCCLUSION_BIAS;
+            let (mut total, mut open) = (0.0, 0.0);
+            for dir in directions.iter() {
+                let weight = dir.dot(n);
+                if weight <= 0.0 {
+                    continue;
+                }
+                total += weight;
+                let blocked = occluders
+                    .iter()
+                    .any(|tri| ray_hits_triangle(origin.into(), (*dir).into(), tri, max_distance));
+                if !blocked {
+                    open += weight;
+                }
+            }
+            let factor = if total > 0.0 { open / total } else { 1.0 };
+            (*corner, (factor * 127.0).round() as i8)
+        })
+        .collect::<HashMap<_, _>>();
+
+    triangles
+        .iter()
+        .map(|tri| {
+            [
+                visibility[&tri[0]],
+                visibility[&tri[1]],
+                visibility[&tri[2]],
+            ]
+        })
+        .collect()
+}
+
+/// Top-down silhouette of a model, as used by the icons of the vehicles.
+/// Each pixel is a byte, 0xFF where the model is, and 0 elsewhere.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pictogram {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Pictogram {
+    /// Rasterize the triangles of the model space in a view from above,
+    /// with the front of the model (+Y) at the top and +X to the right.
+    /// The model is fit into the picture, keeping its proportions.
+    pub fn from_triangles(triangles: &[[[f32; 3]; 3]], width: u32, height: u32) -> Self {
+        let mut pixels = vec![0; width as usize * height as usize];
+        let mut min = [f32::INFINITY; 2];
+        let mut max = [f32::NEG_INFINITY; 2];
+        for corner in triangles.iter().flatten() {
+            for axis in 0..2 {
+                min[axis] = min[axis].min(corner[axis]);
+                max[axis] = max[axis].max(corner[axis]);
+            }
+        }
+        let extent = [max[0] - min[0], max[1] - min[1]];
+        if triangles.is_empty() || extent[0] <= 0.0 || extent[1] <= 0.0 {
+            return Pictogram {
+                width,
+                height,
+                pixels,
+            };
+        }
+
+        let scale = (width as f32 / extent[0]).min(height as f32 / extent[1]);
+        let center = [0.5 * (min[0] + max[0]), 0.5 * (min[1] + max[1])];
+        let to_pixel = |p: &[f32; 3]| {
+            [
+                0.5 * width as f32 + (p[0] - center[0]) * scale,
+                0.5 * height as f32 - (p[1] - center[1]) * scale,
+            ]
+        };
+        for tri in triangles {
+            let [a, b, c] = [to_pixel(&tri[0]), to_pixel(&tri[1]), to_pixel(&tri[2])];
+            let area = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+            if area.abs() < 1.0e-6 {
+                continue;
+            }
+            let edge = |p: [f32; 2], q: [f32; 2], x: f32, y: f32| {
+                ((q[0] - p[0]) * (y - p[1]) - (q[1] - p[1]) * (x - p[0])) / area
+            };
+            let x0 = a[0].min(b[0]).min(c[0]).floor().max(0.0) as u32;
+            let y0 = a[1].min(b[1]).min(c[1]).floor().max(0.0) as u32;
+            let x1 = (a[0].max(b[0]).max(c[0]).ceil() as u32).min(width);
+            let y1 = (a[1].max(b[1]).max(c[1]).ceil() as u32).min(height);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                    // either winding covers the pixel, since the view is from both sides
+                    if edge(b, c, px, py) >= 0.0
+                        && edge(c, a, px, py) >= 0.0
+                        && edge(a, b, px, py) >= 0.0
+                    {
+                        pixels[y as usize * width as usize + x as usize] = 0xFF;
+                    }
+                }
+            }
+        }
+        Pictogram {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Silhouette of the collision shape.
+    pub fn from_shape(
+        geometry: &m3d::Geometry<m3d::CollisionQuad>,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        Self::from_triangles(&shape_triangles(geometry), width, height)
+    }
+
+    /// Size of a bitmap of the given dimensions with rows of `pitch` bytes,
+    /// or `None` if a row doesn't fit into the pitch, or the size is too large.
+    pub fn bitmap_size(width: u32, height: u32, pitch: u32) -> Option<usize> {
+        if pitch < width {
+            return None;
+        }
+        (pitch as usize)
+            .checked_mul(height as usize)
+            .filter(|&size| size <= isize::MAX as usize)
+    }
+
+    /// Copy the picture into a bitmap with rows of `pitch` bytes, see `bitmap_size`.
+    pub fn copy_to(&self, bitmap: &mut [u8], pitch: usize) {
+        if self.width == 0 {
+            return;
+        }
+        let width = self.width as usize;
+        for (dst, src) in bitmap.chunks_mut(pitch).zip(self.pixels.chunks(width)) {
+            dst[..width].copy_from_slice(src);
+        }
+    }
+}
+
+/// Hash of the vertex data of a mesh, the same across the runs and platforms.
+pub fn vertex_id(data: &[u8]) -> u64 {
+    use std::hash::Hasher as _;
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write(data);
+    hasher.finish()
+}
+
+/// Load the mesh into the GPU, with the ambient occlusion baked against
+/// the collision shape, if there is one. Otherwise the mesh is fully open.
 pub fn load_c3d(
     raw: m3d::Mesh<m3d::Geometry<m3d::DrawTriangle>>,
+    shape: Option<&m3d::Geometry<m3d::CollisionQuad>>,
     device: &wgpu::Device,
 ) -> Arc<Mesh> {
     let num_vertices = raw.geometry.polygons.len() * 3;
     debug!("\tGot {} GPU vertices...", num_vertices);
+    let corners = raw
+        .geometry
+        .polygons
+        .iter()
+        .map(|tri| {
+            let corner = |v: &m3d::Vertex| Corner {
+                pos: raw.geometry.positions[v.pos as usize],
+                normal: raw.geometry.normals[v.normal as usize],
+            };
+            [
+                corner(&tri.vertices[0]),
+                corner(&tri.vertices[1]),
+                corner(&tri.vertices[2]),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let occlusion = match shape {
+        Some(geometry) => {
+            bake_occlusion(&corners, &shape_triangles(geometry), raw.max_radius as f32)
+        }
+        None => vec![[127; 3]; corners.len()],
+    };
+
     let vertex_size = mem::size_of::<ObjectVertex>();
+    let mut memory = Allocation::new(Subsystem::Meshes);
+    memory.add_buffer(device, "C3D", (num_vertices * vertex_size) as _);
     let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("C3D"),
         size: (num_vertices * vertex_size) as wgpu::BufferAddress,
         usage: wgpu::BufferUsages::VERTEX,
         mapped_at_creation: true,
     });
-    {
+    let id = {
         let mut mapping = vertex_buf.slice(..).get_mapped_range_mut();
-        for (chunk, tri) in mapping
+        for ((chunk, tri), (tri_corners, tri_occlusion)) in mapping
             .chunks_mut(3 * vertex_size)
             .zip(&raw.geometry.polygons)
+            .zip(corners.iter().zip(&occlusion))
         {
             let out_vertices =
                 unsafe { slice::from_raw_parts_mut(chunk.as_mut_ptr() as *mut ObjectVertex, 3) };
-            for (vo, v) in out_vertices.iter_mut().zip(&tri.vertices) {
-                let p = raw.geometry.positions[v.pos as usize];
-                let n = raw.geometry.normals[v.normal as usize];
+            for ((vo, c), &ao) in out_vertices.iter_mut().zip(tri_corners).zip(tri_occlusion) {
+                let (p, n) = (c.pos, c.normal);
                 *vo = ObjectVertex {
                     pos: [p[0], p[1], p[2], 1],
                     color: tri.material[0],
-                    normal: [n[0], n[1], n[2], 0],
+                    normal: [n[0], n[1], n[2], ao],
                 };
             }
         }
-    }
+        vertex_id(&mapping)
+    };
     vertex_buf.unmap();
 
     Arc::new(Mesh {
+        id,
         num_vertices,
         vertex_buf,
         offset: vec_i2f(raw.parent_off),
@@ -165,9 +491,72 @@ pub fn load_c3d(
             radius: raw.max_radius as f32,
         },
         physics: raw.physics,
+        memory,
     })
 }
 
+/// Length of the strand mesh, along the Z axis.
+const STRAND_LENGTH: i8 = 16;
+
+/// Generate a thin square prism along the Z axis, from zero to `STRAND_LENGTH`,
+/// to be stretched between the points of the antennas and other appendages.
+pub fn strand_mesh(device: &wgpu::Device) -> Arc<Mesh> {
+    let positions = (0..8)
+        .map(|i| {
+            [
+                if i & 1 != 0 { 1 } else { -1 },
+                if i & 2 != 0 { 1 } else { -1 },
+                if i & 4 != 0 { STRAND_LENGTH } else { 0 },
+            ]
+        })
+        .collect::<Vec<_>>();
+    let n = m3d::NORMALIZER as i8;
+    // sides as corner indices, counter-clockwise when looking from the outside
+    let faces: [([u16; 4], [i8; 3]); 4] = [
+        ([0, 1, 5, 4], [0, -n, 0]),
+        ([2, 6, 7, 3], [0, n, 0]),
+        ([0, 4, 6, 2], [-n, 0, 0]),
+        ([1, 3, 7, 5], [n, 0, 0]),
+    ];
+    let mut geometry = m3d::Geometry::<m3d::DrawTriangle> {
+        positions,
+        normals: faces.iter().map(|&(_, normal)| normal).collect(),
+        polygons: Vec::new(),
+    };
+    for (face_index, &(corners, normal)) in faces.iter().enumerate() {
+        let vertex = |i: usize| m3d::Vertex {
+            pos: corners[i],
+            normal: face_index as u16,
+        };
+        for &(v0, v1, v2) in &[(0, 1, 2), (0, 2, 3)] {
+            geometry.polygons.push(m3d::DrawTriangle {
+                vertices: [vertex(v0), vertex(v1), vertex(v2)],
+                flat_normal: normal,
+                material: [m3d::ColorId::Body as u32, 0],
+            });
+        }
+    }
+
+    let length = STRAND_LENGTH as i32;
+    let raw = m3d::Mesh {
+        geometry,
+        bounds: m3d::Bounds {
+            coord_min: [-1, -1, 0],
+            coord_max: [1, 1, length],
+        },
+        parent_off: [0; 3],
+        parent_rot: [0; 3],
+        max_radius: length as u32,
+        physics: m3d::Physics {
+            volume: 0.0,
+            rcm: [0.0; 3],
+            jacobi: [[0.0; 3]; 3],
+        },
+        raw: None,
+    };
+    load_c3d(raw, None, device)
+}
+
 pub fn load_c3d_shape(
     raw: m3d::Mesh<m3d::Geometry<m3d::CollisionQuad>>,
     device: &wgpu::Device,
@@ -213,45 +602,45 @@ pub fn load_c3d_shape(
         let cur_samples = tess.tessellate(&corners[..], quad.middle);
 
         if with_sample_buf {
-            let mut nlen = 16.0;
-            sample_data.push(DebugPos {
-                pos: [middle[0], middle[1], middle[2], 1.0],
-            });
-            sample_data.push(DebugPos {
-                pos: [
-                    middle[0] + normal[0] * nlen,
-                    middle[1] + normal[1] * nlen,
-                    middle[2] + normal[2] * nlen,
-                    1.0,
-                ],
-            });
-            nlen = 4.0;
-            for s in cur_samples {
-                sample_data.push(DebugPos {
-                    pos: [s[0] as f32, s[1] as f32, s[2] as f32, 1.0],
-                });
-                sample_data.push(DebugPos {
-                    pos: [
-                        s[0] as f32 + normal[0] * nlen,
-                        s[1] as f32 + normal[1] * nlen,
-                        s[2] as f32 + normal[2] * nlen,
-                        1.0,
-                    ],
+            let push_normal = |data: &mut Vec<DebugLine>, from: [f32; 3], length: f32| {
+                data.push(DebugLine {
+                    from,
+                    to: [0, 1, 2].map(|i| from[i] + normal[i] * length),
+                    color: SAMPLE_COLOR,
                 });
+            };
+            push_normal(&mut sample_data, middle, 16.0);
+            for s in cur_samples {
+                push_normal(&mut sample_data, s.map(|c| c as f32), 4.0);
             }
         }
 
+        let radius = cur_samples
+            .iter()
+            .map(|s| {
+                let d = [0, 1, 2].map(|i| s[i] as f32 - middle[i]);
+                (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt()
+            })
+            .fold(0.0, f32::max);
         polygons.push(Polygon {
             middle,
             normal,
             samples: samples.len()..samples.len() + cur_samples.len(),
+            radius,
         });
         samples.extend(cur_samples);
     }
 
+    let vertex_size = raw.geometry.positions.len() * mem::size_of::<ShapeVertex>();
+    let mut memory = Allocation::new(Subsystem::Meshes);
+    memory.add_buffer(device, "Shape", vertex_size as _);
+    memory.add_buffer(device, "polygons", mem::size_of_val(&polygon_data[..]) as _);
+    if with_sample_buf {
+        memory.add_buffer(device, "samples", mem::size_of_val(&sample_data[..]) as _);
+    }
     let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("Shape"),
-        size: (raw.geometry.positions.len() * mem::size_of::<ShapeVertex>()) as wgpu::BufferAddress,
+        size: vertex_size as wgpu::BufferAddress,
         usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
         mapped_at_creation: true,
     });
@@ -297,9 +686,11 @@ pub fn load_c3d_shape(
             None
         },
         bounds: raw.bounds,
+        memory,
     })
 }
 
+/// cbindgen:ignore
 pub type VisualModel = m3d::Model<Arc<Mesh>, Arc<Shape>>;
 
 pub fn load_m3d(
@@ -308,26 +699,35 @@ pub fn load_m3d(
     object: &ObjectContext,
     shape_sampling: u8,
 ) -> VisualModel {
-    let raw = m3d::FullModel::load(file);
+    let raw = m3d::FullModel::load(file).expect("Unable to parse the model");
+    convert_m3d(raw, device, object, shape_sampling)
+}
 
+pub fn convert_m3d(
+    raw: m3d::FullModel,
+    device: &wgpu::Device,
+    object: &ObjectContext,
+    shape_sampling: u8,
+) -> VisualModel {
     VisualModel {
-        body: load_c3d(raw.body, device),
+        body: load_c3d(raw.body, Some(&raw.shape.geometry), device),
         shape: load_c3d_shape(raw.shape, device, shape_sampling, true, object),
         bound: raw.bound,
         color: raw.color,
         wheels: raw
             .wheels
             .into_iter()
-            .map(|wheel| wheel.map(|mesh| load_c3d(mesh, device)))
+            .map(|wheel| wheel.map(|mesh| load_c3d(mesh, None, device)))
             .collect(),
         debris: raw
             .debris
             .into_iter()
             .map(|debrie| m3d::Debrie {
-                mesh: load_c3d(debrie.mesh, device),
+                mesh: load_c3d(debrie.mesh, Some(&debrie.shape.geometry), device),
                 shape: load_c3d_shape(debrie.shape, device, 0, false, object),
             })
             .collect(),
         slots: m3d::Slot::map_all(raw.slots, |_, _| unreachable!()),
+        slot_mask: raw.slot_mask,
     }
 }
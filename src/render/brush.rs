@@ -0,0 +1,230 @@
+//! Preview of a terrain brush under the cursor.
+//!
+//! The area of the brush is covered by flat cells at the terrain height,
+//! and the fragment shader draws the ring of the brush radius on them,
+//! with a faint fill of the affected area inside.
+
+use crate::{level, render::DEPTH_FORMAT};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt as _;
+
+use std::mem;
+
+/// Maximum number of the cells across the brush.
+/// Larger brushes cover several texels with each cell.
+const MAX_CELLS: i32 = 64;
+/// Elevation of the cells over the terrain, to win the depth test.
+const LIFT: f32 = 0.25;
+
+/// Placement of the brush to preview.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+    pub center: cgmath::Vector2<f32>,
+    pub radius: f32,
+    /// Linear RGBA color of the ring.
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Locals {
+    center_radius: [f32; 4],
+    color: [f32; 4],
+}
+unsafe impl Pod for Locals {}
+unsafe impl Zeroable for Locals {}
+
+/// Cover the square around the brush with the flat cells,
+/// each at the highest terrain point under it.
+fn build_cells(level: &level::Level, brush: &Brush) -> Vec<[f32; 3]> {
+    let radius = brush.radius.max(1.0);
+    let start_x = (brush.center.x - radius).floor() as i32;
+    let start_y = (brush.center.y - radius).floor() as i32;
+    let end_x = (brush.center.x + radius).ceil() as i32;
+    let end_y = (brush.center.y + radius).ceil() as i32;
+    let cell = ((end_x - start_x).max(end_y - start_y) + MAX_CELLS - 1) / MAX_CELLS;
+
+    let mut vertices = Vec::new();
+    for y0 in (start_y..end_y).step_by(cell as usize) {
+        for x0 in (start_x..end_x).step_by(cell as usize) {
+            let mut height = 0f32;
+            for y in y0..y0 + cell {
+                for x in x0..x0 + cell {
+                    height = height.max(level.get((x, y)).high());
+                }
+            }
+            let z = height + LIFT;
+            let (x1, y1) = ((x0 + cell) as f32, (y0 + cell) as f32);
+            let (x0, y0) = (x0 as f32, y0 as f32);
+            vertices.extend_from_slice(&[
+                [x0, y0, z],
+                [x1, y0, z],
+                [x0, y1, z],
+                [x0, y1, z],
+                [x1, y0, z],
+                [x1, y1, z],
+            ]);
+        }
+    }
+    vertices
+}
+
+pub struct Context {
+    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    uniform_buf: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    color_format: wgpu::TextureFormat,
+    /// The brush the vertices are built for, and the vertices.
+    prepared: Option<(Brush, wgpu::Buffer, u32)>,
+}
+
+impl Context {
+    pub fn new(device: &wgpu::Device, global: &super::global::Context) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Brush"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(mem::size_of::<Locals>() as _),
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("brush"),
+            bind_group_layouts: &[&global.bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Brush"),
+            size: mem::size_of::<Locals>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Brush"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buf.as_entire_binding(),
+            }],
+        });
+        let pipeline = Self::create_pipeline(&pipeline_layout, global.color_format, device);
+
+        Context {
+            pipeline,
+            pipeline_layout,
+            uniform_buf,
+            bind_group,
+            color_format: global.color_format,
+            prepared: None,
+        }
+    }
+
+    fn create_pipeline(
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+    ) -> wgpu::RenderPipeline {
+        let shader = super::load_shader("brush", &[], device).unwrap();
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("brush"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "main_vs",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "main_fs",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device) {
+        self.pipeline = Self::create_pipeline(&self.pipeline_layout, self.color_format, device);
+    }
+
+    /// Follow the brush, rebuilding the cells only when it changes.
+    pub fn prepare(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        stats: &mut super::Stats,
+        level: &level::Level,
+        brush: Option<&Brush>,
+    ) {
+        let brush = match brush {
+            Some(brush) => brush,
+            None => {
+                self.prepared = None;
+                return;
+            }
+        };
+        if let Some((ref prepared, _, _)) = self.prepared {
+            if prepared == brush {
+                return;
+            }
+        }
+
+        let locals = Locals {
+            center_radius: [brush.center.x, brush.center.y, brush.radius, 0.0],
+            color: brush.color,
+        };
+        let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("temp-brush"),
+            contents: bytemuck::bytes_of(&locals),
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+        stats.add_buffer("temp-brush", mem::size_of::<Locals>());
+        encoder.copy_buffer_to_buffer(
+            &staging,
+            0,
+            &self.uniform_buf,
+            0,
+            mem::size_of::<Locals>() as wgpu::BufferAddress,
+        );
+        let vertices = build_cells(level, brush);
+        stats.add_buffer("brush", vertices.len() * mem::size_of::<[f32; 3]>());
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("brush"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        self.prepared = Some((*brush, buffer, vertices.len() as u32));
+    }
+
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        if let Some((_, ref buffer, count)) = self.prepared {
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(1, &self.bind_group, &[]);
+            pass.set_vertex_buffer(0, buffer.slice(..));
+            pass.draw(0..count, 0..1);
+        }
+    }
+}
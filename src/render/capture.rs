@@ -0,0 +1,43 @@
+//! Programmatic frame captures with the graphics debuggers.
+//!
+//! The capture goes through `wgpu::Device::start_capture`, which talks to
+//! the RenderDoc in-application API when the application runs under RenderDoc,
+//! or to Xcode on Metal. Without a debugger attached, it only logs a warning.
+//! A capture is requested ahead, from the UI or the command line, and then
+//! taken around the whole next frame, up to its submission.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Capture the next frame.
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Capture in progress, stopped when dropped.
+pub struct Capture<'a> {
+    device: &'a wgpu::Device,
+}
+
+impl Drop for Capture<'_> {
+    fn drop(&mut self) {
+        self.device.stop_capture();
+        info!("Frame capture is finished");
+    }
+}
+
+/// Start capturing the frame if it was requested.
+/// Keep the result alive until the frame commands are submitted.
+pub fn start(device: &wgpu::Device) -> Option<Capture<'_>> {
+    if !REQUESTED.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+    info!("Capturing the frame");
+    device.start_capture();
+    Some(Capture { device })
+}
@@ -13,99 +13,98 @@ use wgpu::util::DeviceExt as _;
 
 use std::{collections::HashMap, mem, num::NonZeroU64};
 
-const BLEND_FRONT: wgpu::BlendComponent = wgpu::BlendComponent::REPLACE;
-const BLEND_BEHIND: wgpu::BlendComponent = wgpu::BlendComponent {
-    src_factor: wgpu::BlendFactor::Constant,
-    dst_factor: wgpu::BlendFactor::OneMinusConstant,
-    operation: wgpu::BlendOperation::Add,
-};
-
+/// Whether the lines are hidden by the geometry in front of them.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 enum Visibility {
-    Front,
-    Behind,
-}
-type Selector = (Visibility, wgpu::VertexStepMode);
-
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-pub struct Position {
-    pub pos: [f32; 4],
+    DepthTested,
+    Overlay,
 }
-unsafe impl Pod for Position {}
-unsafe impl Zeroable for Position {}
 
+/// A line segment, drawn as a quad facing the screen.
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
-pub struct Color {
+pub struct Line {
+    pub from: [f32; 3],
+    pub to: [f32; 3],
+    /// Color as `0xRRGGBBAA`.
     pub color: u32,
 }
-unsafe impl Pod for Color {}
-unsafe impl Zeroable for Color {}
+unsafe impl Pod for Line {}
+unsafe impl Zeroable for Line {}
 
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct Locals {
     color: [f32; 4],
-    _pad: [f32; 60],
+    /// Screen width and height, line width in pixels, and 1 for the anti-aliasing.
+    line_params: [f32; 4],
+    _pad: [f32; 56],
 }
 unsafe impl Pod for Locals {}
 unsafe impl Zeroable for Locals {}
 
 impl Locals {
-    fn new(color: [f32; 4]) -> Self {
+    fn new(color: [f32; 4], extent: wgpu::Extent3d, lines: &settings::DebugLines) -> Self {
         Locals {
             color,
-            _pad: [0.0; 60],
+            line_params: [
+                extent.width as f32,
+                extent.height as f32,
+                lines.width,
+                if lines.antialias { 1.0 } else { 0.0 },
+            ],
+            _pad: [0.0; 56],
         }
     }
 }
 
 pub struct LineBuffer {
-    vertices: Vec<Position>,
-    colors: Vec<Color>,
+    lines: Vec<Line>,
+    overlay: Vec<Line>,
 }
 
 impl LineBuffer {
     pub fn new() -> Self {
         LineBuffer {
-            vertices: Vec::new(),
-            colors: Vec::new(),
+            lines: Vec::new(),
+            overlay: Vec::new(),
         }
     }
 
     pub fn clear(&mut self) {
-        self.vertices.clear();
-        self.colors.clear();
+        self.lines.clear();
+        self.overlay.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.overlay.is_empty()
     }
 
+    /// Add a line that is hidden behind the geometry.
     pub fn add(&mut self, from: [f32; 3], to: [f32; 3], color: u32) {
-        self.vertices.push(Position {
-            pos: [from[0], from[1], from[2], 1.0],
-        });
-        self.vertices.push(Position {
-            pos: [to[0], to[1], to[2], 1.0],
-        });
-        let color = Color { color };
-        self.colors.push(color);
-        self.colors.push(color);
+        self.lines.push(Line { from, to, color });
+    }
+
+    /// Add a line that shows through the geometry.
+    pub fn add_overlay(&mut self, from: [f32; 3], to: [f32; 3], color: u32) {
+        self.overlay.push(Line { from, to, color });
     }
 }
 
 pub struct Context {
     settings: settings::DebugRender,
     pipeline_layout: Result<wgpu::PipelineLayout, VertexStorageNotSupported>,
-    pipelines_line: HashMap<Selector, wgpu::RenderPipeline>,
+    pipelines_line: HashMap<Visibility, wgpu::RenderPipeline>,
     pipeline_face: Option<wgpu::RenderPipeline>,
     pipeline_edge: Option<wgpu::RenderPipeline>,
-    line_color_buf: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
     bind_group_line: wgpu::BindGroup,
     bind_group_face: wgpu::BindGroup,
     bind_group_edge: wgpu::BindGroup,
     color_format: wgpu::TextureFormat,
     // hold the buffers alive
-    vertex_buf: Option<wgpu::Buffer>,
-    color_buf: Option<wgpu::Buffer>,
+    line_buf: Option<wgpu::Buffer>,
+    overlay_buf: Option<wgpu::Buffer>,
 }
 
 impl Context {
@@ -114,6 +113,7 @@ impl Context {
         settings: &settings::DebugRender,
         global: &GlobalContext,
         object: &ObjectContext,
+        extent: wgpu::Extent3d,
     ) -> Self {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Debug"),
@@ -121,7 +121,7 @@ impl Context {
                 // locals
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -143,57 +143,8 @@ impl Context {
             Err(e) => Err(e),
         };
 
-        let line_color_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("debug-line-color"),
-            contents: bytemuck::bytes_of(&Color { color: 0xFF000080 }), // line
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let locals_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("debug-locals"),
-            contents: bytemuck::cast_slice(&[
-                Locals::new([1.0; 4]),             // line
-                Locals::new([0.0, 1.0, 0.0, 0.2]), // face
-                Locals::new([1.0, 1.0, 0.0, 0.2]), // edge
-            ]),
-            usage: wgpu::BufferUsages::UNIFORM,
-        });
-        let locals_size = mem::size_of::<Locals>() as wgpu::BufferAddress;
-        let bind_group_line = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Debug line"),
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &locals_buf,
-                    offset: 0 * locals_size,
-                    size: NonZeroU64::new(locals_size),
-                }),
-            }],
-        });
-        let bind_group_face = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Debug face"),
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &locals_buf,
-                    offset: 1 * locals_size,
-                    size: NonZeroU64::new(locals_size),
-                }),
-            }],
-        });
-        let bind_group_edge = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Debug edge"),
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &locals_buf,
-                    offset: 2 * locals_size,
-                    size: NonZeroU64::new(locals_size),
-                }),
-            }],
-        });
+        let [bind_group_line, bind_group_face, bind_group_edge] =
+            Self::create_bind_groups(device, &bind_group_layout, extent, &settings.lines);
 
         let mut result = Context {
             settings: *settings,
@@ -201,18 +152,64 @@ impl Context {
             pipelines_line: HashMap::new(),
             pipeline_face: None,
             pipeline_edge: None,
-            line_color_buf,
+            bind_group_layout,
             bind_group_line,
             bind_group_face,
             bind_group_edge,
             color_format: global.color_format,
-            vertex_buf: None,
-            color_buf: None,
+            line_buf: None,
+            overlay_buf: None,
         };
         result.reload(device);
         result
     }
 
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        extent: wgpu::Extent3d,
+        lines: &settings::DebugLines,
+    ) -> [wgpu::BindGroup; 3] {
+        let locals_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("debug-locals"),
+            contents: bytemuck::cast_slice(&[
+                Locals::new([1.0; 4], extent, lines),             // line
+                Locals::new([0.0, 1.0, 0.0, 0.2], extent, lines), // face
+                Locals::new([1.0, 1.0, 0.0, 0.2], extent, lines), // edge
+            ]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let locals_size = mem::size_of::<Locals>() as wgpu::BufferAddress;
+        let names = ["Debug line", "Debug face", "Debug edge"];
+        [0, 1, 2].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(names[i]),
+                layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &locals_buf,
+                        offset: i as wgpu::BufferAddress * locals_size,
+                        size: NonZeroU64::new(locals_size),
+                    }),
+                }],
+            })
+        })
+    }
+
+    /// Follow the new screen size, which the line widths are measured in.
+    pub fn resize(&mut self, extent: wgpu::Extent3d, device: &wgpu::Device) {
+        let [line, face, edge] = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            extent,
+            &self.settings.lines,
+        );
+        self.bind_group_line = line;
+        self.bind_group_face = face;
+        self.bind_group_edge = edge;
+    }
+
     pub fn reload(&mut self, device: &wgpu::Device) {
         let primitive = wgpu::PrimitiveState {
             topology: wgpu::PrimitiveTopology::TriangleStrip,
@@ -222,71 +219,54 @@ impl Context {
         };
 
         self.pipelines_line.clear();
-        if self.settings.impulses {
+        if self.settings.impulses || self.settings.suspension {
             let shader = super::load_shader("debug", &[], device).unwrap();
-            for &visibility in &[Visibility::Front, Visibility::Behind] {
-                let (blend, depth_write_enabled, depth_compare) = match visibility {
-                    Visibility::Front => (BLEND_FRONT, true, wgpu::CompareFunction::LessEqual),
-                    Visibility::Behind => (BLEND_BEHIND, false, wgpu::CompareFunction::Greater),
+            for &visibility in &[Visibility::DepthTested, Visibility::Overlay] {
+                let depth_compare = match visibility {
+                    Visibility::DepthTested => wgpu::CompareFunction::LessEqual,
+                    Visibility::Overlay => wgpu::CompareFunction::Always,
                 };
-                for &color_rate in &[wgpu::VertexStepMode::Vertex, wgpu::VertexStepMode::Instance] {
-                    let name = format!("debug-line-{:?}-{:?}", visibility, color_rate);
-                    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                        label: Some(&name),
-                        layout: match self.pipeline_layout {
-                            Ok(ref layout) => Some(layout),
-                            Err(_) => continue,
-                        },
-                        vertex: wgpu::VertexState {
-                            module: &shader,
-                            entry_point: "main_vs",
-                            buffers: &[
-                                wgpu::VertexBufferLayout {
-                                    array_stride: mem::size_of::<Position>() as wgpu::BufferAddress,
-                                    step_mode: wgpu::VertexStepMode::Vertex,
-                                    attributes: &[wgpu::VertexAttribute {
-                                        offset: 0,
-                                        format: wgpu::VertexFormat::Float32x4,
-                                        shader_location: 0,
-                                    }],
-                                },
-                                wgpu::VertexBufferLayout {
-                                    array_stride: mem::size_of::<Color>() as wgpu::BufferAddress,
-                                    step_mode: color_rate,
-                                    attributes: &[wgpu::VertexAttribute {
-                                        offset: 0,
-                                        format: wgpu::VertexFormat::Unorm8x4,
-                                        shader_location: 1,
-                                    }],
-                                },
+                let name = format!("debug-line-{:?}", visibility);
+                let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(&name),
+                    layout: match self.pipeline_layout {
+                        Ok(ref layout) => Some(layout),
+                        Err(_) => continue,
+                    },
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: "main_vs",
+                        buffers: &[wgpu::VertexBufferLayout {
+                            array_stride: mem::size_of::<Line>() as wgpu::BufferAddress,
+                            step_mode: wgpu::VertexStepMode::Instance,
+                            attributes: &wgpu::vertex_attr_array![
+                                0 => Float32x3,
+                                1 => Float32x3,
+                                2 => Unorm8x4,
                             ],
-                        },
-                        fragment: Some(wgpu::FragmentState {
-                            module: &shader,
-                            entry_point: "main_fs",
-                            targets: &[Some(wgpu::ColorTargetState {
-                                format: self.color_format,
-                                blend: Some(wgpu::BlendState {
-                                    color: blend,
-                                    alpha: blend,
-                                }),
-                                write_mask: wgpu::ColorWrites::all(),
-                            })],
-                        }),
-                        primitive,
-                        depth_stencil: Some(wgpu::DepthStencilState {
-                            format: DEPTH_FORMAT,
-                            depth_write_enabled,
-                            depth_compare,
-                            stencil: Default::default(),
-                            bias: Default::default(),
-                        }),
-                        multisample: wgpu::MultisampleState::default(),
-                        multiview: None,
-                    });
-                    self.pipelines_line
-                        .insert((visibility, color_rate), pipeline);
-                }
+                        }],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: "main_fs",
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: self.color_format,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::all(),
+                        })],
+                    }),
+                    primitive,
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: DEPTH_FORMAT,
+                        depth_write_enabled: false,
+                        depth_compare,
+                        stencil: Default::default(),
+                        bias: Default::default(),
+                    }),
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                });
+                self.pipelines_line.insert(visibility, pipeline);
             }
         }
     }
@@ -294,19 +274,17 @@ impl Context {
     fn draw_liner<'a>(
         &'a self,
         pass: &mut wgpu::RenderPass<'a>,
-        vertex_buf: &'a wgpu::Buffer,
-        color_buf: &'a wgpu::Buffer,
-        color_rate: wgpu::VertexStepMode,
-        num_vert: usize,
+        line_buf: &'a wgpu::Buffer,
+        visibility: Visibility,
+        num_lines: usize,
     ) {
-        pass.set_blend_constant(wgpu::Color::WHITE);
-        pass.set_vertex_buffer(0, vertex_buf.slice(..));
-        pass.set_vertex_buffer(1, color_buf.slice(..));
-        for &vis in &[Visibility::Front, Visibility::Behind] {
-            if let Some(pipeline) = self.pipelines_line.get(&(vis, color_rate)) {
-                pass.set_pipeline(pipeline);
-                pass.draw(0..num_vert as u32, 0..1);
-            }
+        if num_lines == 0 {
+            return;
+        }
+        if let Some(pipeline) = self.pipelines_line.get(&visibility) {
+            pass.set_pipeline(pipeline);
+            pass.set_vertex_buffer(0, line_buf.slice(..));
+            pass.draw(0..4, 0..num_lines as u32);
         }
     }
 
@@ -352,15 +330,9 @@ impl Context {
         }
 
         // draw sample normals
-        if let Some((ref sample_buf, num_vert)) = shape.sample_buf {
+        if let Some((ref sample_buf, num_lines)) = shape.sample_buf {
             pass.set_bind_group(1, &self.bind_group_line, &[]);
-            self.draw_liner(
-                pass,
-                sample_buf,
-                &self.line_color_buf,
-                wgpu::VertexStepMode::Instance,
-                num_vert,
-            );
+            self.draw_liner(pass, sample_buf, Visibility::DepthTested, num_lines);
         }
     }
 
@@ -370,28 +342,33 @@ impl Context {
         device: &wgpu::Device,
         linebuf: &LineBuffer,
     ) {
-        self.vertex_buf = Some(
+        self.line_buf = Some(
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("debug-vertices"),
-                contents: bytemuck::cast_slice(&linebuf.vertices),
+                label: Some("debug-lines"),
+                contents: bytemuck::cast_slice(&linebuf.lines),
                 usage: wgpu::BufferUsages::VERTEX,
             }),
         );
-        self.color_buf = Some(
+        self.overlay_buf = Some(
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("debug-colors"),
-                contents: bytemuck::cast_slice(&linebuf.colors),
+                label: Some("debug-overlay-lines"),
+                contents: bytemuck::cast_slice(&linebuf.overlay),
                 usage: wgpu::BufferUsages::VERTEX,
             }),
         );
-        assert_eq!(linebuf.vertices.len(), linebuf.colors.len());
 
+        pass.set_bind_group(1, &self.bind_group_line, &[]);
+        self.draw_liner(
+            pass,
+            self.line_buf.as_ref().unwrap(),
+            Visibility::DepthTested,
+            linebuf.lines.len(),
+        );
         self.draw_liner(
             pass,
-            self.vertex_buf.as_ref().unwrap(),
-            self.color_buf.as_ref().unwrap(),
-            wgpu::VertexStepMode::Vertex,
-            linebuf.vertices.len(),
+            self.overlay_buf.as_ref().unwrap(),
+            Visibility::Overlay,
+            linebuf.overlay.len(),
         );
     }
 }
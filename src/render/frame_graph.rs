@@ -0,0 +1,115 @@
+//! Recording of the GPU work of a frame: the passes, the dispatches, and the uploads.
+//!
+//! The recording is in JSON, one event per line, so that the frames
+//! of different renderer versions can be diffed mechanically.
+
+use std::{cell::RefCell, fmt::Write as _};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Pass { label: String, compute: bool },
+    Dispatch { groups: [u32; 3] },
+    Upload { label: String, size: usize },
+}
+
+thread_local! {
+    static RECORDING: RefCell<Option<Vec<Event>>> = const { RefCell::new(None) };
+}
+
+/// Start recording the events of the current thread.
+pub fn start() {
+    RECORDING.with(|r| *r.borrow_mut() = Some(Vec::new()));
+}
+
+/// Stop the recording, returning the events since `start`.
+pub fn finish() -> Option<Vec<Event>> {
+    RECORDING.with(|r| r.borrow_mut().take())
+}
+
+/// Record an event, only constructing it if the recording is on.
+fn record(event: impl FnOnce() -> Event) {
+    RECORDING.with(|r| {
+        if let Some(ref mut events) = *r.borrow_mut() {
+            events.push(event());
+        }
+    });
+}
+
+pub(super) fn upload(label: &str, size: usize) {
+    record(|| Event::Upload {
+        label: label.to_string(),
+        size,
+    });
+}
+
+pub(super) fn begin_render_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    desc: &wgpu::RenderPassDescriptor<'a, '_>,
+) -> wgpu::RenderPass<'a> {
+    record(|| Event::Pass {
+        label: desc.label.unwrap_or_default().to_string(),
+        compute: false,
+    });
+    encoder.begin_render_pass(desc)
+}
+
+pub(super) fn begin_compute_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    desc: &wgpu::ComputePassDescriptor<'_>,
+) -> wgpu::ComputePass<'a> {
+    record(|| Event::Pass {
+        label: desc.label.unwrap_or_default().to_string(),
+        compute: true,
+    });
+    encoder.begin_compute_pass(desc)
+}
+
+pub(super) fn dispatch(pass: &mut wgpu::ComputePass<'_>, groups: [u32; 3]) {
+    record(|| Event::Dispatch { groups });
+    pass.dispatch_workgroups(groups[0], groups[1], groups[2]);
+}
+
+fn escape(string: &str) -> String {
+    let mut out = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Format the events as a JSON array.
+pub fn to_json(events: &[Event]) -> String {
+    let mut out = String::from("[\n");
+    for (i, event) in events.iter().enumerate() {
+        out.push_str("  ");
+        let _ = match *event {
+            Event::Pass { ref label, compute } => write!(
+                out,
+                r#"{{"type": "pass", "label": "{}", "compute": {}}}"#,
+                escape(label),
+                compute
+            ),
+            Event::Dispatch { groups } => write!(
+                out,
+                r#"{{"type": "dispatch", "groups": [{}, {}, {}]}}"#,
+                groups[0], groups[1], groups[2]
+            ),
+            Event::Upload { ref label, size } => write!(
+                out,
+                r#"{{"type": "upload", "label": "{}", "size": {}}}"#,
+                escape(label),
+                size
+            ),
+        };
+        out.push_str(if i + 1 < events.len() { ",\n" } else { "\n" });
+    }
+    out.push(']');
+    out
+}
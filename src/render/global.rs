@@ -3,7 +3,7 @@ use bytemuck::{Pod, Zeroable};
 use std::mem;
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct Constants {
     camera_pos: [f32; 4],
     m_vp: [[f32; 4]; 4],
@@ -12,19 +12,28 @@ pub struct Constants {
     light_pos: [f32; 4],
     light_color: [f32; 3],
     pad: f32,
+    origin: [f32; 4],
 }
 unsafe impl Pod for Constants {}
 unsafe impl Zeroable for Constants {}
 
 impl Constants {
-    pub fn new(cam: &Camera, light: &settings::Light, shadow_cam: Option<&Camera>) -> Self {
+    /// Constants of the pass seen by `cam`. All the matrices take the positions
+    /// relative to the `origin`, which is the position of the main camera,
+    /// and also the one the object instances are relative to.
+    pub fn new(
+        cam: &Camera,
+        origin: cgmath::Vector3<f32>,
+        light: &settings::Light,
+        shadow_cam: Option<&Camera>,
+    ) -> Self {
         use cgmath::{SquareMatrix, Zero};
 
         //Note: zero matrix is handled specially in `fetch_shadow` shader
         let m_light_vp = shadow_cam
-            .map_or_else(cgmath::Matrix4::zero, |sc| sc.get_view_proj())
+            .map_or_else(cgmath::Matrix4::zero, |sc| sc.get_view_proj_from(origin))
             .into();
-        let mx_vp = cam.get_view_proj();
+        let mx_vp = cam.get_view_proj_from(origin);
         Constants {
             camera_pos: cam.loc.extend(1.0).into(),
             m_vp: mx_vp.into(),
@@ -33,6 +42,7 @@ impl Constants {
             light_pos: light.pos,
             light_color: light.color,
             pad: 1.0,
+            origin: origin.extend(1.0).into(),
         }
     }
 }
@@ -101,7 +111,7 @@ impl Context {
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
         let shadow_sampler = gfx.device.create_sampler(&wgpu::SamplerDescriptor {
@@ -0,0 +1,218 @@
+//! Estimates of the GPU memory taken by the renderer, per subsystem.
+//!
+//! The sizable long-lived buffers and textures are accounted in an
+//! `Allocation` owned next to them, which gives the memory back when dropped. The drivers
+//! don't tell how much memory there is, so the usage is compared against
+//! the configured budget, and the single allocations against the limits
+//! of the device, warning before the driver runs out of memory on its own.
+
+use crate::locale::{tr, tr_fmt};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Subsystem {
+    /// Height and meta textures of the level, and the terrain buffers.
+    Terrain,
+    /// Voxel grid of the voxel traced terrain.
+    Voxels,
+    /// Vertices of the models and of their collision shapes.
+    Meshes,
+    /// Instance buffers of the objects drawn in a frame.
+    Instances,
+    Shadow,
+    Water,
+    Particles,
+    /// Palettes, and the offscreen targets of the palette output.
+    Other,
+}
+
+impl Subsystem {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Terrain => "Terrain",
+            Self::Voxels => "Voxels",
+            Self::Meshes => "Meshes",
+            Self::Instances => "Instances",
+            Self::Shadow => "Shadow",
+            Self::Water => "Water",
+            Self::Particles => "Particles",
+            Self::Other => "Other",
+        }
+    }
+}
+
+static USAGE: [AtomicU64; 8] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Bytes currently allocated by the subsystem, over all the devices.
+pub fn usage(subsystem: Subsystem) -> u64 {
+    USAGE[subsystem as usize].load(Ordering::Relaxed)
+}
+
+/// Estimate the size of the texture with all its mips and layers.
+pub fn texture_size(desc: &wgpu::TextureDescriptor<'_>) -> u64 {
+    let block_size = desc.format.block_size(None).unwrap_or(4) as u64;
+    let (block_width, block_height) = desc.format.block_dimensions();
+    (0..desc.mip_level_count)
+        .filter_map(|level| desc.mip_level_size(level))
+        .map(|size| {
+            let blocks_x = size.width.div_ceil(block_width);
+            let blocks_y = size.height.div_ceil(block_height);
+            blocks_x as u64 * blocks_y as u64 * size.depth_or_array_layers as u64 * block_size
+        })
+        .sum::<u64>()
+        * desc.sample_count as u64
+}
+
+/// GPU memory taken by the resources of a subsystem, given back on drop.
+#[derive(Debug)]
+pub struct Allocation {
+    subsystem: Subsystem,
+    size: u64,
+}
+
+impl Allocation {
+    pub fn new(subsystem: Subsystem) -> Self {
+        Allocation { subsystem, size: 0 }
+    }
+
+    pub fn subsystem(&self) -> Subsystem {
+        self.subsystem
+    }
+
+    /// Total size of the resources accounted here.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn add(&mut self, size: u64) {
+        self.size += size;
+        USAGE[self.subsystem as usize].fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Account a buffer, warning if it's larger than the device allows.
+    pub fn add_buffer(&mut self, device: &wgpu::Device, label: &str, size: wgpu::BufferAddress) {
+        let limit = device.limits().max_buffer_size;
+        if size > limit {
+            warn!(
+                "Buffer '{}' of {} takes {} MB, over the limit of {} MB",
+                label,
+                self.subsystem.name(),
+                size >> 20,
+                limit >> 20
+            );
+        }
+        self.add(size);
+    }
+
+    /// Account a texture, warning if it's larger than the device allows.
+    pub fn add_texture(&mut self, device: &wgpu::Device, desc: &wgpu::TextureDescriptor<'_>) {
+        let limits = device.limits();
+        let limit = match desc.dimension {
+            wgpu::TextureDimension::D1 => limits.max_texture_dimension_1d,
+            wgpu::TextureDimension::D2 => limits.max_texture_dimension_2d,
+            wgpu::TextureDimension::D3 => limits.max_texture_dimension_3d,
+        };
+        if desc.size.width.max(desc.size.height) > limit {
+            warn!(
+                "Texture '{}' of {} is {}x{}, over the limit of {}",
+                desc.label.unwrap_or_default(),
+                self.subsystem.name(),
+                desc.size.width,
+                desc.size.height,
+                limit
+            );
+        }
+        self.add(texture_size(desc));
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        USAGE[self.subsystem as usize].fetch_sub(self.size, Ordering::Relaxed);
+    }
+}
+
+/// Estimated GPU memory usage by subsystem, in bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Report {
+    pub terrain: u64,
+    pub voxels: u64,
+    pub meshes: u64,
+    pub instances: u64,
+    pub shadow: u64,
+    pub water: u64,
+    pub particles: u64,
+    pub other: u64,
+    pub total: u64,
+    /// Configured budget, zero if there is none.
+    pub budget: u64,
+}
+
+impl Report {
+    /// Collect the current usage, to compare with the budget in bytes.
+    pub fn current(budget: u64) -> Self {
+        let mut report = Report {
+            terrain: usage(Subsystem::Terrain),
+            voxels: usage(Subsystem::Voxels),
+            meshes: usage(Subsystem::Meshes),
+            instances: usage(Subsystem::Instances),
+            shadow: usage(Subsystem::Shadow),
+            water: usage(Subsystem::Water),
+            particles: usage(Subsystem::Particles),
+            other: usage(Subsystem::Other),
+            total: 0,
+            budget,
+        };
+        report.total = report.subsystems().iter().map(|&(_, size)| size).sum();
+        report
+    }
+
+    pub fn subsystems(&self) -> [(Subsystem, u64); 8] {
+        [
+            (Subsystem::Terrain, self.terrain),
+            (Subsystem::Voxels, self.voxels),
+            (Subsystem::Meshes, self.meshes),
+            (Subsystem::Instances, self.instances),
+            (Subsystem::Shadow, self.shadow),
+            (Subsystem::Water, self.water),
+            (Subsystem::Particles, self.particles),
+            (Subsystem::Other, self.other),
+        ]
+    }
+
+    pub fn is_over_budget(&self) -> bool {
+        self.budget != 0 && self.total > self.budget
+    }
+
+    pub fn draw_ui(&self, ui: &mut egui::Ui) {
+        for (subsystem, size) in self.subsystems() {
+            if size != 0 {
+                ui.label(tr_fmt("{}: {} KB", &[&tr(subsystem.name()), &(size >> 10)]));
+            }
+        }
+        if self.budget == 0 {
+            ui.label(tr_fmt("Total: {} MB", &[&(self.total >> 20)]));
+        } else {
+            let text = tr_fmt(
+                "Total: {} MB of {} MB",
+                &[&(self.total >> 20), &(self.budget >> 20)],
+            );
+            if self.is_over_budget() {
+                ui.colored_label(egui::Color32::RED, text);
+            } else {
+                ui.label(text);
+            }
+        }
+    }
+}
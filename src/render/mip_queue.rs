@@ -0,0 +1,106 @@
+//! Rebuild of the voxel grid mips, spread over the frames.
+//!
+//! Every baked region needs the mips of all the coarser LODs rebuilt, one dispatch
+//! per region and LOD. The queue keeps the regions that are not done yet, and picks
+//! the dispatches of each frame within a budget, finer LODs first, so that each one
+//! reads the up to date LOD below it. The rays skip the LODs that are still pending.
+
+/// Region of the voxel grid that has its mips rebuilt over the frames.
+struct PendingMip<R> {
+    region: R,
+    /// The LODs before this one are up to date.
+    next_lod: u32,
+}
+
+/// Mip dispatches of a frame.
+pub struct MipFrame<R> {
+    /// Regions of the dispatches, one per update slot.
+    pub regions: Vec<R>,
+    /// Slot of the region and the destination LOD of each dispatch, in order.
+    pub dispatches: Vec<(usize, u32)>,
+}
+
+pub struct MipQueue<R> {
+    lod_count: u32,
+    /// Dispatches per frame at most, 0 for no limit.
+    max_dispatches: u32,
+    pending: Vec<PendingMip<R>>,
+}
+
+impl<R: Copy> MipQueue<R> {
+    pub fn new(lod_count: u32, max_dispatches: u32) -> Self {
+        MipQueue {
+            lod_count,
+            max_dispatches,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Number of the regions waiting for their mips.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Queue the mips of a newly baked region.
+    pub fn push(&mut self, region: R) {
+        if self.lod_count > 1 {
+            self.pending.push(PendingMip {
+                region,
+                next_lod: 1,
+            });
+        }
+    }
+
+    /// Number of the finest LODs that have no mips waiting to be rebuilt.
+    pub fn ready_lod_count(&self) -> u32 {
+        self.pending
+            .iter()
+            .map(|pending| pending.next_lod)
+            .min()
+            .unwrap_or(self.lod_count)
+    }
+
+    /// Pick the dispatches of this frame, using at most `max_slots` regions,
+    /// and forget the regions that get all their mips.
+    pub fn schedule(&mut self, max_slots: usize) -> MipFrame<R> {
+        let mut budget = match self.max_dispatches {
+            0 => usize::MAX,
+            count => count as usize,
+        };
+        let mut slots = Vec::<usize>::new();
+        let mut dispatches = Vec::new();
+        for dst_lod in 1..self.lod_count {
+            for (index, pending) in self.pending.iter_mut().enumerate() {
+                if pending.next_lod != dst_lod || budget == 0 {
+                    continue;
+                }
+                let slot = match slots.iter().position(|&i| i == index) {
+                    Some(slot) => slot,
+                    None if slots.len() < max_slots => {
+                        slots.push(index);
+                        slots.len() - 1
+                    }
+                    None => continue,
+                };
+                dispatches.push((slot, dst_lod));
+                pending.next_lod += 1;
+                budget -= 1;
+            }
+        }
+
+        let regions = slots
+            .iter()
+            .map(|&index| self.pending[index].region)
+            .collect();
+        let lod_count = self.lod_count;
+        self.pending.retain(|pending| pending.next_lod < lod_count);
+        MipFrame {
+            regions,
+            dispatches,
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{
     config::settings,
-    level, model,
+    level,
+    locale::{tr, tr_fmt},
+    model,
     space::{Camera, Transform},
 };
 
@@ -8,23 +10,36 @@ use bytemuck::{Pod, Zeroable};
 use wgpu::util::DeviceExt as _;
 
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap},
     fs::File,
-    io::{BufReader, Error as IoError, Read},
+    io::{BufReader, Error as IoError, ErrorKind, Read},
     mem,
     ops::Range,
     path::PathBuf,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
+pub mod brush;
+pub mod capture;
 pub mod debug;
+pub mod frame_graph;
 pub mod global;
+pub mod memory;
+pub mod mip_queue;
+pub mod mipmap;
 pub mod object;
+pub mod palettize;
+pub mod particle;
 mod shadow;
+pub mod snapshot;
+pub mod streaming;
 pub mod terrain;
 mod water;
 
-pub use shadow::FORMAT as SHADOW_FORMAT;
+pub use shadow::{Shadow, FORMAT as SHADOW_FORMAT};
 pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
 #[derive(Clone, Copy, Debug)]
@@ -40,17 +55,55 @@ impl GpuTransform {
     pub fn new(t: &Transform) -> Self {
         GpuTransform {
             pos_scale: [t.disp.x, t.disp.y, t.disp.z, t.scale],
-            orientation: [t.rot.v.x, t.rot.v.y, t.rot.v.z, t.rot.s],
+            orientation: crate::space::coords::quat_to_xyzw(t.rot),
         }
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct ScreenTargets<'a> {
     pub extent: wgpu::Extent3d,
     pub color: &'a wgpu::TextureView,
     pub depth: &'a wgpu::TextureView,
 }
 
+/// Per-frame counters of the work submitted by `Render::draw_world`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub terrain_dispatches: u32,
+    pub object_draw_calls: u32,
+    pub object_instances: u32,
+    /// Number of buffers created for the frame, including staging.
+    pub buffers_allocated: u32,
+    pub buffer_memory: u64,
+    pub dirty_rects: u32,
+}
+
+impl Stats {
+    fn add_buffer(&mut self, label: &str, size: usize) {
+        frame_graph::upload(label, size);
+        self.buffers_allocated += 1;
+        self.buffer_memory += size as u64;
+    }
+
+    pub fn draw_ui(&self, ui: &mut egui::Ui) {
+        ui.label(tr_fmt(
+            "Terrain dispatches: {}",
+            &[&self.terrain_dispatches],
+        ));
+        ui.label(tr_fmt(
+            "Objects: {} draws, {} instances",
+            &[&self.object_draw_calls, &self.object_instances],
+        ));
+        ui.label(tr_fmt(
+            "Buffers: {} allocated, {} KB",
+            &[&self.buffers_allocated, &(self.buffer_memory >> 10)],
+        ));
+        ui.label(tr_fmt("Dirty rects: {}", &[&self.dirty_rects]));
+    }
+}
+
 pub struct SurfaceData {
     pub constants: wgpu::Buffer,
     pub height: (wgpu::TextureView, wgpu::Sampler),
@@ -100,7 +153,10 @@ pub fn make_shader_code(name: &str, substitutions: &[(&str, String)]) -> Result<
     let base_path = PathBuf::from("res").join("shader");
     let path = base_path.join(name).with_extension("wgsl");
     if !path.is_file() {
-        panic!("Shader not found: {:?}", path);
+        return Err(IoError::new(
+            ErrorKind::NotFound,
+            format!("Shader not found: {:?}", path),
+        ));
     }
 
     let mut source = String::new();
@@ -109,13 +165,18 @@ pub fn make_shader_code(name: &str, substitutions: &[(&str, String)]) -> Result<
     // parse meta-data
     {
         let mut lines = source.lines();
-        let first = lines.next().unwrap();
+        let first = lines.next().unwrap_or_default();
         if first.starts_with("//!include") {
             for include in first.split_whitespace().skip(1) {
                 let inc_path = base_path.join(include).with_extension("inc.wgsl");
                 match File::open(&inc_path) {
                     Ok(include) => BufReader::new(include).read_to_string(&mut buf)?,
-                    Err(e) => panic!("Unable to include {:?}: {:?}", inc_path, e),
+                    Err(e) => {
+                        return Err(IoError::new(
+                            e.kind(),
+                            format!("Unable to include {:?}: {}", inc_path, e),
+                        ))
+                    }
                 };
             }
         }
@@ -138,49 +199,134 @@ pub fn load_shader(
 
     let code = make_shader_code(name, substitutions)?;
     debug!("shader '{}':\n{}", name, code);
+    crate::crash::record_shader(name, &code);
     if cfg!(debug_assertions) {
-        std::fs::write("last-shader.wgsl", &code).unwrap();
+        if let Err(e) = std::fs::write("last-shader.wgsl", &code) {
+            warn!("Unable to dump the shader: {}", e);
+        }
     }
 
+    // The error scopes of the device are shared by all the threads, and the worlds
+    // are loaded in the background, so the code is validated here instead.
+    let invalid =
+        |e: String| IoError::new(ErrorKind::InvalidData, format!("Shader '{}': {}", name, e));
+    let module =
+        naga::front::wgsl::parse_str(&code).map_err(|e| invalid(e.emit_to_string(&code)))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .map_err(|e| invalid(e.emit_to_string(&code)))?;
+
     Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: Some(name),
         source: wgpu::ShaderSource::Wgsl(code.into()),
     }))
 }
 
+/// All the shader programs, as named for `load_shader`.
+const SHADERS: &[&str] = &[
+    "object",
+    "water",
+    "debug",
+    "brush",
+    "particle",
+    "terrain/ray",
+    "terrain/voxel-bake",
+    "terrain/voxel-draw",
+    "terrain/slice",
+    "terrain/paint",
+    "terrain/scatter",
+    "mipmap",
+    "palettize",
+];
+
+/// Build all the shaders without using them, reporting the first failure.
+/// Used to avoid tearing down working pipelines with broken shader code.
+pub fn check_shaders(device: &wgpu::Device) -> Result<(), IoError> {
+    // voxel shaders expect a tile size, any valid one would do here
+    let substitutions = [("morton_tile_size", "1u".to_string())];
+    for name in SHADERS {
+        load_shader(name, &substitutions, device)
+            .map_err(|e| IoError::new(e.kind(), format!("{}: {}", name, e)))?;
+    }
+    Ok(())
+}
+
+/// Number of palettes that can be bound at once, selected per draw.
+pub const MAX_PALETTES: u32 = 4;
+const PALETTE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// Array of palettes, each being a 256x1 layer.
 pub struct Palette {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
+    _memory: memory::Allocation,
 }
 
 impl Palette {
-    pub fn new(device: &wgpu::Device) -> Self {
+    /// Create the palette array. With more than one mip level,
+    /// the levels past the first need to be filled with `mipmap::Generator`.
+    pub fn new(device: &wgpu::Device, mip_level_count: u32) -> Self {
         profiling::scope!("Create Palette");
         let extent = wgpu::Extent3d {
             width: 0x100,
             height: 1,
-            depth_or_array_layers: 1,
+            depth_or_array_layers: MAX_PALETTES,
         };
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
+        let desc = wgpu::TextureDescriptor {
             label: Some("Palette"),
             size: extent,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
-            dimension: wgpu::TextureDimension::D1,
-            format: wgpu::TextureFormat::Rgba8Unorm,
+            dimension: wgpu::TextureDimension::D2,
+            format: PALETTE_FORMAT,
             view_formats: &[],
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-        });
+            usage: if mip_level_count > 1 {
+                wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT
+            } else {
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST
+            },
+        };
+        let mut memory = memory::Allocation::new(memory::Subsystem::Other);
+        memory.add_texture(device, &desc);
+        let texture = device.create_texture(&desc);
 
         Palette {
-            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            _memory: memory,
+            view: texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            }),
             texture,
         }
     }
 
-    pub fn init(&self, queue: &wgpu::Queue, data: &[[u8; 4]]) {
+    pub fn create_mip_generator(&self, device: &wgpu::Device) -> mipmap::Generator {
+        mipmap::Generator::new(device, PALETTE_FORMAT)
+    }
+
+    /// Upload the colors of the palette at the given layer.
+    pub fn init(&self, queue: &wgpu::Queue, layer: u32, data: &[[u8; 4]]) {
+        assert!(
+            layer < MAX_PALETTES,
+            "Palette layer {} is out of range",
+            layer
+        );
         queue.write_texture(
-            self.texture.as_image_copy(),
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
             bytemuck::cast_slice(data),
             wgpu::ImageDataLayout::default(),
             wgpu::Extent3d {
@@ -194,30 +340,194 @@ impl Palette {
 
 struct InstanceArray {
     data: Vec<object::Instance>,
-    // holding the mesh alive, while the key is just a raw pointer
+    // holding the mesh alive, while the key is just its address
     mesh: Arc<model::Mesh>,
     // actual hardware buffer for this data
-    buffer: Option<wgpu::Buffer>,
+    buffer: Option<(wgpu::Buffer, memory::Allocation)>,
+}
+
+/// Wrapping of the world along X and Y, as seen from a point.
+#[derive(Clone, Copy, Debug)]
+pub struct WorldWrap {
+    pub size: cgmath::Vector2<f32>,
+    /// Center of the visible area, typically the camera position.
+    pub center: cgmath::Vector2<f32>,
+}
+
+impl WorldWrap {
+    pub fn new(level: &level::Level, cam: &Camera) -> Self {
+        WorldWrap {
+            size: cgmath::vec2(level.size.0 as f32, level.size.1 as f32),
+            center: cam.loc.truncate(),
+        }
+    }
+
+    /// Position of the copy that is the closest to the center.
+    pub fn nearest(&self, pos: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        let offset = pos.truncate() - self.center;
+        cgmath::vec3(
+            pos.x - self.size.x * (offset.x / self.size.x).round(),
+            pos.y - self.size.y * (offset.y / self.size.y).round(),
+            pos.z,
+        )
+    }
+
+    /// Positions of the copies that are visible from the center,
+    /// assuming the object radius. There are more than one if it crosses the seam.
+    pub fn copies(&self, pos: cgmath::Vector3<f32>, radius: f32) -> Vec<cgmath::Vector3<f32>> {
+        let base = self.nearest(pos);
+        let offset = base.truncate() - self.center;
+        let shift = |offset: f32, size: f32| -> Option<f32> {
+            if offset > 0.5 * size - radius {
+                Some(-size)
+            } else if offset < radius - 0.5 * size {
+                Some(size)
+            } else {
+                None
+            }
+        };
+        let xs = [Some(0.0), shift(offset.x, self.size.x)];
+        let ys = [Some(0.0), shift(offset.y, self.size.y)];
+        let mut copies = Vec::with_capacity(4);
+        for dx in xs.iter().flatten() {
+            for dy in ys.iter().flatten() {
+                copies.push(base + cgmath::vec3(*dx, *dy, 0.0));
+            }
+        }
+        copies
+    }
+}
+
+/// Kind of an object, for the purpose of the draw distance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    Vehicle,
+    Debris,
+    Prop,
+}
+
+/// Fading out and culling of the objects by their distance to a point,
+/// typically the camera position.
+#[derive(Clone, Copy, Debug)]
+pub struct DistanceCull {
+    pub origin: cgmath::Vector3<f32>,
+    pub distance: settings::DrawDistance,
+}
+
+impl DistanceCull {
+    /// Opacity of an object of the given radius, or `None` if it's too far to be drawn.
+    pub fn fade(&self, category: Category, pos: cgmath::Vector3<f32>, radius: f32) -> Option<f32> {
+        use cgmath::InnerSpace as _;
+
+        let limit = match category {
+            Category::Vehicle => self.distance.vehicles,
+            Category::Debris => self.distance.debris,
+            Category::Prop => self.distance.props,
+        };
+        if limit <= 0.0 {
+            return Some(1.0);
+        }
+        let distance = (pos - self.origin).magnitude() - radius;
+        if distance >= limit {
+            None
+        } else if self.distance.fade > 0.0 {
+            Some(((limit - distance) / self.distance.fade).min(1.0))
+        } else {
+            Some(1.0)
+        }
+    }
+
+    /// Opacity of an object the origin is close to, so that it doesn't block the view.
+    /// The object starts fading at twice its radius and reaches `NEAR_ALPHA` inside it.
+    pub fn near_alpha(&self, pos: cgmath::Vector3<f32>, radius: f32) -> f32 {
+        use cgmath::InnerSpace as _;
+
+        if radius <= 0.0 {
+            return 1.0;
+        }
+        let ratio = ((pos - self.origin).magnitude() / radius - 1.0).clamp(0.0, 1.0);
+        NEAR_ALPHA + (1.0 - NEAR_ALPHA) * ratio
+    }
+}
+
+/// Lowest opacity of the objects close to the camera, see `DistanceCull::near_alpha`.
+pub const NEAR_ALPHA: f32 = 0.3;
+
+/// Sort the instances back to front, as seen from the origin they are relative to.
+pub fn sort_back_to_front<M>(instances: &mut [(M, object::Instance)]) {
+    instances.sort_by(|(_, a), (_, b)| {
+        let distance = |i: &object::Instance| {
+            let [x, y, z, _] = i.pos_scale;
+            x * x + y * y + z * z
+        };
+        distance(b).total_cmp(&distance(a))
+    });
+}
+
+/// Ranges of the consecutive instances of the same mesh, each drawn with one call.
+pub fn mesh_runs<'a, M>(
+    instances: &'a [(M, object::Instance)],
+    same_mesh: impl Fn(&M, &M) -> bool + 'a,
+) -> impl Iterator<Item = Range<usize>> + 'a {
+    let mut start = 0;
+    (1..=instances.len()).filter_map(move |end| {
+        if end < instances.len() && same_mesh(&instances[end].0, &instances[start].0) {
+            None
+        } else {
+            let run = start..end;
+            start = end;
+            Some(run)
+        }
+    })
 }
 
 pub struct Batcher {
-    instances: HashMap<*const model::Mesh, InstanceArray>,
+    /// Opaque instances per mesh, and whether they are fading out.
+    instances: HashMap<(usize, bool), InstanceArray>,
+    /// Instances made transparent with `Instance::with_alpha`,
+    /// sorted back to front by `prepare`.
+    transparent: Vec<(Arc<model::Mesh>, object::Instance)>,
+    transparent_buffer: Option<(wgpu::Buffer, memory::Allocation)>,
     debug_shapes: Vec<Arc<model::Shape>>,
     debug_instances: Vec<object::Instance>,
+    /// If set, the models are placed according to the world wrapping.
+    pub wrap: Option<WorldWrap>,
+    /// If set, the models are faded out with the distance, and culled past it.
+    pub cull: Option<DistanceCull>,
+    /// Opacity of the models added with `add_model`, see `Instance::with_alpha`.
+    pub alpha: f32,
 }
 
 impl Batcher {
     pub fn new() -> Self {
         Batcher {
             instances: HashMap::new(),
+            transparent: Vec::new(),
+            transparent_buffer: None,
             debug_shapes: Vec::new(),
             debug_instances: Vec::new(),
+            wrap: None,
+            cull: None,
+            alpha: 1.0,
+        }
+    }
+
+    /// Opacity of an object, or `None` if it's culled by the distance.
+    pub fn fade(&self, category: Category, pos: cgmath::Vector3<f32>, radius: f32) -> Option<f32> {
+        match self.cull {
+            Some(ref cull) => cull.fade(category, pos, radius),
+            None => Some(1.0),
         }
     }
 
     pub fn add_mesh(&mut self, mesh: &Arc<model::Mesh>, instance: object::Instance) {
+        if instance.is_transparent() {
+            self.transparent.push((Arc::clone(mesh), instance));
+            return;
+        }
+        let is_faded = instance.fade < 1.0;
         self.instances
-            .entry(&**mesh)
+            .entry((Arc::as_ptr(mesh) as usize, is_faded))
             .or_insert_with(|| InstanceArray {
                 data: Vec::new(),
                 mesh: Arc::clone(mesh),
@@ -233,13 +543,44 @@ impl Batcher {
         base_transform: &Transform,
         debug_shape_scale: Option<f32>,
         color: object::BodyColor,
+    ) {
+        match self.wrap {
+            Some(wrap) => {
+                let radius = model.body.bbox.radius * base_transform.scale;
+                for disp in wrap.copies(base_transform.disp, radius) {
+                    let transform = Transform {
+                        disp,
+                        ..*base_transform
+                    };
+                    self.add_model_copy(model, &transform, debug_shape_scale, color);
+                }
+            }
+            None => self.add_model_copy(model, base_transform, debug_shape_scale, color),
+        }
+    }
+
+    fn add_model_copy(
+        &mut self,
+        model: &model::VisualModel,
+        base_transform: &Transform,
+        debug_shape_scale: Option<f32>,
+        color: object::BodyColor,
     ) {
         use cgmath::{One as _, Rotation3 as _, Transform as _};
 
+        let radius = model.body.bbox.radius * base_transform.scale;
+        let fade = match self.fade(Category::Vehicle, base_transform.disp, radius) {
+            Some(fade) => fade,
+            None => return,
+        };
+        let alpha = self.alpha;
+
         // body
         self.add_mesh(
             &model.body,
-            object::Instance::new(base_transform, 0.0, color as u8),
+            object::Instance::new(base_transform, 0.0, color as u8)
+                .with_fade(fade)
+                .with_alpha(alpha),
         );
         if let Some(shape_scale) = debug_shape_scale {
             self.debug_shapes.push(Arc::clone(&model.shape));
@@ -258,7 +599,8 @@ impl Batcher {
                     rot: cgmath::Quaternion::one(),
                     scale: 1.0,
                 });
-                self.add_mesh(mesh, object::Instance::new(&transform, 0.0, color as u8));
+                let instance = object::Instance::new(&transform, 0.0, color as u8);
+                self.add_mesh(mesh, instance.with_fade(fade).with_alpha(alpha));
             }
         }
 
@@ -272,32 +614,158 @@ impl Batcher {
                 };
                 local.disp -= local.transform_vector(cgmath::Vector3::from(mesh.offset));
                 let transform = base_transform.concat(&local);
-                self.add_mesh(mesh, object::Instance::new(&transform, 0.0, color as u8));
+                let instance = object::Instance::new(&transform, 0.0, color as u8);
+                self.add_mesh(mesh, instance.with_fade(fade).with_alpha(alpha));
+            }
+        }
+    }
+
+    /// Add a piece of the model that broke off.
+    pub fn add_debrie(
+        &mut self,
+        mesh: &Arc<model::Mesh>,
+        transform: &Transform,
+        color: object::BodyColor,
+    ) {
+        let radius = mesh.bbox.radius * transform.scale;
+        let copies = match self.wrap {
+            Some(wrap) => wrap.copies(transform.disp, radius),
+            None => vec![transform.disp],
+        };
+        for disp in copies {
+            if let Some(fade) = self.fade(Category::Debris, disp, radius) {
+                let copy = Transform { disp, ..*transform };
+                let instance = object::Instance::new(&copy, 0.0, color as u8);
+                self.add_mesh(mesh, instance.with_fade(fade));
+            }
+        }
+    }
+
+    /// Add a strand mesh, stretched and turned to connect two points,
+    /// such as a segment of an antenna.
+    pub fn add_strand(
+        &mut self,
+        mesh: &Arc<model::Mesh>,
+        from: cgmath::Vector3<f32>,
+        to: cgmath::Vector3<f32>,
+        color: object::BodyColor,
+    ) {
+        use cgmath::{InnerSpace as _, Rotation as _};
+
+        let dir = to - from;
+        let length = dir.magnitude();
+        if length < f32::EPSILON {
+            return;
+        }
+        let transform = Transform {
+            disp: from,
+            rot: cgmath::Quaternion::between_vectors(cgmath::Vector3::unit_z(), dir / length),
+            scale: length / mesh.bbox.max[2],
+        };
+        let copies = match self.wrap {
+            Some(wrap) => wrap.copies(from, length),
+            None => vec![from],
+        };
+        for disp in copies {
+            if let Some(fade) = self.fade(Category::Vehicle, disp, length) {
+                let copy = Transform { disp, ..transform };
+                let instance = object::Instance::new(&copy, 0.0, color as u8);
+                self.add_mesh(mesh, instance.with_fade(fade));
             }
         }
     }
 
-    pub fn prepare(&mut self, device: &wgpu::Device) {
+    /// Add a static prop. Unlike the other objects, its transform is expected
+    /// to already account for the world wrapping.
+    pub fn add_prop(&mut self, mesh: &Arc<model::Mesh>, transform: &Transform) {
+        let radius = mesh.bbox.radius * transform.scale;
+        if let Some(fade) = self.fade(Category::Prop, transform.disp, radius) {
+            let instance = object::Instance::new(transform, 0.0, object::BodyColor::Dummy as u8);
+            self.add_mesh(mesh, instance.with_fade(fade));
+        }
+    }
+
+    /// Upload the instances relative to the `origin` of the global constants.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        stats: &mut Stats,
+        origin: cgmath::Vector3<f32>,
+    ) {
         for array in self.instances.values_mut() {
             if !array.data.is_empty() {
-                array.buffer = Some(
-                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some("instance"),
-                        contents: bytemuck::cast_slice(&array.data),
-                        usage: wgpu::BufferUsages::VERTEX,
-                    }),
-                );
+                let data = array
+                    .data
+                    .iter()
+                    .map(|instance| instance.relative_to(origin))
+                    .collect::<Vec<_>>();
+                stats.object_draw_calls += 1;
+                stats.object_instances += array.data.len() as u32;
+                let size = array.data.len() * mem::size_of::<object::Instance>();
+                stats.add_buffer("instance", size);
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("instance"),
+                    contents: bytemuck::cast_slice(&data),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+                let mut memory = memory::Allocation::new(memory::Subsystem::Instances);
+                memory.add_buffer(device, "instance", size as _);
+                array.buffer = Some((buffer, memory));
+            }
+        }
+
+        if !self.transparent.is_empty() {
+            for (_, instance) in self.transparent.iter_mut() {
+                *instance = instance.relative_to(origin);
             }
+            sort_back_to_front(&mut self.transparent);
+            let data = self
+                .transparent
+                .iter()
+                .map(|&(_, instance)| instance)
+                .collect::<Vec<_>>();
+            stats.object_draw_calls += self.transparent_runs().count() as u32;
+            stats.object_instances += data.len() as u32;
+            let size = data.len() * mem::size_of::<object::Instance>();
+            stats.add_buffer("instance-transparent", size);
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("instance-transparent"),
+                contents: bytemuck::cast_slice(&data),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            let mut memory = memory::Allocation::new(memory::Subsystem::Instances);
+            memory.add_buffer(device, "instance-transparent", size as _);
+            self.transparent_buffer = Some((buffer, memory));
         }
     }
 
+    /// Ranges of the consecutive transparent instances of the same mesh.
+    fn transparent_runs(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        mesh_runs(&self.transparent, Arc::ptr_eq)
+    }
+
+    /// Draw all the opaque instances, with the pipeline of the objects set.
     pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
-        for array in self.instances.values() {
-            if array.data.is_empty() {
+        self.draw_arrays(pass, |_| true);
+    }
+
+    /// Draw the opaque instances that don't fade out, see `draw_faded`.
+    pub fn draw_solid<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        self.draw_arrays(pass, |is_faded| !is_faded);
+    }
+
+    /// Draw the opaque instances fading out, with the dithering pipeline set.
+    pub fn draw_faded<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        self.draw_arrays(pass, |is_faded| is_faded);
+    }
+
+    fn draw_arrays<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, filter: impl Fn(bool) -> bool) {
+        for (&(_, is_faded), array) in self.instances.iter() {
+            if array.data.is_empty() || !filter(is_faded) {
                 continue;
             }
             pass.set_vertex_buffer(0, array.mesh.vertex_buf.slice(..));
-            pass.set_vertex_buffer(1, array.buffer.as_ref().unwrap().slice(..));
+            pass.set_vertex_buffer(1, array.buffer.as_ref().unwrap().0.slice(..));
             pass.draw(
                 0..array.mesh.num_vertices as u32,
                 0..array.data.len() as u32,
@@ -305,11 +773,57 @@ impl Batcher {
         }
     }
 
+    /// Draw the transparent instances, with the pipeline of the objects set.
+    /// Only the opaque instances are drawn by `draw`.
+    pub fn draw_transparent<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        let buffer = match self.transparent_buffer {
+            Some((ref buffer, _)) if !self.transparent.is_empty() => buffer,
+            _ => return,
+        };
+        let stride = mem::size_of::<object::Instance>() as wgpu::BufferAddress;
+        for run in self.transparent_runs() {
+            let mesh = &self.transparent[run.start].0;
+            pass.set_vertex_buffer(0, mesh.vertex_buf.slice(..));
+            pass.set_vertex_buffer(
+                1,
+                buffer.slice(run.start as u64 * stride..run.end as u64 * stride),
+            );
+            pass.draw(0..mesh.num_vertices as u32, 0..run.len() as u32);
+        }
+    }
+
+    /// Make an empty batcher with the same placement, to fill on another thread.
+    pub fn fork(&self) -> Self {
+        Batcher {
+            wrap: self.wrap,
+            cull: self.cull,
+            alpha: self.alpha,
+            ..Batcher::new()
+        }
+    }
+
+    /// Append everything added to the fork.
+    pub fn merge(&mut self, other: Self) {
+        for (key, other_array) in other.instances {
+            match self.instances.entry(key) {
+                Entry::Occupied(mut e) => e.get_mut().data.extend(other_array.data),
+                Entry::Vacant(e) => {
+                    e.insert(other_array);
+                }
+            }
+        }
+        self.transparent.extend(other.transparent);
+        self.debug_shapes.extend(other.debug_shapes);
+        self.debug_instances.extend(other.debug_instances);
+    }
+
     pub fn clear(&mut self) {
         for array in self.instances.values_mut() {
             array.data.clear();
             array.buffer = None;
         }
+        self.transparent.clear();
+        self.transparent_buffer = None;
         self.debug_shapes.clear();
         self.debug_instances.clear();
     }
@@ -317,7 +831,11 @@ impl Batcher {
 
 pub struct PipelineSet {
     main: wgpu::RenderPipeline,
+    /// Variant of `main` with the dithering, for the instances fading out.
+    faded: wgpu::RenderPipeline,
     shadow: wgpu::RenderPipeline,
+    /// Blended variant of `main`, for the transparent instances.
+    transparent: wgpu::RenderPipeline,
 }
 
 #[derive(Copy, Clone)]
@@ -343,24 +861,95 @@ pub struct Rect {
     pub h: u16,
 }
 
+impl Rect {
+    /// Check if the texel is inside, with the rect wrapping around the level of the given size.
+    pub fn contains_wrapped(&self, point: (i32, i32), level_size: (i32, i32)) -> bool {
+        (point.0 - self.x as i32).rem_euclid(level_size.0) < self.w as i32
+            && (point.1 - self.y as i32).rem_euclid(level_size.1) < self.h as i32
+    }
+
+    /// Extend by the margin on all sides, wrapping around the level of the given size.
+    pub fn grow_wrapped(&self, margin: i32, level_size: (i32, i32)) -> Self {
+        Rect {
+            x: (self.x as i32 - margin).rem_euclid(level_size.0) as u16,
+            y: (self.y as i32 - margin).rem_euclid(level_size.1) as u16,
+            w: (self.w as i32 + 2 * margin).min(level_size.0) as u16,
+            h: (self.h as i32 + 2 * margin).min(level_size.1) as u16,
+        }
+    }
+}
+
+/// Shared GPU state. Cloning is cheap, so it can be handed to loader threads.
+#[derive(Clone)]
 pub struct GraphicsContext {
-    pub device: wgpu::Device,
-    pub queue: wgpu::Queue,
+    pub device: Arc<wgpu::Device>,
+    pub queue: Arc<wgpu::Queue>,
     pub downlevel_caps: wgpu::DownlevelCapabilities,
     pub color_format: wgpu::TextureFormat,
     pub screen_size: wgpu::Extent3d,
 }
 
+/// Check if the error is caused by the loss of the device.
+pub fn is_device_lost(error: &wgpu::Error) -> bool {
+    // wgpu reports it as a validation error, caused by the device error of the core
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        if let Some(wgpu_core::device::DeviceError::Lost) = error.downcast_ref() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// Tracks the loss of a device, which wgpu reports through the uncaptured errors.
+/// Any other error is fatal, like with the default handler.
+#[derive(Clone, Default)]
+pub struct DeviceLoss(Arc<AtomicBool>);
+
+impl DeviceLoss {
+    pub fn watch(device: &wgpu::Device) -> Self {
+        let loss = Self::default();
+        let flag = Arc::clone(&loss.0);
+        device.on_uncaptured_error(Box::new(move |error| {
+            if flag.load(Ordering::Acquire) {
+                // everything fails after the loss, there is no point in reporting it
+            } else if is_device_lost(&error) {
+                error!("Device is lost: {}", error);
+                flag.store(true, Ordering::Release);
+            } else {
+                error!("Handling wgpu errors as fatal");
+                panic!("wgpu error: {}", error);
+            }
+        }));
+        loss
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
 pub struct Render {
     global: global::Context,
     pub object: object::Context,
     pub terrain: terrain::Context,
     pub water: water::Context,
     pub debug: debug::Context,
+    brush_context: brush::Context,
+    /// Brush to preview on the terrain, drawn until it's reset.
+    pub brush: Option<brush::Brush>,
     pub shadow: Option<shadow::Shadow>,
+    pub particle: Option<particle::Context>,
     pub light_config: settings::Light,
     pub fog_config: settings::Fog,
+    pub cutaway_config: settings::Cutaway,
+    pub underground_config: settings::Underground,
+    pub stats: Stats,
     screen_size: wgpu::Extent3d,
+    /// Budget of the estimated GPU memory, in bytes, zero if there is none.
+    memory_budget: u64,
+    over_budget: bool,
 }
 
 impl Render {
@@ -370,7 +959,6 @@ impl Render {
         object_palette: &[[u8; 4]],
         settings: &settings::Render,
         geometry: &settings::Geometry,
-        front_face: wgpu::FrontFace,
     ) -> Self {
         profiling::scope!("Init Renderer");
 
@@ -381,7 +969,12 @@ impl Render {
         };
 
         let global = global::Context::new(gfx, shadow.as_ref().map(|shadow| &shadow.view));
-        let object = object::Context::new(gfx, front_face, object_palette, &global);
+        let object = object::Context::new(
+            gfx,
+            settings.handedness.front_face(),
+            object_palette,
+            &global,
+        );
         let terrain = terrain::Context::new(
             gfx,
             level,
@@ -389,9 +982,33 @@ impl Render {
             &global,
             &settings.terrain,
             &settings.light.shadow.terrain,
+            &settings.underground,
+        );
+        let water = water::Context::new(
+            &gfx.device,
+            &settings.water,
+            &global,
+            &terrain,
+            gfx.screen_size,
         );
-        let water = water::Context::new(&gfx.device, &settings.water, &global, &terrain);
-        let debug = debug::Context::new(&gfx.device, &settings.debug, &global, &object);
+        let debug = debug::Context::new(
+            &gfx.device,
+            &settings.debug,
+            &global,
+            &object,
+            gfx.screen_size,
+        );
+        let brush_context = brush::Context::new(&gfx.device, &global);
+        let particle = if gfx
+            .downlevel_caps
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            && gfx.device.limits().max_storage_buffers_per_shader_stage > 0
+        {
+            Some(particle::Context::new(gfx, &global, &terrain))
+        } else {
+            None
+        };
 
         Render {
             global,
@@ -399,34 +1016,112 @@ impl Render {
             terrain,
             water,
             debug,
+            brush_context,
+            brush: None,
             shadow,
+            particle,
             light_config: settings.light,
             fog_config: settings.fog,
+            cutaway_config: settings.cutaway,
+            underground_config: settings.underground,
+            stats: Stats::default(),
             screen_size: gfx.screen_size,
+            memory_budget: (settings.memory_budget_mb as u64) << 20,
+            over_budget: false,
+        }
+    }
+
+    /// Re-create the renderer on a new device, after the old one is lost.
+    /// The light, fog, and cutaway changed at run time are kept,
+    /// and the level is uploaded again with the next updates.
+    pub fn recover(
+        &mut self,
+        gfx: &GraphicsContext,
+        level: &level::LevelConfig,
+        object_palette: &[[u8; 4]],
+        settings: &settings::Render,
+        geometry: &settings::Geometry,
+    ) {
+        let settings = settings::Render {
+            light: self.light_config,
+            fog: self.fog_config,
+            cutaway: self.cutaway_config,
+            underground: self.underground_config,
+            ..settings.clone()
+        };
+        *self = Render::new(gfx, level, object_palette, &settings, geometry);
+    }
+
+    /// Estimated GPU memory taken by the renderers of the process, by subsystem.
+    pub fn memory_report(&self) -> memory::Report {
+        memory::Report::current(self.memory_budget)
+    }
+
+    /// Warn once the memory usage goes over the budget, and again if it comes back.
+    fn check_memory_budget(&mut self) {
+        let report = self.memory_report();
+        if report.is_over_budget() == self.over_budget {
+            return;
+        }
+        self.over_budget = report.is_over_budget();
+        if self.over_budget {
+            let (subsystem, size) = report
+                .subsystems()
+                .into_iter()
+                .max_by_key(|&(_, size)| size)
+                .unwrap();
+            warn!(
+                "Estimated GPU memory of {} MB is over the budget of {} MB, mostly {} with {} MB",
+                report.total >> 20,
+                report.budget >> 20,
+                subsystem.name(),
+                size >> 20
+            );
+        } else {
+            info!(
+                "Estimated GPU memory of {} MB is back within the budget",
+                report.total >> 20
+            );
         }
     }
 
     pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
         let lpos = &mut self.light_config.pos;
         ui.horizontal(|ui| {
-            ui.label("Sun pos");
+            ui.label(tr("Sun pos"));
             ui.add(egui::DragValue::new(&mut lpos[0]).speed(1.0).prefix("x:"));
             ui.add(egui::DragValue::new(&mut lpos[1]).speed(1.0).prefix("y:"));
             ui.add(egui::DragValue::new(&mut lpos[2]).speed(1.0).prefix("z:"));
         });
         ui.horizontal(|ui| {
-            ui.label("Sun color");
+            ui.label(tr("Sun color"));
             ui.color_edit_button_rgb(&mut self.light_config.color);
         });
         ui.horizontal(|ui| {
-            ui.label("Fog color");
+            ui.label(tr("Fog color"));
             ui.color_edit_button_rgb(&mut self.fog_config.color);
         });
-        ui.add(egui::Slider::new(&mut self.fog_config.depth, 0.0..=100.0).text("Fog depth"));
+        ui.add(egui::Slider::new(&mut self.fog_config.depth, 0.0..=100.0).text(tr("Fog depth")));
         ui.group(|ui| {
-            ui.label("Terrain:");
+            ui.label(tr("Terrain:"));
             self.terrain.draw_ui(ui);
         });
+        ui.group(|ui| {
+            ui.label(tr("Stats:"));
+            self.stats.draw_ui(ui);
+        });
+        ui.group(|ui| {
+            ui.label(tr("Memory:"));
+            self.memory_report().draw_ui(ui);
+        });
+        let capture_button = egui::Button::new(tr("Capture a frame"));
+        if ui
+            .add_enabled(!capture::is_requested(), capture_button)
+            .on_hover_text(tr("Needs the application to run under RenderDoc"))
+            .clicked()
+        {
+            capture::request();
+        }
     }
 
     pub fn draw_world(
@@ -440,9 +1135,13 @@ impl Render {
         device: &wgpu::Device,
     ) {
         profiling::scope!("draw_world");
-        batcher.prepare(device);
+        self.stats = Stats::default();
+        batcher.prepare(device, &mut self.stats, cam.loc);
+        self.check_memory_budget();
 
-        self.terrain.update_dirty(encoder, level, device);
+        self.terrain.stream(cam, level);
+        self.terrain
+            .update_dirty(encoder, level, device, &mut self.stats);
 
         //TODO: common routine for draw passes
         //TODO: use `write_buffer`
@@ -451,12 +1150,14 @@ impl Render {
             profiling::scope!("Shadow Pass");
             shadow.update_view(&self.light_config.pos, cam, level.geometry.height as f32);
 
-            let constants = global::Constants::new(&shadow.cam, &self.light_config, None);
+            let constants = global::Constants::new(&shadow.cam, cam.loc, &self.light_config, None);
             let global_staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("temp-global-shadow"),
                 contents: bytemuck::bytes_of(&constants),
                 usage: wgpu::BufferUsages::COPY_SRC,
             });
+            self.stats
+                .add_buffer("temp-global-shadow", mem::size_of::<global::Constants>());
             encoder.copy_buffer_to_buffer(
                 &global_staging,
                 0,
@@ -468,6 +1169,7 @@ impl Render {
             self.terrain.prepare_shadow(
                 encoder,
                 device,
+                &mut self.stats,
                 cam,
                 wgpu::Extent3d {
                     width: shadow.size,
@@ -476,18 +1178,21 @@ impl Render {
                 },
             );
 
-            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("shadow"),
-                color_attachments: &[],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &shadow.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
+            let mut pass = frame_graph::begin_render_pass(
+                encoder,
+                &wgpu::RenderPassDescriptor {
+                    label: Some("shadow"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &shadow.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: true,
+                        }),
+                        stencil_ops: None,
                     }),
-                    stencil_ops: None,
-                }),
-            });
+                },
+            );
 
             pass.set_bind_group(0, &self.global.shadow_bind_group, &[]);
             pass.push_debug_group("terrain");
@@ -505,6 +1210,7 @@ impl Render {
             profiling::scope!("Main Pass");
             let constants = global::Constants::new(
                 cam,
+                cam.loc,
                 &self.light_config,
                 self.shadow.as_ref().map(|shadow| &shadow.cam),
             );
@@ -513,6 +1219,8 @@ impl Render {
                 contents: bytemuck::bytes_of(&constants),
                 usage: wgpu::BufferUsages::COPY_SRC,
             });
+            self.stats
+                .add_buffer("temp-global", mem::size_of::<global::Constants>());
             encoder.copy_buffer_to_buffer(
                 &global_staging,
                 0,
@@ -524,46 +1232,69 @@ impl Render {
             self.terrain.prepare(
                 encoder,
                 device,
+                &mut self.stats,
                 &self.global,
+                &constants,
                 &self.fog_config,
                 level.geometry.height,
                 cam,
-                viewport.unwrap_or_else(|| Rect {
+                viewport.unwrap_or(Rect {
                     x: 0,
                     y: 0,
                     w: self.screen_size.width as u16,
                     h: self.screen_size.height as u16,
                 }),
             );
-            self.water.prepare(encoder, device, cam);
+            self.water.prepare(encoder, device, &mut self.stats, cam);
+            self.brush_context.prepare(
+                encoder,
+                device,
+                &mut self.stats,
+                level,
+                self.brush.as_ref(),
+            );
+            if let Some(ref mut particle) = self.particle {
+                particle.prepare(
+                    encoder,
+                    device,
+                    &mut self.stats,
+                    &self.global,
+                    &self.terrain,
+                );
+            }
 
-            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("main"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: targets.color,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear({
-                            let c = self.fog_config.color;
-                            wgpu::Color {
-                                r: c[0] as f64,
-                                g: c[1] as f64,
-                                b: c[2] as f64,
-                                a: 1.0,
-                            }
+            // with refraction, the opaque scene goes into a separate texture first
+            let scene_view = self.water.scene_view();
+            let mut pass = frame_graph::begin_render_pass(
+                encoder,
+                &wgpu::RenderPassDescriptor {
+                    label: Some("main"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: scene_view.unwrap_or(targets.color),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear({
+                                let c = self.fog_config.color;
+                                wgpu::Color {
+                                    r: c[0] as f64,
+                                    g: c[1] as f64,
+                                    b: c[2] as f64,
+                                    a: 1.0,
+                                }
+                            }),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: targets.depth,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: true,
                         }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: targets.depth,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
+                        stencil_ops: None,
                     }),
-                    stencil_ops: None,
-                }),
-            });
+                },
+            );
 
             if let Some(ref r) = viewport {
                 pass.set_viewport(r.x as f32, r.y as f32, r.w as f32, r.h as f32, 0.0, 1.0);
@@ -577,25 +1308,128 @@ impl Render {
             pass.push_debug_group("vehicles");
             pass.set_pipeline(&self.object.pipelines.main);
             pass.set_bind_group(1, &self.object.bind_group, &[]);
-            batcher.draw(&mut pass);
+            batcher.draw_solid(&mut pass);
+            pass.set_pipeline(&self.object.pipelines.faded);
+            batcher.draw_faded(&mut pass);
+            pass.pop_debug_group();
+
+            pass.push_debug_group("brush");
+            self.brush_context.draw(&mut pass);
             pass.pop_debug_group();
 
-            pass.push_debug_group("water");
-            pass.set_bind_group(1, &self.terrain.bind_group, &[]);
-            self.water.draw(&mut pass);
+            if scene_view.is_none() {
+                self.draw_transparent(&mut pass, batcher);
+            }
+        }
+        if self.water.scene_view().is_some() {
+            profiling::scope!("Water Pass");
+            let mut pass = frame_graph::begin_render_pass(
+                encoder,
+                &wgpu::RenderPassDescriptor {
+                    label: Some("water"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: targets.color,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: targets.depth,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                },
+            );
+
+            if let Some(ref r) = viewport {
+                pass.set_viewport(r.x as f32, r.y as f32, r.w as f32, r.h as f32, 0.0, 1.0);
+            }
+
+            pass.set_bind_group(0, &self.global.bind_group, &[]);
+            self.draw_transparent(&mut pass, batcher);
+        }
+    }
+
+    /// Draw the debug lines over the frame of `draw_world`, with the same camera.
+    pub fn draw_debug_lines(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        targets: ScreenTargets<'_>,
+        lines: &debug::LineBuffer,
+        device: &wgpu::Device,
+    ) {
+        if lines.is_empty() {
+            return;
+        }
+        let mut pass = frame_graph::begin_render_pass(
+            encoder,
+            &wgpu::RenderPassDescriptor {
+                label: Some("debug"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: targets.color,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: targets.depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            },
+        );
+        pass.set_bind_group(0, &self.global.bind_group, &[]);
+        self.debug.draw_lines(&mut pass, device, lines);
+    }
+
+    fn draw_transparent<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, batcher: &'a Batcher) {
+        pass.push_debug_group("water");
+        pass.set_bind_group(1, &self.terrain.bind_group, &[]);
+        self.water.draw(pass);
+        pass.pop_debug_group();
+
+        pass.push_debug_group("transparent vehicles");
+        pass.set_pipeline(&self.object.pipelines.transparent);
+        pass.set_bind_group(1, &self.object.bind_group, &[]);
+        batcher.draw_transparent(pass);
+        pass.pop_debug_group();
+
+        if let Some(ref particle) = self.particle {
+            pass.push_debug_group("particles");
+            particle.draw(pass);
             pass.pop_debug_group();
         }
     }
 
     pub fn reload(&mut self, device: &wgpu::Device) {
         info!("Reloading shaders");
+        if let Err(e) = check_shaders(device) {
+            error!("Shader reload failed, keeping the old pipelines. {}", e);
+            return;
+        }
         self.object.reload(device);
         self.terrain.reload(device);
         self.water.reload(device);
+        self.brush_context.reload(device);
+        if let Some(ref mut particle) = self.particle {
+            particle.reload(device);
+        }
     }
 
     pub fn resize(&mut self, extent: wgpu::Extent3d, device: &wgpu::Device) {
         self.terrain.resize(extent, device);
+        self.water.resize(extent, device);
+        self.debug.resize(extent, device);
         self.screen_size = extent;
     }
 
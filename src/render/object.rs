@@ -67,6 +67,7 @@ impl BodyColor {
 pub struct Vertex {
     pub pos: [i8; 4],
     pub color: u32,
+    /// XYZ = normal, W = baked ambient visibility (127 is fully open)
     pub normal: [i8; 4],
 }
 unsafe impl Pod for Vertex {}
@@ -75,10 +76,15 @@ unsafe impl Zeroable for Vertex {}
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct Instance {
-    pos_scale: [f32; 4],
-    orientation: [f32; 4],
+    pub(super) pos_scale: [f32; 4],
+    pub(super) orientation: [f32; 4],
     shape_scale: f32,
-    body_and_color_id: [u32; 2],
+    pub(super) body_and_color_id: [u32; 2],
+    palette_layer: u32,
+    /// Opacity, reached by dithering.
+    pub(super) fade: f32,
+    /// Opacity, reached by blending, see `with_alpha`.
+    pub(super) alpha: f32,
 }
 unsafe impl Pod for Instance {}
 unsafe impl Zeroable for Instance {}
@@ -91,19 +97,54 @@ impl Instance {
             orientation: gt.orientation,
             shape_scale,
             body_and_color_id: [0, color_id as u32],
+            palette_layer: 0,
+            fade: 1.0,
+            alpha: 1.0,
+        }
+    }
+
+    /// Make the instance partially transparent, see `Batcher::cull`.
+    pub fn with_fade(self, fade: f32) -> Self {
+        Instance { fade, ..self }
+    }
+
+    /// Move the instance by `-origin`, see `global::Constants::new`.
+    pub(super) fn relative_to(self, origin: cgmath::Vector3<f32>) -> Self {
+        let [x, y, z, scale] = self.pos_scale;
+        Instance {
+            pos_scale: [x - origin.x, y - origin.y, z - origin.z, scale],
+            ..self
+        }
+    }
+
+    /// Make the instance translucent, e.g. for a ghost or a preview. Unlike the fade,
+    /// the instance is blended over the scene, sorted back to front with the others.
+    pub fn with_alpha(self, alpha: f32) -> Self {
+        Instance { alpha, ..self }
+    }
+
+    pub(super) fn is_transparent(&self) -> bool {
+        self.alpha < 1.0
+    }
+
+    /// Draw with another layer of the palette, see `Context::set_palette`.
+    pub fn with_palette(self, layer: u32) -> Self {
+        Instance {
+            palette_layer: layer,
+            ..self
         }
     }
 }
 
 #[derive(Copy, Clone)]
 pub struct InstanceDesc {
-    attributes: [wgpu::VertexAttribute; 4],
+    attributes: [wgpu::VertexAttribute; 7],
 }
 
 impl InstanceDesc {
     pub fn new() -> Self {
         InstanceDesc {
-            attributes: wgpu::vertex_attr_array![3 => Float32x4, 4 => Float32x4, 5 => Float32, 6 => Uint32x2],
+            attributes: wgpu::vertex_attr_array![3 => Float32x4, 4 => Float32x4, 5 => Float32, 6 => Uint32x2, 7 => Uint32, 8 => Float32, 9 => Float32],
         }
     }
 
@@ -123,6 +164,7 @@ pub struct Context {
     pub pipelines: PipelineSet,
     pub color_format: wgpu::TextureFormat,
     front_face: wgpu::FrontFace,
+    palette: Palette,
 }
 
 impl Context {
@@ -140,35 +182,49 @@ impl Context {
         let instance_desc = InstanceDesc::new();
         let shader = super::load_shader("object", &[], device).unwrap();
 
-        let main = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("object"),
-            layout: Some(layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "color_vs",
-                buffers: &[vertex_descriptor.clone(), instance_desc.buffer_desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "color_fs",
-                targets: &[Some(color_format.into())],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                front_face,
-                // original was not drawn with rasterizer, used no culling
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: Default::default(),
-                bias: Default::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-        });
+        // the transparent objects are blended over the opaque ones, keeping their depth
+        let create_color_pipeline = |label, entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "color_vs",
+                    buffers: &[vertex_descriptor.clone(), instance_desc.buffer_desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend,
+                        write_mask: wgpu::ColorWrites::all(),
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    front_face,
+                    // original was not drawn with rasterizer, used no culling
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled: blend.is_none(),
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let main = create_color_pipeline("object", "color_fs", None);
+        let faded = create_color_pipeline("object-faded", "faded_fs", None);
+        let transparent = create_color_pipeline(
+            "object-transparent",
+            "faded_fs",
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        );
 
         let shadow = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("object-shadow"),
@@ -200,7 +256,12 @@ impl Context {
             multiview: None,
         });
 
-        PipelineSet { main, shadow }
+        PipelineSet {
+            main,
+            faded,
+            shadow,
+            transparent,
+        }
     }
 
     fn create_color_table(gfx: &super::GraphicsContext) -> (wgpu::TextureView, wgpu::Sampler) {
@@ -273,7 +334,7 @@ impl Context {
                             binding: 1,
                             visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                             ty: wgpu::BindingType::Texture {
-                                view_dimension: wgpu::TextureViewDimension::D1,
+                                view_dimension: wgpu::TextureViewDimension::D2Array,
                                 sample_type: wgpu::TextureSampleType::Float { filterable: true },
                                 multisampled: false,
                             },
@@ -317,8 +378,8 @@ impl Context {
             Err(VertexStorageNotSupported)
         };
 
-        let palette = Palette::new(&gfx.device);
-        palette.init(&gfx.queue, palette_data);
+        let palette = Palette::new(&gfx.device, 1);
+        palette.init(&gfx.queue, 0, palette_data);
 
         let (color_table_view, color_table_sampler) = Self::create_color_table(gfx);
         let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -356,9 +417,16 @@ impl Context {
             pipelines,
             color_format: gfx.color_format,
             front_face,
+            palette,
         }
     }
 
+    /// Upload a palette for the instances that select this layer.
+    /// Layer 0 is the palette given at creation.
+    pub fn set_palette(&self, queue: &wgpu::Queue, layer: u32, data: &[[u8; 4]]) {
+        self.palette.init(queue, layer, data);
+    }
+
     pub fn reload(&mut self, device: &wgpu::Device) {
         self.pipelines = Self::create_pipelines(
             &self.pipeline_layout,
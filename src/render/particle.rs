@@ -0,0 +1,353 @@
+use crate::render::{
+    frame_graph, global::Context as GlobalContext, terrain::Context as TerrainContext,
+    GraphicsContext, Stats, DEPTH_FORMAT,
+};
+use bytemuck::{Pod, Zeroable};
+use rand::Rng as _;
+use std::mem;
+use wgpu::util::DeviceExt as _;
+
+const MAX_PARTICLES: usize = 4096;
+const WORKGROUP_SIZE: usize = 64;
+const GRAVITY: f32 = 150.0;
+const RESTITUTION: f32 = 0.4;
+const FRICTION: f32 = 0.3;
+const LIFE_TIME: f32 = 3.0;
+/// Color of the particles thrown by `spawn_burst`.
+pub const DUST_COLOR: [f32; 3] = [0.6, 0.5, 0.4];
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    pub pos_life: [f32; 4],
+    /// XYZ = velocity, W = color bytes, packed into an integer.
+    pub vel: [f32; 4],
+}
+unsafe impl Pod for Particle {}
+unsafe impl Zeroable for Particle {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Constants {
+    params: [f32; 4],
+}
+unsafe impl Pod for Constants {}
+unsafe impl Zeroable for Constants {}
+
+/// GPU-simulated particles bouncing off the terrain.
+/// The simulation runs in a compute pass, so the context
+/// only exists on platforms that support compute shaders.
+pub struct Context {
+    update_pipeline_layout: wgpu::PipelineLayout,
+    draw_pipeline_layout: wgpu::PipelineLayout,
+    update_pipeline: wgpu::ComputePipeline,
+    draw_pipeline: wgpu::RenderPipeline,
+    color_format: wgpu::TextureFormat,
+    bind_group: wgpu::BindGroup,
+    particle_buf: wgpu::Buffer,
+    uniform_buf: wgpu::Buffer,
+    _memory: super::memory::Allocation,
+    pending: Vec<Particle>,
+    next_index: usize,
+    time_delta: f32,
+    time_to_live: f32,
+}
+
+impl Context {
+    fn create_pipelines(
+        update_layout: &wgpu::PipelineLayout,
+        draw_layout: &wgpu::PipelineLayout,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+    ) -> (wgpu::ComputePipeline, wgpu::RenderPipeline) {
+        let shader = super::load_shader("particle", &[], device).unwrap();
+
+        let update = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("particle-update"),
+            layout: Some(update_layout),
+            module: &shader,
+            entry_point: "update",
+        });
+
+        let draw = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("particle-draw"),
+            layout: Some(draw_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "main_vs",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: mem::size_of::<Particle>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "main_fs",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        (update, draw)
+    }
+
+    pub fn new(gfx: &GraphicsContext, global: &GlobalContext, terrain: &TerrainContext) -> Self {
+        let bind_group_layout =
+            gfx.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("Particle"),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: wgpu::BufferSize::new(
+                                    mem::size_of::<Constants>() as _,
+                                ),
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+        let update_pipeline_layout =
+            gfx.device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("particle-update"),
+                    bind_group_layouts: &[
+                        &global.bind_group_layout,
+                        &terrain.bind_group_layout,
+                        &bind_group_layout,
+                    ],
+                    push_constant_ranges: &[],
+                });
+        let draw_pipeline_layout =
+            gfx.device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("particle-draw"),
+                    bind_group_layouts: &[&global.bind_group_layout],
+                    push_constant_ranges: &[],
+                });
+        let (update_pipeline, draw_pipeline) = Self::create_pipelines(
+            &update_pipeline_layout,
+            &draw_pipeline_layout,
+            &gfx.device,
+            global.color_format,
+        );
+
+        let particle_size = (MAX_PARTICLES * mem::size_of::<Particle>()) as wgpu::BufferAddress;
+        let mut memory = super::memory::Allocation::new(super::memory::Subsystem::Particles);
+        memory.add_buffer(&gfx.device, "particles", particle_size);
+        let particle_buf = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("particles"),
+            size: particle_size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST,
+            // zeroed memory means all the particles are dead
+            mapped_at_creation: false,
+        });
+        let uniform_buf = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("particle-uniforms"),
+            size: mem::size_of::<Constants>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Particle"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: particle_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buf.as_entire_binding(),
+                },
+            ],
+        });
+
+        Context {
+            update_pipeline_layout,
+            draw_pipeline_layout,
+            update_pipeline,
+            draw_pipeline,
+            color_format: global.color_format,
+            bind_group,
+            particle_buf,
+            uniform_buf,
+            _memory: memory,
+            pending: Vec::new(),
+            next_index: 0,
+            time_delta: 0.0,
+            time_to_live: 0.0,
+        }
+    }
+
+    pub fn reload(&mut self, device: &wgpu::Device) {
+        let (update, draw) = Self::create_pipelines(
+            &self.update_pipeline_layout,
+            &self.draw_pipeline_layout,
+            device,
+            self.color_format,
+        );
+        self.update_pipeline = update;
+        self.draw_pipeline = draw;
+    }
+
+    /// Throw a number of particles out of the origin in random upward directions.
+    pub fn spawn_burst(&mut self, origin: cgmath::Vector3<f32>, count: usize, speed: f32) {
+        self.spawn_colored(origin, count, speed, DUST_COLOR);
+    }
+
+    /// Same as `spawn_burst`, with the particles of the given color.
+    pub fn spawn_colored(
+        &mut self,
+        origin: cgmath::Vector3<f32>,
+        count: usize,
+        speed: f32,
+        color: [f32; 3],
+    ) {
+        // an integer below 2^24 is exact in a float
+        let packed = color
+            .iter()
+            .enumerate()
+            .map(|(i, c)| ((c.clamp(0.0, 1.0) * 255.0) as u32) << (8 * i))
+            .sum::<u32>() as f32;
+        let mut rng = rand::thread_rng();
+        for _ in 0..count.min(MAX_PARTICLES) {
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            let spread = rng.gen_range(0.2f32..1.0);
+            let vel = [
+                speed * spread * angle.cos(),
+                speed * spread * angle.sin(),
+                speed * rng.gen_range(0.5..1.0),
+                packed,
+            ];
+            self.pending.push(Particle {
+                pos_life: [
+                    origin.x,
+                    origin.y,
+                    origin.z,
+                    LIFE_TIME * rng.gen_range(0.5..1.0),
+                ],
+                vel,
+            });
+        }
+        self.time_to_live = LIFE_TIME;
+    }
+
+    /// Advance the simulation time, to be applied on the next `prepare`.
+    pub fn update(&mut self, delta: f32) {
+        self.time_delta += delta;
+    }
+
+    pub fn prepare(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        stats: &mut Stats,
+        global: &GlobalContext,
+        terrain: &TerrainContext,
+    ) {
+        let particle_size = mem::size_of::<Particle>();
+        if self.pending.len() > MAX_PARTICLES {
+            let excess = self.pending.len() - MAX_PARTICLES;
+            self.pending.drain(..excess);
+        }
+        if !self.pending.is_empty() {
+            let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("staging particles"),
+                contents: bytemuck::cast_slice(&self.pending),
+                usage: wgpu::BufferUsages::COPY_SRC,
+            });
+            stats.add_buffer("staging particles", self.pending.len() * particle_size);
+            // the particle buffer is a ring, new particles replace the oldest ones
+            let mut offset = 0;
+            while offset < self.pending.len() {
+                let count = (self.pending.len() - offset).min(MAX_PARTICLES - self.next_index);
+                encoder.copy_buffer_to_buffer(
+                    &staging,
+                    (offset * particle_size) as wgpu::BufferAddress,
+                    &self.particle_buf,
+                    (self.next_index * particle_size) as wgpu::BufferAddress,
+                    (count * particle_size) as wgpu::BufferAddress,
+                );
+                offset += count;
+                self.next_index = (self.next_index + count) % MAX_PARTICLES;
+            }
+            self.pending.clear();
+        }
+
+        let time_delta = self.time_delta;
+        self.time_delta = 0.0;
+        if self.time_to_live <= 0.0 || time_delta <= 0.0 {
+            return;
+        }
+        self.time_to_live -= time_delta;
+
+        let constants = Constants {
+            params: [time_delta, GRAVITY, RESTITUTION, FRICTION],
+        };
+        let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("staging particle constants"),
+            contents: bytemuck::bytes_of(&constants),
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+        stats.add_buffer("staging particle constants", mem::size_of::<Constants>());
+        encoder.copy_buffer_to_buffer(
+            &staging,
+            0,
+            &self.uniform_buf,
+            0,
+            mem::size_of::<Constants>() as wgpu::BufferAddress,
+        );
+
+        let mut pass = frame_graph::begin_compute_pass(
+            encoder,
+            &wgpu::ComputePassDescriptor {
+                label: Some("particles"),
+            },
+        );
+        pass.set_pipeline(&self.update_pipeline);
+        pass.set_bind_group(0, &global.bind_group, &[]);
+        pass.set_bind_group(1, &terrain.bind_group, &[]);
+        pass.set_bind_group(2, &self.bind_group, &[]);
+        frame_graph::dispatch(&mut pass, [(MAX_PARTICLES / WORKGROUP_SIZE) as u32, 1, 1]);
+    }
+
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        if self.time_to_live <= 0.0 {
+            return;
+        }
+        pass.set_pipeline(&self.draw_pipeline);
+        pass.set_vertex_buffer(0, self.particle_buf.slice(..));
+        pass.draw(0..6, 0..MAX_PARTICLES as u32);
+    }
+}
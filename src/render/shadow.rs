@@ -8,15 +8,17 @@ use cgmath::{EuclideanSpace as _, One as _, Rotation as _};
 pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
 
 pub struct Shadow {
+    texture: wgpu::Texture,
     pub(super) view: wgpu::TextureView,
     pub(super) cam: Camera,
     pub(super) size: u32,
+    _memory: super::memory::Allocation,
 }
 
 impl Shadow {
     pub(super) fn new(settings: &settings::Shadow, device: &wgpu::Device) -> Self {
         let size = settings.size;
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
+        let desc = wgpu::TextureDescriptor {
             label: Some("Shadow"),
             size: wgpu::Extent3d {
                 width: size,
@@ -29,20 +31,36 @@ impl Shadow {
             format: FORMAT,
             view_formats: &[],
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
-        });
+        };
+        let mut memory = super::memory::Allocation::new(super::memory::Subsystem::Shadow);
+        memory.add_texture(device, &desc);
+        let texture = device.create_texture(&desc);
 
         Shadow {
             view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
             cam: Camera {
                 loc: cgmath::Zero::zero(),
                 rot: cgmath::Quaternion::one(),
-                scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+                handedness: settings::Handedness::Right,
                 proj: Projection::ortho(1, 1, 0.0..1.0),
             },
             size,
+            _memory: memory,
         }
     }
 
+    /// Depth map of the light, in `FORMAT`, rendered by the last frame.
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// Transform of the world positions into the clip space of the light,
+    /// as the last frame rendered the depth map with.
+    pub fn light_view_proj(&self) -> cgmath::Matrix4<f32> {
+        self.cam.get_view_proj()
+    }
+
     fn get_local_point(&self, world_pt: cgmath::Point3<f32>) -> cgmath::Point3<f32> {
         let diff = world_pt.to_vec() - self.cam.loc;
         cgmath::Point3::origin() + self.cam.rot.invert() * diff
@@ -0,0 +1,180 @@
+//! Description of a rendered frame, for reproducing it outside of the game.
+//!
+//! A snapshot has the camera, the opaque and the transparent object instances
+//! with the ids and the bounds of their meshes, and the terrain around the camera,
+//! but no GPU resources. It's saved as RON,
+//! and can be rendered offline with `convert --render-snapshot`.
+
+use super::{object, Batcher};
+use crate::{
+    level::Level,
+    model,
+    space::{Camera, Projection},
+};
+
+use std::{collections::HashMap, fs::File, ops::Range, path::Path, sync::Arc};
+
+/// Largest side of the captured terrain region, in texels.
+pub const MAX_REGION_SIZE: i32 = 1024;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    pub position: [f32; 3],
+    /// Quaternion as `[x, y, z, w]`.
+    pub orientation: [f32; 4],
+    /// Width over height of the viewport.
+    pub aspect: f32,
+    /// Columns of the matrix, with the depth being in [0, 1].
+    pub view_proj: [[f32; 4]; 4],
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeshBounds {
+    /// Hash of the vertices, see `model::vertex_id`.
+    pub id: u64,
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub num_vertices: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Instance {
+    /// Index into the meshes of the snapshot.
+    pub mesh: usize,
+    pub position: [f32; 3],
+    pub scale: f32,
+    /// Quaternion as `[x, y, z, w]`.
+    pub orientation: [f32; 4],
+    pub color_id: u32,
+    pub fade: f32,
+    /// Opacity of the transparent instances, drawn after the opaque ones.
+    pub alpha: f32,
+}
+
+/// Rectangle of the level data, with the coordinates wrapped around the level.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TerrainRegion {
+    pub origin: (i32, i32),
+    pub size: (i32, i32),
+    pub height: Vec<u8>,
+    pub meta: Vec<u8>,
+}
+
+impl TerrainRegion {
+    /// Copy the level data within the bounds, limited to `MAX_REGION_SIZE` around the center.
+    pub fn capture(level: &Level, bounds: Range<cgmath::Vector2<f32>>) -> Self {
+        let center = (bounds.start + bounds.end) * 0.5;
+        let half = MAX_REGION_SIZE as f32 * 0.5;
+        let start = (
+            bounds.start.x.max(center.x - half).floor() as i32,
+            bounds.start.y.max(center.y - half).floor() as i32,
+        );
+        let end = (
+            bounds.end.x.min(center.x + half).ceil() as i32,
+            bounds.end.y.min(center.y + half).ceil() as i32,
+        );
+        let size = ((end.0 - start.0).max(0), (end.1 - start.1).max(0));
+        let mut region = TerrainRegion {
+            origin: start,
+            size,
+            height: Vec::with_capacity((size.0 * size.1) as usize),
+            meta: Vec::with_capacity((size.0 * size.1) as usize),
+        };
+        for y in start.1..end.1 {
+            let row = y.rem_euclid(level.size.1) * level.size.0;
+            for x in start.0..end.0 {
+                let index = (row + x.rem_euclid(level.size.0)) as usize;
+                region.height.push(level.height[index]);
+                region.meta.push(level.meta[index]);
+            }
+        }
+        region
+    }
+
+    /// Height of the texel at the level coordinates, if it's in the region.
+    pub fn height_at(&self, x: i32, y: i32) -> Option<u8> {
+        let (rx, ry) = (x - self.origin.0, y - self.origin.1);
+        if rx < 0 || ry < 0 || rx >= self.size.0 || ry >= self.size.1 {
+            return None;
+        }
+        self.height.get((ry * self.size.0 + rx) as usize).cloned()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub camera: CameraState,
+    pub meshes: Vec<MeshBounds>,
+    pub instances: Vec<Instance>,
+    pub terrain: TerrainRegion,
+}
+
+impl Snapshot {
+    /// Capture the scene as collected by the batcher for the current frame,
+    /// before it's prepared. The opaque instances go first, then the transparent ones.
+    pub fn capture(batcher: &Batcher, level: &Level, camera: &Camera) -> Self {
+        let mut meshes = Vec::new();
+        let mut mesh_indices = HashMap::new();
+        let mut instances = Vec::new();
+        let mut add = |mesh: &Arc<model::Mesh>, instance: &object::Instance| {
+            let index = *mesh_indices
+                .entry(Arc::as_ptr(mesh) as usize)
+                .or_insert_with(|| {
+                    meshes.push(MeshBounds {
+                        id: mesh.id,
+                        min: mesh.bbox.min,
+                        max: mesh.bbox.max,
+                        num_vertices: mesh.num_vertices,
+                    });
+                    meshes.len() - 1
+                });
+            let [x, y, z, scale] = instance.pos_scale;
+            instances.push(Instance {
+                mesh: index,
+                position: [x, y, z],
+                scale,
+                orientation: instance.orientation,
+                color_id: instance.body_and_color_id[1],
+                fade: instance.fade,
+                alpha: instance.alpha,
+            });
+        };
+        for array in batcher.instances.values() {
+            for instance in array.data.iter() {
+                add(&array.mesh, instance);
+            }
+        }
+        for (mesh, instance) in batcher.transparent.iter() {
+            add(mesh, instance);
+        }
+
+        let aspect = match camera.proj {
+            Projection::Ortho { p, .. } => ((p.right - p.left) / (p.bottom - p.top)).abs(),
+            Projection::Perspective(p) => p.aspect,
+        };
+        Snapshot {
+            camera: CameraState {
+                position: camera.loc.into(),
+                orientation: crate::space::coords::quat_to_xyzw(camera.rot),
+                aspect,
+                view_proj: camera.get_view_proj().into(),
+            },
+            meshes,
+            instances,
+            terrain: TerrainRegion::capture(level, camera.visible_bounds()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file =
+            File::open(path).map_err(|e| format!("Unable to open {}: {}", path.display(), e))?;
+        ron::de::from_reader(file).map_err(|e| format!("Unable to parse {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Unable to create {}: {}", path.display(), e))?;
+        ron::ser::to_writer_pretty(file, self, ron::ser::PrettyConfig::default())
+            .map_err(|e| format!("Unable to save {}: {}", path.display(), e))
+    }
+}
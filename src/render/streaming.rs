@@ -0,0 +1,223 @@
+//! Residency of the level tiles for the streamed terrain.
+//!
+//! The level is split into square tiles, and only the ones around the visible
+//! terrain are kept in the slots of an atlas texture. A page table maps each tile
+//! to its slot, or to `NOT_RESIDENT`, and the shaders see the missing tiles as
+//! the flat ground. A tile that becomes needed takes a free slot first, then the
+//! slot of the least recently needed tile.
+//!
+//! The data of the tiles goes through the `UploadQueue`, at most one staging buffer
+//! of tiles per frame. The terrain keeps a few staging buffers, and maps each one
+//! again in the background once the GPU is done copying from it, so the frame never
+//! waits for a mapping: when none is ready, the tiles stay in the queue. Until its
+//! data arrives, a new tile is hidden from the shaders, which see the flat ground.
+
+use super::Rect;
+
+/// Page table entry of a tile that has no slot.
+pub const NOT_RESIDENT: u32 = !0;
+/// Tiles made resident per frame at most, the closest to the center first.
+pub const MAX_NEW_TILES_PER_FRAME: usize = 8;
+
+pub struct Residency {
+    tile_power: u32,
+    /// Number of the tiles along X and Y of the level.
+    tile_count: (u32, u32),
+    /// Slot of each tile, row by row.
+    page_table: Vec<u32>,
+    /// Tile in each slot, with the frame it was needed the last time.
+    slots: Vec<Option<(u32, u64)>>,
+    frame: u64,
+}
+
+impl Residency {
+    /// The tile size has to be a power of two, not larger than the level.
+    pub fn new(level_size: (i32, i32), tile_size: u32, slot_count: usize) -> Self {
+        assert!(tile_size.is_power_of_two());
+        let tile_count = (
+            (level_size.0 as u32 / tile_size).max(1),
+            (level_size.1 as u32 / tile_size).max(1),
+        );
+        let total = (tile_count.0 * tile_count.1) as usize;
+        Residency {
+            tile_power: tile_size.trailing_zeros(),
+            tile_count,
+            page_table: vec![NOT_RESIDENT; total],
+            slots: vec![None; slot_count.min(total)],
+            frame: 0,
+        }
+    }
+
+    pub fn tile_size(&self) -> u32 {
+        1 << self.tile_power
+    }
+
+    pub fn tile_count(&self) -> (u32, u32) {
+        self.tile_count
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Slot of each tile, row by row, as the shaders see it.
+    pub fn page_table(&self) -> &[u32] {
+        &self.page_table
+    }
+
+    /// Number of the tiles that have a slot.
+    pub fn resident_count(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_some()).count()
+    }
+
+    /// Level texels covered by the tile.
+    pub fn tile_rect(&self, tile: u32) -> Rect {
+        let size = self.tile_size();
+        Rect {
+            x: ((tile % self.tile_count.0) * size) as u16,
+            y: ((tile / self.tile_count.0) * size) as u16,
+            w: size as u16,
+            h: size as u16,
+        }
+    }
+
+    /// Indices of the tiles overlapping the area, which wraps around the level.
+    fn tiles_in(&self, area: &Rect) -> Vec<u32> {
+        let range = |start: u16, size: u16, count: u32| {
+            let first = start as u32 >> self.tile_power;
+            let last = (start as u32 + size.max(1) as u32 - 1) >> self.tile_power;
+            (first..=last.min(first + count - 1)).map(move |t| t % count)
+        };
+        let mut tiles = Vec::new();
+        for ty in range(area.y, area.h, self.tile_count.1) {
+            for tx in range(area.x, area.w, self.tile_count.0) {
+                tiles.push(ty * self.tile_count.0 + tx);
+            }
+        }
+        tiles
+    }
+
+    /// Resident tiles overlapping the area, with their slots.
+    pub fn resident_in(&self, area: &Rect) -> Vec<(u32, u32)> {
+        self.tiles_in(area)
+            .into_iter()
+            .filter_map(|tile| match self.page_table[tile as usize] {
+                NOT_RESIDENT => None,
+                slot => Some((tile, slot)),
+            })
+            .collect()
+    }
+
+    /// Start a new frame needing the tiles of the area, and give slots
+    /// to the missing ones, closest to the center texel first.
+    /// Returns the newly resident tiles with their slots.
+    pub fn update(&mut self, area: &Rect, center: (i32, i32)) -> Vec<(u32, u32)> {
+        self.frame += 1;
+        let mut missing = Vec::new();
+        for tile in self.tiles_in(area) {
+            match self.page_table[tile as usize] {
+                NOT_RESIDENT => missing.push(tile),
+                slot => self.slots[slot as usize] = Some((tile, self.frame)),
+            }
+        }
+
+        let (count_x, count_y) = (self.tile_count.0 as i32, self.tile_count.1 as i32);
+        let center_tile = (
+            (center.0 >> self.tile_power).rem_euclid(count_x),
+            (center.1 >> self.tile_power).rem_euclid(count_y),
+        );
+        let distance = |tile: u32| {
+            let dx = (tile as i32 % count_x - center_tile.0).rem_euclid(count_x);
+            let dy = (tile as i32 / count_x - center_tile.1).rem_euclid(count_y);
+            dx.min(count_x - dx).max(dy.min(count_y - dy))
+        };
+        missing.sort_by_key(|&tile| distance(tile));
+
+        let mut added = Vec::new();
+        for tile in missing.into_iter().take(MAX_NEW_TILES_PER_FRAME) {
+            let frame = self.frame;
+            let slot = match self.slots.iter().position(|s| s.is_none()) {
+                Some(slot) => slot,
+                // the least recently needed tile, if it's not needed now
+                None => match self
+                    .slots
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, s)| s.map(|(_, used)| (i, used)))
+                    .filter(|&(_, used)| used != frame)
+                    .min_by_key(|&(_, used)| used)
+                {
+                    Some((slot, _)) => slot,
+                    None => break,
+                },
+            };
+            if let Some((old, _)) = self.slots[slot] {
+                self.page_table[old as usize] = NOT_RESIDENT;
+            }
+            self.slots[slot] = Some((tile, frame));
+            self.page_table[tile as usize] = slot as u32;
+            added.push((tile, slot as u32));
+        }
+        added
+    }
+}
+
+/// Tile waiting for its data to be uploaded into the slot.
+struct PendingTile {
+    tile: u32,
+    slot: u32,
+    /// The slot has the data of another tile, or none yet.
+    fresh: bool,
+}
+
+/// Tiles waiting for their data, oldest first.
+#[derive(Default)]
+pub struct UploadQueue {
+    tiles: Vec<PendingTile>,
+}
+
+impl UploadQueue {
+    /// Queue the data of the tile in the slot. A fresh tile, which is new
+    /// to the slot, stays hidden from the shaders until its data is uploaded.
+    pub fn push(&mut self, tile: u32, slot: u32, fresh: bool) {
+        match self
+            .tiles
+            .iter_mut()
+            .find(|p| p.tile == tile && p.slot == slot)
+        {
+            Some(pending) => pending.fresh |= fresh,
+            None => self.tiles.push(PendingTile { tile, slot, fresh }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Take up to `count` tiles to upload, with their slots.
+    /// The tiles that lost their slots meanwhile are dropped.
+    pub fn take(&mut self, residency: &Residency, count: usize) -> Vec<(u32, u32)> {
+        self.tiles
+            .retain(|p| residency.page_table[p.tile as usize] == p.slot);
+        let count = count.min(self.tiles.len());
+        self.tiles
+            .drain(..count)
+            .map(|p| (p.tile, p.slot))
+            .collect()
+    }
+
+    /// Page table as the shaders should see it, without the fresh tiles.
+    pub fn visible_pages(&self, residency: &Residency) -> Vec<u32> {
+        let mut pages = residency.page_table.clone();
+        for pending in self.tiles.iter().filter(|p| p.fresh) {
+            if pages[pending.tile as usize] == pending.slot {
+                pages[pending.tile as usize] = NOT_RESIDENT;
+            }
+        }
+        pages
+    }
+}
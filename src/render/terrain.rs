@@ -1,20 +1,29 @@
 use crate::{
     config::settings,
-    level,
+    jobs, level,
+    locale::{tr, tr_fmt},
     render::{
-        global::Context as GlobalContext, Palette, PipelineKind, DEPTH_FORMAT, SHADOW_FORMAT,
+        frame_graph,
+        global::{Constants as GlobalConstants, Context as GlobalContext},
+        memory,
+        mip_queue::MipQueue,
+        mipmap,
+        streaming::{Residency, UploadQueue, MAX_NEW_TILES_PER_FRAME},
+        Palette, PipelineKind, DEPTH_FORMAT, SHADOW_FORMAT,
     },
-    space::Camera,
+    space::{coords::WorldPos, Camera},
 };
 
 use bytemuck::{Pod, Zeroable};
 use wgpu::util::DeviceExt as _;
 
-use std::{mem, ops::Range};
+use std::{mem, ops::Range, sync::mpsc};
 
 const SCATTER_GROUP_SIZE: [u32; 3] = [16, 16, 1];
-// Has to agree with the shader
-const VOXEL_TILE_SIZE: u32 = 8;
+/// Number of level rows worth a separate job when staging the updates.
+const ROWS_PER_JOB: usize = 64;
+/// Size of a voxel tile, substituted as `morton_tile_size` into the voxel shaders.
+pub const VOXEL_TILE_SIZE: u32 = 8;
 fn count_tiles(size: u32) -> u32 {
     (size - 1) / VOXEL_TILE_SIZE + 1
 }
@@ -35,14 +44,14 @@ struct SurfaceConstants {
     texture_scale: [f32; 4],
     terrain_bits: u32,
     delta_mode: u32,
-    pad0: u32,
-    pad1: u32,
+    tile_power: u32,
+    tile_slots_per_row: u32,
 }
 unsafe impl Pod for SurfaceConstants {}
 unsafe impl Zeroable for SurfaceConstants {}
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 struct Constants {
     screen_rect: [u32; 4], // x, y, w, h
     cam_origin_dir: [f32; 4],
@@ -50,10 +59,25 @@ struct Constants {
     fog_color: [f32; 3],
     pad: f32,
     fog_params: [f32; 4],
+    cutaway: [f32; 4], // x, y, radius, strength
+    debug_mode: [u32; 4],
+    palette_layer: [u32; 4],
 }
 unsafe impl Pod for Constants {}
 unsafe impl Zeroable for Constants {}
 
+/// Mip levels of the palette, smoothing the color gradients at grazing angles.
+/// Past that, the color ranges of different terrain types bleed into each other.
+const PALETTE_MIP_LEVELS: u32 = 4;
+
+/// Inputs of the last `prepare()`, used to skip the redundant work
+/// when neither the camera nor the level have changed.
+#[derive(Clone, Copy, PartialEq)]
+struct Prepared {
+    locals: Constants,
+    globals: GlobalConstants,
+}
+
 struct ScatterConstants {
     origin: cgmath::Point2<f32>,
     dir: cgmath::Vector2<f32>,
@@ -62,10 +86,11 @@ struct ScatterConstants {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 struct VoxelConstants {
     voxel_size: [u32; 3],
-    pad: u32,
+    /// LODs that are up to date, the coarser ones are skipped by the rays.
+    ready_lod_count: u32,
     max_depth: f32,
     debug_alpha: f32,
     max_outer_steps: u32,
@@ -85,6 +110,35 @@ struct BakeConstants {
 unsafe impl Pod for BakeConstants {}
 unsafe impl Zeroable for BakeConstants {}
 
+/// Write the voxel constants into the buffer, unless they are already there.
+fn update_voxel_constants(
+    encoder: &mut wgpu::CommandEncoder,
+    device: &wgpu::Device,
+    stats: &mut super::Stats,
+    label: &'static str,
+    buffer: &wgpu::Buffer,
+    uploaded: &mut VoxelConstants,
+    constants: VoxelConstants,
+) {
+    if *uploaded == constants {
+        return;
+    }
+    *uploaded = constants;
+    let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: bytemuck::bytes_of(&constants),
+        usage: wgpu::BufferUsages::COPY_SRC,
+    });
+    stats.add_buffer(label, mem::size_of::<VoxelConstants>());
+    encoder.copy_buffer_to_buffer(
+        &staging,
+        0,
+        buffer,
+        0,
+        mem::size_of::<VoxelConstants>() as _,
+    );
+}
+
 impl BakeConstants {
     fn init_workgroups(&self, wg_size: [i32; 3]) -> [u32; 3] {
         let mut wg_count = [0u32; 3];
@@ -181,10 +235,14 @@ struct Geometry {
     vertex_buf: wgpu::Buffer,
     index_buf: wgpu::Buffer,
     num_indices: u32,
+    _memory: memory::Allocation,
 }
 
 impl Geometry {
     fn new(vertices: &[Vertex], indices: &[u16], device: &wgpu::Device) -> Self {
+        let mut memory = memory::Allocation::new(memory::Subsystem::Terrain);
+        memory.add_buffer(device, "terrain-vertex", mem::size_of_val(vertices) as _);
+        memory.add_buffer(device, "terrain-index", mem::size_of_val(indices) as _);
         Geometry {
             vertex_buf: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("terrain-vertex"),
@@ -197,6 +255,7 @@ impl Geometry {
                 usage: wgpu::BufferUsages::INDEX,
             }),
             num_indices: indices.len() as u32,
+            _memory: memory,
         }
     }
 }
@@ -214,21 +273,232 @@ unsafe impl Zeroable for VoxelMip {}
 #[derive(Clone, Copy)]
 struct VoxelHeader {
     lod_count: u32,
-    pad: [u32; 3],
+    /// Offset of the water bits, laid out as the finest LOD.
+    water_offset_in_words: u32,
+    pad: [u32; 2],
     mips: [VoxelMip; 16],
 }
 unsafe impl Pod for VoxelHeader {}
 unsafe impl Zeroable for VoxelHeader {}
 
+fn compute_voxel_mips(grid_extent: wgpu::Extent3d) -> (Vec<VoxelMip>, u32) {
+    let mip_level_count = 32
+        - grid_extent
+            .width
+            .min(grid_extent.height)
+            .min(grid_extent.depth_or_array_layers)
+            .leading_zeros();
+    let mut data_offset_in_words = 0;
+    let mut mips = Vec::new();
+    for base_mip_level in 0..mip_level_count {
+        let mip_extent = grid_extent.mip_level_size(base_mip_level, wgpu::TextureDimension::D3);
+        mips.push(VoxelMip {
+            extent: mip_extent,
+            data_offset_in_words,
+        });
+        let tile_count = count_tiles(mip_extent.width)
+            * count_tiles(mip_extent.height)
+            * count_tiles(mip_extent.depth_or_array_layers);
+        data_offset_in_words += tile_count * VOXEL_TILE_SIZE.pow(3) / 32;
+    }
+    (mips, data_offset_in_words)
+}
+
 struct VoxelDebugRender {
     pipeline: wgpu::RenderPipeline,
     geo: Geometry,
     lod_range: Option<Range<usize>>,
 }
 
+// Has to agree with "steps.inc.wgsl"
+const STEP_HISTOGRAM_SIZE: usize = 16;
+/// Maximum number of steps of the "Ray" kind, as `c_MaxSteps` in "ray.wgsl".
+const RAY_MAX_STEPS: u32 = 21;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct StepCounters {
+    pixels: u32,
+    total: u32,
+    max: u32,
+    pad: u32,
+    histogram: [u32; STEP_HISTOGRAM_SIZE],
+}
+unsafe impl Pod for StepCounters {}
+unsafe impl Zeroable for StepCounters {}
+
+/// Ray marching cost over a frame.
+#[derive(Clone, Debug)]
+pub struct StepReport {
+    pub pixels: u32,
+    pub average: f32,
+    pub max: u32,
+    /// Step limit of the frame, covered by the histogram.
+    pub limit: u32,
+    /// Number of pixels in each of the equal step ranges up to `limit`.
+    pub histogram: [u32; STEP_HISTOGRAM_SIZE],
+}
+
+enum Readback {
+    Idle,
+    /// The counters are copied, waiting for the submission.
+    Copied {
+        limit: u32,
+    },
+    Mapping {
+        limit: u32,
+        receiver: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+    },
+}
+
+/// Step counters gathered by the shaders, and read back a few frames later.
+struct StepStats {
+    buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    readback: Readback,
+    collect: bool,
+    report: Option<StepReport>,
+}
+
+impl StepStats {
+    fn new(device: &wgpu::Device) -> Self {
+        let size = mem::size_of::<StepCounters>() as wgpu::BufferAddress;
+        StepStats {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Step counters"),
+                size,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Step counters readback"),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            readback: Readback::Idle,
+            collect: false,
+            report: None,
+        }
+    }
+
+    fn bind_group_layout_entry() -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(mem::size_of::<StepCounters>() as _),
+            },
+            count: None,
+        }
+    }
+
+    /// Advance the readback, and reset the counters for the new frame.
+    fn update(&mut self, encoder: &mut wgpu::CommandEncoder, limit: u32) {
+        self.readback = match mem::replace(&mut self.readback, Readback::Idle) {
+            Readback::Idle if self.collect => {
+                encoder.copy_buffer_to_buffer(
+                    &self.buffer,
+                    0,
+                    &self.readback_buffer,
+                    0,
+                    mem::size_of::<StepCounters>() as _,
+                );
+                Readback::Copied { limit }
+            }
+            Readback::Idle => Readback::Idle,
+            Readback::Copied { limit } => {
+                let (sender, receiver) = mpsc::channel();
+                self.readback_buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        let _ = sender.send(result);
+                    });
+                Readback::Mapping { limit, receiver }
+            }
+            Readback::Mapping { limit, receiver } => match receiver.try_recv() {
+                Ok(Ok(())) => {
+                    let counters = *bytemuck::from_bytes::<StepCounters>(
+                        &self.readback_buffer.slice(..).get_mapped_range(),
+                    );
+                    self.readback_buffer.unmap();
+                    self.report = Some(StepReport {
+                        pixels: counters.pixels,
+                        average: counters.total as f32 / counters.pixels.max(1) as f32,
+                        max: counters.max,
+                        limit,
+                        histogram: counters.histogram,
+                    });
+                    Readback::Idle
+                }
+                Ok(Err(e)) => {
+                    log::error!("Unable to read the step counters: {}", e);
+                    Readback::Idle
+                }
+                Err(mpsc::TryRecvError::Empty) => Readback::Mapping { limit, receiver },
+                Err(mpsc::TryRecvError::Disconnected) => Readback::Idle,
+            },
+        };
+        if self.collect {
+            encoder.clear_buffer(&self.buffer, 0, None);
+        }
+    }
+
+    fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.collect, tr("Collect step stats"));
+        if !self.collect {
+            return;
+        }
+        let report = match self.report {
+            Some(ref report) => report,
+            None => return,
+        };
+        ui.label(tr_fmt(
+            "Steps: {} average, {} max of {}, over {} pixels",
+            &[
+                &format!("{:.1}", report.average),
+                &report.max,
+                &report.limit,
+                &report.pixels,
+            ],
+        ));
+        let bucket_size = report.limit as f64 / STEP_HISTOGRAM_SIZE as f64;
+        let bars = report
+            .histogram
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| {
+                let share = count as f64 / report.pixels.max(1) as f64;
+                egui::plot::Bar::new((i as f64 + 0.5) * bucket_size, share).width(bucket_size)
+            })
+            .collect();
+        egui::plot::Plot::new("Step histogram")
+            .height(80.0)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .allow_drag(false)
+            .include_y(0.0)
+            .show(ui, |plot_ui| {
+                plot_ui.bar_chart(egui::plot::BarChart::new(bars).name("pixel share"));
+            });
+    }
+}
+
+/// Resources of the "Ray" kind for gathering the step counters.
+struct RayStatsBinding {
+    pipeline_layout: wgpu::PipelineLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+#[allow(clippy::large_enum_variant)]
 enum Kind {
     Ray {
         pipeline: wgpu::RenderPipeline,
+        stats: Option<RayStatsBinding>,
     },
     RayVoxel {
         bake_pipeline_layout: wgpu::PipelineLayout,
@@ -240,6 +510,12 @@ enum Kind {
         bake_bind_group: wgpu::BindGroup,
         draw_bind_group: wgpu::BindGroup,
         constant_buffer: wgpu::Buffer,
+        /// Last constants written into `constant_buffer`.
+        constants: VoxelConstants,
+        /// Same as the draw ones, with the constants of the shadow pass.
+        shadow_bind_group: wgpu::BindGroup,
+        shadow_constant_buffer: wgpu::Buffer,
+        shadow_constants: VoxelConstants,
         update_buffer: wgpu::Buffer,
         voxel_size: [u32; 3],
         max_outer_steps: u32,
@@ -249,6 +525,10 @@ enum Kind {
         debug_alpha: f32,
         debug_render: Option<VoxelDebugRender>,
         mips: Vec<VoxelMip>,
+        dropped_lods: u32,
+        /// Regions of the baked updates that wait for their mips.
+        mip_queue: MipQueue<BakeConstants>,
+        memory: memory::Allocation,
     },
     Slice {
         pipeline: wgpu::RenderPipeline,
@@ -268,6 +548,7 @@ enum Kind {
         bind_group: wgpu::BindGroup,
         compute_groups: [u32; 3],
         density: [u32; 3],
+        _memory: memory::Allocation,
     },
 }
 
@@ -282,16 +563,223 @@ enum ShadowKind {
     },
 }
 
+/// Visualization of the raw terrain data, independent of the palette.
+/// Has to match the `c_Debug*` constants in "color.inc.wgsl".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugMode {
+    None = 0,
+    /// Grayscale height of the hit point.
+    Height = 1,
+    /// Distinct color per terrain type.
+    TerrainType = 2,
+    /// Double-layer texels, with the lower and upper layers tinted differently.
+    DeltaMask = 3,
+    /// Voxel grid LOD at which the ray hit.
+    VoxelLod = 4,
+    /// Heat map of the ray marching steps.
+    StepCount = 5,
+}
+
+impl DebugMode {
+    const COMMON: [Self; 4] = [Self::None, Self::Height, Self::TerrainType, Self::DeltaMask];
+    const RAY: [Self; 5] = [
+        Self::None,
+        Self::Height,
+        Self::TerrainType,
+        Self::DeltaMask,
+        Self::StepCount,
+    ];
+    const RAY_VOXEL: [Self; 6] = [
+        Self::None,
+        Self::Height,
+        Self::TerrainType,
+        Self::DeltaMask,
+        Self::VoxelLod,
+        Self::StepCount,
+    ];
+}
+
+/// Area where the upper layer of the stacked terrain is faded out.
+#[derive(Clone, Copy, Debug)]
+pub struct Cutaway {
+    pub center: cgmath::Point2<f32>,
+    pub radius: f32,
+    pub strength: f32,
+}
+
+impl Cutaway {
+    pub fn new(
+        level: &level::Level,
+        pos: cgmath::Vector3<f32>,
+        config: &settings::Cutaway,
+    ) -> Option<Self> {
+        if config.radius <= 0.0 {
+            return None;
+        }
+        let depth = level.get_at(pos).depth_underground(pos.z)?;
+        Some(Cutaway {
+            center: cgmath::Point2::new(pos.x, pos.y),
+            radius: config.radius,
+            strength: (depth / config.depth.max(1.0)).min(1.0),
+        })
+    }
+}
+
+/// Altitude above which the terrain is cut off, drawn with the slices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SliceView {
+    pub ceiling: f32,
+}
+
+/// Switch into the slice view while the player is under a ceiling, and back.
+///
+/// The view switches once the player gets deep enough under the upper layer,
+/// and only switches back after staying out from under it for a while,
+/// so driving along the edge of a cave doesn't flicker. The cut goes down from
+/// the top of the level to the ceiling over the transition, and back up.
+#[derive(Debug, Default)]
+pub struct SliceTransition {
+    /// Progress of the transition, 0 for the normal view and 1 for the full cut.
+    blend: f32,
+    is_under: bool,
+    /// Seconds since the player got out from under the ceiling.
+    outside_time: f32,
+    /// Altitude the cut goes down to.
+    ceiling: f32,
+}
+
+impl SliceTransition {
+    /// Follow the player at `pos` over `dt` seconds.
+    pub fn update(
+        &mut self,
+        level: &level::Level,
+        pos: cgmath::Vector3<f32>,
+        dt: f32,
+        config: &settings::Underground,
+    ) -> Option<SliceView> {
+        if config.enter_depth <= 0.0 {
+            *self = Self::default();
+            return None;
+        }
+        let step = if config.duration > 0.0 {
+            (dt / config.duration).min(1.0)
+        } else {
+            1.0
+        };
+        match level.get_at(pos) {
+            level::Texel::Dual { low, mid, .. } if pos.z >= low.0 && pos.z < mid => {
+                self.outside_time = 0.0;
+                if mid - pos.z >= config.enter_depth {
+                    if !self.is_under && self.blend <= 0.0 {
+                        self.ceiling = mid;
+                    }
+                    self.is_under = true;
+                }
+                if self.is_under {
+                    // the ceiling changes from texel to texel
+                    self.ceiling += (mid - self.ceiling) * step;
+                }
+            }
+            _ => {
+                self.outside_time += dt;
+                if self.outside_time >= config.exit_delay {
+                    self.is_under = false;
+                }
+            }
+        }
+        self.blend = if self.is_under {
+            (self.blend + step).min(1.0)
+        } else {
+            (self.blend - step).max(0.0)
+        };
+        if self.blend <= 0.0 {
+            return None;
+        }
+        let top = level.geometry.height as f32;
+        let t = self.blend * self.blend * (3.0 - 2.0 * self.blend);
+        Some(SliceView {
+            ceiling: top + (self.ceiling - top) * t,
+        })
+    }
+}
+
 pub struct Flood {
     pub texture: wgpu::Texture,
     pub texture_size: u32,
     pub section_size: (u32, u32),
 }
 
+/// Staging buffers of the tile uploads, enough to cover the frames in flight.
+const TILE_STAGING_BUFFERS: usize = 3;
+
+enum StagingState {
+    /// Mapped, ready for the tile data.
+    Ready,
+    /// Copied from in this frame, to be mapped after the submission.
+    Used,
+    Mapping(mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>),
+}
+
+/// Staging buffer of `MAX_NEW_TILES_PER_FRAME` tiles, mapped again after each use.
+struct TileStaging {
+    buffer: wgpu::Buffer,
+    state: StagingState,
+}
+
+impl TileStaging {
+    /// Advance the mapping, without waiting for it.
+    fn advance(&mut self) {
+        self.state = match mem::replace(&mut self.state, StagingState::Ready) {
+            StagingState::Ready => StagingState::Ready,
+            StagingState::Used => {
+                let (sender, receiver) = mpsc::channel();
+                self.buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Write, move |result| {
+                        let _ = sender.send(result);
+                    });
+                StagingState::Mapping(receiver)
+            }
+            StagingState::Mapping(receiver) => match receiver.try_recv() {
+                Ok(Ok(())) => StagingState::Ready,
+                Ok(Err(e)) => {
+                    log::error!("Unable to map the tile staging: {}", e);
+                    StagingState::Used
+                }
+                Err(mpsc::TryRecvError::Empty) => StagingState::Mapping(receiver),
+                Err(mpsc::TryRecvError::Disconnected) => StagingState::Used,
+            },
+        };
+    }
+}
+
+/// Tiles of the streamed terrain, in the slots of the terrain texture.
+struct Streaming {
+    residency: Residency,
+    margin: u32,
+    slots_per_row: u32,
+    page_texture: wgpu::Texture,
+    dirty_pages: bool,
+    /// Tiles of the slots that need their data.
+    uploads: UploadQueue,
+    staging: Vec<TileStaging>,
+}
+
+impl Streaming {
+    /// Layout of a tile in the staging buffers: bytes per row, and in total.
+    fn tile_layout(&self) -> (u32, usize) {
+        let tile_size = self.residency.tile_size();
+        let bytes_per_row = wgpu::util::align_to(tile_size * 2, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        (bytes_per_row, bytes_per_row as usize * tile_size as usize)
+    }
+}
+
 pub struct Context {
     surface_uni_buf: wgpu::Buffer,
     pub uniform_buf: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
+    shadow_uniform_buf: wgpu::Buffer,
+    shadow_bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pipeline_layout: wgpu::PipelineLayout,
     color_format: wgpu::TextureFormat,
@@ -299,15 +787,52 @@ pub struct Context {
     kind: Kind,
     shadow_kind: ShadowKind,
     terrain_texture: wgpu::Texture,
-    palette_texture: wgpu::Texture,
+    streaming: Option<Streaming>,
+    palette: Palette,
+    palette_mips: mipmap::Generator,
+    /// Layers uploaded with `set_palette`, for which the mips are not generated yet.
+    dirty_palette_layers: Vec<u32>,
     pub flood: Flood,
     pub dirty_rects: Vec<super::DirtyRect>,
     pub dirty_flood: bool,
     pub dirty_palette: Range<u32>,
+    pub cutaway: Option<Cutaway>,
+    /// Draw the slices under the ceiling instead, if set.
+    pub slice_view: Option<SliceView>,
+    /// Pipeline of the slice view, unless the terrain is sliced already.
+    view_slice_pipeline: Option<wgpu::RenderPipeline>,
+    slice_layer_count: u32,
+    pub debug_mode: DebugMode,
+    /// Layer of the palette array to draw with, see `set_palette`.
+    pub palette_layer: u32,
+    step_stats: Option<StepStats>,
     active_surface_constants: SurfaceConstants,
+    prepared: Option<Prepared>,
+    prepared_shadow: Option<Constants>,
+    _memory: memory::Allocation,
 }
 
 impl Context {
+    fn create_ray_color_pipeline(
+        layout: &wgpu::PipelineLayout,
+        stats: Option<&RayStatsBinding>,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let (layout, entry_point) = match stats {
+            Some(binding) => (&binding.pipeline_layout, "ray_color_stats"),
+            None => (layout, "ray_color"),
+        };
+        Self::create_ray_pipeline(
+            layout,
+            device,
+            color_format,
+            "terrain/ray",
+            PipelineKind::Main,
+            entry_point,
+        )
+    }
+
     fn create_ray_pipeline(
         layout: &wgpu::PipelineLayout,
         device: &wgpu::Device,
@@ -437,12 +962,12 @@ impl Context {
             label: Some("terrain-ray-voxel"),
             layout: Some(pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &draw_shader,
+                module: draw_shader,
                 entry_point: "main",
                 buffers: &[],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &draw_shader,
+                module: draw_shader,
                 entry_point: "draw_depth",
                 targets: &[],
             }),
@@ -632,10 +1157,13 @@ impl Context {
         extent: wgpu::Extent3d,
         layout: &wgpu::BindGroupLayout,
         device: &wgpu::Device,
-    ) -> (wgpu::BindGroup, [u32; 3]) {
+    ) -> (wgpu::BindGroup, [u32; 3], memory::Allocation) {
+        let size = 4 * (extent.width * extent.height) as wgpu::BufferAddress;
+        let mut memory = memory::Allocation::new(memory::Subsystem::Terrain);
+        memory.add_buffer(device, "Scatter", size);
         let storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Scatter"),
-            size: 4 * (extent.width * extent.height) as wgpu::BufferAddress,
+            size,
             usage: wgpu::BufferUsages::STORAGE,
             mapped_at_creation: false,
         });
@@ -655,7 +1183,7 @@ impl Context {
                 + (extent.height % SCATTER_GROUP_SIZE[1]).min(1),
             1,
         ];
-        (bind_group, group_count)
+        (bind_group, group_count, memory)
     }
 
     pub fn new(
@@ -665,6 +1193,7 @@ impl Context {
         global: &GlobalContext,
         config: &settings::Terrain,
         shadow_config: &settings::ShadowTerrain,
+        underground: &settings::Underground,
     ) -> Self {
         profiling::scope!("Init Terrain");
 
@@ -672,13 +1201,20 @@ impl Context {
             .downlevel_caps
             .flags
             .contains(wgpu::DownlevelFlags::VERTEX_STORAGE);
+        // the limits requested for some of the terrains have no storage buffers at all
+        let supports_fragment_storage = gfx
+            .downlevel_caps
+            .flags
+            .contains(wgpu::DownlevelFlags::FRAGMENT_WRITABLE_STORAGE)
+            && gfx.device.limits().max_storage_buffers_per_shader_stage > 0;
 
         let extent = wgpu::Extent3d {
             width: level.size.0.as_value() as u32,
             height: level.size.1.as_value() as u32,
             depth_or_array_layers: 1,
         };
-        let flood_section_count = extent.height >> level.section.as_power();
+        // levels shorter than a section, like the flat test one, still have one
+        let flood_section_count = (extent.height >> level.section.as_power()).max(1);
         let table_extent = wgpu::Extent3d {
             width: level.terrains.len() as u32,
             height: 1,
@@ -698,11 +1234,42 @@ impl Context {
             })
             .collect::<Vec<_>>();
 
-        let terrain_texture = gfx.device.create_texture(&wgpu::TextureDescriptor {
+        let mut memory = memory::Allocation::new(memory::Subsystem::Terrain);
+        let (streaming_tiles, terrain_extent) = match *config {
+            settings::Terrain::Streamed {
+                tile_size,
+                margin,
+                max_tiles,
+            } => {
+                let tile_size = tile_size
+                    .next_power_of_two()
+                    .clamp(2, extent.width.min(extent.height));
+                let residency = Residency::new(
+                    (extent.width as i32, extent.height as i32),
+                    tile_size,
+                    max_tiles.max(1) as usize,
+                );
+                let slot_count = residency.slot_count() as u32;
+                let slots_per_row = (slot_count as f32).sqrt().ceil() as u32;
+                let slot_rows = slot_count.div_ceil(slots_per_row);
+                info!(
+                    "Streaming the terrain in {} tiles of {}x{}",
+                    slot_count, tile_size, tile_size
+                );
+                let extent = wgpu::Extent3d {
+                    width: slots_per_row * tile_size,
+                    height: slot_rows * tile_size,
+                    depth_or_array_layers: 1,
+                };
+                (Some((residency, margin, slots_per_row)), extent)
+            }
+            _ => (None, extent),
+        };
+        let terrain_desc = wgpu::TextureDescriptor {
             label: Some("Terrain data"),
             size: wgpu::Extent3d {
-                width: extent.width / 2,
-                ..extent
+                width: terrain_extent.width / 2,
+                ..terrain_extent
             },
             mip_level_count: 1,
             sample_count: 1,
@@ -710,7 +1277,35 @@ impl Context {
             format: wgpu::TextureFormat::Rgba8Uint,
             view_formats: &[],
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-        });
+        };
+        memory.add_texture(&gfx.device, &terrain_desc);
+        let terrain_texture = gfx.device.create_texture(&terrain_desc);
+
+        // a single tile that is never used, when not streaming
+        let page_extent = streaming_tiles.as_ref().map_or(
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            |(residency, _, _)| wgpu::Extent3d {
+                width: residency.tile_count().0,
+                height: residency.tile_count().1,
+                depth_or_array_layers: 1,
+            },
+        );
+        let page_desc = wgpu::TextureDescriptor {
+            label: Some("Terrain tiles"),
+            size: page_extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Uint,
+            view_formats: &[],
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        };
+        memory.add_texture(&gfx.device, &page_desc);
+        let page_texture = gfx.device.create_texture(&page_desc);
 
         let flood_texture = gfx.device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Terrain flood"),
@@ -748,7 +1343,8 @@ impl Context {
             table_extent,
         );
 
-        let palette = Palette::new(&gfx.device);
+        let palette = Palette::new(&gfx.device, PALETTE_MIP_LEVELS);
+        let palette_mips = palette.create_mip_generator(&gfx.device);
 
         let flood_sampler = gfx.device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
@@ -807,6 +1403,17 @@ impl Context {
                             },
                             count: None,
                         },
+                        // page table of the streamed terrain
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::all(),
+                            ty: wgpu::BindingType::Texture {
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Uint,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
                         // flood map
                         wgpu::BindGroupLayoutEntry {
                             binding: 4,
@@ -834,7 +1441,7 @@ impl Context {
                             binding: 6,
                             visibility: wgpu::ShaderStages::FRAGMENT,
                             ty: wgpu::BindingType::Texture {
-                                view_dimension: wgpu::TextureViewDimension::D1,
+                                view_dimension: wgpu::TextureViewDimension::D2Array,
                                 sample_type: wgpu::TextureSampleType::Float { filterable: true },
                                 multisampled: false,
                             },
@@ -869,50 +1476,94 @@ impl Context {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
+        let shadow_uniform_buf = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Terrain shadow uniforms"),
+            size: mem::size_of::<Constants>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
-        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Terrain"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: surface_uni_buf.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: uniform_buf.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(
-                        &terrain_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::TextureView(
-                        &flood_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::TextureView(
-                        &table_texture.create_view(&wgpu::TextureViewDescriptor::default()),
-                    ),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::TextureView(&palette.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 8,
-                    resource: wgpu::BindingResource::Sampler(&flood_sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 9,
-                    resource: wgpu::BindingResource::Sampler(&table_sampler),
-                },
-            ],
+        let create_bind_group = |label, uniform_buf: &wgpu::Buffer| {
+            gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: surface_uni_buf.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: uniform_buf.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(
+                            &terrain_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(
+                            &page_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(
+                            &flood_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::TextureView(
+                            &table_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: wgpu::BindingResource::TextureView(&palette.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 8,
+                        resource: wgpu::BindingResource::Sampler(&flood_sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 9,
+                        resource: wgpu::BindingResource::Sampler(&table_sampler),
+                    },
+                ],
+            })
+        };
+        let bind_group = create_bind_group("Terrain", &uniform_buf);
+        // the shadow pass has its own constants, so that both can stay cached
+        let shadow_bind_group = create_bind_group("Terrain shadow", &shadow_uniform_buf);
+
+        // the bind group keeps the unused page table alive
+        let streaming = streaming_tiles.map(|(residency, margin, slots_per_row)| {
+            let mut streaming = Streaming {
+                residency,
+                margin,
+                slots_per_row,
+                page_texture,
+                dirty_pages: true,
+                uploads: UploadQueue::default(),
+                staging: Vec::new(),
+            };
+            let size = (streaming.tile_layout().1 * MAX_NEW_TILES_PER_FRAME) as wgpu::BufferAddress;
+            for _ in 0..TILE_STAGING_BUFFERS {
+                memory.add_buffer(&gfx.device, "Tile staging", size);
+                streaming.staging.push(TileStaging {
+                    buffer: gfx.device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Tile staging"),
+                        size,
+                        usage: wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
+                        mapped_at_creation: true,
+                    }),
+                    state: StagingState::Ready,
+                });
+            }
+            streaming
         });
 
         let pipeline_layout = gfx
@@ -939,23 +1590,62 @@ impl Context {
             &gfx.device,
         );
 
+        let step_stats = match *config {
+            settings::Terrain::RayTraced | settings::Terrain::Streamed { .. }
+                if supports_fragment_storage =>
+            {
+                Some(StepStats::new(&gfx.device))
+            }
+            settings::Terrain::RayVoxelTraced { .. } => Some(StepStats::new(&gfx.device)),
+            _ => None,
+        };
+
         let kind = match *config {
-            settings::Terrain::RayTraced => {
-                let pipeline = Self::create_ray_pipeline(
+            settings::Terrain::RayTraced | settings::Terrain::Streamed { .. } => {
+                let stats = step_stats.as_ref().map(|step_stats| {
+                    let bg_layout =
+                        gfx.device
+                            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                                label: Some("Ray stats"),
+                                entries: &[StepStats::bind_group_layout_entry()],
+                            });
+                    RayStatsBinding {
+                        pipeline_layout: gfx.device.create_pipeline_layout(
+                            &wgpu::PipelineLayoutDescriptor {
+                                label: Some("terrain-ray-stats"),
+                                bind_group_layouts: &[
+                                    &global.bind_group_layout,
+                                    &bind_group_layout,
+                                    &bg_layout,
+                                ],
+                                push_constant_ranges: &[],
+                            },
+                        ),
+                        bind_group: gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                            label: Some("Ray stats"),
+                            layout: &bg_layout,
+                            entries: &[wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: step_stats.buffer.as_entire_binding(),
+                            }],
+                        }),
+                    }
+                });
+                let pipeline = Self::create_ray_color_pipeline(
                     &pipeline_layout,
+                    stats.as_ref(),
                     &gfx.device,
                     gfx.color_format,
-                    "terrain/ray",
-                    PipelineKind::Main,
-                    "ray_color",
                 );
-                Kind::Ray { pipeline }
+                Kind::Ray { pipeline, stats }
             }
             settings::Terrain::RayVoxelTraced {
                 voxel_size,
                 max_outer_steps,
                 max_inner_steps,
                 max_update_texels,
+                max_memory_mb,
+                max_mip_dispatches,
             } => {
                 let bake_bg_layout =
                     gfx.device
@@ -1045,6 +1735,7 @@ impl Context {
                                     },
                                     count: None,
                                 },
+                                StepStats::bind_group_layout_entry(),
                             ],
                         });
                 let draw_pipeline_layout =
@@ -1093,82 +1784,111 @@ impl Context {
                     None
                 };
 
-                let grid_extent = wgpu::Extent3d {
-                    width: (extent.width - 1) / voxel_size[0] + 1,
-                    height: (extent.height - 1) / voxel_size[1] + 1,
-                    depth_or_array_layers: (level_height - 1) / voxel_size[2] + 1,
+                // Dropping the finest LOD is the same as doubling the voxel size.
+                let mut voxel_size = voxel_size;
+                let mut dropped_lods = 0;
+                let (mips, water_offset_in_words, data_size_in_words) = loop {
+                    let grid_extent = wgpu::Extent3d {
+                        width: (extent.width - 1) / voxel_size[0] + 1,
+                        height: (extent.height - 1) / voxel_size[1] + 1,
+                        depth_or_array_layers: (level_height - 1) / voxel_size[2] + 1,
+                    };
+                    let (mips, terrain_words) = compute_voxel_mips(grid_extent);
+                    // the water bits follow, for the finest LOD only
+                    let water_words = mips
+                        .get(1)
+                        .map_or(terrain_words, |mip| mip.data_offset_in_words);
+                    let words = terrain_words + water_words;
+                    let size = words as u64 * 4;
+                    let size_mb = size >> 20;
+                    if max_memory_mb == 0 || size <= (max_memory_mb as u64) << 20 || mips.len() <= 1
+                    {
+                        break (mips, terrain_words, words);
+                    }
+                    log::warn!(
+                        "Voxel grid of {} MB exceeds the budget, dropping LOD {}",
+                        size_mb,
+                        dropped_lods,
+                    );
+                    dropped_lods += 1;
+                    for vs in voxel_size.iter_mut() {
+                        *vs <<= 1;
+                    }
                 };
-                let mip_level_count = 32
-                    - grid_extent
-                        .width
-                        .min(grid_extent.height)
-                        .min(grid_extent.depth_or_array_layers)
-                        .leading_zeros();
+                let mip_level_count = mips.len() as u32;
 
                 assert_eq!(mem::size_of::<VoxelMip>(), 16);
                 let mut header = VoxelHeader {
                     lod_count: mip_level_count,
-                    pad: [0; 3],
+                    water_offset_in_words,
+                    pad: [0; 2],
                     mips: [VoxelMip::default(); 16],
                 };
-                let mut data_offset_in_words = 0;
-                let mut mips = Vec::new();
-                for base_mip_level in 0..mip_level_count {
-                    let mip_extent =
-                        grid_extent.mip_level_size(base_mip_level, wgpu::TextureDimension::D3);
-                    mips.push(VoxelMip {
-                        extent: mip_extent,
-                        data_offset_in_words,
-                    });
-                    header.mips[base_mip_level as usize] = VoxelMip {
-                        extent: mip_extent,
-                        data_offset_in_words,
-                    };
-                    let tile_count = count_tiles(mip_extent.width)
-                        * count_tiles(mip_extent.height)
-                        * count_tiles(mip_extent.depth_or_array_layers);
-                    data_offset_in_words += tile_count * VOXEL_TILE_SIZE.pow(3) / 32;
-                }
+                header.mips[..mips.len()].copy_from_slice(&mips);
                 log::info!(
                     "Allocating {} MB storage buffer for the voxel grid",
-                    data_offset_in_words >> 18
+                    data_size_in_words >> 18
                 );
 
+                let memory_size =
+                    (mem::size_of::<VoxelHeader>() + data_size_in_words as usize * 4) as _;
+                let mut memory = memory::Allocation::new(memory::Subsystem::Voxels);
+                memory.add_buffer(&gfx.device, "Grid", memory_size);
                 let grid = gfx.device.create_buffer(&wgpu::BufferDescriptor {
                     label: Some("Grid"),
-                    size: (mem::size_of::<VoxelHeader>() + data_offset_in_words as usize * 4) as _,
+                    size: memory_size,
                     usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
                     mapped_at_creation: false,
                 });
                 gfx.queue
                     .write_buffer(&grid, 0, bytemuck::bytes_of(&header));
 
-                let constant_buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
-                    label: Some("Voxel constants"),
-                    size: mem::size_of::<VoxelConstants>() as _,
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                    mapped_at_creation: false,
-                });
-                let draw_bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("Voxel draw"),
-                    layout: &draw_bg_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: grid.as_entire_binding(),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: constant_buffer.as_entire_binding(),
-                        },
-                    ],
-                });
+                // the camera depth is only known on the first `prepare`
+                let constants = VoxelConstants {
+                    voxel_size,
+                    ready_lod_count: mip_level_count,
+                    max_depth: 0.0,
+                    debug_alpha: 0.0,
+                    max_outer_steps,
+                    max_inner_steps,
+                };
+                let create_draw_group = |label| {
+                    let buffer = gfx
+                        .device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some(label),
+                            contents: bytemuck::bytes_of(&constants),
+                            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                        });
+                    let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some(label),
+                        layout: &draw_bg_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: grid.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: step_stats.as_ref().unwrap().buffer.as_entire_binding(),
+                            },
+                        ],
+                    });
+                    (buffer, bind_group)
+                };
+                let (constant_buffer, draw_bind_group) = create_draw_group("Voxel draw");
+                let (shadow_constant_buffer, shadow_bind_group) = create_draw_group("Voxel shadow");
 
                 let max_update_rects = 10usize;
                 assert!(mem::size_of::<BakeConstants>() <= MAXIMUM_UNIFORM_BUFFER_ALIGNMENT);
+                // the rects baked this frame, followed by the ones getting their mips
                 let update_buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
                     label: Some("Bake constants"),
-                    size: (MAXIMUM_UNIFORM_BUFFER_ALIGNMENT * max_update_rects)
+                    size: (MAXIMUM_UNIFORM_BUFFER_ALIGNMENT * 2 * max_update_rects)
                         as wgpu::BufferAddress,
                     usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                     mapped_at_creation: false,
@@ -1226,6 +1946,10 @@ impl Context {
                     draw_bind_group,
                     bake_bind_group,
                     constant_buffer,
+                    constants,
+                    shadow_bind_group,
+                    shadow_constant_buffer,
+                    shadow_constants: constants,
                     update_buffer,
                     voxel_size,
                     max_outer_steps,
@@ -1235,6 +1959,9 @@ impl Context {
                     debug_alpha: 0.0,
                     debug_render,
                     mips,
+                    dropped_lods,
+                    mip_queue: MipQueue::new(mip_level_count, max_mip_dispatches),
+                    memory,
                 }
             }
             settings::Terrain::Sliced => {
@@ -1312,7 +2039,7 @@ impl Context {
                         &gfx.device,
                         gfx.color_format,
                     );
-                let (local_bg, compute_groups) =
+                let (local_bg, compute_groups, memory) =
                     Self::create_scatter_resources(gfx.screen_size, &local_bg_layout, &gfx.device);
                 Kind::Scatter {
                     pipeline_layout: local_pipeline_layout,
@@ -1323,6 +2050,7 @@ impl Context {
                     bind_group: local_bg,
                     compute_groups,
                     density,
+                    _memory: memory,
                 }
             }
         };
@@ -1359,11 +2087,22 @@ impl Context {
                 _ => panic!("Unable to inherit the shadow voxel context"),
             },
         };
+        let view_slice_pipeline = match kind {
+            Kind::Slice { .. } => None,
+            _ if underground.enter_depth > 0.0 => Some(Self::create_slice_pipeline(
+                &pipeline_layout,
+                &gfx.device,
+                gfx.color_format,
+            )),
+            _ => None,
+        };
 
         Context {
             surface_uni_buf,
             uniform_buf,
             bind_group,
+            shadow_uniform_buf,
+            shadow_bind_group,
             bind_group_layout,
             pipeline_layout,
             color_format: gfx.color_format,
@@ -1371,7 +2110,10 @@ impl Context {
             kind,
             shadow_kind,
             terrain_texture,
-            palette_texture: palette.texture,
+            streaming,
+            palette,
+            palette_mips,
+            dirty_palette_layers: Vec::new(),
             flood: Flood {
                 texture: flood_texture,
                 texture_size: flood_section_count,
@@ -1392,28 +2134,37 @@ impl Context {
             }],
             dirty_flood: true,
             dirty_palette: 0..0x100,
+            cutaway: None,
+            slice_view: None,
+            view_slice_pipeline,
+            slice_layer_count: level_height,
+            debug_mode: DebugMode::None,
+            palette_layer: 0,
+            step_stats,
             active_surface_constants: SurfaceConstants {
                 texture_scale: [0.0; 4],
                 terrain_bits: 0,
                 delta_mode: 0,
-                pad0: 0,
-                pad1: 0,
+                tile_power: 0,
+                tile_slots_per_row: 0,
             },
+            prepared: None,
+            prepared_shadow: None,
+            _memory: memory,
         }
     }
 
     pub fn reload(&mut self, device: &wgpu::Device) {
         match self.kind {
             Kind::Ray {
-                ref mut pipeline, ..
+                ref mut pipeline,
+                ref stats,
             } => {
-                *pipeline = Self::create_ray_pipeline(
+                *pipeline = Self::create_ray_color_pipeline(
                     &self.pipeline_layout,
+                    stats.as_ref(),
                     device,
                     self.color_format,
-                    "terrain/ray",
-                    PipelineKind::Main,
-                    "ray_color",
                 );
             }
             Kind::RayVoxel {
@@ -1502,6 +2253,11 @@ impl Context {
                 _ => unreachable!(),
             },
         }
+
+        if let Some(ref mut pipeline) = self.view_slice_pipeline {
+            *pipeline =
+                Self::create_slice_pipeline(&self.pipeline_layout, device, self.color_format);
+        }
     }
 
     pub fn resize(&mut self, extent: wgpu::Extent3d, device: &wgpu::Device) {
@@ -1509,13 +2265,14 @@ impl Context {
             ref bg_layout,
             ref mut bind_group,
             ref mut compute_groups,
+            ref mut _memory,
             ..
         } = self.kind
         {
-            let (bg, gs) = Self::create_scatter_resources(extent, bg_layout, device);
-            *bind_group = bg;
-            *compute_groups = gs;
+            (*bind_group, *compute_groups, *_memory) =
+                Self::create_scatter_resources(extent, bg_layout, device);
         }
+        self.prepared = None;
     }
 
     pub fn update_dirty(
@@ -1523,6 +2280,7 @@ impl Context {
         encoder: &mut wgpu::CommandEncoder,
         level: &level::Level,
         device: &wgpu::Device,
+        stats: &mut super::Stats,
     ) {
         let surface_constants = {
             let bits = level.terrain_bits();
@@ -1538,8 +2296,11 @@ impl Context {
                 ],
                 terrain_bits: bits.shift as u32 | ((bits.mask as u32) << 4),
                 delta_mode,
-                pad0: 0,
-                pad1: 0,
+                tile_power: self
+                    .streaming
+                    .as_ref()
+                    .map_or(0, |st| st.residency.tile_size().trailing_zeros()),
+                tile_slots_per_row: self.streaming.as_ref().map_or(0, |st| st.slots_per_row),
             }
         };
         if surface_constants != self.active_surface_constants {
@@ -1549,6 +2310,7 @@ impl Context {
                 contents: bytemuck::bytes_of(&surface_constants),
                 usage: wgpu::BufferUsages::COPY_SRC,
             });
+            stats.add_buffer("temp-surface-uniforms", mem::size_of::<SurfaceConstants>());
             encoder.copy_buffer_to_buffer(
                 &staging_buf,
                 0,
@@ -1569,7 +2331,180 @@ impl Context {
             });
         }
 
-        if !self.dirty_rects.is_empty() {
+        if !self.dirty_rects.is_empty()
+            || self.dirty_flood
+            || self.dirty_palette.start != self.dirty_palette.end
+        {
+            self.prepared = None;
+        }
+
+        if self.dirty_flood {
+            let staging_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("staging flood update"),
+                contents: &level.flood_map,
+                usage: wgpu::BufferUsages::COPY_SRC,
+            });
+            stats.add_buffer("staging flood update", level.flood_map.len());
+
+            encoder.copy_buffer_to_texture(
+                wgpu::ImageCopyBuffer {
+                    buffer: &staging_buf,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(0x100),
+                        rows_per_image: None,
+                    },
+                },
+                self.flood.texture.as_image_copy(),
+                wgpu::Extent3d {
+                    width: self.flood.texture_size,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+
+            // the water bits of the voxel grid follow the flood
+            let has_full_rect = self
+                .dirty_rects
+                .iter()
+                .any(|dr| dr.rect.w as i32 == level.size.0 && dr.rect.h as i32 == level.size.1);
+            if matches!(self.kind, Kind::RayVoxel { .. }) && !has_full_rect {
+                self.dirty_rects.push(super::DirtyRect {
+                    rect: super::Rect {
+                        x: 0,
+                        y: 0,
+                        w: level.size.0 as _,
+                        h: level.size.1 as _,
+                    },
+                    z_range: 0..level.geometry.height as _,
+                    need_upload: false,
+                });
+            }
+            self.dirty_flood = false;
+        }
+
+        if let Some(ref mut streaming) = self.streaming {
+            // the resident tiles under the dirty rects are uploaded whole
+            for dr in self.dirty_rects.iter_mut() {
+                if dr.need_upload {
+                    for (tile, slot) in streaming.residency.resident_in(&dr.rect) {
+                        streaming.uploads.push(tile, slot, false);
+                    }
+                    dr.need_upload = false;
+                }
+            }
+            for staging in streaming.staging.iter_mut() {
+                staging.advance();
+            }
+            let (bytes_per_row, tile_bytes) = streaming.tile_layout();
+            let ready = streaming
+                .staging
+                .iter_mut()
+                .find(|staging| matches!(staging.state, StagingState::Ready));
+            if let (Some(staging), false) = (ready, streaming.uploads.is_empty()) {
+                let tiles = streaming
+                    .uploads
+                    .take(&streaming.residency, MAX_NEW_TILES_PER_FRAME);
+                let tile_size = streaming.residency.tile_size();
+                let staging_buf = &staging.buffer;
+                stats.add_buffer("staging tiles update", tiles.len() * tile_bytes);
+                {
+                    let mut mapping = staging_buf.slice(..).get_mapped_range_mut();
+                    let residency = &streaming.residency;
+                    let used = &mut mapping[..tiles.len() * tile_bytes];
+                    jobs::for_each_row(used, tile_bytes, 1, |i, data| {
+                        let rect = residency.tile_rect(tiles[i].0);
+                        for (y_off, line) in data.chunks_mut(bytes_per_row as usize).enumerate() {
+                            let base =
+                                (rect.y as usize + y_off) * level.size.0 as usize + rect.x as usize;
+                            for x in 0..tile_size as usize {
+                                line[2 * x + 0] = level.height[base + x];
+                                line[2 * x + 1] = level.meta[base + x];
+                            }
+                        }
+                    });
+                }
+                for (i, &(_, slot)) in tiles.iter().enumerate() {
+                    encoder.copy_buffer_to_texture(
+                        wgpu::ImageCopyBuffer {
+                            buffer: staging_buf,
+                            layout: wgpu::ImageDataLayout {
+                                offset: (i * tile_bytes) as wgpu::BufferAddress,
+                                bytes_per_row: Some(bytes_per_row),
+                                rows_per_image: None,
+                            },
+                        },
+                        wgpu::ImageCopyTexture {
+                            texture: &self.terrain_texture,
+                            mip_level: 0,
+                            origin: wgpu::Origin3d {
+                                x: (slot % streaming.slots_per_row) * tile_size / 2,
+                                y: (slot / streaming.slots_per_row) * tile_size,
+                                z: 0,
+                            },
+                            aspect: wgpu::TextureAspect::All,
+                        },
+                        wgpu::Extent3d {
+                            width: tile_size / 2,
+                            height: tile_size,
+                            depth_or_array_layers: 1,
+                        },
+                    );
+                }
+                staging_buf.unmap();
+                staging.state = StagingState::Used;
+                // the fresh tiles become visible
+                streaming.dirty_pages = true;
+            }
+
+            if streaming.dirty_pages {
+                let (width, height) = streaming.residency.tile_count();
+                let bytes_per_row =
+                    wgpu::util::align_to(width * 4, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+                let total_size = bytes_per_row as wgpu::BufferAddress * height as u64;
+                let staging_buf = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("staging tile pages"),
+                    size: total_size,
+                    usage: wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: true,
+                });
+                stats.add_buffer("staging tile pages", total_size as usize);
+                {
+                    let mut mapping = staging_buf.slice(..).get_mapped_range_mut();
+                    let pages = streaming.uploads.visible_pages(&streaming.residency);
+                    for (line, row) in mapping
+                        .chunks_mut(bytes_per_row as usize)
+                        .zip(pages.chunks(width as usize))
+                    {
+                        line[..row.len() * 4].copy_from_slice(bytemuck::cast_slice(row));
+                    }
+                }
+                encoder.copy_buffer_to_texture(
+                    wgpu::ImageCopyBuffer {
+                        buffer: &staging_buf,
+                        layout: wgpu::ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row: Some(bytes_per_row),
+                            rows_per_image: None,
+                        },
+                    },
+                    streaming.page_texture.as_image_copy(),
+                    wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+                staging_buf.unmap();
+                streaming.dirty_pages = false;
+            }
+        }
+
+        let has_pending_mips = matches!(
+            self.kind,
+            Kind::RayVoxel { ref mip_queue, .. } if !mip_queue.is_empty()
+        );
+        if !self.dirty_rects.is_empty() || has_pending_mips {
             for dr in self.dirty_rects.iter_mut() {
                 if !dr.need_upload {
                     continue;
@@ -1577,23 +2512,29 @@ impl Context {
                 //Note: we are uploading the whole rows currently. We could instead upload
                 // only the relevant sub-rectangle, but managing `bytes_per_row` becomes annoying.
 
-                let total_size =
-                    dr.rect.h as wgpu::BufferAddress * level.size.0 as wgpu::BufferAddress * 2;
+                // the rows of the narrow levels are padded to the copy alignment
+                let bytes_per_row = wgpu::util::align_to(
+                    level.size.0 as u32 * 2,
+                    wgpu::COPY_BYTES_PER_ROW_ALIGNMENT,
+                );
+                let total_size = dr.rect.h as wgpu::BufferAddress * bytes_per_row as u64;
                 let staging_buf = device.create_buffer(&wgpu::BufferDescriptor {
                     label: Some("staging level update"),
                     size: total_size,
                     usage: wgpu::BufferUsages::COPY_SRC,
                     mapped_at_creation: true,
                 });
+                stats.add_buffer("staging level update", total_size as usize);
                 {
                     let mut mapping = staging_buf.slice(..).get_mapped_range_mut();
-                    for (y_off, line) in mapping.chunks_mut(level.size.0 as usize * 2).enumerate() {
+                    let row_size = bytes_per_row as usize;
+                    jobs::for_each_row(&mut mapping, row_size, ROWS_PER_JOB, |y_off, line| {
                         let base = (dr.rect.y as usize + y_off) * level.size.0 as usize;
                         for x in 0..level.size.0 as usize {
                             line[2 * x + 0] = level.height[base + x];
                             line[2 * x + 1] = level.meta[base + x];
                         }
-                    }
+                    });
                 }
 
                 encoder.copy_buffer_to_texture(
@@ -1601,7 +2542,7 @@ impl Context {
                         buffer: &staging_buf,
                         layout: wgpu::ImageDataLayout {
                             offset: 0,
-                            bytes_per_row: Some(level.size.0 as u32 * 2),
+                            bytes_per_row: Some(bytes_per_row),
                             rows_per_image: None,
                         },
                     },
@@ -1630,12 +2571,12 @@ impl Context {
                 Kind::RayVoxel {
                     ref init_pipeline,
                     ref mip_pipeline,
-                    ref mips,
                     ref bake_bind_group,
                     ref update_buffer,
                     voxel_size,
                     max_update_rects,
                     max_update_texels,
+                    ref mut mip_queue,
                     ..
                 } => {
                     fn align_down(v: u16, tile: u32) -> i32 {
@@ -1702,88 +2643,89 @@ impl Context {
                         }
                     }
 
+                    let baked_count = update_buffer_contents.len();
+                    stats.dirty_rects += baked_count as u32;
+                    for &update in update_buffer_contents.iter() {
+                        mip_queue.push(update);
+                    }
+                    let mip_frame = mip_queue.schedule(max_update_rects);
+                    update_buffer_contents.extend_from_slice(&mip_frame.regions);
+
                     let staging_buf =
                         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                             label: Some("Voxel bake update"),
                             contents: bytemuck::cast_slice(&update_buffer_contents),
                             usage: wgpu::BufferUsages::COPY_SRC,
                         });
+                    stats.add_buffer(
+                        "Voxel bake update",
+                        update_buffer_contents.len() * mem::size_of::<BakeConstants>(),
+                    );
                     for i in 0..update_buffer_contents.len() {
+                        // the mip regions take the second half of the slots
+                        let slot = if i < baked_count {
+                            i
+                        } else {
+                            max_update_rects + i - baked_count
+                        };
                         encoder.copy_buffer_to_buffer(
                             &staging_buf,
                             (i * mem::size_of::<BakeConstants>()) as _,
                             update_buffer,
-                            (i * MAXIMUM_UNIFORM_BUFFER_ALIGNMENT) as _,
+                            (slot * MAXIMUM_UNIFORM_BUFFER_ALIGNMENT) as _,
                             mem::size_of::<BakeConstants>() as _,
                         );
                     }
 
-                    let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                        label: Some("Voxel bake"),
-                    });
+                    let mut pass = frame_graph::begin_compute_pass(
+                        encoder,
+                        &wgpu::ComputePassDescriptor {
+                            label: Some("Voxel bake"),
+                        },
+                    );
                     pass.set_pipeline(init_pipeline);
                     pass.set_bind_group(1, &self.bind_group, &[]);
-                    for (i, update) in update_buffer_contents.iter().enumerate() {
+                    for (i, update) in update_buffer_contents[..baked_count].iter().enumerate() {
                         let groups = update.init_workgroups([8, 8, 1]);
                         let offset = i * MAXIMUM_UNIFORM_BUFFER_ALIGNMENT;
                         pass.set_bind_group(0, bake_bind_group, &[offset as u32, 0]);
-                        pass.dispatch_workgroups(groups[0], groups[1], 1);
+                        frame_graph::dispatch(&mut pass, [groups[0], groups[1], 1]);
+                        stats.terrain_dispatches += 1;
                     }
                     pass.set_pipeline(mip_pipeline);
-                    for dst_lod in 1..mips.len() {
-                        for (i, update) in update_buffer_contents.iter().enumerate() {
-                            let groups = update.mip_workgroups([4, 4, 4], dst_lod as u32);
-                            let offset = i * MAXIMUM_UNIFORM_BUFFER_ALIGNMENT;
-                            let mip_data_offset = (dst_lod - 1) * MAXIMUM_UNIFORM_BUFFER_ALIGNMENT;
-                            pass.set_bind_group(
-                                0,
-                                bake_bind_group,
-                                &[offset as u32, mip_data_offset as u32],
-                            );
-                            pass.dispatch_workgroups(groups[0], groups[1], groups[2]);
-                        }
+                    for &(slot, dst_lod) in mip_frame.dispatches.iter() {
+                        let update = &mip_frame.regions[slot];
+                        let groups = update.mip_workgroups([4, 4, 4], dst_lod);
+                        let offset = (max_update_rects + slot) * MAXIMUM_UNIFORM_BUFFER_ALIGNMENT;
+                        let mip_data_offset =
+                            (dst_lod as usize - 1) * MAXIMUM_UNIFORM_BUFFER_ALIGNMENT;
+                        pass.set_bind_group(
+                            0,
+                            bake_bind_group,
+                            &[offset as u32, mip_data_offset as u32],
+                        );
+                        frame_graph::dispatch(&mut pass, groups);
+                        stats.terrain_dispatches += 1;
                     }
+                    drop(pass);
                 }
                 _ => {
+                    stats.dirty_rects += self.dirty_rects.len() as u32;
                     self.dirty_rects.clear();
                 }
             }
         }
 
-        if self.dirty_flood {
-            let staging_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("staging flood update"),
-                contents: &level.flood_map,
-                usage: wgpu::BufferUsages::COPY_SRC,
-            });
-
-            encoder.copy_buffer_to_texture(
-                wgpu::ImageCopyBuffer {
-                    buffer: &staging_buf,
-                    layout: wgpu::ImageDataLayout {
-                        offset: 0,
-                        bytes_per_row: Some(0x100),
-                        rows_per_image: None,
-                    },
-                },
-                self.flood.texture.as_image_copy(),
-                wgpu::Extent3d {
-                    width: self.flood.texture_size,
-                    height: 1,
-                    depth_or_array_layers: 1,
-                },
-            );
-
-            self.dirty_flood = false;
-        }
-
         if self.dirty_palette.start != self.dirty_palette.end {
+            let palette_data = &level.palette[self.dirty_palette.start as usize..];
             let staging_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("staging palette update"),
-                contents: bytemuck::cast_slice(&level.palette[self.dirty_palette.start as usize..]),
+                contents: bytemuck::cast_slice(palette_data),
                 usage: wgpu::BufferUsages::COPY_SRC,
             });
-            let mut img_copy = self.palette_texture.as_image_copy();
+            stats.add_buffer("staging palette update", mem::size_of_val(palette_data));
+            // the level palette lives in the first layer
+            let mut img_copy = self.palette.texture.as_image_copy();
             img_copy.origin.x = self.dirty_palette.start;
 
             encoder.copy_buffer_to_texture(
@@ -1799,20 +2741,56 @@ impl Context {
                 },
             );
             self.dirty_palette = 0..0;
+            self.dirty_palette_layers.push(0);
+        }
+        for layer in self.dirty_palette_layers.drain(..) {
+            self.palette_mips
+                .generate(encoder, device, &self.palette.texture, layer..layer + 1);
         }
     }
 
+    /// Make the tiles around the visible terrain resident, if the terrain is streamed.
+    /// Their data is queued for the upload, and they stay hidden until it's done.
+    pub fn stream(&mut self, cam: &Camera, level: &level::Level) {
+        let streaming = match self.streaming {
+            Some(ref mut streaming) => streaming,
+            None => return,
+        };
+        let area = cam
+            .visible_terrain_rect(level)
+            .grow_wrapped(streaming.margin as i32, level.size);
+        let center = WorldPos(cam.loc).texel().into();
+        for (tile, slot) in streaming.residency.update(&area, center) {
+            streaming.uploads.push(tile, slot, true);
+            streaming.dirty_pages = true;
+        }
+    }
+
+    /// Upload a palette into one of the layers, to be selected with `palette_layer`.
+    /// Layer 0 is reserved for the level palette.
+    pub fn set_palette(&mut self, queue: &wgpu::Queue, layer: u32, data: &[[u8; 4]]) {
+        assert_ne!(layer, 0, "Layer 0 is owned by the level palette");
+        self.palette.init(queue, layer, data);
+        self.dirty_palette_layers.push(layer);
+    }
+
     pub fn prepare(
         &mut self,
         encoder: &mut wgpu::CommandEncoder,
         device: &wgpu::Device,
+        stats: &mut super::Stats,
         global: &GlobalContext,
+        global_constants: &GlobalConstants,
         fog: &settings::Fog,
         level_height: u32,
         cam: &Camera,
         screen_rect: super::Rect,
     ) {
-        let sc = if let Kind::Scatter { .. } = self.kind {
+        let is_scattered = match self.kind {
+            Kind::Scatter { .. } => self.slice_view.is_none(),
+            _ => false,
+        };
+        let sc = if is_scattered {
             compute_scatter_constants(cam, level_height)
         } else {
             use cgmath::EuclideanSpace;
@@ -1825,31 +2803,50 @@ impl Context {
             }
         };
 
-        {
+        let depth_range = cam.depth_range();
+        let prepared = Prepared {
+            locals: Constants {
+                screen_rect: [
+                    screen_rect.x as u32,
+                    screen_rect.y as u32,
+                    screen_rect.w as u32,
+                    screen_rect.h as u32,
+                ],
+                cam_origin_dir: [sc.origin.x, sc.origin.y, sc.dir.x, sc.dir.y],
+                sample_range: [
+                    sc.sample_x.start,
+                    sc.sample_x.end,
+                    sc.sample_y.start,
+                    sc.sample_y.end,
+                ],
+                fog_color: fog.color,
+                pad: 1.0,
+                fog_params: [depth_range.end - fog.depth, depth_range.end, 0.0, 0.0],
+                cutaway: match self.cutaway {
+                    Some(ref c) => [c.center.x, c.center.y, c.radius, c.strength],
+                    None => [0.0; 4],
+                },
+                debug_mode: [
+                    self.debug_mode as u32,
+                    self.step_stats.as_ref().map_or(0, |s| s.collect as u32),
+                    0,
+                    0,
+                ],
+                palette_layer: [self.palette_layer, 0, 0, 0],
+            },
+            globals: *global_constants,
+        };
+        let unchanged = self.prepared == Some(prepared);
+        self.prepared = Some(prepared);
+
+        if !unchanged {
             // constants update
-            let depth_range = cam.depth_range();
             let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("temp-constants"),
-                contents: bytemuck::bytes_of(&Constants {
-                    screen_rect: [
-                        screen_rect.x as u32,
-                        screen_rect.y as u32,
-                        screen_rect.w as u32,
-                        screen_rect.h as u32,
-                    ],
-                    cam_origin_dir: [sc.origin.x, sc.origin.y, sc.dir.x, sc.dir.y],
-                    sample_range: [
-                        sc.sample_x.start,
-                        sc.sample_x.end,
-                        sc.sample_y.start,
-                        sc.sample_y.end,
-                    ],
-                    fog_color: fog.color,
-                    pad: 1.0,
-                    fog_params: [depth_range.end - fog.depth, depth_range.end, 0.0, 0.0],
-                }),
+                contents: bytemuck::bytes_of(&prepared.locals),
                 usage: wgpu::BufferUsages::COPY_SRC,
             });
+            stats.add_buffer("temp-constants", mem::size_of::<Constants>());
             encoder.copy_buffer_to_buffer(
                 &staging,
                 0,
@@ -1859,35 +2856,45 @@ impl Context {
             );
         }
 
+        if let Some(ref mut step_stats) = self.step_stats {
+            let limit = match self.kind {
+                Kind::RayVoxel {
+                    max_outer_steps,
+                    max_inner_steps,
+                    ..
+                } => max_outer_steps + max_inner_steps,
+                _ => RAY_MAX_STEPS,
+            };
+            step_stats.update(encoder, limit);
+        }
+
         match self.kind {
             Kind::RayVoxel {
                 ref constant_buffer,
+                ref mut constants,
                 voxel_size,
                 max_outer_steps,
                 max_inner_steps,
                 debug_alpha,
+                ref mip_queue,
                 ..
             } => {
-                let constants = VoxelConstants {
+                let new_constants = VoxelConstants {
                     voxel_size,
-                    pad: 0,
+                    ready_lod_count: mip_queue.ready_lod_count(),
                     max_depth: cam.depth_range().end,
                     debug_alpha,
                     max_outer_steps,
                     max_inner_steps,
                 };
-                let constant_update =
-                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some("ray-voxel constants"),
-                        contents: bytemuck::bytes_of(&constants),
-                        usage: wgpu::BufferUsages::COPY_SRC,
-                    });
-                encoder.copy_buffer_to_buffer(
-                    &constant_update,
-                    0,
+                update_voxel_constants(
+                    encoder,
+                    device,
+                    stats,
+                    "ray-voxel constants",
                     constant_buffer,
-                    0,
-                    mem::size_of::<VoxelConstants>() as _,
+                    constants,
+                    new_constants,
                 );
             }
             Kind::Paint {
@@ -1911,21 +2918,28 @@ impl Context {
                 compute_groups,
                 density,
                 ..
-            } => {
-                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: Some("scatter"),
-                });
+            } if !unchanged => {
+                let mut pass = frame_graph::begin_compute_pass(
+                    encoder,
+                    &wgpu::ComputePassDescriptor {
+                        label: Some("scatter"),
+                    },
+                );
                 pass.set_bind_group(0, &global.bind_group, &[]);
                 pass.set_bind_group(1, &self.bind_group, &[]);
                 pass.set_bind_group(2, bind_group, &[]);
                 pass.set_pipeline(clear_pipeline);
-                pass.dispatch_workgroups(compute_groups[0], compute_groups[1], compute_groups[2]);
+                frame_graph::dispatch(&mut pass, compute_groups);
                 pass.set_pipeline(scatter_pipeline);
-                pass.dispatch_workgroups(
-                    compute_groups[0] * density[0],
-                    compute_groups[1] * density[1],
-                    density[2],
+                frame_graph::dispatch(
+                    &mut pass,
+                    [
+                        compute_groups[0] * density[0],
+                        compute_groups[1] * density[1],
+                        density[2],
+                    ],
                 );
+                stats.terrain_dispatches += 2;
             }
             _ => {}
         }
@@ -1935,6 +2949,7 @@ impl Context {
         &mut self,
         encoder: &mut wgpu::CommandEncoder,
         device: &wgpu::Device,
+        stats: &mut super::Stats,
         cam: &Camera,
         screen_size: wgpu::Extent3d,
     ) {
@@ -1947,82 +2962,106 @@ impl Context {
             sample_x: bounds.start.x..bounds.end.x,
             sample_y: bounds.start.y..bounds.end.y,
         };
+        let constants = Constants {
+            screen_rect: [0, 0, screen_size.width, screen_size.height],
+            cam_origin_dir: [sc.origin.x, sc.origin.y, sc.dir.x, sc.dir.y],
+            sample_range: [
+                sc.sample_x.start,
+                sc.sample_x.end,
+                sc.sample_y.start,
+                sc.sample_y.end,
+            ],
+            fog_color: [0.0; 3],
+            pad: 1.0,
+            fog_params: [10000000.0, 10000000.0, 0.0, 0.0],
+            cutaway: [0.0; 4],
+            debug_mode: [DebugMode::None as u32, 0, 0, 0],
+            palette_layer: [0; 4],
+        };
 
-        {
-            // constants update
+        if self.prepared_shadow != Some(constants) {
+            self.prepared_shadow = Some(constants);
             let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("temp-constants"),
-                contents: bytemuck::bytes_of(&Constants {
-                    screen_rect: [0, 0, screen_size.width, screen_size.height],
-                    cam_origin_dir: [sc.origin.x, sc.origin.y, sc.dir.x, sc.dir.y],
-                    sample_range: [
-                        sc.sample_x.start,
-                        sc.sample_x.end,
-                        sc.sample_y.start,
-                        sc.sample_y.end,
-                    ],
-                    fog_color: [0.0; 3],
-                    pad: 1.0,
-                    fog_params: [10000000.0, 10000000.0, 0.0, 0.0],
-                }),
+                contents: bytemuck::bytes_of(&constants),
                 usage: wgpu::BufferUsages::COPY_SRC,
             });
+            stats.add_buffer("temp-constants", mem::size_of::<Constants>());
             encoder.copy_buffer_to_buffer(
                 &staging,
                 0,
-                &self.uniform_buf,
+                &self.shadow_uniform_buf,
                 0,
                 mem::size_of::<Constants>() as wgpu::BufferAddress,
             );
         }
 
-        match self.shadow_kind {
-            ShadowKind::InheritRayVoxel {
-                max_outer_steps,
-                max_inner_steps,
-                ..
-            } => match self.kind {
+        if let ShadowKind::InheritRayVoxel {
+            max_outer_steps,
+            max_inner_steps,
+            ..
+        } = self.shadow_kind
+        {
+            match self.kind {
                 Kind::RayVoxel {
-                    ref constant_buffer,
+                    ref shadow_constant_buffer,
+                    ref mut shadow_constants,
                     voxel_size,
                     debug_alpha,
+                    ref mip_queue,
                     ..
                 } => {
-                    let constants = VoxelConstants {
+                    let new_constants = VoxelConstants {
                         voxel_size,
-                        pad: 0,
+                        ready_lod_count: mip_queue.ready_lod_count(),
                         max_depth: cam.depth_range().end,
                         debug_alpha,
                         max_outer_steps,
                         max_inner_steps,
                     };
-                    let constant_update =
-                        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some("ray-voxel shadow constants"),
-                            contents: bytemuck::bytes_of(&constants),
-                            usage: wgpu::BufferUsages::COPY_SRC,
-                        });
-                    encoder.copy_buffer_to_buffer(
-                        &constant_update,
-                        0,
-                        constant_buffer,
-                        0,
-                        mem::size_of::<VoxelConstants>() as _,
+                    update_voxel_constants(
+                        encoder,
+                        device,
+                        stats,
+                        "ray-voxel shadow constants",
+                        shadow_constant_buffer,
+                        shadow_constants,
+                        new_constants,
                     );
                 }
                 _ => unreachable!(),
-            },
-            _ => {}
+            }
         }
     }
 
     pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
         pass.set_bind_group(1, &self.bind_group, &[]);
+        if let Some(view) = self.slice_view {
+            let pipeline = match self.kind {
+                Kind::Slice { ref pipeline, .. } => Some(pipeline),
+                _ => self.view_slice_pipeline.as_ref(),
+            };
+            if let Some(pipeline) = pipeline {
+                // the slices go from the top down, one per altitude
+                let first = self
+                    .slice_layer_count
+                    .saturating_sub(view.ceiling.max(0.0) as u32);
+                pass.set_pipeline(pipeline);
+                pass.draw(0..4, first..self.slice_layer_count);
+                return;
+            }
+        }
         // draw terrain
         match self.kind {
-            Kind::Ray { ref pipeline } => {
+            Kind::Ray {
+                ref pipeline,
+                ref stats,
+            } => {
                 let geo = &self.raytrace_geo;
                 pass.set_pipeline(pipeline);
+                if let Some(ref binding) = *stats {
+                    pass.set_bind_group(2, &binding.bind_group, &[]);
+                }
                 pass.set_index_buffer(geo.index_buf.slice(..), wgpu::IndexFormat::Uint16);
                 pass.set_vertex_buffer(0, geo.vertex_buf.slice(..));
                 pass.draw_indexed(0..geo.num_indices, 0, 0..1);
@@ -2086,7 +3125,7 @@ impl Context {
     }
 
     pub fn draw_shadow<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
-        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.set_bind_group(1, &self.shadow_bind_group, &[]);
         // draw terrain
         match self.shadow_kind {
             ShadowKind::Ray { ref pipeline } => {
@@ -2098,11 +3137,11 @@ impl Context {
             }
             ShadowKind::InheritRayVoxel { ref pipeline, .. } => match self.kind {
                 Kind::RayVoxel {
-                    ref draw_bind_group,
+                    ref shadow_bind_group,
                     ..
                 } => {
                     pass.set_pipeline(pipeline);
-                    pass.set_bind_group(2, draw_bind_group, &[]);
+                    pass.set_bind_group(2, shadow_bind_group, &[]);
                     pass.draw(0..3, 0..1);
                 }
                 _ => unreachable!(),
@@ -2110,35 +3149,76 @@ impl Context {
         }
     }
 
-    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+    /// Debug modes supported by the current terrain kind.
+    pub fn debug_modes(&self) -> &'static [DebugMode] {
         match self.kind {
-            Kind::RayVoxel {
-                ref mut max_outer_steps,
-                ref mut max_inner_steps,
-                ref mut debug_alpha,
-                ref mut debug_render,
-                ..
-            } => {
-                ui.add(egui::Slider::new(max_outer_steps, 0..=100).text("Max outer steps"));
-                ui.add(egui::Slider::new(max_inner_steps, 0..=100).text("Max inner steps"));
-                ui.add(egui::Slider::new(debug_alpha, 0.0..=1.0).text("Debug alpha"));
-                if let Some(ref mut debug) = *debug_render {
-                    let mut debug_voxels = debug.lod_range.is_some();
-                    ui.checkbox(&mut debug_voxels, "Debug voxels");
-                    let mut lod_start = debug.lod_range.clone().map_or(4, |r| r.start);
-                    let mut lod_count = debug.lod_range.clone().map_or(1, |r| r.end - r.start);
-                    ui.add_enabled_ui(debug_voxels, |ui| {
-                        ui.add(egui::Slider::new(&mut lod_start, 1..=8).text("LOD start"));
-                        ui.add(egui::Slider::new(&mut lod_count, 1..=8).text("LOD count"));
-                    });
-                    debug.lod_range = if debug_voxels {
-                        Some(lod_start..lod_start + lod_count)
-                    } else {
-                        None
-                    };
+            Kind::Ray { .. } => &DebugMode::RAY,
+            Kind::RayVoxel { .. } => &DebugMode::RAY_VOXEL,
+            Kind::Slice { .. } | Kind::Paint { .. } | Kind::Scatter { .. } => &DebugMode::COMMON,
+        }
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        let modes = self.debug_modes();
+        egui::ComboBox::from_label(tr("Debug mode"))
+            .selected_text(format!("{:?}", self.debug_mode))
+            .show_ui(ui, |ui| {
+                for &mode in modes {
+                    ui.selectable_value(&mut self.debug_mode, mode, format!("{:?}", mode));
                 }
+            });
+        if let Some(ref mut step_stats) = self.step_stats {
+            step_stats.draw_ui(ui);
+        }
+        if let Some(ref streaming) = self.streaming {
+            ui.label(tr_fmt(
+                "Streamed tiles: {} of {} resident",
+                &[
+                    &streaming.residency.resident_count(),
+                    &streaming.residency.slot_count(),
+                ],
+            ));
+        }
+        if let Kind::RayVoxel {
+            ref mut max_outer_steps,
+            ref mut max_inner_steps,
+            ref mut debug_alpha,
+            ref mut debug_render,
+            ref mips,
+            dropped_lods,
+            ref mip_queue,
+            ref memory,
+            ..
+        } = self.kind
+        {
+            ui.label(tr_fmt(
+                "Voxel grid: {} MB, {} LODs ({} dropped)",
+                &[&(memory.size() >> 20), &mips.len(), &dropped_lods],
+            ));
+            if !mip_queue.is_empty() {
+                ui.label(tr_fmt(
+                    "Rebuilding the mips of {} regions, {} LODs ready",
+                    &[&mip_queue.len(), &mip_queue.ready_lod_count()],
+                ));
+            }
+            ui.add(egui::Slider::new(max_outer_steps, 0..=100).text(tr("Max outer steps")));
+            ui.add(egui::Slider::new(max_inner_steps, 0..=100).text(tr("Max inner steps")));
+            ui.add(egui::Slider::new(debug_alpha, 0.0..=1.0).text(tr("Debug alpha")));
+            if let Some(ref mut debug) = *debug_render {
+                let mut debug_voxels = debug.lod_range.is_some();
+                ui.checkbox(&mut debug_voxels, tr("Debug voxels"));
+                let mut lod_start = debug.lod_range.clone().map_or(4, |r| r.start);
+                let mut lod_count = debug.lod_range.clone().map_or(1, |r| r.end - r.start);
+                ui.add_enabled_ui(debug_voxels, |ui| {
+                    ui.add(egui::Slider::new(&mut lod_start, 1..=8).text(tr("LOD start")));
+                    ui.add(egui::Slider::new(&mut lod_count, 1..=8).text(tr("LOD count")));
+                });
+                debug.lod_range = if debug_voxels {
+                    Some(lod_start..lod_start + lod_count)
+                } else {
+                    None
+                };
             }
-            _ => {}
         }
     }
 }
@@ -1,10 +1,13 @@
 use crate::{
     config::settings,
-    render::{global::Context as GlobalContext, terrain::Context as TerrainContext, DEPTH_FORMAT},
+    render::{
+        global::Context as GlobalContext, memory, terrain::Context as TerrainContext, Stats,
+        DEPTH_FORMAT,
+    },
     space::Camera,
 };
 use bytemuck::{Pod, Zeroable};
-use std::{mem, ops};
+use std::{mem, ops, time::Instant};
 use wgpu::util::DeviceExt as _;
 
 #[repr(C)]
@@ -16,10 +19,33 @@ pub struct Vertex {
 unsafe impl Pod for Vertex {}
 unsafe impl Zeroable for Vertex {}
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Constants {
+    params: [f32; 4],
+}
+unsafe impl Pod for Constants {}
+unsafe impl Zeroable for Constants {}
+
+const REFRACTION_STRENGTH: f32 = 8.0;
+
+/// Copy of the scene behind the water, used for refraction.
+struct Scene {
+    view: wgpu::TextureView,
+    _memory: memory::Allocation,
+    blit_pipeline: wgpu::RenderPipeline,
+}
+
 pub struct Context {
     pub pipeline_layout: wgpu::PipelineLayout,
     pub pipeline: wgpu::RenderPipeline,
     pub color_format: wgpu::TextureFormat,
+    settings: settings::Water,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform_buf: wgpu::Buffer,
+    scene: Option<Scene>,
+    start_time: Instant,
     texture_size: u32,
     section_size: (u32, u32),
     vertex_buf: wgpu::Buffer,
@@ -27,6 +53,83 @@ pub struct Context {
 }
 
 impl Context {
+    fn create_blit_pipeline(
+        layout: &wgpu::PipelineLayout,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = super::load_shader("water", &[], device).unwrap();
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("water-blit"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "blit_vs",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "blit_fs",
+                targets: &[Some(color_format.into())],
+            }),
+            primitive: Default::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    fn create_scene_view(
+        extent: wgpu::Extent3d,
+        color_format: wgpu::TextureFormat,
+        device: &wgpu::Device,
+    ) -> (wgpu::TextureView, memory::Allocation) {
+        let desc = wgpu::TextureDescriptor {
+            label: Some("water-scene"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: color_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        };
+        let mut memory = memory::Allocation::new(memory::Subsystem::Water);
+        memory.add_texture(device, &desc);
+        let view = device
+            .create_texture(&desc)
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        (view, memory)
+    }
+
+    fn create_bind_group(
+        layout: &wgpu::BindGroupLayout,
+        uniform_buf: &wgpu::Buffer,
+        scene_view: &wgpu::TextureView,
+        device: &wgpu::Device,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Water"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(scene_view),
+                },
+            ],
+        })
+    }
+
     fn create_pipeline(
         layout: &wgpu::PipelineLayout,
         device: &wgpu::Device,
@@ -76,17 +179,89 @@ impl Context {
 
     pub fn new(
         device: &wgpu::Device,
-        _settings: &settings::Water,
+        settings: &settings::Water,
         global: &GlobalContext,
         terrain: &TerrainContext,
+        extent: wgpu::Extent3d,
     ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Water"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(mem::size_of::<Constants>() as _),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("water"),
-            bind_group_layouts: &[&global.bind_group_layout, &terrain.bind_group_layout],
+            bind_group_layouts: &[
+                &global.bind_group_layout,
+                &terrain.bind_group_layout,
+                &bind_group_layout,
+            ],
             push_constant_ranges: &[],
         });
         let pipeline = Self::create_pipeline(&pipeline_layout, device, global.color_format);
 
+        let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("water-uniforms"),
+            size: mem::size_of::<Constants>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let scene = if settings.refraction {
+            let (view, memory) = Self::create_scene_view(extent, global.color_format, device);
+            Some(Scene {
+                view,
+                _memory: memory,
+                blit_pipeline: Self::create_blit_pipeline(
+                    &pipeline_layout,
+                    device,
+                    global.color_format,
+                ),
+            })
+        } else {
+            None
+        };
+        let bind_group = {
+            // without refraction, the scene is never sampled
+            let dummy_view;
+            let scene_view = match scene {
+                Some(ref scene) => &scene.view,
+                None => {
+                    dummy_view = Self::create_scene_view(
+                        wgpu::Extent3d {
+                            width: 1,
+                            height: 1,
+                            depth_or_array_layers: 1,
+                        },
+                        global.color_format,
+                        device,
+                    )
+                    .0;
+                    &dummy_view
+                }
+            };
+            Self::create_bind_group(&bind_group_layout, &uniform_buf, scene_view, device)
+        };
+
         let max_vertices = 1000;
         let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("water-vertex"),
@@ -99,6 +274,12 @@ impl Context {
             pipeline_layout,
             pipeline,
             color_format: global.color_format,
+            settings: *settings,
+            bind_group_layout,
+            bind_group,
+            uniform_buf,
+            scene,
+            start_time: Instant::now(),
             texture_size: terrain.flood.texture_size,
             section_size: terrain.flood.section_size,
             vertex_buf,
@@ -108,12 +289,36 @@ impl Context {
 
     pub fn reload(&mut self, device: &wgpu::Device) {
         self.pipeline = Self::create_pipeline(&self.pipeline_layout, device, self.color_format);
+        if let Some(ref mut scene) = self.scene {
+            scene.blit_pipeline =
+                Self::create_blit_pipeline(&self.pipeline_layout, device, self.color_format);
+        }
+    }
+
+    pub fn resize(&mut self, extent: wgpu::Extent3d, device: &wgpu::Device) {
+        if let Some(ref mut scene) = self.scene {
+            (scene.view, scene._memory) =
+                Self::create_scene_view(extent, self.color_format, device);
+            self.bind_group = Self::create_bind_group(
+                &self.bind_group_layout,
+                &self.uniform_buf,
+                &scene.view,
+                device,
+            );
+        }
+    }
+
+    /// The target to render the opaque scene into, if refraction is enabled.
+    /// It gets copied into the final target by `draw`.
+    pub fn scene_view(&self) -> Option<&wgpu::TextureView> {
+        self.scene.as_ref().map(|scene| &scene.view)
     }
 
     pub fn prepare(
         &mut self,
         encoder: &mut wgpu::CommandEncoder,
         device: &wgpu::Device,
+        stats: &mut Stats,
         cam: &Camera,
     ) {
         fn tile_range(start: f32, end: f32, tile_size: u32) -> ops::Range<i32> {
@@ -163,6 +368,7 @@ impl Context {
             usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::MAP_WRITE,
         });
         let total_size = self.vertices.len() * mem::size_of::<Vertex>();
+        stats.add_buffer("staging flood update", total_size);
         encoder.copy_buffer_to_buffer(
             &staging,
             0,
@@ -170,11 +376,42 @@ impl Context {
             0,
             total_size as wgpu::BufferAddress,
         );
+
+        let constants = Constants {
+            params: [
+                self.start_time.elapsed().as_secs_f32(),
+                if self.scene.is_some() {
+                    REFRACTION_STRENGTH
+                } else {
+                    0.0
+                },
+                if self.settings.foam { 1.0 } else { 0.0 },
+                0.0,
+            ],
+        };
+        let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("staging water constants"),
+            contents: bytemuck::bytes_of(&constants),
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+        stats.add_buffer("staging water constants", mem::size_of::<Constants>());
+        encoder.copy_buffer_to_buffer(
+            &staging,
+            0,
+            &self.uniform_buf,
+            0,
+            mem::size_of::<Constants>() as wgpu::BufferAddress,
+        );
     }
 
     pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
         // needs to be set up by the terrain context
         //pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.set_bind_group(2, &self.bind_group, &[]);
+        if let Some(ref scene) = self.scene {
+            pass.set_pipeline(&scene.blit_pipeline);
+            pass.draw(0..3, 0..1);
+        }
         pass.set_pipeline(&self.pipeline);
         pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
         pass.draw(0..self.vertices.len() as u32, 0..1);
@@ -1,8 +1,39 @@
-use cgmath::{Angle as _, EuclideanSpace as _, Rotation as _, Rotation3 as _, Transform as _};
+use crate::{
+    config::settings::{CameraCollision, CameraEffects as EffectsConfig, Handedness},
+    level::Level,
+    locale::tr,
+    render::Rect,
+};
+use cgmath::{
+    Angle as _, EuclideanSpace as _, InnerSpace as _, Rotation as _, Rotation3 as _,
+    Transform as _, Zero as _,
+};
 use std::ops::Range;
 
+pub mod coords;
+
 pub type Transform = cgmath::Decomposed<cgmath::Vector3<f32>, cgmath::Quaternion<f32>>;
 
+/// Rotation of the free camera per pixel of the cursor movement, in radians.
+const ORBIT_SPEED: f32 = 0.005;
+/// Panning of the free camera per pixel, relative to its height.
+const PAN_SPEED: f32 = 0.003;
+/// Vertical movement of the free camera per line of the mouse wheel.
+const ZOOM_SPEED: f32 = 16.0;
+/// Part of the camera clearance over the terrain that is used as the near plane distance.
+const NEAR_CLEARANCE_FRACTION: f32 = 0.5;
+/// Distance between the terrain samples along the collision ray of the follow camera.
+const COLLISION_STEP: f32 = 4.0;
+/// Halvings of the step between the samples when picking the terrain.
+const PICK_REFINE_STEPS: usize = 8;
+/// Shake added by an impact at the threshold speed, out of the full shake of 1.
+const IMPACT_SHAKE: f32 = 0.3;
+/// Turn and displacement of the camera at the full shake.
+const SHAKE_ANGLE: cgmath::Deg<f32> = cgmath::Deg(1.5);
+const SHAKE_OFFSET: f32 = 2.0;
+/// Rate of the view widening following the speed, per second.
+const SPEED_FOV_RATE: f32 = 2.0;
+
 #[derive(Copy, Clone)]
 pub enum Projection {
     Ortho {
@@ -61,8 +92,7 @@ impl Projection {
 pub struct Camera {
     pub loc: cgmath::Vector3<f32>,
     pub rot: cgmath::Quaternion<f32>,
-    // this non-uniform scale is used to make the camera left-handed
-    pub scale: cgmath::Vector3<f32>,
+    pub handedness: Handedness,
     pub proj: Projection,
 }
 
@@ -71,6 +101,83 @@ pub struct Follow {
     pub angle_x: cgmath::Deg<f32>,
     pub offset: cgmath::Vector3<f32>,
     pub speed: f32,
+    pub collision: CameraCollision,
+}
+
+/// March from `start` to `end` over the terrain. If the way is obstructed,
+/// returns the fraction of it that is clear of the terrain.
+/// The terrain around `start` itself is skipped, since the target may be touching it.
+fn cast_ray(
+    level: &Level,
+    start: cgmath::Vector3<f32>,
+    end: cgmath::Vector3<f32>,
+    margin: f32,
+) -> Option<f32> {
+    // nothing to hit if the whole way is above the terrain
+    let min = (
+        start.x.min(end.x).floor() as i32,
+        start.y.min(end.y).floor() as i32,
+    );
+    let max = (
+        start.x.max(end.x).floor() as i32 + 1,
+        start.y.max(end.y).floor() as i32 + 1,
+    );
+    if start.z.min(end.z) >= level.query_minmax(min, max).end + margin {
+        return None;
+    }
+    let count = ((end - start).magnitude() / COLLISION_STEP).ceil().max(1.0) as u32;
+    let mut last_free = None;
+    for i in 0..=count {
+        let t = i as f32 / count as f32;
+        let pos = start + (end - start) * t;
+        let texel = level.get_at(pos);
+        if !texel.obstructs(pos.z, margin) {
+            last_free = Some(t);
+        } else if last_free.is_some() {
+            return last_free;
+        }
+    }
+    None
+}
+
+/// Narrow the hit of `cast_ray` down to the exact texel,
+/// between the last free sample and the next one.
+fn refine_hit(
+    level: &Level,
+    start: cgmath::Vector3<f32>,
+    end: cgmath::Vector3<f32>,
+    mut free: f32,
+) -> cgmath::Vector3<f32> {
+    let count = ((end - start).magnitude() / COLLISION_STEP).ceil().max(1.0);
+    let mut hit = (free + 1.0 / count).min(1.0);
+    for _ in 0..PICK_REFINE_STEPS {
+        let t = 0.5 * (free + hit);
+        let pos = start + (end - start) * t;
+        let texel = level.get_at(pos);
+        if texel.obstructs(pos.z, 0.0) {
+            hit = t;
+        } else {
+            free = t;
+        }
+    }
+    start + (end - start) * hit
+}
+
+/// Find where the ray from `origin` along `dir` hits the terrain,
+/// within `max_distance` from the origin.
+/// The terrain the origin is inside of is skipped, as with the camera collision.
+pub fn trace_ray(
+    level: &Level,
+    origin: cgmath::Vector3<f32>,
+    dir: cgmath::Vector3<f32>,
+    max_distance: f32,
+) -> Option<cgmath::Vector3<f32>> {
+    if dir.magnitude2() == 0.0 || max_distance <= 0.0 {
+        return None;
+    }
+    let end = origin + dir.normalize() * max_distance;
+    let free = cast_ray(level, origin, end, 0.0)?;
+    Some(refine_hit(level, origin, end, free))
 }
 
 #[derive(Copy, Clone)]
@@ -80,14 +187,6 @@ pub struct Direction {
 }
 
 impl Camera {
-    fn _scale_vec(&self, vec: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
-        cgmath::Vector3::new(
-            self.scale.x * vec.x,
-            self.scale.y * vec.y,
-            self.scale.z * vec.z,
-        )
-    }
-
     pub fn dir(&self) -> cgmath::Vector3<f32> {
         self.rot * -cgmath::Vector3::unit_z()
     }
@@ -99,6 +198,33 @@ impl Camera {
         }
     }
 
+    pub fn set_depth_range(&mut self, range: Range<f32>) {
+        match self.proj {
+            Projection::Ortho { ref mut p, .. } => {
+                p.near = range.start;
+                p.far = range.end;
+            }
+            Projection::Perspective(ref mut p) => {
+                p.near = range.start;
+                p.far = range.end;
+            }
+        }
+    }
+
+    /// Fit the depth range to the altitude of the camera over the terrain of the given height.
+    /// The `base` range is used at the ground level. Going up pushes the near plane
+    /// as far as the clearance allows, and extends the far plane to keep the same reach.
+    pub fn fit_depth_range(&mut self, base: Range<f32>, terrain_height: f32) {
+        let altitude = self.loc.z.max(0.0);
+        let clearance = (altitude - terrain_height).max(0.0);
+        let far = base.end + altitude;
+        let near = base
+            .start
+            .max(clearance * NEAR_CLEARANCE_FRACTION)
+            .min(0.5 * far);
+        self.set_depth_range(near..far);
+    }
+
     fn get_proj_matrix(&self) -> cgmath::Matrix4<f32> {
         let mut proj = self.proj.to_matrix();
         // convert from GL's depth of [-1,1] to wgpu/gfx-rs [0,1]
@@ -117,12 +243,27 @@ impl Camera {
         }
     }
 
+    /// Flip of the view space Y axis for the left-handed convention.
     fn scale_matrix(&self) -> cgmath::Matrix4<f32> {
-        cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+        match self.handedness {
+            Handedness::Left => cgmath::Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0),
+            Handedness::Right => cgmath::SquareMatrix::identity(),
+        }
     }
 
     pub fn get_view_proj(&self) -> cgmath::Matrix4<f32> {
-        let view = self.view_transform();
+        self.get_view_proj_from(cgmath::Vector3::zero())
+    }
+
+    /// View-projection of the positions relative to the `origin`.
+    /// Far from the world center, the plain matrix loses the precision of `f32`
+    /// on the large translation, and the rendering jitters. With the origin
+    /// at the camera, the translation part is zero.
+    pub fn get_view_proj_from(&self, origin: cgmath::Vector3<f32>) -> cgmath::Matrix4<f32> {
+        let view = Transform {
+            disp: self.loc - origin,
+            ..self.view_transform()
+        };
         let view_mx = cgmath::Matrix4::from(view.inverse_transform().unwrap());
         self.get_proj_matrix() * self.scale_matrix() * view_mx
     }
@@ -139,6 +280,21 @@ impl Camera {
         self.intersect_ray_height(dir, height)
     }
 
+    /// World position at the point of the screen, given in the normalized device coordinates,
+    /// with the `depth` going from 0 at the near plane to 1 at the far one.
+    pub fn unproject(&self, ndc: cgmath::Vector2<f32>, depth: f32) -> cgmath::Point3<f32> {
+        let inverse = self.get_view_proj().inverse_transform().unwrap();
+        cgmath::Point3::from_homogeneous(inverse * cgmath::vec4(ndc.x, ndc.y, depth, 1.0))
+    }
+
+    /// Find the terrain surface seen at the point of the screen, in the normalized device coordinates.
+    pub fn pick(&self, level: &Level, ndc: cgmath::Vector2<f32>) -> Option<cgmath::Vector3<f32>> {
+        let start = self.unproject(ndc, 0.0).to_vec();
+        let end = self.unproject(ndc, 1.0).to_vec();
+        let free = cast_ray(level, start, end, 0.0)?;
+        Some(refine_hit(level, start, end, free))
+    }
+
     pub fn visible_bounds_at(&self, height: f32) -> Range<cgmath::Vector2<f32>> {
         let center = self.intersect_height(height).to_vec().truncate();
         let mut bounds = center..center;
@@ -178,6 +334,27 @@ impl Camera {
         min..max
     }
 
+    /// Texels of the level that may be seen, between the ground and the top of the terrain.
+    /// The origin is wrapped into the level, and the size is limited by it.
+    pub fn visible_terrain_rect(&self, level: &Level) -> Rect {
+        let lo = self.visible_bounds();
+        let hi = self.visible_bounds_at(level.geometry.height as f32);
+        let start = (
+            lo.start.x.min(hi.start.x).floor() as i32,
+            lo.start.y.min(hi.start.y).floor() as i32,
+        );
+        let end = (
+            lo.end.x.max(hi.end.x).ceil() as i32,
+            lo.end.y.max(hi.end.y).ceil() as i32,
+        );
+        Rect {
+            x: start.0.rem_euclid(level.size.0) as u16,
+            y: start.1.rem_euclid(level.size.1) as u16,
+            w: (end.0 - start.0).clamp(0, level.size.0) as u16,
+            h: (end.1 - start.1).clamp(0, level.size.1) as u16,
+        }
+    }
+
     pub fn bound_points(&self, height: f32) -> [cgmath::Point3<f32>; 4] {
         let vb = self.visible_bounds_at(height);
         [
@@ -188,7 +365,7 @@ impl Camera {
         ]
     }
 
-    pub fn follow(&mut self, target: &Transform, dt: f32, follow: &Follow) {
+    pub fn follow(&mut self, target: &Transform, dt: f32, follow: &Follow, level: &Level) {
         // Determine the Z axis rotation around the target
         let swing = cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_x(), follow.angle_x);
         let mut front = target.rot.rotate_vector(cgmath::Vector3::unit_y());
@@ -204,8 +381,16 @@ impl Camera {
         let k = (dt * -follow.speed).exp();
         self.rot = rotation.slerp(self.rot, k);
 
-        let location = target.disp + (patch * twist).rotate_vector(follow.offset);
-        self.loc = location * (1.0 - k) + self.loc * k;
+        let mut location = target.disp + (patch * twist).rotate_vector(follow.offset);
+        let mut k_loc = k;
+        if follow.collision.enabled {
+            let margin = follow.collision.margin;
+            if let Some(t) = cast_ray(level, target.disp, location, margin) {
+                location = target.disp + (location - target.disp) * t;
+                k_loc = (dt * -follow.speed.max(follow.collision.speed)).exp();
+            }
+        }
+        self.loc = location * (1.0 - k_loc) + self.loc * k_loc;
     }
 
     pub fn look_by(&mut self, target: &Transform, dir: &Direction) {
@@ -271,27 +456,130 @@ impl Camera {
         self.rot = view.rot;
     }
 
-    pub fn front_face(&self) -> wgpu::FrontFace {
-        if self.scale.x * self.scale.y > 0.0 {
-            wgpu::FrontFace::Cw
-        } else {
-            wgpu::FrontFace::Ccw
+    /// Rotate the free camera by a cursor movement in pixels:
+    /// around the world Z axis horizontally, and around its own X axis vertically.
+    pub fn orbit(&mut self, shift: cgmath::Vector2<f32>) {
+        let dy = shift.y * self.handedness.y_sign();
+        let rot_x = cgmath::Quaternion::from_angle_z(cgmath::Rad(ORBIT_SPEED * shift.x));
+        let rot_y = cgmath::Quaternion::from_angle_x(cgmath::Rad(-ORBIT_SPEED * dy));
+        self.rot = rot_x * self.rot * rot_y;
+    }
+
+    /// Drag the free camera along the ground plane by a cursor movement in pixels,
+    /// faster when it's higher up.
+    pub fn pan(&mut self, shift: cgmath::Vector2<f32>) {
+        use cgmath::InnerSpace as _;
+        let dy = shift.y * self.handedness.y_sign();
+        let mut vec = self.rot * cgmath::vec3(-shift.x, dy, 0.0);
+        let norm1 = vec.magnitude();
+        vec.z = 0.0;
+        let norm = vec.magnitude();
+        if norm > 0.0 {
+            vec *= norm1 / norm;
+            self.loc += self.loc.z * PAN_SPEED * vec;
         }
     }
 
+    /// Move the free camera vertically by lines of the mouse wheel.
+    pub fn zoom(&mut self, delta: f32) {
+        self.loc.z += ZOOM_SPEED * delta;
+    }
+
+    pub fn front_face(&self) -> wgpu::FrontFace {
+        self.handedness.front_face()
+    }
+
     pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
         match self.proj {
             Projection::Ortho {
                 ref mut p,
                 original: _,
             } => {
-                ui.add(egui::Slider::new(&mut p.near, 0.1..=50.0).text("Depth near"));
-                ui.add(egui::Slider::new(&mut p.far, 50.0..=10000.0).text("Depth far"));
+                ui.add(egui::Slider::new(&mut p.near, 0.1..=50.0).text(tr("Depth near")));
+                ui.add(egui::Slider::new(&mut p.far, 50.0..=10000.0).text(tr("Depth far")));
             }
             Projection::Perspective(ref mut p) => {
-                ui.add(egui::Slider::new(&mut p.near, 0.1..=50.0).text("Depth near"));
-                ui.add(egui::Slider::new(&mut p.far, 50.0..=10000.0).text("Depth far"));
+                ui.add(egui::Slider::new(&mut p.near, 0.1..=50.0).text(tr("Depth near")));
+                ui.add(egui::Slider::new(&mut p.far, 50.0..=10000.0).text(tr("Depth far")));
             }
         }
     }
 }
+
+/// Transient feedback on top of the follow camera:
+/// a shake on the impacts, and a wider view at speed.
+#[derive(Debug)]
+pub struct CameraEffects {
+    pub config: EffectsConfig,
+    /// Current shake, from 0 to 1.
+    shake: f32,
+    time: f32,
+    /// Smoothed part of the full speed, from 0 to 1.
+    speed: f32,
+}
+
+impl CameraEffects {
+    pub fn new(config: EffectsConfig) -> Self {
+        CameraEffects {
+            config,
+            shake: 0.0,
+            time: 0.0,
+            speed: 0.0,
+        }
+    }
+
+    /// Register an impact, with its strength relative to a noticeable one.
+    pub fn impact(&mut self, strength: f32) {
+        self.shake = (self.shake + self.config.shake * IMPACT_SHAKE * strength).min(1.0);
+    }
+
+    /// Advance the effects, given the current speed of the target.
+    pub fn update(&mut self, dt: f32, speed: f32) {
+        self.time += dt;
+        self.shake = (self.shake - dt * self.config.shake_decay).max(0.0);
+        let target = if self.config.full_speed > 0.0 {
+            (speed / self.config.full_speed).min(1.0)
+        } else {
+            0.0
+        };
+        let k = (dt * -SPEED_FOV_RATE).exp();
+        self.speed = target * (1.0 - k) + self.speed * k;
+    }
+
+    /// Make a copy of the camera with the effects applied.
+    pub fn apply(&self, camera: &Camera) -> Camera {
+        let mut cam = *camera;
+        if self.shake > 0.0 {
+            // squared to keep the small shakes subtle
+            let amount = self.shake * self.shake;
+            // incommensurate frequencies, to avoid a visible pattern
+            let wobble = |freq: f32, phase: f32| (self.time * freq + phase).sin() * amount;
+            let yaw = cgmath::Quaternion::from_angle_z(SHAKE_ANGLE * wobble(37.0, 0.0));
+            let pitch = cgmath::Quaternion::from_angle_x(SHAKE_ANGLE * wobble(29.0, 1.3));
+            cam.rot = yaw * cam.rot * pitch;
+            cam.loc += cgmath::vec3(wobble(23.0, 2.1), wobble(31.0, 0.7), wobble(19.0, 4.2))
+                * SHAKE_OFFSET;
+        }
+        if let Projection::Perspective(ref mut p) = cam.proj {
+            p.fovy += cgmath::Deg(self.config.speed_fov * self.speed).into();
+        }
+        cam
+    }
+
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut self.config.shake)
+                    .speed(0.1)
+                    .clamp_range(0.0..=5.0)
+                    .prefix(tr("shake:")),
+            );
+            ui.add(
+                egui::DragValue::new(&mut self.config.speed_fov)
+                    .speed(0.5)
+                    .clamp_range(0.0..=30.0)
+                    .prefix(tr("speed FOV:")),
+            );
+        });
+    }
+}
@@ -0,0 +1,105 @@
+//! Typed positions in the spaces of the game, and the conversions between them.
+//!
+//! The world units match the level texels along X and Y, and Z goes up in
+//! the units of the level altitudes. The texel of a world position is found by
+//! flooring, not truncating, so the positions past the seam at zero land into
+//! the right texel. Screen positions are in pixels from the top-left corner,
+//! while the normalized device coordinates have Y going up.
+//! The quaternions cross the FFI and reach the GPU as `[x, y, z, w]`.
+
+/// Position in the world.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldPos(pub cgmath::Vector3<f32>);
+
+/// Texel of the level, not necessarily wrapped into it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TexelPos {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Position on the screen, in pixels from the top-left corner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScreenPos(pub cgmath::Vector2<f32>);
+
+impl WorldPos {
+    /// Texel of the level containing the position.
+    pub fn texel(self) -> TexelPos {
+        TexelPos {
+            x: self.0.x.floor() as i32,
+            y: self.0.y.floor() as i32,
+        }
+    }
+}
+
+impl From<cgmath::Vector3<f32>> for WorldPos {
+    fn from(v: cgmath::Vector3<f32>) -> Self {
+        WorldPos(v)
+    }
+}
+
+impl From<cgmath::Point3<f32>> for WorldPos {
+    fn from(p: cgmath::Point3<f32>) -> Self {
+        WorldPos(cgmath::vec3(p.x, p.y, p.z))
+    }
+}
+
+impl TexelPos {
+    /// The same texel, within the level of the given size.
+    pub fn wrap(self, level_size: (i32, i32)) -> Self {
+        TexelPos {
+            x: self.x.rem_euclid(level_size.0),
+            y: self.y.rem_euclid(level_size.1),
+        }
+    }
+
+    /// Index into the level data, wrapping around the level.
+    pub fn index(self, level_size: (i32, i32)) -> usize {
+        let t = self.wrap(level_size);
+        (t.y * level_size.0 + t.x) as usize
+    }
+
+    /// Center of the texel at the altitude.
+    pub fn center(self, z: f32) -> WorldPos {
+        WorldPos(cgmath::vec3(self.x as f32 + 0.5, self.y as f32 + 0.5, z))
+    }
+}
+
+impl From<(i32, i32)> for TexelPos {
+    fn from((x, y): (i32, i32)) -> Self {
+        TexelPos { x, y }
+    }
+}
+
+impl From<TexelPos> for (i32, i32) {
+    fn from(t: TexelPos) -> Self {
+        (t.x, t.y)
+    }
+}
+
+impl ScreenPos {
+    /// Normalized device coordinates on the screen of the size in pixels.
+    pub fn to_ndc(self, screen_size: (u32, u32)) -> cgmath::Vector2<f32> {
+        cgmath::vec2(
+            2.0 * self.0.x / screen_size.0 as f32 - 1.0,
+            1.0 - 2.0 * self.0.y / screen_size.1 as f32,
+        )
+    }
+
+    pub fn from_ndc(ndc: cgmath::Vector2<f32>, screen_size: (u32, u32)) -> Self {
+        ScreenPos(cgmath::vec2(
+            0.5 * (ndc.x + 1.0) * screen_size.0 as f32,
+            0.5 * (1.0 - ndc.y) * screen_size.1 as f32,
+        ))
+    }
+}
+
+/// Quaternion from the `[x, y, z, w]` components.
+pub fn quat_from_xyzw(q: [f32; 4]) -> cgmath::Quaternion<f32> {
+    cgmath::Quaternion::new(q[3], q[0], q[1], q[2])
+}
+
+/// Components of the quaternion as `[x, y, z, w]`.
+pub fn quat_to_xyzw(q: cgmath::Quaternion<f32>) -> [f32; 4] {
+    [q.v.x, q.v.y, q.v.z, q.s]
+}
@@ -0,0 +1,224 @@
+use vangers::{config::settings::Handedness, space::Camera};
+
+fn camera() -> Camera {
+    Camera {
+        loc: cgmath::vec3(0.0, 0.0, 100.0),
+        rot: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        handedness: Handedness::Right,
+        proj: vangers::space::Projection::ortho(100, 100, 1.0..1000.0),
+    }
+}
+
+#[test]
+fn free_controls() {
+    use cgmath::InnerSpace as _;
+
+    let mut cam = camera();
+    cam.pan(cgmath::vec2(10.0, 0.0));
+    assert_eq!(cam.loc.z, 100.0);
+    assert!(cam.loc.x < 0.0 && cam.loc.y == 0.0);
+
+    // panning a tilted camera still moves along the ground, at the same speed
+    let mut tilted = camera();
+    tilted.orbit(cgmath::vec2(0.0, 100.0));
+    assert_eq!(tilted.loc, camera().loc);
+    tilted.pan(cgmath::vec2(10.0, 0.0));
+    assert!((tilted.loc - cam.loc).magnitude() < 1e-3);
+
+    cam.zoom(-1.0);
+    assert!(cam.loc.z < 100.0);
+}
+
+#[test]
+fn follow_collision() {
+    use vangers::{config::settings::CameraCollision, level, space};
+
+    let size = (64, 64);
+    let total = (size.0 * size.1) as usize;
+    // a wall behind the target, across the rows at the top of the level
+    let height: Box<[u8]> = (0..total)
+        .map(|i| if i / size.0 as usize >= 48 { 200 } else { 0 })
+        .collect();
+    let level = level::Level::from_height(size, height);
+    let target = space::Transform {
+        disp: cgmath::vec3(32.0, 16.0, 0.0),
+        rot: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        scale: 1.0,
+    };
+    let mut follow = space::Follow {
+        angle_x: cgmath::Deg(-30.0),
+        offset: cgmath::vec3(0.0, 64.0, 64.0),
+        speed: 1.0e6,
+        collision: CameraCollision {
+            enabled: false,
+            ..Default::default()
+        },
+    };
+
+    let mut cam = camera();
+    cam.follow(&target, 1.0, &follow, &level);
+    let free_distance = (cam.loc - target.disp).y.abs();
+    assert!(free_distance > 60.0);
+
+    follow.collision.enabled = true;
+    cam.follow(&target, 1.0, &follow, &level);
+    let pulled_distance = (cam.loc - target.disp).y.abs();
+    assert!(pulled_distance < free_distance);
+    let texel = level.get((cam.loc.x as i32, cam.loc.y as i32));
+    assert!(!texel.obstructs(cam.loc.z, 0.0));
+}
+
+#[test]
+fn pick_terrain() {
+    use vangers::level;
+
+    let size = (64, 64);
+    let total = (size.0 * size.1) as usize;
+    // a raised block on the right half of the level
+    let height: Box<[u8]> = (0..total)
+        .map(|i| if i % size.0 as usize >= 32 { 80 } else { 40 })
+        .collect();
+    let level = level::Level::from_height(size, height);
+
+    let mut cam = camera();
+    cam.loc = cgmath::vec3(32.0, 16.0, 100.0);
+    let center = cam.unproject(cgmath::vec2(0.0, 0.0), 0.0);
+    assert!((center.x - 32.0).abs() < 1e-3 && (center.z - 99.0).abs() < 1e-3);
+
+    let low = cam.pick(&level, cgmath::vec2(-0.2, 0.0)).unwrap();
+    assert!((low.x - 22.0).abs() < 1e-3);
+    assert!((low.z - level.get((22, 16)).high()).abs() < 0.5);
+    let high = cam.pick(&level, cgmath::vec2(0.2, 0.0)).unwrap();
+    assert!((high.x - 42.0).abs() < 1e-3);
+    assert!((high.z - level.get((42, 16)).high()).abs() < 0.5);
+    assert!(high.z > low.z);
+
+    // a horizontal ray runs into the side of the block
+    let origin = cgmath::vec3(10.0, 16.0, 60.0);
+    let side = vangers::space::trace_ray(&level, origin, cgmath::Vector3::unit_x(), 100.0).unwrap();
+    assert!((side.x - 32.0).abs() < 0.5 && (side.z - 60.0).abs() < 1e-3);
+    let short = vangers::space::trace_ray(&level, origin, cgmath::Vector3::unit_x(), 10.0);
+    assert!(short.is_none());
+    let down =
+        vangers::space::trace_ray(&level, origin, -cgmath::Vector3::unit_z(), 100.0).unwrap();
+    assert!((down.z - 40.0).abs() < 0.5);
+}
+
+#[test]
+fn relative_view_proj() {
+    use cgmath::{EuclideanSpace as _, InnerSpace as _, Transform as _};
+
+    let far = Camera {
+        loc: cgmath::vec3(16000.0, 12000.0, 100.0),
+        ..camera()
+    };
+    // with the origin at the camera, the translation doesn't depend on the location
+    let relative = far.get_view_proj_from(far.loc);
+    assert_eq!(relative, camera().get_view_proj_from(camera().loc));
+
+    let point = cgmath::Point3::new(16010.5, 11995.25, 40.0);
+    let expected = far.get_view_proj().transform_point(point);
+    let actual = relative.transform_point(cgmath::Point3::from_vec(point.to_vec() - far.loc));
+    assert!((actual - expected).magnitude() < 1e-3);
+}
+
+#[test]
+fn fit_depth_range() {
+    let mut cam = camera();
+    cam.loc.z = 0.0;
+    cam.fit_depth_range(10.0..2000.0, 256.0);
+    assert_eq!(cam.depth_range(), 10.0..2000.0);
+
+    cam.loc.z = 1000.0;
+    cam.fit_depth_range(10.0..2000.0, 256.0);
+    let range = cam.depth_range();
+    assert!(range.start > 10.0 && range.start < 1000.0 - 256.0);
+    assert_eq!(range.end, 3000.0);
+}
+
+#[test]
+fn effects() {
+    use vangers::{config::settings::CameraEffects as Config, space};
+
+    let fov = cgmath::Deg(45.0);
+    let mut cam = camera();
+    cam.proj = space::Projection::Perspective(cgmath::PerspectiveFov {
+        fovy: fov.into(),
+        aspect: 1.0,
+        near: 1.0,
+        far: 1000.0,
+    });
+    let fovy = |cam: &Camera| match cam.proj {
+        space::Projection::Perspective(p) => p.fovy,
+        space::Projection::Ortho { .. } => unreachable!(),
+    };
+
+    let mut effects = space::CameraEffects::new(Config::default());
+    effects.update(0.1, 0.0);
+    let calm = effects.apply(&cam);
+    assert_eq!(calm.loc, cam.loc);
+    assert_eq!(fovy(&calm), fovy(&cam));
+
+    effects.impact(2.0);
+    effects.update(0.1, 0.0);
+    assert_ne!(effects.apply(&cam).loc, cam.loc);
+    // the shake calms down with time
+    effects.update(5.0, 0.0);
+    assert_eq!(effects.apply(&cam).loc, cam.loc);
+
+    for _ in 0..100 {
+        effects.update(0.1, 1000.0);
+    }
+    let fast = fovy(&effects.apply(&cam));
+    let expected = cgmath::Rad::from(fov + cgmath::Deg(Config::default().speed_fov));
+    assert!((fast - expected).0.abs() < 1e-3);
+
+    let mut disabled = space::CameraEffects::new(Config {
+        shake: 0.0,
+        speed_fov: 0.0,
+        ..Config::default()
+    });
+    disabled.impact(10.0);
+    disabled.update(0.1, 1000.0);
+    let same = disabled.apply(&cam);
+    assert_eq!(same.loc, cam.loc);
+    assert_eq!(fovy(&same), fovy(&cam));
+}
+
+#[test]
+fn visible_terrain_rect() {
+    use vangers::level;
+
+    let size = (256, 256);
+    let height = vec![0; (size.0 * size.1) as usize].into_boxed_slice();
+    let level = level::Level::from_height(size, height);
+
+    // looking down at the origin, so the rect crosses the seams
+    let rect = camera().visible_terrain_rect(&level);
+    assert!(rect.w > 0 && rect.w < 256 && rect.h > 0 && rect.h < 256);
+    assert!(rect.contains_wrapped((0, 0), size));
+    assert!(rect.contains_wrapped((-3, 3), size));
+    assert!(rect.contains_wrapped((253, 259), size));
+    assert!(!rect.contains_wrapped((20, 0), size));
+    assert!(!rect.contains_wrapped((128, 128), size));
+}
+
+#[test]
+fn handedness_flip() {
+    use cgmath::{SquareMatrix as _, Transform as _};
+
+    let right = camera();
+    let left = Camera {
+        handedness: Handedness::Left,
+        ..camera()
+    };
+    let point = cgmath::Point3::new(10.0, 20.0, 0.0);
+    let a = right.get_view_proj().transform_point(point);
+    let b = left.get_view_proj().transform_point(point);
+    assert_eq!((a.x, a.z), (b.x, b.z));
+    assert_eq!(a.y, -b.y);
+    assert!(right.get_view_proj().determinant() * left.get_view_proj().determinant() < 0.0);
+
+    // the settings written before the option was added keep the original convention
+    assert_eq!(Handedness::default(), Handedness::Left);
+}
@@ -0,0 +1,34 @@
+use vangers::space::coords::{quat_from_xyzw, quat_to_xyzw, ScreenPos, TexelPos, WorldPos};
+
+#[test]
+fn texels() {
+    // flooring past the seam, where truncating would pick the texel on the other side
+    let texel = WorldPos(cgmath::vec3(-0.5, 3.75, 10.0)).texel();
+    assert_eq!(texel, TexelPos { x: -1, y: 3 });
+    let size = (64, 32);
+    assert_eq!(texel.wrap(size), TexelPos { x: 63, y: 3 });
+    assert_eq!(texel.index(size), 3 * 64 + 63);
+    assert_eq!(
+        TexelPos::from((5, -33)).wrap(size),
+        TexelPos { x: 5, y: 31 }
+    );
+    assert_eq!(texel.center(1.0).texel(), texel);
+}
+
+#[test]
+fn screen() {
+    let size = (800, 600);
+    let corner = ScreenPos(cgmath::vec2(0.0, 0.0)).to_ndc(size);
+    assert_eq!(corner, cgmath::vec2(-1.0, 1.0));
+    let center = ScreenPos(cgmath::vec2(400.0, 300.0)).to_ndc(size);
+    assert_eq!(center, cgmath::vec2(0.0, 0.0));
+    let pos = ScreenPos(cgmath::vec2(200.0, 450.0));
+    assert_eq!(ScreenPos::from_ndc(pos.to_ndc(size), size), pos);
+}
+
+#[test]
+fn quaternions() {
+    let q = cgmath::Quaternion::new(0.5, 0.1, 0.2, 0.3);
+    assert_eq!(quat_to_xyzw(q), [0.1, 0.2, 0.3, 0.5]);
+    assert_eq!(quat_from_xyzw(quat_to_xyzw(q)), q);
+}
@@ -0,0 +1,26 @@
+#[test]
+fn report() {
+    vangers::console::init();
+    vangers::crash::record_settings(
+        "config/settings.template.ron",
+        "test",
+        &["game.level=Necross".to_string()],
+    );
+    vangers::crash::record_adapter(&wgpu::AdapterInfo {
+        name: "Test adapter".to_string(),
+        vendor: 0,
+        device: 0,
+        device_type: wgpu::DeviceType::Cpu,
+        driver: String::new(),
+        driver_info: String::new(),
+        backend: wgpu::Backend::Empty,
+    });
+    log::info!("Right before the crash");
+
+    let report = vangers::crash::report("something broke");
+    assert!(report.contains("something broke"));
+    assert!(report.contains("Test adapter"));
+    assert!(report.contains("--- config/settings.template.ron"));
+    assert!(report.contains("game.level=Necross"));
+    assert!(report.contains("Right before the crash"));
+}
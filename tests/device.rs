@@ -0,0 +1,37 @@
+use vangers::render::is_device_lost;
+
+#[derive(Debug)]
+struct Cause(&'static str);
+
+impl std::fmt::Display for Cause {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for Cause {}
+
+/// Wrap the cause the way wgpu does before handing the error out.
+fn validation(cause: impl std::error::Error + Send + Sync + 'static) -> wgpu::Error {
+    let context = wgpu_core::error::ContextError {
+        string: "Device::create_buffer",
+        cause: Box::new(cause),
+        label_key: "label",
+        label: String::new(),
+    };
+    wgpu::Error::Validation {
+        source: Box::new(context),
+        description: "Validation Error".to_string(),
+    }
+}
+
+#[test]
+fn device_lost_error() {
+    let lost = validation(wgpu_core::device::DeviceError::Lost);
+    assert!(is_device_lost(&lost));
+    let invalid = validation(wgpu_core::device::DeviceError::Invalid);
+    assert!(!is_device_lost(&invalid));
+    // only the kind of the error counts, not the message
+    let message = validation(Cause("Parent device is lost"));
+    assert!(!is_device_lost(&message));
+}
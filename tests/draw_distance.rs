@@ -0,0 +1,72 @@
+use vangers::{
+    config::settings::DrawDistance,
+    render::{mesh_runs, object::Instance, sort_back_to_front, Category, DistanceCull, NEAR_ALPHA},
+    space::Transform,
+};
+
+#[test]
+fn fade_out() {
+    let cull = DistanceCull {
+        origin: cgmath::vec3(0.0, 0.0, 0.0),
+        distance: DrawDistance {
+            vehicles: 100.0,
+            debris: 0.0,
+            props: 50.0,
+            fade: 20.0,
+        },
+    };
+    let at = |x: f32| cgmath::vec3(x, 0.0, 0.0);
+    assert_eq!(cull.fade(Category::Vehicle, at(50.0), 0.0), Some(1.0));
+    assert_eq!(cull.fade(Category::Vehicle, at(90.0), 0.0), Some(0.5));
+    assert_eq!(cull.fade(Category::Vehicle, at(100.0), 0.0), None);
+    // the radius keeps large objects from popping out
+    assert_eq!(cull.fade(Category::Vehicle, at(105.0), 10.0), Some(0.25));
+    // zero distance is unlimited
+    assert_eq!(cull.fade(Category::Debris, at(1e6), 0.0), Some(1.0));
+    assert_eq!(cull.fade(Category::Prop, at(60.0), 0.0), None);
+}
+
+#[test]
+fn near_alpha() {
+    let cull = DistanceCull {
+        origin: cgmath::vec3(0.0, 0.0, 0.0),
+        distance: DrawDistance::default(),
+    };
+    let at = |x: f32| cgmath::vec3(x, 0.0, 0.0);
+    assert_eq!(cull.near_alpha(at(50.0), 10.0), 1.0);
+    assert_eq!(cull.near_alpha(at(20.0), 10.0), 1.0);
+    assert_eq!(
+        cull.near_alpha(at(15.0), 10.0),
+        NEAR_ALPHA + (1.0 - NEAR_ALPHA) * 0.5
+    );
+    assert_eq!(cull.near_alpha(at(5.0), 10.0), NEAR_ALPHA);
+    // points have nothing to fade
+    assert_eq!(cull.near_alpha(at(0.0), 0.0), 1.0);
+}
+
+#[test]
+fn transparent_order() {
+    use cgmath::One as _;
+
+    let at = |mesh: char, depth: f32| {
+        let transform = Transform {
+            disp: cgmath::vec3(0.0, depth, 0.0),
+            rot: cgmath::Quaternion::one(),
+            scale: 1.0,
+        };
+        (mesh, Instance::new(&transform, 0.0, 0).with_alpha(0.5))
+    };
+    let mut instances = vec![
+        at('a', 10.0),
+        at('b', -30.0),
+        at('a', 20.0),
+        at('c', 40.0),
+        at('a', -25.0),
+    ];
+    sort_back_to_front(&mut instances);
+    let meshes = instances.iter().map(|&(m, _)| m).collect::<String>();
+    assert_eq!(meshes, "cbaaa");
+    let runs = mesh_runs(&instances, |a, b| a == b).collect::<Vec<_>>();
+    assert_eq!(runs, [0..1, 1..2, 2..5]);
+    assert_eq!(mesh_runs(&instances[..0], |a, b| a == b).count(), 0);
+}
@@ -0,0 +1,28 @@
+use vangers::config::fallback;
+
+#[test]
+fn fallback_car_model() {
+    let model = fallback::car_model();
+    let body = &model.body.geometry;
+    assert_eq!(body.polygons.len(), 12);
+    for tri in &body.polygons {
+        for v in &tri.vertices {
+            assert!((v.pos as usize) < body.positions.len());
+            assert_eq!(body.normals[v.normal as usize], tri.flat_normal);
+        }
+    }
+
+    let shape = &model.shape.geometry;
+    assert_eq!(shape.polygons.len(), 6);
+    for quad in &shape.polygons {
+        // the middle of a box face is offset from the center along its normal
+        let dot = (0..3)
+            .map(|i| quad.middle[i] as i32 * quad.flat_normal[i] as i32)
+            .sum::<i32>();
+        assert!(dot > 0);
+    }
+
+    assert_eq!(model.wheels.len(), 4);
+    assert!(model.body.physics.volume > 0.0);
+    assert!(fallback::palette().iter().all(|c| c[3] == 0xFF));
+}
@@ -0,0 +1,102 @@
+//! Check that the C header of the FFI follows the Rust side.
+//! The header is generated with cbindgen, see `lib/ffi/cbindgen.toml`.
+
+const SOURCES: [&str; 2] = ["lib/ffi/src/lib.rs", "lib/ffi/src/debug_ui.rs"];
+const HEADER: &str = "lib/ffi/vange_rs.h";
+
+fn read(path: &str) -> String {
+    let full = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(path);
+    std::fs::read_to_string(full).unwrap()
+}
+
+/// Names following each occurrence of the prefix, up to a non-identifier character.
+fn names_after<'a>(text: &'a str, prefix: &str) -> Vec<&'a str> {
+    text.match_indices(prefix)
+        .map(|(i, _)| {
+            let rest = &text[i + prefix.len()..];
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            &rest[..end]
+        })
+        .collect()
+}
+
+#[test]
+fn functions() {
+    let header = read(HEADER);
+    let mut exported = Vec::new();
+    for path in SOURCES.iter() {
+        let source = read(path);
+        exported.extend(
+            names_after(&source, "extern \"C\" fn ")
+                .into_iter()
+                .map(str::to_string),
+        );
+    }
+    for name in exported.iter() {
+        assert!(
+            header.contains(&format!(" {}(", name)) || header.contains(&format!("*{}(", name)),
+            "{} is missing from the header",
+            name
+        );
+    }
+    for name in names_after(&header, "rv_") {
+        if name == "api_3" {
+            continue;
+        }
+        assert!(
+            exported.iter().any(|e| e.strip_prefix("rv_") == Some(name)),
+            "rv_{} is no longer exported",
+            name
+        );
+    }
+}
+
+#[test]
+fn types() {
+    let header = read(HEADER);
+    for path in SOURCES.iter() {
+        let source = read(path);
+        // the structure follows the attribute, after the derives
+        for (i, _) in source.match_indices("#[repr(C)]") {
+            let name = names_after(&source[i..], "pub struct ")[0];
+            assert!(
+                header.contains(&format!("}} {};", name)),
+                "{} is missing from the header",
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn constants() {
+    let header = read(HEADER);
+    for path in SOURCES.iter() {
+        let source = read(path);
+        for name in names_after(&source, "pub const ") {
+            let value = names_after(&source, &format!("pub const {}: u32 = ", name))[0];
+            assert!(
+                header.contains(&format!("#define {} {}\n", name, value)),
+                "{} = {} is missing from the header",
+                name,
+                value
+            );
+        }
+    }
+    // the kinds of the map updates are needed by the host
+    assert!(header.contains("#define RV_MAP_UPDATE_DATA "));
+    assert!(header.contains("#define RV_MAP_UPDATE_PALETTE "));
+}
+
+#[test]
+fn api_version() {
+    let source = read(SOURCES[0]);
+    let version = names_after(&source, "pub static rv_api_3: i32 = ")[0];
+    let changelog = names_after(&source, "Changelog:\n  3.")[0];
+    assert_eq!(
+        version, changelog,
+        "rv_api_3 doesn't match the latest changelog entry"
+    );
+}
@@ -0,0 +1,28 @@
+use vangers::render::frame_graph::{self, Event};
+
+#[test]
+fn json() {
+    assert_eq!(frame_graph::finish(), None);
+    frame_graph::start();
+    assert_eq!(frame_graph::finish(), Some(Vec::new()));
+
+    let events = [
+        Event::Pass {
+            label: "main \"pass\"".to_string(),
+            compute: false,
+        },
+        Event::Dispatch { groups: [4, 2, 1] },
+        Event::Upload {
+            label: "instance".to_string(),
+            size: 256,
+        },
+    ];
+    assert_eq!(
+        frame_graph::to_json(&events),
+        r#"[
+  {"type": "pass", "label": "main \"pass\"", "compute": false},
+  {"type": "dispatch", "groups": [4, 2, 1]},
+  {"type": "upload", "label": "instance", "size": 256}
+]"#
+    );
+}
@@ -0,0 +1,31 @@
+use vangers::jobs;
+
+#[test]
+fn chunks_keep_order() {
+    let items = (0..1000).collect::<Vec<u32>>();
+    let sums = jobs::map_chunks(&items, 16, |chunk| chunk.to_vec());
+    assert!(sums.iter().all(|chunk| !chunk.is_empty()));
+    assert_eq!(sums.concat(), items);
+
+    let single = jobs::map_chunks(&items[..10], 16, |chunk| chunk.len());
+    assert_eq!(single, [10]);
+    let empty = jobs::map_chunks(&items[..0], 16, |chunk| chunk.len());
+    assert_eq!(empty, [0]);
+}
+
+#[test]
+fn rows_and_items() {
+    let row_size = 6;
+    let mut data = vec![0u8; row_size * 300];
+    jobs::for_each_row(&mut data, row_size, 8, |i, row| {
+        assert_eq!(row.len(), row_size);
+        row.fill(i as u8);
+    });
+    for (i, row) in data.chunks(row_size).enumerate() {
+        assert!(row.iter().all(|&v| v == i as u8));
+    }
+
+    let mut items = (0..100).collect::<Vec<u32>>();
+    jobs::for_each_mut(&mut items, 1, |v| *v *= 2);
+    assert!(items.iter().enumerate().all(|(i, &v)| v == 2 * i as u32));
+}
@@ -0,0 +1,410 @@
+use vangers::{config::settings::Geometry, level};
+
+#[test]
+fn test_presets() {
+    let geometry = Geometry::default();
+    for &preset in level::TestPreset::ALL.iter() {
+        let name = format!("test:{}", preset.name());
+        assert_eq!(level::TestPreset::from_level_name(&name), Some(preset));
+        let config = level::LevelConfig::new_test_preset(preset);
+        let level = level::load(&config, &geometry);
+        assert!(!level.flood_map.is_empty());
+    }
+    assert_eq!(level::TestPreset::from_level_name("Fostral"), None);
+    assert_eq!(level::TestPreset::from_level_name("test:mountains"), None);
+
+    let cave = level::load(
+        &level::LevelConfig::new_test_preset(level::TestPreset::Cave),
+        &geometry,
+    );
+    match cave.get((cave.size.0 / 2, 0)) {
+        level::Texel::Dual { low, mid, high } => {
+            assert!(low.0 < mid && mid < high.0);
+        }
+        level::Texel::Single(_) => panic!("Cave is expected to have a ceiling"),
+    }
+}
+
+#[test]
+fn flood_sections() {
+    let mut config = level::LevelConfig::new_test_preset(level::TestPreset::Pool);
+    let mut level = level::load(&config, &Geometry::default());
+    let sections = level.flood_map.len() as i32;
+    assert!(sections > 1);
+    level.flood_map[1] = 100;
+    let rows = level.size.1 / sections;
+    assert_eq!(level.flood_level(0), level.flood_level(-level.size.1));
+    assert_eq!(level.flood_level(rows), 100.0);
+    assert_eq!(level.flood_level(2 * rows - 1), 100.0);
+    assert_ne!(level.flood_level(2 * rows), 100.0);
+    // a level without the sections has no water
+    let flood_map = std::mem::take(&mut level.flood_map);
+    assert_eq!(level.flood_level(rows), 0.0);
+    level.flood_map = flood_map;
+
+    // the flood map is patched into an existing VPR file
+    let path = std::env::temp_dir().join(format!("vangers-flood-{}", std::process::id()));
+    config.path_data = path.clone();
+    assert!(level::save_flood(&config, &level.flood_map).is_err());
+    let vpr_path = path.with_extension("vpr");
+    let file_size = 2 * 4 + 9 * 4 + 2 * level.size.0 * level.size.1 + 4 * sections;
+    std::fs::write(&vpr_path, vec![0xAB; file_size as usize]).unwrap();
+    level::save_flood(&config, &level.flood_map).unwrap();
+    let flood_map = level::load_flood(&config);
+    std::fs::remove_file(&vpr_path).unwrap();
+    assert_eq!(flood_map, level.flood_map);
+}
+
+#[test]
+fn scatter_props() {
+    use vangers::config::scatter::Rule;
+
+    let config = level::LevelConfig::new_test_preset(level::TestPreset::Pool);
+    let level = level::load(&config, &Geometry::default());
+    let rule = |terrain, worlds: &[&str]| Rule {
+        model: String::new(),
+        worlds: worlds.iter().map(|w| w.to_string()).collect(),
+        terrain,
+        density: 20.0,
+        max_slope: 10.0,
+        scale: (0.5, 2.0),
+    };
+    let rules = [rule(1, &[]), rule(6, &["test:pool"]), rule(1, &["Fostral"])];
+
+    let props = level::scatter::scatter(&level, "test:pool", &rules, 1);
+    assert!(props.iter().any(|p| p.rule == 0));
+    assert!(props.iter().all(|p| p.rule != 2));
+    for prop in props.iter() {
+        let texel = level.get((prop.position.x as i32, prop.position.y as i32));
+        assert_eq!(prop.position.z, texel.high());
+        assert!(prop.scale >= 0.5 && prop.scale <= 2.0);
+        if prop.rule == 0 {
+            // the rim around the pool is flat
+            assert_eq!(prop.position.z, 64.0);
+        }
+    }
+
+    assert_eq!(
+        level::scatter::scatter(&level, "test:pool", &rules, 1),
+        props
+    );
+    assert_ne!(
+        level::scatter::scatter(&level, "test:pool", &rules, 2),
+        props
+    );
+}
+
+#[test]
+fn patch_round_trip() {
+    let config = level::LevelConfig::new_test_preset(level::TestPreset::Hills);
+    let base = level::load(&config, &Geometry::default());
+    let mut level = level::load(&config, &Geometry::default());
+    assert!(level::Patch::diff(&base, &level).is_empty());
+
+    let width = level.size.0 as usize;
+    level.height[3 * width + 5] = 1;
+    level.height[3 * width + 9] = 2;
+    level.meta[7 * width] ^= 1;
+    let patch = level::Patch::diff(&base, &level);
+    assert_eq!(patch.spans.len(), 2);
+    assert_eq!(patch.spans[0].x, 5);
+    assert_eq!(patch.spans[0].height.len(), 5);
+
+    let mut restored = level::load(&config, &Geometry::default());
+    let bounds = patch.apply(&mut restored);
+    assert_eq!(bounds, Some(((0, 3), (10, 8))));
+    assert_eq!(restored.height, level.height);
+    assert_eq!(restored.meta, level.meta);
+}
+
+#[test]
+fn baseline_diff() {
+    let config = level::LevelConfig::new_test_preset(level::TestPreset::Hills);
+    let base = level::load(&config, &Geometry::default());
+    let mut level = level::load(&config, &Geometry::default());
+    let mut baseline = level::Baseline::default();
+    assert!(baseline.diff(&level).is_empty());
+
+    let width = level.size.0 as usize;
+    baseline.keep(&level, 3..4);
+    level.height[3 * width + 5] = 1;
+    level.height[3 * width + 9] = 2;
+    // changing the kept row again doesn't forget the original
+    baseline.keep(&level, 2..5);
+    level.height[3 * width + 7] = 3;
+    level.meta[4 * width] ^= 1;
+    assert_eq!(baseline.diff(&level), level::Patch::diff(&base, &level));
+
+    // rows that were not kept are not looked at
+    level.meta[7 * width] ^= 1;
+    assert_eq!(baseline.diff(&level).spans.len(), 2);
+}
+
+#[test]
+fn data_round_trip() {
+    let config = level::LevelConfig::new_test_preset(level::TestPreset::Stairs);
+    let data = level::LevelData::from(level::load(&config, &Geometry::default()));
+    let path = std::env::temp_dir().join(format!("vangers-data-{}", std::process::id()));
+
+    let vmp = path.with_extension("vmp");
+    data.save_vmp(&vmp).unwrap();
+    let loaded = formats::level::load_vmp(&vmp, data.size).unwrap();
+    assert_eq!(
+        (loaded.height, loaded.meta),
+        (data.height.clone(), data.meta.clone())
+    );
+
+    let vmc = path.with_extension("vmc");
+    data.save_vmc(&vmc).unwrap();
+    let loaded = formats::level::load_vmc(&vmc, data.size).unwrap();
+    assert_eq!((loaded.height, loaded.meta), (data.height, data.meta));
+
+    assert!(formats::level::load_vmp(&path.with_extension("none"), data.size).is_err());
+    let _ = std::fs::remove_file(vmp);
+    let _ = std::fs::remove_file(vmc);
+}
+
+#[test]
+fn terrain_bits() {
+    let bits = formats::level::TerrainBits::new(16).unwrap();
+    assert_eq!(bits.read(bits.write(11)), 11);
+    let bits = formats::level::TerrainBits::new(8).unwrap();
+    assert_eq!(bits.read(bits.write(5) | formats::level::DOUBLE_LEVEL), 5);
+    // the other counts don't fit into the meta byte
+    assert!(formats::level::TerrainBits::new(12).is_err());
+    assert!(formats::level::TerrainBits::new(0).is_err());
+}
+
+#[test]
+fn snapshot_region() {
+    use vangers::render::snapshot::{Instance, MeshBounds, Snapshot, TerrainRegion};
+
+    let config = level::LevelConfig::new_test_preset(level::TestPreset::Hills);
+    let level = level::load(&config, &Geometry::default());
+    let bounds = cgmath::vec2(-2.0, 0.0)..cgmath::vec2(2.0, 1.0);
+    let region = TerrainRegion::capture(&level, bounds);
+    assert_eq!((region.origin, region.size), ((-2, 0), (4, 1)));
+    // wrapped around the level edge
+    assert_eq!(
+        region.height_at(-1, 0),
+        Some(level.height[level.size.0 as usize - 1])
+    );
+    assert_eq!(region.height_at(1, 0), Some(level.height[1]));
+    assert_eq!(region.height_at(2, 0), None);
+
+    let camera = vangers::space::Camera {
+        loc: cgmath::vec3(100.0, 100.0, 300.0),
+        rot: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        handedness: vangers::config::settings::Handedness::Right,
+        proj: vangers::space::Projection::ortho(200, 100, 1.0..1000.0),
+    };
+    let snapshot = Snapshot::capture(&vangers::render::Batcher::new(), &level, &camera);
+    assert_eq!(snapshot.camera.aspect, 2.0);
+    assert!(snapshot.instances.is_empty() && snapshot.terrain.size.0 > 0);
+
+    // an opaque and a transparent instance of the same mesh
+    let mut snapshot = snapshot;
+    snapshot.meshes.push(MeshBounds {
+        id: 0x0123_4567_89ab_cdef,
+        min: [-4.0; 3],
+        max: [4.0; 3],
+        num_vertices: 36,
+    });
+    let opaque = Instance {
+        mesh: 0,
+        position: [100.0, 100.0, 20.0],
+        scale: 1.0,
+        orientation: [0.0, 0.0, 0.0, 1.0],
+        color_id: 2,
+        fade: 0.5,
+        alpha: 1.0,
+    };
+    let transparent = Instance {
+        position: [110.0, 100.0, 20.0],
+        alpha: 0.3,
+        ..opaque.clone()
+    };
+    snapshot.instances = vec![opaque, transparent];
+
+    let path = std::env::temp_dir().join(format!("vangers-snapshot-{}.ron", std::process::id()));
+    snapshot.save(&path).unwrap();
+    let loaded = Snapshot::load(&path);
+    let _ = std::fs::remove_file(path);
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded, snapshot);
+    assert_eq!(loaded.meshes[0].id, 0x0123_4567_89ab_cdef);
+    assert_eq!(loaded.instances[1].alpha, 0.3);
+}
+
+#[test]
+fn minmax_query() {
+    let config = level::LevelConfig::new_test_preset(level::TestPreset::Hills);
+    let mut level = level::load(&config, &Geometry::default());
+    let altitude_scale = level.geometry.height as f32 / 256.0;
+    let exact = |level: &level::Level, min: (i32, i32), max: (i32, i32)| {
+        let mut range = f32::MAX..0.0f32;
+        for y in min.1..max.1 {
+            for x in min.0..max.0 {
+                let i = y.rem_euclid(level.size.1) * level.size.0 + x.rem_euclid(level.size.0);
+                let altitude = level.height[i as usize] as f32 * altitude_scale;
+                range = range.start.min(altitude)..range.end.max(altitude);
+            }
+        }
+        range
+    };
+    let check = |level: &level::Level, min: (i32, i32), max: (i32, i32)| {
+        let (bound, range) = (level.query_minmax(min, max), exact(level, min, max));
+        assert!(bound.start <= range.start && range.end <= bound.end);
+        bound
+    };
+
+    let whole = check(&level, (0, 0), level.size);
+    assert!(check(&level, (10, 10), (13, 12)).end <= whole.end);
+    // across the level edges
+    check(&level, (-20, level.size.1 - 5), (30, level.size.1 + 40));
+    // single texel at the edge of a chunk
+    check(&level, (15, 31), (16, 32));
+
+    let (x, y) = (100, 200);
+    level.height[(y * level.size.0 + x) as usize] = 0xFF;
+    level.update_pyramid((x, y), (x + 1, y + 1));
+    assert_eq!(
+        check(&level, (x - 2, y - 2), (x + 2, y + 2)).end,
+        0xFF as f32 * altitude_scale
+    );
+    assert_eq!(
+        level.query_minmax((0, 0), level.size).end,
+        0xFF as f32 * altitude_scale
+    );
+}
+
+#[test]
+fn fixture_roundtrip() {
+    let config = level::LevelConfig::new_test_preset(level::TestPreset::Cave);
+    let level = level::load(&config, &Geometry::default());
+    let center = (level.size.0 / 2 + 1, 5);
+    let fixture = level::Fixture::capture(&level, center, 64);
+    assert_eq!(fixture.size, (64, 64));
+    assert_eq!(fixture.origin.0 % 2, 0, "stacked texels are split");
+
+    let path = std::env::temp_dir().join(format!("vangers-fixture-{}.ron", std::process::id()));
+    fixture.save(&path).unwrap();
+    let loaded = level::Fixture::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, fixture);
+    let name = format!("fixture:{}", path.display());
+    assert_eq!(
+        level::Fixture::path_from_level_name(&name),
+        Some(path.as_path())
+    );
+
+    let config = loaded.config();
+    assert_eq!(config.size.0.as_value(), 64);
+    let small = loaded.into_level();
+    assert_eq!(small.flood_map.len(), 64 >> config.section.as_power());
+    for (x, y) in [(0, 0), (7, 13), (63, 63)] {
+        let (lx, ly) = (fixture.origin.0 + x, fixture.origin.1 + y);
+        assert_eq!(small.get((x, y)).low(), level.get((lx, ly)).low());
+        assert_eq!(small.get((x, y)).high(), level.get((lx, ly)).high());
+    }
+
+    // a level without the water sections gives a single dry one
+    let mut dry = level;
+    dry.flood_map = Vec::new().into();
+    let fixture = level::Fixture::capture(&dry, center, 64);
+    assert_eq!(fixture.flood_map, vec![0]);
+    fixture.save(&path).unwrap();
+    let loaded = level::Fixture::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), fixture);
+}
+
+#[test]
+fn generated_level() {
+    let generator = level::Generator::from_level_name("generate:7").unwrap();
+    assert_eq!(generator.seed, 7);
+    assert_eq!(level::Generator::from_level_name("test:hills"), None);
+    assert_eq!(level::Generator::from_level_name("generate:seven"), None);
+    let mut config = level::LevelConfig::new_generated(generator);
+    config.size = (level::Power(9), level::Power(9));
+    config.section = level::Power(6);
+    let geometry = Geometry::default();
+    let level = level::load(&config, &geometry);
+    assert_eq!(level.flood_map.len(), 8);
+    assert!(level.flood_map.iter().any(|&f| f != level.flood_map[0]));
+
+    // the same seed gives the same level
+    let again = level::load(&config, &geometry);
+    assert_eq!(level.height, again.height);
+    assert_eq!(level.meta, again.meta);
+
+    let (mut caves, mut lowest, mut highest) = (0, f32::MAX, 0.0f32);
+    for y in 0..level.size.1 {
+        for x in 0..level.size.0 {
+            let texel = level.get((x, y));
+            if let level::Texel::Dual { low, mid, high } = texel {
+                assert!(low.0 < mid && mid < high.0);
+                caves += 1;
+            }
+            lowest = lowest.min(texel.high());
+            highest = highest.max(texel.high());
+        }
+    }
+    assert!(caves > 0, "No caves are generated");
+    assert!(highest - lowest > 64.0, "The terrain is too flat");
+
+    // no seam across the wrap
+    let (w, h) = level.size;
+    let step = |a: (i32, i32), b: (i32, i32)| (level.get(a).high() - level.get(b).high()).abs();
+    for i in 0..w {
+        assert!(step((i, 0), (i, h - 1)) < 16.0);
+        assert!(step((0, i), (w - 1, i)) < 16.0);
+    }
+}
+
+#[test]
+fn map_diff() {
+    let config = level::LevelConfig::new_test_preset(level::TestPreset::Hills);
+    let base = level::load(&config, &Geometry::default());
+    let mut level = level::load(&config, &Geometry::default());
+    let width = level.size.0 as usize;
+    level.height[10 * width + 20] = 200;
+    level.meta[30 * width + 1] ^= 1;
+    let changed_height = level.height.clone();
+
+    let path = std::env::temp_dir().join(format!("vangers-map-{}.vmp", std::process::id()));
+    level::LevelData::from(level).save_vmp(&path).unwrap();
+    let patch = level::load_map_diff(&path, &base);
+    std::fs::write(&path, [0u8; 16]).unwrap();
+    let truncated = level::load_map_diff(&path, &base);
+    std::fs::remove_file(&path).unwrap();
+
+    let patch = patch.unwrap();
+    assert_eq!(patch.spans.len(), 2);
+    assert_eq!((patch.spans[0].x, patch.spans[0].y), (20, 10));
+    assert!(truncated.is_err());
+    let mut restored = base;
+    patch.apply(&mut restored);
+    assert_eq!(restored.height, changed_height);
+}
+
+#[test]
+fn wrap_texel() {
+    let config = level::LevelConfig::new_test_preset(level::TestPreset::Hills);
+    let mut level = level::load(&config, &Geometry::default());
+    let (width, height) = level.size;
+    assert_eq!(level.wrap_texel(3, 5), ((3, 5), (5 * width + 3) as usize));
+    // past the edge, the texel is the one on the other side, as the FFI setters see it
+    let ((x, y), index) = level.wrap_texel(-1, height);
+    assert_eq!((x, y), (width - 1, 0));
+    level.height[index] = 250;
+    level.meta[index] = 0x12;
+    level.update_pyramid((x, y), (x + 1, y + 1));
+    for &(gx, gy) in &[(width - 1, 0), (-1, height), (2 * width - 1, -height)] {
+        let (_, index) = level.wrap_texel(gx, gy);
+        assert_eq!(level.height[index], 250);
+        assert_eq!(level.meta[index], 0x12);
+    }
+    let altitude = 250.0 * level.geometry.height as f32 / 256.0;
+    assert!(level.query_minmax((x, y), (x + 1, y + 1)).end >= altitude);
+}
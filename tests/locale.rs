@@ -0,0 +1,68 @@
+use std::{fs, path::Path};
+
+fn collect_sources(dir: &Path, sources: &mut Vec<(String, String)>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_sources(&path, sources);
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            let text = fs::read_to_string(&path).unwrap();
+            sources.push((path.display().to_string(), text));
+        }
+    }
+}
+
+/// Find the string literals passed into `tr` and `tr_fmt`.
+fn translated_literals(text: &str) -> Vec<&str> {
+    let mut literals = Vec::new();
+    for call in ["tr(", "tr_fmt("] {
+        for (pos, _) in text.match_indices(call) {
+            let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+            if text[..pos].chars().next_back().is_some_and(is_ident) {
+                continue;
+            }
+            let rest = text[pos + call.len()..].trim_start();
+            if let Some(rest) = rest.strip_prefix('"') {
+                let end = rest.find('"').unwrap();
+                literals.push(&rest[..end]);
+            }
+        }
+    }
+    literals
+}
+
+#[test]
+fn russian_table() {
+    let table = vangers::locale::load(Path::new("res/locale/ru.ron")).unwrap();
+    for (key, value) in table.iter() {
+        assert_eq!(
+            key.matches("{}").count(),
+            value.matches("{}").count(),
+            "Placeholders of {:?} don't match",
+            key
+        );
+    }
+
+    let mut sources = Vec::new();
+    for dir in ["src", "bin", "lib/ffi/src"] {
+        collect_sources(Path::new(dir), &mut sources);
+    }
+    let mut missing = Vec::new();
+    for (path, text) in sources.iter() {
+        for literal in translated_literals(text) {
+            if !table.contains_key(literal) {
+                missing.push(format!("{}: {:?}", path, literal));
+            }
+        }
+    }
+    assert!(missing.is_empty(), "Untranslated strings: {:#?}", missing);
+}
+
+#[test]
+fn untranslated_fallback() {
+    assert_eq!(vangers::locale::tr("No such string"), "No such string");
+    assert_eq!(
+        vangers::locale::tr_fmt("{} of {}", &[&1, &"two"]),
+        "1 of two"
+    );
+}
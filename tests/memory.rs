@@ -0,0 +1,54 @@
+use vangers::render::memory;
+
+fn texture_desc(
+    size: [u32; 3],
+    mip_level_count: u32,
+    format: wgpu::TextureFormat,
+) -> wgpu::TextureDescriptor<'static> {
+    wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width: size[0],
+            height: size[1],
+            depth_or_array_layers: size[2],
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    }
+}
+
+#[test]
+fn texture_size() {
+    let plain = texture_desc([256, 64, 1], 1, wgpu::TextureFormat::Rgba8Uint);
+    assert_eq!(memory::texture_size(&plain), 256 * 64 * 4);
+    // the layers of an array don't shrink with the mips
+    let layered = texture_desc([256, 1, 16], 3, wgpu::TextureFormat::R8Unorm);
+    assert_eq!(memory::texture_size(&layered), (256 + 128 + 64) * 16);
+    // 8 bytes per block of 4x4 texels, rounding up the last mips
+    let compressed = texture_desc([16, 16, 1], 5, wgpu::TextureFormat::Bc1RgbaUnorm);
+    assert_eq!(memory::texture_size(&compressed), (16 + 4 + 1 + 1 + 1) * 8);
+}
+
+#[test]
+fn budget() {
+    let mut report = memory::Report {
+        terrain: 3 << 20,
+        meshes: 2 << 20,
+        total: 5 << 20,
+        ..Default::default()
+    };
+    assert!(!report.is_over_budget(), "no budget is never exceeded");
+    report.budget = 8 << 20;
+    assert!(!report.is_over_budget());
+    report.budget = 4 << 20;
+    assert!(report.is_over_budget());
+    let largest = report
+        .subsystems()
+        .into_iter()
+        .max_by_key(|&(_, size)| size);
+    assert_eq!(largest, Some((memory::Subsystem::Terrain, 3 << 20)));
+}
@@ -0,0 +1,44 @@
+use vangers::render::mip_queue::MipQueue;
+
+#[test]
+fn dispatch_budget() {
+    // 4 LODs, 3 dispatches per frame
+    let mut queue = MipQueue::new(4, 3);
+    queue.push('a');
+    queue.push('b');
+    assert_eq!(queue.ready_lod_count(), 1);
+
+    // LOD 1 of both, then LOD 2 of the first one
+    let frame = queue.schedule(10);
+    assert_eq!(frame.regions, ['a', 'b']);
+    assert_eq!(frame.dispatches, [(0, 1), (1, 1), (0, 2)]);
+    // the second region has LOD 2 pending
+    assert_eq!(queue.ready_lod_count(), 2);
+
+    let frame = queue.schedule(10);
+    assert_eq!(frame.regions, ['b', 'a']);
+    assert_eq!(frame.dispatches, [(0, 2), (1, 3), (0, 3)]);
+    assert_eq!(queue.ready_lod_count(), 4);
+    assert!(queue.is_empty());
+    assert!(queue.schedule(10).dispatches.is_empty());
+}
+
+#[test]
+fn dispatch_slots() {
+    let mut queue = MipQueue::new(3, 0);
+    for region in 0..5 {
+        queue.push(region);
+    }
+    // no budget limit, but only 2 regions fit the update slots
+    let frame = queue.schedule(2);
+    assert_eq!(frame.regions, [0, 1]);
+    assert_eq!(frame.dispatches, [(0, 1), (1, 1), (0, 2), (1, 2)]);
+    assert_eq!(queue.len(), 3);
+    assert_eq!(queue.ready_lod_count(), 1);
+
+    // a single LOD has no mips to rebuild
+    let mut flat = MipQueue::new(1, 0);
+    flat.push(0);
+    assert!(flat.is_empty());
+    assert_eq!(flat.ready_lod_count(), 1);
+}
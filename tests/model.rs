@@ -0,0 +1,215 @@
+use vangers::config::fallback;
+
+#[test]
+fn weld_and_sort() {
+    let mut model = fallback::car_model();
+    // split every corner of the body into a separate position
+    let geometry = &mut model.body.geometry;
+    let original = geometry.positions.clone();
+    for poly in geometry.polygons.iter_mut() {
+        for v in poly.vertices.iter_mut() {
+            geometry.positions.push(original[v.pos as usize]);
+            v.pos = geometry.positions.len() as u16 - 1;
+        }
+    }
+    let num_polygons = geometry.polygons.len();
+    let welded = model.weld();
+    assert_eq!(welded, 3 * num_polygons);
+    assert_eq!(model.body.geometry.positions.len(), original.len());
+    for poly in model.body.geometry.polygons.iter() {
+        for v in poly.vertices.iter() {
+            assert!((v.pos as usize) < original.len());
+        }
+    }
+
+    let sorted = model.shape.geometry.sorted_polygons();
+    for (axis, indices) in sorted.iter().enumerate() {
+        let mut seen = indices.clone();
+        seen.sort();
+        assert_eq!(seen, (0..6).collect::<Vec<_>>());
+        let middles = indices
+            .iter()
+            .map(|&i| model.shape.geometry.polygons[i as usize].middle[axis])
+            .collect::<Vec<_>>();
+        assert!(middles.windows(2).all(|w| w[0] <= w[1]));
+    }
+}
+
+#[test]
+fn lossless_round_trip() {
+    let path = std::env::temp_dir().join("vangers-round-trip.m3d");
+    fallback::car_model().save(std::fs::File::create(&path).unwrap());
+    let original = std::fs::read(&path).unwrap();
+
+    let mut model = m3d::FullModel::load(std::fs::File::open(&path).unwrap()).unwrap();
+    model.slot_mask = 5;
+    let raw = model.body.raw.as_mut().unwrap();
+    raw.positions[0] = ([1, 2, 3], 7);
+    raw.sorted_polygons[0].reverse();
+    model.save(std::fs::File::create(&path).unwrap());
+    assert_eq!(std::fs::read(&path).unwrap().len(), original.len());
+
+    let loaded = m3d::FullModel::load(std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(loaded.slot_mask, 5);
+    let raw = loaded.body.raw.as_ref().unwrap();
+    assert_eq!(raw.positions[0], ([1, 2, 3], 7));
+    assert_eq!(
+        raw.sorted_polygons[0],
+        model.body.raw.as_ref().unwrap().sorted_polygons[0]
+    );
+
+    // unedited models are written back byte by byte
+    loaded.save(std::fs::File::create(&path).unwrap());
+    let saved = std::fs::read(&path).unwrap();
+    model.save(std::fs::File::create(&path).unwrap());
+    assert_eq!(saved, std::fs::read(&path).unwrap());
+
+    // a mesh without the raw data gets it computed from the geometry
+    let mut fresh = fallback::car_model();
+    fresh.body.raw = None;
+    let geometry = &fresh.body.geometry;
+    let mut data = Vec::new();
+    fresh.body.save(&mut data);
+    let reloaded = m3d::DrawMesh::load(&mut data.as_slice()).unwrap();
+    let raw = reloaded.raw.as_deref().unwrap();
+    let middles = geometry.middles();
+    for (i, &(sort_info, _, middle)) in raw.polygons.iter().enumerate() {
+        assert_eq!(sort_info, i as u32);
+        assert_eq!(middle, middles[i]);
+    }
+    assert_eq!(raw.sorted_polygons, geometry.sorted_polygons());
+    let mut again = Vec::new();
+    reloaded.save(&mut again);
+    assert_eq!(again, data);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn broken_model() {
+    let model = fallback::car_model();
+    let mut data = Vec::new();
+    model.body.save(&mut data);
+    // truncated
+    assert!(m3d::DrawMesh::load(&mut &data[..data.len() / 2]).is_err());
+    // wrong version
+    let mut garbage = data.clone();
+    garbage[0] ^= 0xFF;
+    assert!(m3d::DrawMesh::load(&mut garbage.as_slice()).is_err());
+    // too many positions to allocate
+    let mut garbage = data.clone();
+    garbage[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(m3d::DrawMesh::load(&mut garbage.as_slice()).is_err());
+
+    let path = std::env::temp_dir().join("vangers-broken-model.m3d");
+    std::fs::write(&path, &data[..10]).unwrap();
+    assert!(m3d::FullModel::load(std::fs::File::open(&path).unwrap()).is_err());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn transformed_bounds() {
+    use cgmath::Rotation3 as _;
+    use vangers::{model::BoundingBox, space::Transform};
+
+    let bbox = BoundingBox {
+        min: [-2.0, -1.0, 0.0],
+        max: [2.0, 1.0, 1.0],
+        radius: 3.0,
+    };
+    // a quarter turn around Z swaps the extents of X and Y
+    let t = Transform {
+        disp: cgmath::vec3(100.0, 50.0, 10.0),
+        rot: cgmath::Quaternion::from_angle_z(cgmath::Deg(90.0)),
+        scale: 2.0,
+    };
+    let world = bbox.transform(&t);
+    let expected_min = [98.0, 46.0, 10.0];
+    let expected_max = [102.0, 54.0, 12.0];
+    for axis in 0..3 {
+        assert!((world.min[axis] - expected_min[axis]).abs() < 1e-4);
+        assert!((world.max[axis] - expected_max[axis]).abs() < 1e-4);
+    }
+    assert_eq!(world.radius, 6.0);
+}
+
+#[test]
+fn pictogram() {
+    let model = fallback::car_model();
+    let picture = vangers::model::Pictogram::from_shape(&model.shape.geometry, 16, 16);
+    assert_eq!(picture.pixels.len(), 16 * 16);
+    assert!(picture.pixels.iter().all(|&p| p == 0 || p == 0xFF));
+    let covered = |x: u32, y: u32| picture.pixels[(y * 16 + x) as usize] != 0;
+    // the box fills the middle, and touches the edges along its longest side
+    assert!(covered(8, 8));
+    let longest_x = (0..16).any(|y| covered(0, y) && covered(15, y));
+    let longest_y = (0..16).any(|x| covered(x, 0) && covered(x, 15));
+    assert!(longest_x || longest_y);
+
+    let empty = vangers::model::Pictogram::from_triangles(&[], 4, 4);
+    assert!(empty.pixels.iter().all(|&p| p == 0));
+}
+
+#[test]
+fn pictogram_bitmap() {
+    use vangers::model::Pictogram;
+
+    assert_eq!(Pictogram::bitmap_size(4, 3, 6), Some(18));
+    assert_eq!(Pictogram::bitmap_size(0, 5, 0), Some(0));
+    // the rows have to fit, and the size can't overflow
+    assert_eq!(Pictogram::bitmap_size(4, 3, 0), None);
+    assert_eq!(Pictogram::bitmap_size(4, 3, 3), None);
+    assert_eq!(Pictogram::bitmap_size(1, u32::MAX, u32::MAX), None);
+
+    let picture = Pictogram {
+        width: 2,
+        height: 2,
+        pixels: vec![1, 2, 3, 4],
+    };
+    let mut bitmap = [0; 6];
+    picture.copy_to(&mut bitmap, 3);
+    assert_eq!(bitmap, [1, 2, 0, 3, 4, 0]);
+    // nothing to copy from an empty picture, whatever the pitch
+    let empty = Pictogram {
+        width: 0,
+        height: 2,
+        pixels: Vec::new(),
+    };
+    empty.copy_to(&mut [], 0);
+}
+
+#[test]
+fn occlusion_against_shape() {
+    use vangers::model::{bake_occlusion, shape_triangles, Corner};
+
+    // a convex body doesn't occlude itself
+    let model = fallback::car_model();
+    let geometry = &model.body.geometry;
+    let corners = geometry
+        .polygons
+        .iter()
+        .map(|tri| {
+            tri.vertices.map(|v| Corner {
+                pos: geometry.positions[v.pos as usize],
+                normal: geometry.normals[v.normal as usize],
+            })
+        })
+        .collect::<Vec<_>>();
+    let occluders = shape_triangles(&model.shape.geometry);
+    let open = bake_occlusion(&corners, &occluders, 100.0);
+    assert!(open.iter().flatten().all(|&ao| ao == 127));
+
+    // a ceiling above the corner shades it, seen from below only
+    let corner = Corner {
+        pos: [0, 0, 0],
+        normal: [0, 0, 127],
+    };
+    let (a, b, c) = (
+        [-50.0, -50.0, 10.0],
+        [-50.0, 50.0, 10.0],
+        [50.0, -50.0, 10.0],
+    );
+    let shaded = bake_occlusion(&[[corner; 3]], &[[a, b, c]], 100.0)[0][0];
+    assert!(shaded < 100, "Visibility {}", shaded);
+    let back = bake_occlusion(&[[corner; 3]], &[[a, c, b]], 100.0)[0][0];
+    assert_eq!(back, 127);
+}
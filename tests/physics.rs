@@ -0,0 +1,46 @@
+use vangers::config::fallback;
+
+#[test]
+fn box_physics() {
+    let model = fallback::car_model();
+    let expected = model.shape.physics;
+    let computed = m3d::Physics::from_shape(&model.shape.geometry).unwrap();
+    assert!((computed.volume - expected.volume).abs() < 1e-3 * expected.volume);
+    for i in 0..3 {
+        assert!(computed.rcm[i].abs() < 1e-3);
+        for j in 0..3 {
+            let e = expected.jacobi[i][j];
+            assert!((computed.jacobi[i][j] - e).abs() <= 1e-3 * e.abs() + 1e-2);
+        }
+    }
+}
+
+#[test]
+fn patch_physics_in_place() {
+    let path = std::env::temp_dir().join("vangers-patch-physics.m3d");
+    let mut model = fallback::car_model();
+    model.body.physics.volume = 1.0;
+    model.shape.physics.volume = 1.0;
+    model.save(std::fs::File::create(&path).unwrap());
+    let original = std::fs::read(&path).unwrap();
+
+    assert_eq!(model.recompute_physics(), 2);
+    let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    model.patch_physics(&mut file).unwrap();
+    drop(file);
+
+    let patched = std::fs::read(&path).unwrap();
+    assert_eq!(original.len(), patched.len());
+    let changed = original
+        .iter()
+        .zip(patched.iter())
+        .filter(|(a, b)| a != b)
+        .count();
+    // only the physics blocks of the body and the shape are touched
+    assert!(changed > 0 && changed <= 2 * 13 * 8);
+
+    let loaded = m3d::FullModel::load(std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(loaded.body.physics.volume, model.body.physics.volume);
+    assert_eq!(loaded.shape.physics.volume, model.shape.physics.volume);
+    let _ = std::fs::remove_file(&path);
+}
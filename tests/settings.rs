@@ -1,7 +1,9 @@
 #[test]
 fn load_settings() {
     let file = std::fs::File::open("config/settings.template.ron").unwrap();
-    ron::de::from_reader::<_, vangers::config::settings::Settings>(file).unwrap();
+    let settings = ron::de::from_reader::<_, vangers::config::settings::Settings>(file).unwrap();
+    // each binary keeps its own window title
+    assert!(settings.window.title.is_empty());
 }
 
 #[test]
@@ -19,3 +21,142 @@ fn load_ffi_config() {
     )
     .unwrap();
 }
+
+#[test]
+fn override_settings() {
+    let file = std::fs::File::open("config/settings.template.ron").unwrap();
+    let mut settings =
+        ron::de::from_reader::<_, vangers::config::settings::Settings>(file).unwrap();
+    settings.set_value("game.level", "Necross").unwrap();
+    settings.set_value("render.fog.depth", "30").unwrap();
+    settings
+        .set_value("render.terrain", "Scattered(density: (1, 2, 3))")
+        .unwrap();
+    assert_eq!(settings.game.level, "Necross");
+    assert_eq!(settings.render.fog.depth, 30.0);
+    assert!(matches!(
+        settings.render.terrain,
+        vangers::config::settings::Terrain::Scattered { density: [1, 2, 3] }
+    ));
+    assert!(settings.set_value("render.fog.density", "1").is_err());
+    assert!(settings.set_value("ui.enabled", "maybe").is_err());
+}
+
+#[test]
+fn override_surfaces() {
+    let file = std::fs::File::open("config/settings.template.ron").unwrap();
+    let mut settings =
+        ron::de::from_reader::<_, vangers::config::settings::Settings>(file).unwrap();
+    settings
+        .set_value(
+            "game.surfaces",
+            "[(grip: 0.5), (rolling: 2, dust: (1, 1, 1))]",
+        )
+        .unwrap();
+    let surfaces = &settings.game.surfaces;
+    assert_eq!(surfaces.len(), 2);
+    assert_eq!(surfaces[0].grip, 0.5);
+    assert_eq!(surfaces[0].rolling, 0.0);
+    assert_eq!(surfaces[1].grip, 1.0);
+    assert_eq!(surfaces[1].dust, [1.0; 3]);
+}
+
+#[test]
+fn match_adapter() {
+    let file = std::fs::File::open("config/settings.template.ron").unwrap();
+    let mut settings =
+        ron::de::from_reader::<_, vangers::config::settings::Settings>(file).unwrap();
+    let info = wgpu::AdapterInfo {
+        name: "llvmpipe (LLVM 15.0.7, 256 bits)".to_string(),
+        vendor: 0,
+        device: 0,
+        device_type: wgpu::DeviceType::Cpu,
+        driver: String::new(),
+        driver_info: String::new(),
+        backend: wgpu::Backend::Vulkan,
+    };
+    assert!(settings.matches_adapter(&info));
+    settings.set_value("adapter", "LLVMpipe").unwrap();
+    assert!(settings.matches_adapter(&info));
+    settings.set_value("adapter", "GeForce RTX").unwrap();
+    assert!(!settings.matches_adapter(&info));
+}
+
+#[test]
+fn geometry_overrides() {
+    use vangers::config::{settings::Geometry, worlds::GeometryOverrides};
+
+    let path = std::env::temp_dir().join("vangers-geometry-overrides.ron");
+    let _ = std::fs::remove_file(&path);
+    let default = Geometry::default();
+    let mut overrides = GeometryOverrides::load(&path);
+    assert_eq!(
+        overrides.get("Necross", &default).delta_power,
+        default.delta_power
+    );
+
+    let custom = Geometry {
+        delta_power: 1,
+        ..Geometry::default()
+    };
+    overrides.set("Necross", &custom);
+    assert!(
+        !path.exists(),
+        "Overrides are saved before the edit is finished"
+    );
+    overrides.save();
+    let reloaded = GeometryOverrides::load(&path);
+    assert_eq!(reloaded.get("Necross", &default).delta_power, 1);
+    assert_eq!(
+        reloaded.get("Fostral", &default).delta_power,
+        default.delta_power
+    );
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn fit_downlevel() {
+    use vangers::config::settings::{ShadowTerrain, Terrain};
+
+    let file = std::fs::File::open("config/settings.template.ron").unwrap();
+    let mut settings =
+        ron::de::from_reader::<_, vangers::config::settings::Settings>(file).unwrap();
+    let webgl2 = wgpu::DownlevelCapabilities {
+        flags: wgpu::DownlevelFlags::empty(),
+        ..wgpu::DownlevelCapabilities::default()
+    };
+
+    settings.set_value("render.terrain", "Sliced").unwrap();
+    settings.render.fit_downlevel(&webgl2);
+    assert!(matches!(settings.render.terrain, Terrain::Sliced));
+
+    settings
+        .set_value(
+            "render.terrain",
+            "RayVoxelTraced(voxel_size: (2, 4, 1), max_outer_steps: 40, \
+            max_inner_steps: 40, max_update_texels: 1000, max_mip_dispatches: 16)",
+        )
+        .unwrap();
+    settings
+        .set_value(
+            "render.light.shadow.terrain",
+            "RayVoxelTraced(max_outer_steps: 20, max_inner_steps: 20)",
+        )
+        .unwrap();
+    let mut full = settings.render.clone();
+    full.fit_downlevel(&wgpu::DownlevelCapabilities::default());
+    assert!(matches!(
+        full.terrain,
+        Terrain::RayVoxelTraced {
+            max_mip_dispatches: 16,
+            ..
+        }
+    ));
+
+    settings.render.fit_downlevel(&webgl2);
+    assert!(matches!(settings.render.terrain, Terrain::RayTraced));
+    assert!(matches!(
+        settings.render.light.shadow.terrain,
+        ShadowTerrain::RayTraced
+    ));
+}
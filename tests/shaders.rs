@@ -1,17 +1,143 @@
-fn parse(name: &str, substitutions: &[(&str, String)]) {
-    println!("Parsing {}", name);
+use vangers::render::terrain::VOXEL_TILE_SIZE;
+
+/// Build the shader with the substitutions, validate it,
+/// and check that it has the entry points used by the pipelines.
+fn check(name: &str, substitutions: &[(&str, String)], entry_points: &[&str]) {
+    println!("Checking {}", name);
     let code = vangers::render::make_shader_code(name, substitutions).unwrap();
-    naga::front::wgsl::Frontend::new().parse(&code).unwrap();
+    // comments are free to quote names in backticks
+    assert!(
+        !code
+            .lines()
+            .any(|line| line.split("//").next().unwrap().contains('`')),
+        "{} has substitutions left unresolved",
+        name
+    );
+    let module = naga::front::wgsl::Frontend::new()
+        .parse(&code)
+        .unwrap_or_else(|e| panic!("{}: {}", name, e.emit_to_string(&code)));
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .unwrap_or_else(|e| panic!("{}: {:?}", name, e));
+    for entry_point in entry_points {
+        assert!(
+            module.entry_points.iter().any(|ep| ep.name == *entry_point),
+            "{} is missing the entry point {}",
+            name,
+            entry_point
+        );
+    }
+}
+
+/// Substitutions of the voxel shaders: the one used by the terrain,
+/// and the one used for the shader checks on reload.
+fn voxel_permutations() -> [[(&'static str, String); 1]; 2] {
+    [
+        [("morton_tile_size", format!("{}u", VOXEL_TILE_SIZE))],
+        [("morton_tile_size", "1u".to_string())],
+    ]
 }
 
 #[test]
 fn parse_shaders() {
-    parse("terrain/ray", &[]);
-    parse("terrain/paint", &[]);
-    parse("terrain/scatter", &[]);
-    parse("terrain/paint", &[]);
-    parse("terrain/slice", &[]);
-    let voxel_subs = [("morton_tile_size", "1u".to_string())];
-    parse("terrain/voxel-bake", &voxel_subs);
-    parse("terrain/voxel-draw", &voxel_subs);
+    check(
+        "object",
+        &[],
+        &["geometry_vs", "color_vs", "color_fs", "faded_fs"],
+    );
+    check("particle", &[], &["update", "main_vs", "main_fs"]);
+    check("water", &[], &["main_vs", "main_fs", "blit_vs", "blit_fs"]);
+    check("mipmap", &[], &["main_vs", "main_fs"]);
+    check("palettize", &[], &["main_vs", "main_fs"]);
+    check("debug", &[], &["main_vs", "main_fs"]);
+    check("brush", &[], &["main_vs", "main_fs"]);
+    // main and shadow passes, with and without the step counters
+    check(
+        "terrain/ray",
+        &[],
+        &[
+            "main",
+            "ray_color",
+            "ray_color_stats",
+            "ray_color_debug",
+            "ray_depth",
+        ],
+    );
+    check("terrain/paint", &[], &["vertex", "fragment"]);
+    check(
+        "terrain/scatter",
+        &[],
+        &["clear", "main", "copy_vs", "copy_fs"],
+    );
+    check("terrain/slice", &[], &["main_vs", "main_fs"]);
+    for subs in voxel_permutations().iter() {
+        check("terrain/voxel-bake", subs, &["init", "mip"]);
+        check(
+            "terrain/voxel-draw",
+            subs,
+            &[
+                "main",
+                "draw_color",
+                "draw_depth",
+                "vert_bound",
+                "draw_bound",
+            ],
+        );
+    }
+}
+
+#[test]
+fn missing_shader() {
+    assert!(vangers::render::make_shader_code("terrain/none", &[]).is_err());
+}
+
+/// Find the location of the named input of the entry point, looking into the structures.
+fn input_location(module: &naga::Module, entry_point: &str, name: &str) -> Option<u32> {
+    let ep = module
+        .entry_points
+        .iter()
+        .find(|ep| ep.name == entry_point)?;
+    let location = |binding: &Option<naga::Binding>| match *binding {
+        Some(naga::Binding::Location { location, .. }) => Some(location),
+        _ => None,
+    };
+    ep.function.arguments.iter().find_map(|arg| {
+        if arg.name.as_deref() == Some(name) {
+            return location(&arg.binding);
+        }
+        match module.types[arg.ty].inner {
+            naga::TypeInner::Struct { ref members, .. } => members
+                .iter()
+                .find(|m| m.name.as_deref() == Some(name))
+                .and_then(|m| location(&m.binding)),
+            _ => None,
+        }
+    })
+}
+
+#[test]
+fn instance_palette_layer() {
+    use vangers::render::object::{Instance, InstanceDesc};
+
+    let code = vangers::render::make_shader_code("object", &[]).unwrap();
+    let module = naga::front::wgsl::Frontend::new().parse(&code).unwrap();
+    let location = input_location(&module, "color_vs", "palette_layer").unwrap();
+    assert!(input_location(&module, "color_fs", "palette_layer").is_some());
+
+    let instance = Instance::new(&cgmath::One::one(), 1.0, 0).with_palette(2);
+    let desc = InstanceDesc::new();
+    let attribute = desc
+        .buffer_desc()
+        .attributes
+        .iter()
+        .find(|a| a.shader_location == location)
+        .copied()
+        .unwrap();
+    assert_eq!(attribute.format, wgpu::VertexFormat::Uint32);
+    let offset = attribute.offset as usize;
+    let bytes = &bytemuck::bytes_of(&instance)[offset..offset + 4];
+    assert_eq!(u32::from_le_bytes(bytes.try_into().unwrap()), 2);
 }
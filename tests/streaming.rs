@@ -0,0 +1,116 @@
+use vangers::render::{
+    streaming::{Residency, UploadQueue, MAX_NEW_TILES_PER_FRAME, NOT_RESIDENT},
+    Rect,
+};
+
+#[test]
+fn residency() {
+    // 16x16 tiles of 64 texels, with slots for 8 of them
+    let mut residency = Residency::new((1024, 1024), 64, 8);
+    assert_eq!(residency.tile_count(), (16, 16));
+    let area = Rect {
+        x: 1000,
+        y: 0,
+        w: 100,
+        h: 64,
+    };
+    // crossing the seam: tiles 15 and 0, 1 of the first row
+    let added = residency.update(&area, (1010, 10));
+    let tiles = added.iter().map(|&(tile, _)| tile).collect::<Vec<_>>();
+    assert_eq!(tiles, vec![15, 0, 1]);
+    assert_eq!(residency.resident_count(), 3);
+    assert_eq!(residency.page_table()[15], added[0].1);
+    assert_eq!(residency.page_table()[2], NOT_RESIDENT);
+    assert!(residency.update(&area, (1010, 10)).is_empty());
+
+    // a far area takes the free slots, then evicts the least recently needed tiles
+    let far = Rect {
+        x: 512,
+        y: 512,
+        w: 256,
+        h: 64,
+    };
+    assert_eq!(residency.update(&far, (600, 520)).len(), 4);
+    assert_eq!(residency.resident_count(), 7);
+    let next = Rect {
+        x: 512,
+        y: 576,
+        w: 256,
+        h: 64,
+    };
+    assert_eq!(residency.update(&next, (600, 600)).len(), 4);
+    assert_eq!(residency.resident_count(), 8);
+    // the first area went away before the far one
+    assert_eq!(residency.page_table()[15], NOT_RESIDENT);
+    assert_ne!(residency.page_table()[8 * 16 + 8], NOT_RESIDENT);
+    assert_eq!(residency.resident_in(&next).len(), 4);
+}
+
+#[test]
+fn new_tiles_per_frame() {
+    let mut residency = Residency::new((1024, 1024), 64, 256);
+    let all = Rect {
+        x: 0,
+        y: 0,
+        w: 1024,
+        h: 1024,
+    };
+    let added = residency.update(&all, (0, 0));
+    assert_eq!(added.len(), MAX_NEW_TILES_PER_FRAME);
+    // the closest to the center come first
+    assert_eq!(added[0].0, 0);
+    assert_eq!(
+        residency.update(&all, (0, 0)).len(),
+        MAX_NEW_TILES_PER_FRAME
+    );
+    assert_eq!(residency.resident_count(), 2 * MAX_NEW_TILES_PER_FRAME);
+}
+
+#[test]
+fn upload_queue() {
+    let mut residency = Residency::new((256, 256), 64, 2);
+    let area = Rect {
+        x: 0,
+        y: 0,
+        w: 128,
+        h: 64,
+    };
+    let mut uploads = UploadQueue::default();
+    let added = residency.update(&area, (0, 0));
+    for &(tile, slot) in added.iter() {
+        uploads.push(tile, slot, true);
+    }
+    // an edit of a tile that is already waiting doesn't queue it twice
+    uploads.push(added[0].0, added[0].1, false);
+    assert_eq!(uploads.len(), 2);
+    // the tiles are hidden until their data is uploaded
+    let pages = uploads.visible_pages(&residency);
+    assert!(added
+        .iter()
+        .all(|&(tile, _)| pages[tile as usize] == NOT_RESIDENT));
+
+    assert_eq!(uploads.take(&residency, 1), &added[..1]);
+    let pages = uploads.visible_pages(&residency);
+    assert_eq!(pages[added[0].0 as usize], added[0].1);
+    assert_eq!(pages[added[1].0 as usize], NOT_RESIDENT);
+
+    // the edits of the visible tiles keep them visible
+    uploads.push(added[0].0, added[0].1, false);
+    let pages = uploads.visible_pages(&residency);
+    assert_eq!(pages[added[0].0 as usize], added[0].1);
+
+    // a tile that loses its slot before the upload is dropped
+    let far = Rect {
+        x: 128,
+        y: 128,
+        w: 128,
+        h: 64,
+    };
+    let moved = residency.update(&far, (128, 128));
+    assert_eq!(moved.len(), 2);
+    for &(tile, slot) in moved.iter() {
+        uploads.push(tile, slot, true);
+    }
+    assert_eq!(uploads.take(&residency, 10), moved);
+    assert!(uploads.is_empty());
+}
@@ -0,0 +1,59 @@
+use vangers::{
+    config::settings::{Geometry, Underground},
+    level,
+    render::terrain::SliceTransition,
+};
+
+#[test]
+fn slice_transition() {
+    let cave = level::load(
+        &level::LevelConfig::new_test_preset(level::TestPreset::Cave),
+        &Geometry::default(),
+    );
+    let coords = (cave.size.0 / 2, 0);
+    let (low, mid) = match cave.get(coords) {
+        level::Texel::Dual { low, mid, .. } => (low.0, mid),
+        level::Texel::Single(_) => panic!("Cave is expected to have a ceiling"),
+    };
+    let inside = cgmath::vec3(coords.0 as f32 + 0.5, 0.5, low + 1.0);
+    let outside = cgmath::vec3(inside.x, inside.y, mid + 100.0);
+    let config = Underground {
+        enter_depth: 1.0,
+        exit_delay: 1.0,
+        duration: 0.5,
+    };
+    let top = cave.geometry.height as f32;
+
+    let mut transition = SliceTransition::default();
+    assert_eq!(
+        transition.update(&cave, outside, 0.1, &config),
+        None,
+        "Nothing to cut outside"
+    );
+    let mut last = top;
+    for _ in 0..5 {
+        let view = transition.update(&cave, inside, 0.1, &config).unwrap();
+        assert!(view.ceiling < last, "The cut goes down");
+        last = view.ceiling;
+    }
+    assert!(
+        (last - mid).abs() < 1e-3,
+        "Settled at {} instead of {}",
+        last,
+        mid
+    );
+
+    // stepping out for less than the delay keeps the view
+    for _ in 0..5 {
+        let view = transition.update(&cave, outside, 0.1, &config);
+        assert_eq!(view.map(|v| v.ceiling), Some(last));
+    }
+    assert!(transition.update(&cave, inside, 0.1, &config).is_some());
+    for _ in 0..15 {
+        transition.update(&cave, outside, 0.1, &config);
+    }
+    assert_eq!(transition.update(&cave, outside, 0.1, &config), None);
+
+    let disabled = Underground::default();
+    assert_eq!(transition.update(&cave, inside, 0.1, &disabled), None);
+}
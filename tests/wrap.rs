@@ -0,0 +1,19 @@
+use vangers::render::WorldWrap;
+
+#[test]
+fn wrap_copies() {
+    let wrap = WorldWrap {
+        size: cgmath::vec2(1000.0, 2000.0),
+        center: cgmath::vec2(100.0, 100.0),
+    };
+    // the copy of the other side of the world is the closest
+    let far = cgmath::vec3(1050.0, 2100.0, 5.0);
+    assert_eq!(wrap.nearest(far), cgmath::vec3(50.0, 100.0, 5.0));
+    assert_eq!(wrap.copies(far, 10.0), vec![cgmath::vec3(50.0, 100.0, 5.0)]);
+
+    // crossing the seam in both directions
+    let seam = cgmath::vec3(598.0, -898.0, 0.0);
+    let copies = wrap.copies(seam, 10.0);
+    assert_eq!(copies.len(), 4);
+    assert!(copies.contains(&cgmath::vec3(-402.0, 1102.0, 0.0)));
+}